@@ -56,6 +56,23 @@ check_testcase () {
             fi
             ;;
 
+        carefulresume )
+            # The server stores the parameters of the path observed on the
+            # first connection, and the client reconnects from the same
+            # address so that the second connection resumes from them.
+            echo "supported"
+            if [ "$ROLE" == "client" ]; then
+                QUICHE_CLIENT_OPT="$QUICHE_CLIENT_OPT --session-file=session.bin"
+            elif [ "$ROLE" == "server" ]; then
+                cat > $QUICHE_DIR/carefulresume.toml <<EOF
+[careful_resume]
+enabled = true
+store_file = "$LOG_DIR/cr.store"
+EOF
+                QUICHE_SERVER_OPT="$QUICHE_SERVER_OPT --config $QUICHE_DIR/carefulresume.toml"
+            fi
+            ;;
+
         retry )
             echo "supported"
             QUICHE_SERVER_OPT="$QUICHE_SERVER_OPT_COMMON"
@@ -83,7 +100,7 @@ run_quiche_client_tests () {
             done
             ;;
 
-        resumption | zerortt )
+        resumption | zerortt | carefulresume )
             REQS=($REQUESTS)
 
             # Run first request in 1-RTT to establish session.
@@ -25,6 +25,9 @@
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
 use super::common::alpns;
+use super::config_file::flag_given;
+use super::config_file::ConfigFile;
+use super::shard::MAX_SHARDS;
 
 pub trait Args {
     fn with_docopt(docopt: &docopt::Docopt) -> Self;
@@ -46,6 +49,7 @@ pub struct CommonArgs {
     pub cc_algorithm: String,
     pub disable_hystart: bool,
     pub disable_resume: bool,
+    pub cr_validating_policy: String,
     pub dgrams_enabled: bool,
     pub dgram_count: u64,
     pub dgram_data: String,
@@ -55,6 +59,8 @@ pub struct CommonArgs {
     pub qpack_max_table_capacity: Option<u64>,
     pub qpack_blocked_streams: Option<u64>,
     pub initial_cwnd_packets: u64,
+    pub available_versions: Vec<u32>,
+    pub config_file: Option<ConfigFile>,
 }
 
 /// Creates a new `CommonArgs` structure using the provided [`Docopt`].
@@ -73,6 +79,7 @@ pub struct CommonArgs {
 /// --cc-algorithm NAME         Set a congestion control algorithm.
 /// --disable-hystart           Disable HyStart++.
 /// --disable-resume            Disable  Careful Resume.
+/// --cr-validating-policy NAME Careful Resume validating phase growth policy.
 /// --dgram-proto PROTO         DATAGRAM application protocol.
 /// --dgram-count COUNT         Number of DATAGRAMs to send.
 /// --dgram-data DATA           DATAGRAM data to send.
@@ -82,6 +89,11 @@ pub struct CommonArgs {
 /// --qpack-max-table-capacity BYTES  Max capacity of dynamic QPACK decoding.
 /// --qpack-blocked-streams STREAMS  Limit of blocked streams while decoding.
 /// --initial-cwnd-packets      Size of initial congestion window, in packets.
+/// --available-versions VERSIONS  QUIC versions to use, in order of preference.
+/// --config FILE               TOML configuration file.
+///
+/// Settings from the configuration file are used for the options that are
+/// not given on the command line.
 ///
 /// [`Docopt`]: https://docs.rs/docopt/1.1.0/docopt/
 impl Args for CommonArgs {
@@ -150,6 +162,7 @@ impl Args for CommonArgs {
 
         let disable_hystart = args.get_bool("--disable-hystart");
         let disable_resume = args.get_bool("--disable-resume");
+        let cr_validating_policy = args.get_str("--cr-validating-policy");
 
         let max_active_cids = args.get_str("--max-active-cids");
         let max_active_cids = max_active_cids.parse::<u64>().unwrap();
@@ -194,7 +207,15 @@ impl Args for CommonArgs {
             .parse::<u64>()
             .unwrap();
 
-        CommonArgs {
+        let available_versions = args
+            .get_str("--available-versions")
+            .split(',')
+            .map(|v| u32::from_str_radix(v.trim(), 16).unwrap())
+            .collect();
+
+        let config_file = load_config_file(&args);
+
+        let mut common_args = CommonArgs {
             alpns,
             max_data,
             max_window,
@@ -209,6 +230,7 @@ impl Args for CommonArgs {
             cc_algorithm: cc_algorithm.to_string(),
             disable_hystart,
             disable_resume,
+            cr_validating_policy: cr_validating_policy.to_string(),
             dgrams_enabled,
             dgram_count,
             dgram_data,
@@ -218,7 +240,18 @@ impl Args for CommonArgs {
             qpack_max_table_capacity,
             qpack_blocked_streams,
             initial_cwnd_packets,
+            available_versions,
+            config_file: None,
+        };
+
+        if let Some(file) = config_file {
+            let argv: Vec<String> = std::env::args().collect();
+            file.apply_to_args(&mut common_args, |flag| flag_given(&argv, flag));
+
+            common_args.config_file = Some(file);
         }
+
+        common_args
     }
 }
 
@@ -239,6 +272,7 @@ impl Default for CommonArgs {
             cc_algorithm: "cubic".to_string(),
             disable_hystart: false,
             disable_resume: false,
+            cr_validating_policy: "slow_start".to_string(),
             dgrams_enabled: false,
             dgram_count: 0,
             dgram_data: "quack".to_string(),
@@ -248,10 +282,33 @@ impl Default for CommonArgs {
             qpack_max_table_capacity: None,
             qpack_blocked_streams: None,
             initial_cwnd_packets: 10,
+            available_versions: vec![
+                quiche::PROTOCOL_VERSION_V1,
+                quiche::PROTOCOL_VERSION_V2,
+            ],
+            config_file: None,
         }
     }
 }
 
+/// Loads the configuration file given with `--config`, exiting on error.
+fn load_config_file(args: &docopt::ArgvMap) -> Option<ConfigFile> {
+    let path = args.get_str("--config");
+
+    if path.is_empty() {
+        return None;
+    }
+
+    match ConfigFile::load(path) {
+        Ok(file) => Some(file),
+
+        Err(e) => {
+            eprintln!("failed to load config file {}", e);
+            std::process::exit(1);
+        },
+    }
+}
+
 pub const CLIENT_USAGE: &str = "Usage:
   quiche-client [options] URL...
   quiche-client -h | --help
@@ -266,7 +323,7 @@ Options:
   --max-streams-bidi STREAMS  Number of allowed concurrent streams [default: 100].
   --max-streams-uni STREAMS   Number of allowed concurrent streams [default: 100].
   --idle-timeout TIMEOUT   Idle timeout in milliseconds [default: 30000].
-  --wire-version VERSION   The version number to send to the server [default: babababa].
+  --wire-version VERSION   The version number to send to the server, e.g. 1 or 6b3343cf for QUIC v2 [default: babababa].
   --http-version VERSION   HTTP version to use [default: all].
   --early-data             Enable sending early data.
   --dgram-proto PROTO      DATAGRAM application protocol to use [default: none].
@@ -283,6 +340,7 @@ Options:
   --cc-algorithm NAME      Specify which congestion control algorithm to use [default: cubic].
   --disable-hystart        Disable HyStart++.
   --disable-resume        Disable Careful Resume.
+  --cr-validating-policy NAME  Careful Resume validating phase growth policy: freeze, pipesize or slow_start [default: slow_start].
   --max-active-cids NUM    The maximum number of active Connection IDs we can support [default: 2].
   --enable-active-migration   Enable active connection migration.
   --perform-migration      Perform connection migration on another source port.
@@ -293,8 +351,12 @@ Options:
   --qpack-max-table-capacity BYTES  Max capacity of dynamic QPACK decoding.. Any value other that 0 is currently unsupported.
   --qpack-blocked-streams STREAMS   Limit of blocked streams while decoding. Any value other that 0 is currently unsupported.
   --session-file PATH      File used to cache a TLS session for resumption.
+  --repeat N               Run the transfer N times, each run resuming from the previous one [default: 1].
+  --cooldown SECS          Seconds to wait between repeated runs [default: 0].
   --source-port PORT       Source port to use when connecting to the server [default: 0].
   --initial-cwnd-packets PACKETS   The initial congestion window size in terms of packet count [default: 10].
+  --available-versions VERSIONS    Comma-separated QUIC versions to use, in order of preference [default: 1,6b3343cf].
+  --config FILE            TOML file with transport, congestion control and Careful Resume settings, overridden by the options given here.
   -h --help                Show this screen.
 ";
 
@@ -315,6 +377,8 @@ pub struct ClientArgs {
     pub source_port: u16,
     pub perform_migration: bool,
     pub send_priority_update: bool,
+    pub repeat: u64,
+    pub cooldown: std::time::Duration,
 }
 
 impl Args for ClientArgs {
@@ -392,7 +456,14 @@ impl Args for ClientArgs {
 
         let send_priority_update = args.get_bool("--send-priority-update");
 
-        ClientArgs {
+        let repeat = args.get_str("--repeat");
+        let repeat = repeat.parse::<u64>().unwrap();
+
+        let cooldown = args.get_str("--cooldown");
+        let cooldown =
+            std::time::Duration::from_secs_f64(cooldown.parse::<f64>().unwrap());
+
+        let mut client_args = ClientArgs {
             version,
             dump_response_path,
             dump_json,
@@ -408,7 +479,18 @@ impl Args for ClientArgs {
             source_port,
             perform_migration,
             send_priority_update,
+            repeat,
+            cooldown,
+        };
+
+        if let Some(file) = load_config_file(&args) {
+            let argv: Vec<String> = std::env::args().collect();
+            file.apply_to_client_args(&mut client_args, |flag| {
+                flag_given(&argv, flag)
+            });
         }
+
+        client_args
     }
 }
 
@@ -430,6 +512,8 @@ impl Default for ClientArgs {
             source_port: 0,
             perform_migration: false,
             send_priority_update: false,
+            repeat: 1,
+            cooldown: std::time::Duration::ZERO,
         }
     }
 }
@@ -463,6 +547,7 @@ Options:
   --cc-algorithm NAME         Specify which congestion control algorithm to use [default: cubic].
   --disable-hystart           Disable HyStart++.
   --disable-resume            Disable Careful Resume.
+  --cr-validating-policy NAME  Careful Resume validating phase growth policy: freeze, pipesize or slow_start [default: slow_start].
   --max-active-cids NUM       The maximum number of active Connection IDs we can support [default: 2].
   --enable-active-migration   Enable active connection migration.
   --max-field-section-size BYTES    Max size of uncompressed HTTP/3 field section. Default is unlimited.
@@ -470,7 +555,11 @@ Options:
   --qpack-blocked-streams STREAMS   Limit of streams that can be blocked while decoding. Any value other that 0 is currently unsupported.
   --disable-gso               Disable GSO (linux only).
   --disable-pacing            Disable pacing (linux only).
+  --pacing-offload            Pace with fq through SO_MAX_PACING_RATE instead of SO_TXTIME (linux only).
   --initial-cwnd-packets PACKETS      The initial congestion window size in terms of packet count [default: 10].
+  --available-versions VERSIONS       Comma-separated QUIC versions to use, in order of preference [default: 1,6b3343cf].
+  --config FILE               TOML file with transport, congestion control and Careful Resume settings, overridden by the options given here.
+  --shards NUM                Number of threads handling connections, each with its own event loop [default: 1].
   -h --help                   Show this screen.
 ";
 
@@ -484,7 +573,9 @@ pub struct ServerArgs {
     pub key: String,
     pub disable_gso: bool,
     pub disable_pacing: bool,
+    pub pacing_offload: bool,
     pub enable_pmtud: bool,
+    pub shards: usize,
 }
 
 impl Args for ServerArgs {
@@ -499,8 +590,16 @@ impl Args for ServerArgs {
         let key = args.get_str("--key").to_string();
         let disable_gso = args.get_bool("--disable-gso");
         let disable_pacing = args.get_bool("--disable-pacing");
+        let pacing_offload = args.get_bool("--pacing-offload");
         let enable_pmtud = args.get_bool("--enable-pmtud");
 
+        let shards = args.get_str("--shards");
+        let shards = shards.parse::<usize>().unwrap();
+
+        if !(1..=MAX_SHARDS).contains(&shards) {
+            panic!("Unsupported number of shards.");
+        }
+
         ServerArgs {
             listen,
             no_retry,
@@ -510,7 +609,9 @@ impl Args for ServerArgs {
             key,
             disable_gso,
             disable_pacing,
+            pacing_offload,
             enable_pmtud,
+            shards,
         }
     }
 }
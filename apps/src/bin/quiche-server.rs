@@ -41,6 +41,13 @@ use std::rc::Rc;
 
 use std::cell::RefCell;
 
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use std::time::Duration;
+use std::time::Instant;
+
 use ring::rand::*;
 
 use quiche_apps::args::*;
@@ -49,15 +56,41 @@ use quiche_apps::common::*;
 
 use quiche_apps::sendto::*;
 
+use quiche_apps::shard;
+
 const MAX_BUF_SIZE: usize = 65507;
 
 const MAX_DATAGRAM_SIZE: usize = 1350;
 
-fn main() {
-    let mut buf = [0; MAX_BUF_SIZE];
-    let mut out = [0; MAX_BUF_SIZE];
-    let mut pacing = false;
+/// The event loop token of the listening socket.
+const SOCKET: mio::Token = mio::Token(0);
+
+/// The event loop token used to wake up a shard when packets are handed off
+/// to it.
+const HANDOFF: mio::Token = mio::Token(1);
+
+/// The granularity of the connection timers.
+const TIMER_TICK: Duration = Duration::from_millis(1);
+
+/// The number of slots of the timer wheel of each shard.
+const TIMER_SLOTS: usize = 1024;
+
+/// The state shared by all the shards.
+struct Shared {
+    conn_args: CommonArgs,
+
+    args: ServerArgs,
+
+    conn_id_seed: ring::hmac::Key,
+
+    keylog: Option<std::fs::File>,
+
+    cr_store: Option<Arc<dyn quiche::CrStore>>,
+
+    pkt_count: AtomicU64,
+}
 
+fn main() {
     env_logger::builder().format_timestamp_nanos().init();
 
     // Parse CLI parameters.
@@ -65,17 +98,102 @@ fn main() {
     let conn_args = CommonArgs::with_docopt(&docopt);
     let args = ServerArgs::with_docopt(&docopt);
 
-    // Setup the event loop.
-    let mut poll = mio::Poll::new().unwrap();
-    let mut events = mio::Events::with_capacity(1024);
+    let keylog = std::env::var_os("SSLKEYLOGFILE").map(|keylog_path| {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(keylog_path)
+            .unwrap()
+    });
 
-    // Create the UDP listening socket, and register it with the event loop.
-    let mut socket =
-        mio::net::UdpSocket::bind(args.listen.parse().unwrap()).unwrap();
+    // The Careful Resume store is opened once and shared by all the shards,
+    // so that a connection can resume from parameters stored by any of them.
+    let cr_store = conn_args
+        .config_file
+        .as_ref()
+        .and_then(|file| file.open_cr_store().unwrap());
+
+    let rng = SystemRandom::new();
+    let conn_id_seed =
+        ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &rng).unwrap();
+
+    // Create the UDP listening socket of each shard. With more than one shard,
+    // the sockets are all bound to the same address and the kernel spreads
+    // the incoming connections between them.
+    let listen = args.listen.parse().unwrap();
+
+    let sockets: Vec<mio::net::UdpSocket> = if args.shards == 1 {
+        vec![mio::net::UdpSocket::bind(listen).unwrap()]
+    } else {
+        let first = shard::bind_reuseport(listen).unwrap();
+
+        // Bind to the address of the first socket, in case the port was
+        // picked by the kernel.
+        let listen = first.local_addr().unwrap();
+
+        let mut sockets = vec![first];
+
+        for _ in 1..args.shards {
+            sockets.push(shard::bind_reuseport(listen).unwrap());
+        }
+
+        sockets
+    };
+
+    // Setup the event loop of each shard.
+    let polls: Vec<mio::Poll> =
+        sockets.iter().map(|_| mio::Poll::new().unwrap()).collect();
+
+    let registries: Vec<&mio::Registry> =
+        polls.iter().map(|poll| poll.registry()).collect();
+    let routers = shard::Router::new(&registries, HANDOFF).unwrap();
+
+    let shared = Shared {
+        conn_args,
+        args,
+        conn_id_seed,
+        keylog,
+        cr_store,
+        pkt_count: AtomicU64::new(0),
+    };
+
+    std::thread::scope(|s| {
+        let shards = polls.into_iter().zip(sockets).zip(routers);
+
+        for ((poll, socket), router) in shards {
+            let shared = &shared;
+
+            std::thread::Builder::new()
+                .name(format!("shard-{}", router.shard()))
+                .spawn_scoped(s, move || run_shard(poll, socket, router, shared))
+                .unwrap();
+        }
+    });
+}
+
+/// Runs the event loop of a shard, which handles the connections received on
+/// its `socket`, and the packets of its connections handed off by the other
+/// shards through its `router`.
+fn run_shard(
+    mut poll: mio::Poll, mut socket: mio::net::UdpSocket, router: shard::Router,
+    shared: &Shared,
+) {
+    let mut buf = [0; MAX_BUF_SIZE];
+    let mut out = [0; MAX_BUF_SIZE];
+    let mut pacing = false;
+
+    let conn_args = &shared.conn_args;
+    let args = &shared.args;
+
+    let mut events = mio::Events::with_capacity(1024);
 
     // Set SO_TXTIME socket option on the listening UDP socket for pacing
     // outgoing packets.
-    if !args.disable_pacing {
+    //
+    // With --pacing-offload the connections still pace, but instead of
+    // timestamping each packet the aggregate pacing rate is handed to the fq
+    // qdisc with SO_MAX_PACING_RATE, and the kernel spreads the packets.
+    if !args.disable_pacing && !args.pacing_offload {
         match set_txtime_sockopt(&socket) {
             Ok(_) => {
                 pacing = true;
@@ -85,10 +203,14 @@ fn main() {
         };
     }
 
-    info!("listening on {:}", socket.local_addr().unwrap());
+    info!(
+        "shard {} listening on {:}",
+        router.shard(),
+        socket.local_addr().unwrap()
+    );
 
     poll.registry()
-        .register(&mut socket, mio::Token(0), mio::Interest::READABLE)
+        .register(&mut socket, SOCKET, mio::Interest::READABLE)
         .unwrap();
 
     let max_datagram_size = MAX_DATAGRAM_SIZE;
@@ -127,19 +249,11 @@ fn main() {
     config.set_max_connection_window(conn_args.max_window);
     config.set_max_stream_window(conn_args.max_stream_window);
 
-    config.enable_pacing(pacing);
+    config.enable_pacing(pacing || (args.pacing_offload && !args.disable_pacing));
 
-    let mut keylog = None;
-
-    if let Some(keylog_path) = std::env::var_os("SSLKEYLOGFILE") {
-        let file = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(keylog_path)
-            .unwrap();
-
-        keylog = Some(file);
+    let mut max_pacing_rate = None;
 
+    if shared.keylog.is_some() {
         config.log_keys();
     }
 
@@ -162,32 +276,42 @@ fn main() {
     if conn_args.disable_resume {
         config.enable_resume(false);
     }
+
+    config
+        .set_cr_validating_policy_name(&conn_args.cr_validating_policy)
+        .unwrap();
+
+    if let Some(file) = &conn_args.config_file {
+        file.configure_with_cr_store(&mut config, shared.cr_store.clone())
+            .unwrap();
+    }
+
+    config
+        .set_available_versions(&conn_args.available_versions)
+        .unwrap();
     if conn_args.dgrams_enabled {
         config.enable_dgram(true, 1000, 1000);
     }
 
     let rng = SystemRandom::new();
-    let conn_id_seed =
-        ring::hmac::Key::generate(ring::hmac::HMAC_SHA256, &rng).unwrap();
 
     let mut next_client_id = 0;
     let mut clients_ids = ClientIdMap::new();
     let mut clients = ClientMap::new();
 
-    let mut pkt_count = 0;
+    let mut timers =
+        shard::TimerWheel::new(TIMER_TICK, TIMER_SLOTS, Instant::now());
 
     let mut continue_write = false;
 
     let local_addr = socket.local_addr().unwrap();
 
     loop {
-        // Find the shorter timeout from all the active connections.
-        //
-        // TODO: use event loop that properly supports timers
+        // Wait until the next timer of the connections fires.
         let timeout = match continue_write {
-            true => Some(std::time::Duration::from_secs(0)),
+            true => Some(Duration::from_secs(0)),
 
-            false => clients.values().filter_map(|c| c.conn.timeout()).min(),
+            false => timers.timeout(Instant::now()),
         };
 
         let mut poll_res = poll.poll(&mut events, timeout);
@@ -200,40 +324,57 @@ fn main() {
             }
         }
 
+        // Handle the timers of the connections that fired.
+        for client_id in timers.expire(Instant::now()) {
+            trace!("timed out");
+
+            if let Some(client) = clients.get_mut(&client_id) {
+                client.conn.on_timeout();
+
+                timers.schedule(client_id, client.conn.timeout_instant());
+            }
+        }
+
         // Read incoming UDP packets from the socket and feed them to quiche,
         // until there are no more packets to read.
         'read: loop {
             // If the event loop reported no events, it means that the timeout
-            // has expired, so handle it without attempting to read packets. We
-            // will then proceed with the send loop.
+            // has expired, so there are no packets to read. We will then
+            // proceed with the send loop.
             if events.is_empty() && !continue_write {
-                trace!("timed out");
-
-                clients.values_mut().for_each(|c| c.conn.on_timeout());
-
                 break 'read;
             }
 
-            let (len, from) = match socket.recv_from(&mut buf) {
-                Ok(v) => v,
+            let (len, from, handed_off) = match socket.recv_from(&mut buf) {
+                Ok((len, from)) => (len, from, false),
 
-                Err(e) => {
-                    // There are no more UDP packets to read, so end the read
-                    // loop.
-                    if e.kind() == std::io::ErrorKind::WouldBlock {
-                        trace!("recv() would block");
-                        break 'read;
-                    }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    // There are no more UDP packets to read, so process the
+                    // packets handed off by the other shards, and then end the
+                    // read loop.
+                    match router.recv(&mut buf) {
+                        Some((len, from)) => (len, from, true),
 
-                    panic!("recv() failed: {:?}", e);
+                        None => {
+                            trace!("recv() would block");
+                            break 'read;
+                        },
+                    }
                 },
+
+                Err(e) => panic!("recv() failed: {:?}", e),
             };
 
             trace!("got {} bytes", len);
 
             let pkt_buf = &mut buf[..len];
 
-            if let Some(target_path) = conn_args.dump_packet_path.as_ref() {
+            // Packets handed off by another shard were dumped by it.
+            let dump_packet_path =
+                conn_args.dump_packet_path.as_ref().filter(|_| !handed_off);
+
+            if let Some(target_path) = dump_packet_path {
+                let pkt_count = shared.pkt_count.fetch_add(1, Ordering::Relaxed);
                 let path = format!("{target_path}/{pkt_count}.pkt");
 
                 if let Ok(f) = std::fs::File::create(path) {
@@ -242,8 +383,6 @@ fn main() {
                 }
             }
 
-            pkt_count += 1;
-
             // Parse the QUIC packet's header.
             let hdr = match quiche::Header::from_slice(
                 pkt_buf,
@@ -259,10 +398,34 @@ fn main() {
 
             trace!("got packet {:?}", hdr);
 
+            // The kernel delivers all the packets of a 4-tuple to the same
+            // shard, but after a connection migrated its packets can be
+            // delivered to another one. Short header packets of unknown
+            // connections are handed off to the shard that issued their
+            // connection ID.
+            if !handed_off &&
+                hdr.ty == quiche::Type::Short &&
+                !clients_ids.contains_key(&hdr.dcid)
+            {
+                let owner = router.owner(&hdr.dcid);
+
+                if owner != router.shard() {
+                    trace!("handing off packet to shard {}", owner);
+
+                    if let Err(e) = router.hand_off(owner, pkt_buf, from) {
+                        error!("hand off to shard {} failed: {:?}", owner, e);
+                    }
+
+                    continue 'read;
+                }
+            }
+
             let conn_id = if !cfg!(feature = "fuzzing") {
-                let conn_id = ring::hmac::sign(&conn_id_seed, &hdr.dcid);
-                let conn_id = &conn_id.as_ref()[..quiche::MAX_CONN_ID_LEN];
-                conn_id.to_vec().into()
+                let conn_id = ring::hmac::sign(&shared.conn_id_seed, &hdr.dcid);
+                let mut conn_id =
+                    conn_id.as_ref()[..quiche::MAX_CONN_ID_LEN].to_vec();
+                shard::set_shard(&mut conn_id, router.shard());
+                conn_id.into()
             } else {
                 // When fuzzing use an all zero connection ID.
                 [0; quiche::MAX_CONN_ID_LEN].to_vec().into()
@@ -303,10 +466,12 @@ fn main() {
 
                 let mut odcid = None;
 
-                if !args.no_retry {
-                    // Token is always present in Initial packets.
-                    let token = hdr.token.as_ref().unwrap();
+                // Token is always present in Initial packets.
+                let token = hdr.token.as_ref().unwrap();
 
+                // Tokens carrying Careful Resume parameters are validated by
+                // the connection itself, so they don't go through Retry.
+                if !args.no_retry && !quiche::is_resume_token(token) {
                     // Do stateless retry if the client didn't send a token.
                     if token.is_empty() {
                         warn!("Doing stateless retry");
@@ -370,7 +535,7 @@ fn main() {
                 )
                 .unwrap();
 
-                if let Some(keylog) = &mut keylog {
+                if let Some(keylog) = &shared.keylog {
                     if let Ok(keylog) = keylog.try_clone() {
                         conn.set_keylog(Box::new(keylog));
                     }
@@ -391,6 +556,21 @@ fn main() {
                     }
                 }
 
+                // Only capture packets if the user asked for it.
+                if let Some(dir) = std::env::var_os("PCAPDIR") {
+                    let id = format!("{:?}", &scid);
+                    let writer = make_pcap_writer(&dir, "server", &id);
+
+                    conn.set_pcap(std::boxed::Box::new(writer));
+                }
+
+                if let Some(dir) = std::env::var_os("RATESDIR") {
+                    let id = format!("{:?}", &scid);
+                    let writer = make_rate_sample_writer(&dir, "server", &id);
+
+                    conn.set_rate_sample_writer(std::boxed::Box::new(writer));
+                }
+
                 let client_id = next_client_id;
 
                 let client = Client {
@@ -528,13 +708,16 @@ fn main() {
 
             // Provides as many CIDs as possible.
             while client.conn.scids_left() > 0 {
-                let (scid, reset_token) = generate_cid_and_reset_token(&rng);
+                let (scid, reset_token) =
+                    shard::generate_cid_and_reset_token(&rng, router.shard());
                 if client.conn.new_scid(&scid, reset_token, false).is_err() {
                     break;
                 }
 
                 clients_ids.insert(scid, client.client_id);
             }
+
+            timers.schedule(client.client_id, client.conn.timeout_instant());
         }
 
         // Generate outgoing QUIC packets for all active connections and send
@@ -591,6 +774,8 @@ fn main() {
                 }
             }
 
+            timers.schedule(client.client_id, client.conn.timeout_instant());
+
             if total_write == 0 || dst_info.is_none() {
                 continue;
             }
@@ -620,6 +805,26 @@ fn main() {
             }
         }
 
+        // Hand the pacing rate of the connections to fq. All connections share
+        // the listening socket, so its rate is the sum of theirs.
+        if args.pacing_offload && !args.disable_pacing {
+            let rate = clients
+                .values()
+                .filter_map(|c| c.conn.pacing_rate())
+                .fold(0_u64, |acc, r| acc.saturating_add(r));
+
+            if max_pacing_rate != Some(rate) {
+                match set_max_pacing_rate_sockopt(&socket, rate) {
+                    Ok(_) => {
+                        trace!("set SO_MAX_PACING_RATE to {} bytes/s", rate);
+                        max_pacing_rate = Some(rate);
+                    },
+
+                    Err(e) => debug!("setsockopt failed {:?}", e),
+                }
+            }
+        }
+
         // Garbage collect closed connections.
         clients.retain(|_, ref mut c| {
             trace!("Collecting garbage");
@@ -636,6 +841,8 @@ fn main() {
                     let id_owned = id.clone().into_owned();
                     clients_ids.remove(&id_owned);
                 }
+
+                timers.schedule(c.client_id, None);
             }
 
             !c.conn.is_closed()
@@ -801,3 +1008,57 @@ fn set_txtime_sockopt(_: &mio::net::UdpSocket) -> io::Result<()> {
         "Not supported on this platform",
     ))
 }
+
+/// Set SO_MAX_PACING_RATE socket option.
+///
+/// This socket option caps the rate at which the fq qdisc sends the packets
+/// of the socket, in bytes per second. A rate of 0, as when no connection is
+/// pacing, removes the cap.
+///
+/// Note that this socket option is set only on linux platforms.
+#[cfg(target_os = "linux")]
+fn set_max_pacing_rate_sockopt(
+    sock: &mio::net::UdpSocket, rate: u64,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // Not exported by libc for all targets.
+    const SO_MAX_PACING_RATE: libc::c_int = 47;
+
+    // The kernel takes a 32-bit rate unless given a 64-bit value, but older
+    // kernels only accept the former.
+    let rate = match rate {
+        0 => u32::MAX,
+
+        rate => u32::try_from(rate).unwrap_or(u32::MAX),
+    };
+
+    let rc = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            SO_MAX_PACING_RATE,
+            &rate as *const u32 as *const libc::c_void,
+            std::mem::size_of::<u32>() as libc::socklen_t,
+        )
+    };
+
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_max_pacing_rate_sockopt(
+    _: &mio::net::UdpSocket, _: u64,
+) -> io::Result<()> {
+    use std::io::Error;
+    use std::io::ErrorKind;
+
+    Err(Error::new(
+        ErrorKind::Other,
+        "Not supported on this platform",
+    ))
+}
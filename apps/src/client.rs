@@ -35,6 +35,8 @@ use std::rc::Rc;
 
 use std::cell::RefCell;
 
+use std::convert::TryFrom;
+
 use ring::rand::*;
 
 const MAX_DATAGRAM_SIZE: usize = 1350;
@@ -46,16 +48,131 @@ pub enum ClientError {
     Other(String),
 }
 
+impl std::fmt::Display for ClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ClientError::HandshakeFail => write!(f, "handshake failed"),
+
+            ClientError::HttpFail => write!(f, "http failed"),
+
+            ClientError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The result of a single transfer.
+pub struct RunReport {
+    /// Whether the TLS session of a previous run was resumed.
+    pub resumed: bool,
+
+    /// The largest increase of the congestion window between two iterations
+    /// of the event loop, in bytes. quiche doesn't expose the Careful Resume
+    /// jump, so this is how it is estimated.
+    pub jump: usize,
+
+    /// The number of packets lost.
+    pub lost: usize,
+
+    /// The state to resume the next run from.
+    pub saved: quiche::SavedTransport,
+}
+
 pub fn connect(
     args: ClientArgs, conn_args: CommonArgs,
     output_sink: impl FnMut(String) + 'static,
 ) -> Result<(), ClientError> {
-    let mut buf = [0; 65535];
-    let mut out = [0; MAX_DATAGRAM_SIZE];
-
     let output_sink =
         Rc::new(RefCell::new(output_sink)) as Rc<RefCell<dyn FnMut(_)>>;
 
+    if args.repeat <= 1 {
+        return run(&args, &conn_args, &output_sink, None).map(|_| ());
+    }
+
+    // Run the transfer repeatedly, each run resuming from the state saved
+    // by the last successful one, so that Careful Resume cycles between
+    // observing and resuming.
+    let mut saved = None;
+    let mut runs = Vec::new();
+    let mut result = Ok(());
+
+    for i in 0..args.repeat {
+        if i > 0 {
+            std::thread::sleep(args.cooldown);
+        }
+
+        let start = std::time::Instant::now();
+
+        let outcome = run(&args, &conn_args, &output_sink, saved.as_ref());
+
+        let outcome = match outcome {
+            Ok(report) => {
+                saved = Some(report.saved.clone());
+                Ok(report)
+            },
+
+            Err(e) => {
+                let outcome = Err(e.to_string());
+
+                if result.is_ok() {
+                    result = Err(e);
+                }
+
+                outcome
+            },
+        };
+
+        runs.push((start.elapsed(), outcome));
+    }
+
+    print_runs(&runs);
+
+    result
+}
+
+// Prints a table summarizing repeated runs to stderr, leaving stdout to the
+// responses.
+fn print_runs(runs: &[(std::time::Duration, Result<RunReport, String>)]) {
+    eprintln!(
+        "{:>4} {:>12} {:>8} {:>10} {:>6}  outcome",
+        "run", "duration", "resumed", "jump", "lost"
+    );
+
+    for (i, (duration, outcome)) in runs.iter().enumerate() {
+        let duration = format!("{:.3}ms", duration.as_secs_f64() * 1000.0);
+
+        match outcome {
+            Ok(r) => eprintln!(
+                "{:>4} {:>12} {:>8} {:>10} {:>6}  ok",
+                i + 1,
+                duration,
+                if r.resumed { "yes" } else { "no" },
+                r.jump,
+                r.lost
+            ),
+
+            Err(e) => eprintln!(
+                "{:>4} {:>12} {:>8} {:>10} {:>6}  {}",
+                i + 1,
+                duration,
+                "-",
+                "-",
+                "-",
+                e
+            ),
+        }
+    }
+}
+
+/// Runs the transfer once, resuming from `previous` when given, or from the
+/// session file otherwise.
+fn run(
+    args: &ClientArgs, conn_args: &CommonArgs,
+    output_sink: &Rc<RefCell<dyn FnMut(String)>>,
+    previous: Option<&quiche::SavedTransport>,
+) -> Result<RunReport, ClientError> {
+    let mut buf = [0; 65535];
+    let mut out = [0; MAX_DATAGRAM_SIZE];
+
     // Setup the event loop.
     let mut poll = mio::Poll::new().unwrap();
     let mut events = mio::Events::with_capacity(1024);
@@ -131,6 +248,10 @@ pub fn connect(
     config.set_max_connection_window(conn_args.max_window);
     config.set_max_stream_window(conn_args.max_stream_window);
 
+    config
+        .set_available_versions(&conn_args.available_versions)
+        .unwrap();
+
     let mut keylog = None;
 
     if let Some(keylog_path) = std::env::var_os("SSLKEYLOGFILE") {
@@ -161,6 +282,22 @@ pub fn connect(
         config.enable_hystart(false);
     }
 
+    config.set_initial_congestion_window_packets(
+        usize::try_from(conn_args.initial_cwnd_packets).unwrap(),
+    );
+
+    if conn_args.disable_resume {
+        config.enable_resume(false);
+    }
+
+    config
+        .set_cr_validating_policy_name(&conn_args.cr_validating_policy)
+        .unwrap();
+
+    if let Some(file) = &conn_args.config_file {
+        file.configure(&mut config).map_err(ClientError::Other)?;
+    }
+
     if conn_args.dgrams_enabled {
         config.enable_dgram(true, 1000, 1000);
     }
@@ -217,7 +354,24 @@ pub fn connect(
         }
     }
 
-    if let Some(session_file) = &args.session_file {
+    // Only capture packets if the user asked for it.
+    if let Some(dir) = std::env::var_os("PCAPDIR") {
+        let id = format!("{scid:?}");
+        let writer = make_pcap_writer(&dir, "client", &id);
+
+        conn.set_pcap(std::boxed::Box::new(writer));
+    }
+
+    if let Some(dir) = std::env::var_os("RATESDIR") {
+        let id = format!("{scid:?}");
+        let writer = make_rate_sample_writer(&dir, "client", &id);
+
+        conn.set_rate_sample_writer(std::boxed::Box::new(writer));
+    }
+
+    if let Some(previous) = previous {
+        conn.resume_from(previous).ok();
+    } else if let Some(session_file) = &args.session_file {
         if let Ok(session) = std::fs::read(session_file) {
             conn.set_session(&session).ok();
         }
@@ -255,6 +409,9 @@ pub fn connect(
     let mut new_path_probed = false;
     let mut migrated = false;
 
+    let mut cwnd = None;
+    let mut jump = 0;
+
     loop {
         if !conn.is_in_early_data() || app_proto_selected {
             poll.poll(&mut events, conn.timeout()).unwrap();
@@ -384,7 +541,7 @@ pub fn connect(
                 http_conn = Some(Http09Conn::with_urls(
                     &args.urls,
                     args.reqs_cardinal,
-                    Rc::clone(&output_sink),
+                    Rc::clone(output_sink),
                 ));
 
                 app_proto_selected = true;
@@ -412,7 +569,7 @@ pub fn connect(
                     conn_args.qpack_blocked_streams,
                     args.dump_json,
                     dgram_sender,
-                    Rc::clone(&output_sink),
+                    Rc::clone(output_sink),
                 ));
 
                 app_proto_selected = true;
@@ -562,6 +719,14 @@ pub fn connect(
             }
         }
 
+        if let Some(stats) = conn.path_stats().find(|s| s.active) {
+            if let Some(cwnd) = cwnd {
+                jump = jump.max(stats.cwnd.saturating_sub(cwnd));
+            }
+
+            cwnd = Some(stats.cwnd);
+        }
+
         if conn.is_closed() {
             info!(
                 "connection closed, {:?} {:?}",
@@ -594,5 +759,10 @@ pub fn connect(
         }
     }
 
-    Ok(())
+    Ok(RunReport {
+        resumed: conn.is_resumed(),
+        jump,
+        lost: conn.stats().lost,
+        saved: conn.saved_transport(),
+    })
 }
@@ -164,6 +164,36 @@ pub fn make_qlog_writer(
     }
 }
 
+/// Makes a buffered writer for a packet capture.
+pub fn make_pcap_writer(
+    dir: &std::ffi::OsStr, role: &str, id: &str,
+) -> std::io::BufWriter<std::fs::File> {
+    make_trace_writer(dir, &format!("{role}-{id}.pcap"), "pcap")
+}
+
+/// Makes a buffered writer for delivery rate samples.
+pub fn make_rate_sample_writer(
+    dir: &std::ffi::OsStr, role: &str, id: &str,
+) -> std::io::BufWriter<std::fs::File> {
+    make_trace_writer(dir, &format!("{role}-{id}.rates.csv"), "rate sample")
+}
+
+fn make_trace_writer(
+    dir: &std::ffi::OsStr, filename: &str, kind: &str,
+) -> std::io::BufWriter<std::fs::File> {
+    let mut path = std::path::PathBuf::from(dir);
+    path.push(filename);
+
+    match std::fs::File::create(&path) {
+        Ok(f) => std::io::BufWriter::new(f),
+
+        Err(e) => panic!(
+            "Error creating {} file attempted path was {:?}: {}",
+            kind, path, e
+        ),
+    }
+}
+
 fn dump_json(reqs: &[Http3Request], output_sink: &mut dyn FnMut(String)) {
     let mut out = String::new();
 
@@ -533,7 +563,7 @@ impl HttpConn for Http09Conn {
                             req_start.elapsed()
                         );
 
-                        match conn.close(true, 0x00, b"kthxbye") {
+                        match conn.close_and_flush(true, 0x00, b"kthxbye") {
                             // Already closed.
                             Ok(_) | Err(quiche::Error::Done) => (),
 
@@ -1298,7 +1328,7 @@ impl HttpConn for Http3Conn {
                             );
                         }
 
-                        match conn.close(true, 0x100, b"kthxbye") {
+                        match conn.close_and_flush(true, 0x100, b"kthxbye") {
                             // Already closed.
                             Ok(_) | Err(quiche::Error::Done) => (),
 
@@ -1312,7 +1342,7 @@ impl HttpConn for Http3Conn {
                 Ok((_stream_id, quiche::h3::Event::Reset(e))) => {
                     error!("request was reset by peer with {}, closing...", e);
 
-                    match conn.close(true, 0x100, b"kthxbye") {
+                    match conn.close_and_flush(true, 0x100, b"kthxbye") {
                         // Already closed.
                         Ok(_) | Err(quiche::Error::Done) => (),
 
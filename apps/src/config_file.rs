@@ -0,0 +1,665 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! TOML configuration files for the example apps.
+//!
+//! A configuration file passed with `--config` provides the transport,
+//! congestion control and Careful Resume settings of quiche-client and
+//! quiche-server, so that experiments can be scripted without long command
+//! lines:
+//!
+//! ```toml
+//! [transport]
+//! max_data = 10000000
+//! idle_timeout = 30000
+//!
+//! [cc]
+//! algorithm = "cubic"
+//! hystart = false
+//! initial_cwnd_packets = 10
+//!
+//! [careful_resume]
+//! enabled = true
+//! validating_policy = "pipesize"
+//! min_rtts_before_jump = 3
+//! ```
+//!
+//! Options given on the command line take precedence over the file. All keys
+//! are optional, and unknown keys are rejected so that typos don't silently
+//! fall back to defaults.
+
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::args::ClientArgs;
+use crate::args::CommonArgs;
+
+/// How long parameters persisted with `store_file` are used by default.
+const DEFAULT_STORE_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub transport: TransportSection,
+    pub cc: CcSection,
+    pub careful_resume: CarefulResumeSection,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransportSection {
+    pub max_data: Option<u64>,
+    pub max_window: Option<u64>,
+    pub max_stream_data: Option<u64>,
+    pub max_stream_window: Option<u64>,
+    pub max_streams_bidi: Option<u64>,
+    pub max_streams_uni: Option<u64>,
+    pub idle_timeout: Option<u64>,
+    pub early_data: Option<bool>,
+    pub grease: Option<bool>,
+    pub max_active_cids: Option<u64>,
+    pub active_migration: Option<bool>,
+    /// QUIC versions in hex, in order of preference.
+    pub available_versions: Option<Vec<String>>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CcSection {
+    pub algorithm: Option<String>,
+    pub hystart: Option<bool>,
+    pub initial_cwnd_packets: Option<u64>,
+    pub ack_aggregation_compensation: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CarefulResumeSection {
+    pub enabled: Option<bool>,
+    /// One of `default`, `geo`, `leo` or `long_fat`, applied before the
+    /// other settings of the section.
+    pub profile: Option<String>,
+    pub validating_policy: Option<String>,
+    pub min_rtts_before_jump: Option<usize>,
+    pub reconnaissance_timeout_ms: Option<u64>,
+    pub idle_threshold_ms: Option<u64>,
+    pub unvalidated_timeout_ms: Option<u64>,
+    pub validating_timeout_ms: Option<u64>,
+    /// In packets, 0 for no limit.
+    pub max_unvalidated_burst: Option<usize>,
+    /// The RTT must be between these ratios of the previous RTT to jump.
+    pub rtt_min_ratio: Option<f64>,
+    pub rtt_max_ratio: Option<f64>,
+    pub jump_divisor: Option<usize>,
+    /// Between 0.25 and 1.0, takes precedence over `jump_divisor`.
+    pub jump_factor: Option<f64>,
+    /// In multiples of the initial congestion window.
+    pub min_previous_cwnd: Option<usize>,
+    /// Caps the jump at this multiple of the capacity measured during
+    /// reconnaissance, 0 disables the cap.
+    pub plausibility_factor: Option<f64>,
+    /// Either `paced` or `burst`.
+    pub jump_pacing: Option<String>,
+    /// Either `resume` or `congestion_avoidance`.
+    pub hystart_policy: Option<String>,
+    /// One of `pipesize`, `half_pipesize`, `iw_floor:N` or `pre_jump`.
+    pub retreat_policy: Option<String>,
+    pub retreat_export: Option<bool>,
+    /// Either `significant_change` or `periodic`.
+    pub observe_policy: Option<String>,
+    pub observe_max_gap_ms: Option<u64>,
+    pub peer_params_max_age_ms: Option<u64>,
+    pub peer_params_max_cwnd: Option<usize>,
+    pub blob_max_age_ms: Option<u64>,
+    /// Server only: file holding the key used to seal resume blobs.
+    pub blob_key_file: Option<String>,
+    /// Server only: carry resume blobs in NEW_TOKEN frames.
+    pub blob_new_token: Option<bool>,
+    /// File used to persist the observed parameters between runs.
+    pub store_file: Option<String>,
+    pub store_max_age_ms: Option<u64>,
+    /// Client only: file used to cache the TLS session between runs.
+    pub session_file: Option<String>,
+}
+
+impl ConfigFile {
+    /// Reads and parses the configuration file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Self::parse(&s).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let file: ConfigFile = toml::from_str(s).map_err(|e| e.to_string())?;
+
+        if let Some(versions) = &file.transport.available_versions {
+            for v in versions {
+                u32::from_str_radix(v.trim(), 16)
+                    .map_err(|_| format!("invalid QUIC version {:?}", v))?;
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// Copies the settings of the file into `args`, except for those whose
+    /// command line flag `given` reports as explicitly set.
+    pub fn apply_to_args<F: Fn(&str) -> bool>(
+        &self, args: &mut CommonArgs, given: F,
+    ) {
+        fn set<T: Clone>(
+            field: &mut T, value: &Option<T>, flag: &str,
+            given: &dyn Fn(&str) -> bool,
+        ) {
+            if let Some(v) = value {
+                if !given(flag) {
+                    *field = v.clone();
+                }
+            }
+        }
+
+        let t = &self.transport;
+
+        set(&mut args.max_data, &t.max_data, "--max-data", &given);
+        set(&mut args.max_window, &t.max_window, "--max-window", &given);
+        set(
+            &mut args.max_stream_data,
+            &t.max_stream_data,
+            "--max-stream-data",
+            &given,
+        );
+        set(
+            &mut args.max_stream_window,
+            &t.max_stream_window,
+            "--max-stream-window",
+            &given,
+        );
+        set(
+            &mut args.max_streams_bidi,
+            &t.max_streams_bidi,
+            "--max-streams-bidi",
+            &given,
+        );
+        set(
+            &mut args.max_streams_uni,
+            &t.max_streams_uni,
+            "--max-streams-uni",
+            &given,
+        );
+        set(
+            &mut args.idle_timeout,
+            &t.idle_timeout,
+            "--idle-timeout",
+            &given,
+        );
+        set(&mut args.early_data, &t.early_data, "--early-data", &given);
+        set(
+            &mut args.no_grease,
+            &t.grease.map(|v| !v),
+            "--no-grease",
+            &given,
+        );
+        set(
+            &mut args.max_active_cids,
+            &t.max_active_cids,
+            "--max-active-cids",
+            &given,
+        );
+        set(
+            &mut args.enable_active_migration,
+            &t.active_migration,
+            "--enable-active-migration",
+            &given,
+        );
+
+        let versions = t.available_versions.as_ref().map(|versions| {
+            versions
+                .iter()
+                .map(|v| u32::from_str_radix(v.trim(), 16).unwrap())
+                .collect()
+        });
+
+        set(
+            &mut args.available_versions,
+            &versions,
+            "--available-versions",
+            &given,
+        );
+
+        let cc = &self.cc;
+
+        set(
+            &mut args.cc_algorithm,
+            &cc.algorithm,
+            "--cc-algorithm",
+            &given,
+        );
+        set(
+            &mut args.disable_hystart,
+            &cc.hystart.map(|v| !v),
+            "--disable-hystart",
+            &given,
+        );
+        set(
+            &mut args.initial_cwnd_packets,
+            &cc.initial_cwnd_packets,
+            "--initial-cwnd-packets",
+            &given,
+        );
+
+        let cr = &self.careful_resume;
+
+        set(
+            &mut args.disable_resume,
+            &cr.enabled.map(|v| !v),
+            "--disable-resume",
+            &given,
+        );
+        set(
+            &mut args.cr_validating_policy,
+            &cr.validating_policy,
+            "--cr-validating-policy",
+            &given,
+        );
+    }
+
+    /// Copies the client specific settings of the file into `args`, except
+    /// for those whose command line flag `given` reports as explicitly set.
+    pub fn apply_to_client_args<F: Fn(&str) -> bool>(
+        &self, args: &mut ClientArgs, given: F,
+    ) {
+        if let Some(session_file) = &self.careful_resume.session_file {
+            if !given("--session-file") {
+                args.session_file = Some(session_file.clone());
+            }
+        }
+    }
+
+    /// Applies the settings that have no command line flag to `config`.
+    pub fn configure(&self, config: &mut quiche::Config) -> Result<(), String> {
+        let store = self.open_cr_store()?;
+
+        self.configure_with_cr_store(config, store)
+    }
+
+    /// Opens the Careful Resume store given with `store_file`, if any.
+    pub fn open_cr_store(
+        &self,
+    ) -> Result<Option<Arc<dyn quiche::CrStore>>, String> {
+        let cr = &self.careful_resume;
+
+        let path = match &cr.store_file {
+            Some(v) => v,
+
+            None => return Ok(None),
+        };
+
+        let max_age = cr
+            .store_max_age_ms
+            .map_or(DEFAULT_STORE_MAX_AGE, Duration::from_millis);
+
+        let store = quiche::FileCrStore::open(path, max_age)
+            .map_err(|e| format!("{}: {}", path, e))?;
+
+        Ok(Some(Arc::new(store)))
+    }
+
+    /// Like `configure()`, but uses `store` as the Careful Resume store
+    /// instead of opening `store_file`, so that configs can share it.
+    pub fn configure_with_cr_store(
+        &self, config: &mut quiche::Config,
+        store: Option<Arc<dyn quiche::CrStore>>,
+    ) -> Result<(), String> {
+        if let Some(v) = self.cc.ack_aggregation_compensation {
+            config.enable_ack_aggregation_compensation(v);
+        }
+
+        let cr = &self.careful_resume;
+
+        if let Some(v) = &cr.profile {
+            config
+                .set_cr_profile_name(v)
+                .map_err(|_| format!("invalid careful resume profile: {}", v))?;
+        }
+
+        if let Some(v) = cr.min_rtts_before_jump {
+            config.set_cr_min_rtts_before_jump(v);
+        }
+
+        if let Some(v) = cr.reconnaissance_timeout_ms {
+            config.set_cr_reconnaissance_timeout(Duration::from_millis(v));
+        }
+
+        if let Some(v) = cr.idle_threshold_ms {
+            config.set_cr_idle_threshold(Duration::from_millis(v));
+        }
+
+        if let Some(v) = cr.unvalidated_timeout_ms {
+            config.set_cr_unvalidated_timeout(Duration::from_millis(v));
+        }
+
+        if let Some(v) = cr.validating_timeout_ms {
+            config.set_cr_validating_timeout(Duration::from_millis(v));
+        }
+
+        if let Some(v) = cr.max_unvalidated_burst {
+            config.set_cr_max_unvalidated_burst(v);
+        }
+
+        if cr.rtt_min_ratio.is_some() || cr.rtt_max_ratio.is_some() {
+            let min_ratio = cr.rtt_min_ratio.unwrap_or(0.5);
+            let max_ratio = cr.rtt_max_ratio.unwrap_or(10.0);
+
+            config
+                .set_cr_rtt_bounds(min_ratio, max_ratio)
+                .map_err(|e| {
+                    format!(
+                        "invalid RTT bounds {}-{}: {}",
+                        min_ratio, max_ratio, e
+                    )
+                })?;
+        }
+
+        if let Some(v) = cr.jump_divisor {
+            config.set_cr_jump_divisor(v);
+        }
+
+        if let Some(v) = cr.jump_factor {
+            config
+                .set_cr_jump_factor(v)
+                .map_err(|e| format!("invalid jump factor {}: {}", v, e))?;
+        }
+
+        if let Some(v) = cr.min_previous_cwnd {
+            config.set_cr_min_previous_cwnd(v);
+        }
+
+        if let Some(v) = cr.plausibility_factor {
+            config.set_cr_plausibility_factor(v).map_err(|e| {
+                format!("invalid plausibility factor {}: {}", v, e)
+            })?;
+        }
+
+        if let Some(v) = &cr.jump_pacing {
+            config
+                .set_cr_jump_pacing_name(v)
+                .map_err(|_| format!("invalid jump pacing: {}", v))?;
+        }
+
+        if let Some(v) = &cr.hystart_policy {
+            config
+                .set_cr_hystart_policy_name(v)
+                .map_err(|_| format!("invalid HyStart++ policy: {}", v))?;
+        }
+
+        if let Some(v) = &cr.retreat_policy {
+            config
+                .set_cr_retreat_policy_name(v)
+                .map_err(|_| format!("invalid retreat policy: {}", v))?;
+        }
+
+        if let Some(v) = cr.retreat_export {
+            config.enable_resume_retreat_export(v);
+        }
+
+        if let Some(v) = &cr.observe_policy {
+            config
+                .set_cr_observe_policy_name(v)
+                .map_err(|_| format!("invalid observe policy: {}", v))?;
+        }
+
+        if let Some(v) = cr.observe_max_gap_ms {
+            config
+                .set_cr_observe_max_gap(Duration::from_millis(v))
+                .map_err(|e| format!("invalid observe max gap {}: {}", v, e))?;
+        }
+
+        if let Some(v) = cr.peer_params_max_age_ms {
+            config.set_cr_peer_params_max_age(Duration::from_millis(v));
+        }
+
+        if let Some(v) = cr.peer_params_max_cwnd {
+            config.set_cr_peer_params_max_cwnd(v);
+        }
+
+        if let Some(v) = cr.blob_max_age_ms {
+            config.set_resume_blob_max_age(Duration::from_millis(v));
+        }
+
+        if let Some(path) = &cr.blob_key_file {
+            let key =
+                std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+
+            config.set_resume_blob_key(&key).map_err(|e| {
+                format!("{}: invalid resume blob key: {}", path, e)
+            })?;
+        }
+
+        if let Some(v) = cr.blob_new_token {
+            config.enable_resume_new_token(v);
+        }
+
+        if let Some(store) = store {
+            config.set_cr_store(store);
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns whether `flag` was given in the command line `argv`.
+pub fn flag_given(argv: &[String], flag: &str) -> bool {
+    argv.iter().any(|a| {
+        a == flag || (a.starts_with(flag) && a[flag.len()..].starts_with('='))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let file = ConfigFile::parse(
+            r#"
+            [transport]
+            max_data = 20000000
+            grease = false
+            available_versions = ["1"]
+
+            [cc]
+            algorithm = "reno"
+            hystart = false
+            ack_aggregation_compensation = true
+
+            [careful_resume]
+            validating_policy = "pipesize"
+            min_rtts_before_jump = 3
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(file.transport.max_data, Some(20000000));
+        assert_eq!(file.transport.grease, Some(false));
+        assert_eq!(file.transport.idle_timeout, None);
+        assert_eq!(file.cc.algorithm.as_deref(), Some("reno"));
+        assert_eq!(file.cc.ack_aggregation_compensation, Some(true));
+        assert_eq!(file.careful_resume.min_rtts_before_jump, Some(3));
+        assert_eq!(file.careful_resume.enabled, None);
+
+        assert_eq!(ConfigFile::parse("").unwrap(), ConfigFile::default());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        // Unknown keys and sections.
+        assert!(ConfigFile::parse("[cc]\nalgoritm = \"reno\"").is_err());
+        assert!(ConfigFile::parse("[resume]\nenabled = false").is_err());
+
+        // Wrong types.
+        assert!(ConfigFile::parse("[transport]\nmax_data = \"1\"").is_err());
+
+        assert!(
+            ConfigFile::parse("[transport]\navailable_versions = [\"v1\"]")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn apply_to_args() {
+        let file = ConfigFile::parse(
+            r#"
+            [transport]
+            max_data = 20000000
+            max_window = 30000000
+            grease = false
+            available_versions = ["1"]
+
+            [cc]
+            algorithm = "reno"
+            hystart = false
+
+            [careful_resume]
+            enabled = false
+            validating_policy = "freeze"
+            "#,
+        )
+        .unwrap();
+
+        let argv: Vec<String> = [
+            "quiche-server",
+            "--max-window=1000",
+            "--cc-algorithm",
+            "bbr2",
+        ]
+        .iter()
+        .map(|a| a.to_string())
+        .collect();
+
+        let mut args = CommonArgs {
+            max_window: 1000,
+            cc_algorithm: "bbr2".to_string(),
+            ..Default::default()
+        };
+
+        file.apply_to_args(&mut args, |flag| flag_given(&argv, flag));
+
+        assert_eq!(args.max_data, 20000000);
+        assert!(args.no_grease);
+        assert_eq!(args.available_versions, vec![quiche::PROTOCOL_VERSION_V1]);
+        assert!(args.disable_hystart);
+        assert!(args.disable_resume);
+        assert_eq!(args.cr_validating_policy, "freeze");
+
+        // Flags given on the command line are kept.
+        assert_eq!(args.max_window, 1000);
+        assert_eq!(args.cc_algorithm, "bbr2");
+
+        // Settings missing from the file are kept.
+        assert_eq!(args.idle_timeout, CommonArgs::default().idle_timeout);
+    }
+
+    #[test]
+    fn configure() {
+        let file = ConfigFile::parse(
+            r#"
+            [careful_resume]
+            min_rtts_before_jump = 3
+            reconnaissance_timeout_ms = 1000
+            idle_threshold_ms = 5000
+            validating_timeout_ms = 2000
+            max_unvalidated_burst = 10
+            rtt_max_ratio = 4.0
+            jump_divisor = 3
+            jump_pacing = "burst"
+            hystart_policy = "congestion_avoidance"
+            retreat_policy = "iw_floor:4"
+            peer_params_max_age_ms = 60000
+            blob_key_file = "/nonexistent/quiche-blob.key"
+            "#,
+        )
+        .unwrap();
+
+        let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+
+        assert!(file.configure(&mut config).is_err());
+
+        let mut file = file;
+        file.careful_resume.blob_key_file = None;
+
+        assert!(file.configure(&mut config).is_ok());
+
+        file.careful_resume.jump_factor = Some(1.5);
+
+        assert!(file.configure(&mut config).is_err());
+
+        file.careful_resume.jump_factor = Some(0.75);
+
+        assert!(file.configure(&mut config).is_ok());
+        assert_eq!(config.cr_config().jump_factor(), 0.75);
+
+        file.careful_resume.jump_pacing = Some("sometimes".to_string());
+
+        assert!(file.configure(&mut config).is_err());
+
+        file.careful_resume.jump_pacing = None;
+        file.careful_resume.hystart_policy = Some("never".to_string());
+
+        assert!(file.configure(&mut config).is_err());
+
+        file.careful_resume.hystart_policy = None;
+        file.careful_resume.retreat_policy = Some("iw_floor:x".to_string());
+
+        assert!(file.configure(&mut config).is_err());
+
+        file.careful_resume.retreat_policy = None;
+        file.careful_resume.observe_policy = Some("sometimes".to_string());
+
+        assert!(file.configure(&mut config).is_err());
+
+        file.careful_resume.observe_policy = Some("periodic".to_string());
+        file.careful_resume.observe_max_gap_ms = Some(5000);
+
+        assert!(file.configure(&mut config).is_ok());
+
+        file.careful_resume.profile = Some("meo".to_string());
+
+        assert!(file.configure(&mut config).is_err());
+
+        // The jump factor set in the section overrides the profile.
+        file.careful_resume.profile = Some("geo".to_string());
+
+        assert!(file.configure(&mut config).is_ok());
+        assert_eq!(config.cr_config().jump_factor(), 0.75);
+    }
+}
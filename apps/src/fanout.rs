@@ -0,0 +1,290 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Spreading queued bodies over several streams.
+//!
+//! When Careful Resume jumps the congestion window, the jumped window has to
+//! be filled during the Unvalidated phase, otherwise the sender is considered
+//! application limited and the jump is abandoned. Writing queued responses one
+//! after the other quickly runs into stream flow control, so [`FanOut`] writes
+//! them round-robin, in chunks sized so that the data written in each round
+//! exceeds what the congestion window allows to be sent.
+
+use std::collections::VecDeque;
+
+/// The smallest chunk written to a stream in a round.
+pub const MIN_CHUNK_SIZE: usize = 1350;
+
+/// How much more data than the congestion window is written in each round,
+/// so that the connection stays cwnd-limited when the window grows.
+const CWND_BUDGET_MULTIPLIER: usize = 2;
+
+struct Pending {
+    stream_id: u64,
+    body: Vec<u8>,
+    written: usize,
+}
+
+/// Round-robin writer of queued request or response bodies.
+#[derive(Default)]
+pub struct FanOut {
+    pending: VecDeque<Pending>,
+}
+
+impl FanOut {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues `body` to be written on `stream_id`, followed by a FIN.
+    pub fn push(&mut self, stream_id: u64, body: Vec<u8>) {
+        self.pending.push_back(Pending {
+            stream_id,
+            body,
+            written: 0,
+        });
+    }
+
+    /// Returns the number of streams that still have data or a FIN queued.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Returns how many bytes should be written in the next round to keep
+    /// `conn` cwnd-limited.
+    pub fn budget(conn: &quiche::Connection) -> usize {
+        let cwnd = conn
+            .path_stats()
+            .find(|s| s.active)
+            .map(|s| s.cwnd)
+            .unwrap_or(0);
+
+        cwnd * CWND_BUDGET_MULTIPLIER
+    }
+
+    /// Returns the size of the chunk written to each stream in a round.
+    pub fn chunk_size(&self, budget: usize) -> usize {
+        if self.pending.is_empty() {
+            return 0;
+        }
+
+        budget.div_ceil(self.pending.len()).max(MIN_CHUNK_SIZE)
+    }
+
+    /// Writes up to `budget` bytes, round-robin across the queued streams,
+    /// using `write(stream_id, data, fin)`.
+    ///
+    /// `write` returns the number of bytes accepted by the stream, which can
+    /// be fewer than given, or zero when the stream is blocked. Streams that
+    /// had all their data and FIN accepted are removed from the queue.
+    ///
+    /// On error the stream is removed from the queue and the error returned.
+    /// Otherwise the total number of bytes written is returned.
+    pub fn send<E>(
+        &mut self, budget: usize,
+        mut write: impl FnMut(u64, &[u8], bool) -> Result<usize, E>,
+    ) -> Result<usize, E> {
+        let chunk = self.chunk_size(budget);
+        let mut total = 0;
+
+        loop {
+            let mut progress = false;
+            let mut i = 0;
+
+            while i < self.pending.len() && total < budget {
+                let p = &mut self.pending[i];
+
+                let end = (p.written + chunk).min(p.body.len());
+                let fin = end == p.body.len();
+
+                let data = &p.body[p.written..end];
+
+                let written = match write(p.stream_id, data, fin) {
+                    Ok(v) => v,
+
+                    Err(e) => {
+                        self.pending.remove(i);
+                        return Err(e);
+                    },
+                };
+
+                p.written += written;
+                total += written;
+
+                if written > 0 {
+                    progress = true;
+                }
+
+                if fin && p.written == p.body.len() {
+                    self.pending.remove(i);
+                    progress = true;
+                    continue;
+                }
+
+                i += 1;
+            }
+
+            if !progress || total >= budget || self.pending.is_empty() {
+                break;
+            }
+        }
+
+        Ok(total)
+    }
+
+    /// Writes the next round of data on `conn`'s streams, sized by
+    /// [`budget()`].
+    ///
+    /// [`budget()`]: struct.FanOut.html#method.budget
+    pub fn send_on_conn(
+        &mut self, conn: &mut quiche::Connection,
+    ) -> quiche::Result<usize> {
+        let budget = Self::budget(conn);
+
+        self.send(budget, |stream_id, data, fin| {
+            match conn.stream_send(stream_id, data, fin) {
+                Ok(v) => Ok(v),
+
+                Err(quiche::Error::Done) => Ok(0),
+
+                Err(e) => Err(e),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn chunk_size() {
+        let mut f = FanOut::new();
+        assert_eq!(f.chunk_size(100_000), 0);
+
+        for i in 0..4 {
+            f.push(i * 4, vec![0; 100_000]);
+        }
+
+        assert_eq!(f.chunk_size(100_000), 25_000);
+        assert_eq!(f.chunk_size(1_000), MIN_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn round_robin() {
+        let mut f = FanOut::new();
+
+        for i in 0..4 {
+            f.push(i * 4, vec![i as u8; 10_000]);
+        }
+
+        let mut order = Vec::new();
+        let written = f
+            .send(20_000, |stream_id, data, _| {
+                order.push((stream_id, data.len()));
+                Ok::<_, ()>(data.len())
+            })
+            .unwrap();
+
+        assert_eq!(written, 20_000);
+        assert_eq!(order, vec![
+            (0, 5_000),
+            (4, 5_000),
+            (8, 5_000),
+            (12, 5_000)
+        ]);
+        assert_eq!(f.len(), 4);
+    }
+
+    #[test]
+    fn completes_with_fin() {
+        let mut f = FanOut::new();
+        f.push(0, vec![0; 3_000]);
+        f.push(4, Vec::new());
+
+        let mut received: HashMap<u64, (usize, bool)> = HashMap::new();
+        let written = f
+            .send(100_000, |stream_id, data, fin| {
+                let e = received.entry(stream_id).or_default();
+                e.0 += data.len();
+                e.1 |= fin;
+                Ok::<_, ()>(data.len())
+            })
+            .unwrap();
+
+        assert_eq!(written, 3_000);
+        assert_eq!(received[&0], (3_000, true));
+        assert_eq!(received[&4], (0, true));
+        assert!(f.is_empty());
+    }
+
+    #[test]
+    fn blocked_stream() {
+        let mut f = FanOut::new();
+        f.push(0, vec![0; 10_000]);
+        f.push(4, vec![0; 10_000]);
+
+        // Stream 0 is flow control blocked, so the budget goes to stream 4.
+        let written = f
+            .send(10_000, |stream_id, data, _| {
+                Ok::<_, ()>(if stream_id == 0 { 0 } else { data.len() })
+            })
+            .unwrap();
+
+        assert_eq!(written, 10_000);
+        assert_eq!(f.len(), 1);
+
+        // Nothing can be written when all streams are blocked.
+        assert_eq!(f.send(10_000, |_, _, _| Ok::<_, ()>(0)), Ok(0));
+        assert_eq!(f.len(), 1);
+    }
+
+    #[test]
+    fn write_error() {
+        let mut f = FanOut::new();
+        f.push(0, vec![0; 10_000]);
+        f.push(4, vec![0; 10_000]);
+
+        assert_eq!(
+            f.send(10_000, |stream_id, data, _| {
+                if stream_id == 4 {
+                    Err(quiche::Error::StreamStopped(0))
+                } else {
+                    Ok(data.len())
+                }
+            }),
+            Err(quiche::Error::StreamStopped(0))
+        );
+
+        assert_eq!(f.len(), 1);
+    }
+}
@@ -30,4 +30,8 @@ extern crate log;
 pub mod args;
 pub mod client;
 pub mod common;
+pub mod config_file;
+pub mod fanout;
+pub mod pool;
 pub mod sendto;
+pub mod shard;
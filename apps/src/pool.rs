@@ -0,0 +1,466 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Pooling connections to the same peers.
+//!
+//! Careful Resume lets a new connection jump to the capacity observed by a
+//! previous one, so the deployment it is designed for keeps connections to a
+//! peer around while they are in use, and remembers their transport state
+//! once they are closed. [`Pool`] implements this pattern:
+//!
+//! - new requests are sent on the established connection to the peer with the
+//!   highest measured bandwidth, as reported by Careful Resume and the delivery
+//!   rate estimate;
+//!
+//! - connections idle for longer than the idle threshold are not reused, and
+//!   are returned by [`evict_idle()`] to be closed. Their congestion window
+//!   hasn't been validated for a while, so rather than sending at that rate, a
+//!   new connection is opened and re-armed with the parameters the idle one
+//!   saved when it was closed;
+//!
+//! - when no connection can be reused, [`select()`] returns the
+//!   [`SavedTransport`] of the most recently closed connection to the peer, to
+//!   be passed to [`resume_from()`] on the new connection.
+//!
+//! The pool doesn't own the connections: the application keeps them, e.g. in
+//! a map keyed by the [`ConnId`] returned by [`insert()`], and reports their
+//! state with [`observe()`] after processing their events.
+//!
+//! [`evict_idle()`]: struct.Pool.html#method.evict_idle
+//! [`select()`]: struct.Pool.html#method.select
+//! [`insert()`]: struct.Pool.html#method.insert
+//! [`observe()`]: struct.Pool.html#method.observe
+//! [`SavedTransport`]: ../../quiche/struct.SavedTransport.html
+//! [`resume_from()`]: ../../quiche/struct.Connection.html#method.resume_from
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How long a connection can stay idle before it is no longer reused.
+pub const DEFAULT_IDLE_THRESHOLD: Duration = Duration::from_secs(5);
+
+/// The number of requests a connection carries concurrently before another
+/// connection is preferred.
+pub const DEFAULT_MAX_REQUESTS: usize = 100;
+
+/// Identifies a connection in a [`Pool`].
+pub type ConnId = u64;
+
+/// The state of a connection, as reported to [`Pool::update()`].
+///
+/// [`Pool::update()`]: struct.Pool.html#method.update
+#[derive(Clone, Debug, Default)]
+pub struct Observation {
+    pub established: bool,
+
+    pub closed: bool,
+
+    /// The latest Careful Resume parameters reported by the connection.
+    pub cr: Option<quiche::CREvent>,
+
+    /// The delivery rate of the active path, in bytes per second.
+    pub delivery_rate: u64,
+
+    /// The state to resume a future connection with, when `closed`.
+    pub saved: Option<quiche::SavedTransport>,
+}
+
+/// Where a new request should be sent.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Selection {
+    /// On the pooled connection with the given ID.
+    Reuse(ConnId),
+
+    /// On a new connection, resumed with the given state when known.
+    New(Option<quiche::SavedTransport>),
+}
+
+struct Entry<K> {
+    peer: K,
+
+    established: bool,
+
+    requests: usize,
+
+    last_active: Instant,
+
+    cr: Option<quiche::CREvent>,
+
+    delivery_rate: u64,
+}
+
+impl<K> Entry<K> {
+    /// Returns the bandwidth of the connection, in bytes per second.
+    ///
+    /// The delivery rate only reflects what the application recently sent, so
+    /// the rate allowed by the Careful Resume parameters is used when higher.
+    fn bandwidth(&self) -> u64 {
+        let cr_rate = self
+            .cr
+            .filter(|cr| !cr.min_rtt.is_zero())
+            .map(|cr| {
+                (cr.cwnd as u128 * 1_000_000 / cr.min_rtt.as_micros()) as u64
+            })
+            .unwrap_or(0);
+
+        cr_rate.max(self.delivery_rate)
+    }
+
+    fn is_idle(&self, threshold: Duration, now: Instant) -> bool {
+        self.requests == 0 &&
+            now.saturating_duration_since(self.last_active) > threshold
+    }
+}
+
+/// Tracks connections to peers identified by `K`, and selects which to use
+/// for new requests.
+pub struct Pool<K> {
+    entries: HashMap<ConnId, Entry<K>>,
+
+    saved: HashMap<K, quiche::SavedTransport>,
+
+    next_id: ConnId,
+
+    idle_threshold: Duration,
+
+    max_requests: usize,
+}
+
+impl<K: Clone + Eq + Hash> Default for Pool<K> {
+    fn default() -> Self {
+        Self::new(DEFAULT_IDLE_THRESHOLD, DEFAULT_MAX_REQUESTS)
+    }
+}
+
+impl<K: Clone + Eq + Hash> Pool<K> {
+    pub fn new(idle_threshold: Duration, max_requests: usize) -> Self {
+        Pool {
+            entries: HashMap::new(),
+            saved: HashMap::new(),
+            next_id: 0,
+            idle_threshold,
+            max_requests: max_requests.max(1),
+        }
+    }
+
+    /// Returns the number of pooled connections.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds a new connection to `peer` to the pool, and returns its ID.
+    pub fn insert(&mut self, peer: K, now: Instant) -> ConnId {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.entries.insert(id, Entry {
+            peer,
+            established: false,
+            requests: 0,
+            last_active: now,
+            cr: None,
+            delivery_rate: 0,
+        });
+
+        id
+    }
+
+    /// Returns where a new request to `peer` should be sent at `now`.
+    ///
+    /// Established connections that are neither idle nor carrying the
+    /// maximum number of requests are preferred, highest bandwidth first.
+    pub fn select(&self, peer: &K, now: Instant) -> Selection {
+        let best = self
+            .entries
+            .iter()
+            .filter(|(_, e)| {
+                e.peer == *peer &&
+                    e.established &&
+                    e.requests < self.max_requests &&
+                    !e.is_idle(self.idle_threshold, now)
+            })
+            .max_by_key(|(id, e)| (e.bandwidth(), std::cmp::Reverse(**id)));
+
+        match best {
+            Some((id, _)) => Selection::Reuse(*id),
+
+            None => Selection::New(self.saved.get(peer).cloned()),
+        }
+    }
+
+    /// Returns whether a connection to `peer` should be opened ahead of the
+    /// next request, because no pooled connection can be reused, but a
+    /// previous connection left parameters to resume from.
+    pub fn should_warm(&self, peer: &K, now: Instant) -> bool {
+        let connecting = self
+            .entries
+            .values()
+            .any(|e| e.peer == *peer && !e.established);
+
+        !connecting && matches!(self.select(peer, now), Selection::New(Some(_)))
+    }
+
+    /// Records that a request was started on connection `id`.
+    pub fn start_request(&mut self, id: ConnId, now: Instant) {
+        if let Some(e) = self.entries.get_mut(&id) {
+            e.requests += 1;
+            e.last_active = now;
+        }
+    }
+
+    /// Records that a request on connection `id` completed.
+    pub fn finish_request(&mut self, id: ConnId, now: Instant) {
+        if let Some(e) = self.entries.get_mut(&id) {
+            e.requests = e.requests.saturating_sub(1);
+            e.last_active = now;
+        }
+    }
+
+    /// Updates the state of connection `id`.
+    ///
+    /// Closed connections are removed from the pool, and the state they
+    /// saved is kept for the next connection to the same peer.
+    pub fn update(&mut self, id: ConnId, obs: Observation) {
+        let e = match self.entries.get_mut(&id) {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        e.established = obs.established;
+
+        if obs.cr.is_some() {
+            e.cr = obs.cr;
+        }
+
+        if obs.delivery_rate > 0 {
+            e.delivery_rate = obs.delivery_rate;
+        }
+
+        if obs.closed {
+            let e = self.entries.remove(&id).unwrap();
+
+            if let Some(saved) = obs.saved {
+                self.saved.insert(e.peer, saved);
+            }
+        }
+    }
+
+    /// Updates the state of connection `id` from `conn`.
+    ///
+    /// This consumes the Careful Resume events of `conn`, see
+    /// [`cr_event_next()`].
+    ///
+    /// [`cr_event_next()`]: ../../quiche/struct.Connection.html#method.cr_event_next
+    pub fn observe(&mut self, id: ConnId, conn: &mut quiche::Connection) {
+        let mut cr = None;
+
+        while let Some(ev) = conn.cr_event_next() {
+            cr = Some(ev);
+        }
+
+        let delivery_rate = conn
+            .path_stats()
+            .find(|s| s.active)
+            .map(|s| s.delivery_rate)
+            .unwrap_or(0);
+
+        let closed = conn.is_closed();
+
+        self.update(id, Observation {
+            established: conn.is_established(),
+            closed,
+            cr,
+            delivery_rate,
+            saved: if closed {
+                Some(conn.saved_transport())
+            } else {
+                None
+            },
+        });
+    }
+
+    /// Returns the connections that have been idle for longer than the idle
+    /// threshold at `now`, which should be closed.
+    ///
+    /// They stay in the pool, but are no longer selected, until they are
+    /// reported closed to [`observe()`].
+    ///
+    /// [`observe()`]: struct.Pool.html#method.observe
+    pub fn evict_idle(&self, now: Instant) -> Vec<ConnId> {
+        let mut ids: Vec<ConnId> = self
+            .entries
+            .iter()
+            .filter(|(_, e)| e.is_idle(self.idle_threshold, now))
+            .map(|(id, _)| *id)
+            .collect();
+
+        ids.sort_unstable();
+
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cr(cwnd: usize, rtt_ms: u64) -> quiche::CREvent {
+        quiche::CREvent {
+            min_rtt: Duration::from_millis(rtt_ms),
+            cwnd,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        }
+    }
+
+    fn established(
+        cr: Option<quiche::CREvent>, delivery_rate: u64,
+    ) -> Observation {
+        Observation {
+            established: true,
+            cr,
+            delivery_rate,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn select_highest_bandwidth() {
+        let mut pool = Pool::default();
+        let now = Instant::now();
+
+        assert_eq!(pool.select(&"a", now), Selection::New(None));
+
+        let slow = pool.insert("a", now);
+        let fast = pool.insert("a", now);
+        let other = pool.insert("b", now);
+
+        // Connections still handshaking are not selected.
+        assert_eq!(pool.select(&"a", now), Selection::New(None));
+
+        pool.update(slow, established(None, 1_000_000));
+        pool.update(fast, established(Some(cr(500_000, 50)), 500_000));
+        pool.update(other, established(Some(cr(10_000_000, 50)), 0));
+
+        // 500KB per 50ms is 10MB/s.
+        assert_eq!(pool.select(&"a", now), Selection::Reuse(fast));
+
+        // Busy connections are not selected.
+        for _ in 0..DEFAULT_MAX_REQUESTS {
+            pool.start_request(fast, now);
+        }
+
+        assert_eq!(pool.select(&"a", now), Selection::Reuse(slow));
+
+        pool.finish_request(fast, now);
+
+        assert_eq!(pool.select(&"a", now), Selection::Reuse(fast));
+    }
+
+    #[test]
+    fn resume_after_close() {
+        let mut pool = Pool::default();
+        let now = Instant::now();
+
+        let id = pool.insert("a", now);
+        pool.update(id, established(Some(cr(500_000, 50)), 0));
+
+        let saved = quiche::SavedTransport {
+            session: None,
+            rtt: Some(Duration::from_millis(50)),
+            careful_resume: Some(cr(500_000, 50)),
+        };
+
+        pool.update(id, Observation {
+            established: true,
+            closed: true,
+            saved: Some(saved.clone()),
+            ..Default::default()
+        });
+
+        assert!(pool.is_empty());
+        assert_eq!(pool.select(&"a", now), Selection::New(Some(saved)));
+        assert_eq!(pool.select(&"b", now), Selection::New(None));
+
+        assert!(pool.should_warm(&"a", now));
+        assert!(!pool.should_warm(&"b", now));
+
+        // Not while a new connection is being established.
+        let id = pool.insert("a", now);
+        assert!(!pool.should_warm(&"a", now));
+
+        pool.update(id, established(None, 0));
+        assert!(!pool.should_warm(&"a", now));
+    }
+
+    #[test]
+    fn idle() {
+        let mut pool = Pool::new(Duration::from_secs(1), 10);
+        let now = Instant::now();
+
+        let idle = pool.insert("a", now);
+        pool.update(idle, established(Some(cr(500_000, 50)), 0));
+
+        let busy = pool.insert("a", now);
+        pool.update(busy, established(None, 1_000));
+        pool.start_request(busy, now);
+
+        assert_eq!(pool.select(&"a", now), Selection::Reuse(idle));
+        assert!(pool.evict_idle(now).is_empty());
+
+        // Connections with requests in flight are never idle.
+        let later = now + Duration::from_secs(2);
+
+        assert_eq!(pool.select(&"a", later), Selection::Reuse(busy));
+        assert_eq!(pool.evict_idle(later), vec![idle]);
+
+        pool.finish_request(busy, later);
+
+        let much_later = later + Duration::from_secs(2);
+
+        assert_eq!(pool.select(&"a", much_later), Selection::New(None));
+        assert_eq!(pool.evict_idle(much_later), vec![idle, busy]);
+
+        // Once closed, the idle connection leaves its state for the next one.
+        pool.update(idle, Observation {
+            closed: true,
+            saved: Some(quiche::SavedTransport::default()),
+            ..Default::default()
+        });
+
+        assert_eq!(pool.len(), 1);
+        assert!(pool.should_warm(&"a", much_later));
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Sharding of the server's event loop across threads.
+//!
+//! Each shard runs its own event loop on its own thread, with its own
+//! `SO_REUSEPORT` socket bound to the listening address, so the kernel spreads
+//! the incoming connections between shards by 4-tuple. Connections never
+//! leave the shard that accepted them; instead, the first byte of the
+//! connection IDs issued by a shard identifies it, and packets of a migrated
+//! connection that the kernel delivers to another shard are handed off to the
+//! owning shard through a channel.
+
+use std::collections::HashMap;
+use std::io;
+use std::net;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use ring::rand::SecureRandom;
+
+use crate::common::ClientId;
+
+/// The maximum number of shards, as the shard is encoded in a single byte of
+/// the connection IDs.
+pub const MAX_SHARDS: usize = 256;
+
+/// Returns the shard that issued the connection ID `cid`.
+pub fn shard_of(cid: &[u8], shards: usize) -> usize {
+    cid.first().map_or(0, |b| *b as usize % shards)
+}
+
+/// Marks the connection ID `cid` as issued by `shard`.
+pub fn set_shard(cid: &mut [u8], shard: usize) {
+    if let Some(b) = cid.first_mut() {
+        *b = shard as u8;
+    }
+}
+
+/// Generates a new connection ID issued by `shard`, and its stateless reset
+/// token.
+pub fn generate_cid_and_reset_token<T: SecureRandom>(
+    rng: &T, shard: usize,
+) -> (quiche::ConnectionId<'static>, u128) {
+    let (scid, reset_token) = crate::common::generate_cid_and_reset_token(rng);
+
+    let mut scid = scid.to_vec();
+    set_shard(&mut scid, shard);
+
+    (scid.into(), reset_token)
+}
+
+/// Binds a UDP socket to `addr` with `SO_REUSEPORT`, so that each shard can
+/// bind its own socket to the same address.
+pub fn bind_reuseport(addr: net::SocketAddr) -> io::Result<mio::net::UdpSocket> {
+    use nix::sys::socket::*;
+    use std::os::unix::io::AsRawFd;
+
+    let family = match addr {
+        net::SocketAddr::V4(_) => AddressFamily::Inet,
+        net::SocketAddr::V6(_) => AddressFamily::Inet6,
+    };
+
+    let fd = socket(family, SockType::Datagram, SockFlag::empty(), None)?;
+
+    setsockopt(&fd, sockopt::ReusePort, &true)?;
+    bind(fd.as_raw_fd(), &SockaddrStorage::from(addr))?;
+
+    let socket = net::UdpSocket::from(fd);
+    socket.set_nonblocking(true)?;
+
+    Ok(mio::net::UdpSocket::from_std(socket))
+}
+
+/// A packet received by a shard on behalf of another.
+struct Handoff {
+    buf: Vec<u8>,
+
+    from: net::SocketAddr,
+}
+
+/// Hands off packets between the shards.
+pub struct Router {
+    shard: usize,
+
+    shards: Vec<(mpsc::Sender<Handoff>, Arc<mio::Waker>)>,
+
+    rx: mpsc::Receiver<Handoff>,
+}
+
+impl Router {
+    /// Creates the routers of shards whose event loops are registered with
+    /// `registries`. A shard's event loop is woken up with `token` when a
+    /// packet is handed off to it.
+    pub fn new(
+        registries: &[&mio::Registry], token: mio::Token,
+    ) -> io::Result<Vec<Router>> {
+        let mut shards = Vec::with_capacity(registries.len());
+        let mut receivers = Vec::with_capacity(registries.len());
+
+        for registry in registries {
+            let (tx, rx) = mpsc::channel();
+            let waker = Arc::new(mio::Waker::new(registry, token)?);
+
+            shards.push((tx, waker));
+            receivers.push(rx);
+        }
+
+        Ok(receivers
+            .into_iter()
+            .enumerate()
+            .map(|(shard, rx)| Router {
+                shard,
+                shards: shards.clone(),
+                rx,
+            })
+            .collect())
+    }
+
+    /// Returns the shard this router belongs to.
+    pub fn shard(&self) -> usize {
+        self.shard
+    }
+
+    /// Returns the number of shards.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Returns the shard that owns the connection with the destination
+    /// connection ID `dcid`.
+    pub fn owner(&self, dcid: &[u8]) -> usize {
+        shard_of(dcid, self.shards.len())
+    }
+
+    /// Hands off the packet in `buf`, received from `from`, to `shard`.
+    pub fn hand_off(
+        &self, shard: usize, buf: &[u8], from: net::SocketAddr,
+    ) -> io::Result<()> {
+        let (tx, waker) = &self.shards[shard];
+
+        let handoff = Handoff {
+            buf: buf.to_vec(),
+            from,
+        };
+
+        tx.send(handoff).map_err(|_| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "shard gone")
+        })?;
+
+        waker.wake()
+    }
+
+    /// Copies the next packet handed off to this shard into `buf`, returning
+    /// its length and where it was received from.
+    pub fn recv(&self, buf: &mut [u8]) -> Option<(usize, net::SocketAddr)> {
+        let handoff = self.rx.try_recv().ok()?;
+
+        let len = handoff.buf.len().min(buf.len());
+        buf[..len].copy_from_slice(&handoff.buf[..len]);
+
+        Some((len, handoff.from))
+    }
+}
+
+/// A hashed timer wheel tracking the timeout of each connection of a shard.
+///
+/// Each connection has at most one timer, which is rescheduled whenever its
+/// timeout changes. Timers that were rescheduled are left in their slot and
+/// skipped when it is visited, so rescheduling is constant time.
+pub struct TimerWheel {
+    tick: Duration,
+
+    start: Instant,
+
+    slots: Vec<Vec<(ClientId, Instant)>>,
+
+    deadlines: HashMap<ClientId, Instant>,
+
+    /// The first tick that wasn't expired yet.
+    current: u64,
+}
+
+impl TimerWheel {
+    /// Creates a wheel of `slots` slots of `tick` each.
+    pub fn new(tick: Duration, slots: usize, now: Instant) -> TimerWheel {
+        TimerWheel {
+            tick,
+            start: now,
+            slots: vec![Vec::new(); slots.max(1)],
+            deadlines: HashMap::new(),
+            current: 0,
+        }
+    }
+
+    fn tick_of(&self, t: Instant) -> u64 {
+        (t.saturating_duration_since(self.start).as_nanos() /
+            self.tick.as_nanos().max(1)) as u64
+    }
+
+    fn insert(&mut self, id: ClientId, deadline: Instant) {
+        let tick = self.tick_of(deadline).max(self.current);
+        let slot = (tick % self.slots.len() as u64) as usize;
+
+        self.slots[slot].push((id, deadline));
+    }
+
+    /// Sets the timer of the connection `id` to fire at `deadline`, or
+    /// cancels it if `None`.
+    pub fn schedule(&mut self, id: ClientId, deadline: Option<Instant>) {
+        let deadline = match deadline {
+            Some(v) => v,
+
+            None => {
+                self.deadlines.remove(&id);
+                return;
+            },
+        };
+
+        if self.deadlines.insert(id, deadline) != Some(deadline) {
+            self.insert(id, deadline);
+        }
+    }
+
+    /// Returns the connections whose timer fired by `now`, and removes their
+    /// timer.
+    pub fn expire(&mut self, now: Instant) -> Vec<ClientId> {
+        let target = self.tick_of(now);
+        let lap = self.slots.len() as u64;
+
+        let mut expired = Vec::new();
+        let mut pending = Vec::new();
+
+        // Past a full lap every slot has been visited.
+        let end = target.min(self.current + lap - 1);
+
+        for tick in self.current..=end {
+            let slot = (tick % lap) as usize;
+
+            for (id, deadline) in std::mem::take(&mut self.slots[slot]) {
+                // The timer was rescheduled or cancelled.
+                if self.deadlines.get(&id) != Some(&deadline) {
+                    continue;
+                }
+
+                if deadline <= now {
+                    self.deadlines.remove(&id);
+                    expired.push(id);
+                } else {
+                    pending.push((id, deadline));
+                }
+            }
+        }
+
+        self.current = self.current.max(target);
+
+        for (id, deadline) in pending {
+            self.insert(id, deadline);
+        }
+
+        expired
+    }
+
+    /// Returns how long until the next timer fires, if any.
+    ///
+    /// This is at most one lap of the wheel, after which the timers of later
+    /// laps are considered.
+    pub fn timeout(&self, now: Instant) -> Option<Duration> {
+        if self.deadlines.is_empty() {
+            return None;
+        }
+
+        let lap = self.slots.len() as u64;
+        let lap_end = self.current + lap;
+
+        // Slots are visited in the order of their ticks, so the first one
+        // with a timer of this lap has the next one.
+        for tick in self.current..lap_end {
+            let slot = &self.slots[(tick % lap) as usize];
+
+            let next = slot
+                .iter()
+                .filter(|(id, deadline)| {
+                    self.deadlines.get(id) == Some(deadline) &&
+                        self.tick_of(*deadline) < lap_end
+                })
+                .map(|(_, deadline)| *deadline)
+                .min();
+
+            if let Some(next) = next {
+                return Some(next.saturating_duration_since(now));
+            }
+        }
+
+        let nanos = self.tick.as_nanos() * lap_end as u128;
+        let lap_end = self.start + Duration::from_nanos(nanos as u64);
+
+        Some(lap_end.saturating_duration_since(now))
+    }
+
+    /// Returns the number of connections with a timer.
+    pub fn len(&self) -> usize {
+        self.deadlines.len()
+    }
+
+    /// Returns whether no connection has a timer.
+    pub fn is_empty(&self) -> bool {
+        self.deadlines.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shard_cids() {
+        let rng = ring::rand::SystemRandom::new();
+
+        for shard in [0, 1, 7] {
+            let (cid, _) = generate_cid_and_reset_token(&rng, shard);
+
+            assert_eq!(cid.len(), quiche::MAX_CONN_ID_LEN);
+            assert_eq!(shard_of(&cid, 8), shard);
+        }
+
+        assert_eq!(shard_of(&[9, 0, 0], 8), 1);
+        assert_eq!(shard_of(&[], 8), 0);
+    }
+
+    #[test]
+    fn router() {
+        let polls: Vec<mio::Poll> =
+            (0..2).map(|_| mio::Poll::new().unwrap()).collect();
+        let registries: Vec<&mio::Registry> =
+            polls.iter().map(|p| p.registry()).collect();
+
+        let routers = Router::new(&registries, mio::Token(1)).unwrap();
+        assert_eq!(routers.len(), 2);
+        assert_eq!(routers[1].shard(), 1);
+        assert_eq!(routers[1].shards(), 2);
+
+        let from = "127.0.0.1:4433".parse().unwrap();
+        routers[0].hand_off(1, b"packet", from).unwrap();
+
+        let mut buf = [0; 16];
+        assert_eq!(routers[0].recv(&mut buf), None);
+        assert_eq!(routers[1].recv(&mut buf), Some((6, from)));
+        assert_eq!(&buf[..6], b"packet");
+        assert_eq!(routers[1].recv(&mut buf), None);
+
+        // The owning shard was woken up.
+        let mut polls = polls;
+        let mut events = mio::Events::with_capacity(8);
+        polls[1]
+            .poll(&mut events, Some(Duration::from_secs(1)))
+            .unwrap();
+        assert!(events.iter().any(|e| e.token() == mio::Token(1)));
+    }
+
+    #[test]
+    fn timer_wheel() {
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+
+        let mut timers = TimerWheel::new(ms(1), 8, now);
+        assert_eq!(timers.timeout(now), None);
+
+        timers.schedule(1, Some(now + ms(5)));
+        timers.schedule(2, Some(now + ms(3)));
+        // Beyond a lap of the wheel.
+        timers.schedule(3, Some(now + ms(20)));
+        assert_eq!(timers.len(), 3);
+        assert_eq!(timers.timeout(now), Some(ms(3)));
+
+        // Rescheduling leaves a stale entry behind, which doesn't fire.
+        timers.schedule(2, Some(now + ms(6)));
+        assert_eq!(timers.timeout(now), Some(ms(5)));
+
+        assert!(timers.expire(now + ms(4)).is_empty());
+        assert_eq!(timers.expire(now + ms(5)), vec![1]);
+        assert_eq!(timers.expire(now + ms(7)), vec![2]);
+
+        // The timer of the next lap isn't used for the timeout until the
+        // wheel gets to its lap.
+        assert_eq!(timers.timeout(now + ms(7)), Some(ms(8)));
+        assert!(timers.expire(now + ms(15)).is_empty());
+        assert_eq!(timers.timeout(now + ms(15)), Some(ms(5)));
+        assert_eq!(timers.expire(now + ms(30)), vec![3]);
+        assert!(timers.is_empty());
+
+        // Cancelled timers don't fire.
+        timers.schedule(4, Some(now + ms(31)));
+        timers.schedule(4, None);
+        assert!(timers.expire(now + ms(40)).is_empty());
+        assert_eq!(timers.timeout(now + ms(40)), None);
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Interop tests against other QUIC implementations.
+//!
+//! The quiche-client and quiche-server apps are run against each other, and
+//! against other implementations, in containers laid out as expected by the
+//! [quic-interop-runner]: a network simulator between a client and a server
+//! container, each running the testcase given in `TESTCASE`. The quiche
+//! image is built with `make docker-qns`.
+//!
+//! Besides the transfer, the tests check the qlog traces written by the
+//! endpoints, e.g. that a resumed connection jumps in the `carefulresume`
+//! testcase. The tests need docker, and are only built with the
+//! `interop-tests` feature:
+//!
+//! ```text
+//! make docker-qns
+//! cargo test -p quiche_apps --features interop-tests --test interop
+//! ```
+//!
+//! Other implementations are given as a comma-separated list of `name=image`
+//! in `QUICHE_INTEROP_PEERS`, and the images used for quiche and the network
+//! simulator can be overridden with `QUICHE_INTEROP_IMAGE` and
+//! `QUICHE_INTEROP_SIM`.
+//!
+//! [quic-interop-runner]: https://github.com/quic-interop/quic-interop-runner
+
+#![cfg(feature = "interop-tests")]
+
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+
+use qlog::events::resume::CarefulResumePhase;
+use qlog::events::resume::CarefulResumePhaseUpdated;
+use qlog::events::EventData;
+use qlog::reader::Event;
+use qlog::reader::QlogSeqReader;
+
+const QUICHE_IMAGE: &str = "cloudflare/quiche-qns:latest";
+
+const SIM_IMAGE: &str = "martenseemann/quic-network-simulator:latest";
+
+/// The path emulated by the network simulator, with a bandwidth-delay
+/// product large enough for careful resume to make a difference.
+const SCENARIO: &str = "simple-p2p --delay=50ms --bandwidth=10Mbps --queue=100";
+
+/// The size of each file downloaded by the client, in bytes.
+const FILE_SIZE: usize = 5_000_000;
+
+/// The exit code of an endpoint that doesn't support a testcase.
+const UNSUPPORTED: i32 = 127;
+
+/// The containers have fixed names and addresses, so only one testcase can
+/// run at a time.
+static DOCKER: Mutex<()> = Mutex::new(());
+
+/// An endpoint image, and the testcase it runs.
+///
+/// Other implementations don't know about the `carefulresume` testcase, but
+/// the `resumption` one has their client reconnect in the same way.
+struct Endpoint<'a> {
+    image: &'a str,
+
+    testcase: &'a str,
+}
+
+/// A testcase run between a client and a server image.
+struct Run {
+    dir: PathBuf,
+}
+
+impl Run {
+    fn server_qlogs(&self) -> Vec<PathBuf> {
+        qlogs(&self.dir.join("logs/server/qlog"))
+    }
+}
+
+/// The outcome of a testcase run.
+enum Outcome {
+    Done(Run),
+
+    /// The client or the server doesn't support the testcase.
+    Unsupported,
+}
+
+/// Runs the testcases of the `client` and the `server`, and checks that the
+/// client downloaded all the files.
+fn run_testcase(
+    name: &str, client: Endpoint, server: Endpoint, files: usize,
+) -> Outcome {
+    let _guard = DOCKER.lock().unwrap_or_else(|e| e.into_inner());
+
+    let dir = std::env::temp_dir().join(format!("quiche-interop-{name}"));
+    std::fs::remove_dir_all(&dir).ok();
+
+    for d in ["www", "downloads", "certs", "logs/client", "logs/server"] {
+        std::fs::create_dir_all(dir.join(d)).unwrap();
+    }
+
+    let names: Vec<String> = (1..=files).map(|i| format!("file{i}")).collect();
+
+    for name in &names {
+        std::fs::write(dir.join("www").join(name), body()).unwrap();
+    }
+
+    let bin = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/bin");
+    std::fs::copy(bin.join("cert.crt"), dir.join("certs/cert.pem")).unwrap();
+    std::fs::copy(bin.join("cert.key"), dir.join("certs/priv.key")).unwrap();
+
+    let requests: Vec<String> = names
+        .iter()
+        .map(|name| format!("https://server4:443/{name}"))
+        .collect();
+
+    let compose = |args: &[&str]| {
+        let compose_file = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/interop/docker-compose.yml");
+
+        Command::new("docker")
+            .arg("compose")
+            .arg("-f")
+            .arg(compose_file)
+            .args(args)
+            .env("SIM", image("QUICHE_INTEROP_SIM", SIM_IMAGE))
+            .env("SCENARIO", SCENARIO)
+            .env("CLIENT", client.image)
+            .env("SERVER", server.image)
+            .env("CLIENT_TESTCASE", client.testcase)
+            .env("SERVER_TESTCASE", server.testcase)
+            .env("REQUESTS", requests.join(" "))
+            .env("CLIENT_PARAMS", "")
+            .env("SERVER_PARAMS", "")
+            .env("WWW", dir.join("www"))
+            .env("DOWNLOADS", dir.join("downloads"))
+            .env("CERTS", dir.join("certs"))
+            .env("CLIENT_LOGS", dir.join("logs/client"))
+            .env("SERVER_LOGS", dir.join("logs/server"))
+            .status()
+            .expect("failed to run docker compose")
+    };
+
+    let status = compose(&[
+        "up",
+        "--abort-on-container-exit",
+        "--exit-code-from",
+        "client",
+        "--timeout",
+        "1",
+    ]);
+
+    let server_status = Command::new("docker")
+        .args(["inspect", "server", "--format", "{{.State.ExitCode}}"])
+        .output()
+        .ok()
+        .and_then(|o| String::from_utf8(o.stdout).ok())
+        .and_then(|s| s.trim().parse::<i32>().ok());
+
+    compose(&["down"]);
+
+    if status.code() == Some(UNSUPPORTED) || server_status == Some(UNSUPPORTED) {
+        return Outcome::Unsupported;
+    }
+
+    assert!(status.success(), "{}: client failed: {}", name, status);
+
+    for name in &names {
+        let downloaded = std::fs::read(dir.join("downloads").join(name))
+            .unwrap_or_else(|e| panic!("{}: not downloaded: {}", name, e));
+
+        assert!(downloaded == body(), "{}: downloaded content differs", name);
+    }
+
+    Outcome::Done(Run { dir })
+}
+
+/// The content of each downloaded file.
+fn body() -> Vec<u8> {
+    (0..FILE_SIZE).map(|i| (i * 7 % 251) as u8).collect()
+}
+
+fn image(var: &str, default: &str) -> String {
+    std::env::var(var).unwrap_or_else(|_| default.to_string())
+}
+
+/// Returns the `(name, image)` of the other implementations to test against.
+fn peers() -> Vec<(String, String)> {
+    let peers = std::env::var("QUICHE_INTEROP_PEERS").unwrap_or_default();
+
+    peers
+        .split(',')
+        .filter(|p| !p.is_empty())
+        .map(|p| match p.split_once('=') {
+            Some((name, image)) => (name.to_string(), image.to_string()),
+
+            None => panic!("invalid QUICHE_INTEROP_PEERS entry: {}", p),
+        })
+        .collect()
+}
+
+/// Returns the qlog traces in `dir`, oldest first.
+fn qlogs(dir: &Path) -> Vec<PathBuf> {
+    let mut qlogs: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map(|entries| entries.flatten().map(|e| e.path()).collect())
+        .unwrap_or_default();
+
+    qlogs.retain(|p| p.extension().map_or(false, |e| e == "sqlog"));
+    qlogs.sort_by_key(|p| p.metadata().and_then(|m| m.modified()).ok());
+
+    qlogs
+}
+
+/// Returns the careful resume phase updates logged in the trace at `path`.
+fn cr_phase_updates(path: &Path) -> Vec<CarefulResumePhaseUpdated> {
+    let file = std::fs::File::open(path).unwrap();
+
+    let reader =
+        QlogSeqReader::new(Box::new(std::io::BufReader::new(file))).unwrap();
+
+    reader
+        .filter_map(|event| match event {
+            Event::Qlog(qlog::events::Event {
+                data: EventData::CarefulResumePhaseUpdated(v),
+                ..
+            }) => Some(v),
+
+            _ => None,
+        })
+        .collect()
+}
+
+/// Checks the traces of a quiche server in the `carefulresume` testcase: the
+/// first connection has nothing to resume from, and the second one jumps
+/// from the parameters stored by the first.
+fn check_server_resumed(run: &Run) {
+    let qlogs = run.server_qlogs();
+    assert_eq!(qlogs.len(), 2, "expected one trace per connection");
+
+    let jumped = |path: &Path| {
+        cr_phase_updates(path).iter().any(|u| {
+            u.new == CarefulResumePhase::Unvalidated && u.restored_data.is_some()
+        })
+    };
+
+    assert!(!jumped(&qlogs[0]), "first connection jumped");
+    assert!(jumped(&qlogs[1]), "resumed connection didn't jump");
+}
+
+#[test]
+fn carefulresume() {
+    let quiche = image("QUICHE_INTEROP_IMAGE", QUICHE_IMAGE);
+
+    let endpoint = || Endpoint {
+        image: &quiche,
+        testcase: "carefulresume",
+    };
+
+    match run_testcase("carefulresume", endpoint(), endpoint(), 2) {
+        Outcome::Done(run) => check_server_resumed(&run),
+
+        Outcome::Unsupported => panic!("quiche doesn't support carefulresume"),
+    }
+}
+
+#[test]
+fn carefulresume_peers() {
+    let quiche = image("QUICHE_INTEROP_IMAGE", QUICHE_IMAGE);
+
+    let quiche = || Endpoint {
+        image: &quiche,
+        testcase: "carefulresume",
+    };
+
+    for (peer, peer_image) in peers() {
+        let peer_endpoint = || Endpoint {
+            image: &peer_image,
+            testcase: "resumption",
+        };
+
+        // Careful resume is done by the server, as the sender of the files,
+        // so the traces are only checked when quiche is the server.
+        let name = format!("carefulresume-{peer}-quiche");
+
+        match run_testcase(&name, peer_endpoint(), quiche(), 2) {
+            Outcome::Done(run) => check_server_resumed(&run),
+
+            Outcome::Unsupported =>
+                eprintln!("{peer} client doesn't support resumption"),
+        }
+
+        let name = format!("carefulresume-quiche-{peer}");
+
+        if let Outcome::Unsupported =
+            run_testcase(&name, quiche(), peer_endpoint(), 2)
+        {
+            eprintln!("{peer} server doesn't support resumption");
+        }
+    }
+}
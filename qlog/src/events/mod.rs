@@ -277,6 +277,15 @@ impl From<EventType> for EventImportance {
             ) => EventImportance::Extra,
             EventType::RecoveryEventType(RecoveryEventType::CarefulResumePhaseUpdated) =>
                 EventImportance::Base,
+            EventType::RecoveryEventType(
+                RecoveryEventType::CarefulResumeParametersStored,
+            ) => EventImportance::Base,
+            EventType::RecoveryEventType(
+                RecoveryEventType::CarefulResumeParametersRestored,
+            ) => EventImportance::Base,
+            EventType::RecoveryEventType(
+                RecoveryEventType::PeerResumeSuspected,
+            ) => EventImportance::Base,
 
             EventType::Http3EventType(Http3EventType::ParametersSet) =>
                 EventImportance::Base,
@@ -463,6 +472,18 @@ impl From<&EventData> for EventType {
                 EventType::RecoveryEventType(
                     RecoveryEventType::CarefulResumePhaseUpdated,
                 ),
+            EventData::CarefulResumeParametersStored { .. } =>
+                EventType::RecoveryEventType(
+                    RecoveryEventType::CarefulResumeParametersStored,
+                ),
+            EventData::CarefulResumeParametersRestored { .. } =>
+                EventType::RecoveryEventType(
+                    RecoveryEventType::CarefulResumeParametersRestored,
+                ),
+            EventData::PeerResumeSuspected { .. } =>
+                EventType::RecoveryEventType(
+                    RecoveryEventType::PeerResumeSuspected,
+                ),
 
             EventData::H3ParametersSet { .. } =>
                 EventType::Http3EventType(Http3EventType::ParametersSet),
@@ -629,6 +650,15 @@ pub enum EventData {
     #[serde(rename = "recovery:careful_resume_phase_updated")]
     CarefulResumePhaseUpdated(resume::CarefulResumePhaseUpdated),
 
+    #[serde(rename = "recovery:careful_resume_parameters_stored")]
+    CarefulResumeParametersStored(resume::CarefulResumeParametersStored),
+
+    #[serde(rename = "recovery:careful_resume_parameters_restored")]
+    CarefulResumeParametersRestored(resume::CarefulResumeParametersRestored),
+
+    #[serde(rename = "recovery:peer_resume_suspected")]
+    PeerResumeSuspected(resume::PeerResumeSuspected),
+
     // HTTP/3
     #[serde(rename = "http:parameters_set")]
     H3ParametersSet(h3::H3ParametersSet),
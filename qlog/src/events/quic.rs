@@ -309,6 +309,9 @@ pub enum RecoveryEventType {
     PacketLost,
     MarkedForRetransmit,
     CarefulResumePhaseUpdated,
+    CarefulResumeParametersStored,
+    CarefulResumeParametersRestored,
+    PeerResumeSuspected,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
@@ -1,18 +1,64 @@
 use serde::Deserialize;
 use serde::Serialize;
 
+use crate::units::ByteCount;
+use crate::units::Milliseconds;
+
 #[serde_with::skip_serializing_none]
 #[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[non_exhaustive]
 pub struct CarefulResumePhaseUpdated {
     pub old: Option<CarefulResumePhase>,
     pub new: CarefulResumePhase,
     pub state_data: CarefulResumeStateParameters,
     pub restored_data: Option<CarefulResumeRestoredParameters>,
-    pub trigger: Option<CarefulResumeTrigger>
+    pub trigger: Option<CarefulResumeTrigger>,
+    pub phase_durations: Option<CarefulResumePhaseDurations>,
+}
+
+impl CarefulResumePhaseUpdated {
+    /// Creates a new CarefulResumePhaseUpdated, without restored parameters
+    /// or trigger.
+    pub fn new(
+        old: Option<CarefulResumePhase>, new: CarefulResumePhase,
+        state_data: CarefulResumeStateParameters,
+    ) -> Self {
+        CarefulResumePhaseUpdated {
+            old,
+            new,
+            state_data,
+            restored_data: None,
+            trigger: None,
+            phase_durations: None,
+        }
+    }
+
+    /// Sets the parameters of the previous connection the jump is based on.
+    pub fn with_restored_data(
+        mut self, restored_data: Option<CarefulResumeRestoredParameters>,
+    ) -> Self {
+        self.restored_data = restored_data;
+        self
+    }
+
+    /// Sets the event that caused the phase change.
+    pub fn with_trigger(mut self, trigger: Option<CarefulResumeTrigger>) -> Self {
+        self.trigger = trigger;
+        self
+    }
+
+    /// Sets the time spent in each phase so far.
+    pub fn with_phase_durations(
+        mut self, phase_durations: Option<CarefulResumePhaseDurations>,
+    ) -> Self {
+        self.phase_durations = phase_durations;
+        self
+    }
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
 #[serde(rename_all = "snake_case")]
+#[non_exhaustive]
 pub enum CarefulResumeTrigger {
     PacketLoss, // Loss triggers moving to safe retreat
     CwndLimited, // Trigger for moving to unvalidated
@@ -21,6 +67,14 @@ pub enum CarefulResumeTrigger {
     #[serde(rename = "ECN_CE")]
     EcnCe, // Trigger for moving to safe retreat.
     ExitRecovery, // Trigger for moving to normal 1rtt after a congestion event
+    AppLimited, // Reconnaissance timed out while the sender was app-limited
+    Timeout, // A phase timed out, e.g. reconnaissance with data to send
+    ExternalCongestion, // Congestion reported from outside the transport
+    PathChange, // The connection migrated to another path
+    TransferTooSmall, // The data left to send fits without jumping
+    PtoExpired, // A PTO fired while the jumped window wasn't validated
+    Abandoned, // The application abandoned careful resume
+    Reset, // The recovery state was reset, e.g. after a Retry
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
@@ -33,18 +87,735 @@ pub enum CarefulResumePhase {
     SafeRetreat,
 }
 
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum CarefulResumeValidatingPolicy {
+    Freeze, // cwnd held at the flightsize
+    PipesizeLimited, // ack-clocked growth, limited to the pipesize
+    SlowStart, // congestion controller growth from the flightsize
+}
+
 #[serde_with::skip_serializing_none]
 #[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
 pub struct CarefulResumeStateParameters {
-    pub pipesize: u64,
+    pub pipesize: ByteCount,
     pub cr_mark: u64,
-    pub congestion_window: Option<u64>,
-    pub ssthresh: Option<u64>,
+    pub congestion_window: Option<ByteCount>,
+    pub ssthresh: Option<ByteCount>,
+    pub iw_acked: Option<bool>,
+    pub rtt_samples: Option<u64>,
+    pub min_rtt_samples: Option<u64>,
+    pub validating_policy: Option<CarefulResumeValidatingPolicy>,
+}
+
+impl CarefulResumeStateParameters {
+    /// Creates a new CarefulResumeStateParameters, with all optional fields
+    /// unset.
+    pub fn new(pipesize: ByteCount, cr_mark: u64) -> Self {
+        CarefulResumeStateParameters {
+            pipesize,
+            cr_mark,
+            congestion_window: None,
+            ssthresh: None,
+            iw_acked: None,
+            rtt_samples: None,
+            min_rtt_samples: None,
+            validating_policy: None,
+        }
+    }
+
+    /// Sets the congestion window at the time of the phase change.
+    pub fn with_congestion_window(
+        mut self, congestion_window: ByteCount,
+    ) -> Self {
+        self.congestion_window = Some(congestion_window);
+        self
+    }
+
+    /// Sets the slow start threshold at the time of the phase change.
+    pub fn with_ssthresh(mut self, ssthresh: ByteCount) -> Self {
+        self.ssthresh = Some(ssthresh);
+        self
+    }
+
+    /// Sets whether the initial window was acknowledged.
+    pub fn with_iw_acked(mut self, iw_acked: bool) -> Self {
+        self.iw_acked = Some(iw_acked);
+        self
+    }
+
+    /// Sets the number of RTT samples taken during reconnaissance.
+    pub fn with_rtt_samples(mut self, rtt_samples: u64) -> Self {
+        self.rtt_samples = Some(rtt_samples);
+        self
+    }
+
+    /// Sets the number of RTT samples required before jumping.
+    pub fn with_min_rtt_samples(mut self, min_rtt_samples: u64) -> Self {
+        self.min_rtt_samples = Some(min_rtt_samples);
+        self
+    }
+
+    /// Sets how the congestion window grows in the validating phase.
+    pub fn with_validating_policy(
+        mut self, validating_policy: CarefulResumeValidatingPolicy,
+    ) -> Self {
+        self.validating_policy = Some(validating_policy);
+        self
+    }
+}
+
+/// The time spent in each phase.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+pub struct CarefulResumePhaseDurations {
+    pub reconnaissance: Milliseconds,
+    pub unvalidated: Milliseconds,
+    pub validating: Milliseconds,
+    pub safe_retreat: Milliseconds,
 }
 
 #[serde_with::skip_serializing_none]
 #[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[non_exhaustive]
 pub struct CarefulResumeRestoredParameters {
-    pub previous_congestion_window: u64,
-    pub previous_rtt: f32,
-}
\ No newline at end of file
+    pub previous_congestion_window: ByteCount,
+    pub previous_rtt: Milliseconds,
+    pub previous_pacing_rate: Option<u64>,
+    pub jump_factor: Option<f32>,
+    pub jump_fraction: Option<f32>,
+    pub jump: Option<ByteCount>,
+    pub flow_control_limited: Option<bool>,
+    pub plausibility_capped: Option<bool>,
+}
+
+impl CarefulResumeRestoredParameters {
+    /// Creates a new CarefulResumeRestoredParameters, with all optional
+    /// fields unset.
+    pub fn new(
+        previous_congestion_window: ByteCount, previous_rtt: Milliseconds,
+    ) -> Self {
+        CarefulResumeRestoredParameters {
+            previous_congestion_window,
+            previous_rtt,
+            previous_pacing_rate: None,
+            jump_factor: None,
+            jump_fraction: None,
+            jump: None,
+            flow_control_limited: None,
+            plausibility_capped: None,
+        }
+    }
+
+    /// Sets the pacing rate of the previous connection, in bytes per second.
+    pub fn with_previous_pacing_rate(
+        mut self, previous_pacing_rate: u64,
+    ) -> Self {
+        self.previous_pacing_rate = Some(previous_pacing_rate);
+        self
+    }
+
+    /// Sets the configured fraction of `previous_congestion_window` that the
+    /// jump aims for.
+    pub fn with_jump_factor(mut self, jump_factor: f32) -> Self {
+        self.jump_factor = Some(jump_factor);
+        self
+    }
+
+    /// Sets the congestion window reached by the jump, as a fraction of
+    /// `previous_congestion_window`.
+    pub fn with_jump_fraction(mut self, jump_fraction: f32) -> Self {
+        self.jump_fraction = Some(jump_fraction);
+        self
+    }
+
+    /// Sets the increase of the congestion window made by the jump.
+    pub fn with_jump(mut self, jump: ByteCount) -> Self {
+        self.jump = Some(jump);
+        self
+    }
+
+    /// Sets whether the jump was reduced to what the flow control limits of
+    /// the peer allow to send.
+    pub fn with_flow_control_limited(
+        mut self, flow_control_limited: bool,
+    ) -> Self {
+        self.flow_control_limited = Some(flow_control_limited);
+        self
+    }
+
+    /// Sets whether the jump was capped at a multiple of the capacity
+    /// measured during reconnaissance.
+    pub fn with_plausibility_capped(
+        mut self, plausibility_capped: bool,
+    ) -> Self {
+        self.plausibility_capped = Some(plausibility_capped);
+        self
+    }
+}
+
+/// Careful Resume parameters observed on the path, to be stored for a later
+/// connection.
+#[serde_with::skip_serializing_none]
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[non_exhaustive]
+pub struct CarefulResumeParametersStored {
+    pub congestion_window: ByteCount,
+    pub min_rtt: Milliseconds,
+    pub pacing_rate: Option<u64>,
+    pub post_congestion: Option<bool>,
+}
+
+impl CarefulResumeParametersStored {
+    /// Creates a new CarefulResumeParametersStored, with all optional fields
+    /// unset.
+    pub fn new(congestion_window: ByteCount, min_rtt: Milliseconds) -> Self {
+        CarefulResumeParametersStored {
+            congestion_window,
+            min_rtt,
+            pacing_rate: None,
+            post_congestion: None,
+        }
+    }
+
+    /// Sets the observed pacing rate, in bytes per second.
+    pub fn with_pacing_rate(mut self, pacing_rate: u64) -> Self {
+        self.pacing_rate = Some(pacing_rate);
+        self
+    }
+
+    /// Sets whether the parameters were measured when leaving the safe
+    /// retreat phase, rather than before congestion.
+    pub fn with_post_congestion(mut self, post_congestion: bool) -> Self {
+        self.post_congestion = Some(post_congestion);
+        self
+    }
+}
+
+/// Careful Resume parameters of a previous connection, set up on this
+/// connection.
+#[serde_with::skip_serializing_none]
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[non_exhaustive]
+pub struct CarefulResumeParametersRestored {
+    pub previous_congestion_window: ByteCount,
+    pub previous_rtt: Milliseconds,
+    pub previous_pacing_rate: Option<u64>,
+}
+
+impl CarefulResumeParametersRestored {
+    /// Creates a new CarefulResumeParametersRestored, with all optional
+    /// fields unset.
+    pub fn new(
+        previous_congestion_window: ByteCount, previous_rtt: Milliseconds,
+    ) -> Self {
+        CarefulResumeParametersRestored {
+            previous_congestion_window,
+            previous_rtt,
+            previous_pacing_rate: None,
+        }
+    }
+
+    /// Sets the pacing rate of the previous connection, in bytes per second.
+    pub fn with_previous_pacing_rate(
+        mut self, previous_pacing_rate: u64,
+    ) -> Self {
+        self.previous_pacing_rate = Some(previous_pacing_rate);
+        self
+    }
+}
+
+/// A Careful Resume jump by the peer, suspected by the receiver from a sudden
+/// increase of the data received over a round trip.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[non_exhaustive]
+pub struct PeerResumeSuspected {
+    pub estimated_jump: ByteCount,
+    pub previous_round_bytes: ByteCount,
+    pub round: u64,
+    pub rtt: Milliseconds,
+}
+
+impl PeerResumeSuspected {
+    /// Creates a new PeerResumeSuspected. `estimated_jump` and
+    /// `previous_round_bytes` are the data received in the round trips after
+    /// and before the jump.
+    pub fn new(
+        estimated_jump: ByteCount, previous_round_bytes: ByteCount, round: u64,
+        rtt: Milliseconds,
+    ) -> Self {
+        PeerResumeSuspected {
+            estimated_jump,
+            previous_round_bytes,
+            round,
+            rtt,
+        }
+    }
+}
+
+/// Rounds all the durations of a Careful Resume event to whole microseconds,
+/// the precision they are measured with.
+///
+/// This is meant for events read from traces written by older versions of
+/// this crate, which logged durations as single precision floats, so that
+/// e.g. a 25ms phase read as 24.999998ms. Only the values are changed: the
+/// event is not otherwise checked or converted. Other events are left
+/// unchanged.
+pub fn round_durations_to_micros(ev: &mut crate::events::EventData) {
+    use crate::events::EventData;
+
+    match ev {
+        EventData::CarefulResumePhaseUpdated(ev) => {
+            if let Some(r) = &mut ev.restored_data {
+                r.previous_rtt = r.previous_rtt.round_to_micros();
+            }
+
+            if let Some(d) = &mut ev.phase_durations {
+                d.reconnaissance = d.reconnaissance.round_to_micros();
+                d.unvalidated = d.unvalidated.round_to_micros();
+                d.validating = d.validating.round_to_micros();
+                d.safe_retreat = d.safe_retreat.round_to_micros();
+            }
+        },
+
+        EventData::CarefulResumeParametersStored(ev) =>
+            ev.min_rtt = ev.min_rtt.round_to_micros(),
+
+        EventData::CarefulResumeParametersRestored(ev) =>
+            ev.previous_rtt = ev.previous_rtt.round_to_micros(),
+
+        EventData::PeerResumeSuspected(ev) => ev.rtt = ev.rtt.round_to_micros(),
+
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::Value;
+
+    use crate::events::EventData;
+
+    #[derive(Clone, Copy)]
+    enum Kind {
+        Integer,
+        Number,
+        String,
+        Bool,
+        Object,
+    }
+
+    /// Checks that `data` is an object with exactly the `required` fields,
+    /// plus any of the `optional` ones, each with the given JSON type.
+    ///
+    /// The fields are the ones this crate serializes: they are not checked
+    /// against a published qlog schema.
+    fn check_fields(
+        data: &Value, required: &[(&str, Kind)], optional: &[(&str, Kind)],
+    ) {
+        let obj = data.as_object().expect("not an object");
+
+        for (name, _) in required {
+            assert!(obj.contains_key(*name), "missing field {}", name);
+        }
+
+        for (name, value) in obj {
+            let (_, kind) = required
+                .iter()
+                .chain(optional.iter())
+                .find(|(n, _)| n == name)
+                .unwrap_or_else(|| panic!("unexpected field {}", name));
+
+            let ok = match kind {
+                Kind::Integer => value.is_u64(),
+                Kind::Number => value.is_number(),
+                Kind::String => value.is_string(),
+                Kind::Bool => value.is_boolean(),
+                Kind::Object => value.is_object(),
+            };
+
+            assert!(ok, "field {} has the wrong type: {}", name, value);
+        }
+    }
+
+    fn check_state_fields(data: &Value) {
+        check_fields(
+            data,
+            &[("pipesize", Kind::Integer), ("cr_mark", Kind::Integer)],
+            &[
+                ("congestion_window", Kind::Integer),
+                ("ssthresh", Kind::Integer),
+                ("validating_policy", Kind::String),
+            ],
+        );
+    }
+
+    fn check_restored_fields(data: &Value) {
+        check_fields(
+            data,
+            &[
+                ("previous_congestion_window", Kind::Integer),
+                ("previous_rtt", Kind::Number),
+            ],
+            &[
+                ("previous_pacing_rate", Kind::Integer),
+                ("jump_factor", Kind::Number),
+                ("jump_fraction", Kind::Number),
+                ("jump", Kind::Integer),
+                ("flow_control_limited", Kind::Bool),
+                ("plausibility_capped", Kind::Bool),
+            ],
+        );
+    }
+
+    fn check_durations_fields(data: &Value) {
+        check_fields(
+            data,
+            &[
+                ("reconnaissance", Kind::Number),
+                ("unvalidated", Kind::Number),
+                ("validating", Kind::Number),
+                ("safe_retreat", Kind::Number),
+            ],
+            &[],
+        );
+    }
+
+    /// Checks the field names and JSON types of the serialization of a
+    /// Careful Resume event against the ones expected for its event type.
+    /// of its event type.
+    fn check_event_fields(ev: &EventData) {
+        let json = serde_json::to_value(ev).unwrap();
+        let data = &json["data"];
+
+        match ev {
+            EventData::CarefulResumePhaseUpdated(_) => {
+                check_fields(
+                    data,
+                    &[("new", Kind::String), ("state_data", Kind::Object)],
+                    &[
+                        ("old", Kind::String),
+                        ("restored_data", Kind::Object),
+                        ("trigger", Kind::String),
+                        ("phase_durations", Kind::Object),
+                    ],
+                );
+
+                check_state_fields(&data["state_data"]);
+
+                if let Some(restored) = data.get("restored_data") {
+                    check_restored_fields(restored);
+                }
+
+                if let Some(durations) = data.get("phase_durations") {
+                    check_durations_fields(durations);
+                }
+            },
+
+            EventData::CarefulResumeParametersStored(_) => check_fields(
+                data,
+                &[
+                    ("congestion_window", Kind::Integer),
+                    ("min_rtt", Kind::Number),
+                ],
+                &[
+                    ("pacing_rate", Kind::Integer),
+                    ("post_congestion", Kind::Bool),
+                ],
+            ),
+
+            EventData::CarefulResumeParametersRestored(_) => check_fields(
+                data,
+                &[
+                    ("previous_congestion_window", Kind::Integer),
+                    ("previous_rtt", Kind::Number),
+                ],
+                &[("previous_pacing_rate", Kind::Integer)],
+            ),
+
+            EventData::PeerResumeSuspected(_) => check_fields(
+                data,
+                &[
+                    ("estimated_jump", Kind::Integer),
+                    ("previous_round_bytes", Kind::Integer),
+                    ("round", Kind::Integer),
+                    ("rtt", Kind::Number),
+                ],
+                &[],
+            ),
+
+            _ => panic!("not a Careful Resume event"),
+        }
+
+        // Round trip.
+        assert_eq!(
+            serde_json::from_value::<EventData>(json).unwrap(),
+            ev.clone()
+        );
+    }
+
+    #[test]
+    fn trigger_serialization() {
+        let triggers = [
+            (CarefulResumeTrigger::PacketLoss, "\"packet_loss\""),
+            (CarefulResumeTrigger::CwndLimited, "\"cwnd_limited\""),
+            (
+                CarefulResumeTrigger::CrMarkAcknowledged,
+                "\"cr_mark_acknowledged\"",
+            ),
+            (
+                CarefulResumeTrigger::RttNotValidated,
+                "\"rtt_not_validated\"",
+            ),
+            (CarefulResumeTrigger::EcnCe, "\"ECN_CE\""),
+            (CarefulResumeTrigger::ExitRecovery, "\"exit_recovery\""),
+            (CarefulResumeTrigger::AppLimited, "\"app_limited\""),
+            (CarefulResumeTrigger::Timeout, "\"timeout\""),
+            (
+                CarefulResumeTrigger::TransferTooSmall,
+                "\"transfer_too_small\"",
+            ),
+            (CarefulResumeTrigger::Abandoned, "\"abandoned\""),
+            (CarefulResumeTrigger::Reset, "\"reset\""),
+        ];
+
+        for (trigger, json) in triggers {
+            assert_eq!(serde_json::to_string(&trigger).unwrap(), json);
+            assert_eq!(
+                serde_json::from_str::<CarefulResumeTrigger>(json).unwrap(),
+                trigger
+            );
+        }
+    }
+
+    #[test]
+    fn phase_updated_builder() {
+        let ev = CarefulResumePhaseUpdated::new(
+            Some(CarefulResumePhase::Reconnaissance),
+            CarefulResumePhase::Unvalidated,
+            CarefulResumeStateParameters::new(ByteCount(14_000), 12)
+                .with_congestion_window(ByteCount(40_000))
+                .with_validating_policy(CarefulResumeValidatingPolicy::SlowStart),
+        )
+        .with_restored_data(Some(
+            CarefulResumeRestoredParameters::new(
+                ByteCount(80_000),
+                Milliseconds(50.0),
+            )
+            .with_previous_pacing_rate(1_000_000)
+            .with_jump_factor(0.5)
+            .with_jump_fraction(0.5)
+            .with_jump(ByteCount(28_000))
+            .with_flow_control_limited(true)
+            .with_plausibility_capped(true),
+        ))
+        .with_trigger(Some(CarefulResumeTrigger::CwndLimited));
+
+        let log_string = r#"{
+  "old": "reconnaissance",
+  "new": "unvalidated",
+  "state_data": {
+    "pipesize": 14000,
+    "cr_mark": 12,
+    "congestion_window": 40000,
+    "validating_policy": "slow_start"
+  },
+  "restored_data": {
+    "previous_congestion_window": 80000,
+    "previous_rtt": 50.0,
+    "previous_pacing_rate": 1000000,
+    "jump_factor": 0.5,
+    "jump_fraction": 0.5,
+    "jump": 28000,
+    "flow_control_limited": true,
+    "plausibility_capped": true
+  },
+  "trigger": "cwnd_limited"
+}"#;
+
+        assert_eq!(serde_json::to_string_pretty(&ev).unwrap(), log_string);
+        assert_eq!(
+            serde_json::from_str::<CarefulResumePhaseUpdated>(log_string)
+                .unwrap(),
+            ev
+        );
+
+        // Unset optional fields are omitted.
+        let ev = CarefulResumePhaseUpdated::new(
+            None,
+            CarefulResumePhase::Reconnaissance,
+            CarefulResumeStateParameters::new(ByteCount(0), 0),
+        );
+
+        assert_eq!(
+            serde_json::to_string(&ev).unwrap(),
+            r#"{"new":"reconnaissance","state_data":{"pipesize":0,"cr_mark":0}}"#
+        );
+    }
+
+    #[test]
+    fn parameters_stored_restored() {
+        let ev = crate::events::EventData::CarefulResumeParametersStored(
+            CarefulResumeParametersStored::new(
+                ByteCount(80_000),
+                Milliseconds(50.0),
+            )
+            .with_pacing_rate(1_000_000)
+            .with_post_congestion(false),
+        );
+
+        let log_string = r#"{"name":"recovery:careful_resume_parameters_stored","data":{"congestion_window":80000,"min_rtt":50.0,"pacing_rate":1000000,"post_congestion":false}}"#;
+
+        assert_eq!(serde_json::to_string(&ev).unwrap(), log_string);
+        assert_eq!(
+            serde_json::from_str::<crate::events::EventData>(log_string).unwrap(),
+            ev
+        );
+
+        let ev = crate::events::EventData::CarefulResumeParametersRestored(
+            CarefulResumeParametersRestored::new(
+                ByteCount(80_000),
+                Milliseconds(50.0),
+            ),
+        );
+
+        let log_string = r#"{"name":"recovery:careful_resume_parameters_restored","data":{"previous_congestion_window":80000,"previous_rtt":50.0}}"#;
+
+        assert_eq!(serde_json::to_string(&ev).unwrap(), log_string);
+        assert_eq!(
+            serde_json::from_str::<crate::events::EventData>(log_string).unwrap(),
+            ev
+        );
+    }
+
+    #[test]
+    fn peer_resume_suspected() {
+        let ev = crate::events::EventData::PeerResumeSuspected(
+            PeerResumeSuspected::new(
+                ByteCount(72_000),
+                ByteCount(12_000),
+                1,
+                Milliseconds(50.0),
+            ),
+        );
+
+        let log_string = r#"{"name":"recovery:peer_resume_suspected","data":{"estimated_jump":72000,"previous_round_bytes":12000,"round":1,"rtt":50.0}}"#;
+
+        assert_eq!(serde_json::to_string(&ev).unwrap(), log_string);
+        assert_eq!(
+            serde_json::from_str::<crate::events::EventData>(log_string).unwrap(),
+            ev
+        );
+        assert_eq!(
+            crate::events::EventType::from(&ev),
+            crate::events::EventType::RecoveryEventType(
+                crate::events::quic::RecoveryEventType::PeerResumeSuspected
+            )
+        );
+    }
+
+    #[test]
+    fn event_fields() {
+        let events = [
+            EventData::CarefulResumePhaseUpdated(CarefulResumePhaseUpdated::new(
+                None,
+                CarefulResumePhase::Reconnaissance,
+                CarefulResumeStateParameters::new(ByteCount(0), 0),
+            )),
+            EventData::CarefulResumePhaseUpdated(
+                CarefulResumePhaseUpdated::new(
+                    Some(CarefulResumePhase::Validating),
+                    CarefulResumePhase::Normal,
+                    CarefulResumeStateParameters::new(ByteCount(80_000), 40)
+                        .with_congestion_window(ByteCount(80_000))
+                        .with_ssthresh(ByteCount(60_000))
+                        .with_validating_policy(
+                            CarefulResumeValidatingPolicy::SlowStart,
+                        ),
+                )
+                .with_restored_data(Some(
+                    CarefulResumeRestoredParameters::new(
+                        ByteCount(80_000),
+                        Milliseconds(0.05),
+                    )
+                    .with_previous_pacing_rate(1_000_000)
+                    .with_jump_factor(0.5)
+                    .with_jump_fraction(0.5)
+                    .with_jump(ByteCount(28_000))
+                    .with_flow_control_limited(false),
+                ))
+                .with_trigger(Some(CarefulResumeTrigger::CrMarkAcknowledged))
+                .with_phase_durations(Some(
+                    CarefulResumePhaseDurations {
+                        reconnaissance: Milliseconds(100.0),
+                        unvalidated: Milliseconds(50.0),
+                        validating: Milliseconds(0.5),
+                        safe_retreat: Milliseconds(0.0),
+                    },
+                )),
+            ),
+            EventData::CarefulResumeParametersStored(
+                CarefulResumeParametersStored::new(
+                    ByteCount(80_000),
+                    Milliseconds(50.0),
+                )
+                .with_pacing_rate(1_000_000)
+                .with_post_congestion(true),
+            ),
+            EventData::CarefulResumeParametersRestored(
+                CarefulResumeParametersRestored::new(
+                    ByteCount(80_000),
+                    Milliseconds(50.0),
+                )
+                .with_previous_pacing_rate(1_000_000),
+            ),
+            EventData::PeerResumeSuspected(PeerResumeSuspected::new(
+                ByteCount(72_000),
+                ByteCount(12_000),
+                1,
+                Milliseconds(50.0),
+            )),
+        ];
+
+        for ev in &events {
+            check_event_fields(ev);
+        }
+    }
+
+    #[test]
+    fn round_legacy_durations() {
+        // Written by a version that logged durations as f32.
+        let log_string = r#"{"name":"recovery:careful_resume_phase_updated","data":{"old":"unvalidated","new":"validating","state_data":{"pipesize":40000,"cr_mark":30},"restored_data":{"previous_congestion_window":80000,"previous_rtt":24.999998},"trigger":"cr_mark_acknowledged","phase_durations":{"reconnaissance":100.00001,"unvalidated":24.999998,"validating":0.0,"safe_retreat":0.0}}}"#;
+
+        let mut ev: EventData = serde_json::from_str(log_string).unwrap();
+        check_event_fields(&ev);
+
+        round_durations_to_micros(&mut ev);
+
+        let ev = match ev {
+            EventData::CarefulResumePhaseUpdated(ev) => ev,
+            _ => panic!("unexpected event"),
+        };
+
+        assert_eq!(ev.restored_data.unwrap().previous_rtt, Milliseconds(25.0));
+
+        let d = ev.phase_durations.unwrap();
+        assert_eq!(d.reconnaissance, Milliseconds(100.0));
+        assert_eq!(d.unvalidated, Milliseconds(25.0));
+
+        let mut ev: EventData = serde_json::from_str(r#"{"name":"recovery:careful_resume_parameters_stored","data":{"congestion_window":80000,"min_rtt":49.999996}}"#).unwrap();
+        round_durations_to_micros(&mut ev);
+
+        assert_eq!(
+            ev,
+            EventData::CarefulResumeParametersStored(
+                CarefulResumeParametersStored::new(
+                    ByteCount(80_000),
+                    Milliseconds(50.0),
+                )
+            )
+        );
+    }
+}
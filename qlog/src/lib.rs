@@ -985,3 +985,4 @@ mod tests {
 pub mod events;
 pub mod reader;
 pub mod streamer;
+pub mod units;
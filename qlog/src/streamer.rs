@@ -136,6 +136,14 @@ impl QlogStreamer {
         Ok(())
     }
 
+    /// Flushes the events serialized so far to the writer, without finishing
+    /// the log.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.as_mut().flush()?;
+
+        Ok(())
+    }
+
     /// Writes a serializable to a JSON-SEQ record using
     /// [std::time::Instant::now()].
     pub fn add_event_now<E: Serialize + Eventable>(
@@ -0,0 +1,128 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Typed units of event fields.
+//!
+//! qlog represents durations as a number of milliseconds, and amounts of data
+//! as a number of bytes. Keeping the unit in the type of a field means that
+//! e.g. a duration in seconds can't be logged where milliseconds are
+//! expected. Both types serialize as plain JSON numbers, so traces are the
+//! same as with bare numbers.
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// A duration, in milliseconds.
+#[derive(
+    Serialize, Deserialize, Copy, Clone, Default, PartialEq, PartialOrd, Debug,
+)]
+#[serde(transparent)]
+pub struct Milliseconds(pub f64);
+
+impl Milliseconds {
+    /// Rounds the duration to whole microseconds.
+    ///
+    /// This removes the rounding errors of durations that went through lower
+    /// precision floating point numbers, e.g. 24.999998ms becomes 25ms.
+    pub fn round_to_micros(self) -> Self {
+        Milliseconds((self.0 * 1000.0).round() / 1000.0)
+    }
+
+    /// Returns the duration as a [`Duration`], or `None` if it is negative
+    /// or not finite.
+    ///
+    /// [`Duration`]: https://doc.rust-lang.org/std/time/struct.Duration.html
+    pub fn to_duration(self) -> Option<Duration> {
+        Duration::try_from_secs_f64(self.0 / 1000.0).ok()
+    }
+}
+
+impl From<Duration> for Milliseconds {
+    /// Converts through whole microseconds, which keeps sub-millisecond
+    /// durations exact, e.g. 50µs is 0.05ms.
+    fn from(d: Duration) -> Self {
+        Milliseconds(d.as_micros() as f64 / 1000.0)
+    }
+}
+
+/// An amount of data, in bytes.
+#[derive(
+    Serialize,
+    Deserialize,
+    Copy,
+    Clone,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Debug,
+)]
+#[serde(transparent)]
+pub struct ByteCount(pub u64);
+
+impl From<u64> for ByteCount {
+    fn from(v: u64) -> Self {
+        ByteCount(v)
+    }
+}
+
+impl From<usize> for ByteCount {
+    fn from(v: usize) -> Self {
+        ByteCount(v as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn milliseconds() {
+        let ms = Milliseconds::from(Duration::from_micros(50));
+        assert_eq!(ms, Milliseconds(0.05));
+        assert_eq!(serde_json::to_string(&ms).unwrap(), "0.05");
+        assert_eq!(ms.to_duration(), Some(Duration::from_micros(50)));
+
+        let ms: Milliseconds = serde_json::from_str("24.999998").unwrap();
+        assert_eq!(ms.round_to_micros(), Milliseconds(25.0));
+
+        assert_eq!(Milliseconds(-1.0).to_duration(), None);
+        assert_eq!(Milliseconds(f64::NAN).to_duration(), None);
+    }
+
+    #[test]
+    fn byte_count() {
+        assert_eq!(ByteCount::from(80_000_usize), ByteCount(80_000));
+        assert_eq!(serde_json::to_string(&ByteCount(80_000)).unwrap(), "80000");
+        assert_eq!(
+            serde_json::from_str::<ByteCount>("80000").unwrap(),
+            ByteCount(80_000)
+        );
+    }
+}
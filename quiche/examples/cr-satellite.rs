@@ -0,0 +1,450 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Careful Resume over a geostationary satellite path.
+//!
+//! A client downloads the same object twice from a server, over a relay that
+//! emulates a GEO satellite hop: a 600ms round-trip time and a fixed
+//! bottleneck bandwidth. The server keeps the parameters observed on the
+//! first connection in a [`quiche::FileCrStore`], and the second connection
+//! resumes from them, jumping to the previously observed congestion window
+//! instead of slow starting across the satellite.
+//!
+//! In a deployment the relay is the CONNECT-UDP (MASQUE) proxy on the ground
+//! station, which forwards the client's datagrams to the server. quiche
+//! doesn't provide such a proxy, so here the relay only forwards datagrams
+//! in process, with the delay and bandwidth of the satellite hop. The
+//! careful resume behaviour seen by the endpoints is the same.
+//!
+//! For each connection a qlog trace of both endpoints is written to the
+//! output directory, along with the store of parameters:
+//!
+//! ```text
+//! cargo run --example cr-satellite --features qlog -- [OUT_DIR]
+//! ```
+
+#[macro_use]
+extern crate log;
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use ring::rand::*;
+
+/// The round-trip time of the satellite hop.
+const RTT: Duration = Duration::from_millis(600);
+
+/// The bottleneck bandwidth of the satellite hop, in bits per second.
+const BANDWIDTH: u64 = 20_000_000;
+
+/// The size of the downloaded object, in bytes.
+const OBJECT_SIZE: usize = 5_000_000;
+
+/// How long the server keeps the parameters of a path.
+const STORE_MAX_AGE: Duration = Duration::from_secs(3600);
+
+/// A download that takes longer than this is considered stuck.
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+/// One direction of the satellite hop.
+///
+/// Datagrams are serialized at the bottleneck bandwidth, after waiting in a
+/// drop-tail buffer holding up to one bandwidth-delay product, and are
+/// delivered after the one-way delay.
+struct Hop {
+    /// When the bottleneck finishes transmitting the last queued datagram.
+    free_at: Instant,
+
+    /// Datagrams in flight, with the time they arrive at the other end.
+    in_flight: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl Hop {
+    fn new(now: Instant) -> Hop {
+        Hop {
+            free_at: now,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    fn bdp() -> usize {
+        (BANDWIDTH as u128 * RTT.as_micros() / 8_000_000) as usize
+    }
+
+    /// Queues a datagram handed to the hop at `at`.
+    fn push(&mut self, at: Instant, dgram: &[u8]) {
+        let start = self.free_at.max(at);
+
+        let queued = start.saturating_duration_since(at);
+        let queued_bytes = queued.as_micros() * BANDWIDTH as u128 / 8_000_000;
+
+        if queued_bytes as usize + dgram.len() > Hop::bdp() {
+            debug!("satellite hop dropped {} bytes", dgram.len());
+            return;
+        }
+
+        let tx_time = Duration::from_micros(
+            (dgram.len() as u128 * 8_000_000 / BANDWIDTH as u128) as u64,
+        );
+
+        self.free_at = start + tx_time;
+
+        self.in_flight
+            .push_back((self.free_at + RTT / 2, dgram.to_vec()));
+    }
+
+    /// Returns the next datagram that arrived by `now`.
+    fn pop(&mut self, now: Instant) -> Option<Vec<u8>> {
+        match self.in_flight.front() {
+            Some((arrival, _)) if *arrival <= now =>
+                self.in_flight.pop_front().map(|(_, dgram)| dgram),
+
+            _ => None,
+        }
+    }
+
+    fn next_arrival(&self) -> Option<Instant> {
+        self.in_flight.front().map(|(arrival, _)| *arrival)
+    }
+}
+
+fn main() {
+    let mut args = std::env::args();
+
+    let cmd = &args.next().unwrap();
+
+    let out_dir = match (args.next(), args.next()) {
+        (None, None) => PathBuf::from("."),
+
+        (Some(dir), None) => PathBuf::from(dir),
+
+        _ => {
+            println!("Usage: {cmd} [OUT_DIR]");
+            println!("\nSee tools/apps/ for more complete implementations.");
+            return;
+        },
+    };
+
+    let store_path = out_dir.join("cr-satellite.store");
+
+    // Start from an empty store, so the first download slow starts.
+    std::fs::remove_file(&store_path).ok();
+
+    let store =
+        Arc::new(quiche::FileCrStore::open(&store_path, STORE_MAX_AGE).unwrap());
+
+    let mut client_config =
+        quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+    configure(&mut client_config);
+
+    // The client doesn't verify the example certificate.
+    client_config.verify_peer(false);
+
+    let mut server_config =
+        quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+    configure(&mut server_config);
+
+    server_config
+        .load_cert_chain_from_pem_file("examples/cert.crt")
+        .unwrap();
+    server_config
+        .load_priv_key_from_pem_file("examples/cert.key")
+        .unwrap();
+
+    // The server observes the path on each connection, stores what it saw,
+    // and resumes from it on the next connection from the same network.
+    server_config.enable_resume(true);
+    server_config.set_cr_store(store.clone());
+
+    for name in ["first", "resumed"] {
+        let d = download(&mut client_config, &mut server_config, &out_dir, name);
+
+        println!("{} download: {:?}", name, d.duration);
+        println!("  server path: {:?}", d.path);
+
+        let counters = &d.path.cr_counters;
+
+        // The first connection has nothing to resume from, the second one
+        // jumps once to the parameters stored by the first.
+        match name {
+            "first" => assert_eq!(counters.jumps_attempted, 0),
+
+            _ => {
+                assert_eq!(counters.rtt_mismatches, 0);
+                assert_eq!(counters.jumps_attempted, 1);
+            },
+        }
+    }
+
+    store.flush().unwrap();
+
+    println!(
+        "careful resume parameters stored in {}",
+        store_path.display()
+    );
+}
+
+/// Applies the settings shared by the client and the server.
+fn configure(config: &mut quiche::Config) {
+    config.set_application_protos(&[b"cr-satellite"]).unwrap();
+
+    config.set_max_idle_timeout(30_000);
+    config.set_max_recv_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_max_send_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_initial_max_data(2 * OBJECT_SIZE as u64);
+    config.set_initial_max_stream_data_bidi_local(2 * OBJECT_SIZE as u64);
+    config.set_initial_max_stream_data_bidi_remote(2 * OBJECT_SIZE as u64);
+    config.set_initial_max_streams_bidi(1);
+    config.set_disable_active_migration(true);
+}
+
+struct Download {
+    duration: Duration,
+
+    path: quiche::PathStats,
+}
+
+/// Downloads the object over a new connection, writing the qlog traces of
+/// both endpoints to `out_dir`.
+fn download(
+    client_config: &mut quiche::Config, server_config: &mut quiche::Config,
+    out_dir: &Path, name: &str,
+) -> Download {
+    let client_addr: SocketAddr = "198.51.100.7:50000".parse().unwrap();
+    let server_addr: SocketAddr = "192.0.2.1:4433".parse().unwrap();
+
+    let rng = SystemRandom::new();
+
+    let mut scid = [0; quiche::MAX_CONN_ID_LEN];
+    rng.fill(&mut scid[..]).unwrap();
+    let scid = quiche::ConnectionId::from_ref(&scid);
+
+    let mut client = quiche::connect(
+        Some("satellite.example"),
+        &scid,
+        client_addr,
+        server_addr,
+        client_config,
+    )
+    .unwrap();
+
+    set_qlog(&mut client, out_dir, name, "client");
+
+    let mut server: Option<quiche::Connection> = None;
+
+    let start = Instant::now();
+
+    let mut uplink = Hop::new(start);
+    let mut downlink = Hop::new(start);
+
+    let mut buf = [0; 65535];
+    let body = vec![0; OBJECT_SIZE];
+
+    let mut request_sent = false;
+    let mut response_written = None;
+    let mut received = 0;
+
+    loop {
+        let now = Instant::now();
+
+        if now - start > DOWNLOAD_TIMEOUT {
+            panic!(
+                "{} download did not complete in {:?}",
+                name, DOWNLOAD_TIMEOUT
+            );
+        }
+
+        // Datagrams from the client create the server connection, as a
+        // listening server would.
+        while let Some(mut dgram) = uplink.pop(now) {
+            let conn = server.get_or_insert_with(|| {
+                let hdr = quiche::Header::from_slice(
+                    &mut dgram,
+                    quiche::MAX_CONN_ID_LEN,
+                )
+                .unwrap();
+
+                let mut conn = quiche::accept(
+                    &hdr.dcid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    server_config,
+                )
+                .unwrap();
+
+                set_qlog(&mut conn, out_dir, name, "server");
+
+                conn
+            });
+
+            let info = quiche::RecvInfo {
+                from: client_addr,
+                to: server_addr,
+            };
+
+            match conn.recv(&mut dgram, info) {
+                Ok(_) | Err(quiche::Error::Done) => (),
+
+                Err(e) => error!("server recv failed: {:?}", e),
+            }
+        }
+
+        while let Some(mut dgram) = downlink.pop(now) {
+            let info = quiche::RecvInfo {
+                from: server_addr,
+                to: client_addr,
+            };
+
+            match client.recv(&mut dgram, info) {
+                Ok(_) | Err(quiche::Error::Done) => (),
+
+                Err(e) => error!("client recv failed: {:?}", e),
+            }
+        }
+
+        if client.is_established() && !request_sent {
+            client.stream_send(0, b"GET /", true).unwrap();
+            request_sent = true;
+        }
+
+        if let Some(conn) = server.as_mut() {
+            if response_written.is_none() && conn.readable().any(|s| s == 0) {
+                while conn.stream_recv(0, &mut buf).is_ok() {}
+                response_written = Some(0);
+            }
+
+            if let Some(written) = response_written.as_mut() {
+                if *written < OBJECT_SIZE {
+                    match conn.stream_send(0, &body[*written..], true) {
+                        Ok(v) => *written += v,
+
+                        Err(quiche::Error::Done) => (),
+
+                        Err(e) => panic!("stream_send failed: {:?}", e),
+                    }
+                }
+            }
+        }
+
+        while let Ok((len, fin)) = client.stream_recv(0, &mut buf) {
+            received += len;
+
+            if fin {
+                assert_eq!(received, OBJECT_SIZE);
+
+                let server = server.as_mut().unwrap();
+
+                let download = Download {
+                    duration: now - start,
+                    path: server.path_stats().next().unwrap(),
+                };
+
+                // Flush the traces of both endpoints.
+                client.close(true, 0x00, b"done").ok();
+                server.close(true, 0x00, b"done").ok();
+
+                return download;
+            }
+        }
+
+        flush(&mut client, &mut uplink, &mut buf, now);
+
+        if let Some(conn) = server.as_mut() {
+            flush(conn, &mut downlink, &mut buf, now);
+        }
+
+        // Sleep until a datagram arrives or a connection timer fires.
+        let next = [
+            uplink.next_arrival(),
+            downlink.next_arrival(),
+            client.timeout().map(|t| now + t),
+            server.as_ref().and_then(|c| c.timeout()).map(|t| now + t),
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .copied()
+        .unwrap_or(now + Duration::from_millis(1));
+
+        std::thread::sleep(next.saturating_duration_since(Instant::now()));
+
+        if client.timeout().map_or(false, |t| t.is_zero()) {
+            client.on_timeout();
+        }
+
+        if let Some(conn) = server.as_mut() {
+            if conn.timeout().map_or(false, |t| t.is_zero()) {
+                conn.on_timeout();
+            }
+        }
+
+        if client.is_closed() {
+            panic!("{} download failed: {:?}", name, client.peer_error());
+        }
+    }
+}
+
+/// Hands the datagrams sent by `conn` to the satellite hop.
+fn flush(
+    conn: &mut quiche::Connection, hop: &mut Hop, buf: &mut [u8], now: Instant,
+) {
+    loop {
+        let (len, info) = match conn.send(buf) {
+            Ok(v) => v,
+
+            Err(quiche::Error::Done) => break,
+
+            Err(e) => {
+                error!("send failed: {:?}", e);
+                break;
+            },
+        };
+
+        hop.push(info.at.max(now), &buf[..len]);
+    }
+}
+
+fn set_qlog(
+    conn: &mut quiche::Connection, out_dir: &Path, name: &str, role: &str,
+) {
+    let path = out_dir.join(format!("cr-satellite-{name}-{role}.sqlog"));
+
+    let file = std::fs::File::create(&path).unwrap();
+
+    conn.set_qlog(
+        Box::new(std::io::BufWriter::new(file)),
+        format!("cr-satellite {name} {role}"),
+        format!("{role} of the {name} download over a GEO satellite path"),
+    );
+}
@@ -173,6 +173,9 @@ void quiche_config_log_keys(quiche_config *config);
 // Enables sending or receiving early data.
 void quiche_config_enable_early_data(quiche_config *config);
 
+// Configures whether 1-RTT packets are only protected with ChaCha20-Poly1305.
+int quiche_config_set_chacha20_only(quiche_config *config, bool v);
+
 // Configures the list of supported application protocols.
 int quiche_config_set_application_protos(quiche_config *config,
                                          const uint8_t *protos,
@@ -457,6 +460,15 @@ void quiche_conn_on_timeout(quiche_conn *conn);
 int quiche_conn_close(quiche_conn *conn, bool app, uint64_t err,
                       const uint8_t *reason, size_t reason_len);
 
+// Closes the connection like quiche_conn_close(), after recording the last
+// Careful Resume parameters observed and flushing the qlog.
+int quiche_conn_close_and_flush(quiche_conn *conn, bool app, uint64_t err,
+                                const uint8_t *reason, size_t reason_len);
+
+// Abandons Careful Resume on all paths, e.g. when the application learns
+// about a path change first.
+void quiche_conn_abandon_careful_resume(quiche_conn *conn);
+
 // Returns a string uniquely representing the connection.
 void quiche_conn_trace_id(const quiche_conn *conn, const uint8_t **out, size_t *out_len);
 
@@ -847,6 +859,101 @@ void quiche_socket_addr_iter_free(quiche_socket_addr_iter *iter);
 // If the 4-tuple does not exist over the connection, returns an InvalidState.
 int quiche_conn_is_path_validated(const quiche_conn *conn, const struct sockaddr *from, size_t from_len, const struct sockaddr *to, size_t to_len);
 
+// Extensions
+//
+// Features specific to this fork are reached through versioned tables of
+// functions, so that embedders can adopt them incrementally. A table is never
+// changed once released: new features are added in a new version, and older
+// versions keep being returned.
+
+#define QUICHE_CONN_EXT_V1 1
+
+// Careful Resume parameters observed on a connection.
+typedef struct {
+    // The minimum RTT (in nanoseconds).
+    uint64_t min_rtt;
+
+    // The congestion window in bytes.
+    size_t cwnd;
+
+    // The pacing rate in bytes/s, or 0 if unknown.
+    uint64_t pacing_rate;
+
+    // Whether the parameters were observed after a congestion event.
+    bool post_congestion;
+
+    // The maximum datagram size the congestion window was measured with, in
+    // bytes, or 0 if unknown.
+    size_t max_datagram_size;
+
+    // The fraction of the bytes sent that were lost, in parts per million.
+    uint32_t loss_rate_ppm;
+} quiche_cr_params;
+
+// Storage for Careful Resume parameters, shared between connections.
+typedef struct {
+    // Fills |out| and returns true if parameters are stored for |key|. The
+    // key isn't NUL-terminated.
+    bool (*get)(const uint8_t *key, size_t key_len, quiche_cr_params *out,
+                void *argp);
+
+    // Stores |params| for |key|, replacing any previous parameters.
+    void (*put)(const uint8_t *key, size_t key_len,
+                const quiche_cr_params *params, void *argp);
+
+    // Passed to the callbacks, which may be called from any thread.
+    void *argp;
+} quiche_cr_store_callbacks;
+
+// How packets are paced on the active path.
+typedef struct {
+    // The pacing rate in bytes/s, or 0 if packets aren't paced.
+    uint64_t rate;
+
+    // The maximum number of bytes to send in a burst.
+    size_t send_quantum;
+} quiche_pacing_plan;
+
+// Careful Resume state of the active path.
+typedef struct {
+    // Whether Careful Resume was set up.
+    bool enabled;
+
+    // Whether the jumped congestion window is being validated.
+    bool in_jump;
+
+    // Whether |params| holds the latest parameters observed.
+    bool has_params;
+
+    quiche_cr_params params;
+
+    // The number of Careful Resume phase changes.
+    uint64_t phase_updated_count;
+} quiche_cr_stats;
+
+typedef struct {
+    // QUICHE_CONN_EXT_V1.
+    uint32_t version;
+
+    // Sets how often recovery metrics are written to qlog. Has no effect
+    // unless qlog support is built.
+    void (*config_set_qlog_metrics_sampling)(quiche_config *config, size_t v);
+
+    // Sets the store for Careful Resume parameters. The callbacks are copied.
+    void (*config_set_cr_store)(quiche_config *config,
+                                const quiche_cr_store_callbacks *callbacks);
+
+    // Collects the pacing plan of the active path.
+    void (*conn_pacing_plan)(const quiche_conn *conn, quiche_pacing_plan *out);
+
+    // Collects the Careful Resume state of the active path.
+    void (*conn_cr_stats)(const quiche_conn *conn, quiche_cr_stats *out);
+} quiche_conn_ext_v1;
+
+// Returns the extension table for |version|, e.g. a `quiche_conn_ext_v1`
+// for QUICHE_CONN_EXT_V1, or NULL if the version isn't supported.
+const void *quiche_conn_ext(uint32_t version);
+
 // Frees the connection object.
 void quiche_conn_free(quiche_conn *conn);
 
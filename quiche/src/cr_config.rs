@@ -0,0 +1,950 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Careful Resume configuration.
+//!
+//! Careful Resume interacts with the rest of congestion control: the jump is
+//! only safe when paced, and it relies on the congestion window being managed
+//! by Reno, CUBIC, BBR or BBRv2. [`CrConfig`] groups the Careful Resume
+//! settings together with the congestion control settings they depend on, so
+//! that incompatible combinations are rejected by [`Config::set_cr_config()`]
+//! instead of silently misbehaving once connections are running.
+//!
+//! [`Config::set_cr_config()`]: crate::Config::set_cr_config
+
+use std::cmp;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::recovery::congestion::CongestionControlAlgorithm;
+use crate::recovery::CrObserveThresholds;
+use crate::recovery::CrThresholds;
+use crate::resume_blob;
+use crate::CrHystartPolicy;
+use crate::CrJumpLimiter;
+use crate::CrJumpPacing;
+use crate::CrObservePolicy;
+use crate::CrRetreatPolicy;
+use crate::CrValidatingPolicy;
+use crate::Error;
+
+/// Careful Resume settings, along with the congestion control settings they
+/// interact with.
+///
+/// The settings are applied with [`Config::set_cr_config()`], which validates
+/// them. The individual `Config` setters, e.g. [`Config::enable_resume()`],
+/// change the same settings without validation.
+///
+/// ## Examples:
+///
+/// ```
+/// # let mut config = quiche::Config::new(0xbabababa)?;
+/// let mut cr = quiche::CrConfig::default();
+/// cr.set_min_rtts_before_jump(3);
+/// cr.set_validating_policy(quiche::CrValidatingPolicy::PipesizeLimited);
+///
+/// config.set_cr_config(cr)?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+///
+/// [`Config::set_cr_config()`]: crate::Config::set_cr_config
+/// [`Config::enable_resume()`]: crate::Config::enable_resume
+#[derive(Clone, Debug)]
+pub struct CrConfig {
+    pub(crate) resume: bool,
+
+    pub(crate) hystart: bool,
+
+    pub(crate) pacing: bool,
+
+    pub(crate) max_pacing_rate: Option<u64>,
+
+    pub(crate) allow_unpaced_jump: bool,
+
+    pub(crate) retreat_export: bool,
+
+    pub(crate) min_rtts_before_jump: usize,
+
+    pub(crate) validating_policy: CrValidatingPolicy,
+
+    pub(crate) jump_pacing: CrJumpPacing,
+
+    pub(crate) hystart_policy: CrHystartPolicy,
+
+    pub(crate) retreat_policy: CrRetreatPolicy,
+
+    pub(crate) reconnaissance_timeout: Duration,
+
+    pub(crate) idle_threshold: Duration,
+
+    pub(crate) unvalidated_timeout: Duration,
+
+    pub(crate) unvalidated_timeout_rtts: u32,
+
+    pub(crate) validating_timeout: Duration,
+
+    pub(crate) max_unvalidated_burst: usize,
+
+    pub(crate) thresholds: CrThresholds,
+
+    pub(crate) observe_thresholds: CrObserveThresholds,
+
+    pub(crate) jump_limiter: Option<CrJumpLimiter>,
+
+    pub(crate) early_data: bool,
+
+    pub(crate) peer_params_max_age: Duration,
+
+    pub(crate) peer_params_max_cwnd: usize,
+}
+
+impl Default for CrConfig {
+    fn default() -> Self {
+        CrConfig {
+            resume: true,
+            hystart: true,
+            pacing: true,
+            max_pacing_rate: None,
+            allow_unpaced_jump: false,
+            retreat_export: false,
+            min_rtts_before_jump: 1,
+            validating_policy: CrValidatingPolicy::SlowStart,
+            jump_pacing: CrJumpPacing::Paced,
+            hystart_policy: CrHystartPolicy::Resume,
+            retreat_policy: CrRetreatPolicy::Pipesize,
+            reconnaissance_timeout: Duration::ZERO,
+            idle_threshold: Duration::ZERO,
+            unvalidated_timeout: Duration::ZERO,
+            unvalidated_timeout_rtts: 0,
+            validating_timeout: Duration::ZERO,
+            max_unvalidated_burst: 0,
+            thresholds: CrThresholds::default(),
+            observe_thresholds: CrObserveThresholds::default(),
+            jump_limiter: None,
+            early_data: false,
+            peer_params_max_age: resume_blob::DEFAULT_MAX_AGE,
+            peer_params_max_cwnd: usize::MAX,
+        }
+    }
+}
+
+impl CrConfig {
+    /// Configures whether to enable Careful Resume.
+    ///
+    /// The default value is `true`.
+    pub fn enable_resume(&mut self, v: bool) {
+        self.resume = v;
+    }
+
+    /// Configures whether to enable HyStart++.
+    ///
+    /// The default value is `true`.
+    pub fn enable_hystart(&mut self, v: bool) {
+        self.hystart = v;
+    }
+
+    /// Configures whether to enable pacing.
+    ///
+    /// Without pacing the Careful Resume jump is sent in a single burst, so
+    /// disabling it while Careful Resume is enabled is rejected unless
+    /// [`allow_unpaced_jump()`] is also set.
+    ///
+    /// The default value is `true`.
+    ///
+    /// [`allow_unpaced_jump()`]: CrConfig::allow_unpaced_jump
+    pub fn enable_pacing(&mut self, v: bool) {
+        self.pacing = v;
+    }
+
+    /// Sets the max value for pacing rate, in bytes per second.
+    ///
+    /// By default pacing rate is not limited.
+    pub fn set_max_pacing_rate(&mut self, v: u64) {
+        self.max_pacing_rate = Some(v);
+    }
+
+    /// Configures whether Careful Resume may jump with pacing disabled, e.g.
+    /// because packets are paced by the kernel or the NIC instead.
+    ///
+    /// The default value is `false`.
+    pub fn allow_unpaced_jump(&mut self, v: bool) {
+        self.allow_unpaced_jump = v;
+    }
+
+    /// Configures whether the pipesize reached when leaving the safe retreat
+    /// phase is reported as a post-congestion [`CREvent`].
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`CREvent`]: crate::CREvent
+    pub fn enable_retreat_export(&mut self, v: bool) {
+        self.retreat_export = v;
+    }
+
+    /// Sets when the parameters observed on a path are reported as
+    /// [`CREvent`]s.
+    ///
+    /// The default value is `CrObservePolicy::SignificantChange`.
+    ///
+    /// [`CREvent`]: crate::CREvent
+    pub fn set_observe_policy(&mut self, v: CrObservePolicy) {
+        self.observe_thresholds.policy = v;
+    }
+
+    /// Sets the longest time between two reports of the observed parameters.
+    /// With the periodic policy, this is the time between reports.
+    ///
+    /// The default value is 60 seconds.
+    pub fn set_observe_max_gap(&mut self, v: Duration) {
+        self.observe_thresholds.max_gap = v;
+    }
+
+    /// Sets the smallest congestion window, as a multiple of the initial
+    /// congestion window, that is reported as an observed parameter.
+    ///
+    /// The default value is `4`.
+    pub fn set_observe_min_cwnd(&mut self, iw_multiple: usize) {
+        self.observe_thresholds.min_cwnd_iw = iw_multiple;
+    }
+
+    /// Sets the relative changes of the min RTT and of the congestion window
+    /// that are significant one second after the previous report, with the
+    /// significant change policy. The change needed is inversely
+    /// proportional to the time since the previous report, e.g. half of
+    /// these after two seconds. Both must be above 0.
+    ///
+    /// The default values are `1.0` and `1.0`.
+    pub fn set_observe_change_thresholds(&mut self, rtt: f64, cwnd: f64) {
+        self.observe_thresholds.rtt_change = rtt;
+        self.observe_thresholds.cwnd_change = cwnd;
+    }
+
+    /// Sets the minimum number of RTT samples collected during the
+    /// reconnaissance phase before jumping. A value of 0 is treated as 1.
+    ///
+    /// The default value is `1`.
+    pub fn set_min_rtts_before_jump(&mut self, v: usize) {
+        self.min_rtts_before_jump = cmp::max(v, 1);
+    }
+
+    /// Sets how the congestion window may grow during the validating phase.
+    ///
+    /// The default value is `CrValidatingPolicy::SlowStart`.
+    pub fn set_validating_policy(&mut self, policy: CrValidatingPolicy) {
+        self.validating_policy = policy;
+    }
+
+    /// Sets how the congestion window added by the jump is released during
+    /// the unvalidated phase. It is only paced when pacing is enabled.
+    ///
+    /// The default value is `CrJumpPacing::Paced`.
+    pub fn set_jump_pacing(&mut self, v: CrJumpPacing) {
+        self.jump_pacing = v;
+    }
+
+    /// Sets how HyStart++ carries on once the jump completes. HyStart++ is
+    /// suspended while the jumped window is unvalidated or validating.
+    ///
+    /// The default value is `CrHystartPolicy::Resume`.
+    pub fn set_hystart_policy(&mut self, v: CrHystartPolicy) {
+        self.hystart_policy = v;
+    }
+
+    /// Sets the slow start threshold set on exiting the safe retreat phase.
+    ///
+    /// The default value is `CrRetreatPolicy::Pipesize`.
+    pub fn set_retreat_policy(&mut self, v: CrRetreatPolicy) {
+        self.retreat_policy = v;
+    }
+
+    /// Sets how long the reconnaissance phase may last before Careful Resume
+    /// is abandoned. A value of 0 lets reconnaissance last until the jump
+    /// conditions are met.
+    ///
+    /// The default value is 0.
+    pub fn set_reconnaissance_timeout(&mut self, v: Duration) {
+        self.reconnaissance_timeout = v;
+    }
+
+    /// Sets how long the sender may have nothing in flight during the
+    /// reconnaissance phase before its RTT sample is considered stale, and
+    /// the jump waits for a new one. A value of 0 uses 8 times the RTT of
+    /// the previous connection.
+    ///
+    /// The default value is 0.
+    pub fn set_idle_threshold(&mut self, v: Duration) {
+        self.idle_threshold = v;
+    }
+
+    /// Sets how close the RTT must be to the RTT of the previous connection
+    /// for the jump to be made: above `min_ratio` and below `max_ratio`
+    /// times the previous RTT. The bounds must be on either side of 1.
+    ///
+    /// The default values are `0.5` and `10.0`.
+    pub fn set_rtt_bounds(&mut self, min_ratio: f64, max_ratio: f64) {
+        self.thresholds.rtt_ratios = (min_ratio, max_ratio);
+    }
+
+    /// Sets the fraction of the previous congestion window that the jump
+    /// brings the congestion window to. It must be between `0.25` and `1.0`:
+    /// stable paths can jump closer to the previous congestion window, while
+    /// cautious deployments can jump less far.
+    ///
+    /// The default value is `0.5`.
+    pub fn set_jump_factor(&mut self, v: f64) {
+        self.thresholds.jump_factor = v;
+    }
+
+    /// Sets the jump factor to the inverse of `v`, see
+    /// [`set_jump_factor()`]. A value of 0 is treated as 1.
+    ///
+    /// The default value is `2`.
+    ///
+    /// [`set_jump_factor()`]: CrConfig::set_jump_factor
+    pub fn set_jump_divisor(&mut self, v: usize) {
+        self.thresholds.jump_factor = 1.0 / cmp::max(v, 1) as f64;
+    }
+
+    /// Sets the smallest previous congestion window, as a multiple of the
+    /// initial congestion window, that a jump is attempted from.
+    ///
+    /// The default value is `0`.
+    pub fn set_min_previous_cwnd(&mut self, iw_multiple: usize) {
+        self.thresholds.min_previous_cwnd_iw = iw_multiple;
+    }
+
+    /// Caps the jump at `v` times the capacity estimated during
+    /// reconnaissance, i.e. the delivery rate of the initial window times the
+    /// previous RTT. A value of 0 disables the cap.
+    ///
+    /// This guards against stale parameters on a path that became much
+    /// slower but has a similar RTT, which the RTT check can't tell apart.
+    /// The cap is reported in the `plausibility_capped` field of the qlog
+    /// phase update of the jump.
+    ///
+    /// The default value is 0.
+    pub fn set_plausibility_factor(&mut self, v: f64) {
+        self.thresholds.plausibility_factor = v;
+    }
+
+    /// Sets how long the unvalidated phase may last before Careful Resume
+    /// retreats, as if the jump had caused congestion. A value of 0 lets the
+    /// phase last until the jumped window is acknowledged or lost.
+    ///
+    /// The default value is 0.
+    pub fn set_unvalidated_timeout(&mut self, v: Duration) {
+        self.unvalidated_timeout = v;
+    }
+
+    /// Sets how long the unvalidated phase may last, as a multiple of the
+    /// RTT measured when jumping, before Careful Resume retreats. When
+    /// [`set_unvalidated_timeout()`] is also set, the earliest of the two
+    /// applies. A value of 0 doesn't bound the phase in RTTs.
+    ///
+    /// The default value is 0.
+    ///
+    /// [`set_unvalidated_timeout()`]: CrConfig::set_unvalidated_timeout
+    pub fn set_unvalidated_timeout_rtts(&mut self, v: u32) {
+        self.unvalidated_timeout_rtts = v;
+    }
+
+    /// Sets how long the validating phase may last before Careful Resume
+    /// completes with the congestion window reached so far. A value of 0
+    /// lets the phase last until the validated window is acknowledged or
+    /// lost.
+    ///
+    /// The default value is 0.
+    pub fn set_validating_timeout(&mut self, v: Duration) {
+        self.validating_timeout = v;
+    }
+
+    /// Sets the largest number of packets sent in a burst during the
+    /// unvalidated phase. A value of 0 doesn't limit bursts.
+    ///
+    /// The default value is 0.
+    pub fn set_max_unvalidated_burst(&mut self, packets: usize) {
+        self.max_unvalidated_burst = packets;
+    }
+
+    /// Limits how many connections may be in the unvalidated phase at the
+    /// same time through each local interface.
+    ///
+    /// The default is not to limit jumps.
+    pub fn set_jump_limiter(&mut self, limiter: CrJumpLimiter) {
+        self.jump_limiter = Some(limiter);
+    }
+
+    /// Configures whether a client sending 0-RTT data may jump as soon as
+    /// the handshake completes.
+    ///
+    /// See [`Config::enable_cr_early_data()`].
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`Config::enable_cr_early_data()`]: crate::Config::enable_cr_early_data
+    pub fn enable_early_data(&mut self, v: bool) {
+        self.early_data = v;
+    }
+
+    /// Sets the maximum age of Careful Resume parameters provided by the
+    /// peer that are accepted.
+    ///
+    /// The default value is 24 hours.
+    pub fn set_peer_params_max_age(&mut self, v: Duration) {
+        self.peer_params_max_age = v;
+    }
+
+    /// Applies the settings of a [`CrProfile`], i.e. the RTT bounds, the jump
+    /// factor and the unvalidated phase timeouts suited to a kind of path.
+    ///
+    /// Settings changed afterwards override those of the profile.
+    pub fn set_profile(&mut self, profile: CrProfile) {
+        let default = CrConfig::default();
+
+        let (rtt_ratios, jump_factor, unvalidated_timeout_rtts) = match profile {
+            CrProfile::Default => (
+                default.thresholds.rtt_ratios,
+                default.thresholds.jump_factor,
+                default.unvalidated_timeout_rtts,
+            ),
+
+            CrProfile::Geo => ((0.75, 1.5), 1.0, 3),
+
+            CrProfile::Leo => ((0.25, 4.0), 0.5, 4),
+
+            CrProfile::LongFat => ((0.5, 2.0), 0.75, 3),
+        };
+
+        self.thresholds.rtt_ratios = rtt_ratios;
+        self.thresholds.jump_factor = jump_factor;
+        self.unvalidated_timeout = default.unvalidated_timeout;
+        self.unvalidated_timeout_rtts = unvalidated_timeout_rtts;
+    }
+
+    /// Sets the largest congestion window, in bytes, in Careful Resume
+    /// parameters provided by the peer that are accepted.
+    ///
+    /// The default value is `usize::MAX`, i.e. no cap.
+    pub fn set_peer_params_max_cwnd(&mut self, v: usize) {
+        self.peer_params_max_cwnd = v;
+    }
+
+    /// Returns whether Careful Resume is enabled.
+    pub fn resume_enabled(&self) -> bool {
+        self.resume
+    }
+
+    /// Returns whether pacing is enabled.
+    pub fn pacing_enabled(&self) -> bool {
+        self.pacing
+    }
+
+    /// Returns the fraction of the previous congestion window that the jump
+    /// brings the congestion window to.
+    pub fn jump_factor(&self) -> f64 {
+        self.thresholds.jump_factor
+    }
+
+    /// Checks that the settings can be used together with the
+    /// `cc_algorithm` congestion control algorithm.
+    pub fn validate(
+        &self, cc_algorithm: CongestionControlAlgorithm,
+    ) -> Result<(), CrConfigError> {
+        if self.max_pacing_rate == Some(0) {
+            return Err(CrConfigError::ZeroMaxPacingRate);
+        }
+
+        let (min_ratio, max_ratio) = self.thresholds.rtt_ratios;
+
+        // Also rejects NaN.
+        if !((0.0..1.0).contains(&min_ratio) && max_ratio > 1.0) {
+            return Err(CrConfigError::InvalidRttBounds);
+        }
+
+        if !(0.25..=1.0).contains(&self.thresholds.jump_factor) {
+            return Err(CrConfigError::InvalidJumpFactor);
+        }
+
+        // Also rejects NaN and infinity.
+        if !(0.0..=f64::MAX).contains(&self.thresholds.plausibility_factor) {
+            return Err(CrConfigError::InvalidPlausibilityFactor);
+        }
+
+        let observe = &self.observe_thresholds;
+
+        // Also rejects NaN.
+        if observe.max_gap.is_zero() ||
+            !(observe.rtt_change > 0.0 && observe.cwnd_change > 0.0)
+        {
+            return Err(CrConfigError::InvalidObserveThresholds);
+        }
+
+        if !self.resume {
+            let default = CrConfig::default();
+
+            // Settings that only affect Careful Resume would be ignored.
+            if self.retreat_export ||
+                self.allow_unpaced_jump ||
+                self.jump_limiter.is_some() ||
+                self.early_data ||
+                self.min_rtts_before_jump != default.min_rtts_before_jump ||
+                self.validating_policy != default.validating_policy ||
+                self.jump_pacing != default.jump_pacing ||
+                self.hystart_policy != default.hystart_policy ||
+                self.retreat_policy != default.retreat_policy ||
+                self.reconnaissance_timeout != default.reconnaissance_timeout ||
+                self.idle_threshold != default.idle_threshold ||
+                self.unvalidated_timeout != default.unvalidated_timeout ||
+                self.unvalidated_timeout_rtts !=
+                    default.unvalidated_timeout_rtts ||
+                self.validating_timeout != default.validating_timeout ||
+                self.max_unvalidated_burst != default.max_unvalidated_burst ||
+                self.thresholds != default.thresholds
+            {
+                return Err(CrConfigError::ResumeDisabled);
+            }
+
+            return Ok(());
+        }
+
+        match cc_algorithm {
+            CongestionControlAlgorithm::Reno |
+            CongestionControlAlgorithm::CUBIC |
+            CongestionControlAlgorithm::BBR |
+            CongestionControlAlgorithm::BBR2 => (),
+        }
+
+        if !self.pacing {
+            if !self.allow_unpaced_jump {
+                return Err(CrConfigError::UnpacedJump);
+            }
+
+            warn!("careful resume is enabled without pacing, the jump will be sent in a burst");
+        }
+
+        Ok(())
+    }
+}
+
+/// Preset Careful Resume settings for a kind of path, applied with
+/// [`CrConfig::set_profile()`].
+///
+/// The profiles select how far the RTT may diverge from the previous RTT,
+/// how far the jump goes and how long the jumped window may stay
+/// unvalidated.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CrProfile {
+    /// The default settings, suited to most terrestrial paths. `default` in
+    /// a string form.
+    #[default]
+    Default,
+
+    /// Geostationary satellite paths. The RTT is long, around 600ms, but
+    /// stable, so the RTT must stay close to the previous RTT and the jump
+    /// goes up to the previous congestion window, saving several slow start
+    /// rounds. `geo` in a string form.
+    Geo,
+
+    /// Low earth orbit satellite paths. Handovers between satellites change
+    /// the RTT and the capacity every few seconds, so the RTT may be well
+    /// below the previous RTT, the jump is cautious and the jumped window
+    /// has longer to be acknowledged. `leo` in a string form.
+    Leo,
+
+    /// Terrestrial paths with a long RTT and a high capacity, e.g. between
+    /// continents. `long_fat` in a string form.
+    LongFat,
+}
+
+impl FromStr for CrProfile {
+    type Err = Error;
+
+    /// Converts a string to `CrProfile`.
+    ///
+    /// If `name` is not valid, `Error::CongestionControl` is returned.
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name {
+            "default" => Ok(CrProfile::Default),
+            "geo" => Ok(CrProfile::Geo),
+            "leo" => Ok(CrProfile::Leo),
+            "long_fat" => Ok(CrProfile::LongFat),
+
+            _ => Err(Error::CongestionControl),
+        }
+    }
+}
+
+/// Careful Resume and congestion control settings overriding the [`Config`]
+/// of a single connection.
+///
+/// A server shares one [`Config`] between all the connections it accepts.
+/// The delta is applied to a connection with [`Connection::reconfigure()`],
+/// e.g. once the SNI, the ALPN or the client address show that it should use
+/// a different resume policy. Settings that are not set in the delta keep
+/// their value from the [`Config`].
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+/// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+/// # let peer = "127.0.0.1:1234".parse().unwrap();
+/// # let local = "127.0.0.1:4321".parse().unwrap();
+/// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+/// if conn.server_name() == Some("video.example") {
+///     let mut cr = quiche::CrConfig::default();
+///     cr.set_min_rtts_before_jump(3);
+///
+///     let mut delta = quiche::ConfigDelta::default();
+///     delta.set_cc_algorithm(quiche::CongestionControlAlgorithm::BBR2);
+///     delta.set_cr_config(cr);
+///
+///     conn.reconfigure(&delta)?;
+/// }
+/// # Ok::<(), quiche::Error>(())
+/// ```
+///
+/// [`Config`]: crate::Config
+/// [`Connection::reconfigure()`]: crate::Connection::reconfigure
+#[derive(Clone, Debug, Default)]
+pub struct ConfigDelta {
+    pub(crate) cc_algorithm: Option<CongestionControlAlgorithm>,
+
+    pub(crate) initial_congestion_window_packets: Option<usize>,
+
+    pub(crate) cr: Option<CrConfig>,
+}
+
+impl ConfigDelta {
+    /// Overrides the congestion control algorithm.
+    pub fn set_cc_algorithm(&mut self, algo: CongestionControlAlgorithm) {
+        self.cc_algorithm = Some(algo);
+    }
+
+    /// Overrides the initial congestion window size in terms of packet
+    /// count.
+    pub fn set_initial_congestion_window_packets(&mut self, packets: usize) {
+        self.initial_congestion_window_packets = Some(packets);
+    }
+
+    /// Overrides the Careful Resume settings.
+    ///
+    /// The settings are validated when the delta is applied, against the
+    /// congestion control algorithm of the connection.
+    pub fn set_cr_config(&mut self, cr: CrConfig) {
+        self.cr = Some(cr);
+    }
+}
+
+/// The reason a [`CrConfig`] was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrConfigError {
+    /// Careful Resume is enabled with pacing disabled, so the jump would be
+    /// sent in a single burst. See [`CrConfig::allow_unpaced_jump()`].
+    UnpacedJump,
+
+    /// Careful Resume is disabled, but settings that only affect Careful
+    /// Resume were changed from their defaults.
+    ResumeDisabled,
+
+    /// Careful Resume is enabled with a congestion control algorithm that
+    /// doesn't support it.
+    UnsupportedCongestionControl,
+
+    /// The maximum pacing rate is 0.
+    ZeroMaxPacingRate,
+
+    /// The RTT bounds don't include the previous RTT. See
+    /// [`CrConfig::set_rtt_bounds()`].
+    InvalidRttBounds,
+
+    /// The jump factor is outside of the allowed range. See
+    /// [`CrConfig::set_jump_factor()`].
+    InvalidJumpFactor,
+
+    /// The plausibility factor is negative or not finite. See
+    /// [`CrConfig::set_plausibility_factor()`].
+    InvalidPlausibilityFactor,
+
+    /// The observed parameters would be reported continuously. See
+    /// [`CrConfig::set_observe_max_gap()`] and
+    /// [`CrConfig::set_observe_change_thresholds()`].
+    InvalidObserveThresholds,
+}
+
+impl std::fmt::Display for CrConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for CrConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+impl From<CrConfigError> for Error {
+    fn from(_err: CrConfigError) -> Self {
+        Error::CongestionControl
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate() {
+        let cr = CrConfig::default();
+        assert_eq!(cr.validate(CongestionControlAlgorithm::CUBIC), Ok(()));
+        assert_eq!(cr.validate(CongestionControlAlgorithm::Reno), Ok(()));
+        assert_eq!(cr.validate(CongestionControlAlgorithm::BBR), Ok(()));
+        assert_eq!(cr.validate(CongestionControlAlgorithm::BBR2), Ok(()));
+
+        // Jumping without pacing needs to be allowed explicitly.
+        let mut cr = CrConfig::default();
+        cr.enable_pacing(false);
+        assert_eq!(
+            cr.validate(CongestionControlAlgorithm::CUBIC),
+            Err(CrConfigError::UnpacedJump)
+        );
+
+        cr.allow_unpaced_jump(true);
+        assert_eq!(cr.validate(CongestionControlAlgorithm::CUBIC), Ok(()));
+
+        let mut cr = CrConfig::default();
+        cr.set_max_pacing_rate(0);
+        assert_eq!(
+            cr.validate(CongestionControlAlgorithm::CUBIC),
+            Err(CrConfigError::ZeroMaxPacingRate)
+        );
+
+        let mut cr = CrConfig::default();
+        cr.set_rtt_bounds(0.8, 1.25);
+        cr.set_jump_divisor(0);
+        assert_eq!(cr.thresholds.jump_factor, 1.0);
+        assert_eq!(cr.validate(CongestionControlAlgorithm::CUBIC), Ok(()));
+
+        cr.set_jump_divisor(4);
+        assert_eq!(cr.thresholds.jump_factor, 0.25);
+        assert_eq!(cr.validate(CongestionControlAlgorithm::CUBIC), Ok(()));
+
+        for v in [0.2, 1.5, f64::NAN] {
+            cr.set_jump_factor(v);
+            assert_eq!(
+                cr.validate(CongestionControlAlgorithm::CUBIC),
+                Err(CrConfigError::InvalidJumpFactor)
+            );
+        }
+
+        cr.set_jump_factor(0.75);
+
+        for v in [-1.0, f64::INFINITY, f64::NAN] {
+            cr.set_plausibility_factor(v);
+            assert_eq!(
+                cr.validate(CongestionControlAlgorithm::CUBIC),
+                Err(CrConfigError::InvalidPlausibilityFactor)
+            );
+        }
+
+        cr.set_plausibility_factor(2.0);
+        assert_eq!(cr.validate(CongestionControlAlgorithm::CUBIC), Ok(()));
+
+        for (min_ratio, max_ratio) in [(1.0, 2.0), (0.5, 1.0), (-0.5, 2.0)] {
+            cr.set_rtt_bounds(min_ratio, max_ratio);
+            assert_eq!(
+                cr.validate(CongestionControlAlgorithm::CUBIC),
+                Err(CrConfigError::InvalidRttBounds)
+            );
+        }
+
+        cr.set_rtt_bounds(f64::NAN, 2.0);
+        assert_eq!(
+            cr.validate(CongestionControlAlgorithm::CUBIC),
+            Err(CrConfigError::InvalidRttBounds)
+        );
+    }
+
+    #[test]
+    fn validate_observe() {
+        let mut cr = CrConfig::default();
+        cr.set_observe_policy(CrObservePolicy::Periodic);
+        cr.set_observe_max_gap(Duration::from_secs(5));
+        cr.set_observe_min_cwnd(0);
+        cr.set_observe_change_thresholds(0.5, 2.0);
+        assert_eq!(cr.validate(CongestionControlAlgorithm::CUBIC), Ok(()));
+
+        // The observe phase also runs without Careful Resume.
+        cr.enable_resume(false);
+        assert_eq!(cr.validate(CongestionControlAlgorithm::CUBIC), Ok(()));
+
+        cr.set_observe_max_gap(Duration::ZERO);
+        assert_eq!(
+            cr.validate(CongestionControlAlgorithm::CUBIC),
+            Err(CrConfigError::InvalidObserveThresholds)
+        );
+
+        cr.set_observe_max_gap(Duration::from_secs(5));
+
+        for (rtt, cwnd) in [(0.0, 1.0), (1.0, -1.0), (f64::NAN, 1.0)] {
+            cr.set_observe_change_thresholds(rtt, cwnd);
+            assert_eq!(
+                cr.validate(CongestionControlAlgorithm::CUBIC),
+                Err(CrConfigError::InvalidObserveThresholds)
+            );
+        }
+    }
+
+    #[test]
+    fn config_setters() {
+        let mut config = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+
+        assert_eq!(
+            config.set_cr_jump_factor(1.5),
+            Err(CrConfigError::InvalidJumpFactor)
+        );
+        assert_eq!(
+            config.set_cr_plausibility_factor(f64::NAN),
+            Err(CrConfigError::InvalidPlausibilityFactor)
+        );
+        assert_eq!(
+            config.set_cr_rtt_bounds(1.0, 2.0),
+            Err(CrConfigError::InvalidRttBounds)
+        );
+        assert_eq!(
+            config.set_cr_observe_max_gap(Duration::ZERO),
+            Err(CrConfigError::InvalidObserveThresholds)
+        );
+        assert_eq!(
+            config.set_cr_observe_change_thresholds(0.0, 1.0),
+            Err(CrConfigError::InvalidObserveThresholds)
+        );
+
+        // Rejected values leave the settings unchanged.
+        assert_eq!(
+            config.cr_config().thresholds,
+            CrConfig::default().thresholds
+        );
+        assert_eq!(
+            config.cr_config().observe_thresholds,
+            CrConfig::default().observe_thresholds
+        );
+
+        assert_eq!(config.set_cr_jump_factor(0.75), Ok(()));
+        assert_eq!(config.set_cr_rtt_bounds(0.8, 1.25), Ok(()));
+        assert_eq!(config.cr_config().jump_factor(), 0.75);
+        assert_eq!(config.cr_config().thresholds.rtt_ratios, (0.8, 1.25));
+
+        // Errors caused by other settings are left to set_cr_config().
+        config.enable_pacing(false);
+        assert_eq!(config.set_cr_plausibility_factor(2.0), Ok(()));
+        assert_eq!(config.cr_config().thresholds.plausibility_factor, 2.0);
+    }
+
+    #[test]
+    fn validate_resume_disabled() {
+        // Without Careful Resume the congestion control and pacing settings
+        // are not restricted.
+        let mut cr = CrConfig::default();
+        cr.enable_resume(false);
+        cr.enable_pacing(false);
+        assert_eq!(cr.validate(CongestionControlAlgorithm::BBR2), Ok(()));
+
+        cr.set_min_rtts_before_jump(3);
+        assert_eq!(
+            cr.validate(CongestionControlAlgorithm::CUBIC),
+            Err(CrConfigError::ResumeDisabled)
+        );
+
+        let mut cr = CrConfig::default();
+        cr.enable_resume(false);
+        cr.enable_retreat_export(true);
+        assert_eq!(
+            cr.validate(CongestionControlAlgorithm::CUBIC),
+            Err(CrConfigError::ResumeDisabled)
+        );
+
+        let mut cr = CrConfig::default();
+        cr.enable_resume(false);
+        cr.set_jump_pacing(CrJumpPacing::Burst);
+        assert_eq!(
+            cr.validate(CongestionControlAlgorithm::CUBIC),
+            Err(CrConfigError::ResumeDisabled)
+        );
+
+        let mut cr = CrConfig::default();
+        cr.enable_resume(false);
+        cr.set_idle_threshold(Duration::from_secs(1));
+        assert_eq!(
+            cr.validate(CongestionControlAlgorithm::CUBIC),
+            Err(CrConfigError::ResumeDisabled)
+        );
+
+        let mut cr = CrConfig::default();
+        cr.enable_resume(false);
+        cr.set_validating_timeout(Duration::from_secs(1));
+        assert_eq!(
+            cr.validate(CongestionControlAlgorithm::CUBIC),
+            Err(CrConfigError::ResumeDisabled)
+        );
+    }
+
+    #[test]
+    fn profiles() {
+        for profile in [
+            CrProfile::Default,
+            CrProfile::Geo,
+            CrProfile::Leo,
+            CrProfile::LongFat,
+        ] {
+            let mut cr = CrConfig::default();
+            cr.set_profile(profile);
+            assert_eq!(cr.validate(CongestionControlAlgorithm::CUBIC), Ok(()));
+        }
+
+        let mut cr = CrConfig::default();
+        cr.set_unvalidated_timeout(Duration::from_secs(1));
+        cr.set_profile(CrProfile::Geo);
+        assert_eq!(cr.thresholds.rtt_ratios, (0.75, 1.5));
+        assert_eq!(cr.jump_factor(), 1.0);
+        assert_eq!(cr.unvalidated_timeout, Duration::ZERO);
+        assert_eq!(cr.unvalidated_timeout_rtts, 3);
+
+        // Settings changed afterwards override the profile.
+        cr.set_jump_factor(0.5);
+        assert_eq!(cr.jump_factor(), 0.5);
+
+        // The default profile restores the default settings.
+        cr.set_profile(CrProfile::Default);
+        assert_eq!(cr.thresholds, CrConfig::default().thresholds);
+        assert_eq!(cr.unvalidated_timeout_rtts, 0);
+
+        assert_eq!(CrProfile::from_str("leo"), Ok(CrProfile::Leo));
+        assert_eq!(CrProfile::from_str("long_fat"), Ok(CrProfile::LongFat));
+        assert_eq!(CrProfile::from_str("meo"), Err(Error::CongestionControl));
+    }
+}
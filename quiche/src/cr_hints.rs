@@ -0,0 +1,108 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Careful Resume hints sent by the server after the handshake.
+//!
+//! When both endpoints enable them with [`Config::enable_cr_hints()`], the
+//! server can revise what it knows about the path to the client at any time
+//! during the connection, e.g. the capacity it measured once its own
+//! congestion window grew, with [`Connection::send_cr_hints()`]. The hints
+//! are carried in CR_HINTS frames, which are numbered so that the client
+//! only reports the latest ones, even when they are reordered or
+//! retransmitted.
+//!
+//! Clients report the hints to the [`CrHintsHandler`] set with
+//! [`Config::set_cr_hints_handler()`] as soon as they are received, and to
+//! [`Connection::cr_hints_next()`], so that the application can provision
+//! careful resume mid-connection rather than only when it reconnects.
+//!
+//! [`Config::enable_cr_hints()`]: crate::Config::enable_cr_hints
+//! [`Config::set_cr_hints_handler()`]: crate::Config::set_cr_hints_handler
+//! [`Connection::send_cr_hints()`]: crate::Connection::send_cr_hints
+//! [`Connection::cr_hints_next()`]: crate::Connection::cr_hints_next
+
+use std::time::Duration;
+
+/// Careful Resume hints about the path, sent by the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CrHints {
+    /// How long the hints may be used for after they were sent
+    pub lifetime: Duration,
+    /// The capacity of the path measured by the server, in bytes, or 0 if
+    /// unknown
+    pub capacity: usize,
+    /// The minimum round-trip-time measured by the server, or zero if
+    /// unknown
+    pub min_rtt: Duration,
+    /// The largest congestion window careful resume should jump to on the
+    /// path, in bytes, or 0 for no limit
+    pub max_jump: usize,
+}
+
+impl CrHints {
+    /// Returns the capacity careful resume may jump to, i.e. the capacity of
+    /// the path bounded by the largest jump.
+    pub fn jump_capacity(&self) -> usize {
+        match self.max_jump {
+            0 => self.capacity,
+
+            max_jump => self.capacity.min(max_jump),
+        }
+    }
+}
+
+/// Receives the Careful Resume hints sent by the server, on clients.
+pub trait CrHintsHandler: Send + Sync {
+    /// Called with the latest hints received on the connection to the
+    /// server with source connection ID `scid`.
+    ///
+    /// This is called while the connection processes incoming packets, so it
+    /// should not block.
+    fn on_cr_hints(&self, scid: &[u8], hints: &CrHints);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jump_capacity() {
+        let mut hints = CrHints {
+            lifetime: Duration::from_secs(60),
+            capacity: 120_000,
+            min_rtt: Duration::from_millis(50),
+            max_jump: 0,
+        };
+
+        assert_eq!(hints.jump_capacity(), 120_000);
+
+        hints.max_jump = 80_000;
+        assert_eq!(hints.jump_capacity(), 80_000);
+
+        hints.max_jump = 200_000;
+        assert_eq!(hints.jump_capacity(), 120_000);
+    }
+}
@@ -0,0 +1,369 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Careful Resume parameters shared by the connections to a destination.
+//!
+//! A [`CrPathCache`] set with [`Config::set_cr_path_cache()`] plays the role
+//! of the TCP metrics cache: the parameters observed by all the connections
+//! to a destination, concurrent or sequential, are folded into a single
+//! estimate of the RTT and of the capacity of the path. A new connection
+//! that has no stored parameters of its own, i.e. none from a [`CrStore`] or
+//! passed to [`setup_careful_resume()`], sets up careful resume with its
+//! share of that capacity.
+//!
+//! Connections to the same destination are assumed to share a path, and so
+//! its capacity. The congestion window observed by one of `n` concurrent
+//! connections counts for `n` times its value, and a new connection is
+//! given `1 / (n + 1)` of the capacity, so that it doesn't jump to a window
+//! the other connections are already using.
+//!
+//! Destinations are keyed by the IP address of the server on clients, and by
+//! the network prefix of the client's IP address on servers, like the
+//! parameters of a [`CrStore`].
+//!
+//! [`Config::set_cr_path_cache()`]: crate::Config::set_cr_path_cache
+//! [`CrStore`]: crate::CrStore
+//! [`setup_careful_resume()`]: crate::Connection::setup_careful_resume
+
+use std::cmp;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::cr_store;
+use crate::CREvent;
+
+/// Weight of a new min RTT sample in the shared estimate, like the smoothed
+/// RTT of RFC 9002.
+const RTT_GAIN: u32 = 8;
+
+/// Weight of a new capacity sample in the shared estimate.
+const CAPACITY_GAIN: usize = 4;
+
+/// Returns the key of the path to `peer`.
+pub(crate) fn key(is_server: bool, peer: IpAddr) -> String {
+    if is_server {
+        cr_store::server_key(peer)
+    } else {
+        peer.to_string()
+    }
+}
+
+#[derive(Default)]
+struct Entry {
+    /// The smoothed min RTT of the path.
+    min_rtt: Duration,
+
+    /// The smoothed capacity of the path, in bytes.
+    capacity: usize,
+
+    /// The smoothed pacing rate the path was used with, in bytes per second,
+    /// or 0 if unknown.
+    pacing_rate: u64,
+
+    /// When the estimate was last updated, or `None` if there is no
+    /// estimate yet.
+    updated_at: Option<SystemTime>,
+
+    /// The number of connections currently using the path.
+    connections: usize,
+}
+
+impl Entry {
+    fn is_expired(&self, now: SystemTime, max_age: Duration) -> bool {
+        match self.updated_at {
+            Some(t) => now.duration_since(t).unwrap_or_default() > max_age,
+
+            None => true,
+        }
+    }
+}
+
+/// Careful Resume parameters shared by the connections to a destination.
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use std::time::Duration;
+/// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+/// let cache = quiche::CrPathCache::new(Duration::from_secs(600));
+/// config.set_cr_path_cache(Arc::new(cache));
+/// # Ok::<(), quiche::Error>(())
+/// ```
+pub struct CrPathCache {
+    entries: Mutex<HashMap<String, Entry>>,
+
+    max_age: Duration,
+}
+
+impl CrPathCache {
+    /// Creates an empty cache, using the estimate of a path for up to
+    /// `max_age` after it was last updated.
+    pub fn new(max_age: Duration) -> Self {
+        CrPathCache {
+            entries: Mutex::new(HashMap::new()),
+            max_age,
+        }
+    }
+
+    /// Returns the number of paths in the cache, including expired ones.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Returns whether the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of connections currently using the path `key`.
+    pub fn connections(&self, key: &str) -> usize {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(key)
+            .map_or(0, |e| e.connections)
+    }
+
+    /// Returns the parameters a new connection on the path `key` may resume
+    /// with, i.e. its share of the capacity of the path, or `None` if there
+    /// is no recent estimate.
+    pub fn get(&self, key: &str) -> Option<CREvent> {
+        self.get_at(key, SystemTime::now())
+    }
+
+    pub(crate) fn update(&self, key: &str, params: CREvent) {
+        self.update_at(key, params, SystemTime::now())
+    }
+
+    /// Counts a connection on the path `key` until the returned lease is
+    /// dropped.
+    pub(crate) fn join(self: &Arc<Self>, key: String) -> CrPathLease {
+        self.entries
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .connections += 1;
+
+        CrPathLease {
+            cache: self.clone(),
+            key,
+        }
+    }
+
+    fn leave(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap();
+
+        if let Some(e) = entries.get_mut(key) {
+            e.connections = e.connections.saturating_sub(1);
+
+            if e.connections == 0 && e.updated_at.is_none() {
+                entries.remove(key);
+            }
+        }
+    }
+
+    fn get_at(&self, key: &str, now: SystemTime) -> Option<CREvent> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+
+        if entry.is_expired(now, self.max_age) {
+            return None;
+        }
+
+        let share = entry.connections + 1;
+
+        Some(CREvent {
+            min_rtt: entry.min_rtt,
+            cwnd: entry.capacity / share,
+            pacing_rate: entry.pacing_rate / share as u64,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        })
+    }
+
+    fn update_at(&self, key: &str, params: CREvent, now: SystemTime) {
+        let mut entries = self.entries.lock().unwrap();
+
+        // Drop unused expired paths, so the cache doesn't grow without bound.
+        entries
+            .retain(|_, e| e.connections > 0 || !e.is_expired(now, self.max_age));
+
+        let max_age = self.max_age;
+        let entry = entries.entry(key.to_string()).or_default();
+
+        // The connection reporting the parameters has its share of the path.
+        let share = cmp::max(entry.connections, 1);
+        let capacity = params.cwnd.saturating_mul(share);
+        let pacing_rate = params.pacing_rate.saturating_mul(share as u64);
+
+        if entry.is_expired(now, max_age) {
+            entry.min_rtt = params.min_rtt;
+            entry.capacity = capacity;
+            entry.pacing_rate = pacing_rate;
+        } else {
+            entry.min_rtt =
+                (entry.min_rtt * (RTT_GAIN - 1) + params.min_rtt) / RTT_GAIN;
+
+            // The capacity measured after congestion replaces the estimate,
+            // like the slow start threshold of the TCP metrics cache.
+            if params.post_congestion {
+                entry.capacity = capacity;
+            } else {
+                entry.capacity = (entry.capacity * (CAPACITY_GAIN - 1) +
+                    capacity) /
+                    CAPACITY_GAIN;
+            }
+
+            entry.pacing_rate = if pacing_rate == 0 || entry.pacing_rate == 0 {
+                pacing_rate
+            } else {
+                (entry.pacing_rate * (CAPACITY_GAIN as u64 - 1) + pacing_rate) /
+                    CAPACITY_GAIN as u64
+            };
+        }
+
+        entry.updated_at = Some(now);
+    }
+}
+
+impl std::fmt::Debug for CrPathCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "paths={} max_age={:?}", self.len(), self.max_age)
+    }
+}
+
+/// A connection counted on a path of a [`CrPathCache`].
+pub(crate) struct CrPathLease {
+    cache: Arc<CrPathCache>,
+
+    key: String,
+}
+
+impl CrPathLease {
+    pub(crate) fn cache(&self) -> &CrPathCache {
+        &self.cache
+    }
+}
+
+impl Drop for CrPathLease {
+    fn drop(&mut self) {
+        self.cache.leave(&self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(min_rtt_ms: u64, cwnd: usize) -> CREvent {
+        CREvent {
+            min_rtt: Duration::from_millis(min_rtt_ms),
+            cwnd,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        }
+    }
+
+    #[test]
+    fn keys() {
+        let ip: IpAddr = "192.0.2.77".parse().unwrap();
+
+        assert_eq!(key(false, ip), "192.0.2.77");
+        assert_eq!(key(true, ip), "192.0.2.0/24");
+    }
+
+    #[test]
+    fn sequential() {
+        let cache = CrPathCache::new(Duration::from_secs(60));
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        assert_eq!(cache.get_at("a", now), None);
+
+        cache.update_at("a", params(80, 100_000), now);
+        assert_eq!(cache.get_at("a", now), Some(params(80, 100_000)));
+
+        // Later reports are smoothed into the estimate.
+        cache.update_at("a", params(40, 200_000), now);
+        assert_eq!(cache.get_at("a", now), Some(params(75, 125_000)));
+
+        // Until it expires.
+        let later = now + Duration::from_secs(61);
+        assert_eq!(cache.get_at("a", later), None);
+
+        cache.update_at("a", params(40, 200_000), later);
+        assert_eq!(cache.get_at("a", later), Some(params(40, 200_000)));
+
+        // A post-congestion capacity replaces the estimate.
+        let mut retreat = params(40, 50_000);
+        retreat.post_congestion = true;
+
+        cache.update_at("a", retreat, later);
+        assert_eq!(cache.get_at("a", later), Some(params(40, 50_000)));
+    }
+
+    #[test]
+    fn concurrent() {
+        let cache = Arc::new(CrPathCache::new(Duration::from_secs(60)));
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+        let first = cache.join("a".to_string());
+        let second = cache.join("a".to_string());
+        assert_eq!(cache.connections("a"), 2);
+
+        // There is no estimate until a connection reports one.
+        assert_eq!(cache.get_at("a", now), None);
+
+        // Each of the two connections uses half of the capacity.
+        cache.update_at("a", params(50, 60_000), now);
+
+        // A third connection gets a third of it.
+        assert_eq!(cache.get_at("a", now), Some(params(50, 40_000)));
+
+        drop(first);
+        assert_eq!(cache.get_at("a", now), Some(params(50, 60_000)));
+
+        drop(second);
+        assert_eq!(cache.connections("a"), 0);
+        assert_eq!(cache.get_at("a", now), Some(params(50, 120_000)));
+
+        // Paths that were joined without reporting parameters are removed
+        // when the last connection leaves.
+        let lease = cache.join("b".to_string());
+        assert_eq!(cache.len(), 2);
+
+        drop(lease);
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Server-side policy for the Careful Resume parameters of a connection.
+//!
+//! A server that resumes with parameters supplied by the client, either in a
+//! stateless blob or in a BDP frame, has to trust the client not to claim a
+//! path capacity it doesn't have. A [`CrParamsPolicy`] set with
+//! [`Config::set_cr_params_policy()`] sees all the parameters a server is
+//! about to set up careful resume with, whatever their source, and can
+//! reject them, or clamp or scale them, e.g. to cap the jump of each client
+//! at a rate limit.
+//!
+//! Parameters rejected by the policy are not used, and are reported by
+//! [`Connection::cr_rejection_next()`] as [`CrRejectReason::Policy`].
+//!
+//! [`Config::set_cr_params_policy()`]: crate::Config::set_cr_params_policy
+//! [`Connection::cr_rejection_next()`]: crate::Connection::cr_rejection_next
+//! [`CrRejectReason::Policy`]: crate::CrRejectReason::Policy
+
+use std::net::SocketAddr;
+
+use crate::CREvent;
+
+/// Where Careful Resume parameters set up on a server come from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrParamsSource {
+    /// A stateless blob echoed by the client, in a transport parameter or in
+    /// the token of a NEW_TOKEN frame.
+    ResumeBlob,
+    /// A BDP frame echoed by the client.
+    BdpFrame,
+    /// The [`CrStore`] set on the configuration.
+    ///
+    /// [`CrStore`]: crate::CrStore
+    Store,
+    /// The [`CrPathCache`] set on the configuration.
+    ///
+    /// [`CrPathCache`]: crate::CrPathCache
+    PathCache,
+    /// The application, with
+    /// [`Connection::setup_server_careful_resume()`].
+    ///
+    /// [`Connection::setup_server_careful_resume()`]: crate::Connection::setup_server_careful_resume
+    Application,
+}
+
+impl CrParamsSource {
+    /// Returns whether the parameters were supplied by the client.
+    pub fn is_peer(self) -> bool {
+        matches!(self, CrParamsSource::ResumeBlob | CrParamsSource::BdpFrame)
+    }
+}
+
+/// Validates the Careful Resume parameters a server sets up careful resume
+/// with.
+pub trait CrParamsPolicy: Send + Sync {
+    /// Returns the parameters to set up careful resume with on the
+    /// connection from `peer`, given `params` from `source`, or `None` to
+    /// reject them.
+    ///
+    /// This is called while the connection processes incoming packets, so it
+    /// should not block.
+    fn check(
+        &self, peer: SocketAddr, source: CrParamsSource, params: &CREvent,
+    ) -> Option<CREvent>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_peer() {
+        assert!(CrParamsSource::ResumeBlob.is_peer());
+        assert!(CrParamsSource::BdpFrame.is_peer());
+        assert!(!CrParamsSource::Store.is_peer());
+        assert!(!CrParamsSource::PathCache.is_peer());
+        assert!(!CrParamsSource::Application.is_peer());
+    }
+}
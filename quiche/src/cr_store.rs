@@ -0,0 +1,788 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Persistence of Careful Resume parameters between connections.
+//!
+//! A [`CrStore`] set with [`Config::set_cr_store()`] is consulted when a
+//! connection is created, to set up careful resume with the parameters
+//! observed on a previous connection to the same peer, and is updated with
+//! the parameters observed on the connection as they are reported by
+//! [`cr_event_next()`].
+//!
+//! Parameters are keyed by peer. Clients use the server name passed to
+//! [`connect()`], or the IP address of the server when there is none. Servers
+//! use the network prefix of the client's IP address, i.e. `/24` for IPv4 and
+//! `/48` for IPv6, so that clients sharing a path share parameters. The
+//! parameters observed after a connection migrated are stored for the peer
+//! address of the new path.
+//!
+//! Each endpoint observes the congestion window and minimum RTT of the data
+//! it sends, so the parameters stored by a server are those of its responses.
+//! With a download-heavy workload, a server with a store resumes repeat
+//! visitors from the same network prefix at the capacity it measured itself,
+//! without any help from the client. The clients of such a workload send too
+//! little to observe their path, and store nothing.
+//!
+//! Clients connecting with [`connect_with_cr_origin()`] key parameters by
+//! the IP address of the server within an origin chosen by the application,
+//! e.g. the host name that resolved to several addresses. A connection to an
+//! address with no parameters of its own falls back to the parameters of
+//! another address of the origin, with less confidence, see
+//! [`MemoryCrStore::set_origin_confidence()`].
+//!
+//! When resuming from stored parameters makes careful resume retreat, the
+//! parameters are marked as invalid, so that later connections to the same
+//! peer don't attempt the same jump until the marking expires.
+//!
+//! [`Config::set_cr_store()`]: crate::Config::set_cr_store
+//! [`cr_event_next()`]: crate::Connection::cr_event_next
+//! [`connect()`]: crate::connect
+//! [`connect_with_cr_origin()`]: crate::connect_with_cr_origin
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use crate::CREvent;
+
+/// The default fraction of the parameters of another address of an origin
+/// that are used.
+const DEFAULT_ORIGIN_CONFIDENCE: f64 = 0.5;
+
+/// The default minimum interval between two writes of a [`FileCrStore`].
+const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Storage for Careful Resume parameters, shared between connections.
+pub trait CrStore: Send + Sync {
+    /// Returns the parameters stored for `key`, or `None` if there are none
+    /// or they are too old to be used.
+    fn get(&self, key: &str) -> Option<CREvent>;
+
+    /// Stores `params` for `key`, replacing any previous parameters.
+    fn put(&self, key: &str, params: CREvent);
+
+    /// Marks `params`, used for `key`, as invalid because careful resume
+    /// retreated after jumping with them.
+    ///
+    /// Stores should not return the same congestion window and RTT for `key`
+    /// for some time. The default implementation does nothing.
+    fn invalidate(&self, key: &str, params: CREvent) {
+        let _ = (key, params);
+    }
+
+    /// Returns the parameters stored for `key`, an address of `origin`, or
+    /// when there are none, parameters derived from those of other addresses
+    /// of `origin`.
+    ///
+    /// The default implementation only returns the parameters stored for
+    /// `key`.
+    fn get_in_origin(&self, origin: &str, key: &str) -> Option<CREvent> {
+        let _ = origin;
+
+        self.get(key)
+    }
+}
+
+/// Returns the key of the parameters for a connection to `server_name`, or
+/// to `peer` when there is no server name.
+pub(crate) fn client_key(server_name: Option<&str>, peer: IpAddr) -> String {
+    match server_name {
+        Some(name) => name.to_string(),
+
+        None => peer.to_string(),
+    }
+}
+
+/// Returns the key of the parameters for a connection to `peer`, an address
+/// of `origin`.
+pub(crate) fn origin_key(origin: &str, peer: IpAddr) -> String {
+    format!("{origin}@{peer}")
+}
+
+/// Returns the origin of `key`, if it is the key of an address of an origin.
+fn key_origin(key: &str) -> Option<&str> {
+    key.rsplit_once('@').map(|(origin, _)| origin)
+}
+
+/// Returns the key of the parameters for a connection from `peer`.
+pub(crate) fn server_key(peer: IpAddr) -> String {
+    match peer {
+        IpAddr::V4(ip) => {
+            let prefix = u32::from(ip) & 0xffff_ff00;
+
+            format!("{}/24", Ipv4Addr::from(prefix))
+        },
+
+        IpAddr::V6(ip) => {
+            let prefix = u128::from(ip) & !((1_u128 << 80) - 1);
+
+            format!("{}/48", Ipv6Addr::from(prefix))
+        },
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    params: CREvent,
+
+    stored_at: SystemTime,
+}
+
+impl Entry {
+    fn is_expired(&self, now: SystemTime, max_age: Duration) -> bool {
+        now.duration_since(self.stored_at).unwrap_or_default() > max_age
+    }
+}
+
+/// A [`CrStore`] keeping parameters in memory.
+pub struct MemoryCrStore {
+    entries: Mutex<HashMap<String, Entry>>,
+
+    /// Parameters marked as invalid, with the time they were marked.
+    invalid: Mutex<HashMap<String, Entry>>,
+
+    max_age: Duration,
+
+    invalid_max_age: Duration,
+
+    origin_confidence: f64,
+}
+
+impl MemoryCrStore {
+    /// Creates an empty store, returning parameters for up to `max_age`
+    /// after they were stored.
+    pub fn new(max_age: Duration) -> Self {
+        MemoryCrStore {
+            entries: Mutex::new(HashMap::new()),
+            invalid: Mutex::new(HashMap::new()),
+            max_age,
+            invalid_max_age: max_age,
+            origin_confidence: DEFAULT_ORIGIN_CONFIDENCE,
+        }
+    }
+
+    /// Sets how long parameters marked as invalid are not returned.
+    ///
+    /// The default value is the maximum age of the parameters.
+    pub fn set_invalid_max_age(&mut self, v: Duration) {
+        self.invalid_max_age = v;
+    }
+
+    /// Sets the fraction of the congestion window and pacing rate stored for
+    /// another address of the same origin that a connection to an address
+    /// with no parameters of its own resumes with, between 0 and 1.
+    ///
+    /// The path to another address may not share its bottleneck, so by
+    /// default the jump is halved. A value of 0 disables the fallback, and a
+    /// value of 1 reuses the parameters in full.
+    ///
+    /// The default value is `0.5`.
+    pub fn set_origin_confidence(&mut self, v: f64) {
+        self.origin_confidence = v.clamp(0.0, 1.0);
+    }
+
+    /// Returns the number of stored parameters, including expired ones.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Returns whether no parameters are stored.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get_at(&self, key: &str, now: SystemTime) -> Option<CREvent> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+
+        if entry.is_expired(now, self.max_age) {
+            return None;
+        }
+
+        let invalid = self.invalid.lock().unwrap();
+
+        if let Some(marked) = invalid.get(key) {
+            let same_jump = marked.params.cwnd == entry.params.cwnd &&
+                marked.params.min_rtt == entry.params.min_rtt;
+
+            if same_jump && !marked.is_expired(now, self.invalid_max_age) {
+                return None;
+            }
+        }
+
+        Some(entry.params)
+    }
+
+    fn get_in_origin_at(
+        &self, origin: &str, key: &str, now: SystemTime,
+    ) -> Option<CREvent> {
+        if let Some(params) = self.get_at(key, now) {
+            return Some(params);
+        }
+
+        if self.origin_confidence == 0.0 {
+            return None;
+        }
+
+        // The parameters most recently stored for another address of the
+        // origin, unless they were marked as invalid.
+        let others: Vec<(String, SystemTime)> = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(k, _)| key_origin(k) == Some(origin))
+            .map(|(k, e)| (k.clone(), e.stored_at))
+            .collect();
+
+        let params = others
+            .into_iter()
+            .filter_map(|(k, stored_at)| Some((self.get_at(&k, now)?, stored_at)))
+            .max_by_key(|(_, stored_at)| *stored_at)
+            .map(|(params, _)| params)?;
+
+        Some(CREvent {
+            cwnd: (params.cwnd as f64 * self.origin_confidence) as usize,
+            pacing_rate: (params.pacing_rate as f64 * self.origin_confidence)
+                as u64,
+            ..params
+        })
+    }
+
+    fn put_at(&self, key: &str, params: CREvent, stored_at: SystemTime) {
+        let mut entries = self.entries.lock().unwrap();
+
+        // Drop expired parameters, so the store doesn't grow without bound.
+        entries.retain(|_, e| !e.is_expired(stored_at, self.max_age));
+
+        entries.insert(key.to_string(), Entry { params, stored_at });
+    }
+
+    fn invalidate_at(&self, key: &str, params: CREvent, marked_at: SystemTime) {
+        let mut invalid = self.invalid.lock().unwrap();
+
+        invalid.retain(|_, e| !e.is_expired(marked_at, self.invalid_max_age));
+
+        invalid.insert(key.to_string(), Entry {
+            params,
+            stored_at: marked_at,
+        });
+    }
+}
+
+impl CrStore for MemoryCrStore {
+    fn get(&self, key: &str) -> Option<CREvent> {
+        self.get_at(key, SystemTime::now())
+    }
+
+    fn put(&self, key: &str, params: CREvent) {
+        self.put_at(key, params, SystemTime::now())
+    }
+
+    fn invalidate(&self, key: &str, params: CREvent) {
+        self.invalidate_at(key, params, SystemTime::now())
+    }
+
+    fn get_in_origin(&self, origin: &str, key: &str) -> Option<CREvent> {
+        self.get_in_origin_at(origin, key, SystemTime::now())
+    }
+}
+
+impl std::fmt::Debug for MemoryCrStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "entries={} max_age={:?}", self.len(), self.max_age)
+    }
+}
+
+/// A [`CrStore`] keeping parameters in a file, so they survive restarts.
+///
+/// The file is read when the store is opened. Parameters stored or marked as
+/// invalid are kept in memory, and the file is rewritten with them at most
+/// once per flush interval, see [`set_flush_interval()`], when the store is
+/// dropped, or when [`flush()`] is called. Changes made since the last write
+/// are lost if the process exits without dropping the store.
+///
+/// The file holds one line per key, with the parameters serialized by
+/// [`CREvent::to_bytes()`] for the key, in hexadecimal.
+///
+/// Parameters marked as invalid use the same format, prefixed by `!`, with
+/// the time they were marked.
+///
+/// [`CREvent::to_bytes()`]: crate::CREvent::to_bytes
+/// [`set_flush_interval()`]: FileCrStore::set_flush_interval
+/// [`flush()`]: FileCrStore::flush
+pub struct FileCrStore {
+    path: PathBuf,
+
+    memory: MemoryCrStore,
+
+    flush_interval: Duration,
+
+    // Held while the file is written, so concurrent writes don't interleave.
+    flush_state: Mutex<FlushState>,
+}
+
+struct FlushState {
+    // When the file was last written.
+    flushed_at: Instant,
+
+    // Whether the parameters changed since the file was last written.
+    dirty: bool,
+}
+
+impl FileCrStore {
+    /// Opens the store kept in the file at `path`, returning parameters for
+    /// up to `max_age` after they were stored.
+    ///
+    /// A missing file is treated as an empty store, and is created when
+    /// parameters are first stored. Malformed lines are ignored. Parameters
+    /// marked as invalid are not returned for `max_age` either.
+    pub fn open<P: AsRef<Path>>(
+        path: P, max_age: Duration,
+    ) -> std::io::Result<Self> {
+        let memory = MemoryCrStore::new(max_age);
+
+        let contents = match std::fs::read_to_string(path.as_ref()) {
+            Ok(v) => v,
+
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+
+            Err(e) => return Err(e),
+        };
+
+        {
+            let mut entries = memory.entries.lock().unwrap();
+            let mut invalid = memory.invalid.lock().unwrap();
+
+            for line in contents.lines() {
+                let (key, entry) = match parse_record(line) {
+                    Some(v) => v,
+
+                    None => continue,
+                };
+
+                match key.strip_prefix('!') {
+                    Some(key) => invalid.insert(key.to_string(), entry),
+
+                    None => entries.insert(key, entry),
+                };
+            }
+        }
+
+        Ok(FileCrStore {
+            path: path.as_ref().to_path_buf(),
+            memory,
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            flush_state: Mutex::new(FlushState {
+                flushed_at: Instant::now(),
+                dirty: false,
+            }),
+        })
+    }
+
+    /// Sets the minimum interval between two writes of the file when
+    /// parameters change.
+    ///
+    /// A zero interval writes the file each time parameters are stored or
+    /// marked as invalid.
+    ///
+    /// The default value is 5 seconds.
+    pub fn set_flush_interval(&mut self, v: Duration) {
+        self.flush_interval = v;
+    }
+
+    /// Writes the parameters changed since the file was last written.
+    pub fn flush(&self) -> std::io::Result<()> {
+        let mut state = self.flush_state.lock().unwrap();
+
+        self.flush_locked(&mut state)
+    }
+
+    fn flush_locked(&self, state: &mut FlushState) -> std::io::Result<()> {
+        if !state.dirty {
+            return Ok(());
+        }
+
+        self.write()?;
+
+        state.flushed_at = Instant::now();
+        state.dirty = false;
+
+        Ok(())
+    }
+
+    /// Records that parameters changed, and writes the file if it wasn't
+    /// written during the last flush interval.
+    fn changed(&self) {
+        let mut state = self.flush_state.lock().unwrap();
+
+        state.dirty = true;
+
+        if state.flushed_at.elapsed() < self.flush_interval {
+            return;
+        }
+
+        if let Err(e) = self.flush_locked(&mut state) {
+            error!(
+                "failed to write careful resume store {:?}: {:?}",
+                self.path, e
+            );
+        }
+    }
+
+    /// Sets the fraction of the parameters of another address of the same
+    /// origin used by connections to an address with no parameters of its
+    /// own, see [`MemoryCrStore::set_origin_confidence()`].
+    pub fn set_origin_confidence(&mut self, v: f64) {
+        self.memory.set_origin_confidence(v);
+    }
+
+    fn write(&self) -> std::io::Result<()> {
+        let mut out = Vec::new();
+
+        // Only keep the entries locked while they are serialized, so
+        // connections looking up parameters don't wait for the file.
+        {
+            let entries = self.memory.entries.lock().unwrap();
+            let invalid = self.memory.invalid.lock().unwrap();
+
+            let lines = entries
+                .iter()
+                .map(|(key, e)| ("", key, e))
+                .chain(invalid.iter().map(|(key, e)| ("!", key, e)));
+
+            for (prefix, key, e) in lines {
+                let record = e.params.to_bytes(key, e.stored_at);
+
+                write!(out, "{prefix}")?;
+
+                for b in record {
+                    write!(out, "{b:02x}")?;
+                }
+
+                writeln!(out)?;
+            }
+        }
+
+        // Replace the file atomically, so readers never see a partial one.
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+
+        std::fs::write(&tmp, out)?;
+        std::fs::rename(&tmp, &self.path)
+    }
+}
+
+impl CrStore for FileCrStore {
+    fn get(&self, key: &str) -> Option<CREvent> {
+        self.memory.get(key)
+    }
+
+    fn get_in_origin(&self, origin: &str, key: &str) -> Option<CREvent> {
+        self.memory.get_in_origin(origin, key)
+    }
+
+    fn put(&self, key: &str, params: CREvent) {
+        self.memory.put(key, params);
+
+        self.changed();
+    }
+
+    fn invalidate(&self, key: &str, params: CREvent) {
+        self.memory.invalidate(key, params);
+
+        self.changed();
+    }
+}
+
+impl Drop for FileCrStore {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!(
+                "failed to write careful resume store {:?}: {:?}",
+                self.path, e
+            );
+        }
+    }
+}
+
+impl std::fmt::Debug for FileCrStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "path={:?} {:?}", self.path, self.memory)
+    }
+}
+
+fn parse_record(line: &str) -> Option<(String, Entry)> {
+    let (prefix, hex) = match line.strip_prefix('!') {
+        Some(hex) => ("!", hex),
+
+        None => ("", line),
+    };
+
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+
+    let record = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+
+    let record = CREvent::from_bytes(&record).ok()?;
+
+    let entry = Entry {
+        params: record.params,
+        stored_at: record.stored_at,
+    };
+
+    Some((format!("{}{}", prefix, record.endpoint), entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(cwnd: usize) -> CREvent {
+        CREvent {
+            min_rtt: Duration::from_millis(600),
+            cwnd,
+            pacing_rate: 6_250_000,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        }
+    }
+
+    #[test]
+    fn keys() {
+        let ip = "192.0.2.77".parse().unwrap();
+
+        assert_eq!(client_key(Some("quic.tech"), ip), "quic.tech");
+        assert_eq!(client_key(None, ip), "192.0.2.77");
+
+        assert_eq!(server_key(ip), "192.0.2.0/24");
+        assert_eq!(
+            server_key("2001:db8:1:2::1".parse().unwrap()),
+            "2001:db8:1::/48"
+        );
+    }
+
+    #[test]
+    fn memory_expiry() {
+        let store = MemoryCrStore::new(Duration::from_secs(60));
+        let now = SystemTime::now();
+
+        store.put_at("a", params(100_000), now);
+        assert_eq!(store.get_at("a", now), Some(params(100_000)));
+        assert_eq!(store.get_at("b", now), None);
+
+        // Newer parameters replace older ones.
+        store.put_at("a", params(200_000), now);
+        assert_eq!(store.get_at("a", now), Some(params(200_000)));
+
+        let later = now + Duration::from_secs(61);
+        assert_eq!(store.get_at("a", later), None);
+
+        // Expired parameters are dropped when others are stored.
+        store.put_at("b", params(100_000), later);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn memory_invalid() {
+        let mut store = MemoryCrStore::new(Duration::from_secs(60));
+        store.set_invalid_max_age(Duration::from_secs(10));
+
+        let now = SystemTime::now();
+
+        store.put_at("a", params(100_000), now);
+        store.put_at("b", params(100_000), now);
+        store.invalidate_at("a", params(100_000), now);
+
+        assert_eq!(store.get_at("a", now), None);
+        assert_eq!(store.get_at("b", now), Some(params(100_000)));
+
+        // Parameters observed later are used.
+        store.put_at("a", params(50_000), now);
+        assert_eq!(store.get_at("a", now), Some(params(50_000)));
+
+        // The marking expires.
+        store.put_at("a", params(100_000), now);
+        assert_eq!(store.get_at("a", now), None);
+
+        let later = now + Duration::from_secs(11);
+        assert_eq!(store.get_at("a", later), Some(params(100_000)));
+    }
+
+    #[test]
+    fn memory_origin() {
+        let mut store = MemoryCrStore::new(Duration::from_secs(60));
+        let now = SystemTime::now();
+
+        let a = origin_key("quic.tech", "192.0.2.1".parse().unwrap());
+        let b = origin_key("quic.tech", "192.0.2.2".parse().unwrap());
+        let c = origin_key("quic.tech", "2001:db8::3".parse().unwrap());
+
+        assert_eq!(a, "quic.tech@192.0.2.1");
+        assert_eq!(key_origin(&c), Some("quic.tech"));
+        assert_eq!(key_origin("quic.tech"), None);
+
+        assert_eq!(store.get_in_origin_at("quic.tech", &a, now), None);
+
+        store.put_at(&a, params(100_000), now);
+        store.put_at("quic.tech", params(400_000), now);
+        store.put_at(
+            &origin_key("other.tech", "192.0.2.2".parse().unwrap()),
+            params(400_000),
+            now,
+        );
+
+        // The address's own parameters are used in full.
+        assert_eq!(
+            store.get_in_origin_at("quic.tech", &a, now),
+            Some(params(100_000))
+        );
+
+        // Another address falls back to them, discounted.
+        let mut discounted = params(50_000);
+        discounted.pacing_rate = 3_125_000;
+
+        assert_eq!(
+            store.get_in_origin_at("quic.tech", &b, now),
+            Some(discounted)
+        );
+
+        // The most recently stored parameters of the origin are used.
+        let later = now + Duration::from_secs(1);
+        store.put_at(&c, params(200_000), later);
+        assert_eq!(
+            store
+                .get_in_origin_at("quic.tech", &b, later)
+                .map(|p| p.cwnd),
+            Some(100_000)
+        );
+
+        // Unless they were marked as invalid.
+        store.invalidate_at(&c, params(200_000), later);
+        assert_eq!(
+            store
+                .get_in_origin_at("quic.tech", &b, later)
+                .map(|p| p.cwnd),
+            Some(50_000)
+        );
+
+        store.set_origin_confidence(0.0);
+        assert_eq!(store.get_in_origin_at("quic.tech", &b, later), None);
+    }
+
+    #[test]
+    fn file_reopen() {
+        let path = std::env::temp_dir()
+            .join(format!("quiche-cr-store-{}", std::process::id()));
+
+        std::fs::remove_file(&path).ok();
+
+        let store = FileCrStore::open(&path, Duration::from_secs(60)).unwrap();
+        assert_eq!(store.get("quic.tech"), None);
+
+        store.put("quic.tech", params(100_000));
+        store.put("192.0.2.0/24", params(200_000));
+        store.put("198.51.100.0/24", params(300_000));
+        store.invalidate("198.51.100.0/24", params(300_000));
+
+        // The file is written when the store is dropped.
+        drop(store);
+
+        let store = FileCrStore::open(&path, Duration::from_secs(60)).unwrap();
+        assert_eq!(store.get("quic.tech"), Some(params(100_000)));
+        assert_eq!(store.get("192.0.2.0/24"), Some(params(200_000)));
+        assert_eq!(store.get("198.51.100.0/24"), None);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_flush() {
+        let path = std::env::temp_dir()
+            .join(format!("quiche-cr-store-flush-{}", std::process::id()));
+
+        std::fs::remove_file(&path).ok();
+
+        let mut store =
+            FileCrStore::open(&path, Duration::from_secs(60)).unwrap();
+        store.set_flush_interval(Duration::from_secs(3600));
+
+        // Changes are kept in memory until the store is flushed.
+        store.put("quic.tech", params(100_000));
+        assert_eq!(store.get("quic.tech"), Some(params(100_000)));
+        assert!(!path.exists());
+
+        store.flush().unwrap();
+
+        let reopened = FileCrStore::open(&path, Duration::from_secs(60)).unwrap();
+        assert_eq!(reopened.get("quic.tech"), Some(params(100_000)));
+
+        // Without changes, flushing doesn't write the file.
+        std::fs::remove_file(&path).unwrap();
+        store.flush().unwrap();
+        assert!(!path.exists());
+
+        // Without an interval, each change is written.
+        store.set_flush_interval(Duration::ZERO);
+        store.invalidate("quic.tech", params(100_000));
+
+        let reopened = FileCrStore::open(&path, Duration::from_secs(60)).unwrap();
+        assert_eq!(reopened.get("quic.tech"), None);
+
+        drop(reopened);
+        drop(store);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn malformed_records() {
+        let record = params(100_000).to_bytes("quic.tech", SystemTime::now());
+        let hex: String = record.iter().map(|b| format!("{b:02x}")).collect();
+
+        assert_eq!(parse_record(&hex).unwrap().0, "quic.tech");
+        assert_eq!(parse_record(&format!("!{hex}")).unwrap().0, "!quic.tech");
+
+        assert!(parse_record("").is_none());
+        assert!(parse_record(&hex[..hex.len() - 1]).is_none());
+        assert!(parse_record(&hex[..hex.len() - 2]).is_none());
+        assert!(parse_record(&format!("{hex}00")).is_none());
+        assert!(parse_record(&hex.replace('0', "g")).is_none());
+    }
+}
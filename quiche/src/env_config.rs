@@ -0,0 +1,279 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Settings read from environment variables, with the `env-config` feature.
+//!
+//! This lets binaries embedding quiche be experimented with in the field
+//! without rebuilding them. The variables are documented on
+//! [`Config::new()`], which reads them.
+//!
+//! [`Config::new()`]: crate::Config::new
+
+use std::env;
+use std::str::FromStr;
+
+use crate::Config;
+
+/// Applies the settings from the environment to a new `config`.
+pub(crate) fn apply(config: &mut Config) {
+    apply_vars(config, |name| env::var(name).ok());
+}
+
+// Applies the settings returned by `var` for each variable name.
+fn apply_vars(config: &mut Config, var: impl Fn(&str) -> Option<String>) {
+    #[cfg(feature = "qlog")]
+    if let Some(dir) = var("QUICHE_QLOG_DIR") {
+        config.qlog_dir = Some(dir);
+    }
+
+    let mut cr = config.cr_config().clone();
+    let mut cr_observe = config.cr_observe;
+
+    match var("QUICHE_CR").as_deref() {
+        Some("on") => {
+            cr.enable_resume(true);
+            cr_observe = true;
+        },
+
+        Some("shadow") => {
+            cr.enable_resume(false);
+            cr_observe = true;
+        },
+
+        Some("off") => {
+            cr.enable_resume(false);
+            cr_observe = false;
+        },
+
+        Some(v) => warn!("invalid QUICHE_CR={}, expected on, shadow or off", v),
+
+        None => (),
+    }
+
+    if let Some(v) = parse(&var, "QUICHE_CR_MIN_RTTS_BEFORE_JUMP") {
+        cr.set_min_rtts_before_jump(v);
+    }
+
+    if let Some(v) = parse(&var, "QUICHE_CR_JUMP_DIVISOR") {
+        cr.set_jump_divisor(v);
+    }
+
+    if let Some(v) = parse(&var, "QUICHE_CR_JUMP_FACTOR") {
+        cr.set_jump_factor(v);
+    }
+
+    if let Some(v) = parse(&var, "QUICHE_CR_MIN_PREVIOUS_CWND") {
+        cr.set_min_previous_cwnd(v);
+    }
+
+    if let Some(v) = parse(&var, "QUICHE_CR_PLAUSIBILITY_FACTOR") {
+        cr.set_plausibility_factor(v);
+    }
+
+    if let Some(v) = parse(&var, "QUICHE_CR_RETREAT_POLICY") {
+        cr.set_retreat_policy(v);
+    }
+
+    if let Some(v) = var("QUICHE_CR_RTT_BOUNDS") {
+        let bounds = v.split_once(',').and_then(|(min, max)| {
+            Some((min.trim().parse().ok()?, max.trim().parse().ok()?))
+        });
+
+        match bounds {
+            Some((min, max)) => cr.set_rtt_bounds(min, max),
+
+            None => warn!(
+                "invalid QUICHE_CR_RTT_BOUNDS={}, expected min_ratio,max_ratio",
+                v
+            ),
+        }
+    }
+
+    match config.set_cr_config(cr) {
+        Ok(()) => config.cr_observe = cr_observe,
+
+        Err(e) => warn!(
+            "ignoring the Careful Resume settings from the environment: {:?}",
+            e
+        ),
+    }
+}
+
+fn parse<T: FromStr>(
+    var: &impl Fn(&str) -> Option<String>, name: &str,
+) -> Option<T> {
+    let v = var(name)?;
+
+    match v.parse() {
+        Ok(v) => Some(v),
+
+        Err(_) => {
+            warn!("invalid {}={}", name, v);
+            None
+        },
+    }
+}
+
+/// Writes the qlog of `conn` to a new file in `dir`.
+#[cfg(feature = "qlog")]
+pub(crate) fn set_qlog(conn: &mut crate::Connection, dir: &str) {
+    let role = if conn.is_server { "server" } else { "client" };
+
+    let mut path = std::path::PathBuf::from(dir);
+    path.push(format!("{}-{}.sqlog", role, conn.trace_id()));
+
+    match std::fs::File::create(&path) {
+        Ok(f) => {
+            let title = format!("quiche-{} qlog", role);
+            let description = format!("{} id={}", title, conn.trace_id());
+
+            conn.set_qlog(
+                Box::new(std::io::BufWriter::new(f)),
+                title,
+                description,
+            );
+        },
+
+        Err(e) => warn!("failed to create qlog file {:?}: {}", path, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    fn config(vars: &[(&str, &str)]) -> Config {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        apply_vars(&mut config, |name| vars.get(name).cloned());
+        config
+    }
+
+    #[test]
+    fn cr_mode() {
+        let c = config(&[]);
+        assert!(c.cr_config().resume_enabled());
+        assert!(c.cr_observe);
+
+        let c = config(&[("QUICHE_CR", "off")]);
+        assert!(!c.cr_config().resume_enabled());
+        assert!(!c.cr_observe);
+
+        let c = config(&[("QUICHE_CR", "shadow")]);
+        assert!(!c.cr_config().resume_enabled());
+        assert!(c.cr_observe);
+
+        let c = config(&[("QUICHE_CR", "on")]);
+        assert!(c.cr_config().resume_enabled());
+        assert!(c.cr_observe);
+
+        let c = config(&[("QUICHE_CR", "maybe")]);
+        assert!(c.cr_config().resume_enabled());
+        assert!(c.cr_observe);
+    }
+
+    #[test]
+    fn cr_thresholds() {
+        let c = config(&[
+            ("QUICHE_CR_MIN_RTTS_BEFORE_JUMP", "3"),
+            ("QUICHE_CR_JUMP_DIVISOR", "4"),
+            ("QUICHE_CR_MIN_PREVIOUS_CWND", "2"),
+            ("QUICHE_CR_PLAUSIBILITY_FACTOR", "1.5"),
+            ("QUICHE_CR_RTT_BOUNDS", "0.25, 4"),
+        ]);
+
+        let mut expected = crate::CrConfig::default();
+        expected.set_min_rtts_before_jump(3);
+        expected.set_jump_divisor(4);
+        expected.set_min_previous_cwnd(2);
+        expected.set_plausibility_factor(1.5);
+        expected.set_rtt_bounds(0.25, 4.0);
+
+        assert_eq!(c.cr_config().min_rtts_before_jump, 3);
+        assert_eq!(c.cr_config().thresholds, expected.thresholds);
+
+        // Invalid values are ignored.
+        let c = config(&[
+            ("QUICHE_CR_JUMP_DIVISOR", "four"),
+            ("QUICHE_CR_RTT_BOUNDS", "0.25"),
+        ]);
+        assert_eq!(
+            c.cr_config().thresholds,
+            crate::CrConfig::default().thresholds
+        );
+
+        let c = config(&[("QUICHE_CR_JUMP_FACTOR", "0.75")]);
+        assert_eq!(c.cr_config().thresholds.jump_factor, 0.75);
+
+        let c = config(&[("QUICHE_CR_RETREAT_POLICY", "half_pipesize")]);
+        assert_eq!(
+            c.cr_config().retreat_policy,
+            crate::CrRetreatPolicy::HalfPipesize
+        );
+
+        // Thresholds are validated.
+        let c = config(&[("QUICHE_CR_JUMP_FACTOR", "2")]);
+        assert_eq!(
+            c.cr_config().thresholds,
+            crate::CrConfig::default().thresholds
+        );
+
+        // Thresholds are rejected along with Careful Resume being disabled.
+        let c = config(&[("QUICHE_CR", "off"), ("QUICHE_CR_JUMP_DIVISOR", "4")]);
+        assert!(c.cr_config().resume_enabled());
+        assert!(c.cr_observe);
+    }
+
+    #[cfg(feature = "qlog")]
+    #[test]
+    fn qlog_dir() {
+        assert_eq!(config(&[]).qlog_dir, None);
+
+        let dir = std::env::temp_dir()
+            .join(format!("quiche-env-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut c = config(&[("QUICHE_QLOG_DIR", dir.to_str().unwrap())]);
+        assert_eq!(c.qlog_dir.as_deref(), dir.to_str());
+
+        // Each connection writes its qlog to a new file.
+        let scid = crate::ConnectionId::from_ref(&[0xba; 16]);
+        let local = "127.0.0.1:4321".parse().unwrap();
+        let peer = "127.0.0.1:1234".parse().unwrap();
+        let conn = crate::accept(&scid, None, local, peer, &mut c).unwrap();
+
+        let path = dir.join(format!("server-{}.sqlog", conn.trace_id()));
+        assert!(path.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
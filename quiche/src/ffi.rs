@@ -225,6 +225,17 @@ pub extern fn quiche_config_enable_early_data(config: &mut Config) {
     config.enable_early_data();
 }
 
+#[no_mangle]
+pub extern fn quiche_config_set_chacha20_only(
+    config: &mut Config, v: bool,
+) -> c_int {
+    match config.set_chacha20_only(v) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
 #[no_mangle]
 /// Corresponds to the `Config::set_application_protos_wire_format` Rust
 /// function.
@@ -983,6 +994,25 @@ pub extern fn quiche_conn_close(
     }
 }
 
+#[no_mangle]
+pub extern fn quiche_conn_close_and_flush(
+    conn: &mut Connection, app: bool, err: u64, reason: *const u8,
+    reason_len: size_t,
+) -> c_int {
+    let reason = unsafe { slice::from_raw_parts(reason, reason_len) };
+
+    match conn.close_and_flush(app, err, reason) {
+        Ok(_) => 0,
+
+        Err(e) => e.to_c() as c_int,
+    }
+}
+
+#[no_mangle]
+pub extern fn quiche_conn_abandon_careful_resume(conn: &mut Connection) {
+    conn.abandon_careful_resume()
+}
+
 #[no_mangle]
 pub extern fn quiche_conn_timeout_as_nanos(conn: &Connection) -> u64 {
     match conn.timeout() {
@@ -1790,6 +1820,186 @@ pub extern fn quiche_path_event_free(ev: *mut PathEvent) {
     drop(unsafe { Box::from_raw(ev) });
 }
 
+#[repr(C)]
+pub struct CrParams {
+    min_rtt: u64,
+    cwnd: size_t,
+    pacing_rate: u64,
+    post_congestion: bool,
+    max_datagram_size: size_t,
+    loss_rate_ppm: u32,
+}
+
+impl From<&CREvent> for CrParams {
+    fn from(ev: &CREvent) -> Self {
+        CrParams {
+            min_rtt: ev.min_rtt.as_nanos() as u64,
+            cwnd: ev.cwnd,
+            pacing_rate: ev.pacing_rate,
+            post_congestion: ev.post_congestion,
+            max_datagram_size: ev.max_datagram_size,
+            loss_rate_ppm: ev.loss_rate_ppm,
+        }
+    }
+}
+
+impl From<&CrParams> for CREvent {
+    fn from(params: &CrParams) -> Self {
+        CREvent {
+            min_rtt: std::time::Duration::from_nanos(params.min_rtt),
+            cwnd: params.cwnd,
+            pacing_rate: params.pacing_rate,
+            post_congestion: params.post_congestion,
+            max_datagram_size: params.max_datagram_size,
+            loss_rate_ppm: params.loss_rate_ppm,
+        }
+    }
+}
+
+type CrStoreGet = extern fn(
+    key: *const u8,
+    key_len: size_t,
+    out: &mut CrParams,
+    argp: *mut c_void,
+) -> bool;
+
+type CrStorePut = extern fn(
+    key: *const u8,
+    key_len: size_t,
+    params: &CrParams,
+    argp: *mut c_void,
+);
+
+#[repr(C)]
+pub struct CrStoreCallbacks {
+    get: CrStoreGet,
+    put: CrStorePut,
+    argp: *mut c_void,
+}
+
+struct CallbackCrStore {
+    get: CrStoreGet,
+    put: CrStorePut,
+    argp: atomic::AtomicPtr<c_void>,
+}
+
+impl CrStore for CallbackCrStore {
+    fn get(&self, key: &str) -> Option<CREvent> {
+        let mut out = CrParams {
+            min_rtt: 0,
+            cwnd: 0,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        };
+
+        let argp = self.argp.load(atomic::Ordering::Relaxed);
+
+        if !(self.get)(key.as_ptr(), key.len(), &mut out, argp) {
+            return None;
+        }
+
+        Some((&out).into())
+    }
+
+    fn put(&self, key: &str, params: CREvent) {
+        let argp = self.argp.load(atomic::Ordering::Relaxed);
+
+        (self.put)(key.as_ptr(), key.len(), &(&params).into(), argp);
+    }
+}
+
+#[repr(C)]
+pub struct PacingPlan {
+    rate: u64,
+    send_quantum: size_t,
+}
+
+#[repr(C)]
+pub struct CrStats {
+    enabled: bool,
+    in_jump: bool,
+    has_params: bool,
+    params: CrParams,
+    phase_updated_count: u64,
+}
+
+#[repr(C)]
+pub struct ConnExtV1 {
+    version: u32,
+    config_set_qlog_metrics_sampling: extern fn(config: &mut Config, v: size_t),
+    config_set_cr_store:
+        extern fn(config: &mut Config, callbacks: &CrStoreCallbacks),
+    conn_pacing_plan: extern fn(conn: &Connection, out: &mut PacingPlan),
+    conn_cr_stats: extern fn(conn: &Connection, out: &mut CrStats),
+}
+
+/// The extension table returned for `QUICHE_CONN_EXT_V1`. Fields are never
+/// removed or reordered, new features go in a new version.
+static CONN_EXT_V1: ConnExtV1 = ConnExtV1 {
+    version: 1,
+    config_set_qlog_metrics_sampling: ext_config_set_qlog_metrics_sampling,
+    config_set_cr_store: ext_config_set_cr_store,
+    conn_pacing_plan: ext_conn_pacing_plan,
+    conn_cr_stats: ext_conn_cr_stats,
+};
+
+#[no_mangle]
+pub extern fn quiche_conn_ext(version: u32) -> *const c_void {
+    match version {
+        1 => &CONN_EXT_V1 as *const ConnExtV1 as *const c_void,
+
+        _ => ptr::null(),
+    }
+}
+
+#[cfg(feature = "qlog")]
+extern fn ext_config_set_qlog_metrics_sampling(config: &mut Config, v: size_t) {
+    config.set_qlog_metrics_sampling(v);
+}
+
+#[cfg(not(feature = "qlog"))]
+extern fn ext_config_set_qlog_metrics_sampling(_config: &mut Config, _v: size_t) {
+}
+
+extern fn ext_config_set_cr_store(
+    config: &mut Config, callbacks: &CrStoreCallbacks,
+) {
+    config.set_cr_store(std::sync::Arc::new(CallbackCrStore {
+        get: callbacks.get,
+        put: callbacks.put,
+        argp: atomic::AtomicPtr::new(callbacks.argp),
+    }));
+}
+
+extern fn ext_conn_pacing_plan(conn: &Connection, out: &mut PacingPlan) {
+    out.rate = conn.pacing_rate().unwrap_or(0);
+    out.send_quantum = conn.send_quantum();
+}
+
+extern fn ext_conn_cr_stats(conn: &Connection, out: &mut CrStats) {
+    let (enabled, in_jump, latest) = match conn.paths.get_active() {
+        Ok(path) => (
+            path.recovery.resume_enabled(),
+            path.recovery.resume_in_jump(),
+            path.latest_cr_event,
+        ),
+
+        Err(_) => (false, false, None),
+    };
+
+    out.enabled = enabled;
+    out.in_jump = in_jump;
+    out.has_params = latest.is_some();
+
+    if let Some(ev) = &latest {
+        out.params = ev.into();
+    }
+
+    out.phase_updated_count = conn.stats().qlog_cr_phase_updated_count;
+}
+
 #[no_mangle]
 pub extern fn quiche_put_varint(
     buf: *mut u8, buf_len: size_t, val: u64,
@@ -2021,6 +2231,8 @@ mod tests {
     #[cfg(windows)]
     use winapi::um::ws2tcpip::inet_ntop;
 
+    use std::sync::Mutex;
+
     #[test]
     fn addr_v4() {
         let addr = "127.0.0.1:8080".parse().unwrap();
@@ -2111,6 +2323,81 @@ mod tests {
         );
     }
 
+    #[test]
+    fn conn_ext() {
+        assert!(quiche_conn_ext(0).is_null());
+        assert!(quiche_conn_ext(2).is_null());
+
+        let ext = unsafe { &*(quiche_conn_ext(1) as *const ConnExtV1) };
+        assert_eq!(ext.version, 1);
+    }
+
+    #[test]
+    fn cr_store_callbacks() {
+        extern fn get(
+            key: *const u8, key_len: size_t, out: &mut CrParams,
+            argp: *mut c_void,
+        ) -> bool {
+            let stored = unsafe { &*(argp as *const Mutex<Option<CrParams>>) };
+            let key = unsafe { slice::from_raw_parts(key, key_len) };
+
+            match &*stored.lock().unwrap() {
+                Some(params) if key == b"quic.tech" => {
+                    out.min_rtt = params.min_rtt;
+                    out.cwnd = params.cwnd;
+                    out.pacing_rate = params.pacing_rate;
+                    out.post_congestion = params.post_congestion;
+                    out.max_datagram_size = params.max_datagram_size;
+                    out.loss_rate_ppm = params.loss_rate_ppm;
+
+                    true
+                },
+
+                _ => false,
+            }
+        }
+
+        extern fn put(
+            _key: *const u8, _key_len: size_t, params: &CrParams,
+            argp: *mut c_void,
+        ) {
+            let stored = unsafe { &*(argp as *const Mutex<Option<CrParams>>) };
+
+            *stored.lock().unwrap() = Some(CrParams {
+                min_rtt: params.min_rtt,
+                cwnd: params.cwnd,
+                pacing_rate: params.pacing_rate,
+                post_congestion: params.post_congestion,
+                max_datagram_size: params.max_datagram_size,
+                loss_rate_ppm: params.loss_rate_ppm,
+            });
+        }
+
+        let stored: Mutex<Option<CrParams>> = Mutex::new(None);
+
+        let store = CallbackCrStore {
+            get,
+            put,
+            argp: atomic::AtomicPtr::new(&stored as *const _ as *mut c_void),
+        };
+
+        let params = CREvent {
+            min_rtt: std::time::Duration::from_millis(600),
+            cwnd: 100_000,
+            pacing_rate: 6_250_000,
+            post_congestion: true,
+            max_datagram_size: 1350,
+            loss_rate_ppm: 2_500,
+        };
+
+        assert_eq!(store.get("quic.tech"), None);
+
+        store.put("quic.tech", params);
+
+        assert_eq!(store.get("quic.tech"), Some(params));
+        assert_eq!(store.get("example.com"), None);
+    }
+
     #[cfg(not(windows))]
     extern {
         fn inet_ntop(
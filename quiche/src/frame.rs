@@ -54,6 +54,12 @@ pub struct EcnCounts {
     ecn_ce_count: u64,
 }
 
+impl EcnCounts {
+    pub fn ecn_ce_count(&self) -> u64 {
+        self.ecn_ce_count
+    }
+}
+
 #[derive(Clone, PartialEq, Eq)]
 pub enum Frame {
     Padding {
@@ -184,6 +190,28 @@ pub enum Frame {
     DatagramHeader {
         length: usize,
     },
+
+    Bdp {
+        lifetime: u64,
+        saved_capacity: u64,
+        saved_rtt: u64,
+        token: Vec<u8>,
+    },
+
+    CrHints {
+        seq: u64,
+        lifetime: u64,
+        capacity: u64,
+        min_rtt: u64,
+        max_jump: u64,
+    },
+
+    ResetStreamAt {
+        stream_id: u64,
+        error_code: u64,
+        final_size: u64,
+        reliable_size: u64,
+    },
 }
 
 impl Frame {
@@ -329,8 +357,30 @@ impl Frame {
 
             0x1e => Frame::HandshakeDone,
 
+            0x24 => Frame::ResetStreamAt {
+                stream_id: b.get_varint()?,
+                error_code: b.get_varint()?,
+                final_size: b.get_varint()?,
+                reliable_size: b.get_varint()?,
+            },
+
             0x30 | 0x31 => parse_datagram_frame(frame_type, b)?,
 
+            0x1314 => Frame::Bdp {
+                lifetime: b.get_varint()?,
+                saved_capacity: b.get_varint()?,
+                saved_rtt: b.get_varint()?,
+                token: b.get_bytes_with_varint_length()?.to_vec(),
+            },
+
+            0x1315 => Frame::CrHints {
+                seq: b.get_varint()?,
+                lifetime: b.get_varint()?,
+                capacity: b.get_varint()?,
+                min_rtt: b.get_varint()?,
+                max_jump: b.get_varint()?,
+            },
+
             _ => return Err(Error::InvalidFrame),
         };
 
@@ -593,6 +643,51 @@ impl Frame {
             },
 
             Frame::DatagramHeader { .. } => (),
+
+            Frame::Bdp {
+                lifetime,
+                saved_capacity,
+                saved_rtt,
+                token,
+            } => {
+                b.put_varint(0x1314)?;
+
+                b.put_varint(*lifetime)?;
+                b.put_varint(*saved_capacity)?;
+                b.put_varint(*saved_rtt)?;
+                b.put_varint(token.len() as u64)?;
+                b.put_bytes(token.as_ref())?;
+            },
+
+            Frame::CrHints {
+                seq,
+                lifetime,
+                capacity,
+                min_rtt,
+                max_jump,
+            } => {
+                b.put_varint(0x1315)?;
+
+                b.put_varint(*seq)?;
+                b.put_varint(*lifetime)?;
+                b.put_varint(*capacity)?;
+                b.put_varint(*min_rtt)?;
+                b.put_varint(*max_jump)?;
+            },
+
+            Frame::ResetStreamAt {
+                stream_id,
+                error_code,
+                final_size,
+                reliable_size,
+            } => {
+                b.put_varint(0x24)?;
+
+                b.put_varint(*stream_id)?;
+                b.put_varint(*error_code)?;
+                b.put_varint(*final_size)?;
+                b.put_varint(*reliable_size)?;
+            },
         }
 
         Ok(before - b.cap())
@@ -808,6 +903,48 @@ impl Frame {
                 2 + // length, always encode as 2-byte varint
                 *length // data
             },
+
+            Frame::Bdp {
+                lifetime,
+                saved_capacity,
+                saved_rtt,
+                token,
+            } => {
+                octets::varint_len(0x1314) + // frame type
+                octets::varint_len(*lifetime) + // lifetime
+                octets::varint_len(*saved_capacity) + // saved_capacity
+                octets::varint_len(*saved_rtt) + // saved_rtt
+                octets::varint_len(token.len() as u64) + // token length
+                token.len() // token
+            },
+
+            Frame::CrHints {
+                seq,
+                lifetime,
+                capacity,
+                min_rtt,
+                max_jump,
+            } => {
+                octets::varint_len(0x1315) + // frame type
+                octets::varint_len(*seq) + // seq
+                octets::varint_len(*lifetime) + // lifetime
+                octets::varint_len(*capacity) + // capacity
+                octets::varint_len(*min_rtt) + // min_rtt
+                octets::varint_len(*max_jump) // max_jump
+            },
+
+            Frame::ResetStreamAt {
+                stream_id,
+                error_code,
+                final_size,
+                reliable_size,
+            } => {
+                octets::varint_len(0x24) + // frame type
+                octets::varint_len(*stream_id) + // stream_id
+                octets::varint_len(*error_code) + // error_code
+                octets::varint_len(*final_size) + // final_size
+                octets::varint_len(*reliable_size) // reliable_size
+            },
         }
     }
 
@@ -1032,6 +1169,24 @@ impl Frame {
                 length: *length as u64,
                 raw: None,
             },
+
+            Frame::Bdp { .. } => QuicFrame::Unknown {
+                raw_frame_type: 0x1314,
+                frame_type_value: None,
+                raw: None,
+            },
+
+            Frame::CrHints { .. } => QuicFrame::Unknown {
+                raw_frame_type: 0x1315,
+                frame_type_value: None,
+                raw: None,
+            },
+
+            Frame::ResetStreamAt { .. } => QuicFrame::Unknown {
+                raw_frame_type: 0x24,
+                frame_type_value: None,
+                raw: None,
+            },
         }
     }
 }
@@ -1199,6 +1354,43 @@ impl std::fmt::Debug for Frame {
             Frame::DatagramHeader { length } => {
                 write!(f, "DATAGRAM len={length}")?;
             },
+
+            Frame::Bdp {
+                lifetime,
+                saved_capacity,
+                saved_rtt,
+                token,
+            } => {
+                write!(
+                    f,
+                    "BDP lifetime={lifetime} saved_capacity={saved_capacity} saved_rtt={saved_rtt} token={token:02x?}"
+                )?;
+            },
+
+            Frame::CrHints {
+                seq,
+                lifetime,
+                capacity,
+                min_rtt,
+                max_jump,
+            } => {
+                write!(
+                    f,
+                    "CR_HINTS seq={seq} lifetime={lifetime} capacity={capacity} min_rtt={min_rtt} max_jump={max_jump}"
+                )?;
+            },
+
+            Frame::ResetStreamAt {
+                stream_id,
+                error_code,
+                final_size,
+                reliable_size,
+            } => {
+                write!(
+                    f,
+                    "RESET_STREAM_AT stream={stream_id} err={error_code:x} size={final_size} reliable={reliable_size}"
+                )?;
+            },
         }
 
         Ok(())
@@ -1525,6 +1717,38 @@ mod tests {
         assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
     }
 
+    #[test]
+    fn reset_stream_at() {
+        let mut d = [42; 128];
+
+        let frame = Frame::ResetStreamAt {
+            stream_id: 123_213,
+            error_code: 21_123_767,
+            final_size: 21_123_767,
+            reliable_size: 1_000,
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 15);
+        assert_eq!(wire_len, frame.wire_len());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
     #[test]
     fn stop_sending() {
         let mut d = [42; 128];
@@ -2118,4 +2342,69 @@ mod tests {
 
         assert_eq!(frame_data, data);
     }
+
+    #[test]
+    fn bdp() {
+        let mut d = [42; 128];
+
+        let frame = Frame::Bdp {
+            lifetime: 86400,
+            saved_capacity: 1_200_000,
+            saved_rtt: 50_000,
+            token: vec![0xba; 48],
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 63);
+        assert_eq!(wire_len, frame.wire_len());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
+
+    #[test]
+    fn cr_hints() {
+        let mut d = [42; 128];
+
+        let frame = Frame::CrHints {
+            seq: 3,
+            lifetime: 3600,
+            capacity: 1_200_000,
+            min_rtt: 50_000,
+            max_jump: 0,
+        };
+
+        let wire_len = {
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            frame.to_bytes(&mut b).unwrap()
+        };
+
+        assert_eq!(wire_len, 14);
+        assert_eq!(wire_len, frame.wire_len());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert_eq!(Frame::from_bytes(&mut b, packet::Type::Short), Ok(frame));
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::ZeroRTT).is_ok());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Initial).is_err());
+
+        let mut b = octets::Octets::with_slice(&d);
+        assert!(Frame::from_bytes(&mut b, packet::Type::Handshake).is_err());
+    }
 }
@@ -249,8 +249,14 @@
 //! socket option on Linux), or custom methods (for example by using user-space
 //! timers).
 //!
+//! Alternatively, the current pacing rate returned by [`pacing_rate()`] can
+//! be handed over to the kernel with the [`SO_MAX_PACING_RATE`] socket option,
+//! so that the Linux `fq` queueing discipline spreads packets itself.
+//!
 //! [pace]: https://datatracker.ietf.org/doc/html/rfc9002#section-7.7
 //! [`SO_TXTIME`]: https://man7.org/linux/man-pages/man8/tc-etf.8.html
+//! [`pacing_rate()`]: struct.Connection.html#method.pacing_rate
+//! [`SO_MAX_PACING_RATE`]: https://man7.org/linux/man-pages/man8/tc-fq.8.html
 //!
 //! ## Sending and receiving stream data
 //!
@@ -391,6 +397,12 @@ use qlog::events::quic::RecoveryEventType;
 #[cfg(feature = "qlog")]
 use qlog::events::quic::TransportEventType;
 #[cfg(feature = "qlog")]
+use qlog::events::resume::CarefulResumeParametersRestored;
+#[cfg(feature = "qlog")]
+use qlog::events::resume::CarefulResumeParametersStored;
+#[cfg(feature = "qlog")]
+use qlog::events::resume::PeerResumeSuspected;
+#[cfg(feature = "qlog")]
 use qlog::events::DataRecipient;
 #[cfg(feature = "qlog")]
 use qlog::events::Event;
@@ -423,8 +435,11 @@ use smallvec::SmallVec;
 /// The current QUIC wire version.
 pub const PROTOCOL_VERSION: u32 = PROTOCOL_VERSION_V1;
 
-/// Supported QUIC versions.
-const PROTOCOL_VERSION_V1: u32 = 0x0000_0001;
+/// QUIC version 1, as defined in RFC 9000.
+pub const PROTOCOL_VERSION_V1: u32 = 0x0000_0001;
+
+/// QUIC version 2, as defined in RFC 9369.
+pub const PROTOCOL_VERSION_V2: u32 = 0x6b33_43cf;
 
 /// The maximum length of a connection ID.
 pub const MAX_CONN_ID_LEN: usize = crate::packet::MAX_CID_LEN as usize;
@@ -432,6 +447,11 @@ pub const MAX_CONN_ID_LEN: usize = crate::packet::MAX_CID_LEN as usize;
 /// The minimum length of Initial packets sent by a client.
 pub const MIN_CLIENT_INITIAL_LEN: usize = 1200;
 
+/// The maximum length of a Careful Resume blob echoed by a client.
+///
+/// Blobs sealed by quiche are at most 119 bytes long.
+pub const MAX_RESUME_BLOB_LEN: usize = 128;
+
 #[cfg(not(feature = "fuzzing"))]
 const PAYLOAD_MIN_LEN: usize = 4;
 
@@ -495,6 +515,9 @@ const DEFAULT_INITIAL_CONGESTION_WINDOW_PACKETS: usize = 10;
 // The maximum data offset that can be stored in a crypto stream.
 const MAX_CRYPTO_STREAM_OFFSET: u64 = 1 << 16;
 
+// The number of Careful Resume events kept until the application reads them.
+const MAX_PENDING_CR_EVENTS: usize = 16;
+
 /// A specialized [`Result`] type for quiche operations.
 ///
 /// This type is used throughout quiche's public API for any operation that
@@ -642,6 +665,9 @@ pub enum WireErrorCode {
     /// CONNECTION_CLOSE frame carrying this code except when the path does
     /// not support a large enough MTU.
     NoViablePath         = 0x10,
+    /// An endpoint detected a version downgrade, or failed to agree on a
+    /// version with its peer.
+    VersionNegotiationError = 0x11,
 }
 
 impl Error {
@@ -660,6 +686,8 @@ impl Error {
             Error::CryptoBufferExceeded =>
                 WireErrorCode::CryptoBufferExceeded as u64,
             Error::KeyUpdate => WireErrorCode::KeyUpdateError as u64,
+            Error::UnknownVersion =>
+                WireErrorCode::VersionNegotiationError as u64,
             _ => WireErrorCode::ProtocolViolation as u64,
         }
     }
@@ -787,6 +815,8 @@ pub struct Config {
 
     tls_ctx: tls::Context,
 
+    chacha20_only: bool,
+
     application_protos: Vec<Vec<u8>>,
 
     grease: bool,
@@ -796,13 +826,9 @@ pub struct Config {
 
     pmtud: bool,
 
-    hystart: bool,
-
-    pacing: bool,
+    ack_aggregation_compensation: bool,
 
-    resume: bool,
-
-    max_pacing_rate: Option<u64>,
+    cr: CrConfig,
 
     dgram_recv_max_queue_len: usize,
     dgram_send_max_queue_len: usize,
@@ -817,6 +843,32 @@ pub struct Config {
     max_amplification_factor: usize,
 
     disable_dcid_reuse: bool,
+
+    resume_blob_key: Option<Arc<resume_blob::BlobKey>>,
+    resume_blob_max_age: Duration,
+    resume_new_token: bool,
+
+    cr_store: Option<Arc<dyn CrStore>>,
+
+    cr_path_cache: Option<Arc<CrPathCache>>,
+
+    cr_hints_handler: Option<Arc<dyn CrHintsHandler>>,
+
+    cr_params_policy: Option<Arc<dyn CrParamsPolicy>>,
+
+    cr_phase_observer: Option<Arc<dyn CrPhaseObserver>>,
+
+    // Whether the Careful Resume parameters observed on paths are recorded,
+    // only cleared by the `env-config` feature.
+    cr_observe: bool,
+
+    #[cfg(feature = "qlog")]
+    qlog_metrics_sampling: usize,
+
+    #[cfg(all(feature = "env-config", feature = "qlog"))]
+    qlog_dir: Option<String>,
+
+    available_versions: Vec<u32>,
 }
 
 // See https://quicwg.org/base-drafts/rfc9000.html#section-15
@@ -827,12 +879,40 @@ fn is_reserved_version(version: u32) -> bool {
 impl Config {
     /// Creates a config object with the given version.
     ///
+    /// With the `env-config` feature, the defaults of some settings are
+    /// replaced by environment variables, so that binaries embedding quiche
+    /// can be experimented with without rebuilding them. Settings changed on
+    /// the config afterwards take precedence over them:
+    ///
+    /// * `QUICHE_QLOG_DIR`: the directory a qlog file is written to for each
+    ///   connection, named after its role and trace ID. Requires the `qlog`
+    ///   feature.
+    /// * `QUICHE_CR`: `on` enables Careful Resume, `shadow` disables it but
+    ///   still records the parameters it would use, see [`cr_events()`] and
+    ///   [`set_cr_store()`], and `off` disables both.
+    /// * `QUICHE_CR_MIN_RTTS_BEFORE_JUMP`, `QUICHE_CR_JUMP_DIVISOR`,
+    ///   `QUICHE_CR_JUMP_FACTOR` and `QUICHE_CR_MIN_PREVIOUS_CWND`: override
+    ///   the [`CrConfig`] setting of the same name.
+    /// * `QUICHE_CR_PLAUSIBILITY_FACTOR` and `QUICHE_CR_RETREAT_POLICY`:
+    ///   override the [`CrConfig`] setting of the same name, the policy in its
+    ///   string form.
+    /// * `QUICHE_CR_RTT_BOUNDS`: overrides the [`CrConfig`] RTT bounds, as
+    ///   `min_ratio,max_ratio`.
+    ///
+    /// Invalid values are logged and ignored. The Careful Resume settings are
+    /// validated together, e.g. thresholds set along with `QUICHE_CR=off` are
+    /// all ignored.
+    ///
     /// ## Examples:
     ///
     /// ```
     /// let config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # Ok::<(), quiche::Error>(())
     /// ```
+    ///
+    /// [`cr_events()`]: struct.Connection.html#method.cr_events
+    /// [`set_cr_store()`]: struct.Config.html#method.set_cr_store
+    /// [`CrConfig`]: struct.CrConfig.html
     pub fn new(version: u32) -> Result<Config> {
         Self::with_tls_ctx(version, tls::Context::new()?)
     }
@@ -857,20 +937,20 @@ impl Config {
             return Err(Error::UnknownVersion);
         }
 
-        Ok(Config {
+        #[allow(unused_mut)]
+        let mut config = Config {
             local_transport_params: TransportParams::default(),
             version,
             tls_ctx,
+            chacha20_only: false,
             application_protos: Vec::new(),
             grease: true,
             cc_algorithm: CongestionControlAlgorithm::CUBIC,
             initial_congestion_window_packets:
                 DEFAULT_INITIAL_CONGESTION_WINDOW_PACKETS,
             pmtud: false,
-            hystart: true,
-            pacing: true,
-            resume: true,
-            max_pacing_rate: None,
+            ack_aggregation_compensation: false,
+            cr: CrConfig::default(),
 
             dgram_recv_max_queue_len: DEFAULT_MAX_DGRAM_QUEUE_LEN,
             dgram_send_max_queue_len: DEFAULT_MAX_DGRAM_QUEUE_LEN,
@@ -886,7 +966,36 @@ impl Config {
             max_amplification_factor: MAX_AMPLIFICATION_FACTOR,
 
             disable_dcid_reuse: false,
-        })
+
+            resume_blob_key: None,
+            resume_blob_max_age: resume_blob::DEFAULT_MAX_AGE,
+            resume_new_token: false,
+
+            cr_store: None,
+
+            cr_path_cache: None,
+
+            cr_hints_handler: None,
+
+            cr_params_policy: None,
+
+            cr_phase_observer: None,
+
+            cr_observe: true,
+
+            #[cfg(feature = "qlog")]
+            qlog_metrics_sampling: 1,
+
+            #[cfg(all(feature = "env-config", feature = "qlog"))]
+            qlog_dir: None,
+
+            available_versions: vec![PROTOCOL_VERSION_V1, PROTOCOL_VERSION_V2],
+        };
+
+        #[cfg(feature = "env-config")]
+        env_config::apply(&mut config);
+
+        Ok(config)
     }
 
     /// Configures the given certificate chain.
@@ -1015,6 +1124,26 @@ impl Config {
         self.tls_ctx.set_early_data_enabled(true);
     }
 
+    /// Configures whether 1-RTT packets are only protected with
+    /// ChaCha20-Poly1305.
+    ///
+    /// This avoids AES-GCM on hosts without AES hardware support, where it
+    /// can limit the throughput of a connection. Handshakes that negotiate
+    /// another cipher fail.
+    ///
+    /// With OpenSSL (quictls), this restricts the TLS 1.3 cipher suites that
+    /// are offered and accepted. BoringSSL doesn't support configuring them,
+    /// but already prefers ChaCha20-Poly1305 on hosts without AES hardware
+    /// support, so only the negotiated cipher is checked.
+    ///
+    /// The default value is `false`.
+    pub fn set_chacha20_only(&mut self, v: bool) -> Result<()> {
+        self.tls_ctx.set_chacha20_only(v)?;
+        self.chacha20_only = v;
+
+        Ok(())
+    }
+
     /// Configures the list of supported application protocols.
     ///
     /// On the client this configures the list of protocols to send to the
@@ -1273,272 +1402,1090 @@ impl Config {
     ///
     /// The default value is `true`.
     pub fn enable_hystart(&mut self, v: bool) {
-        self.hystart = v;
+        self.cr.enable_hystart(v);
+    }
+
+    /// Configures whether to compensate for ACK aggregation.
+    ///
+    /// When ACKs arrive in bunches, acknowledging data faster than it was
+    /// sent, the excess bytes are not used to grow the congestion window of
+    /// Reno and CUBIC, nor counted in the pipesize measured by Careful
+    /// Resume. Aggregation is detected and reported in [`PathStats`]
+    /// regardless.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`PathStats`]: struct.PathStats.html
+    pub fn enable_ack_aggregation_compensation(&mut self, v: bool) {
+        self.ack_aggregation_compensation = v;
     }
 
     /// Configures whether to enable Careful Resume.
     ///
     /// The default value is `true`.
     pub fn enable_resume(&mut self, v: bool) {
-        self.resume = v;
+        self.cr.enable_resume(v);
     }
-    /// Configures whether to enable pacing.
+
+    /// Sets the Careful Resume settings, along with the congestion control
+    /// settings they interact with.
     ///
-    /// The default value is `true`.
-    pub fn enable_pacing(&mut self, v: bool) {
-        self.pacing = v;
+    /// Unlike the individual setters such as [`enable_resume()`] and
+    /// [`enable_pacing()`], the settings are validated against each other and
+    /// against the congestion control algorithm currently configured, and
+    /// left unchanged if they are incompatible. The algorithm should therefore
+    /// be set first.
+    ///
+    /// [`enable_resume()`]: struct.Config.html#method.enable_resume
+    /// [`enable_pacing()`]: struct.Config.html#method.enable_pacing
+    pub fn set_cr_config(
+        &mut self, cr: CrConfig,
+    ) -> std::result::Result<(), CrConfigError> {
+        cr.validate(self.cc_algorithm)?;
+
+        self.cr = cr;
+
+        Ok(())
     }
 
-    /// Sets the max value for pacing rate.
+    /// Returns the Careful Resume settings.
     ///
-    /// By default pacing rate is not limited.
-    pub fn set_max_pacing_rate(&mut self, v: u64) {
-        self.max_pacing_rate = Some(v);
+    /// This can be used as a starting point for [`set_cr_config()`].
+    ///
+    /// [`set_cr_config()`]: struct.Config.html#method.set_cr_config
+    pub fn cr_config(&self) -> &CrConfig {
+        &self.cr
     }
 
-    /// Configures whether to enable receiving DATAGRAM frames.
+    /// Applies `f` to the Careful Resume settings, and validates them as
+    /// [`set_cr_config()`] does.
     ///
-    /// When enabled, the `max_datagram_frame_size` transport parameter is set
-    /// to 65536 as recommended by draft-ietf-quic-datagram-01.
+    /// The settings are left unchanged if the change makes them invalid.
+    /// Errors the settings already had are not returned, as they are caused
+    /// by other settings, e.g. pacing being disabled before Careful Resume
+    /// is.
     ///
-    /// The default is `false`.
-    pub fn enable_dgram(
-        &mut self, enabled: bool, recv_queue_len: usize, send_queue_len: usize,
-    ) {
-        self.local_transport_params.max_datagram_frame_size = if enabled {
-            Some(MAX_DGRAM_FRAME_SIZE)
-        } else {
-            None
-        };
-        self.dgram_recv_max_queue_len = recv_queue_len;
-        self.dgram_send_max_queue_len = send_queue_len;
+    /// [`set_cr_config()`]: struct.Config.html#method.set_cr_config
+    fn update_cr(
+        &mut self, f: impl FnOnce(&mut CrConfig),
+    ) -> std::result::Result<(), CrConfigError> {
+        let mut cr = self.cr.clone();
+        f(&mut cr);
+
+        if let Err(e) = cr.validate(self.cc_algorithm) {
+            if self.cr.validate(self.cc_algorithm) != Err(e) {
+                return Err(e);
+            }
+        }
+
+        self.cr = cr;
+
+        Ok(())
     }
 
-    /// Configures the max number of queued received PATH_CHALLENGE frames.
+    /// Applies preset Careful Resume settings suited to a kind of path, e.g.
+    /// `CrProfile::Geo` for geostationary satellite paths.
     ///
-    /// When an endpoint receives a PATH_CHALLENGE frame and the queue is full,
-    /// the frame is discarded.
+    /// The profile sets the RTT bounds, the jump factor and the unvalidated
+    /// phase timeouts, see [`CrConfig::set_profile()`]. Settings changed
+    /// afterwards override those of the profile.
     ///
-    /// The default is 3.
-    pub fn set_path_challenge_recv_max_queue_len(&mut self, queue_len: usize) {
-        self.path_challenge_recv_max_queue_len = queue_len;
+    /// The default value is `CrProfile::Default`.
+    ///
+    /// [`CrConfig::set_profile()`]: struct.CrConfig.html#method.set_profile
+    pub fn set_cr_profile(&mut self, profile: CrProfile) {
+        self.cr.set_profile(profile);
     }
 
-    /// Sets the maximum size of the connection window.
+    /// Applies preset Careful Resume settings suited to a kind of path by
+    /// string.
     ///
-    /// The default value is MAX_CONNECTION_WINDOW (24MBytes).
-    pub fn set_max_connection_window(&mut self, v: u64) {
-        self.max_connection_window = v;
+    /// The default value is `default`. On error `Error::CongestionControl`
+    /// will be returned.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_cr_profile_name("geo")?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_cr_profile_name(&mut self, name: &str) -> Result<()> {
+        self.cr.set_profile(CrProfile::from_str(name)?);
+
+        Ok(())
     }
 
-    /// Sets the maximum size of the stream window.
+    /// Configures whether the pipesize reached when leaving the Careful Resume
+    /// safe retreat phase is reported as a post-congestion [`CREvent`].
     ///
-    /// The default value is MAX_STREAM_WINDOW (16MBytes).
-    pub fn set_max_stream_window(&mut self, v: u64) {
-        self.max_stream_window = v;
+    /// After a retreat, this is a better estimate of the path capacity than
+    /// the congestion window used for the jump, so storing it lets the next
+    /// connection jump to a more realistic value.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`CREvent`]: struct.CREvent.html
+    pub fn enable_resume_retreat_export(&mut self, v: bool) {
+        self.cr.enable_retreat_export(v);
     }
 
-    /// Sets the initial stateless reset token.
+    /// Sets the minimum number of RTT samples Careful Resume collects during
+    /// the reconnaissance phase before jumping.
     ///
-    /// This value is only advertised by servers. Setting a stateless retry
-    /// token as a client has no effect on the connection.
+    /// Waiting for more samples delays the jump, but makes the comparison
+    /// with the RTT of the previous connection more reliable on paths with
+    /// jittery RTTs. A value of 0 is treated as 1.
     ///
-    /// The default value is `None`.
-    pub fn set_stateless_reset_token(&mut self, v: Option<u128>) {
-        self.local_transport_params.stateless_reset_token = v;
+    /// The default value is `1`.
+    pub fn set_cr_min_rtts_before_jump(&mut self, v: usize) {
+        self.cr.set_min_rtts_before_jump(v);
     }
 
-    /// Sets whether the QUIC connection should avoid reusing DCIDs over
-    /// different paths.
+    /// Sets how long the Careful Resume reconnaissance phase may last before
+    /// Careful Resume is abandoned.
     ///
-    /// When set to `true`, it ensures that a destination Connection ID is never
-    /// reused on different paths. Such behaviour may lead to connection stall
-    /// if the peer performs a non-voluntary migration (e.g., NAT rebinding) and
-    /// does not provide additional destination Connection IDs to handle such
-    /// event.
+    /// The timeout starts when the first application packet is sent. The
+    /// `app_limited` qlog trigger is used when the sender was app-limited at
+    /// the time, i.e. the application didn't send enough data to jump, and
+    /// `timeout` otherwise. A value of 0 lets reconnaissance last until the
+    /// jump conditions are met.
     ///
-    /// The default value is `false`.
-    pub fn set_disable_dcid_reuse(&mut self, v: bool) {
-        self.disable_dcid_reuse = v;
+    /// The default value is 0.
+    pub fn set_cr_reconnaissance_timeout(&mut self, v: Duration) {
+        self.cr.set_reconnaissance_timeout(v);
     }
 
-    /// Configures advertising that the server is sending BDP tokens in its NEW_TOKEN frames
+    /// Sets how long the sender may have nothing in flight during the
+    /// Careful Resume reconnaissance phase before its RTT sample is
+    /// considered stale.
     ///
-    /// The default value is `false`.
-    pub fn set_bdp_tokens(&mut self, v: bool) {
-        self.local_transport_params.bdp_tokens = v;
+    /// When the sender resumes sending after a longer idle period, the RTT
+    /// may have changed since it was sampled, so reconnaissance is run again:
+    /// the jump waits for an RTT sample of the packets sent after the idle
+    /// period, and for the RTT to be compared with the RTT of the previous
+    /// connection once more. The reconnaissance timeout restarts too. A
+    /// value of 0 uses 8 times the RTT of the previous connection.
+    ///
+    /// The default value is 0.
+    pub fn set_cr_idle_threshold(&mut self, v: Duration) {
+        self.cr.set_idle_threshold(v);
     }
-}
-
-/// A QUIC connection.
-pub struct Connection {
-    /// QUIC wire version used for the connection.
-    version: u32,
-
-    /// Connection Identifiers.
-    ids: cid::ConnectionIdentifiers,
 
-    /// Unique opaque ID for the connection that can be used for logging.
-    trace_id: String,
+    /// Sets how close the RTT must be to the RTT of the previous connection
+    /// for Careful Resume to jump, as ratios of the previous RTT.
+    ///
+    /// The RTT must be above `min_ratio` and below `max_ratio` times the
+    /// previous RTT. The same bounds apply to parameters provided by the
+    /// peer, compared with the handshake RTT. The bounds are validated as by
+    /// [`set_cr_config()`], and left unchanged if they are invalid.
+    ///
+    /// The default values are `0.5` and `10.0`.
+    ///
+    /// [`set_cr_config()`]: struct.Config.html#method.set_cr_config
+    pub fn set_cr_rtt_bounds(
+        &mut self, min_ratio: f64, max_ratio: f64,
+    ) -> std::result::Result<(), CrConfigError> {
+        self.update_cr(|cr| cr.set_rtt_bounds(min_ratio, max_ratio))
+    }
 
-    /// Packet number spaces.
-    pkt_num_spaces: [packet::PktNumSpace; packet::Epoch::count()],
+    /// Sets the divisor applied to the previous congestion window to get the
+    /// congestion window Careful Resume jumps to. A value of 0 is treated as
+    /// 1.
+    ///
+    /// The default value is `2`.
+    pub fn set_cr_jump_divisor(&mut self, v: usize) {
+        self.cr.set_jump_divisor(v);
+    }
 
-    /// Peer's transport parameters.
-    peer_transport_params: TransportParams,
+    /// Sets the fraction of the previous congestion window that Careful
+    /// Resume jumps to, see [`CrConfig::set_jump_factor()`]. The value is
+    /// validated as by [`set_cr_config()`], and left unchanged if it is
+    /// invalid.
+    ///
+    /// The default value is `0.5`.
+    ///
+    /// [`CrConfig::set_jump_factor()`]: struct.CrConfig.html#method.set_jump_factor
+    /// [`set_cr_config()`]: struct.Config.html#method.set_cr_config
+    pub fn set_cr_jump_factor(
+        &mut self, v: f64,
+    ) -> std::result::Result<(), CrConfigError> {
+        self.update_cr(|cr| cr.set_jump_factor(v))
+    }
 
-    /// Local transport parameters.
-    local_transport_params: TransportParams,
+    /// Sets the smallest previous congestion window, as a multiple of the
+    /// initial congestion window, that Careful Resume attempts to jump from.
+    ///
+    /// The default value is `0`, i.e. the jump is only skipped when it
+    /// wouldn't grow the congestion window.
+    pub fn set_cr_min_previous_cwnd(&mut self, iw_multiple: usize) {
+        self.cr.set_min_previous_cwnd(iw_multiple);
+    }
 
-    /// TLS handshake state.
-    handshake: tls::Handshake,
+    /// Caps the Careful Resume jump at a multiple of the capacity estimated
+    /// during reconnaissance, see [`CrConfig::set_plausibility_factor()`].
+    /// The value is validated as by [`set_cr_config()`], and left unchanged
+    /// if it is invalid.
+    ///
+    /// The default value is `0`, i.e. the jump isn't capped.
+    ///
+    /// [`CrConfig::set_plausibility_factor()`]: struct.CrConfig.html#method.set_plausibility_factor
+    /// [`set_cr_config()`]: struct.Config.html#method.set_cr_config
+    pub fn set_cr_plausibility_factor(
+        &mut self, v: f64,
+    ) -> std::result::Result<(), CrConfigError> {
+        self.update_cr(|cr| cr.set_plausibility_factor(v))
+    }
 
-    /// Serialized TLS session buffer.
+    /// Sets when the Careful Resume parameters observed on a path are
+    /// reported as [`CREvent`]s.
     ///
-    /// This field is populated when a new session ticket is processed on the
-    /// client. On the server this is empty.
-    session: Option<Vec<u8>>,
+    /// With `CrObservePolicy::SignificantChange` a report is made when the
+    /// min RTT or the congestion window changed significantly since the
+    /// previous one, and with `CrObservePolicy::Periodic` once per
+    /// [`set_cr_observe_max_gap()`] interval.
+    ///
+    /// The default value is `CrObservePolicy::SignificantChange`.
+    ///
+    /// [`CREvent`]: struct.CREvent.html
+    /// [`set_cr_observe_max_gap()`]: struct.Config.html#method.set_cr_observe_max_gap
+    pub fn set_cr_observe_policy(&mut self, v: CrObservePolicy) {
+        self.cr.set_observe_policy(v);
+    }
 
-    /// The configuration for recovery.
-    recovery_config: recovery::RecoveryConfig,
+    /// Sets when the Careful Resume observed parameters are reported by
+    /// string.
+    ///
+    /// The default value is `significant_change`. On error
+    /// `Error::CongestionControl` will be returned.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_cr_observe_policy_name("periodic")?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_cr_observe_policy_name(&mut self, name: &str) -> Result<()> {
+        self.cr.set_observe_policy(CrObservePolicy::from_str(name)?);
 
-    /// The path manager.
-    paths: path::PathMap,
+        Ok(())
+    }
 
-    /// PATH_CHALLENGE receive queue max length.
-    path_challenge_recv_max_queue_len: usize,
+    /// Sets the longest time between two reports of the Careful Resume
+    /// observed parameters. A value of 0 is rejected, and leaves the
+    /// setting unchanged.
+    ///
+    /// The default value is 60 seconds.
+    pub fn set_cr_observe_max_gap(
+        &mut self, v: Duration,
+    ) -> std::result::Result<(), CrConfigError> {
+        self.update_cr(|cr| cr.set_observe_max_gap(v))
+    }
 
-    /// Total number of received PATH_CHALLENGE frames.
-    path_challenge_rx_count: u64,
+    /// Sets the smallest congestion window, as a multiple of the initial
+    /// congestion window, that is reported as a Careful Resume observed
+    /// parameter.
+    ///
+    /// The default value is `4`.
+    pub fn set_cr_observe_min_cwnd(&mut self, iw_multiple: usize) {
+        self.cr.set_observe_min_cwnd(iw_multiple);
+    }
 
-    /// List of supported application protocols.
-    application_protos: Vec<Vec<u8>>,
+    /// Sets the relative changes of the min RTT and of the congestion window
+    /// that trigger a report of the Careful Resume observed parameters one
+    /// second after the previous report.
+    ///
+    /// The change needed is inversely proportional to the time since the
+    /// previous report. Lower values report more often. Values that are not
+    /// positive are rejected, and leave the thresholds unchanged.
+    ///
+    /// The default values are `1.0` and `1.0`.
+    pub fn set_cr_observe_change_thresholds(
+        &mut self, rtt: f64, cwnd: f64,
+    ) -> std::result::Result<(), CrConfigError> {
+        self.update_cr(|cr| cr.set_observe_change_thresholds(rtt, cwnd))
+    }
 
-    /// Total number of received packets.
-    recv_count: usize,
+    /// Sets how long the Careful Resume unvalidated phase may last.
+    ///
+    /// When the jumped window isn't acknowledged within the timeout, Careful
+    /// Resume enters the safe retreat phase as it would on a loss, with the
+    /// `timeout` qlog trigger. A value of 0 lets the phase last until the
+    /// jumped window is acknowledged or lost.
+    ///
+    /// The default value is 0.
+    pub fn set_cr_unvalidated_timeout(&mut self, v: Duration) {
+        self.cr.set_unvalidated_timeout(v);
+    }
 
-    /// Total number of sent packets.
-    sent_count: usize,
+    /// Sets how long the Careful Resume unvalidated phase may last, as a
+    /// multiple of the RTT measured when jumping.
+    ///
+    /// The Careful Resume draft expects the jumped window to be acknowledged
+    /// within a small number of RTTs. When it isn't, Careful Resume enters
+    /// the safe retreat phase as it would on a loss, halving the pipesize,
+    /// with the `timeout` qlog trigger. When [`set_cr_unvalidated_timeout()`]
+    /// is also set, the earliest of the two applies. A value of 0 doesn't
+    /// bound the phase in RTTs.
+    ///
+    /// The default value is 0.
+    ///
+    /// [`set_cr_unvalidated_timeout()`]: struct.Config.html#method.set_cr_unvalidated_timeout
+    pub fn set_cr_unvalidated_timeout_rtts(&mut self, v: u32) {
+        self.cr.set_unvalidated_timeout_rtts(v);
+    }
 
-    /// Total number of lost packets.
-    lost_count: usize,
+    /// Sets how long the Careful Resume validating phase may last.
+    ///
+    /// When the validating phase doesn't complete within the timeout,
+    /// Careful Resume ends with the congestion window reached so far, with
+    /// the `timeout` qlog trigger. A value of 0 lets the phase last until the
+    /// validated window is acknowledged or lost.
+    ///
+    /// The default value is 0.
+    pub fn set_cr_validating_timeout(&mut self, v: Duration) {
+        self.cr.set_validating_timeout(v);
+    }
 
-    /// Total number of packets sent with data retransmitted.
-    retrans_count: usize,
+    /// Sets the largest number of packets sent in a burst during the Careful
+    /// Resume unvalidated phase.
+    ///
+    /// The jump can open tens of packets of congestion window at once, which
+    /// applications using segmentation offload would send in a single burst.
+    /// With a limit set, [`send()`] returns [`Done`] once a burst reached it,
+    /// until the time it takes to send it at the rate of the congestion
+    /// window has passed, which is reported by [`timeout()`]. The limit
+    /// applies whether or not the application paces packets, and
+    /// [`send_quantum()`] doesn't exceed it during the phase. A value of 0
+    /// doesn't limit bursts.
+    ///
+    /// The default value is 0.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`timeout()`]: struct.Connection.html#method.timeout
+    /// [`send_quantum()`]: struct.Connection.html#method.send_quantum
+    pub fn set_cr_max_unvalidated_burst(&mut self, packets: usize) {
+        self.cr.set_max_unvalidated_burst(packets);
+    }
 
-    /// Total number of bytes received from the peer.
-    rx_data: u64,
+    /// Configures whether Careful Resume on a client sending 0-RTT data may
+    /// jump as soon as the handshake completes.
+    ///
+    /// Packets carrying 0-RTT data are sent before any RTT sample is taken,
+    /// and are part of the initial window, so Careful Resume normally waits
+    /// for them to be acknowledged after the handshake. If the server
+    /// rejects the 0-RTT data they are never acknowledged, and their loss
+    /// also ends Careful Resume.
+    ///
+    /// When enabled, the RTT stored with the session, as set by
+    /// [`Connection::set_initial_rtt()`], is compared with the previous RTT
+    /// while 0-RTT data is sent, so that Careful Resume is abandoned early
+    /// if they diverge. The jump can then be made as soon as the handshake
+    /// completes, once the RTT measured during the handshake is confirmed to
+    /// be similar, and the loss of packets sent before the handshake
+    /// completed, e.g. because 0-RTT data was rejected, isn't handled as
+    /// congestion by Careful Resume.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`Connection::set_initial_rtt()`]: struct.Connection.html#method.set_initial_rtt
+    pub fn enable_cr_early_data(&mut self, v: bool) {
+        self.cr.enable_early_data(v);
+    }
 
-    /// Receiver flow controller.
-    flow_control: flowcontrol::FlowControl,
+    /// Limits how many connections may be in the Careful Resume unvalidated
+    /// phase at the same time through each local interface.
+    ///
+    /// Connections that are ready to jump while the limit is reached stay in
+    /// the reconnaissance phase, and jump in turn as other connections leave
+    /// the unvalidated phase. This protects the access link when many
+    /// connections resume at once, e.g. after a server restart. The limiter
+    /// can be shared between multiple `Config` objects by cloning it.
+    ///
+    /// The default is not to limit jumps.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// let limiter = quiche::CrJumpLimiter::new(64);
+    /// config.set_cr_jump_limiter(limiter.clone());
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_cr_jump_limiter(&mut self, limiter: CrJumpLimiter) {
+        self.cr.set_jump_limiter(limiter);
+    }
 
-    /// Whether we send MAX_DATA frame.
-    almost_full: bool,
+    /// Sets how the congestion window may grow during the Careful Resume
+    /// validating phase.
+    ///
+    /// The default value is `CrValidatingPolicy::SlowStart`.
+    pub fn set_cr_validating_policy(&mut self, policy: CrValidatingPolicy) {
+        self.cr.set_validating_policy(policy);
+    }
 
-    /// Number of stream data bytes that can be buffered.
-    tx_cap: usize,
-
-    // Number of bytes buffered in the send buffer.
-    tx_buffered: usize,
+    /// Sets the Careful Resume validating phase growth policy by string.
+    ///
+    /// The default value is `slow_start`. On error `Error::CongestionControl`
+    /// will be returned.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_cr_validating_policy_name("freeze")?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_cr_validating_policy_name(&mut self, name: &str) -> Result<()> {
+        self.cr
+            .set_validating_policy(CrValidatingPolicy::from_str(name)?);
 
-    /// Total number of bytes sent to the peer.
-    tx_data: u64,
+        Ok(())
+    }
 
-    /// Peer's flow control limit for the connection.
-    max_tx_data: u64,
+    /// Sets how the congestion window added by the Careful Resume jump is
+    /// released during the unvalidated phase.
+    ///
+    /// By default it is paced at the rate of the previous connection, i.e.
+    /// its congestion window over its RTT. This only has an effect when
+    /// pacing is enabled.
+    ///
+    /// The default value is `CrJumpPacing::Paced`.
+    pub fn set_cr_jump_pacing(&mut self, v: CrJumpPacing) {
+        self.cr.set_jump_pacing(v);
+    }
 
-    /// Last tx_data before running a full send() loop.
-    last_tx_data: u64,
+    /// Sets how the Careful Resume jump is released by string.
+    ///
+    /// The default value is `paced`. On error `Error::CongestionControl` will
+    /// be returned.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_cr_jump_pacing_name("burst")?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_cr_jump_pacing_name(&mut self, name: &str) -> Result<()> {
+        self.cr.set_jump_pacing(CrJumpPacing::from_str(name)?);
 
-    /// Total number of bytes retransmitted over the connection.
-    /// This counts only STREAM and CRYPTO data.
-    stream_retrans_bytes: u64,
+        Ok(())
+    }
 
-    /// Total number of bytes sent over the connection.
-    sent_bytes: u64,
+    /// Sets how HyStart++ carries on once the Careful Resume jump completes.
+    ///
+    /// HyStart++ doesn't sample RTTs while the jumped window is unvalidated
+    /// or validating. Once the jump completes it either starts sampling
+    /// again, or slow start ends and the congestion controller moves to
+    /// congestion avoidance.
+    ///
+    /// The default value is `CrHystartPolicy::Resume`.
+    pub fn set_cr_hystart_policy(&mut self, v: CrHystartPolicy) {
+        self.cr.set_hystart_policy(v);
+    }
 
-    /// Total number of bytes received over the connection.
-    recv_bytes: u64,
+    /// Sets how HyStart++ carries on once the Careful Resume jump completes
+    /// by string.
+    ///
+    /// The default value is `resume`. On error `Error::CongestionControl`
+    /// will be returned.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_cr_hystart_policy_name("congestion_avoidance")?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_cr_hystart_policy_name(&mut self, name: &str) -> Result<()> {
+        self.cr.set_hystart_policy(CrHystartPolicy::from_str(name)?);
 
-    /// Total number of bytes sent acked over the connection.
-    acked_bytes: u64,
+        Ok(())
+    }
 
-    /// Total number of bytes sent lost over the connection.
-    lost_bytes: u64,
+    /// Sets the slow start threshold set when Careful Resume exits the safe
+    /// retreat phase, after the jumped window caused congestion.
+    ///
+    /// The default value is `CrRetreatPolicy::Pipesize`.
+    pub fn set_cr_retreat_policy(&mut self, v: CrRetreatPolicy) {
+        self.cr.set_retreat_policy(v);
+    }
 
-    /// Streams map, indexed by stream ID.
-    streams: stream::StreamMap,
+    /// Sets the slow start threshold set when Careful Resume exits the safe
+    /// retreat phase by string.
+    ///
+    /// The default value is `pipesize`. On error `Error::CongestionControl`
+    /// will be returned.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// config.set_cr_retreat_policy_name("iw_floor:4")?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn set_cr_retreat_policy_name(&mut self, name: &str) -> Result<()> {
+        self.cr.set_retreat_policy(CrRetreatPolicy::from_str(name)?);
 
-    /// Peer's original destination connection ID. Used by the client to
-    /// validate the server's transport parameter.
-    odcid: Option<ConnectionId<'static>>,
+        Ok(())
+    }
 
-    /// Peer's retry source connection ID. Used by the client during stateless
-    /// retry to validate the server's transport parameter.
-    rscid: Option<ConnectionId<'static>>,
+    /// Configures whether to enable pacing.
+    ///
+    /// The default value is `true`.
+    pub fn enable_pacing(&mut self, v: bool) {
+        self.cr.enable_pacing(v);
+    }
 
-    /// Address verification token to send in the INITIAL packet
-    token: Option<Vec<u8>>,
+    /// Sets the max value for pacing rate.
+    ///
+    /// By default pacing rate is not limited.
+    pub fn set_max_pacing_rate(&mut self, v: u64) {
+        self.cr.set_max_pacing_rate(v);
+    }
 
-    /// Address verification token to send in next packet in a NEW_TOKEN frame
-    new_token: Option<Vec<u8>>,
+    /// Configures whether to enable receiving DATAGRAM frames.
+    ///
+    /// When enabled, the `max_datagram_frame_size` transport parameter is set
+    /// to 65536 as recommended by draft-ietf-quic-datagram-01.
+    ///
+    /// The default is `false`.
+    pub fn enable_dgram(
+        &mut self, enabled: bool, recv_queue_len: usize, send_queue_len: usize,
+    ) {
+        self.local_transport_params.max_datagram_frame_size = if enabled {
+            Some(MAX_DGRAM_FRAME_SIZE)
+        } else {
+            None
+        };
+        self.dgram_recv_max_queue_len = recv_queue_len;
+        self.dgram_send_max_queue_len = send_queue_len;
+    }
 
-    /// Received address verification token from the INITIAL packet.
-    peer_token: Option<Vec<u8>>,
+    /// Configures the max number of queued received PATH_CHALLENGE frames.
+    ///
+    /// When an endpoint receives a PATH_CHALLENGE frame and the queue is full,
+    /// the frame is discarded.
+    ///
+    /// The default is 3.
+    pub fn set_path_challenge_recv_max_queue_len(&mut self, queue_len: usize) {
+        self.path_challenge_recv_max_queue_len = queue_len;
+    }
 
-    /// Queue of tokens received in NEW_TOKEN frames
-    received_tokens: VecDeque<Vec<u8>>,
+    /// Sets the maximum size of the connection window.
+    ///
+    /// The default value is MAX_CONNECTION_WINDOW (24MBytes).
+    pub fn set_max_connection_window(&mut self, v: u64) {
+        self.max_connection_window = v;
+    }
 
-    /// Error code and reason to be sent to the peer in a CONNECTION_CLOSE
-    /// frame.
-    local_error: Option<ConnectionError>,
+    /// Sets the maximum size of the stream window.
+    ///
+    /// The default value is MAX_STREAM_WINDOW (16MBytes).
+    pub fn set_max_stream_window(&mut self, v: u64) {
+        self.max_stream_window = v;
+    }
 
-    /// Error code and reason received from the peer in a CONNECTION_CLOSE
-    /// frame.
-    peer_error: Option<ConnectionError>,
+    /// Sets the initial stateless reset token.
+    ///
+    /// This value is only advertised by servers. Setting a stateless retry
+    /// token as a client has no effect on the connection.
+    ///
+    /// The default value is `None`.
+    pub fn set_stateless_reset_token(&mut self, v: Option<u128>) {
+        self.local_transport_params.stateless_reset_token = v;
+    }
 
-    /// The connection-level limit at which send blocking occurred.
-    blocked_limit: Option<u64>,
+    /// Sets whether the QUIC connection should avoid reusing DCIDs over
+    /// different paths.
+    ///
+    /// When set to `true`, it ensures that a destination Connection ID is never
+    /// reused on different paths. Such behaviour may lead to connection stall
+    /// if the peer performs a non-voluntary migration (e.g., NAT rebinding) and
+    /// does not provide additional destination Connection IDs to handle such
+    /// event.
+    ///
+    /// The default value is `false`.
+    pub fn set_disable_dcid_reuse(&mut self, v: bool) {
+        self.disable_dcid_reuse = v;
+    }
 
-    /// Idle timeout expiration time.
-    idle_timer: Option<time::Instant>,
+    /// Configures advertising that the server is sending BDP tokens in its NEW_TOKEN frames
+    ///
+    /// The default value is `false`.
+    pub fn set_bdp_tokens(&mut self, v: bool) {
+        self.local_transport_params.bdp_tokens = v;
+    }
 
-    /// Draining timeout expiration time.
-    draining_timer: Option<time::Instant>,
+    /// Configures whether to exchange Careful Resume parameters in BDP frames.
+    ///
+    /// When both endpoints enable it, the server sends the parameters it
+    /// observes to the client in BDP frames, which are reported by
+    /// [`bdp_frame_next()`]. The client echoes the last one back with
+    /// [`echo_bdp_frame()`] when it reconnects, and the server uses it to set
+    /// up careful resume without storing any state, subject to the same checks
+    /// as [`setup_careful_resume_from_server()`].
+    ///
+    /// The lifetime of the parameters sent by a server is the value set with
+    /// [`set_resume_blob_max_age()`]. A server also seals the parameters with
+    /// the key set with [`set_resume_blob_key()`] in the frame, and only uses
+    /// the sealed parameters of an echoed frame, which are bound to the
+    /// client's IP address and rejected once older than their lifetime. A
+    /// server without a key ignores echoed frames.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`bdp_frame_next()`]: struct.Connection.html#method.bdp_frame_next
+    /// [`echo_bdp_frame()`]: struct.Connection.html#method.echo_bdp_frame
+    /// [`setup_careful_resume_from_server()`]: struct.Connection.html#method.setup_careful_resume_from_server
+    /// [`set_resume_blob_max_age()`]: struct.Config.html#method.set_resume_blob_max_age
+    /// [`set_resume_blob_key()`]: struct.Config.html#method.set_resume_blob_key
+    pub fn enable_bdp_frame(&mut self, v: bool) {
+        self.local_transport_params.bdp_frame = v;
+    }
 
-    /// List of raw packets that were received before they could be decrypted.
-    undecryptable_pkts: VecDeque<(Vec<u8>, RecvInfo)>,
+    /// Configures whether to exchange Careful Resume hints in CR_HINTS
+    /// frames after the handshake.
+    ///
+    /// When both endpoints enable it, the server can send revised hints about
+    /// the path with [`send_cr_hints()`] at any time during the connection,
+    /// and the client reports them to the handler set with
+    /// [`set_cr_hints_handler()`] and to [`cr_hints_next()`]. See
+    /// [`CrHints`].
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`send_cr_hints()`]: struct.Connection.html#method.send_cr_hints
+    /// [`set_cr_hints_handler()`]: struct.Config.html#method.set_cr_hints_handler
+    /// [`cr_hints_next()`]: struct.Connection.html#method.cr_hints_next
+    /// [`CrHints`]: struct.CrHints.html
+    pub fn enable_cr_hints(&mut self, v: bool) {
+        self.local_transport_params.cr_hints = v;
+    }
 
-    /// The negotiated ALPN protocol.
-    alpn: Vec<u8>,
+    /// Sets the handler that clients report the Careful Resume hints sent by
+    /// the server to, as soon as they are received.
+    ///
+    /// The handler is shared by all the connections created with this
+    /// configuration. The hints are also reported by [`cr_hints_next()`].
+    ///
+    /// See [`enable_cr_hints()`].
+    ///
+    /// [`cr_hints_next()`]: struct.Connection.html#method.cr_hints_next
+    /// [`enable_cr_hints()`]: struct.Config.html#method.enable_cr_hints
+    pub fn set_cr_hints_handler(&mut self, handler: Arc<dyn CrHintsHandler>) {
+        self.cr_hints_handler = Some(handler);
+    }
 
-    /// Whether this is a server-side connection.
-    is_server: bool,
+    /// Configures whether to support resetting streams with a reliable size.
+    ///
+    /// When both endpoints enable it, [`stream_reset_at()`] can be used to
+    /// reset the sending side of a stream while still delivering the data
+    /// written up to a given offset, and the peer may do the same.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`stream_reset_at()`]: struct.Connection.html#method.stream_reset_at
+    pub fn enable_reset_stream_at(&mut self, v: bool) {
+        self.local_transport_params.reset_stream_at = v;
+    }
 
-    /// Whether the initial secrets have been derived.
-    derived_initial_secrets: bool,
+    /// Configures the key used by a server to seal and open stateless Careful
+    /// Resume blobs.
+    ///
+    /// When set, the server advertises the `careful_resume_blob` transport
+    /// parameter, [`resume_blob()`] returns the parameters observed on a
+    /// connection sealed into an opaque blob for the client to keep, and
+    /// blobs echoed back by clients are validated and used to set up careful
+    /// resume without storing any state on the server.
+    ///
+    /// The key must be 16 (AES-128-GCM) or 32 (AES-256-GCM) bytes long,
+    /// otherwise [`CryptoFail`] is returned. All servers that may receive a
+    /// blob must share the same key.
+    ///
+    /// [`resume_blob()`]: struct.Connection.html#method.resume_blob
+    /// [`CryptoFail`]: enum.Error.html#variant.CryptoFail
+    pub fn set_resume_blob_key(&mut self, key: &[u8]) -> Result<()> {
+        self.resume_blob_key = Some(Arc::new(resume_blob::BlobKey::new(key)?));
+        self.local_transport_params.careful_resume_blob = Some(Vec::new());
 
-    /// Whether a version negotiation packet has already been received. Only
-    /// relevant for client connections.
-    did_version_negotiation: bool,
+        Ok(())
+    }
 
-    /// Whether stateless retry has been performed.
-    did_retry: bool,
+    /// Sets the maximum age of stateless Careful Resume blobs accepted by a
+    /// server.
+    ///
+    /// Blobs older than this are ignored, which limits the window in which a
+    /// blob can be replayed.
+    ///
+    /// The default value is 24 hours.
+    pub fn set_resume_blob_max_age(&mut self, v: Duration) {
+        self.resume_blob_max_age = v;
+    }
 
-    /// Whether the peer already updated its connection ID.
-    got_peer_conn_id: bool,
+    /// Configures whether a server carries stateless Careful Resume blobs in
+    /// the tokens of its NEW_TOKEN frames.
+    ///
+    /// When enabled, the server sends a new token each time it observes new
+    /// Careful Resume parameters, and sets up careful resume when a client
+    /// presents one of these tokens in the Initial packets of a later
+    /// connection, subject to the same checks as blobs echoed in the
+    /// `careful_resume_blob` transport parameter. The client only needs to
+    /// keep the tokens received with [`recv_new_token()`] and pass the latest
+    /// one to [`set_token()`] when it reconnects.
+    ///
+    /// This requires a key to be set with [`set_resume_blob_key()`], and
+    /// also advertises the `bdp_tokens` transport parameter (see
+    /// [`set_bdp_tokens()`]). Servers that validate addresses with Retry
+    /// should use [`is_resume_token()`] to tell these tokens apart from the
+    /// ones they mint.
+    ///
+    /// The default value is `false`.
+    ///
+    /// [`recv_new_token()`]: struct.Connection.html#method.recv_new_token
+    /// [`set_token()`]: struct.Connection.html#method.set_token
+    /// [`set_resume_blob_key()`]: struct.Config.html#method.set_resume_blob_key
+    /// [`set_bdp_tokens()`]: struct.Config.html#method.set_bdp_tokens
+    /// [`is_resume_token()`]: fn.is_resume_token.html
+    pub fn enable_resume_new_token(&mut self, v: bool) {
+        self.resume_new_token = v;
+        self.local_transport_params.bdp_tokens = v;
+    }
 
-    /// Whether the peer verified our initial address.
-    peer_verified_initial_address: bool,
+    /// Sets the store used to persist Careful Resume parameters between
+    /// connections.
+    ///
+    /// New connections set up careful resume with the parameters stored for
+    /// their peer, if any, and store the parameters they observe. Servers
+    /// only set it up once the client's address is validated, as with
+    /// [`Connection::setup_server_careful_resume()`]. See [`CrStore`] for how
+    /// peers are identified.
+    ///
+    /// The default is not to persist parameters.
+    ///
+    /// ## Examples:
+    ///
+    /// ```
+    /// # use std::sync::Arc;
+    /// # use std::time::Duration;
+    /// # let mut config = quiche::Config::new(0xbabababa)?;
+    /// let store = quiche::MemoryCrStore::new(Duration::from_secs(3600));
+    /// config.set_cr_store(Arc::new(store));
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    ///
+    /// [`Connection::setup_server_careful_resume()`]: struct.Connection.html#method.setup_server_careful_resume
+    /// [`CrStore`]: trait.CrStore.html
+    pub fn set_cr_store(&mut self, store: Arc<dyn CrStore>) {
+        self.cr_store = Some(store);
+    }
 
-    /// Whether the peer's transport parameters were parsed.
-    parsed_peer_transport_params: bool,
+    /// Sets the cache of Careful Resume parameters shared by the connections
+    /// to a destination, see [`CrPathCache`].
+    ///
+    /// Connections created with this configuration report the parameters
+    /// they observe to the cache, and set up careful resume with their share
+    /// of the capacity of the path when there are no parameters for them in
+    /// the [`CrStore`] set with [`set_cr_store()`]. Parameters passed to
+    /// [`Connection::setup_careful_resume()`] replace those of the cache.
+    ///
+    /// The default is not to share parameters between connections.
+    ///
+    /// [`CrPathCache`]: struct.CrPathCache.html
+    /// [`CrStore`]: trait.CrStore.html
+    /// [`set_cr_store()`]: struct.Config.html#method.set_cr_store
+    /// [`Connection::setup_careful_resume()`]: struct.Connection.html#method.setup_careful_resume
+    pub fn set_cr_path_cache(&mut self, cache: Arc<CrPathCache>) {
+        self.cr_path_cache = Some(cache);
+    }
 
-    /// Whether the connection handshake has been completed.
-    handshake_completed: bool,
+    /// Sets the policy that servers pass Careful Resume parameters through
+    /// before setting up careful resume with them, see [`CrParamsPolicy`].
+    ///
+    /// The policy can reject the parameters, or clamp or scale them. It
+    /// applies to the parameters supplied by the client as well as to the
+    /// ones from the [`CrStore`], the [`CrPathCache`] or the application.
+    ///
+    /// The default is to use the parameters as they are.
+    ///
+    /// [`CrParamsPolicy`]: trait.CrParamsPolicy.html
+    /// [`CrStore`]: trait.CrStore.html
+    /// [`CrPathCache`]: struct.CrPathCache.html
+    pub fn set_cr_params_policy(&mut self, policy: Arc<dyn CrParamsPolicy>) {
+        self.cr_params_policy = Some(policy);
+    }
 
-    /// Whether the HANDSHAKE_DONE frame has been sent.
-    handshake_done_sent: bool,
+    /// Sets the observer of the Careful Resume phase changes of the paths of
+    /// connections created with this configuration, see [`CrPhaseObserver`].
+    ///
+    /// The default is not to observe phase changes.
+    ///
+    /// [`CrPhaseObserver`]: trait.CrPhaseObserver.html
+    pub fn set_cr_phase_observer(&mut self, observer: Arc<dyn CrPhaseObserver>) {
+        self.cr_phase_observer = Some(observer);
+    }
+
+    /// Sets the maximum age of Careful Resume parameters provided by the
+    /// server that a client accepts.
+    ///
+    /// See [`setup_careful_resume_from_server()`].
+    ///
+    /// The default value is 24 hours.
+    ///
+    /// [`setup_careful_resume_from_server()`]: struct.Connection.html#method.setup_careful_resume_from_server
+    pub fn set_cr_peer_params_max_age(&mut self, v: Duration) {
+        self.cr.set_peer_params_max_age(v);
+    }
+
+    /// Sets the largest congestion window, in bytes, in Careful Resume
+    /// parameters provided by the server that a client accepts.
+    ///
+    /// See [`setup_careful_resume_from_server()`].
+    ///
+    /// The default value is `usize::MAX`, i.e. no cap.
+    ///
+    /// [`setup_careful_resume_from_server()`]: struct.Connection.html#method.setup_careful_resume_from_server
+    pub fn set_cr_peer_params_max_cwnd(&mut self, v: usize) {
+        self.cr.set_peer_params_max_cwnd(v);
+    }
+
+    /// Sets how often recovery metrics are written to qlog.
+    ///
+    /// Only one in every `v` `MetricsUpdated` events is written, which reduces
+    /// the time spent serializing events during Careful Resume, when the
+    /// congestion window changes on almost every ACK. Phase changes of Careful
+    /// Resume are always written. A value of 0 is treated as 1.
+    ///
+    /// The default value is 1, i.e. every event is written.
+    #[cfg(feature = "qlog")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "qlog")))]
+    pub fn set_qlog_metrics_sampling(&mut self, v: usize) {
+        self.qlog_metrics_sampling = cmp::max(v, 1);
+    }
+
+    /// Sets the QUIC versions the endpoint is willing to use, in order of
+    /// preference.
+    ///
+    /// The list is advertised to the peer in the `version_information`
+    /// transport parameter (RFC 9368). A client picks the first of these
+    /// versions also offered by the server when it receives a Version
+    /// Negotiation packet, and lets the server switch the connection to any of
+    /// them during the handshake. A server switches the connection to the
+    /// first of these versions also supported by the client, when the client's
+    /// ClientHello fits in its first Initial packet.
+    ///
+    /// Returns [`UnknownVersion`] if the list is empty or contains a version
+    /// that is not supported.
+    ///
+    /// The default value is `[PROTOCOL_VERSION_V1, PROTOCOL_VERSION_V2]`.
+    ///
+    /// [`UnknownVersion`]: enum.Error.html#variant.UnknownVersion
+    pub fn set_available_versions(&mut self, versions: &[u32]) -> Result<()> {
+        if versions.is_empty() ||
+            !versions.iter().all(|&v| version_is_supported(v))
+        {
+            return Err(Error::UnknownVersion);
+        }
+
+        self.available_versions = versions.to_vec();
+
+        Ok(())
+    }
+}
+
+/// A QUIC connection.
+pub struct Connection {
+    /// QUIC wire version used for the connection.
+    version: u32,
+
+    /// Connection Identifiers.
+    ids: cid::ConnectionIdentifiers,
+
+    /// Unique opaque ID for the connection that can be used for logging.
+    trace_id: String,
+
+    /// Packet number spaces.
+    pkt_num_spaces: [packet::PktNumSpace; packet::Epoch::count()],
+
+    /// Peer's transport parameters.
+    peer_transport_params: TransportParams,
+
+    /// Local transport parameters.
+    local_transport_params: TransportParams,
+
+    /// TLS handshake state.
+    handshake: tls::Handshake,
+
+    /// Serialized TLS session buffer.
+    ///
+    /// This field is populated when a new session ticket is processed on the
+    /// client. On the server this is empty.
+    session: Option<Vec<u8>>,
+
+    /// The configuration for recovery.
+    recovery_config: recovery::RecoveryConfig,
+
+    /// The path manager.
+    paths: path::PathMap,
+
+    /// PATH_CHALLENGE receive queue max length.
+    path_challenge_recv_max_queue_len: usize,
+
+    /// Total number of received PATH_CHALLENGE frames.
+    path_challenge_rx_count: u64,
+
+    /// Total number of payload bytes protected by packet protection.
+    crypto_sealed_bytes: u64,
+
+    /// Total number of payload bytes unprotected by packet protection.
+    crypto_opened_bytes: u64,
+
+    /// Total time spent protecting and unprotecting packets.
+    crypto_time: time::Duration,
+
+    /// List of supported application protocols.
+    application_protos: Vec<Vec<u8>>,
+
+    /// Total number of received packets.
+    recv_count: usize,
+
+    /// Total number of sent packets.
+    sent_count: usize,
+
+    /// Total number of lost packets.
+    lost_count: usize,
+
+    /// Total number of packets sent with data retransmitted.
+    retrans_count: usize,
+
+    /// Total number of bytes received from the peer.
+    rx_data: u64,
+
+    /// Receiver flow controller.
+    flow_control: flowcontrol::FlowControl,
+
+    /// Whether we send MAX_DATA frame.
+    almost_full: bool,
+
+    /// Number of stream data bytes that can be buffered.
+    tx_cap: usize,
+
+    // Number of bytes buffered in the send buffer.
+    tx_buffered: usize,
+
+    /// Total number of bytes sent to the peer.
+    tx_data: u64,
+
+    /// Peer's flow control limit for the connection.
+    max_tx_data: u64,
+
+    /// Last tx_data before running a full send() loop.
+    last_tx_data: u64,
+
+    /// Total number of bytes retransmitted over the connection.
+    /// This counts only STREAM and CRYPTO data.
+    stream_retrans_bytes: u64,
+
+    /// Total number of bytes sent over the connection.
+    sent_bytes: u64,
+
+    /// Total number of bytes received over the connection.
+    recv_bytes: u64,
+
+    /// Total number of bytes sent acked over the connection.
+    acked_bytes: u64,
+
+    /// Total number of bytes sent lost over the connection.
+    lost_bytes: u64,
+
+    /// Streams map, indexed by stream ID.
+    streams: stream::StreamMap,
+
+    /// Peer's original destination connection ID. Used by the client to
+    /// validate the server's transport parameter.
+    odcid: Option<ConnectionId<'static>>,
+
+    /// Peer's retry source connection ID. Used by the client during stateless
+    /// retry to validate the server's transport parameter.
+    rscid: Option<ConnectionId<'static>>,
+
+    /// Address verification token to send in the INITIAL packet
+    token: Option<Vec<u8>>,
+
+    /// Address verification token to send in next packet in a NEW_TOKEN frame
+    new_token: Option<Vec<u8>>,
+
+    /// Received address verification token from the INITIAL packet.
+    peer_token: Option<Vec<u8>>,
+
+    /// Queue of tokens received in NEW_TOKEN frames
+    received_tokens: VecDeque<Vec<u8>>,
+
+    /// Error code and reason to be sent to the peer in a CONNECTION_CLOSE
+    /// frame.
+    local_error: Option<ConnectionError>,
+
+    /// Error code and reason received from the peer in a CONNECTION_CLOSE
+    /// frame.
+    peer_error: Option<ConnectionError>,
+
+    /// The last recovery decisions on the active path when the connection
+    /// was closed with a transport error.
+    close_decisions: Option<recovery::RecoveryDecisionLog>,
+
+    /// The connection-level limit at which send blocking occurred.
+    blocked_limit: Option<u64>,
+
+    /// Idle timeout expiration time.
+    idle_timer: Option<time::Instant>,
+
+    /// Draining timeout expiration time.
+    draining_timer: Option<time::Instant>,
+
+    /// List of raw packets that were received before they could be decrypted.
+    undecryptable_pkts: VecDeque<(Vec<u8>, RecvInfo)>,
+
+    /// The negotiated ALPN protocol.
+    alpn: Vec<u8>,
+
+    /// Whether this is a server-side connection.
+    is_server: bool,
+
+    /// Whether the initial secrets have been derived.
+    derived_initial_secrets: bool,
+
+    /// Whether a version negotiation packet has already been received. Only
+    /// relevant for client connections.
+    did_version_negotiation: bool,
+
+    /// The versions the endpoint is willing to use, in order of preference.
+    available_versions: Vec<u32>,
+
+    /// The version of the client's first Initial packet, when the server
+    /// switched the connection to a compatible version. Only relevant for
+    /// server connections.
+    original_version: Option<u32>,
+
+    /// Whether stateless retry has been performed.
+    did_retry: bool,
+
+    /// Whether the peer already updated its connection ID.
+    got_peer_conn_id: bool,
+
+    /// Whether the peer verified our initial address.
+    peer_verified_initial_address: bool,
+
+    /// Whether the peer's transport parameters were parsed.
+    parsed_peer_transport_params: bool,
+
+    /// Whether the connection handshake has been completed.
+    handshake_completed: bool,
+
+    /// Whether the HANDSHAKE_DONE frame has been sent.
+    handshake_done_sent: bool,
 
     /// Whether the HANDSHAKE_DONE frame has been acked.
     handshake_done_acked: bool,
@@ -1565,6 +2512,12 @@ pub struct Connection {
     /// TLS keylog writer.
     keylog: Option<Box<dyn std::io::Write + Send + Sync>>,
 
+    /// Packet capture writer.
+    pcap: Option<pcap::PcapWriter>,
+
+    /// Delivery rate samples writer.
+    rate_samples: Option<rate_samples::RateSampleWriter>,
+
     #[cfg(feature = "qlog")]
     qlog: QlogInfo,
 
@@ -1575,6 +2528,9 @@ pub struct Connection {
     /// Whether to emit DATAGRAM frames in the next packet.
     emit_dgram: bool,
 
+    /// Whether the 1-RTT keys must use ChaCha20-Poly1305.
+    chacha20_only: bool,
+
     /// Whether the connection should prevent from reusing destination
     /// Connection IDs when the peer migrates.
     disable_dcid_reuse: bool,
@@ -1594,7 +2550,83 @@ pub struct Connection {
     /// The anti-amplification limit factor.
     max_amplification_factor: usize,
 
-    cr_event: Option<recovery::CREvent>,
+    /// Careful Resume events not yet reported to the application.
+    cr_events: VecDeque<recovery::CREvent>,
+
+    /// Key used to seal and open stateless Careful Resume blobs.
+    resume_blob_key: Option<Arc<resume_blob::BlobKey>>,
+
+    /// Maximum age of an accepted stateless Careful Resume blob.
+    resume_blob_max_age: Duration,
+
+    /// Whether to carry stateless Careful Resume blobs in NEW_TOKEN frames.
+    resume_new_token: bool,
+
+    /// Store of Careful Resume parameters, and the server name keying them
+    /// on a client.
+    cr_store: Option<(Arc<dyn CrStore>, Option<String>)>,
+
+    /// The origin the addresses of the server are grouped by in the store of
+    /// Careful Resume parameters, on a client.
+    cr_origin: Option<String>,
+
+    /// The connection counted on the path of the shared Careful Resume
+    /// parameters cache.
+    cr_path_lease: Option<cr_path_cache::CrPathLease>,
+
+    /// Whether the Careful Resume parameters observed on paths are recorded.
+    cr_observe: bool,
+
+    /// Whether the last Careful Resume parameters observed before closing
+    /// were recorded.
+    cr_final_event_done: bool,
+
+    /// Maximum age of accepted Careful Resume parameters from the server.
+    cr_peer_params_max_age: Duration,
+
+    /// Largest congestion window of accepted Careful Resume parameters from
+    /// the server.
+    cr_peer_params_max_cwnd: usize,
+
+    /// Careful Resume parameters from the server waiting for an RTT sample
+    /// to be validated against.
+    pending_peer_cr_params: Option<recovery::CREvent>,
+
+    /// Careful Resume parameters set up by the server, waiting for the
+    /// client's address to be validated.
+    pending_server_cr_params: Option<recovery::CREvent>,
+
+    /// Reasons for rejecting Careful Resume parameters from the server, not
+    /// yet reported to the application.
+    cr_rejections: VecDeque<recovery::CrRejectReason>,
+
+    /// Whether the server should send a BDP frame with the latest Careful
+    /// Resume parameters.
+    bdp_frame_pending: bool,
+
+    /// The latest BDP frame received from the server, not yet reported to
+    /// the application.
+    peer_bdp_frame: Option<recovery::BdpFrame>,
+
+    /// The BDP frame of a previous connection to be echoed by the client.
+    bdp_frame_echo: Option<recovery::BdpFrame>,
+
+    /// The Careful Resume hints to be sent by the server.
+    cr_hints_pending: Option<CrHints>,
+
+    /// The sequence number of the latest Careful Resume hints sent by the
+    /// server, or received by the client.
+    cr_hints_seq: u64,
+
+    /// The latest Careful Resume hints received from the server, not yet
+    /// reported to the application.
+    peer_cr_hints: Option<CrHints>,
+
+    /// Handler of the Careful Resume hints received from the server.
+    cr_hints_handler: Option<Arc<dyn CrHintsHandler>>,
+
+    /// Policy the server passes Careful Resume parameters through.
+    cr_params_policy: Option<Arc<dyn CrParamsPolicy>>,
 
     default_stream_window: Option<u64>
 }
@@ -1623,7 +2655,17 @@ pub fn accept(
     scid: &ConnectionId, odcid: Option<&ConnectionId>, local: SocketAddr,
     peer: SocketAddr, config: &mut Config,
 ) -> Result<Connection> {
-    let conn = Connection::new(scid, odcid, local, peer, config, true)?;
+    let mut conn = Connection::new(scid, odcid, local, peer, config, true)?;
+
+    let restored = match &config.cr_store {
+        Some(store) => conn.restore_cr_params(store.clone(), None),
+
+        None => false,
+    };
+
+    if let Some(cache) = &config.cr_path_cache {
+        conn.join_cr_path_cache(cache.clone(), restored);
+    }
 
     Ok(conn)
 }
@@ -1651,30 +2693,92 @@ pub fn connect(
     server_name: Option<&str>, scid: &ConnectionId, local: SocketAddr,
     peer: SocketAddr, config: &mut Config,
 ) -> Result<Connection> {
-    let mut conn = Connection::new(scid, None, local, peer, config, false)?;
-
-    if let Some(server_name) = server_name {
-        conn.handshake.set_host_name(server_name)?;
-    }
-
-    Ok(conn)
+    connect_with_origin(server_name, None, scid, local, peer, config)
 }
 
-/// Writes a version negotiation packet.
+/// Creates a new client-side connection, grouping the Careful Resume
+/// parameters of the addresses of the server by `cr_origin`.
 ///
-/// The `scid` and `dcid` parameters are the source connection ID and the
-/// destination connection ID extracted from the received client's Initial
-/// packet that advertises an unsupported version.
+/// This is like [`connect()`], except that the parameters in the [`CrStore`]
+/// set with [`Config::set_cr_store()`] are keyed by the IP address of the
+/// server within `cr_origin`, e.g. the host name that resolved to it, rather
+/// than by the server name. When the server is reached at an address that has
+/// no parameters of its own, careful resume is set up with the parameters of
+/// another address of the origin, discounted as the path may differ.
 ///
 /// ## Examples:
 ///
 /// ```no_run
-/// # let mut buf = [0; 512];
-/// # let mut out = [0; 512];
-/// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
-/// let (len, src) = socket.recv_from(&mut buf).unwrap();
+/// # let mut config = quiche::Config::new(0xbabababa)?;
+/// # let server_name = "quic.tech";
+/// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+/// # let local = "127.0.0.1:4321".parse().unwrap();
+/// # let peer = "127.0.0.1:1234".parse().unwrap();
+/// let conn = quiche::connect_with_cr_origin(
+///     Some(&server_name),
+///     server_name,
+///     &scid,
+///     local,
+///     peer,
+///     &mut config,
+/// )?;
+/// # Ok::<(), quiche::Error>(())
+/// ```
 ///
-/// let hdr =
+/// [`connect()`]: fn.connect.html
+/// [`CrStore`]: trait.CrStore.html
+/// [`Config::set_cr_store()`]: struct.Config.html#method.set_cr_store
+#[inline]
+pub fn connect_with_cr_origin(
+    server_name: Option<&str>, cr_origin: &str, scid: &ConnectionId,
+    local: SocketAddr, peer: SocketAddr, config: &mut Config,
+) -> Result<Connection> {
+    connect_with_origin(server_name, Some(cr_origin), scid, local, peer, config)
+}
+
+fn connect_with_origin(
+    server_name: Option<&str>, cr_origin: Option<&str>, scid: &ConnectionId,
+    local: SocketAddr, peer: SocketAddr, config: &mut Config,
+) -> Result<Connection> {
+    let mut conn = Connection::new(scid, None, local, peer, config, false)?;
+
+    if let Some(server_name) = server_name {
+        conn.handshake.set_host_name(server_name)?;
+    }
+
+    conn.cr_origin = cr_origin.map(str::to_string);
+
+    let restored = match &config.cr_store {
+        Some(store) => {
+            let server_name = server_name.map(str::to_string);
+            conn.restore_cr_params(store.clone(), server_name)
+        },
+
+        None => false,
+    };
+
+    if let Some(cache) = &config.cr_path_cache {
+        conn.join_cr_path_cache(cache.clone(), restored);
+    }
+
+    Ok(conn)
+}
+
+/// Writes a version negotiation packet.
+///
+/// The `scid` and `dcid` parameters are the source connection ID and the
+/// destination connection ID extracted from the received client's Initial
+/// packet that advertises an unsupported version.
+///
+/// ## Examples:
+///
+/// ```no_run
+/// # let mut buf = [0; 512];
+/// # let mut out = [0; 512];
+/// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+/// let (len, src) = socket.recv_from(&mut buf).unwrap();
+///
+/// let hdr =
 ///     quiche::Header::from_slice(&mut buf[..len], quiche::MAX_CONN_ID_LEN)?;
 ///
 /// if hdr.version != quiche::PROTOCOL_VERSION {
@@ -1757,10 +2861,61 @@ pub fn retry(
     packet::retry(scid, dcid, new_scid, token, version, out)
 }
 
+/// Returns true if the given token carries stateless Careful Resume
+/// parameters.
+///
+/// Such tokens are sent in NEW_TOKEN frames by servers that enabled
+/// [`Config::enable_resume_new_token()`], and are validated by the connection
+/// itself. Servers that also mint address validation tokens for Retry can use
+/// this to avoid rejecting them.
+///
+/// [`Config::enable_resume_new_token()`]: struct.Config.html#method.enable_resume_new_token
+#[inline]
+pub fn is_resume_token(token: &[u8]) -> bool {
+    resume_blob::from_token(token).is_some()
+}
+
 /// Returns true if the given protocol version is supported.
 #[inline]
 pub fn version_is_supported(version: u32) -> bool {
-    matches!(version, PROTOCOL_VERSION_V1)
+    matches!(version, PROTOCOL_VERSION_V1 | PROTOCOL_VERSION_V2)
+}
+
+/// Returns the QUIC transport parameters of the TLS ClientHello at the start
+/// of `buf`, or `None` if `buf` doesn't start with a complete ClientHello
+/// carrying them.
+fn client_hello_transport_params(buf: &[u8]) -> Option<&[u8]> {
+    const CLIENT_HELLO: u8 = 1;
+    const QUIC_TRANSPORT_PARAMETERS: u16 = 0x39;
+
+    let mut b = octets::Octets::with_slice(buf);
+
+    if b.get_u8().ok()? != CLIENT_HELLO {
+        return None;
+    }
+
+    let len = b.get_u24().ok()? as usize;
+    let mut b = b.get_bytes(len).ok()?;
+
+    // legacy_version, random, legacy_session_id, cipher_suites and
+    // legacy_compression_methods.
+    b.skip(2 + 32).ok()?;
+    b.get_bytes_with_u8_length().ok()?;
+    b.get_bytes_with_u16_length().ok()?;
+    b.get_bytes_with_u8_length().ok()?;
+
+    let mut extensions = b.get_bytes_with_u16_length().ok()?;
+
+    while extensions.cap() > 0 {
+        let ty = extensions.get_u16().ok()?;
+        let data = extensions.get_bytes_with_u16_length().ok()?;
+
+        if ty == QUIC_TRANSPORT_PARAMETERS {
+            return Some(data.buf());
+        }
+    }
+
+    None
 }
 
 /// Pushes a frame to the output packet if there is enough space.
@@ -1824,6 +2979,20 @@ const QLOG_METRICS: EventType =
 const QLOG_CR_PHASE: EventType =
     EventType::RecoveryEventType(RecoveryEventType::CarefulResumePhaseUpdated);
 
+#[cfg(feature = "qlog")]
+const QLOG_CR_PARAMS_STORED: EventType = EventType::RecoveryEventType(
+    RecoveryEventType::CarefulResumeParametersStored,
+);
+
+#[cfg(feature = "qlog")]
+const QLOG_CR_PARAMS_RESTORED: EventType = EventType::RecoveryEventType(
+    RecoveryEventType::CarefulResumeParametersRestored,
+);
+
+#[cfg(feature = "qlog")]
+const QLOG_PEER_RESUME_SUSPECTED: EventType =
+    EventType::RecoveryEventType(RecoveryEventType::PeerResumeSuspected);
+
 #[cfg(feature = "qlog")]
 const QLOG_PACKET_LOST: EventType =
     EventType::RecoveryEventType(RecoveryEventType::PacketLost);
@@ -1837,6 +3006,8 @@ struct QlogInfo {
     streamer: Option<qlog::streamer::QlogStreamer>,
     logged_peer_params: bool,
     level: EventImportance,
+    metrics_sampler: QlogSampler,
+    stats: QlogStats,
 }
 
 #[cfg(feature = "qlog")]
@@ -1846,7 +3017,68 @@ impl Default for QlogInfo {
             streamer: None,
             logged_peer_params: false,
             level: EventImportance::Base,
+            metrics_sampler: QlogSampler::new(1),
+            stats: QlogStats::default(),
+        }
+    }
+}
+
+/// Selects one in every `every` events.
+#[cfg(feature = "qlog")]
+struct QlogSampler {
+    every: usize,
+    seen: usize,
+}
+
+#[cfg(feature = "qlog")]
+impl QlogSampler {
+    fn new(every: usize) -> Self {
+        QlogSampler {
+            every: cmp::max(every, 1),
+            seen: 0,
+        }
+    }
+
+    fn sample(&mut self) -> bool {
+        let selected = self.seen % self.every == 0;
+        self.seen = self.seen.wrapping_add(1);
+
+        selected
+    }
+}
+
+/// Keeps track of the recovery events written to qlog and of the time spent
+/// writing them.
+#[derive(Clone, Copy, Default)]
+struct QlogStats {
+    metrics_updated: u64,
+    cr_phase_updated: u64,
+    packet_lost: u64,
+    elapsed: time::Duration,
+}
+
+#[cfg(feature = "qlog")]
+impl QlogStats {
+    fn add_event_data(
+        &mut self, q: &mut qlog::streamer::QlogStreamer, ev_data: EventData,
+        now: time::Instant,
+    ) {
+        let start = time::Instant::now();
+
+        match ev_data {
+            EventData::MetricsUpdated(_) => self.metrics_updated += 1,
+
+            EventData::CarefulResumePhaseUpdated(_) =>
+                self.cr_phase_updated += 1,
+
+            EventData::PacketLost(_) => self.packet_lost += 1,
+
+            _ => (),
         }
+
+        q.add_event_data_with_instant(ev_data, now).ok();
+
+        self.elapsed += start.elapsed();
     }
 }
 
@@ -1939,6 +3171,10 @@ impl Connection {
                 .path_challenge_recv_max_queue_len,
             path_challenge_rx_count: 0,
 
+            crypto_sealed_bytes: 0,
+            crypto_opened_bytes: 0,
+            crypto_time: time::Duration::ZERO,
+
             application_protos: config.application_protos.clone(),
 
             recv_count: 0,
@@ -1987,6 +3223,8 @@ impl Connection {
 
             peer_error: None,
 
+            close_decisions: None,
+
             blocked_limit: None,
 
             idle_timer: None,
@@ -2003,6 +3241,10 @@ impl Connection {
 
             did_version_negotiation: false,
 
+            available_versions: config.available_versions.clone(),
+
+            original_version: None,
+
             did_retry: false,
 
             got_peer_conn_id: false,
@@ -2031,8 +3273,15 @@ impl Connection {
 
             keylog: None,
 
+            pcap: None,
+
+            rate_samples: None,
+
             #[cfg(feature = "qlog")]
-            qlog: Default::default(),
+            qlog: QlogInfo {
+                metrics_sampler: QlogSampler::new(config.qlog_metrics_sampling),
+                ..Default::default()
+            },
 
             dgram_recv_queue: dgram::DatagramQueue::new(
                 config.dgram_recv_max_queue_len,
@@ -2044,6 +3293,8 @@ impl Connection {
 
             emit_dgram: true,
 
+            chacha20_only: config.chacha20_only,
+
             disable_dcid_reuse: config.disable_dcid_reuse,
 
             reset_stream_local_count: 0,
@@ -2053,7 +3304,49 @@ impl Connection {
 
             max_amplification_factor: config.max_amplification_factor,
 
-            cr_event: None,
+            cr_events: VecDeque::new(),
+
+            resume_blob_key: config.resume_blob_key.clone(),
+
+            resume_blob_max_age: config.resume_blob_max_age,
+
+            resume_new_token: config.resume_new_token,
+
+            cr_store: None,
+
+            cr_origin: None,
+
+            cr_path_lease: None,
+
+            cr_observe: config.cr_observe,
+
+            cr_final_event_done: false,
+
+            cr_peer_params_max_age: config.cr.peer_params_max_age,
+
+            cr_peer_params_max_cwnd: config.cr.peer_params_max_cwnd,
+
+            pending_peer_cr_params: None,
+
+            pending_server_cr_params: None,
+
+            cr_rejections: VecDeque::new(),
+
+            bdp_frame_pending: false,
+
+            peer_bdp_frame: None,
+
+            bdp_frame_echo: None,
+
+            cr_hints_pending: None,
+
+            cr_hints_seq: 0,
+
+            peer_cr_hints: None,
+
+            cr_hints_handler: config.cr_hints_handler.clone(),
+
+            cr_params_policy: config.cr_params_policy.clone(),
 
             default_stream_window: None,
         };
@@ -2074,7 +3367,7 @@ impl Connection {
         conn.handshake.init(is_server)?;
 
         conn.handshake
-            .use_legacy_codepoint(config.version != PROTOCOL_VERSION_V1);
+            .use_legacy_codepoint(!version_is_supported(config.version));
 
         conn.encode_transport_params()?;
 
@@ -2105,6 +3398,11 @@ impl Connection {
             conn.derived_initial_secrets = true;
         }
 
+        #[cfg(all(feature = "env-config", feature = "qlog"))]
+        if let Some(dir) = &config.qlog_dir {
+            env_config::set_qlog(&mut conn, dir);
+        }
+
         Ok(conn)
     }
 
@@ -2119,6 +3417,39 @@ impl Connection {
         self.keylog = Some(writer);
     }
 
+    /// Sets packet capture output to the designated [`Writer`].
+    ///
+    /// The datagrams sent and received by the connection are written in the
+    /// pcap format, with synthesized IP and UDP headers, so that their timing
+    /// can be analysed with standard tools. Only the invariant header of the
+    /// first QUIC packet in each datagram is captured, the rest is omitted but
+    /// counted in the datagram length. Sent datagrams are timestamped with
+    /// their [`SendInfo::at`] time.
+    ///
+    /// This needs to be called as soon as the connection is created, to avoid
+    /// missing the first datagrams.
+    ///
+    /// [`Writer`]: https://doc.rust-lang.org/std/io/trait.Write.html
+    /// [`SendInfo::at`]: struct.SendInfo.html#structfield.at
+    pub fn set_pcap(&mut self, writer: Box<dyn std::io::Write + Send + Sync>) {
+        self.pcap = Some(pcap::PcapWriter::new(writer));
+    }
+
+    /// Sets delivery rate sample output to the designated [`Writer`].
+    ///
+    /// Each delivery rate sample generated by an ACK is written as a line of
+    /// CSV, with the number of bytes delivered, the interval and whether the
+    /// sender was app-limited, along with the congestion window and the
+    /// Careful Resume phase of the path at that point. This gives the raw
+    /// data seen by the congestion controller, e.g. for research.
+    ///
+    /// [`Writer`]: https://doc.rust-lang.org/std/io/trait.Write.html
+    pub fn set_rate_sample_writer(
+        &mut self, writer: Box<dyn std::io::Write + Send + Sync>,
+    ) {
+        self.rate_samples = Some(rate_samples::RateSampleWriter::new(writer));
+    }
+
     /// Sets qlog output to the designated [`Writer`].
     ///
     /// Only events included in `QlogLevel::Base` are written. The serialization
@@ -2275,38 +3606,229 @@ impl Connection {
     /// Configures careful resume on the active path with stored CC parameters.
     /// Careful resume will not be enabled until this function is called, even if [`enable_resume()`] is called.
     ///
+    /// `previous_pacing_rate` is the pacing rate of the previous connection,
+    /// in bytes per second, as reported by [`CREvent`]. When not 0 it bounds
+    /// the pacing rate during the unvalidated phase. Use 0 when it is not
+    /// known.
+    ///
+    /// `previous_cwnd` is used as is. A congestion window from a [`CREvent`]
+    /// observed with a different datagram size should be scaled with
+    /// [`CREvent::cwnd_for_datagram_size()`] first.
+    ///
+    /// If careful resume was disabled with [`enable_resume()`],
+    /// [`InvalidState`] is returned.
+    ///
     /// [`enable_resume()`]: struct.Config.html#method.enable_resume
-    pub fn setup_careful_resume(&mut self, previous_rtt: Duration, previous_cwnd: usize) -> Result<()> {
-        self.paths.get_active_mut()?.recovery
-            .setup_careful_resume(previous_rtt, previous_cwnd);
+    /// [`CREvent`]: struct.CREvent.html
+    /// [`CREvent::cwnd_for_datagram_size()`]: struct.CREvent.html#method.cwnd_for_datagram_size
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn setup_careful_resume(
+        &mut self, previous_rtt: Duration, previous_cwnd: usize,
+        previous_pacing_rate: u64,
+    ) -> Result<()> {
+        if !self.recovery_config.resume {
+            return Err(Error::InvalidState);
+        }
+
+        self.paths.get_active_mut()?.recovery.setup_careful_resume(
+            previous_rtt,
+            previous_cwnd,
+            previous_pacing_rate,
+        );
+
+        self.qlog_cr_params_restored(
+            previous_rtt,
+            previous_cwnd,
+            previous_pacing_rate,
+        );
+
         Ok(())
     }
 
-    /// Sets the default window for jumps in stream flow credits
-    pub fn setup_default_stream_window(&mut self, window: u64) {
-        self.default_stream_window = Some(window);
+    /// Configures careful resume on a server with stored CC parameters, once
+    /// the client's address is validated.
+    ///
+    /// Unlike [`setup_careful_resume()`], the parameters are not applied to
+    /// the active path while the server is still subject to the
+    /// anti-amplification limit of RFC 9000, i.e. before the client sent a
+    /// Handshake packet or after a stateless retry. They are kept until the
+    /// address is validated, so the jump is never planned on a path that can
+    /// only carry three times the data received from the client.
+    ///
+    /// The parameters are passed through the policy set with
+    /// [`Config::set_cr_params_policy()`], if any.
+    ///
+    /// This must be called on a server with careful resume enabled, otherwise
+    /// [`InvalidState`] is returned.
+    ///
+    /// [`setup_careful_resume()`]: struct.Connection.html#method.setup_careful_resume
+    /// [`Config::set_cr_params_policy()`]: struct.Config.html#method.set_cr_params_policy
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn setup_server_careful_resume(
+        &mut self, previous_rtt: Duration, previous_cwnd: usize,
+        previous_pacing_rate: u64,
+    ) -> Result<()> {
+        if !self.is_server || !self.recovery_config.resume {
+            return Err(Error::InvalidState);
+        }
+
+        let params = CREvent {
+            min_rtt: previous_rtt,
+            cwnd: previous_cwnd,
+            pacing_rate: previous_pacing_rate,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        };
+
+        self.queue_server_cr_params(params, CrParamsSource::Application);
+
+        Ok(())
     }
 
-    /// Sets the initial RTT heuristic on the active path, if the application has information about
-    /// what the RTT of the path is likely to be.
-    pub fn set_initial_rtt(&mut self, initial_rtt: Duration) -> Result<()> {
-        self.paths.get_active_mut()?.recovery
-            .set_initial_rtt(initial_rtt);
+    /// Keeps Careful Resume parameters from `source` on a server, once the
+    /// policy accepted them, until the client's address is validated.
+    fn queue_server_cr_params(
+        &mut self, params: CREvent, source: CrParamsSource,
+    ) {
+        if let Some(params) = self.check_cr_params(params, source) {
+            self.pending_server_cr_params = Some(params);
+
+            self.maybe_setup_server_careful_resume();
+        }
+    }
+
+    /// Passes Careful Resume parameters from `source` through the policy set
+    /// on a server, and returns the parameters to use, or `None` if they are
+    /// rejected.
+    fn check_cr_params(
+        &mut self, params: CREvent, source: CrParamsSource,
+    ) -> Option<CREvent> {
+        let policy = match &self.cr_params_policy {
+            Some(v) if self.is_server => v,
+
+            _ => return Some(params),
+        };
+
+        let peer = self.paths.get_active().ok()?.peer_addr();
+
+        let checked = policy.check(peer, source, &params);
+
+        if checked.is_none() {
+            trace!(
+                "{} careful resume parameters from {:?} rejected by policy",
+                self.trace_id,
+                source
+            );
+
+            self.cr_rejections
+                .push_back(recovery::CrRejectReason::Policy);
+        }
+
+        checked
+    }
+
+    /// Configures careful resume on a client with parameters provided by the
+    /// server, `age` after they were observed.
+    ///
+    /// Unlike [`setup_careful_resume()`], the parameters are not trusted
+    /// blindly. They are rejected if they are older than
+    /// [`Config::set_cr_peer_params_max_age()`], if the congestion window is
+    /// above [`Config::set_cr_peer_params_max_cwnd()`], or if the RTT is too
+    /// different from the one measured during the handshake. The RTT check
+    /// is deferred until the handshake provides an RTT sample.
+    ///
+    /// Rejected parameters are not used, and the reason is reported by
+    /// [`cr_rejection_next()`].
+    ///
+    /// This must be called on a client with careful resume enabled, otherwise
+    /// [`InvalidState`] is returned.
+    ///
+    /// [`setup_careful_resume()`]: struct.Connection.html#method.setup_careful_resume
+    /// [`Config::set_cr_peer_params_max_age()`]: struct.Config.html#method.set_cr_peer_params_max_age
+    /// [`Config::set_cr_peer_params_max_cwnd()`]: struct.Config.html#method.set_cr_peer_params_max_cwnd
+    /// [`cr_rejection_next()`]: struct.Connection.html#method.cr_rejection_next
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn setup_careful_resume_from_server(
+        &mut self, params: &CREvent, age: Duration,
+    ) -> Result<()> {
+        if self.is_server || !self.recovery_config.resume {
+            return Err(Error::InvalidState);
+        }
+
+        self.accept_peer_cr_params(*params, age);
+
         Ok(())
     }
 
-    /// Processes QUIC packets received from the peer.
+    /// Indicates whether the current workload should allow the Careful Resume
+    /// jump.
     ///
-    /// On success the number of bytes processed from the input buffer is
-    /// returned. On error the connection will be closed by calling [`close()`]
-    /// with the appropriate error code.
+    /// The hint is consulted during the reconnaissance phase. While it is
+    /// [`Interactive`], e.g. because latency-critical control streams
+    /// dominate early in the connection, the jump is held back. It is made
+    /// once the hint becomes [`Bulk`] and the jump conditions are still met.
+    /// The hint has no effect once the jump has been made or Careful Resume
+    /// was abandoned.
     ///
-    /// Coalesced packets will be processed as necessary.
+    /// The default value is `CrWorkloadHint::Bulk`.
     ///
-    /// Note that the contents of the input buffer `buf` might be modified by
-    /// this function due to, for example, in-place decryption.
+    /// [`Interactive`]: enum.CrWorkloadHint.html#variant.Interactive
+    /// [`Bulk`]: enum.CrWorkloadHint.html#variant.Bulk
+    pub fn set_cr_workload_hint(&mut self, hint: CrWorkloadHint) {
+        for (_, p) in self.paths.iter_mut() {
+            p.recovery.set_cr_workload_hint(hint);
+        }
+    }
+
+    /// Indicates how many bytes the application still expects to send on
+    /// the connection, e.g. once the size of a response is known.
     ///
-    /// [`close()`]: struct.Connection.html#method.close
+    /// The hint is consulted during the reconnaissance phase. When the data
+    /// left to send fits in the current congestion window, the Careful
+    /// Resume jump would not speed up the transfer and would only risk loss,
+    /// so Careful Resume is abandoned with the `transfer_too_small` qlog
+    /// trigger. The data left is counted down as packets are sent, including
+    /// retransmissions and packet overhead, so it is an estimate. `None`
+    /// removes the hint.
+    ///
+    /// By default there is no hint.
+    pub fn set_cr_transfer_size_hint(&mut self, bytes: Option<usize>) {
+        for (_, p) in self.paths.iter_mut() {
+            p.recovery.set_cr_transfer_size_hint(bytes);
+        }
+    }
+
+    /// Bounds the queuing delay the sender may build on the path, for
+    /// interactive applications that can't tolerate bufferbloat.
+    ///
+    /// The congestion window is capped to the bandwidth-delay product, i.e.
+    /// the delivery rate times the min RTT, plus the data sent at the
+    /// delivery rate during `budget`. This also applies during the Careful
+    /// Resume jump, where the rate of the previous connection is used until
+    /// a higher delivery rate is measured, so that Careful Resume still
+    /// speeds up the ramp-up without queuing beyond the budget. The window
+    /// isn't capped until a rate is known. A value of 0 removes the budget.
+    ///
+    /// By default there is no budget.
+    pub fn set_latency_budget(&mut self, budget: Duration) {
+        let budget = if budget.is_zero() { None } else { Some(budget) };
+
+        for (_, p) in self.paths.iter_mut() {
+            p.recovery.set_latency_budget(budget);
+        }
+    }
+
+    /// Reports congestion signaled from outside the transport on the paths
+    /// using the local address `local_addr`.
+    ///
+    /// This feeds indications such as router AQM reports or the buffer status
+    /// of a modem or satellite terminal into recovery. `source` records where
+    /// the signal came from. During the Careful Resume jump the signal is
+    /// handled as an ECN CE mark, i.e. Careful Resume enters the safe retreat
+    /// phase, with the `external_congestion` qlog trigger. The congestion
+    /// controller itself doesn't react to it. The signals are counted in
+    /// [`PathStats::external_congestion_count`].
     ///
     /// ## Examples:
     ///
@@ -2318,1330 +3840,1459 @@ impl Connection {
     /// # let peer = "127.0.0.1:1234".parse().unwrap();
     /// # let local = socket.local_addr().unwrap();
     /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
-    /// loop {
-    ///     let (read, from) = socket.recv_from(&mut buf).unwrap();
-    ///
-    ///     let recv_info = quiche::RecvInfo {
-    ///         from,
-    ///         to: local,
-    ///     };
-    ///
-    ///     let read = match conn.recv(&mut buf[..read], recv_info) {
-    ///         Ok(v) => v,
-    ///
-    ///         Err(e) => {
-    ///             // An error occurred, handle it.
-    ///             break;
-    ///         },
-    ///     };
-    /// }
+    /// // The terminal reported that its uplink buffer is filling up.
+    /// conn.on_external_congestion(local, quiche::CongestionSignalSource::LinkLayer);
     /// # Ok::<(), quiche::Error>(())
     /// ```
-    pub fn recv(&mut self, buf: &mut [u8], info: RecvInfo) -> Result<usize> {
-        let len = buf.len();
+    ///
+    /// [`PathStats::external_congestion_count`]: struct.PathStats.html#structfield.external_congestion_count
+    pub fn on_external_congestion(
+        &mut self, local_addr: SocketAddr, source: CongestionSignalSource,
+    ) {
+        let now = time::Instant::now();
 
-        if len == 0 {
-            return Err(Error::BufferTooShort);
+        for (_, p) in self.paths.iter_mut() {
+            if p.local_addr() == local_addr {
+                p.recovery.on_external_congestion(source, now);
+            }
         }
 
-        let recv_pid = self.paths.path_id_from_addrs(&(info.to, info.from));
+        self.invalidate_cr_params();
+    }
 
-        if let Some(recv_pid) = recv_pid {
-            let recv_path = self.paths.get_mut(recv_pid)?;
+    /// Abandons Careful Resume on all paths, whatever its phase.
+    ///
+    /// This lets an application that learns about a path change before the
+    /// transport does, e.g. from an interface change notification, bail out
+    /// of the jump immediately. When the jumped congestion window wasn't
+    /// validated yet, it is reduced to the data acknowledged since the jump,
+    /// as for a retreat. Careful Resume enters the normal phase with the
+    /// `abandoned` qlog trigger. Nothing happens on paths where Careful
+    /// Resume isn't in use or was already abandoned.
+    pub fn abandon_careful_resume(&mut self) {
+        let now = time::Instant::now();
 
-            // Keep track of how many bytes we received from the client, so we
-            // can limit bytes sent back before address validation, to a
-            // multiple of this. The limit needs to be increased early on, so
-            // that if there is an error there is enough credit to send a
-            // CONNECTION_CLOSE.
-            //
-            // It doesn't matter if the packets received were valid or not, we
-            // only need to track the total amount of bytes received.
-            //
-            // Note that we also need to limit the number of bytes we sent on a
-            // path if we are not the host that initiated its usage.
-            if self.is_server && !recv_path.verified_peer_address {
-                recv_path.max_send_bytes += len * self.max_amplification_factor;
-            }
-        } else if !self.is_server {
-            // If a client receives packets from an unknown server address,
-            // the client MUST discard these packets.
+        for (_, p) in self.paths.iter_mut() {
+            p.recovery.abandon_careful_resume(now);
+        }
+    }
+
+    /// Sets the observer of the Careful Resume phase changes of the paths of
+    /// this connection, replacing the one set with
+    /// [`Config::set_cr_phase_observer()`].
+    ///
+    /// This lets the observer tell connections apart, e.g. by giving each
+    /// connection its own observer.
+    ///
+    /// [`Config::set_cr_phase_observer()`]: struct.Config.html#method.set_cr_phase_observer
+    pub fn set_cr_phase_observer(&mut self, observer: Arc<dyn CrPhaseObserver>) {
+        for (_, p) in self.paths.iter_mut() {
+            p.recovery.set_cr_phase_observer(observer.clone());
+        }
+
+        self.recovery_config.cr_phase_observer = Some(observer);
+    }
+
+    /// Validates Careful Resume parameters from the peer, `age` after they
+    /// were observed, and sets up careful resume with them once the handshake
+    /// RTT is known.
+    fn accept_peer_cr_params(&mut self, params: CREvent, age: Duration) {
+        if !self.recovery_config.resume {
+            return;
+        }
+
+        let reject = if age > self.cr_peer_params_max_age {
+            Some(recovery::CrRejectReason::Expired)
+        } else if params.cwnd > self.cr_peer_params_max_cwnd {
+            Some(recovery::CrRejectReason::CwndAboveCap)
+        } else {
+            None
+        };
+
+        if let Some(reason) = reject {
             trace!(
-                "{} client received packet from unknown address {:?}, dropping",
+                "{} rejecting careful resume parameters from peer: {:?}",
                 self.trace_id,
-                info,
+                reason
             );
 
-            return Ok(len);
+            self.cr_rejections.push_back(reason);
+
+            return;
         }
 
-        let mut done = 0;
-        let mut left = len;
+        self.pending_peer_cr_params = Some(params);
+        self.maybe_setup_peer_careful_resume();
+    }
 
-        // Process coalesced packets.
-        while left > 0 {
-            let read = match self.recv_single(
-                &mut buf[len - left..len],
-                &info,
-                recv_pid,
-            ) {
-                Ok(v) => v,
+    /// Sets up careful resume with the parameters pending on the server, once
+    /// the client's address is validated.
+    fn maybe_setup_server_careful_resume(&mut self) {
+        if self.pending_server_cr_params.is_none() {
+            return;
+        }
 
-                Err(Error::Done) => {
-                    // If the packet can't be processed or decrypted, check if
-                    // it's a stateless reset.
-                    if self.is_stateless_reset(&buf[len - left..len]) {
-                        trace!("{} packet is a stateless reset", self.trace_id);
+        let active_path = match self.paths.get_active_mut() {
+            Ok(v) => v,
 
-                        self.mark_closed();
-                    }
+            Err(_) => return,
+        };
 
-                    left
-                },
+        if !active_path.verified_peer_address {
+            return;
+        }
 
-                Err(e) => {
-                    // In case of error processing the incoming packet, close
-                    // the connection.
-                    self.close(false, e.to_wire(), b"").ok();
-                    return Err(e);
-                },
-            };
+        if let Some(params) = self.pending_server_cr_params.take() {
+            let cwnd = params
+                .cwnd_for_datagram_size(active_path.recovery.max_datagram_size());
 
-            done += read;
-            left -= read;
-        }
+            trace!(
+                "{} client address validated, setting up careful resume min_rtt={:?} cwnd={}",
+                self.trace_id,
+                params.min_rtt,
+                cwnd
+            );
 
-        // Even though the packet was previously "accepted", it
-        // should be safe to forward the error, as it also comes
-        // from the `recv()` method.
-        self.process_undecrypted_0rtt_packets()?;
+            active_path.recovery.setup_careful_resume(
+                params.min_rtt,
+                cwnd,
+                params.pacing_rate,
+            );
 
-        Ok(done)
+            self.qlog_cr_params_restored(
+                params.min_rtt,
+                cwnd,
+                params.pacing_rate,
+            );
+        }
     }
 
-    fn process_undecrypted_0rtt_packets(&mut self) -> Result<()> {
-        // Process previously undecryptable 0-RTT packets if the decryption key
-        // is now available.
-        if self.pkt_num_spaces[packet::Epoch::Application]
-            .crypto_0rtt_open
-            .is_some()
+    /// Sets up careful resume with the pending parameters from the peer,
+    /// once their RTT can be compared with the handshake RTT.
+    fn maybe_setup_peer_careful_resume(&mut self) {
+        let params = match self.pending_peer_cr_params {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        let active_path = match self.paths.get_active_mut() {
+            Ok(v) => v,
+
+            Err(_) => return,
+        };
+
+        let handshake_rtt = match active_path.recovery.min_rtt() {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        self.pending_peer_cr_params = None;
+
+        if !self
+            .recovery_config
+            .cr_thresholds
+            .rtt_similar(params.min_rtt, handshake_rtt)
         {
-            while let Some((mut pkt, info)) = self.undecryptable_pkts.pop_front()
-            {
-                if let Err(e) = self.recv(&mut pkt, info) {
-                    self.undecryptable_pkts.clear();
+            trace!(
+                "{} rejecting careful resume parameters from peer: rtt={:?} handshake_rtt={:?}",
+                self.trace_id,
+                params.min_rtt,
+                handshake_rtt
+            );
 
-                    return Err(e);
-                }
-            }
+            self.cr_rejections
+                .push_back(recovery::CrRejectReason::RttImplausible);
+
+            return;
         }
-        Ok(())
+
+        let cwnd = params
+            .cwnd_for_datagram_size(active_path.recovery.max_datagram_size());
+
+        active_path.recovery.setup_careful_resume(
+            params.min_rtt,
+            cwnd,
+            params.pacing_rate,
+        );
+
+        self.qlog_cr_params_restored(params.min_rtt, cwnd, params.pacing_rate);
     }
 
-    /// Returns true if a QUIC packet is a stateless reset.
-    fn is_stateless_reset(&self, buf: &[u8]) -> bool {
-        // If the packet is too small, then we just throw it away.
-        let buf_len = buf.len();
-        if buf_len < 21 {
-            return false;
-        }
+    /// Logs a careful resume jump suspected from the data sent by the peer to
+    /// qlog.
+    fn qlog_peer_resume_suspected(&mut self, jump: &recovery::SuspectedPeerJump) {
+        qlog_with_type!(QLOG_PEER_RESUME_SUSPECTED, self.qlog, q, {
+            let ev_data =
+                EventData::PeerResumeSuspected(PeerResumeSuspected::new(
+                    jump.estimated_jump.into(),
+                    jump.previous_round_bytes.into(),
+                    jump.round as u64,
+                    jump.rtt.into(),
+                ));
 
-        // TODO: we should iterate over all active destination connection IDs
-        // and check against their reset token.
-        match self.peer_transport_params.stateless_reset_token {
-            Some(token) => {
-                let token_len = 16;
-                ring::constant_time::verify_slices_are_equal(
-                    &token.to_be_bytes(),
-                    &buf[buf_len - token_len..buf_len],
-                )
-                .is_ok()
-            },
+            q.add_event_data_now(ev_data).ok();
+        });
+    }
 
-            None => false,
-        }
+    /// Logs careful resume parameters set up on the active path to qlog.
+    fn qlog_cr_params_restored(
+        &mut self, previous_rtt: Duration, previous_cwnd: usize,
+        previous_pacing_rate: u64,
+    ) {
+        qlog_with_type!(QLOG_CR_PARAMS_RESTORED, self.qlog, q, {
+            let restored = CarefulResumeParametersRestored::new(
+                previous_cwnd.into(),
+                previous_rtt.into(),
+            );
+
+            let restored = if previous_pacing_rate != 0 {
+                restored.with_previous_pacing_rate(previous_pacing_rate)
+            } else {
+                restored
+            };
+
+            let ev_data = EventData::CarefulResumeParametersRestored(restored);
+
+            q.add_event_data_now(ev_data).ok();
+        });
     }
 
-    /// Processes a single QUIC packet received from the peer.
+    /// Returns the reason Careful Resume parameters passed to
+    /// [`setup_careful_resume_from_server()`] were rejected, or `None` when
+    /// there are no rejections to report.
     ///
-    /// On success the number of bytes processed from the input buffer is
-    /// returned. When the [`Done`] error is returned, processing of the
-    /// remainder of the incoming UDP datagram should be interrupted.
+    /// On a server, parameters rejected by the policy set with
+    /// [`Config::set_cr_params_policy()`] are reported too.
     ///
-    /// Note that a server might observe a new 4-tuple, preventing to
-    /// know in advance to which path the incoming packet belongs to (`recv_pid`
-    /// is `None`). As a client, packets from unknown 4-tuple are dropped
-    /// beforehand (see `recv()`).
+    /// [`setup_careful_resume_from_server()`]: struct.Connection.html#method.setup_careful_resume_from_server
+    /// [`Config::set_cr_params_policy()`]: struct.Config.html#method.set_cr_params_policy
+    pub fn cr_rejection_next(&mut self) -> Option<CrRejectReason> {
+        self.cr_rejections.pop_front()
+    }
+
+    /// Returns the jump made by Careful Resume on the active path when it
+    /// entered the unvalidated phase, in bytes, or `None` if it didn't jump.
     ///
-    /// On error, an error other than [`Done`] is returned.
+    /// This is the increase of the congestion window at the transition, the
+    /// congestion window reached by the jump is also reported as a fraction
+    /// of the previous one in [`PathStats`].
     ///
-    /// [`Done`]: enum.Error.html#variant.Done
-    fn recv_single(
-        &mut self, buf: &mut [u8], info: &RecvInfo, recv_pid: Option<usize>,
-    ) -> Result<usize> {
-        let now = time::Instant::now();
+    /// [`PathStats`]: struct.PathStats.html#structfield.cr_jump_fraction
+    pub fn cr_last_jump(&self) -> Option<usize> {
+        self.paths
+            .get_active()
+            .ok()
+            .and_then(|p| p.recovery.cr_last_jump())
+    }
 
-        if buf.is_empty() {
-            return Err(Error::Done);
+    /// Returns the phase of Careful Resume on the active path, or `None` if
+    /// Careful Resume isn't enabled on it.
+    ///
+    /// ```no_run
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// if let Some(quiche::CarefulResumeState::SafeRetreat { cr_mark }) =
+    ///     conn.careful_resume_state()
+    /// {
+    ///     println!("jump failed, retreating until packet {cr_mark} is acked");
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn careful_resume_state(&self) -> Option<CarefulResumeState> {
+        self.paths
+            .get_active()
+            .ok()
+            .and_then(|p| p.recovery.cr_state())
+    }
+
+    /// Sets the stateless Careful Resume blob previously obtained from the
+    /// server, to be echoed back in the `careful_resume_blob` transport
+    /// parameter.
+    ///
+    /// The server validates the blob and, if it was issued to this client's
+    /// address and hasn't expired, uses the parameters it contains to set up
+    /// careful resume. Invalid blobs are ignored by the server.
+    ///
+    /// This must be called on a client before the first packet is sent,
+    /// otherwise [`InvalidState`] is returned. Blobs longer than
+    /// [`MAX_RESUME_BLOB_LEN`] are rejected with [`BufferTooShort`], and any
+    /// blob set previously is kept.
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    /// [`MAX_RESUME_BLOB_LEN`]: constant.MAX_RESUME_BLOB_LEN.html
+    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
+    pub fn set_resume_blob(&mut self, blob: &[u8]) -> Result<()> {
+        if self.is_server || self.sent_count > 0 {
+            return Err(Error::InvalidState);
         }
 
-        if self.is_closed() || self.is_draining() {
-            return Err(Error::Done);
+        if blob.len() > MAX_RESUME_BLOB_LEN {
+            return Err(Error::BufferTooShort);
         }
 
-        let is_closing = self.local_error.is_some();
+        let previous = self
+            .local_transport_params
+            .careful_resume_blob
+            .replace(blob.to_vec());
 
-        if is_closing {
-            return Err(Error::Done);
-        }
+        // The blob might still not fit along with the other parameters.
+        if let Err(e) = self.encode_transport_params() {
+            self.local_transport_params.careful_resume_blob = previous;
 
-        let buf_len = buf.len();
+            self.encode_transport_params()?;
 
-        let mut b = octets::OctetsMut::with_slice(buf);
+            return Err(e);
+        }
 
-        let mut hdr = Header::from_bytes(&mut b, self.source_id().len())
-            .map_err(|e| {
-                drop_pkt_on_err(
-                    e,
-                    self.recv_count,
-                    self.is_server,
-                    &self.trace_id,
-                )
-            })?;
+        Ok(())
+    }
 
-        if hdr.ty == packet::Type::VersionNegotiation {
-            // Version negotiation packets can only be sent by the server.
-            if self.is_server {
-                return Err(Error::Done);
-            }
+    /// Returns a stateless Careful Resume blob containing the parameters most
+    /// recently observed on the connection, sealed for the peer's current
+    /// address.
+    ///
+    /// The server application is expected to hand the blob over to the
+    /// client, e.g. in a NEW_TOKEN frame using [`send_new_token()`], so that
+    /// the client can echo it with [`set_resume_blob()`] when it reconnects.
+    ///
+    /// Returns `None` if no key was configured with
+    /// [`Config::set_resume_blob_key()`], or no parameters were observed yet.
+    ///
+    /// [`send_new_token()`]: struct.Connection.html#method.send_new_token
+    /// [`set_resume_blob()`]: struct.Connection.html#method.set_resume_blob
+    /// [`Config::set_resume_blob_key()`]: struct.Config.html#method.set_resume_blob_key
+    pub fn resume_blob(&self) -> Option<Vec<u8>> {
+        let key = self.resume_blob_key.as_ref()?;
+        let active_path = self.paths.get_active().ok()?;
+        let params = active_path.latest_cr_event.as_ref()?;
+        let peer = active_path.peer_addr().ip();
 
-            // Ignore duplicate version negotiation.
-            if self.did_version_negotiation {
-                return Err(Error::Done);
-            }
+        key.seal(params, peer, time::SystemTime::now()).ok()
+    }
 
-            // Ignore version negotiation if any other packet has already been
-            // successfully processed.
-            if self.recv_count > 0 {
-                return Err(Error::Done);
-            }
+    /// Returns whether the peer advertised that it accepts stateless Careful
+    /// Resume blobs.
+    pub fn peer_accepts_resume_blob(&self) -> bool {
+        self.peer_transport_params.careful_resume_blob.is_some()
+    }
 
-            if hdr.dcid != self.source_id() {
-                return Err(Error::Done);
-            }
+    /// Returns the latest BDP frame received from the server, or `None` when
+    /// there is no new frame to report.
+    ///
+    /// The application is expected to keep the frame, along with the time it
+    /// was received, and echo it with [`echo_bdp_frame()`] on its next
+    /// connection to the server.
+    ///
+    /// See [`Config::enable_bdp_frame()`].
+    ///
+    /// [`echo_bdp_frame()`]: struct.Connection.html#method.echo_bdp_frame
+    /// [`Config::enable_bdp_frame()`]: struct.Config.html#method.enable_bdp_frame
+    pub fn bdp_frame_next(&mut self) -> Option<BdpFrame> {
+        self.peer_bdp_frame.take()
+    }
 
-            if hdr.scid != self.destination_id() {
-                return Err(Error::Done);
-            }
+    /// Echoes a BDP frame received from the server on a previous connection,
+    /// `age` after it was received.
+    ///
+    /// The frame is sent once the handshake completes if the server
+    /// advertised support for BDP frames. Frames older than their lifetime
+    /// are not sent, and the reason is reported by [`cr_rejection_next()`].
+    ///
+    /// This must be called on a client, otherwise [`InvalidState`] is
+    /// returned.
+    ///
+    /// [`cr_rejection_next()`]: struct.Connection.html#method.cr_rejection_next
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn echo_bdp_frame(
+        &mut self, bdp: &BdpFrame, age: Duration,
+    ) -> Result<()> {
+        if self.is_server {
+            return Err(Error::InvalidState);
+        }
 
-            trace!("{} rx pkt {:?}", self.trace_id, hdr);
+        if age > bdp.lifetime {
+            self.cr_rejections
+                .push_back(recovery::CrRejectReason::Expired);
 
-            let versions = hdr.versions.ok_or(Error::Done)?;
+            return Ok(());
+        }
 
-            // Ignore version negotiation if the version already selected is
-            // listed.
-            if versions.iter().any(|&v| v == self.version) {
-                return Err(Error::Done);
-            }
+        self.bdp_frame_echo = Some(bdp.clone());
 
-            let supported_versions =
-                versions.iter().filter(|&&v| version_is_supported(v));
-
-            let mut found_version = false;
-
-            for &v in supported_versions {
-                found_version = true;
-
-                // The final version takes precedence over draft ones.
-                if v == PROTOCOL_VERSION_V1 {
-                    self.version = v;
-                    break;
-                }
+        Ok(())
+    }
 
-                self.version = cmp::max(self.version, v);
-            }
+    /// Sends revised Careful Resume hints about the path to the client.
+    ///
+    /// The hints are sent in a CR_HINTS frame, replacing any hints sent
+    /// earlier that were not acknowledged yet. They are only sent if both
+    /// endpoints enabled them, see [`Config::enable_cr_hints()`].
+    ///
+    /// This must be called on a server, otherwise [`InvalidState`] is
+    /// returned.
+    ///
+    /// [`Config::enable_cr_hints()`]: struct.Config.html#method.enable_cr_hints
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn send_cr_hints(&mut self, hints: CrHints) -> Result<()> {
+        if !self.is_server {
+            return Err(Error::InvalidState);
+        }
 
-            if !found_version {
-                // We don't support any of the versions offered.
-                //
-                // While a man-in-the-middle attacker might be able to
-                // inject a version negotiation packet that triggers this
-                // failure, the window of opportunity is very small and
-                // this error is quite useful for debugging, so don't just
-                // ignore the packet.
-                return Err(Error::UnknownVersion);
-            }
+        self.cr_hints_seq += 1;
+        self.cr_hints_pending = Some(hints);
 
-            self.did_version_negotiation = true;
+        Ok(())
+    }
 
-            // Derive Initial secrets based on the new version.
-            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
-                &self.destination_id(),
-                self.version,
-                self.is_server,
-            )?;
+    /// Returns the latest Careful Resume hints received from the server, or
+    /// `None` when there are no new hints to report.
+    ///
+    /// The client can use them to set up careful resume on its next
+    /// connection to the server, or to provision the current connection,
+    /// e.g. with [`setup_careful_resume()`].
+    ///
+    /// See [`Config::enable_cr_hints()`].
+    ///
+    /// [`setup_careful_resume()`]: struct.Connection.html#method.setup_careful_resume
+    /// [`Config::enable_cr_hints()`]: struct.Config.html#method.enable_cr_hints
+    pub fn cr_hints_next(&mut self) -> Option<CrHints> {
+        self.peer_cr_hints.take()
+    }
 
-            // Reset connection state to force sending another Initial packet.
-            self.drop_epoch_state(packet::Epoch::Initial, now);
-            self.got_peer_conn_id = false;
-            self.handshake.clear()?;
+    /// Overrides the Careful Resume and congestion control settings of this
+    /// connection with the ones set in `delta`.
+    ///
+    /// This lets a server choose the resume policy of each connection it
+    /// accepts, e.g. based on [`server_name()`], [`application_proto()`] or
+    /// the client address, instead of applying the same [`Config`] to all of
+    /// them. The congestion controller of every path is restarted with the
+    /// new settings, keeping any careful resume parameters already set up
+    /// with [`setup_careful_resume()`] if careful resume remains enabled.
+    ///
+    /// This must be called before the handshake completes, otherwise
+    /// [`InvalidState`] is returned. If the Careful Resume settings are not
+    /// valid with the resulting congestion control algorithm, as checked by
+    /// [`CrConfig::validate()`], [`CongestionControl`] is returned and the
+    /// connection is left unchanged.
+    ///
+    /// [`server_name()`]: struct.Connection.html#method.server_name
+    /// [`application_proto()`]: struct.Connection.html#method.application_proto
+    /// [`Config`]: struct.Config.html
+    /// [`setup_careful_resume()`]: struct.Connection.html#method.setup_careful_resume
+    /// [`CrConfig::validate()`]: struct.CrConfig.html#method.validate
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    /// [`CongestionControl`]: enum.Error.html#variant.CongestionControl
+    pub fn reconfigure(&mut self, delta: &ConfigDelta) -> Result<()> {
+        if self.handshake_completed {
+            return Err(Error::InvalidState);
+        }
 
-            self.pkt_num_spaces[packet::Epoch::Initial].crypto_open =
-                Some(aead_open);
-            self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal =
-                Some(aead_seal);
+        if let Some(cr) = &delta.cr {
+            let cc_algorithm = delta
+                .cc_algorithm
+                .unwrap_or_else(|| self.recovery_config.cc_algorithm());
 
-            self.handshake
-                .use_legacy_codepoint(self.version != PROTOCOL_VERSION_V1);
+            cr.validate(cc_algorithm)?;
 
-            // Encode transport parameters again, as the new version might be
-            // using a different format.
-            self.encode_transport_params()?;
+            self.cr_peer_params_max_age = cr.peer_params_max_age;
+            self.cr_peer_params_max_cwnd = cr.peer_params_max_cwnd;
 
-            return Err(Error::Done);
+            if !cr.resume {
+                self.pending_peer_cr_params = None;
+                self.pending_server_cr_params = None;
+            }
         }
 
-        if hdr.ty == packet::Type::Retry {
-            // Retry packets can only be sent by the server.
-            if self.is_server {
-                return Err(Error::Done);
-            }
+        self.recovery_config.apply_delta(delta);
 
-            // Ignore duplicate retry.
-            if self.did_retry {
-                return Err(Error::Done);
-            }
+        for (_, p) in self.paths.iter_mut() {
+            p.recovery
+                .reconfigure(&self.recovery_config, &self.trace_id);
 
-            // Check if Retry packet is valid.
-            if packet::verify_retry_integrity(
-                &b,
-                &self.destination_id(),
-                self.version,
-            )
-            .is_err()
-            {
-                return Err(Error::Done);
+            if let Some(limiter) = &self.recovery_config.cr_jump_limiter {
+                p.recovery.set_cr_jump_limiter(limiter, p.local_addr().ip());
             }
+        }
 
-            trace!("{} rx pkt {:?}", self.trace_id, hdr);
-
-            self.token = hdr.token;
-            self.did_retry = true;
-
-            // Remember peer's new connection ID.
-            self.odcid = Some(self.destination_id().into_owned());
-
-            self.set_initial_dcid(
-                hdr.scid.clone(),
-                None,
-                self.paths.get_active_path_id()?,
-            )?;
-
-            self.rscid = Some(self.destination_id().into_owned());
-
-            // Derive Initial secrets using the new connection ID.
-            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
-                &hdr.scid,
-                self.version,
-                self.is_server,
-            )?;
+        Ok(())
+    }
 
-            // Reset connection state to force sending another Initial packet.
-            self.drop_epoch_state(packet::Epoch::Initial, now);
-            self.got_peer_conn_id = false;
-            self.handshake.clear()?;
+    /// Resumes the transport state of a previous connection to the same peer.
+    ///
+    /// This is a convenience over calling [`set_session()`],
+    /// [`set_initial_rtt()`] and [`setup_careful_resume()`] individually,
+    /// using the state captured by [`saved_transport()`] when the previous
+    /// connection was closed. Any part of the state that is missing is
+    /// skipped, as are the careful resume parameters when careful resume is
+    /// disabled, and the session is only installed on clients.
+    ///
+    /// This must be called before the first packet is sent, otherwise
+    /// [`InvalidState`] is returned.
+    ///
+    /// [`set_session()`]: struct.Connection.html#method.set_session
+    /// [`set_initial_rtt()`]: struct.Connection.html#method.set_initial_rtt
+    /// [`setup_careful_resume()`]: struct.Connection.html#method.setup_careful_resume
+    /// [`saved_transport()`]: struct.Connection.html#method.saved_transport
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn resume_from(&mut self, previous: &SavedTransport) -> Result<()> {
+        if self.sent_count > 0 {
+            return Err(Error::InvalidState);
+        }
 
-            self.pkt_num_spaces[packet::Epoch::Initial].crypto_open =
-                Some(aead_open);
-            self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal =
-                Some(aead_seal);
+        if let Some(session) = &previous.session {
+            if !self.is_server {
+                self.set_session(session)?;
+            }
+        }
 
-            return Err(Error::Done);
+        if let Some(rtt) = previous.rtt {
+            self.set_initial_rtt(rtt)?;
         }
 
-        if self.is_server && !self.did_version_negotiation {
-            if !version_is_supported(hdr.version) {
-                return Err(Error::UnknownVersion);
+        if let Some(cr) = previous.careful_resume {
+            if self.recovery_config.resume {
+                self.setup_careful_resume(cr.min_rtt, cr.cwnd, cr.pacing_rate)?;
             }
+        }
 
-            self.version = hdr.version;
-            self.did_version_negotiation = true;
+        Ok(())
+    }
 
-            self.handshake
-                .use_legacy_codepoint(self.version != PROTOCOL_VERSION_V1);
+    /// Returns the transport state to be used to resume a future connection
+    /// to the same peer with [`resume_from()`].
+    ///
+    /// This is intended to be called once the connection is closed, so that
+    /// the most recent session and path parameters are captured.
+    ///
+    /// [`resume_from()`]: struct.Connection.html#method.resume_from
+    pub fn saved_transport(&self) -> SavedTransport {
+        let rtt = self.paths.get_active().ok().and_then(|p| {
+            p.recovery.min_rtt().map(|_| p.recovery.rtt())
+        });
 
-            // Encode transport parameters again, as the new version might be
-            // using a different format.
-            self.encode_transport_params()?;
+        SavedTransport {
+            session: self.session.clone(),
+            rtt,
+            careful_resume: self
+                .paths
+                .get_active()
+                .ok()
+                .and_then(|p| p.latest_cr_event),
         }
+    }
 
-        if hdr.ty != packet::Type::Short && hdr.version != self.version {
-            // At this point version negotiation was already performed, so
-            // ignore packets that don't match the connection's version.
-            return Err(Error::Done);
-        }
+    /// Sets the default window for jumps in stream flow credits
+    pub fn setup_default_stream_window(&mut self, window: u64) {
+        self.default_stream_window = Some(window);
+    }
 
-        // Long header packets have an explicit payload length, but short
-        // packets don't so just use the remaining capacity in the buffer.
-        let payload_len = if hdr.ty == packet::Type::Short {
-            b.cap()
-        } else {
-            b.get_varint().map_err(|e| {
-                drop_pkt_on_err(
-                    e.into(),
-                    self.recv_count,
-                    self.is_server,
-                    &self.trace_id,
-                )
-            })? as usize
-        };
+    /// Sets the initial RTT heuristic on the active path, if the application has information about
+    /// what the RTT of the path is likely to be.
+    pub fn set_initial_rtt(&mut self, initial_rtt: Duration) -> Result<()> {
+        self.paths.get_active_mut()?.recovery
+            .set_initial_rtt(initial_rtt);
+        Ok(())
+    }
 
-        // Make sure the buffer is same or larger than an explicit
-        // payload length.
-        if payload_len > b.cap() {
-            return Err(drop_pkt_on_err(
-                Error::InvalidPacket,
-                self.recv_count,
-                self.is_server,
-                &self.trace_id,
-            ));
+    /// Processes QUIC packets received from the peer.
+    ///
+    /// On success the number of bytes processed from the input buffer is
+    /// returned. On error the connection will be closed by calling [`close()`]
+    /// with the appropriate error code.
+    ///
+    /// Coalesced packets will be processed as necessary.
+    ///
+    /// Note that the contents of the input buffer `buf` might be modified by
+    /// this function due to, for example, in-place decryption.
+    ///
+    /// [`close()`]: struct.Connection.html#method.close
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// loop {
+    ///     let (read, from) = socket.recv_from(&mut buf).unwrap();
+    ///
+    ///     let recv_info = quiche::RecvInfo {
+    ///         from,
+    ///         to: local,
+    ///     };
+    ///
+    ///     let read = match conn.recv(&mut buf[..read], recv_info) {
+    ///         Ok(v) => v,
+    ///
+    ///         Err(e) => {
+    ///             // An error occurred, handle it.
+    ///             break;
+    ///         },
+    ///     };
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn recv(&mut self, buf: &mut [u8], info: RecvInfo) -> Result<usize> {
+        let len = buf.len();
+
+        if len == 0 {
+            return Err(Error::BufferTooShort);
         }
 
-        // Derive initial secrets on the server.
-        if !self.derived_initial_secrets {
-            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
-                &hdr.dcid,
-                self.version,
-                self.is_server,
-            )?;
+        if self.pcap.is_some() {
+            let dcid_len = self.source_id().len();
 
-            self.pkt_num_spaces[packet::Epoch::Initial].crypto_open =
-                Some(aead_open);
-            self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal =
-                Some(aead_seal);
+            if let Some(pcap) = &mut self.pcap {
+                let now = time::Instant::now();
 
-            self.derived_initial_secrets = true;
+                pcap.write_datagram(buf, info.from, info.to, dcid_len, now);
+            }
         }
 
-        // Select packet number space epoch based on the received packet's type.
-        let epoch = hdr.ty.to_epoch()?;
+        let recv_pid = self.paths.path_id_from_addrs(&(info.to, info.from));
 
-        // Select AEAD context used to open incoming packet.
-        let aead = if hdr.ty == packet::Type::ZeroRTT {
-            // Only use 0-RTT key if incoming packet is 0-RTT.
-            self.pkt_num_spaces[epoch].crypto_0rtt_open.as_ref()
-        } else {
-            // Otherwise use the packet number space's main key.
-            self.pkt_num_spaces[epoch].crypto_open.as_ref()
-        };
+        if let Some(recv_pid) = recv_pid {
+            let recv_path = self.paths.get_mut(recv_pid)?;
 
-        // Finally, discard packet if no usable key is available.
-        let mut aead = match aead {
-            Some(v) => v,
-
-            None => {
-                if hdr.ty == packet::Type::ZeroRTT &&
-                    self.undecryptable_pkts.len() < MAX_UNDECRYPTABLE_PACKETS &&
-                    !self.is_established()
-                {
-                    // Buffer 0-RTT packets when the required read key is not
-                    // available yet, and process them later.
-                    //
-                    // TODO: in the future we might want to buffer other types
-                    // of undecryptable packets as well.
-                    let pkt_len = b.off() + payload_len;
-                    let pkt = (b.buf()[..pkt_len]).to_vec();
-
-                    self.undecryptable_pkts.push_back((pkt, *info));
-                    return Ok(pkt_len);
-                }
+            // Keep track of how many bytes we received from the client, so we
+            // can limit bytes sent back before address validation, to a
+            // multiple of this. The limit needs to be increased early on, so
+            // that if there is an error there is enough credit to send a
+            // CONNECTION_CLOSE.
+            //
+            // It doesn't matter if the packets received were valid or not, we
+            // only need to track the total amount of bytes received.
+            //
+            // Note that we also need to limit the number of bytes we sent on a
+            // path if we are not the host that initiated its usage.
+            if self.is_server && !recv_path.verified_peer_address {
+                recv_path.max_send_bytes += len * self.max_amplification_factor;
+            }
+        } else if !self.is_server {
+            // If a client receives packets from an unknown server address,
+            // the client MUST discard these packets.
+            trace!(
+                "{} client received packet from unknown address {:?}, dropping",
+                self.trace_id,
+                info,
+            );
 
-                let e = drop_pkt_on_err(
-                    Error::CryptoFail,
-                    self.recv_count,
-                    self.is_server,
-                    &self.trace_id,
-                );
+            return Ok(len);
+        }
 
-                return Err(e);
-            },
-        };
+        let mut done = 0;
+        let mut left = len;
 
-        let aead_tag_len = aead.alg().tag_len();
+        // Process coalesced packets.
+        while left > 0 {
+            let read = match self.recv_single(
+                &mut buf[len - left..len],
+                &info,
+                recv_pid,
+            ) {
+                Ok(v) => v,
 
-        packet::decrypt_hdr(&mut b, &mut hdr, aead).map_err(|e| {
-            drop_pkt_on_err(e, self.recv_count, self.is_server, &self.trace_id)
-        })?;
+                Err(Error::Done) => {
+                    // If the packet can't be processed or decrypted, check if
+                    // it's a stateless reset.
+                    if self.is_stateless_reset(&buf[len - left..len]) {
+                        trace!("{} packet is a stateless reset", self.trace_id);
 
-        let pn = packet::decode_pkt_num(
-            self.pkt_num_spaces[epoch].largest_rx_pkt_num,
-            hdr.pkt_num,
-            hdr.pkt_num_len,
-        );
+                        self.mark_closed();
+                    }
 
-        let pn_len = hdr.pkt_num_len;
+                    left
+                },
 
-        trace!(
-            "{} rx pkt {:?} len={} pn={} {}",
-            self.trace_id,
-            hdr,
-            payload_len,
-            pn,
-            AddrTupleFmt(info.from, info.to)
-        );
+                Err(e) => {
+                    // In case of error processing the incoming packet, close
+                    // the connection.
+                    self.close(false, e.to_wire(), b"").ok();
+                    return Err(e);
+                },
+            };
 
-        if hdr.ty == Type::Initial {
-            if let Some(token) = &hdr.token {
-                if token.len() != 0 {
-                    self.peer_token.replace(token.clone());
-                }
-            }
+            done += read;
+            left -= read;
         }
 
-        #[cfg(feature = "qlog")]
-        let mut qlog_frames = vec![];
+        // Even though the packet was previously "accepted", it
+        // should be safe to forward the error, as it also comes
+        // from the `recv()` method.
+        self.process_undecrypted_0rtt_packets()?;
 
-        // Check for key update.
-        let mut aead_next = None;
+        Ok(done)
+    }
 
-        if self.handshake_confirmed &&
-            hdr.ty != Type::ZeroRTT &&
-            hdr.key_phase != self.key_phase
+    fn process_undecrypted_0rtt_packets(&mut self) -> Result<()> {
+        // Process previously undecryptable 0-RTT packets if the decryption key
+        // is now available.
+        if self.pkt_num_spaces[packet::Epoch::Application]
+            .crypto_0rtt_open
+            .is_some()
         {
-            // Check if this packet arrived before key update.
-            if let Some(key_update) = self.pkt_num_spaces[epoch]
-                .key_update
-                .as_ref()
-                .and_then(|key_update| {
-                    (pn < key_update.pn_on_update).then_some(key_update)
-                })
+            while let Some((mut pkt, info)) = self.undecryptable_pkts.pop_front()
             {
-                aead = &key_update.crypto_open;
-            } else {
-                trace!("{} peer-initiated key update", self.trace_id);
-
-                aead_next = Some((
-                    self.pkt_num_spaces[epoch]
-                        .crypto_open
-                        .as_ref()
-                        .unwrap()
-                        .derive_next_packet_key()?,
-                    self.pkt_num_spaces[epoch]
-                        .crypto_seal
-                        .as_ref()
-                        .unwrap()
-                        .derive_next_packet_key()?,
-                ));
+                if let Err(e) = self.recv(&mut pkt, info) {
+                    self.undecryptable_pkts.clear();
 
-                // `aead_next` is always `Some()` at this point, so the `unwrap()`
-                // will never fail.
-                aead = &aead_next.as_ref().unwrap().0;
+                    return Err(e);
+                }
             }
         }
+        Ok(())
+    }
 
-        let mut payload = packet::decrypt_pkt(
-            &mut b,
-            pn,
-            pn_len,
-            payload_len,
-            aead,
-        )
-        .map_err(|e| {
-            drop_pkt_on_err(e, self.recv_count, self.is_server, &self.trace_id)
-        })?;
-
-        if self.pkt_num_spaces[epoch].recv_pkt_num.contains(pn) {
-            trace!("{} ignored duplicate packet {}", self.trace_id, pn);
-            return Err(Error::Done);
+    /// Returns true if a QUIC packet is a stateless reset.
+    fn is_stateless_reset(&self, buf: &[u8]) -> bool {
+        // If the packet is too small, then we just throw it away.
+        let buf_len = buf.len();
+        if buf_len < 21 {
+            return false;
         }
 
-        // Packets with no frames are invalid.
-        if payload.cap() == 0 {
-            return Err(Error::InvalidPacket);
-        }
+        // TODO: we should iterate over all active destination connection IDs
+        // and check against their reset token.
+        match self.peer_transport_params.stateless_reset_token {
+            Some(token) => {
+                let token_len = 16;
+                ring::constant_time::verify_slices_are_equal(
+                    &token.to_be_bytes(),
+                    &buf[buf_len - token_len..buf_len],
+                )
+                .is_ok()
+            },
 
-        // Now that we decrypted the packet, let's see if we can map it to an
-        // existing path.
-        let recv_pid = if hdr.ty == packet::Type::Short && self.got_peer_conn_id {
-            let pkt_dcid = ConnectionId::from_ref(&hdr.dcid);
-            self.get_or_create_recv_path_id(recv_pid, &pkt_dcid, buf_len, info)?
-        } else {
-            // During handshake, we are on the initial path.
-            self.paths.get_active_path_id()?
-        };
+            None => false,
+        }
+    }
 
-        // The key update is verified once a packet is successfully decrypted
-        // using the new keys.
-        if let Some((open_next, seal_next)) = aead_next {
-            if !self.pkt_num_spaces[epoch]
-                .key_update
-                .as_ref()
-                .map_or(true, |prev| prev.update_acked)
-            {
-                // Peer has updated keys twice without awaiting confirmation.
-                return Err(Error::KeyUpdate);
-            }
+    /// Processes a single QUIC packet received from the peer.
+    ///
+    /// On success the number of bytes processed from the input buffer is
+    /// returned. When the [`Done`] error is returned, processing of the
+    /// remainder of the incoming UDP datagram should be interrupted.
+    ///
+    /// Note that a server might observe a new 4-tuple, preventing to
+    /// know in advance to which path the incoming packet belongs to (`recv_pid`
+    /// is `None`). As a client, packets from unknown 4-tuple are dropped
+    /// beforehand (see `recv()`).
+    ///
+    /// On error, an error other than [`Done`] is returned.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    fn recv_single(
+        &mut self, buf: &mut [u8], info: &RecvInfo, recv_pid: Option<usize>,
+    ) -> Result<usize> {
+        let now = time::Instant::now();
 
-            trace!("{} key update verified", self.trace_id);
+        if buf.is_empty() {
+            return Err(Error::Done);
+        }
 
-            let _ = self.pkt_num_spaces[epoch].crypto_seal.replace(seal_next);
+        if self.is_closed() || self.is_draining() {
+            return Err(Error::Done);
+        }
 
-            let open_prev = self.pkt_num_spaces[epoch]
-                .crypto_open
-                .replace(open_next)
-                .unwrap();
+        let is_closing = self.local_error.is_some();
 
-            let recv_path = self.paths.get_mut(recv_pid)?;
+        if is_closing {
+            return Err(Error::Done);
+        }
 
-            self.pkt_num_spaces[epoch].key_update = Some(packet::KeyUpdate {
-                crypto_open: open_prev,
-                pn_on_update: pn,
-                update_acked: false,
-                timer: now + (recv_path.recovery.pto() * 3),
-            });
+        let buf_len = buf.len();
 
-            self.key_phase = !self.key_phase;
+        let mut b = octets::OctetsMut::with_slice(buf);
 
-            qlog_with_type!(QLOG_PACKET_RX, self.qlog, q, {
-                let trigger = Some(
-                    qlog::events::security::KeyUpdateOrRetiredTrigger::RemoteUpdate,
-                );
+        let mut hdr = Header::from_bytes(&mut b, self.source_id().len())
+            .map_err(|e| {
+                drop_pkt_on_err(
+                    e,
+                    self.recv_count,
+                    self.is_server,
+                    &self.trace_id,
+                )
+            })?;
 
-                let ev_data_client =
-                    EventData::KeyUpdated(qlog::events::security::KeyUpdated {
-                        key_type:
-                            qlog::events::security::KeyType::Client1RttSecret,
-                        old: None,
-                        new: String::new(),
-                        generation: None,
-                        trigger: trigger.clone(),
-                    });
+        if hdr.ty == packet::Type::VersionNegotiation {
+            // Version negotiation packets can only be sent by the server.
+            if self.is_server {
+                return Err(Error::Done);
+            }
 
-                q.add_event_data_with_instant(ev_data_client, now).ok();
+            // Ignore duplicate version negotiation.
+            if self.did_version_negotiation {
+                return Err(Error::Done);
+            }
 
-                let ev_data_server =
-                    EventData::KeyUpdated(qlog::events::security::KeyUpdated {
-                        key_type:
-                            qlog::events::security::KeyType::Server1RttSecret,
-                        old: None,
-                        new: String::new(),
-                        generation: None,
-                        trigger,
-                    });
+            // Ignore version negotiation if any other packet has already been
+            // successfully processed.
+            if self.recv_count > 0 {
+                return Err(Error::Done);
+            }
 
-                q.add_event_data_with_instant(ev_data_server, now).ok();
-            });
-        }
+            if hdr.dcid != self.source_id() {
+                return Err(Error::Done);
+            }
 
-        if !self.is_server && !self.got_peer_conn_id {
-            if self.odcid.is_none() {
-                self.odcid = Some(self.destination_id().into_owned());
+            if hdr.scid != self.destination_id() {
+                return Err(Error::Done);
             }
 
-            // Replace the randomly generated destination connection ID with
-            // the one supplied by the server.
-            self.set_initial_dcid(
-                hdr.scid.clone(),
-                self.peer_transport_params.stateless_reset_token,
-                recv_pid,
-            )?;
+            trace!("{} rx pkt {:?}", self.trace_id, hdr);
 
-            self.got_peer_conn_id = true;
-        }
+            let versions = hdr.versions.ok_or(Error::Done)?;
 
-        if self.is_server && !self.got_peer_conn_id {
-            self.set_initial_dcid(hdr.scid.clone(), None, recv_pid)?;
+            // Ignore version negotiation if the version already selected is
+            // listed.
+            if versions.iter().any(|&v| v == self.version) {
+                return Err(Error::Done);
+            }
 
-            if !self.did_retry {
-                self.local_transport_params
-                    .original_destination_connection_id =
-                    Some(hdr.dcid.to_vec().into());
+            // Pick the most preferred version also supported by the server.
+            let version = self
+                .available_versions
+                .iter()
+                .find(|v| versions.contains(v) && version_is_supported(**v));
 
-                self.encode_transport_params()?;
+            if let Some(&v) = version {
+                self.version = v;
+            } else {
+                // We don't support any of the versions offered.
+                //
+                // While a man-in-the-middle attacker might be able to
+                // inject a version negotiation packet that triggers this
+                // failure, the window of opportunity is very small and
+                // this error is quite useful for debugging, so don't just
+                // ignore the packet.
+                return Err(Error::UnknownVersion);
             }
 
-            self.got_peer_conn_id = true;
-        }
+            self.did_version_negotiation = true;
 
-        // To avoid sending an ACK in response to an ACK-only packet, we need
-        // to keep track of whether this packet contains any frame other than
-        // ACK and PADDING.
-        let mut ack_elicited = false;
+            // Derive Initial secrets based on the new version.
+            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+                &self.destination_id(),
+                self.version,
+                self.is_server,
+            )?;
 
-        // Process packet payload. If a frame cannot be processed, store the
-        // error and stop further packet processing.
-        let mut frame_processing_err = None;
+            // Reset connection state to force sending another Initial packet.
+            self.drop_epoch_state(packet::Epoch::Initial, now);
+            self.got_peer_conn_id = false;
+            self.handshake.clear()?;
 
-        // To know if the peer migrated the connection, we need to keep track
-        // whether this is a non-probing packet.
-        let mut probing = true;
+            // Careful resume starts over, with the same parameters, from the
+            // new Initial packet.
+            self.paths
+                .get_active_mut()?
+                .recovery
+                .reset_careful_resume(true, now);
 
-        // Process packet payload.
-        while payload.cap() > 0 {
-            let frame = frame::Frame::from_bytes(&mut payload, hdr.ty)?;
+            self.pkt_num_spaces[packet::Epoch::Initial].crypto_open =
+                Some(aead_open);
+            self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal =
+                Some(aead_seal);
 
-            qlog_with_type!(QLOG_PACKET_RX, self.qlog, _q, {
-                qlog_frames.push(frame.to_qlog());
-            });
+            self.handshake
+                .use_legacy_codepoint(!version_is_supported(self.version));
 
-            if frame.ack_eliciting() {
-                ack_elicited = true;
+            // Encode transport parameters again, as the new version might be
+            // using a different format.
+            self.encode_transport_params()?;
+
+            return Err(Error::Done);
+        }
+
+        if hdr.ty == packet::Type::Retry {
+            // Retry packets can only be sent by the server.
+            if self.is_server {
+                return Err(Error::Done);
             }
 
-            if !frame.probing() {
-                probing = false;
+            // Ignore duplicate retry.
+            if self.did_retry {
+                return Err(Error::Done);
             }
 
-            if let Err(e) = self.process_frame(frame, &hdr, recv_pid, epoch, now)
+            // Check if Retry packet is valid.
+            if packet::verify_retry_integrity(
+                &b,
+                &self.destination_id(),
+                self.version,
+            )
+            .is_err()
             {
-                frame_processing_err = Some(e);
-                break;
+                return Err(Error::Done);
             }
-        }
 
-        qlog_with_type!(QLOG_PACKET_RX, self.qlog, q, {
-            let packet_size = b.len();
+            trace!("{} rx pkt {:?}", self.trace_id, hdr);
 
-            let qlog_pkt_hdr = qlog::events::quic::PacketHeader::with_type(
-                hdr.ty.to_qlog(),
-                Some(pn),
-                Some(hdr.version),
-                Some(&hdr.scid),
-                Some(&hdr.dcid),
-            );
+            self.token = hdr.token;
+            self.did_retry = true;
 
-            let qlog_raw_info = RawInfo {
-                length: Some(packet_size as u64),
-                payload_length: Some(payload_len as u64),
-                data: None,
-            };
+            // Remember peer's new connection ID.
+            self.odcid = Some(self.destination_id().into_owned());
 
-            let ev_data =
-                EventData::PacketReceived(qlog::events::quic::PacketReceived {
-                    header: qlog_pkt_hdr,
-                    frames: Some(qlog_frames),
-                    is_coalesced: None,
-                    retry_token: None,
-                    stateless_reset_token: None,
-                    supported_versions: None,
-                    raw: Some(qlog_raw_info),
-                    datagram_id: None,
-                    trigger: None,
-                });
+            self.set_initial_dcid(
+                hdr.scid.clone(),
+                None,
+                self.paths.get_active_path_id()?,
+            )?;
 
-            q.add_event_data_with_instant(ev_data, now).ok();
-        });
+            self.rscid = Some(self.destination_id().into_owned());
 
-        let recv_path = self.paths.get_mut(recv_pid)?;
-        qlog_with_type!(QLOG_METRICS, self.qlog, q, {
-            if let Some(ev_data) = recv_path.recovery.maybe_qlog() {
-                q.add_event_data_with_instant(ev_data, now).ok();
-            }
-        });
-        qlog_with_type!(QLOG_CR_PHASE, self.qlog, q, {
-            if let Some(ev_data) = recv_path.recovery.maybe_cr_qlog() {
-                q.add_event_data_with_instant(ev_data, now).ok();
-            }
-        });
+            // Derive Initial secrets using the new connection ID.
+            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+                &hdr.scid,
+                self.version,
+                self.is_server,
+            )?;
 
-        if recv_path.active() {
-            if let Some(cr_event) = recv_path.recovery.maybe_cr_event() {
-                self.update_cr_event(cr_event);
-            }
-        }
+            // Reset connection state to force sending another Initial packet.
+            self.drop_epoch_state(packet::Epoch::Initial, now);
+            self.got_peer_conn_id = false;
+            self.handshake.clear()?;
 
-        if let Some(e) = frame_processing_err {
-            // Any frame error is terminal, so now just return.
-            return Err(e);
+            // Careful resume starts over, with the same parameters, from the
+            // new Initial packet.
+            self.paths
+                .get_active_mut()?
+                .recovery
+                .reset_careful_resume(true, now);
+
+            self.pkt_num_spaces[packet::Epoch::Initial].crypto_open =
+                Some(aead_open);
+            self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal =
+                Some(aead_seal);
+
+            return Err(Error::Done);
         }
 
-        // Only log the remote transport parameters once the connection is
-        // established (i.e. after frames have been fully parsed) and only
-        // once per connection.
-        if self.is_established() {
-            qlog_with_type!(QLOG_PARAMS_SET, self.qlog, q, {
-                if !self.qlog.logged_peer_params {
-                    let ev_data = self
-                        .peer_transport_params
-                        .to_qlog(TransportOwner::Remote, self.handshake.cipher());
+        if self.is_server && !self.did_version_negotiation {
+            if !version_is_supported(hdr.version) {
+                return Err(Error::UnknownVersion);
+            }
 
-                    q.add_event_data_with_instant(ev_data, now).ok();
+            self.version = hdr.version;
+            self.did_version_negotiation = true;
 
-                    self.qlog.logged_peer_params = true;
-                }
-            });
-        }
+            self.handshake
+                .use_legacy_codepoint(!version_is_supported(self.version));
 
-        // Following flag used to upgrade datagram size, if probe is successful.
-        let mut pmtud_probe = false;
+            // Encode transport parameters again, as the new version might be
+            // using a different format.
+            self.encode_transport_params()?;
+        }
 
-        // Process acked frames. Note that several packets from several paths
-        // might have been acked by the received packet.
-        for (_, p) in self.paths.iter_mut() {
-            for acked in p.recovery.get_acked_frames(epoch) {
-                match acked {
-                    frame::Frame::Ping {
-                        mtu_probe: Some(mtu_probe),
-                    } => {
-                        let pmtud_next = p.pmtud.get_current();
-                        p.pmtud.set_current(cmp::max(pmtud_next, mtu_probe));
-
-                        // Stop sending path MTU probes after successful probe.
-                        p.pmtud.should_probe(false);
-                        pmtud_probe = true;
-
-                        trace!(
-                            "{} pmtud acked; pmtu size {:?}",
-                            self.trace_id,
-                            p.pmtud.get_current()
-                        );
-                    },
-
-                    frame::Frame::ACK { ranges, .. } => {
-                        // Stop acknowledging packets less than or equal to the
-                        // largest acknowledged in the sent ACK frame that, in
-                        // turn, got acked.
-                        if let Some(largest_acked) = ranges.last() {
-                            self.pkt_num_spaces[epoch]
-                                .recv_pkt_need_ack
-                                .remove_until(largest_acked);
-                        }
-                    },
-
-                    frame::Frame::CryptoHeader { offset, length } => {
-                        self.pkt_num_spaces[epoch]
-                            .crypto_stream
-                            .send
-                            .ack_and_drop(offset, length);
-                    },
-
-                    frame::Frame::StreamHeader {
-                        stream_id,
-                        offset,
-                        length,
-                        ..
-                    } => {
-                        let stream = match self.streams.get_mut(stream_id) {
-                            Some(v) => v,
+        // The server can switch the connection to a compatible version when
+        // replying to the client's first flight (RFC 9368), in which case the
+        // Initial keys need to be derived again for the new version. The
+        // switch is only made once the packet decrypts under them, so that a
+        // spoofed Initial can't break the handshake.
+        let mut version_next = None;
+
+        if !self.is_server &&
+            hdr.ty == packet::Type::Initial &&
+            hdr.version != self.version &&
+            self.recv_count == 0 &&
+            version_is_supported(hdr.version) &&
+            self.available_versions.contains(&hdr.version)
+        {
+            let keys = crypto::derive_initial_key_material(
+                &self.destination_id(),
+                hdr.version,
+                self.is_server,
+            )?;
 
-                            None => continue,
-                        };
+            version_next = Some((hdr.version, keys));
+        }
 
-                        stream.send.ack_and_drop(offset, length);
+        if hdr.ty != packet::Type::Short &&
+            hdr.version != self.version &&
+            version_next.is_none()
+        {
+            // At this point version negotiation was already performed, so
+            // ignore packets that don't match the connection's version.
+            return Err(Error::Done);
+        }
 
-                        self.tx_buffered =
-                            self.tx_buffered.saturating_sub(length);
+        // Long header packets have an explicit payload length, but short
+        // packets don't so just use the remaining capacity in the buffer.
+        let payload_len = if hdr.ty == packet::Type::Short {
+            b.cap()
+        } else {
+            b.get_varint().map_err(|e| {
+                drop_pkt_on_err(
+                    e.into(),
+                    self.recv_count,
+                    self.is_server,
+                    &self.trace_id,
+                )
+            })? as usize
+        };
 
-                        qlog_with_type!(QLOG_DATA_MV, self.qlog, q, {
-                            let ev_data = EventData::DataMoved(
-                                qlog::events::quic::DataMoved {
-                                    stream_id: Some(stream_id),
-                                    offset: Some(offset),
-                                    length: Some(length as u64),
-                                    from: Some(DataRecipient::Transport),
-                                    to: Some(DataRecipient::Dropped),
-                                    raw: None,
-                                },
-                            );
+        // Make sure the buffer is same or larger than an explicit
+        // payload length.
+        if payload_len > b.cap() {
+            return Err(drop_pkt_on_err(
+                Error::InvalidPacket,
+                self.recv_count,
+                self.is_server,
+                &self.trace_id,
+            ));
+        }
 
-                            q.add_event_data_with_instant(ev_data, now).ok();
-                        });
+        // Derive initial secrets on the server.
+        if !self.derived_initial_secrets {
+            let (aead_open, aead_seal) = crypto::derive_initial_key_material(
+                &hdr.dcid,
+                self.version,
+                self.is_server,
+            )?;
 
-                        // Only collect the stream if it is complete and not
-                        // readable. If it is readable, it will get collected when
-                        // stream_recv() is used.
-                        if stream.is_complete() && !stream.is_readable() {
-                            let local = stream.local;
-                            self.streams.collect(stream_id, local);
-                        }
-                    },
+            self.pkt_num_spaces[packet::Epoch::Initial].crypto_open =
+                Some(aead_open);
+            self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal =
+                Some(aead_seal);
 
-                    frame::Frame::HandshakeDone => {
-                        // Explicitly set this to true, so that if the frame was
-                        // already scheduled for retransmission, it is aborted.
-                        self.handshake_done_sent = true;
+            self.derived_initial_secrets = true;
+        }
 
-                        self.handshake_done_acked = true;
-                    },
+        // Select packet number space epoch based on the received packet's type.
+        let epoch = hdr.ty.to_epoch()?;
 
-                    frame::Frame::ResetStream { stream_id, .. } => {
-                        let stream = match self.streams.get_mut(stream_id) {
-                            Some(v) => v,
+        // Select AEAD context used to open incoming packet.
+        let aead = if let Some((_, (aead_open, _))) = &version_next {
+            // Use the key of the version the server switched to.
+            Some(aead_open)
+        } else if hdr.ty == packet::Type::ZeroRTT {
+            // Only use 0-RTT key if incoming packet is 0-RTT.
+            self.pkt_num_spaces[epoch].crypto_0rtt_open.as_ref()
+        } else {
+            // Otherwise use the packet number space's main key.
+            self.pkt_num_spaces[epoch].crypto_open.as_ref()
+        };
 
-                            None => continue,
-                        };
+        // Finally, discard packet if no usable key is available.
+        let mut aead = match aead {
+            Some(v) => v,
 
-                        // Only collect the stream if it is complete and not
-                        // readable. If it is readable, it will get collected when
-                        // stream_recv() is used.
-                        if stream.is_complete() && !stream.is_readable() {
-                            let local = stream.local;
-                            self.streams.collect(stream_id, local);
-                        }
-                    },
+            None => {
+                if hdr.ty == packet::Type::ZeroRTT &&
+                    self.undecryptable_pkts.len() < MAX_UNDECRYPTABLE_PACKETS &&
+                    !self.is_established()
+                {
+                    // Buffer 0-RTT packets when the required read key is not
+                    // available yet, and process them later.
+                    //
+                    // TODO: in the future we might want to buffer other types
+                    // of undecryptable packets as well.
+                    let pkt_len = b.off() + payload_len;
+                    let pkt = (b.buf()[..pkt_len]).to_vec();
 
-                    _ => (),
+                    self.undecryptable_pkts.push_back((pkt, *info));
+                    return Ok(pkt_len);
                 }
-            }
-
-            // Update max datagram send size with newly acked probe size.
-            if pmtud_probe {
-                trace!(
-                    "{} updating pmtu {:?}",
-                    p.pmtud.get_current(),
-                    self.trace_id
-                );
-
-                qlog_with_type!(
-                    EventType::ConnectivityEventType(
-                        ConnectivityEventType::MtuUpdated
-                    ),
-                    self.qlog,
-                    q,
-                    {
-                        let pmtu_data = EventData::MtuUpdated(
-                            qlog::events::connectivity::MtuUpdated {
-                                old: Some(p.recovery.max_datagram_size() as u16),
-                                new: p.pmtud.get_current() as u16,
-                                done: Some(pmtud_probe),
-                            },
-                        );
 
-                        q.add_event_data_with_instant(pmtu_data, now).ok();
-                    }
+                let e = drop_pkt_on_err(
+                    Error::CryptoFail,
+                    self.recv_count,
+                    self.is_server,
+                    &self.trace_id,
                 );
 
-                p.recovery
-                    .pmtud_update_max_datagram_size(p.pmtud.get_current());
-            }
-        }
+                return Err(e);
+            },
+        };
 
-        // Now that we processed all the frames, if there is a path that has no
-        // Destination CID, try to allocate one.
-        let no_dcid = self
-            .paths
-            .iter_mut()
-            .filter(|(_, p)| p.active_dcid_seq.is_none());
+        let aead_tag_len = aead.alg().tag_len();
 
-        for (pid, p) in no_dcid {
-            if self.ids.zero_length_dcid() {
-                p.active_dcid_seq = Some(0);
-                continue;
-            }
+        packet::decrypt_hdr(&mut b, &mut hdr, aead).map_err(|e| {
+            drop_pkt_on_err(e, self.recv_count, self.is_server, &self.trace_id)
+        })?;
 
-            let dcid_seq = match self.ids.lowest_available_dcid_seq() {
-                Some(seq) => seq,
-                None => break,
-            };
+        let pn = packet::decode_pkt_num(
+            self.pkt_num_spaces[epoch].largest_rx_pkt_num,
+            hdr.pkt_num,
+            hdr.pkt_num_len,
+        );
 
-            self.ids.link_dcid_to_path_id(dcid_seq, pid)?;
+        let pn_len = hdr.pkt_num_len;
 
-            p.active_dcid_seq = Some(dcid_seq);
-        }
+        trace!(
+            "{} rx pkt {:?} len={} pn={} {}",
+            self.trace_id,
+            hdr,
+            payload_len,
+            pn,
+            AddrTupleFmt(info.from, info.to)
+        );
 
-        // We only record the time of arrival of the largest packet number
-        // that still needs to be acked, to be used for ACK delay calculation.
-        if self.pkt_num_spaces[epoch].recv_pkt_need_ack.last() < Some(pn) {
-            self.pkt_num_spaces[epoch].largest_rx_pkt_time = now;
+        if hdr.ty == Type::Initial {
+            if let Some(token) = &hdr.token {
+                if token.len() != 0 {
+                    self.peer_token.replace(token.clone());
+                }
+            }
         }
 
-        self.pkt_num_spaces[epoch].recv_pkt_num.insert(pn);
+        #[cfg(feature = "qlog")]
+        let mut qlog_frames = vec![];
 
-        self.pkt_num_spaces[epoch].recv_pkt_need_ack.push_item(pn);
+        // Check for key update.
+        let mut aead_next = None;
 
-        self.pkt_num_spaces[epoch].ack_elicited =
-            cmp::max(self.pkt_num_spaces[epoch].ack_elicited, ack_elicited);
+        if self.handshake_confirmed &&
+            hdr.ty != Type::ZeroRTT &&
+            hdr.key_phase != self.key_phase
+        {
+            // Check if this packet arrived before key update.
+            if let Some(key_update) = self.pkt_num_spaces[epoch]
+                .key_update
+                .as_ref()
+                .and_then(|key_update| {
+                    (pn < key_update.pn_on_update).then_some(key_update)
+                })
+            {
+                aead = &key_update.crypto_open;
+            } else {
+                trace!("{} peer-initiated key update", self.trace_id);
 
-        self.pkt_num_spaces[epoch].largest_rx_pkt_num =
-            cmp::max(self.pkt_num_spaces[epoch].largest_rx_pkt_num, pn);
+                aead_next = Some((
+                    self.pkt_num_spaces[epoch]
+                        .crypto_open
+                        .as_ref()
+                        .unwrap()
+                        .derive_next_packet_key()?,
+                    self.pkt_num_spaces[epoch]
+                        .crypto_seal
+                        .as_ref()
+                        .unwrap()
+                        .derive_next_packet_key()?,
+                ));
 
-        if !probing {
-            self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num = cmp::max(
-                self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num,
-                pn,
+                // `aead_next` is always `Some()` at this point, so the `unwrap()`
+                // will never fail.
+                aead = &aead_next.as_ref().unwrap().0;
+            }
+        }
+
+        let crypto_start = time::Instant::now();
+
+        let payload = packet::decrypt_pkt(&mut b, pn, pn_len, payload_len, aead);
+
+        self.crypto_time += crypto_start.elapsed();
+
+        let mut payload = payload.map_err(|e| {
+            drop_pkt_on_err(e, self.recv_count, self.is_server, &self.trace_id)
+        })?;
+
+        // The version switch is verified now that the packet was successfully
+        // decrypted using the new version's keys.
+        if let Some((version, (aead_open, aead_seal))) = version_next {
+            trace!(
+                "{} server switched version {:x} -> {:x}",
+                self.trace_id,
+                self.version,
+                version
             );
 
-            // Did the peer migrated to another path?
-            let active_path_id = self.paths.get_active_path_id()?;
+            self.version = version;
 
-            if self.is_server &&
-                recv_pid != active_path_id &&
-                self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num == pn
+            self.pkt_num_spaces[packet::Epoch::Initial].crypto_open =
+                Some(aead_open);
+            self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal =
+                Some(aead_seal);
+        }
+
+        self.crypto_opened_bytes += payload.len() as u64;
+
+        if self.pkt_num_spaces[epoch].recv_pkt_num.contains(pn) {
+            trace!("{} ignored duplicate packet {}", self.trace_id, pn);
+            return Err(Error::Done);
+        }
+
+        // Packets with no frames are invalid.
+        if payload.cap() == 0 {
+            return Err(Error::InvalidPacket);
+        }
+
+        // Now that we decrypted the packet, let's see if we can map it to an
+        // existing path.
+        let recv_pid = if hdr.ty == packet::Type::Short && self.got_peer_conn_id {
+            let pkt_dcid = ConnectionId::from_ref(&hdr.dcid);
+            self.get_or_create_recv_path_id(recv_pid, &pkt_dcid, buf_len, info)?
+        } else {
+            // During handshake, we are on the initial path.
+            self.paths.get_active_path_id()?
+        };
+
+        // The key update is verified once a packet is successfully decrypted
+        // using the new keys.
+        if let Some((open_next, seal_next)) = aead_next {
+            if !self.pkt_num_spaces[epoch]
+                .key_update
+                .as_ref()
+                .map_or(true, |prev| prev.update_acked)
             {
-                self.on_peer_migrated(recv_pid, self.disable_dcid_reuse, now)?;
+                // Peer has updated keys twice without awaiting confirmation.
+                return Err(Error::KeyUpdate);
             }
-        }
 
-        if let Some(idle_timeout) = self.idle_timeout() {
-            self.idle_timer = Some(now + idle_timeout);
+            trace!("{} key update verified", self.trace_id);
+
+            let _ = self.pkt_num_spaces[epoch].crypto_seal.replace(seal_next);
+
+            let open_prev = self.pkt_num_spaces[epoch]
+                .crypto_open
+                .replace(open_next)
+                .unwrap();
+
+            let recv_path = self.paths.get_mut(recv_pid)?;
+
+            self.pkt_num_spaces[epoch].key_update = Some(packet::KeyUpdate {
+                crypto_open: open_prev,
+                pn_on_update: pn,
+                update_acked: false,
+                timer: now + (recv_path.recovery.pto() * 3),
+            });
+
+            self.key_phase = !self.key_phase;
+
+            qlog_with_type!(QLOG_PACKET_RX, self.qlog, q, {
+                let trigger = Some(
+                    qlog::events::security::KeyUpdateOrRetiredTrigger::RemoteUpdate,
+                );
+
+                let ev_data_client =
+                    EventData::KeyUpdated(qlog::events::security::KeyUpdated {
+                        key_type:
+                            qlog::events::security::KeyType::Client1RttSecret,
+                        old: None,
+                        new: String::new(),
+                        generation: None,
+                        trigger: trigger.clone(),
+                    });
+
+                q.add_event_data_with_instant(ev_data_client, now).ok();
+
+                let ev_data_server =
+                    EventData::KeyUpdated(qlog::events::security::KeyUpdated {
+                        key_type:
+                            qlog::events::security::KeyType::Server1RttSecret,
+                        old: None,
+                        new: String::new(),
+                        generation: None,
+                        trigger,
+                    });
+
+                q.add_event_data_with_instant(ev_data_server, now).ok();
+            });
         }
 
-        // Update send capacity.
-        self.update_tx_cap();
+        if !self.is_server && !self.got_peer_conn_id {
+            if self.odcid.is_none() {
+                self.odcid = Some(self.destination_id().into_owned());
+            }
 
-        self.recv_count += 1;
-        self.paths.get_mut(recv_pid)?.recv_count += 1;
+            // Replace the randomly generated destination connection ID with
+            // the one supplied by the server.
+            self.set_initial_dcid(
+                hdr.scid.clone(),
+                self.peer_transport_params.stateless_reset_token,
+                recv_pid,
+            )?;
 
-        let read = b.off() + aead_tag_len;
+            self.got_peer_conn_id = true;
+        }
 
-        self.recv_bytes += read as u64;
-        self.paths.get_mut(recv_pid)?.recv_bytes += read as u64;
+        if self.is_server && !self.got_peer_conn_id {
+            self.set_initial_dcid(hdr.scid.clone(), None, recv_pid)?;
 
-        // An Handshake packet has been received from the client and has been
-        // successfully processed, so we can drop the initial state and consider
-        // the client's address to be verified.
-        if self.is_server && hdr.ty == packet::Type::Handshake {
-            self.drop_epoch_state(packet::Epoch::Initial, now);
+            if !self.did_retry {
+                self.local_transport_params
+                    .original_destination_connection_id =
+                    Some(hdr.dcid.to_vec().into());
 
-            self.paths.get_mut(recv_pid)?.verified_peer_address = true;
+                self.encode_transport_params()?;
+            }
+
+            self.got_peer_conn_id = true;
         }
 
-        self.ack_eliciting_sent = false;
+        // To avoid sending an ACK in response to an ACK-only packet, we need
+        // to keep track of whether this packet contains any frame other than
+        // ACK and PADDING.
+        let mut ack_elicited = false;
 
-        Ok(read)
-    }
+        // Process packet payload. If a frame cannot be processed, store the
+        // error and stop further packet processing.
+        let mut frame_processing_err = None;
 
-    /// Writes a single QUIC packet to be sent to the peer.
-    ///
-    /// On success the number of bytes written to the output buffer is
-    /// returned, or [`Done`] if there was nothing to write.
-    ///
-    /// The application should call `send()` multiple times until [`Done`] is
-    /// returned, indicating that there are no more packets to send. It is
-    /// recommended that `send()` be called in the following cases:
-    ///
-    ///  * When the application receives QUIC packets from the peer (that is,
-    ///    any time [`recv()`] is also called).
-    ///
-    ///  * When the connection timer expires (that is, any time [`on_timeout()`]
-    ///    is also called).
-    ///
-    ///  * When the application sends data to the peer (for example, any time
-    ///    [`stream_send()`] or [`stream_shutdown()`] are called).
-    ///
-    ///  * When the application receives data from the peer (for example any
-    ///    time [`stream_recv()`] is called).
-    ///
-    /// Once [`is_draining()`] returns `true`, it is no longer necessary to call
-    /// `send()` and all calls will return [`Done`].
-    ///
-    /// [`Done`]: enum.Error.html#variant.Done
-    /// [`recv()`]: struct.Connection.html#method.recv
-    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
-    /// [`stream_send()`]: struct.Connection.html#method.stream_send
-    /// [`stream_shutdown()`]: struct.Connection.html#method.stream_shutdown
-    /// [`stream_recv()`]: struct.Connection.html#method.stream_recv
-    /// [`is_draining()`]: struct.Connection.html#method.is_draining
-    ///
-    /// ## Examples:
-    ///
-    /// ```no_run
-    /// # let mut out = [0; 512];
-    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
-    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
-    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let peer = "127.0.0.1:1234".parse().unwrap();
-    /// # let local = socket.local_addr().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
-    /// loop {
-    ///     let (write, send_info) = match conn.send(&mut out) {
-    ///         Ok(v) => v,
-    ///
-    ///         Err(quiche::Error::Done) => {
-    ///             // Done writing.
-    ///             break;
-    ///         },
-    ///
-    ///         Err(e) => {
-    ///             // An error occurred, handle it.
-    ///             break;
-    ///         },
-    ///     };
-    ///
-    ///     socket.send_to(&out[..write], &send_info.to).unwrap();
-    /// }
-    /// # Ok::<(), quiche::Error>(())
-    /// ```
-    pub fn send(&mut self, out: &mut [u8]) -> Result<(usize, SendInfo)> {
-        self.send_on_path(out, None, None)
-    }
+        // To know if the peer migrated the connection, we need to keep track
+        // whether this is a non-probing packet.
+        let mut probing = true;
 
-    /// Writes a single QUIC packet to be sent to the peer from the specified
-    /// local address `from` to the destination address `to`.
-    ///
-    /// The behavior of this method differs depending on the value of the `from`
-    /// and `to` parameters:
-    ///
-    ///  * If both are `Some`, then the method only consider the 4-tuple
-    ///    (`from`, `to`). Application can monitor the 4-tuple availability,
-    ///    either by monitoring [`path_event_next()`] events or by relying on
-    ///    the [`paths_iter()`] method. If the provided 4-tuple does not exist
-    ///    on the connection (anymore), it returns an [`InvalidState`].
-    ///
-    ///  * If `from` is `Some` and `to` is `None`, then the method only
-    ///    considers sending packets on paths having `from` as local address.
-    ///
-    ///  * If `to` is `Some` and `from` is `None`, then the method only
-    ///    considers sending packets on paths having `to` as peer address.
-    ///
-    ///  * If both are `None`, all available paths are considered.
-    ///
-    /// On success the number of bytes written to the output buffer is
-    /// returned, or [`Done`] if there was nothing to write.
-    ///
-    /// The application should call `send_on_path()` multiple times until
-    /// [`Done`] is returned, indicating that there are no more packets to
-    /// send. It is recommended that `send_on_path()` be called in the
-    /// following cases:
-    ///
-    ///  * When the application receives QUIC packets from the peer (that is,
-    ///    any time [`recv()`] is also called).
-    ///
-    ///  * When the connection timer expires (that is, any time [`on_timeout()`]
-    ///    is also called).
-    ///
-    ///  * When the application sends data to the peer (for examples, any time
-    ///    [`stream_send()`] or [`stream_shutdown()`] are called).
-    ///
-    ///  * When the application receives data from the peer (for example any
-    ///    time [`stream_recv()`] is called).
-    ///
-    /// Once [`is_draining()`] returns `true`, it is no longer necessary to call
-    /// `send_on_path()` and all calls will return [`Done`].
-    ///
-    /// [`Done`]: enum.Error.html#variant.Done
-    /// [`InvalidState`]: enum.Error.html#InvalidState
-    /// [`recv()`]: struct.Connection.html#method.recv
-    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
-    /// [`stream_send()`]: struct.Connection.html#method.stream_send
-    /// [`stream_shutdown()`]: struct.Connection.html#method.stream_shutdown
-    /// [`stream_recv()`]: struct.Connection.html#method.stream_recv
-    /// [`path_event_next()`]: struct.Connection.html#method.path_event_next
-    /// [`paths_iter()`]: struct.Connection.html#method.paths_iter
-    /// [`is_draining()`]: struct.Connection.html#method.is_draining
-    ///
-    /// ## Examples:
-    ///
-    /// ```no_run
-    /// # let mut out = [0; 512];
-    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
-    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
-    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let peer = "127.0.0.1:1234".parse().unwrap();
-    /// # let local = socket.local_addr().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
-    /// loop {
-    ///     let (write, send_info) = match conn.send_on_path(&mut out, Some(local), Some(peer)) {
-    ///         Ok(v) => v,
-    ///
-    ///         Err(quiche::Error::Done) => {
-    ///             // Done writing.
-    ///             break;
-    ///         },
-    ///
-    ///         Err(e) => {
-    ///             // An error occurred, handle it.
-    ///             break;
-    ///         },
-    ///     };
-    ///
-    ///     socket.send_to(&out[..write], &send_info.to).unwrap();
-    /// }
-    /// # Ok::<(), quiche::Error>(())
-    /// ```
-    pub fn send_on_path(
-        &mut self, out: &mut [u8], from: Option<SocketAddr>,
-        to: Option<SocketAddr>,
-    ) -> Result<(usize, SendInfo)> {
-        if out.is_empty() {
-            return Err(Error::BufferTooShort);
-        }
-
-        if self.is_closed() || self.is_draining() {
-            return Err(Error::Done);
-        }
+        // Process packet payload.
+        while payload.cap() > 0 {
+            let frame = frame::Frame::from_bytes(&mut payload, hdr.ty)?;
 
-        let now = time::Instant::now();
+            qlog_with_type!(QLOG_PACKET_RX, self.qlog, _q, {
+                qlog_frames.push(frame.to_qlog());
+            });
 
-        if self.local_error.is_none() {
-            self.do_handshake(now)?;
-        }
+            if frame.ack_eliciting() {
+                ack_elicited = true;
+            }
 
-        // Forwarding the error value here could confuse
-        // applications, as they may not expect getting a `recv()`
-        // error when calling `send()`.
-        //
-        // We simply fall-through to sending packets, which should
-        // take care of terminating the connection as needed.
-        let _ = self.process_undecrypted_0rtt_packets();
+            if !frame.probing() {
+                probing = false;
+            }
 
-        // There's no point in trying to send a packet if the Initial secrets
-        // have not been derived yet, so return early.
-        if !self.derived_initial_secrets {
-            return Err(Error::Done);
+            if let Err(e) = self.process_frame(frame, &hdr, recv_pid, epoch, now)
+            {
+                frame_processing_err = Some(e);
+                break;
+            }
         }
 
-        let mut has_initial = false;
-
-        let mut done = 0;
+        qlog_with_type!(QLOG_PACKET_RX, self.qlog, q, {
+            let packet_size = b.len();
 
-        // Limit output packet size to respect the sender and receiver's
-        // maximum UDP payload size limit.
-        let mut left = cmp::min(out.len(), self.max_send_udp_payload_size());
+            let qlog_pkt_hdr = qlog::events::quic::PacketHeader::with_type(
+                hdr.ty.to_qlog(),
+                Some(pn),
+                Some(hdr.version),
+                Some(&hdr.scid),
+                Some(&hdr.dcid),
+            );
 
-        let send_pid = match (from, to) {
-            (Some(f), Some(t)) => self
-                .paths
-                .path_id_from_addrs(&(f, t))
-                .ok_or(Error::InvalidState)?,
+            let qlog_raw_info = RawInfo {
+                length: Some(packet_size as u64),
+                payload_length: Some(payload_len as u64),
+                data: None,
+            };
 
-            _ => self.get_send_path_id(from, to)?,
-        };
+            let ev_data =
+                EventData::PacketReceived(qlog::events::quic::PacketReceived {
+                    header: qlog_pkt_hdr,
+                    frames: Some(qlog_frames),
+                    is_coalesced: None,
+                    retry_token: None,
+                    stateless_reset_token: None,
+                    supported_versions: None,
+                    raw: Some(qlog_raw_info),
+                    datagram_id: None,
+                    trigger: None,
+                });
 
-        let send_path = self.paths.get_mut(send_pid)?;
+            q.add_event_data_with_instant(ev_data, now).ok();
+        });
 
-        // Update max datagram size to allow path MTU discovery probe to be sent.
-        if send_path.pmtud.get_probe_status() {
-            let size = if self.handshake_confirmed || self.handshake_done_sent {
-                send_path.pmtud.get_probe_size()
-            } else {
-                send_path.pmtud.get_current()
-            };
+        let recv_path = self.paths.get_mut(recv_pid)?;
+        qlog_with_type!(QLOG_METRICS, self.qlog, q, {
+            let sampler = &mut self.qlog.metrics_sampler;
 
-            send_path.recovery.pmtud_update_max_datagram_size(size);
+            if let Some(ev_data) =
+                recv_path.recovery.maybe_qlog(|| sampler.sample())
+            {
+                self.qlog.stats.add_event_data(q, ev_data, now);
+            }
+        });
+        qlog_with_type!(QLOG_CR_PHASE, self.qlog, q, {
+            if let Some(ev_data) = recv_path.recovery.maybe_cr_qlog() {
+                self.qlog.stats.add_event_data(q, ev_data, now);
+            }
+        });
 
-            left = cmp::min(out.len(), send_path.recovery.max_datagram_size());
+        if recv_path.active() {
+            if let Some(cr_event) = recv_path.recovery.maybe_cr_event(now) {
+                self.update_cr_event(recv_pid, cr_event);
+            }
         }
 
-        // Limit data sent by the server based on the amount of data received
-        // from the client before its address is validated.
-        if !send_path.verified_peer_address && self.is_server {
-            left = cmp::min(left, send_path.max_send_bytes);
+        self.invalidate_cr_params();
+
+        if let Some(e) = frame_processing_err {
+            // Any frame error is terminal, so now just return.
+            return Err(e);
         }
 
-        // Generate coalesced packets.
-        while left > 0 {
-            let (ty, written) = match self.send_single(
-                &mut out[done..done + left],
-                send_pid,
-                has_initial,
-                now,
-            ) {
-                Ok(v) => v,
+        // Only log the remote transport parameters once the connection is
+        // established (i.e. after frames have been fully parsed) and only
+        // once per connection.
+        if self.is_established() {
+            qlog_with_type!(QLOG_PARAMS_SET, self.qlog, q, {
+                if !self.qlog.logged_peer_params {
+                    let ev_data = self
+                        .peer_transport_params
+                        .to_qlog(TransportOwner::Remote, self.handshake.cipher());
 
-                Err(Error::BufferTooShort) | Err(Error::Done) => break,
+                    q.add_event_data_with_instant(ev_data, now).ok();
 
-                Err(e) => return Err(e),
-            };
+                    self.qlog.logged_peer_params = true;
+                }
+            });
+        }
 
-            done += written;
-            left -= written;
+        // Following flag used to upgrade datagram size, if probe is successful.
+        let mut pmtud_probe = false;
 
-            match ty {
-                packet::Type::Initial => has_initial = true,
+        // Process acked frames. Note that several packets from several paths
+        // might have been acked by the received packet.
+        for (_, p) in self.paths.iter_mut() {
+            for acked in p.recovery.get_acked_frames(epoch) {
+                match acked {
+                    frame::Frame::Ping {
+                        mtu_probe: Some(mtu_probe),
+                    } => {
+                        let pmtud_next = p.pmtud.get_current();
+                        p.pmtud.set_current(cmp::max(pmtud_next, mtu_probe));
 
-                // No more packets can be coalesced after a 1-RTT.
-                packet::Type::Short => break,
+                        // Stop sending path MTU probes after successful probe.
+                        p.pmtud.should_probe(false);
+                        pmtud_probe = true;
 
-                _ => (),
-            };
+                        trace!(
+                            "{} pmtud acked; pmtu size {:?}",
+                            self.trace_id,
+                            p.pmtud.get_current()
+                        );
+                    },
 
-            // When sending multiple PTO probes, don't coalesce them together,
-            // so they are sent on separate UDP datagrams.
-            if let Ok(epoch) = ty.to_epoch() {
-                if self.paths.get_mut(send_pid)?.recovery.loss_probes(epoch) > 0 {
-                    break;
-                }
-            }
-
-            // Don't coalesce packets that must go on different paths.
-            if !(from.is_some() && to.is_some()) &&
-                self.get_send_path_id(from, to)? != send_pid
-            {
-                break;
-            }
-        }
-
-        self.update_tx_cap();
-
-        if done == 0 {
-            self.last_tx_data = self.tx_data;
-
-            return Err(Error::Done);
-        }
-
-        // Pad UDP datagram if it contains a QUIC Initial packet.
-        #[cfg(not(feature = "fuzzing"))]
-        if has_initial && left > 0 && done < MIN_CLIENT_INITIAL_LEN {
-            let pad_len = cmp::min(left, MIN_CLIENT_INITIAL_LEN - done);
-
-            // Fill padding area with null bytes, to avoid leaking information
-            // in case the application reuses the packet buffer.
-            out[done..done + pad_len].fill(0);
-
-            done += pad_len;
-        }
-
-        let send_path = self.paths.get(send_pid)?;
-
-        let info = SendInfo {
-            from: send_path.local_addr(),
-            to: send_path.peer_addr(),
-
-            at: send_path.recovery.get_packet_send_time(),
-        };
-
-        Ok((done, info))
-    }
-
-    fn send_single(
-        &mut self, out: &mut [u8], send_pid: usize, has_initial: bool,
-        now: time::Instant,
-    ) -> Result<(packet::Type, usize)> {
-        if out.is_empty() {
-            return Err(Error::BufferTooShort);
-        }
-
-        if self.is_draining() {
-            return Err(Error::Done);
-        }
-
-        let is_closing = self.local_error.is_some();
-
-        let out_len = out.len();
-
-        let mut b = octets::OctetsMut::with_slice(out);
-
-        let pkt_type = self.write_pkt_type(send_pid)?;
-
-        let max_dgram_len = if !self.dgram_send_queue.is_empty() {
-            self.dgram_max_writable_len()
-        } else {
-            None
-        };
-
-        let epoch = pkt_type.to_epoch()?;
-        let pkt_space = &mut self.pkt_num_spaces[epoch];
+                    frame::Frame::ACK { ranges, .. } => {
+                        // Stop acknowledging packets less than or equal to the
+                        // largest acknowledged in the sent ACK frame that, in
+                        // turn, got acked.
+                        if let Some(largest_acked) = ranges.last() {
+                            self.pkt_num_spaces[epoch]
+                                .recv_pkt_need_ack
+                                .remove_until(largest_acked);
+                        }
+                    },
 
-        // Process lost frames. There might be several paths having lost frames.
-        for (_, p) in self.paths.iter_mut() {
-            for lost in p.recovery.get_lost_frames(epoch) {
-                match lost {
                     frame::Frame::CryptoHeader { offset, length } => {
-                        pkt_space.crypto_stream.send.retransmit(offset, length);
-
-                        self.stream_retrans_bytes += length as u64;
-                        p.stream_retrans_bytes += length as u64;
-
-                        self.retrans_count += 1;
-                        p.retrans_count += 1;
+                        self.pkt_num_spaces[epoch]
+                            .crypto_stream
+                            .send
+                            .ack_and_drop(offset, length);
                     },
 
                     frame::Frame::StreamHeader {
                         stream_id,
                         offset,
                         length,
-                        fin,
+                        ..
                     } => {
                         let stream = match self.streams.get_mut(stream_id) {
                             Some(v) => v,
@@ -3649,5265 +5300,9282 @@ impl Connection {
                             None => continue,
                         };
 
-                        let was_flushable = stream.is_flushable();
-
-                        let empty_fin = length == 0 && fin;
+                        stream.send.ack_and_drop(offset, length);
 
-                        stream.send.retransmit(offset, length);
+                        self.tx_buffered =
+                            self.tx_buffered.saturating_sub(length);
 
-                        // If the stream is now flushable push it to the
-                        // flushable queue, but only if it wasn't already
-                        // queued.
-                        //
-                        // Consider the stream flushable also when we are
-                        // sending a zero-length frame that has the fin flag
-                        // set.
-                        if (stream.is_flushable() || empty_fin) && !was_flushable
-                        {
-                            let priority_key = Arc::clone(&stream.priority_key);
-                            self.streams.insert_flushable(&priority_key);
-                        }
+                        qlog_with_type!(QLOG_DATA_MV, self.qlog, q, {
+                            let ev_data = EventData::DataMoved(
+                                qlog::events::quic::DataMoved {
+                                    stream_id: Some(stream_id),
+                                    offset: Some(offset),
+                                    length: Some(length as u64),
+                                    from: Some(DataRecipient::Transport),
+                                    to: Some(DataRecipient::Dropped),
+                                    raw: None,
+                                },
+                            );
 
-                        self.stream_retrans_bytes += length as u64;
-                        p.stream_retrans_bytes += length as u64;
+                            q.add_event_data_with_instant(ev_data, now).ok();
+                        });
 
-                        self.retrans_count += 1;
-                        p.retrans_count += 1;
+                        // Only collect the stream if it is complete and not
+                        // readable. If it is readable, it will get collected when
+                        // stream_recv() is used.
+                        if stream.is_complete() && !stream.is_readable() {
+                            let local = stream.local;
+                            self.streams.collect(stream_id, local);
+                        }
                     },
 
-                    frame::Frame::ACK { .. } => {
-                        pkt_space.ack_elicited = true;
+                    frame::Frame::HandshakeDone => {
+                        // Explicitly set this to true, so that if the frame was
+                        // already scheduled for retransmission, it is aborted.
+                        self.handshake_done_sent = true;
+
+                        self.handshake_done_acked = true;
                     },
 
-                    frame::Frame::ResetStream {
-                        stream_id,
-                        error_code,
-                        final_size,
-                    } =>
-                        if self.streams.get(stream_id).is_some() {
-                            self.streams
-                                .insert_reset(stream_id, error_code, final_size);
-                        },
+                    frame::Frame::ResetStream { stream_id, .. } |
+                    frame::Frame::ResetStreamAt { stream_id, .. } => {
+                        let stream = match self.streams.get_mut(stream_id) {
+                            Some(v) => v,
 
-                    // Retransmit HANDSHAKE_DONE only if it hasn't been acked at
-                    // least once already.
-                    frame::Frame::HandshakeDone if !self.handshake_done_acked => {
-                        self.handshake_done_sent = false;
-                    },
+                            None => continue,
+                        };
 
-                    frame::Frame::MaxStreamData { stream_id, .. } => {
-                        if self.streams.get(stream_id).is_some() {
-                            self.streams.insert_almost_full(stream_id);
+                        // Only collect the stream if it is complete and not
+                        // readable. If it is readable, it will get collected when
+                        // stream_recv() is used.
+                        if stream.is_complete() && !stream.is_readable() {
+                            let local = stream.local;
+                            self.streams.collect(stream_id, local);
                         }
                     },
 
-                    frame::Frame::MaxData { .. } => {
-                        self.almost_full = true;
-                    },
+                    _ => (),
+                }
+            }
 
-                    frame::Frame::NewConnectionId { seq_num, .. } => {
-                        self.ids.mark_advertise_new_scid_seq(seq_num, true);
-                    },
+            // Update max datagram send size with newly acked probe size.
+            if pmtud_probe {
+                trace!(
+                    "{} updating pmtu {:?}",
+                    p.pmtud.get_current(),
+                    self.trace_id
+                );
 
-                    frame::Frame::RetireConnectionId { seq_num } => {
-                        self.ids.mark_retire_dcid_seq(seq_num, true)?;
-                    },
+                qlog_with_type!(
+                    EventType::ConnectivityEventType(
+                        ConnectivityEventType::MtuUpdated
+                    ),
+                    self.qlog,
+                    q,
+                    {
+                        let pmtu_data = EventData::MtuUpdated(
+                            qlog::events::connectivity::MtuUpdated {
+                                old: Some(p.recovery.max_datagram_size() as u16),
+                                new: p.pmtud.get_current() as u16,
+                                done: Some(pmtud_probe),
+                            },
+                        );
 
-                    frame::Frame::Ping { mtu_probe } if mtu_probe.is_some() => {
-                        p.pmtud.pmtu_probe_lost();
-                    },
+                        q.add_event_data_with_instant(pmtu_data, now).ok();
+                    }
+                );
 
-                    _ => (),
-                }
+                p.recovery
+                    .pmtud_update_max_datagram_size(p.pmtud.get_current());
             }
         }
 
-        let is_app_limited = self.delivery_rate_check_if_app_limited();
-        let n_paths = self.paths.len();
-        let path = self.paths.get_mut(send_pid)?;
-        let flow_control = &mut self.flow_control;
-        let pkt_space = &mut self.pkt_num_spaces[epoch];
+        // Now that we processed all the frames, if there is a path that has no
+        // Destination CID, try to allocate one.
+        let no_dcid = self
+            .paths
+            .iter_mut()
+            .filter(|(_, p)| p.active_dcid_seq.is_none());
 
-        let mut left = if path.pmtud.is_enabled() {
-            // Limit output buffer size by estimated path MTU.
-            cmp::min(path.pmtud.get_current(), b.cap())
-        } else {
-            b.cap()
-        };
+        for (pid, p) in no_dcid {
+            if self.ids.zero_length_dcid() {
+                p.active_dcid_seq = Some(0);
+                continue;
+            }
 
-        let pn = pkt_space.next_pkt_num;
-        let largest_acked_pkt =
-            path.recovery.get_largest_acked_on_epoch(epoch).unwrap_or(0);
-        let pn_len = packet::pkt_num_len(pn, largest_acked_pkt);
+            let dcid_seq = match self.ids.lowest_available_dcid_seq() {
+                Some(seq) => seq,
+                None => break,
+            };
 
-        // The AEAD overhead at the current encryption level.
-        let crypto_overhead = pkt_space.crypto_overhead().ok_or(Error::Done)?;
+            self.ids.link_dcid_to_path_id(dcid_seq, pid)?;
 
-        let dcid_seq = path.active_dcid_seq.ok_or(Error::OutOfIdentifiers)?;
+            p.active_dcid_seq = Some(dcid_seq);
+        }
 
-        let dcid =
-            ConnectionId::from_ref(self.ids.get_dcid(dcid_seq)?.cid.as_ref());
-
-        let scid = if let Some(scid_seq) = path.active_scid_seq {
-            ConnectionId::from_ref(self.ids.get_scid(scid_seq)?.cid.as_ref())
-        } else if pkt_type == packet::Type::Short {
-            ConnectionId::default()
-        } else {
-            return Err(Error::InvalidState);
-        };
-
-        let hdr = Header {
-            ty: pkt_type,
-
-            version: self.version,
-
-            dcid,
-            scid,
-
-            pkt_num: 0,
-            pkt_num_len: pn_len,
-
-            // Only clone token for Initial packets, as other packets don't have
-            // this field (Retry doesn't count, as it's not encoded as part of
-            // this code path).
-            token: if pkt_type == packet::Type::Initial {
-                self.token.clone()
-            } else {
-                None
-            },
-
-            versions: None,
-            key_phase: self.key_phase,
-        };
-
-        hdr.to_bytes(&mut b)?;
-
-        let hdr_trace = if log::max_level() == log::LevelFilter::Trace {
-            Some(format!("{hdr:?}"))
-        } else {
-            None
-        };
-
-        let hdr_ty = hdr.ty;
-
-        #[cfg(feature = "qlog")]
-        let qlog_pkt_hdr = self.qlog.streamer.as_ref().map(|_q| {
-            qlog::events::quic::PacketHeader::with_type(
-                hdr.ty.to_qlog(),
-                Some(pn),
-                Some(hdr.version),
-                Some(&hdr.scid),
-                Some(&hdr.dcid),
-            )
-        });
-
-        // Calculate the space required for the packet, including the header
-        // the payload length, the packet number and the AEAD overhead.
-        let mut overhead = b.off() + pn_len + crypto_overhead;
-
-        // We assume that the payload length, which is only present in long
-        // header packets, can always be encoded with a 2-byte varint.
-        if pkt_type != packet::Type::Short {
-            overhead += PAYLOAD_LENGTH_LEN;
-        }
-
-        // Make sure we have enough space left for the packet overhead.
-        match left.checked_sub(overhead) {
-            Some(v) => left = v,
-
-            None => {
-                // We can't send more because there isn't enough space available
-                // in the output buffer.
-                //
-                // This usually happens when we try to send a new packet but
-                // failed because cwnd is almost full. In such case app_limited
-                // is set to false here to make cwnd grow when ACK is received.
-                path.recovery.update_app_limited(false);
-                return Err(Error::Done);
-            },
-        }
-
-        // Make sure there is enough space for the minimum payload length.
-        if left < PAYLOAD_MIN_LEN {
-            path.recovery.update_app_limited(false);
-            return Err(Error::Done);
-        }
-
-        let mut frames: SmallVec<[frame::Frame; 1]> = SmallVec::new();
-
-        let mut ack_eliciting = false;
-        let mut in_flight = false;
-        // Foll. flag used to upgrade datagram size, if probe successful
-        let mut pmtud_probe = false;
-        let mut has_data = false;
-
-        // Whether or not we should explicitly elicit an ACK via PING frame if we
-        // implicitly elicit one otherwise.
-        let ack_elicit_required = path.recovery.should_elicit_ack(epoch);
-
-        let header_offset = b.off();
-
-        // Reserve space for payload length in advance. Since we don't yet know
-        // what the final length will be, we reserve 2 bytes in all cases.
-        //
-        // Only long header packets have an explicit length field.
-        if pkt_type != packet::Type::Short {
-            b.skip(PAYLOAD_LENGTH_LEN)?;
+        // We only record the time of arrival of the largest packet number
+        // that still needs to be acked, to be used for ACK delay calculation.
+        if self.pkt_num_spaces[epoch].recv_pkt_need_ack.last() < Some(pn) {
+            self.pkt_num_spaces[epoch].largest_rx_pkt_time = now;
         }
 
-        packet::encode_pkt_num(pn, pn_len, &mut b)?;
-
-        let payload_offset = b.off();
+        self.pkt_num_spaces[epoch].recv_pkt_num.insert(pn);
 
-        let cwnd_available =
-            path.recovery.cwnd_available().saturating_sub(overhead);
+        self.pkt_num_spaces[epoch].recv_pkt_need_ack.push_item(pn);
 
-        let left_before_packing_ack_frame = left;
+        self.pkt_num_spaces[epoch].ack_elicited =
+            cmp::max(self.pkt_num_spaces[epoch].ack_elicited, ack_elicited);
 
-        // Create ACK frame.
-        //
-        // When we need to explicitly elicit an ACK via PING later, go ahead and
-        // generate an ACK (if there's anything to ACK) since we're going to
-        // send a packet with PING anyways, even if we haven't received anything
-        // ACK eliciting.
-        if pkt_space.recv_pkt_need_ack.len() > 0 &&
-            (pkt_space.ack_elicited || ack_elicit_required) &&
-            (!is_closing ||
-                (pkt_type == Type::Handshake &&
-                    self.local_error
-                        .as_ref()
-                        .map_or(false, |le| le.is_app))) &&
-            path.active()
-        {
-            let ack_delay = pkt_space.largest_rx_pkt_time.elapsed();
+        self.pkt_num_spaces[epoch].largest_rx_pkt_num =
+            cmp::max(self.pkt_num_spaces[epoch].largest_rx_pkt_num, pn);
 
-            let ack_delay = ack_delay.as_micros() as u64 /
-                2_u64
-                    .pow(self.local_transport_params.ack_delay_exponent as u32);
+        if !probing {
+            self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num = cmp::max(
+                self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num,
+                pn,
+            );
 
-            let frame = frame::Frame::ACK {
-                ack_delay,
-                ranges: pkt_space.recv_pkt_need_ack.clone(),
-                ecn_counts: None, // sending ECN is not supported at this time
-            };
+            // Did the peer migrated to another path?
+            let active_path_id = self.paths.get_active_path_id()?;
 
-            // When a PING frame needs to be sent, avoid sending the ACK if
-            // there is not enough cwnd available for both (note that PING
-            // frames are always 1 byte, so we just need to check that the
-            // ACK's length is lower than cwnd).
-            if pkt_space.ack_elicited || frame.wire_len() < cwnd_available {
-                // ACK-only packets are not congestion controlled so ACKs must
-                // be bundled considering the buffer capacity only, and not the
-                // available cwnd.
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    pkt_space.ack_elicited = false;
-                }
+            if self.is_server &&
+                recv_pid != active_path_id &&
+                self.pkt_num_spaces[epoch].largest_rx_non_probing_pkt_num == pn
+            {
+                self.on_peer_migrated(recv_pid, self.disable_dcid_reuse, now)?;
             }
         }
 
-        // Limit output packet size by congestion window size.
-        left = cmp::min(
-            left,
-            // Bytes consumed by ACK frames.
-            cwnd_available.saturating_sub(left_before_packing_ack_frame - left),
-        );
+        if let Some(idle_timeout) = self.idle_timeout() {
+            self.idle_timer = Some(now + idle_timeout);
+        }
 
-        let mut challenge_data = None;
+        // Update send capacity.
+        self.update_tx_cap();
 
-        let active_path = self.paths.get_active_mut()?;
+        self.recv_count += 1;
+        self.paths.get_mut(recv_pid)?.recv_count += 1;
 
-        if pkt_type == packet::Type::Short {
-            // Create PMTUD probe.
-            //
-            // In order to send a PMTUD probe the current `left` value, which was
-            // already limited by the current PMTU measure, needs to be ignored,
-            // but the outgoing packet still needs to be limited by
-            // the output buffer size, as well as the congestion
-            // window.
-            //
-            // In addition, the PMTUD probe is only generated when the handshake
-            // is confirmed, to avoid interfering with the handshake
-            // (e.g. due to the anti-amplification limits).
+        let read = b.off() + aead_tag_len;
 
-            let pmtu_probe = active_path.should_send_pmtu_probe(
-                self.handshake_confirmed,
-                self.handshake_done_sent,
-                out_len,
-                is_closing,
-                frames.is_empty(),
-            );
+        self.recv_bytes += read as u64;
+        self.paths.get_mut(recv_pid)?.recv_bytes += read as u64;
 
-            trace!("{} pmtud probe status {} hs_con={} hs_sent={} cwnd_avail={} out_len={} left={}", self.trace_id, pmtu_probe, self.handshake_confirmed, self.handshake_done_sent,
-                    active_path.recovery.cwnd_available(), out_len, left);
+        if epoch == packet::Epoch::Application {
+            let jump = self
+                .paths
+                .get_mut(recv_pid)?
+                .recovery
+                .on_app_packet_received(read, now);
 
-            if pmtu_probe {
+            if let Some(jump) = jump {
                 trace!(
-                    "{} sending pmtud probe pmtu_probe={} next_size={} pmtu={}",
+                    "{} peer careful resume suspected {:?}",
                     self.trace_id,
-                    active_path.pmtud.get_probe_size(),
-                    active_path.pmtud.get_probe_status(),
-                    active_path.pmtud.get_current(),
+                    jump
                 );
 
-                left = active_path.pmtud.get_probe_size();
+                self.qlog_peer_resume_suspected(&jump);
+            }
+        }
 
-                match left.checked_sub(overhead) {
-                    Some(v) => left = v,
+        // An Handshake packet has been received from the client and has been
+        // successfully processed, so we can drop the initial state and consider
+        // the client's address to be verified.
+        if self.is_server && hdr.ty == packet::Type::Handshake {
+            self.drop_epoch_state(packet::Epoch::Initial, now);
 
-                    None => {
-                        // We can't send more because there isn't enough space
-                        // available in the output buffer.
-                        //
-                        // This usually happens when we try to send a new packet
-                        // but failed because cwnd is almost full.
-                        //
-                        // In such case app_limited is set to false here to make
-                        // cwnd grow when ACK is received.
-                        active_path.recovery.update_app_limited(false);
-                        return Err(Error::Done);
-                    },
-                }
+            self.paths.get_mut(recv_pid)?.verified_peer_address = true;
 
-                let frame = frame::Frame::Padding {
-                    len: active_path.pmtud.get_probe_size() - overhead - 1,
-                };
+            self.maybe_setup_server_careful_resume();
+        }
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    let frame = frame::Frame::Ping {
-                        mtu_probe: Some(active_path.pmtud.get_probe_size()),
-                    };
+        self.ack_eliciting_sent = false;
 
-                    if push_frame_to_pkt!(b, frames, frame, left) {
-                        ack_eliciting = true;
-                        in_flight = true;
-                    }
-                }
+        Ok(read)
+    }
+
+    /// Writes a single QUIC packet to be sent to the peer.
+    ///
+    /// On success the number of bytes written to the output buffer is
+    /// returned, or [`Done`] if there was nothing to write.
+    ///
+    /// The application should call `send()` multiple times until [`Done`] is
+    /// returned, indicating that there are no more packets to send. It is
+    /// recommended that `send()` be called in the following cases:
+    ///
+    ///  * When the application receives QUIC packets from the peer (that is,
+    ///    any time [`recv()`] is also called).
+    ///
+    ///  * When the connection timer expires (that is, any time [`on_timeout()`]
+    ///    is also called).
+    ///
+    ///  * When the application sends data to the peer (for example, any time
+    ///    [`stream_send()`] or [`stream_shutdown()`] are called).
+    ///
+    ///  * When the application receives data from the peer (for example any
+    ///    time [`stream_recv()`] is called).
+    ///
+    /// Once [`is_draining()`] returns `true`, it is no longer necessary to call
+    /// `send()` and all calls will return [`Done`].
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`stream_shutdown()`]: struct.Connection.html#method.stream_shutdown
+    /// [`stream_recv()`]: struct.Connection.html#method.stream_recv
+    /// [`is_draining()`]: struct.Connection.html#method.is_draining
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut out = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// loop {
+    ///     let (write, send_info) = match conn.send(&mut out) {
+    ///         Ok(v) => v,
+    ///
+    ///         Err(quiche::Error::Done) => {
+    ///             // Done writing.
+    ///             break;
+    ///         },
+    ///
+    ///         Err(e) => {
+    ///             // An error occurred, handle it.
+    ///             break;
+    ///         },
+    ///     };
+    ///
+    ///     socket.send_to(&out[..write], &send_info.to).unwrap();
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn send(&mut self, out: &mut [u8]) -> Result<(usize, SendInfo)> {
+        self.send_on_path(out, None, None)
+    }
+
+    /// Writes a single QUIC packet to be sent to the peer from the specified
+    /// local address `from` to the destination address `to`.
+    ///
+    /// The behavior of this method differs depending on the value of the `from`
+    /// and `to` parameters:
+    ///
+    ///  * If both are `Some`, then the method only consider the 4-tuple
+    ///    (`from`, `to`). Application can monitor the 4-tuple availability,
+    ///    either by monitoring [`path_event_next()`] events or by relying on
+    ///    the [`paths_iter()`] method. If the provided 4-tuple does not exist
+    ///    on the connection (anymore), it returns an [`InvalidState`].
+    ///
+    ///  * If `from` is `Some` and `to` is `None`, then the method only
+    ///    considers sending packets on paths having `from` as local address.
+    ///
+    ///  * If `to` is `Some` and `from` is `None`, then the method only
+    ///    considers sending packets on paths having `to` as peer address.
+    ///
+    ///  * If both are `None`, all available paths are considered.
+    ///
+    /// On success the number of bytes written to the output buffer is
+    /// returned, or [`Done`] if there was nothing to write.
+    ///
+    /// The application should call `send_on_path()` multiple times until
+    /// [`Done`] is returned, indicating that there are no more packets to
+    /// send. It is recommended that `send_on_path()` be called in the
+    /// following cases:
+    ///
+    ///  * When the application receives QUIC packets from the peer (that is,
+    ///    any time [`recv()`] is also called).
+    ///
+    ///  * When the connection timer expires (that is, any time [`on_timeout()`]
+    ///    is also called).
+    ///
+    ///  * When the application sends data to the peer (for examples, any time
+    ///    [`stream_send()`] or [`stream_shutdown()`] are called).
+    ///
+    ///  * When the application receives data from the peer (for example any
+    ///    time [`stream_recv()`] is called).
+    ///
+    /// Once [`is_draining()`] returns `true`, it is no longer necessary to call
+    /// `send_on_path()` and all calls will return [`Done`].
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`InvalidState`]: enum.Error.html#InvalidState
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`stream_shutdown()`]: struct.Connection.html#method.stream_shutdown
+    /// [`stream_recv()`]: struct.Connection.html#method.stream_recv
+    /// [`path_event_next()`]: struct.Connection.html#method.path_event_next
+    /// [`paths_iter()`]: struct.Connection.html#method.paths_iter
+    /// [`is_draining()`]: struct.Connection.html#method.is_draining
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut out = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// loop {
+    ///     let (write, send_info) = match conn.send_on_path(&mut out, Some(local), Some(peer)) {
+    ///         Ok(v) => v,
+    ///
+    ///         Err(quiche::Error::Done) => {
+    ///             // Done writing.
+    ///             break;
+    ///         },
+    ///
+    ///         Err(e) => {
+    ///             // An error occurred, handle it.
+    ///             break;
+    ///         },
+    ///     };
+    ///
+    ///     socket.send_to(&out[..write], &send_info.to).unwrap();
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn send_on_path(
+        &mut self, out: &mut [u8], from: Option<SocketAddr>,
+        to: Option<SocketAddr>,
+    ) -> Result<(usize, SendInfo)> {
+        if out.is_empty() {
+            return Err(Error::BufferTooShort);
+        }
+
+        if self.is_closed() || self.is_draining() {
+            return Err(Error::Done);
+        }
+
+        let now = time::Instant::now();
+
+        if self.local_error.is_none() {
+            self.do_handshake(now)?;
+        }
+
+        // Forwarding the error value here could confuse
+        // applications, as they may not expect getting a `recv()`
+        // error when calling `send()`.
+        //
+        // We simply fall-through to sending packets, which should
+        // take care of terminating the connection as needed.
+        let _ = self.process_undecrypted_0rtt_packets();
+
+        // There's no point in trying to send a packet if the Initial secrets
+        // have not been derived yet, so return early.
+        if !self.derived_initial_secrets {
+            return Err(Error::Done);
+        }
+
+        let mut has_initial = false;
+
+        let mut done = 0;
+
+        // Limit output packet size to respect the sender and receiver's
+        // maximum UDP payload size limit.
+        let mut left = cmp::min(out.len(), self.max_send_udp_payload_size());
+
+        let send_pid = match (from, to) {
+            (Some(f), Some(t)) => self
+                .paths
+                .path_id_from_addrs(&(f, t))
+                .ok_or(Error::InvalidState)?,
+
+            _ => self.get_send_path_id(from, to)?,
+        };
+
+        let send_path = self.paths.get_mut(send_pid)?;
+
+        send_path.recovery.release_burst(now);
+
+        // Update max datagram size to allow path MTU discovery probe to be sent.
+        if send_path.pmtud.get_probe_status() {
+            let size = if self.handshake_confirmed || self.handshake_done_sent {
+                send_path.pmtud.get_probe_size()
+            } else {
+                send_path.pmtud.get_current()
+            };
+
+            send_path.recovery.pmtud_update_max_datagram_size(size);
+
+            left = cmp::min(out.len(), send_path.recovery.max_datagram_size());
+        }
+
+        // Limit data sent by the server based on the amount of data received
+        // from the client before its address is validated.
+        if !send_path.verified_peer_address && self.is_server {
+            left = cmp::min(left, send_path.max_send_bytes);
+        }
+
+        // Generate coalesced packets.
+        while left > 0 {
+            let (ty, written) = match self.send_single(
+                &mut out[done..done + left],
+                send_pid,
+                has_initial,
+                now,
+            ) {
+                Ok(v) => v,
+
+                Err(Error::BufferTooShort) | Err(Error::Done) => break,
+
+                Err(e) => return Err(e),
+            };
+
+            done += written;
+            left -= written;
+
+            match ty {
+                packet::Type::Initial => has_initial = true,
+
+                // No more packets can be coalesced after a 1-RTT.
+                packet::Type::Short => break,
+
+                _ => (),
+            };
+
+            // When sending multiple PTO probes, don't coalesce them together,
+            // so they are sent on separate UDP datagrams.
+            if let Ok(epoch) = ty.to_epoch() {
+                if self.paths.get_mut(send_pid)?.recovery.loss_probes(epoch) > 0 {
+                    break;
+                }
+            }
+
+            // Don't coalesce packets that must go on different paths.
+            if !(from.is_some() && to.is_some()) &&
+                self.get_send_path_id(from, to)? != send_pid
+            {
+                break;
+            }
+        }
+
+        self.update_tx_cap();
+
+        if done == 0 {
+            self.last_tx_data = self.tx_data;
+
+            return Err(Error::Done);
+        }
+
+        // Pad UDP datagram if it contains a QUIC Initial packet.
+        #[cfg(not(feature = "fuzzing"))]
+        if has_initial && left > 0 && done < MIN_CLIENT_INITIAL_LEN {
+            let pad_len = cmp::min(left, MIN_CLIENT_INITIAL_LEN - done);
+
+            // Fill padding area with null bytes, to avoid leaking information
+            // in case the application reuses the packet buffer.
+            out[done..done + pad_len].fill(0);
+
+            done += pad_len;
+        }
+
+        let send_path = self.paths.get(send_pid)?;
+
+        let info = SendInfo {
+            from: send_path.local_addr(),
+            to: send_path.peer_addr(),
+
+            at: send_path.recovery.get_packet_send_time(),
+        };
+
+        if self.pcap.is_some() {
+            let dcid_len = self.destination_id().len();
+
+            if let Some(pcap) = &mut self.pcap {
+                pcap.write_datagram(
+                    &out[..done],
+                    info.from,
+                    info.to,
+                    dcid_len,
+                    info.at,
+                );
+            }
+        }
+
+        Ok((done, info))
+    }
+
+    fn send_single(
+        &mut self, out: &mut [u8], send_pid: usize, has_initial: bool,
+        now: time::Instant,
+    ) -> Result<(packet::Type, usize)> {
+        if out.is_empty() {
+            return Err(Error::BufferTooShort);
+        }
+
+        if self.is_draining() {
+            return Err(Error::Done);
+        }
+
+        let is_closing = self.local_error.is_some();
+
+        let out_len = out.len();
+
+        let mut b = octets::OctetsMut::with_slice(out);
+
+        let pkt_type = self.write_pkt_type(send_pid)?;
+
+        let max_dgram_len = if !self.dgram_send_queue.is_empty() {
+            self.dgram_max_writable_len()
+        } else {
+            None
+        };
+
+        let epoch = pkt_type.to_epoch()?;
+        let pkt_space = &mut self.pkt_num_spaces[epoch];
+
+        // Process lost frames. There might be several paths having lost frames.
+        for (_, p) in self.paths.iter_mut() {
+            for lost in p.recovery.get_lost_frames(epoch) {
+                match lost {
+                    frame::Frame::CryptoHeader { offset, length } => {
+                        pkt_space.crypto_stream.send.retransmit(offset, length);
+
+                        self.stream_retrans_bytes += length as u64;
+                        p.stream_retrans_bytes += length as u64;
+
+                        self.retrans_count += 1;
+                        p.retrans_count += 1;
+                    },
+
+                    frame::Frame::StreamHeader {
+                        stream_id,
+                        offset,
+                        length,
+                        fin,
+                    } => {
+                        let stream = match self.streams.get_mut(stream_id) {
+                            Some(v) => v,
+
+                            None => continue,
+                        };
+
+                        let was_flushable = stream.is_flushable();
+
+                        let empty_fin = length == 0 && fin;
+
+                        stream.send.retransmit(offset, length);
+
+                        // If the stream is now flushable push it to the
+                        // flushable queue, but only if it wasn't already
+                        // queued.
+                        //
+                        // Consider the stream flushable also when we are
+                        // sending a zero-length frame that has the fin flag
+                        // set.
+                        if (stream.is_flushable() || empty_fin) && !was_flushable
+                        {
+                            let priority_key = Arc::clone(&stream.priority_key);
+                            self.streams.insert_flushable(&priority_key);
+                        }
+
+                        self.stream_retrans_bytes += length as u64;
+                        p.stream_retrans_bytes += length as u64;
+
+                        self.retrans_count += 1;
+                        p.retrans_count += 1;
+                    },
+
+                    frame::Frame::ACK { .. } => {
+                        pkt_space.ack_elicited = true;
+                    },
+
+                    frame::Frame::ResetStream {
+                        stream_id,
+                        error_code,
+                        final_size,
+                    } =>
+                        if self.streams.get(stream_id).is_some() {
+                            self.streams
+                                .insert_reset(stream_id, error_code, final_size);
+                        },
+
+                    frame::Frame::ResetStreamAt {
+                        stream_id,
+                        error_code,
+                        final_size,
+                        reliable_size,
+                    } if self.streams.get(stream_id).is_some() => {
+                        self.streams.insert_reset_at(
+                            stream_id,
+                            error_code,
+                            final_size,
+                            reliable_size,
+                        );
+                    },
+
+                    // Retransmit HANDSHAKE_DONE only if it hasn't been acked at
+                    // least once already.
+                    frame::Frame::HandshakeDone if !self.handshake_done_acked => {
+                        self.handshake_done_sent = false;
+                    },
+
+                    frame::Frame::MaxStreamData { stream_id, .. } => {
+                        if self.streams.get(stream_id).is_some() {
+                            self.streams.insert_almost_full(stream_id);
+                        }
+                    },
+
+                    frame::Frame::MaxData { .. } => {
+                        self.almost_full = true;
+                    },
+
+                    frame::Frame::NewConnectionId { seq_num, .. } => {
+                        self.ids.mark_advertise_new_scid_seq(seq_num, true);
+                    },
+
+                    frame::Frame::RetireConnectionId { seq_num } => {
+                        self.ids.mark_retire_dcid_seq(seq_num, true)?;
+                    },
+
+                    frame::Frame::Ping { mtu_probe } if mtu_probe.is_some() => {
+                        p.pmtud.pmtu_probe_lost();
+                    },
+
+                    // Servers send the latest parameters again, while clients
+                    // echo the same ones unless a new echo was requested.
+                    frame::Frame::Bdp {
+                        lifetime,
+                        saved_capacity,
+                        saved_rtt,
+                        token,
+                    } =>
+                        if self.is_server {
+                            self.bdp_frame_pending = true;
+                        } else if self.bdp_frame_echo.is_none() {
+                            self.bdp_frame_echo = Some(BdpFrame {
+                                lifetime: time::Duration::from_secs(lifetime),
+                                saved_capacity: saved_capacity as usize,
+                                saved_rtt: time::Duration::from_micros(
+                                    saved_rtt,
+                                ),
+                                token,
+                            });
+                        },
+
+                    // Only the latest hints are sent again.
+                    frame::Frame::CrHints {
+                        seq,
+                        lifetime,
+                        capacity,
+                        min_rtt,
+                        max_jump,
+                    } if seq == self.cr_hints_seq &&
+                        self.cr_hints_pending.is_none() =>
+                    {
+                        self.cr_hints_pending = Some(CrHints {
+                            lifetime: time::Duration::from_secs(lifetime),
+                            capacity: capacity as usize,
+                            min_rtt: time::Duration::from_micros(min_rtt),
+                            max_jump: max_jump as usize,
+                        });
+                    },
+
+                    _ => (),
+                }
+            }
+        }
+
+        let is_app_limited = self.delivery_rate_check_if_app_limited();
+        let n_paths = self.paths.len();
+        let path = self.paths.get_mut(send_pid)?;
+        let flow_control = &mut self.flow_control;
+        let pkt_space = &mut self.pkt_num_spaces[epoch];
+
+        let mut left = if path.pmtud.is_enabled() {
+            // Limit output buffer size by estimated path MTU.
+            cmp::min(path.pmtud.get_current(), b.cap())
+        } else {
+            b.cap()
+        };
+
+        let pn = pkt_space.next_pkt_num;
+        let largest_acked_pkt =
+            path.recovery.get_largest_acked_on_epoch(epoch).unwrap_or(0);
+        let pn_len = packet::pkt_num_len(pn, largest_acked_pkt);
+
+        // The AEAD overhead at the current encryption level.
+        let crypto_overhead = pkt_space.crypto_overhead().ok_or(Error::Done)?;
+
+        let dcid_seq = path.active_dcid_seq.ok_or(Error::OutOfIdentifiers)?;
+
+        let dcid =
+            ConnectionId::from_ref(self.ids.get_dcid(dcid_seq)?.cid.as_ref());
+
+        let scid = if let Some(scid_seq) = path.active_scid_seq {
+            ConnectionId::from_ref(self.ids.get_scid(scid_seq)?.cid.as_ref())
+        } else if pkt_type == packet::Type::Short {
+            ConnectionId::default()
+        } else {
+            return Err(Error::InvalidState);
+        };
+
+        let hdr = Header {
+            ty: pkt_type,
+
+            version: self.version,
+
+            dcid,
+            scid,
+
+            pkt_num: 0,
+            pkt_num_len: pn_len,
+
+            // Only clone token for Initial packets, as other packets don't have
+            // this field (Retry doesn't count, as it's not encoded as part of
+            // this code path).
+            token: if pkt_type == packet::Type::Initial {
+                self.token.clone()
+            } else {
+                None
+            },
+
+            versions: None,
+            key_phase: self.key_phase,
+        };
+
+        hdr.to_bytes(&mut b)?;
+
+        let hdr_trace = if log::max_level() == log::LevelFilter::Trace {
+            Some(format!("{hdr:?}"))
+        } else {
+            None
+        };
+
+        let hdr_ty = hdr.ty;
+
+        #[cfg(feature = "qlog")]
+        let qlog_pkt_hdr = self.qlog.streamer.as_ref().map(|_q| {
+            qlog::events::quic::PacketHeader::with_type(
+                hdr.ty.to_qlog(),
+                Some(pn),
+                Some(hdr.version),
+                Some(&hdr.scid),
+                Some(&hdr.dcid),
+            )
+        });
+
+        // Calculate the space required for the packet, including the header
+        // the payload length, the packet number and the AEAD overhead.
+        let mut overhead = b.off() + pn_len + crypto_overhead;
+
+        // We assume that the payload length, which is only present in long
+        // header packets, can always be encoded with a 2-byte varint.
+        if pkt_type != packet::Type::Short {
+            overhead += PAYLOAD_LENGTH_LEN;
+        }
+
+        // Make sure we have enough space left for the packet overhead.
+        match left.checked_sub(overhead) {
+            Some(v) => left = v,
+
+            None => {
+                // We can't send more because there isn't enough space available
+                // in the output buffer.
+                //
+                // This usually happens when we try to send a new packet but
+                // failed because cwnd is almost full. In such case app_limited
+                // is set to false here to make cwnd grow when ACK is received.
+                path.recovery.update_app_limited(false);
+                return Err(Error::Done);
+            },
+        }
+
+        // Make sure there is enough space for the minimum payload length.
+        if left < PAYLOAD_MIN_LEN {
+            path.recovery.update_app_limited(false);
+            return Err(Error::Done);
+        }
+
+        let mut frames: SmallVec<[frame::Frame; 1]> = SmallVec::new();
+
+        let mut ack_eliciting = false;
+        let mut in_flight = false;
+        // Foll. flag used to upgrade datagram size, if probe successful
+        let mut pmtud_probe = false;
+        let mut has_data = false;
+        // Whether the packet carries stream data that was already sent.
+        let mut retransmission = false;
+
+        // Whether or not we should explicitly elicit an ACK via PING frame if we
+        // implicitly elicit one otherwise.
+        let ack_elicit_required = path.recovery.should_elicit_ack(epoch);
+
+        let header_offset = b.off();
+
+        // Reserve space for payload length in advance. Since we don't yet know
+        // what the final length will be, we reserve 2 bytes in all cases.
+        //
+        // Only long header packets have an explicit length field.
+        if pkt_type != packet::Type::Short {
+            b.skip(PAYLOAD_LENGTH_LEN)?;
+        }
+
+        packet::encode_pkt_num(pn, pn_len, &mut b)?;
+
+        let payload_offset = b.off();
+
+        let cwnd_available =
+            path.recovery.cwnd_available().saturating_sub(overhead);
+
+        let left_before_packing_ack_frame = left;
+
+        // Create ACK frame.
+        //
+        // When we need to explicitly elicit an ACK via PING later, go ahead and
+        // generate an ACK (if there's anything to ACK) since we're going to
+        // send a packet with PING anyways, even if we haven't received anything
+        // ACK eliciting.
+        if pkt_space.recv_pkt_need_ack.len() > 0 &&
+            (pkt_space.ack_elicited || ack_elicit_required) &&
+            (!is_closing ||
+                (pkt_type == Type::Handshake &&
+                    self.local_error
+                        .as_ref()
+                        .map_or(false, |le| le.is_app))) &&
+            path.active()
+        {
+            let ack_delay = pkt_space.largest_rx_pkt_time.elapsed();
+
+            let ack_delay = ack_delay.as_micros() as u64 /
+                2_u64
+                    .pow(self.local_transport_params.ack_delay_exponent as u32);
+
+            let frame = frame::Frame::ACK {
+                ack_delay,
+                ranges: pkt_space.recv_pkt_need_ack.clone(),
+                ecn_counts: None, // sending ECN is not supported at this time
+            };
+
+            // When a PING frame needs to be sent, avoid sending the ACK if
+            // there is not enough cwnd available for both (note that PING
+            // frames are always 1 byte, so we just need to check that the
+            // ACK's length is lower than cwnd).
+            if pkt_space.ack_elicited || frame.wire_len() < cwnd_available {
+                // ACK-only packets are not congestion controlled so ACKs must
+                // be bundled considering the buffer capacity only, and not the
+                // available cwnd.
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    pkt_space.ack_elicited = false;
+                }
+            }
+        }
+
+        // Limit output packet size by congestion window size.
+        left = cmp::min(
+            left,
+            // Bytes consumed by ACK frames.
+            cwnd_available.saturating_sub(left_before_packing_ack_frame - left),
+        );
+
+        let mut challenge_data = None;
+
+        let active_path = self.paths.get_active_mut()?;
+
+        if pkt_type == packet::Type::Short {
+            // Create PMTUD probe.
+            //
+            // In order to send a PMTUD probe the current `left` value, which was
+            // already limited by the current PMTU measure, needs to be ignored,
+            // but the outgoing packet still needs to be limited by
+            // the output buffer size, as well as the congestion
+            // window.
+            //
+            // In addition, the PMTUD probe is only generated when the handshake
+            // is confirmed, to avoid interfering with the handshake
+            // (e.g. due to the anti-amplification limits).
+
+            let pmtu_probe = active_path.should_send_pmtu_probe(
+                self.handshake_confirmed,
+                self.handshake_done_sent,
+                out_len,
+                is_closing,
+                frames.is_empty(),
+            );
+
+            trace!("{} pmtud probe status {} hs_con={} hs_sent={} cwnd_avail={} out_len={} left={}", self.trace_id, pmtu_probe, self.handshake_confirmed, self.handshake_done_sent,
+                    active_path.recovery.cwnd_available(), out_len, left);
+
+            if pmtu_probe {
+                trace!(
+                    "{} sending pmtud probe pmtu_probe={} next_size={} pmtu={}",
+                    self.trace_id,
+                    active_path.pmtud.get_probe_size(),
+                    active_path.pmtud.get_probe_status(),
+                    active_path.pmtud.get_current(),
+                );
+
+                left = active_path.pmtud.get_probe_size();
+
+                match left.checked_sub(overhead) {
+                    Some(v) => left = v,
+
+                    None => {
+                        // We can't send more because there isn't enough space
+                        // available in the output buffer.
+                        //
+                        // This usually happens when we try to send a new packet
+                        // but failed because cwnd is almost full.
+                        //
+                        // In such case app_limited is set to false here to make
+                        // cwnd grow when ACK is received.
+                        active_path.recovery.update_app_limited(false);
+                        return Err(Error::Done);
+                    },
+                }
+
+                let frame = frame::Frame::Padding {
+                    len: active_path.pmtud.get_probe_size() - overhead - 1,
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    let frame = frame::Frame::Ping {
+                        mtu_probe: Some(active_path.pmtud.get_probe_size()),
+                    };
+
+                    if push_frame_to_pkt!(b, frames, frame, left) {
+                        ack_eliciting = true;
+                        in_flight = true;
+                    }
+                }
 
                 pmtud_probe = true;
             }
 
-            let path = self.paths.get_mut(send_pid)?;
-            // Create PATH_RESPONSE frame if needed.
-            // We do not try to ensure that these are really sent.
-            while let Some(challenge) = path.pop_received_challenge() {
-                let frame = frame::Frame::PathResponse { data: challenge };
+            let path = self.paths.get_mut(send_pid)?;
+            // Create PATH_RESPONSE frame if needed.
+            // We do not try to ensure that these are really sent.
+            while let Some(challenge) = path.pop_received_challenge() {
+                let frame = frame::Frame::PathResponse { data: challenge };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    ack_eliciting = true;
+                    in_flight = true;
+                } else {
+                    // If there are other pending PATH_RESPONSE, don't lose them
+                    // now.
+                    break;
+                }
+            }
+
+            // Create PATH_CHALLENGE frame if needed.
+            if path.validation_requested() {
+                // TODO: ensure that data is unique over paths.
+                let data = rand::rand_u64().to_be_bytes();
+
+                let frame = frame::Frame::PathChallenge { data };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    // Let's notify the path once we know the packet size.
+                    challenge_data = Some(data);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            if let Some(key_update) = pkt_space.key_update.as_mut() {
+                key_update.update_acked = true;
+            }
+        }
+
+        let path = self.paths.get_mut(send_pid)?;
+
+        if pkt_type == packet::Type::Short && !is_closing {
+            // Create NEW_CONNECTION_ID frames as needed.
+            while let Some(seq_num) = self.ids.next_advertise_new_scid_seq() {
+                let frame = self.ids.get_new_connection_id_frame_for(seq_num)?;
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.ids.mark_advertise_new_scid_seq(seq_num, false);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                } else {
+                    break;
+                }
+            }
+        }
+
+        if pkt_type == packet::Type::Short && !is_closing && path.active() {
+            // Create HANDSHAKE_DONE frame.
+            // self.should_send_handshake_done() but without the need to borrow
+            if self.handshake_completed &&
+                !self.handshake_done_sent &&
+                self.is_server
+            {
+                let frame = frame::Frame::HandshakeDone;
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.handshake_done_sent = true;
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create MAX_STREAMS_BIDI frame.
+            if self.streams.should_update_max_streams_bidi() {
+                let frame = frame::Frame::MaxStreamsBidi {
+                    max: self.streams.max_streams_bidi_next(),
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.streams.update_max_streams_bidi();
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create MAX_STREAMS_UNI frame.
+            if self.streams.should_update_max_streams_uni() {
+                let frame = frame::Frame::MaxStreamsUni {
+                    max: self.streams.max_streams_uni_next(),
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.streams.update_max_streams_uni();
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create DATA_BLOCKED frame.
+            if let Some(limit) = self.blocked_limit {
+                let frame = frame::Frame::DataBlocked { limit };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.blocked_limit = None;
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create MAX_STREAM_DATA frames as needed.
+            for stream_id in self.streams.almost_full() {
+                let stream = match self.streams.get_mut(stream_id) {
+                    Some(v) => v,
+
+                    None => {
+                        // The stream doesn't exist anymore, so remove it from
+                        // the almost full set.
+                        self.streams.remove_almost_full(stream_id);
+                        continue;
+                    },
+                };
+
+                // Autotune the stream window size.
+                stream.recv.autotune_window(now, path.recovery.rtt());
+
+                let frame = frame::Frame::MaxStreamData {
+                    stream_id,
+                    max: stream.recv.max_data_next(),
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    let recv_win = stream.recv.window();
+
+                    stream.recv.update_max_data(now);
+
+                    self.streams.remove_almost_full(stream_id);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+
+                    // Make sure the connection window always has some
+                    // room compared to the stream window.
+                    flow_control.ensure_window_lower_bound(
+                        (recv_win as f64 * CONNECTION_WINDOW_FACTOR) as u64,
+                    );
+
+                    // Also send MAX_DATA when MAX_STREAM_DATA is sent, to avoid a
+                    // potential race condition.
+                    self.almost_full = true;
+                }
+            }
+
+            for stream_id in self.streams.force_update() {
+                let stream = match self.streams.get_mut(stream_id) {
+                    Some(v) => v,
+                    None => {
+                        self.streams.remove_force_update(stream_id);
+                        continue;
+                    },
+                };
+
+                let frame = frame::Frame::MaxStreamData {
+                    stream_id,
+                    max: stream.recv.max_data()
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.streams.remove_force_update(stream_id);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+
+                    self.almost_full = true;
+                }
+            }
+
+            // Create MAX_DATA frame as needed.
+            if self.almost_full &&
+                flow_control.max_data() < flow_control.max_data_next()
+            {
+                // Autotune the connection window size.
+                flow_control.autotune_window(now, path.recovery.rtt());
+
+                let frame = frame::Frame::MaxData {
+                    max: flow_control.max_data_next(),
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.almost_full = false;
+
+                    // Commits the new max_rx_data limit.
+                    flow_control.update_max_data(now);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create STOP_SENDING frames as needed.
+            for (stream_id, error_code) in self
+                .streams
+                .stopped()
+                .map(|(&k, &v)| (k, v))
+                .collect::<Vec<(u64, u64)>>()
+            {
+                let frame = frame::Frame::StopSending {
+                    stream_id,
+                    error_code,
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.streams.remove_stopped(stream_id);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create RESET_STREAM frames as needed.
+            for (stream_id, (error_code, final_size)) in self
+                .streams
+                .reset()
+                .map(|(&k, &v)| (k, v))
+                .collect::<Vec<(u64, (u64, u64))>>()
+            {
+                let frame = frame::Frame::ResetStream {
+                    stream_id,
+                    error_code,
+                    final_size,
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.streams.remove_reset(stream_id);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create RESET_STREAM_AT frames as needed.
+            for (stream_id, (error_code, final_size, reliable_size)) in self
+                .streams
+                .reset_at()
+                .map(|(&k, &v)| (k, v))
+                .collect::<Vec<(u64, (u64, u64, u64))>>()
+            {
+                let frame = frame::Frame::ResetStreamAt {
+                    stream_id,
+                    error_code,
+                    final_size,
+                    reliable_size,
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.streams.remove_reset_at(stream_id);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create STREAM_DATA_BLOCKED frames as needed.
+            for (stream_id, limit) in self
+                .streams
+                .blocked()
+                .map(|(&k, &v)| (k, v))
+                .collect::<Vec<(u64, u64)>>()
+            {
+                let frame = frame::Frame::StreamDataBlocked { stream_id, limit };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.streams.remove_blocked(stream_id);
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create RETIRE_CONNECTION_ID frames as needed.
+            while let Some(seq_num) = self.ids.next_retire_dcid_seq() {
+                // The sequence number specified in a RETIRE_CONNECTION_ID frame
+                // MUST NOT refer to the Destination Connection ID field of the
+                // packet in which the frame is contained.
+                let dcid_seq = path.active_dcid_seq.ok_or(Error::InvalidState)?;
+
+                if seq_num == dcid_seq {
+                    continue;
+                }
+
+                let frame = frame::Frame::RetireConnectionId { seq_num };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.ids.mark_retire_dcid_seq(seq_num, false)?;
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                } else {
+                    break;
+                }
+            }
+
+            // Create NEW_TOKEN frame.
+            if let Some(token) = &self.new_token {
+                let frame = frame::Frame::NewToken { token: token.clone() };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.new_token = None;
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create BDP frame, with the latest parameters on the server or
+            // the echoed ones on the client.
+            let bdp = match &path.latest_cr_event {
+                Some(event) if self.is_server && self.bdp_frame_pending => {
+                    // Seal the parameters, so the server can trust them when
+                    // they are echoed.
+                    let token = self
+                        .resume_blob_key
+                        .as_ref()
+                        .and_then(|key| {
+                            key.seal(
+                                event,
+                                path.peer_addr().ip(),
+                                time::SystemTime::now(),
+                            )
+                            .ok()
+                        })
+                        .unwrap_or_default();
+
+                    Some(BdpFrame::new(event, self.resume_blob_max_age, token))
+                },
+
+                _ => self.bdp_frame_echo.clone(),
+            };
+
+            let bdp_frame_enabled = self.local_transport_params.bdp_frame &&
+                self.peer_transport_params.bdp_frame;
+
+            if let Some(bdp) = bdp.filter(|_| bdp_frame_enabled) {
+                let frame = frame::Frame::Bdp {
+                    lifetime: bdp.lifetime.as_secs(),
+                    saved_capacity: bdp.saved_capacity as u64,
+                    saved_rtt: bdp.saved_rtt.as_micros() as u64,
+                    token: bdp.token,
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.bdp_frame_pending = false;
+                    self.bdp_frame_echo = None;
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+
+            // Create CR_HINTS frame.
+            let cr_hints_enabled = self.local_transport_params.cr_hints &&
+                self.peer_transport_params.cr_hints;
+
+            if let Some(hints) =
+                self.cr_hints_pending.filter(|_| cr_hints_enabled)
+            {
+                let frame = frame::Frame::CrHints {
+                    seq: self.cr_hints_seq,
+                    lifetime: hints.lifetime.as_secs(),
+                    capacity: hints.capacity as u64,
+                    min_rtt: hints.min_rtt.as_micros() as u64,
+                    max_jump: hints.max_jump as u64,
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    self.cr_hints_pending = None;
+
+                    ack_eliciting = true;
+                    in_flight = true;
+                }
+            }
+        }
+
+        // Create CONNECTION_CLOSE frame. Try to send this only on the active
+        // path, unless it is the last one available.
+        if path.active() || n_paths == 1 {
+            if let Some(conn_err) = self.local_error.as_ref() {
+                if conn_err.is_app {
+                    // Create ApplicationClose frame.
+                    if pkt_type == packet::Type::Short {
+                        let frame = frame::Frame::ApplicationClose {
+                            error_code: conn_err.error_code,
+                            reason: conn_err.reason.clone(),
+                        };
+
+                        if push_frame_to_pkt!(b, frames, frame, left) {
+                            let pto = path.recovery.pto();
+                            self.draining_timer = Some(now + (pto * 3));
+
+                            ack_eliciting = true;
+                            in_flight = true;
+                        }
+                    }
+                } else {
+                    // Create ConnectionClose frame.
+                    let frame = frame::Frame::ConnectionClose {
+                        error_code: conn_err.error_code,
+                        frame_type: 0,
+                        reason: conn_err.reason.clone(),
+                    };
+
+                    if push_frame_to_pkt!(b, frames, frame, left) {
+                        let pto = path.recovery.pto();
+                        self.draining_timer = Some(now + (pto * 3));
+
+                        ack_eliciting = true;
+                        in_flight = true;
+                    }
+                }
+            }
+        }
+
+        // Create CRYPTO frame.
+        if pkt_space.crypto_stream.is_flushable() &&
+            left > frame::MAX_CRYPTO_OVERHEAD &&
+            !is_closing &&
+            path.active()
+        {
+            let crypto_off = pkt_space.crypto_stream.send.off_front();
+
+            // Encode the frame.
+            //
+            // Instead of creating a `frame::Frame` object, encode the frame
+            // directly into the packet buffer.
+            //
+            // First we reserve some space in the output buffer for writing the
+            // frame header (we assume the length field is always a 2-byte
+            // varint as we don't know the value yet).
+            //
+            // Then we emit the data from the crypto stream's send buffer.
+            //
+            // Finally we go back and encode the frame header with the now
+            // available information.
+            let hdr_off = b.off();
+            let hdr_len = 1 + // frame type
+                octets::varint_len(crypto_off) + // offset
+                2; // length, always encode as 2-byte varint
+
+            if let Some(max_len) = left.checked_sub(hdr_len) {
+                let (mut crypto_hdr, mut crypto_payload) =
+                    b.split_at(hdr_off + hdr_len)?;
+
+                // Write stream data into the packet buffer.
+                let (len, _) = pkt_space
+                    .crypto_stream
+                    .send
+                    .emit(&mut crypto_payload.as_mut()[..max_len])?;
+
+                // Encode the frame's header.
+                //
+                // Due to how `OctetsMut::split_at()` works, `crypto_hdr` starts
+                // from the initial offset of `b` (rather than the current
+                // offset), so it needs to be advanced to the
+                // initial frame offset.
+                crypto_hdr.skip(hdr_off)?;
+
+                frame::encode_crypto_header(
+                    crypto_off,
+                    len as u64,
+                    &mut crypto_hdr,
+                )?;
+
+                // Advance the packet buffer's offset.
+                b.skip(hdr_len + len)?;
+
+                let frame = frame::Frame::CryptoHeader {
+                    offset: crypto_off,
+                    length: len,
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    ack_eliciting = true;
+                    in_flight = true;
+                    has_data = true;
+                }
+            }
+        }
+
+        // The preference of data-bearing frame to include in a packet
+        // is managed by `self.emit_dgram`. However, whether any frames
+        // can be sent depends on the state of their buffers. In the case
+        // where one type is preferred but its buffer is empty, fall back
+        // to the other type in order not to waste this function call.
+        let mut dgram_emitted = false;
+        let dgrams_to_emit = max_dgram_len.is_some();
+        let stream_to_emit = self.streams.has_flushable();
+
+        let mut do_dgram = self.emit_dgram && dgrams_to_emit;
+        let do_stream = !self.emit_dgram && stream_to_emit;
+
+        if !do_stream && dgrams_to_emit {
+            do_dgram = true;
+        }
+
+        // Create DATAGRAM frame.
+        if (pkt_type == packet::Type::Short || pkt_type == packet::Type::ZeroRTT) &&
+            left > frame::MAX_DGRAM_OVERHEAD &&
+            !is_closing &&
+            path.active() &&
+            do_dgram
+        {
+            if let Some(max_dgram_payload) = max_dgram_len {
+                while let Some(len) = self.dgram_send_queue.peek_front_len() {
+                    let hdr_off = b.off();
+                    let hdr_len = 1 + // frame type
+                        2; // length, always encode as 2-byte varint
+
+                    if (hdr_len + len) <= left {
+                        // Front of the queue fits this packet, send it.
+                        match self.dgram_send_queue.pop() {
+                            Some(data) => {
+                                // Encode the frame.
+                                //
+                                // Instead of creating a `frame::Frame` object,
+                                // encode the frame directly into the packet
+                                // buffer.
+                                //
+                                // First we reserve some space in the output
+                                // buffer for writing the frame header (we
+                                // assume the length field is always a 2-byte
+                                // varint as we don't know the value yet).
+                                //
+                                // Then we emit the data from the DATAGRAM's
+                                // buffer.
+                                //
+                                // Finally we go back and encode the frame
+                                // header with the now available information.
+                                let (mut dgram_hdr, mut dgram_payload) =
+                                    b.split_at(hdr_off + hdr_len)?;
+
+                                dgram_payload.as_mut()[..len]
+                                    .copy_from_slice(&data);
+
+                                // Encode the frame's header.
+                                //
+                                // Due to how `OctetsMut::split_at()` works,
+                                // `dgram_hdr` starts from the initial offset
+                                // of `b` (rather than the current offset), so
+                                // it needs to be advanced to the initial frame
+                                // offset.
+                                dgram_hdr.skip(hdr_off)?;
+
+                                frame::encode_dgram_header(
+                                    len as u64,
+                                    &mut dgram_hdr,
+                                )?;
+
+                                // Advance the packet buffer's offset.
+                                b.skip(hdr_len + len)?;
+
+                                let frame =
+                                    frame::Frame::DatagramHeader { length: len };
+
+                                if push_frame_to_pkt!(b, frames, frame, left) {
+                                    ack_eliciting = true;
+                                    in_flight = true;
+                                    dgram_emitted = true;
+                                }
+                            },
+
+                            None => continue,
+                        };
+                    } else if len > max_dgram_payload {
+                        // This dgram frame will never fit. Let's purge it.
+                        self.dgram_send_queue.pop();
+                    } else {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Create a single STREAM frame for the first stream that is flushable.
+        if (pkt_type == packet::Type::Short || pkt_type == packet::Type::ZeroRTT) &&
+            left > frame::MAX_STREAM_OVERHEAD &&
+            !is_closing &&
+            path.active() &&
+            !dgram_emitted
+        {
+            while let Some(priority_key) = self.streams.peek_flushable() {
+                let stream_id = priority_key.id;
+                let stream = match self.streams.get_mut(stream_id) {
+                    // Avoid sending frames for streams that were already stopped.
+                    //
+                    // This might happen if stream data was buffered but not yet
+                    // flushed on the wire when a STOP_SENDING frame is received.
+                    Some(v) if !v.send.is_stopped() => v,
+                    _ => {
+                        self.streams.remove_flushable(&priority_key);
+                        continue;
+                    },
+                };
+
+                let stream_off = stream.send.off_front();
+
+                // Data below the largest emitted offset was sent before, and
+                // is being retransmitted.
+                let stream_retransmission = stream.send.is_emitted(stream_off);
+
+                // Encode the frame.
+                //
+                // Instead of creating a `frame::Frame` object, encode the frame
+                // directly into the packet buffer.
+                //
+                // First we reserve some space in the output buffer for writing
+                // the frame header (we assume the length field is always a
+                // 2-byte varint as we don't know the value yet).
+                //
+                // Then we emit the data from the stream's send buffer.
+                //
+                // Finally we go back and encode the frame header with the now
+                // available information.
+                let hdr_off = b.off();
+                let hdr_len = 1 + // frame type
+                    octets::varint_len(stream_id) + // stream_id
+                    octets::varint_len(stream_off) + // offset
+                    2; // length, always encode as 2-byte varint
+
+                let max_len = match left.checked_sub(hdr_len) {
+                    Some(v) => v,
+                    None => {
+                        let priority_key = Arc::clone(&stream.priority_key);
+                        self.streams.remove_flushable(&priority_key);
+
+                        continue;
+                    },
+                };
+
+                let (mut stream_hdr, mut stream_payload) =
+                    b.split_at(hdr_off + hdr_len)?;
+
+                // Write stream data into the packet buffer.
+                let (len, fin) =
+                    stream.send.emit(&mut stream_payload.as_mut()[..max_len])?;
+
+                // Encode the frame's header.
+                //
+                // Due to how `OctetsMut::split_at()` works, `stream_hdr` starts
+                // from the initial offset of `b` (rather than the current
+                // offset), so it needs to be advanced to the initial frame
+                // offset.
+                stream_hdr.skip(hdr_off)?;
+
+                frame::encode_stream_header(
+                    stream_id,
+                    stream_off,
+                    len as u64,
+                    fin,
+                    &mut stream_hdr,
+                )?;
+
+                // Advance the packet buffer's offset.
+                b.skip(hdr_len + len)?;
+
+                let frame = frame::Frame::StreamHeader {
+                    stream_id,
+                    offset: stream_off,
+                    length: len,
+                    fin,
+                };
+
+                if push_frame_to_pkt!(b, frames, frame, left) {
+                    ack_eliciting = true;
+                    in_flight = true;
+                    has_data = true;
+                    retransmission |= stream_retransmission;
+                }
+
+                let priority_key = Arc::clone(&stream.priority_key);
+                // If the stream is no longer flushable, remove it from the queue
+                if !stream.is_flushable() {
+                    self.streams.remove_flushable(&priority_key);
+                } else if stream.incremental {
+                    // Shuffle the incremental stream to the back of the
+                    // queue.
+                    self.streams.remove_flushable(&priority_key);
+                    self.streams.insert_flushable(&priority_key);
+                }
+
+                #[cfg(feature = "fuzzing")]
+                // Coalesce STREAM frames when fuzzing.
+                if left > frame::MAX_STREAM_OVERHEAD {
+                    continue;
+                }
+
+                break;
+            }
+        }
+
+        // Alternate trying to send DATAGRAMs next time.
+        self.emit_dgram = !dgram_emitted;
+
+        // If no other ack-eliciting frame is sent, include a PING frame
+        // - if PTO probe needed; OR
+        // - if we've sent too many non ack-eliciting packets without having
+        // sent an ACK eliciting one; OR
+        // - the application requested an ack-eliciting frame be sent.
+        if (ack_elicit_required || path.needs_ack_eliciting) &&
+            !ack_eliciting &&
+            left >= 1 &&
+            !is_closing
+        {
+            let frame = frame::Frame::Ping { mtu_probe: None };
+
+            if push_frame_to_pkt!(b, frames, frame, left) {
+                ack_eliciting = true;
+                in_flight = true;
+            }
+        }
+
+        if ack_eliciting && !pmtud_probe {
+            path.needs_ack_eliciting = false;
+            path.recovery.ping_sent(epoch);
+        }
+
+        if frames.is_empty() {
+            // When we reach this point we are not able to write more, so set
+            // app_limited to false.
+            path.recovery.update_app_limited(false);
+            return Err(Error::Done);
+        }
+
+        // When coalescing a 1-RTT packet, we can't add padding in the UDP
+        // datagram, so use PADDING frames instead.
+        //
+        // This is only needed if
+        // 1) an Initial packet has already been written to the UDP datagram,
+        // as Initial always requires padding.
+        //
+        // 2) this is a probing packet towards an unvalidated peer address.
+        if (has_initial || !path.validated()) &&
+            pkt_type == packet::Type::Short &&
+            left >= 1
+        {
+            let frame = frame::Frame::Padding { len: left };
+
+            if push_frame_to_pkt!(b, frames, frame, left) {
+                in_flight = true;
+            }
+        }
+
+        // Pad payload so that it's always at least 4 bytes.
+        if b.off() - payload_offset < PAYLOAD_MIN_LEN {
+            let payload_len = b.off() - payload_offset;
+
+            let frame = frame::Frame::Padding {
+                len: PAYLOAD_MIN_LEN - payload_len,
+            };
+
+            #[allow(unused_assignments)]
+            if push_frame_to_pkt!(b, frames, frame, left) {
+                in_flight = true;
+            }
+        }
+
+        let payload_len = b.off() - payload_offset;
+
+        // Fill in payload length.
+        if pkt_type != packet::Type::Short {
+            let len = pn_len + payload_len + crypto_overhead;
+
+            let (_, mut payload_with_len) = b.split_at(header_offset)?;
+            payload_with_len
+                .put_varint_with_len(len as u64, PAYLOAD_LENGTH_LEN)?;
+        }
+
+        trace!(
+            "{} tx pkt {} len={} pn={} {}",
+            self.trace_id,
+            hdr_trace.unwrap_or_default(),
+            payload_len,
+            pn,
+            AddrTupleFmt(path.local_addr(), path.peer_addr())
+        );
+
+        #[cfg(feature = "qlog")]
+        let mut qlog_frames: SmallVec<
+            [qlog::events::quic::QuicFrame; 1],
+        > = SmallVec::with_capacity(frames.len());
+
+        for frame in &mut frames {
+            trace!("{} tx frm {:?}", self.trace_id, frame);
+
+            qlog_with_type!(QLOG_PACKET_TX, self.qlog, _q, {
+                qlog_frames.push(frame.to_qlog());
+            });
+        }
+
+        qlog_with_type!(QLOG_PACKET_TX, self.qlog, q, {
+            if let Some(header) = qlog_pkt_hdr {
+                // Qlog packet raw info described at
+                // https://datatracker.ietf.org/doc/html/draft-ietf-quic-qlog-main-schema-00#section-5.1
+                //
+                // `length` includes packet headers and trailers (AEAD tag).
+                let length = payload_len + payload_offset + crypto_overhead;
+                let qlog_raw_info = RawInfo {
+                    length: Some(length as u64),
+                    payload_length: Some(payload_len as u64),
+                    data: None,
+                };
+
+                let send_at_time =
+                    now.duration_since(q.start_time()).as_secs_f32() * 1000.0;
+
+                let ev_data =
+                    EventData::PacketSent(qlog::events::quic::PacketSent {
+                        header,
+                        frames: Some(qlog_frames),
+                        is_coalesced: None,
+                        retry_token: None,
+                        stateless_reset_token: None,
+                        supported_versions: None,
+                        raw: Some(qlog_raw_info),
+                        datagram_id: None,
+                        send_at_time: Some(send_at_time),
+                        trigger: None,
+                    });
+
+                q.add_event_data_with_instant(ev_data, now).ok();
+            }
+        });
+
+        let aead = match pkt_space.crypto_seal {
+            Some(ref v) => v,
+            None => return Err(Error::InvalidState),
+        };
+
+        let crypto_start = time::Instant::now();
+
+        let written = packet::encrypt_pkt(
+            &mut b,
+            pn,
+            pn_len,
+            payload_len,
+            payload_offset,
+            None,
+            aead,
+        );
+
+        self.crypto_time += crypto_start.elapsed();
+
+        let written = written?;
+
+        self.crypto_sealed_bytes += payload_len as u64;
+
+        let sent_pkt = recovery::Sent {
+            pkt_num: pn,
+            frames,
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: if ack_eliciting { written } else { 0 },
+            ack_eliciting,
+            in_flight,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            tx_in_flight: 0,
+            lost: 0,
+            has_data,
+            pmtud: pmtud_probe,
+            retransmission,
+        };
+
+        if in_flight && is_app_limited {
+            path.recovery.delivery_rate_update_app_limited(true);
+        }
+
+        pkt_space.next_pkt_num += 1;
+
+        let handshake_status = recovery::HandshakeStatus {
+            has_handshake_keys: self.pkt_num_spaces[packet::Epoch::Handshake]
+                .has_keys(),
+            peer_verified_address: self.peer_verified_initial_address,
+            completed: self.handshake_completed,
+        };
+
+        // Careful Resume doesn't jump beyond what the peer's flow control
+        // limit lets us send.
+        path.recovery.set_cr_flow_control_credit(
+            (self.max_tx_data - self.tx_data) as usize,
+        );
+
+        path.recovery.on_packet_sent(
+            sent_pkt,
+            epoch,
+            handshake_status,
+            now,
+            &self.trace_id,
+        );
+
+        qlog_with_type!(QLOG_METRICS, self.qlog, q, {
+            let sampler = &mut self.qlog.metrics_sampler;
+
+            if let Some(ev_data) = path.recovery.maybe_qlog(|| sampler.sample()) {
+                self.qlog.stats.add_event_data(q, ev_data, now);
+            }
+        });
+        qlog_with_type!(QLOG_CR_PHASE, self.qlog, q, {
+            if let Some(ev_data) = path.recovery.maybe_cr_qlog() {
+                self.qlog.stats.add_event_data(q, ev_data, now);
+            }
+        });
+
+        // Record sent packet size if we probe the path.
+        if let Some(data) = challenge_data {
+            path.add_challenge_sent(data, written, now);
+        }
+
+        self.sent_count += 1;
+        self.sent_bytes += written as u64;
+        path.sent_count += 1;
+        path.sent_bytes += written as u64;
+
+        if self.dgram_send_queue.byte_size() > path.recovery.cwnd_available() {
+            path.recovery.update_app_limited(false);
+        }
+
+        path.max_send_bytes = path.max_send_bytes.saturating_sub(written);
+
+        if path.active() {
+            if let Some(cr_event) = path.recovery.maybe_cr_event(now) {
+                self.update_cr_event(send_pid, cr_event);
+            }
+        }
+
+        // On the client, drop initial state after sending an Handshake packet.
+        if !self.is_server && hdr_ty == packet::Type::Handshake {
+            self.drop_epoch_state(packet::Epoch::Initial, now);
+        }
+
+        // (Re)start the idle timer if we are sending the first ack-eliciting
+        // packet since last receiving a packet.
+        if ack_eliciting && !self.ack_eliciting_sent {
+            if let Some(idle_timeout) = self.idle_timeout() {
+                self.idle_timer = Some(now + idle_timeout);
+            }
+        }
+
+        if ack_eliciting {
+            self.ack_eliciting_sent = true;
+        }
+
+        let active_path = self.paths.get_active_mut()?;
+        if active_path.pmtud.is_enabled() {
+            active_path
+                .recovery
+                .pmtud_update_max_datagram_size(active_path.pmtud.get_current());
+        }
+
+        Ok((pkt_type, written))
+    }
+
+    /// Returns the size of the send quantum, in bytes.
+    ///
+    /// This represents the maximum size of a packet burst as determined by the
+    /// congestion control algorithm in use.
+    ///
+    /// Applications can, for example, use it in conjunction with segmentation
+    /// offloading mechanisms as the maximum limit for outgoing aggregates of
+    /// multiple packets.
+    #[inline]
+    pub fn send_quantum(&self) -> usize {
+        match self.paths.get_active() {
+            Ok(p) => p.recovery.send_quantum(),
+            _ => 0,
+        }
+    }
+
+    /// Returns the size of the send quantum over the given 4-tuple, in bytes.
+    ///
+    /// This represents the maximum size of a packet burst as determined by the
+    /// congestion control algorithm in use.
+    ///
+    /// Applications can, for example, use it in conjunction with segmentation
+    /// offloading mechanisms as the maximum limit for outgoing aggregates of
+    /// multiple packets.
+    ///
+    /// If the (`local_addr`, peer_addr`) 4-tuple relates to a non-existing
+    /// path, this method returns 0.
+    pub fn send_quantum_on_path(
+        &self, local_addr: SocketAddr, peer_addr: SocketAddr,
+    ) -> usize {
+        self.paths
+            .path_id_from_addrs(&(local_addr, peer_addr))
+            .and_then(|pid| self.paths.get(pid).ok())
+            .map(|path| path.recovery.send_quantum())
+            .unwrap_or(0)
+    }
+
+    /// Returns the current pacing rate of the active path, in bytes per
+    /// second.
+    ///
+    /// Applications sending through a socket paced by the kernel, e.g. by the
+    /// Linux `fq` queueing discipline, can apply it with the
+    /// `SO_MAX_PACING_RATE` socket option instead of honoring
+    /// [`SendInfo.at`], and leave the fine-grained spreading of bursts, such
+    /// as the Careful Resume jump, to the kernel. The rate follows the
+    /// congestion window, so it should be read again after packets are sent
+    /// or received.
+    ///
+    /// Returns `None` when pacing is disabled, or the rate isn't known yet.
+    ///
+    /// [`SendInfo.at`]: struct.SendInfo.html#structfield.at
+    pub fn pacing_rate(&self) -> Option<u64> {
+        self.paths.get_active().ok()?.recovery.pacing_rate()
+    }
+
+    /// Reads contiguous data from a stream into the provided slice.
+    ///
+    /// The slice must be sized by the caller and will be populated up to its
+    /// capacity.
+    ///
+    /// On success the amount of bytes read and a flag indicating the fin state
+    /// is returned as a tuple, or [`Done`] if there is no data to read.
+    ///
+    /// Reading data from a stream may trigger queueing of control messages
+    /// (e.g. MAX_STREAM_DATA). [`send()`] should be called after reading.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`send()`]: struct.Connection.html#method.send
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # let stream_id = 0;
+    /// while let Ok((read, fin)) = conn.stream_recv(stream_id, &mut buf) {
+    ///     println!("Got {} bytes on stream {}", read, stream_id);
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn stream_recv(
+        &mut self, stream_id: u64, out: &mut [u8],
+    ) -> Result<(usize, bool)> {
+        // We can't read on our own unidirectional streams.
+        if !stream::is_bidi(stream_id) &&
+            stream::is_local(stream_id, self.is_server)
+        {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        let stream = self
+            .streams
+            .get_mut(stream_id)
+            .ok_or(Error::InvalidStreamState(stream_id))?;
+
+        if !stream.is_readable() {
+            return Err(Error::Done);
+        }
+
+        let local = stream.local;
+        let priority_key = Arc::clone(&stream.priority_key);
+
+        #[cfg(feature = "qlog")]
+        let offset = stream.recv.off_front();
+
+        let (read, fin) = match stream.recv.emit(out) {
+            Ok(v) => v,
+
+            Err(e) => {
+                // Collect the stream if it is now complete. This can happen if
+                // we got a `StreamReset` error which will now be propagated to
+                // the application, so we don't need to keep the stream's state
+                // anymore.
+                if stream.is_complete() {
+                    self.streams.collect(stream_id, local);
+                }
+
+                self.streams.remove_readable(&priority_key);
+                return Err(e);
+            },
+        };
+
+        self.flow_control.add_consumed(read as u64);
+
+        let readable = stream.is_readable();
+
+        let complete = stream.is_complete();
+
+        if stream.recv.almost_full() {
+            self.streams.insert_almost_full(stream_id);
+        }
+
+        if !readable {
+            self.streams.remove_readable(&priority_key);
+        }
+
+        if complete {
+            self.streams.collect(stream_id, local);
+        }
+
+        qlog_with_type!(QLOG_DATA_MV, self.qlog, q, {
+            let ev_data = EventData::DataMoved(qlog::events::quic::DataMoved {
+                stream_id: Some(stream_id),
+                offset: Some(offset),
+                length: Some(read as u64),
+                from: Some(DataRecipient::Transport),
+                to: Some(DataRecipient::Application),
+                raw: None,
+            });
+
+            let now = time::Instant::now();
+            q.add_event_data_with_instant(ev_data, now).ok();
+        });
+
+        if self.should_update_max_data() {
+            self.almost_full = true;
+        }
+
+        if priority_key.incremental && readable {
+            // Shuffle the incremental stream to the back of the queue.
+            self.streams.remove_readable(&priority_key);
+            self.streams.insert_readable(&priority_key);
+        }
+
+        Ok((read, fin))
+    }
+
+    /// Writes data to a stream.
+    ///
+    /// On success the number of bytes written is returned, or [`Done`] if no
+    /// data was written (e.g. because the stream has no capacity).
+    ///
+    /// Applications can provide a 0-length buffer with the fin flag set to
+    /// true. This will lead to a 0-length FIN STREAM frame being sent at the
+    /// latest offset. The `Ok(0)` value is only returned when the application
+    /// provided a 0-length buffer.
+    ///
+    /// In addition, if the peer has signalled that it doesn't want to receive
+    /// any more data from this stream by sending the `STOP_SENDING` frame, the
+    /// [`StreamStopped`] error will be returned instead of any data.
+    ///
+    /// Note that in order to avoid buffering an infinite amount of data in the
+    /// stream's send buffer, streams are only allowed to buffer outgoing data
+    /// up to the amount that the peer allows it to send (that is, up to the
+    /// stream's outgoing flow control capacity).
+    ///
+    /// This means that the number of written bytes returned can be lower than
+    /// the length of the input buffer when the stream doesn't have enough
+    /// capacity for the operation to complete. The application should retry the
+    /// operation once the stream is reported as writable again.
+    ///
+    /// Applications should call this method only after the handshake is
+    /// completed (whenever [`is_established()`] returns `true`) or during
+    /// early data if enabled (whenever [`is_in_early_data()`] returns `true`).
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`StreamStopped`]: enum.Error.html#variant.StreamStopped
+    /// [`is_established()`]: struct.Connection.html#method.is_established
+    /// [`is_in_early_data()`]: struct.Connection.html#method.is_in_early_data
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// # let stream_id = 0;
+    /// conn.stream_send(stream_id, b"hello", true)?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn stream_send(
+        &mut self, stream_id: u64, buf: &[u8], fin: bool,
+    ) -> Result<usize> {
+        // We can't write on the peer's unidirectional streams.
+        if !stream::is_bidi(stream_id) &&
+            !stream::is_local(stream_id, self.is_server)
+        {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        // Mark the connection as blocked if the connection-level flow control
+        // limit doesn't let us buffer all the data.
+        //
+        // Note that this is separate from "send capacity" as that also takes
+        // congestion control into consideration.
+        if self.max_tx_data - self.tx_data < buf.len() as u64 {
+            self.blocked_limit = Some(self.max_tx_data);
+        }
+
+        let cap = self.tx_cap;
+
+        // Get existing stream or create a new one.
+        let stream = self.get_or_create_stream(stream_id, true)?;
+
+        #[cfg(feature = "qlog")]
+        let offset = stream.send.off_back();
+
+        let was_writable = stream.is_writable();
+
+        let was_flushable = stream.is_flushable();
+
+        let priority_key = Arc::clone(&stream.priority_key);
+
+        // Truncate the input buffer based on the connection's send capacity if
+        // necessary.
+        //
+        // When the cap is zero, the method returns Ok(0) *only* when the passed
+        // buffer is empty. We return Error::Done otherwise.
+        if cap == 0 && !buf.is_empty() {
+            if was_writable {
+                // When `stream_writable_next()` returns a stream, the writable
+                // mark is removed, but because the stream is blocked by the
+                // connection-level send capacity it won't be marked as writable
+                // again once the capacity increases.
+                //
+                // Since the stream is writable already, mark it here instead.
+                self.streams.insert_writable(&priority_key);
+            }
+
+            return Err(Error::Done);
+        }
+
+        let (buf, fin, blocked_by_cap) = if cap < buf.len() {
+            (&buf[..cap], false, true)
+        } else {
+            (buf, fin, false)
+        };
+
+        let sent = match stream.send.write(buf, fin) {
+            Ok(v) => v,
+
+            Err(e) => {
+                self.streams.remove_writable(&priority_key);
+                return Err(e);
+            },
+        };
+
+        let incremental = stream.incremental;
+        let priority_key = Arc::clone(&stream.priority_key);
+
+        let flushable = stream.is_flushable();
+
+        let writable = stream.is_writable();
+
+        let empty_fin = buf.is_empty() && fin;
+
+        if sent < buf.len() {
+            let max_off = stream.send.max_off();
+
+            if stream.send.blocked_at() != Some(max_off) {
+                stream.send.update_blocked_at(Some(max_off));
+                self.streams.insert_blocked(stream_id, max_off);
+            }
+        } else {
+            stream.send.update_blocked_at(None);
+            self.streams.remove_blocked(stream_id);
+        }
+
+        // If the stream is now flushable push it to the flushable queue, but
+        // only if it wasn't already queued.
+        //
+        // Consider the stream flushable also when we are sending a zero-length
+        // frame that has the fin flag set.
+        if (flushable || empty_fin) && !was_flushable {
+            self.streams.insert_flushable(&priority_key);
+        }
+
+        if !writable {
+            self.streams.remove_writable(&priority_key);
+        } else if was_writable && blocked_by_cap {
+            // When `stream_writable_next()` returns a stream, the writable
+            // mark is removed, but because the stream is blocked by the
+            // connection-level send capacity it won't be marked as writable
+            // again once the capacity increases.
+            //
+            // Since the stream is writable already, mark it here instead.
+            self.streams.insert_writable(&priority_key);
+        }
+
+        self.tx_cap -= sent;
+
+        self.tx_data += sent as u64;
+
+        self.tx_buffered += sent;
+
+        qlog_with_type!(QLOG_DATA_MV, self.qlog, q, {
+            let ev_data = EventData::DataMoved(qlog::events::quic::DataMoved {
+                stream_id: Some(stream_id),
+                offset: Some(offset),
+                length: Some(sent as u64),
+                from: Some(DataRecipient::Application),
+                to: Some(DataRecipient::Transport),
+                raw: None,
+            });
+
+            let now = time::Instant::now();
+            q.add_event_data_with_instant(ev_data, now).ok();
+        });
+
+        if sent == 0 && !buf.is_empty() {
+            return Err(Error::Done);
+        }
+
+        if incremental && writable {
+            // Shuffle the incremental stream to the back of the queue.
+            self.streams.remove_writable(&priority_key);
+            self.streams.insert_writable(&priority_key);
+        }
+
+        Ok(sent)
+    }
+
+    /// Sets the priority for a stream.
+    ///
+    /// A stream's priority determines the order in which stream data is sent
+    /// on the wire (streams with lower priority are sent first). Streams are
+    /// created with a default priority of `127`.
+    ///
+    /// The target stream is created if it did not exist before calling this
+    /// method.
+    pub fn stream_priority(
+        &mut self, stream_id: u64, urgency: u8, incremental: bool,
+    ) -> Result<()> {
+        // Get existing stream or create a new one, but if the stream
+        // has already been closed and collected, ignore the prioritization.
+        let stream = match self.get_or_create_stream(stream_id, true) {
+            Ok(v) => v,
+
+            Err(Error::Done) => return Ok(()),
+
+            Err(e) => return Err(e),
+        };
+
+        if stream.urgency == urgency && stream.incremental == incremental {
+            return Ok(());
+        }
+
+        stream.urgency = urgency;
+        stream.incremental = incremental;
+
+        let new_priority_key = Arc::new(StreamPriorityKey {
+            urgency: stream.urgency,
+            incremental: stream.incremental,
+            id: stream_id,
+            ..Default::default()
+        });
+
+        let old_priority_key =
+            std::mem::replace(&mut stream.priority_key, new_priority_key.clone());
+
+        self.streams
+            .update_priority(&old_priority_key, &new_priority_key);
+
+        Ok(())
+    }
+
+    /// Sets the data flow credit limit for a stream. This will not reduce a flow credit to lower
+    /// than it already is. Causes a `MAX_STREAM_DATA` frame to be sent.
+    pub fn stream_max_data(
+        &mut self, stream_id: u64, max_data: u64
+    ) -> Result<()> {
+        let stream = match self.get_or_create_stream(stream_id, true) {
+            Ok(v) => v,
+            Err(Error::Done) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        stream.recv.set_max_data(max_data, time::Instant::now());
+        self.streams.insert_force_update(stream_id);
+
+        Ok(())
+    }
+
+    /// Shuts down reading or writing from/to the specified stream.
+    ///
+    /// When the `direction` argument is set to [`Shutdown::Read`], outstanding
+    /// data in the stream's receive buffer is dropped, and no additional data
+    /// is added to it. Data received after calling this method is still
+    /// validated and acked but not stored, and [`stream_recv()`] will not
+    /// return it to the application. In addition, a `STOP_SENDING` frame will
+    /// be sent to the peer to signal it to stop sending data.
+    ///
+    /// When the `direction` argument is set to [`Shutdown::Write`], outstanding
+    /// data in the stream's send buffer is dropped, and no additional data is
+    /// added to it. Data passed to [`stream_send()`] after calling this method
+    /// will be ignored. In addition, a `RESET_STREAM` frame will be sent to the
+    /// peer to signal the reset.
+    ///
+    /// Locally-initiated unidirectional streams can only be closed in the
+    /// [`Shutdown::Write`] direction. Remotely-initiated unidirectional streams
+    /// can only be closed in the [`Shutdown::Read`] direction. Using an
+    /// incorrect direction will return [`InvalidStreamState`].
+    ///
+    /// [`Shutdown::Read`]: enum.Shutdown.html#variant.Read
+    /// [`Shutdown::Write`]: enum.Shutdown.html#variant.Write
+    /// [`stream_recv()`]: struct.Connection.html#method.stream_recv
+    /// [`stream_send()`]: struct.Connection.html#method.stream_send
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    pub fn stream_shutdown(
+        &mut self, stream_id: u64, direction: Shutdown, err: u64,
+    ) -> Result<()> {
+        // Don't try to stop a local unidirectional stream.
+        if direction == Shutdown::Read &&
+            stream::is_local(stream_id, self.is_server) &&
+            !stream::is_bidi(stream_id)
+        {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        // Dont' try to reset a remote unidirectional stream.
+        if direction == Shutdown::Write &&
+            !stream::is_local(stream_id, self.is_server) &&
+            !stream::is_bidi(stream_id)
+        {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        // Get existing stream.
+        let stream = self.streams.get_mut(stream_id).ok_or(Error::Done)?;
+
+        let priority_key = Arc::clone(&stream.priority_key);
+
+        match direction {
+            Shutdown::Read => {
+                stream.recv.shutdown()?;
+
+                if !stream.recv.is_fin() {
+                    self.streams.insert_stopped(stream_id, err);
+                }
+
+                // Once shutdown, the stream is guaranteed to be non-readable.
+                self.streams.remove_readable(&priority_key);
+
+                self.stopped_stream_local_count =
+                    self.stopped_stream_local_count.saturating_add(1);
+            },
+
+            Shutdown::Write => {
+                let (final_size, unsent) = stream.send.shutdown()?;
+
+                // Claw back some flow control allowance from data that was
+                // buffered but not actually sent before the stream was reset.
+                self.tx_data = self.tx_data.saturating_sub(unsent);
+
+                self.tx_buffered =
+                    self.tx_buffered.saturating_sub(unsent as usize);
+
+                // Update send capacity.
+                self.update_tx_cap();
+
+                self.streams.insert_reset(stream_id, err, final_size);
+
+                // Once shutdown, the stream is guaranteed to be non-writable.
+                self.streams.remove_writable(&priority_key);
+
+                self.reset_stream_local_count =
+                    self.reset_stream_local_count.saturating_add(1);
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Resets the sending side of the specified stream, while still
+    /// delivering the data written up to `reliable_size`.
+    ///
+    /// This is like [`stream_shutdown()`] in the [`Shutdown::Write`]
+    /// direction, except that data below `reliable_size` is still sent, and
+    /// retransmitted when lost, before the peer is notified of the reset.
+    /// Data past it is dropped, which lets an application abandon stale data
+    /// without tearing down the stream. A `RESET_STREAM_AT` frame is sent to
+    /// the peer to signal the reset.
+    ///
+    /// The extension must have been enabled by the peer with
+    /// [`Config::enable_reset_stream_at()`], otherwise [`InvalidState`] is
+    /// returned. If `reliable_size` is past the data written to the stream,
+    /// or the stream is a remotely-initiated unidirectional one,
+    /// [`InvalidStreamState`] is returned.
+    ///
+    /// [`stream_shutdown()`]: struct.Connection.html#method.stream_shutdown
+    /// [`Shutdown::Write`]: enum.Shutdown.html#variant.Write
+    /// [`Config::enable_reset_stream_at()`]: struct.Config.html#method.enable_reset_stream_at
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    pub fn stream_reset_at(
+        &mut self, stream_id: u64, err: u64, reliable_size: u64,
+    ) -> Result<()> {
+        if !self.peer_transport_params.reset_stream_at {
+            return Err(Error::InvalidState);
+        }
+
+        // Don't try to reset a remote unidirectional stream.
+        if !stream::is_local(stream_id, self.is_server) &&
+            !stream::is_bidi(stream_id)
+        {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        // Get existing stream.
+        let stream = self.streams.get_mut(stream_id).ok_or(Error::Done)?;
+
+        if reliable_size > stream.send.off_back() {
+            return Err(Error::InvalidStreamState(stream_id));
+        }
+
+        let priority_key = Arc::clone(&stream.priority_key);
+
+        let (final_size, unsent) = stream.send.reset_at(reliable_size)?;
+
+        // Claw back some flow control allowance from data that was buffered
+        // but won't be sent because of the reset.
+        self.tx_data = self.tx_data.saturating_sub(unsent);
+
+        self.tx_buffered = self.tx_buffered.saturating_sub(unsent as usize);
+
+        // Update send capacity.
+        self.update_tx_cap();
+
+        self.streams
+            .insert_reset_at(stream_id, err, final_size, reliable_size);
+
+        // Once reset, the stream is guaranteed to be non-writable.
+        self.streams.remove_writable(&priority_key);
+
+        self.reset_stream_local_count =
+            self.reset_stream_local_count.saturating_add(1);
+
+        Ok(())
+    }
+
+    /// Returns the stream's send capacity in bytes.
+    ///
+    /// If the specified stream doesn't exist (including when it has already
+    /// been completed and closed), the [`InvalidStreamState`] error will be
+    /// returned.
+    ///
+    /// In addition, if the peer has signalled that it doesn't want to receive
+    /// any more data from this stream by sending the `STOP_SENDING` frame, the
+    /// [`StreamStopped`] error will be returned.
+    ///
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    /// [`StreamStopped`]: enum.Error.html#variant.StreamStopped
+    #[inline]
+    pub fn stream_capacity(&self, stream_id: u64) -> Result<usize> {
+        if let Some(stream) = self.streams.get(stream_id) {
+            let cap = cmp::min(self.tx_cap, stream.send.cap()?);
+            return Ok(cap);
+        };
+
+        Err(Error::InvalidStreamState(stream_id))
+    }
+
+    /// Returns the next stream that has data to read.
+    ///
+    /// Note that once returned by this method, a stream ID will not be returned
+    /// again until it is "re-armed".
+    ///
+    /// The application will need to read all of the pending data on the stream,
+    /// and new data has to be received before the stream is reported again.
+    ///
+    /// This is unlike the [`readable()`] method, that returns the same list of
+    /// readable streams when called multiple times in succession.
+    ///
+    /// [`readable()`]: struct.Connection.html#method.readable
+    pub fn stream_readable_next(&mut self) -> Option<u64> {
+        let priority_key = self.streams.readable.front().clone_pointer()?;
+
+        self.streams.remove_readable(&priority_key);
+
+        Some(priority_key.id)
+    }
+
+    /// Returns true if the stream has data that can be read.
+    pub fn stream_readable(&self, stream_id: u64) -> bool {
+        let stream = match self.streams.get(stream_id) {
+            Some(v) => v,
+
+            None => return false,
+        };
+
+        stream.is_readable()
+    }
+
+    /// Returns the next stream that can be written to.
+    ///
+    /// Note that once returned by this method, a stream ID will not be returned
+    /// again until it is "re-armed".
+    ///
+    /// This is unlike the [`writable()`] method, that returns the same list of
+    /// writable streams when called multiple times in succession. It is not
+    /// advised to use both `stream_writable_next()` and [`writable()`] on the
+    /// same connection, as it may lead to unexpected results.
+    ///
+    /// The [`stream_writable()`] method can also be used to fine-tune when a
+    /// stream is reported as writable again.
+    ///
+    /// [`stream_writable()`]: struct.Connection.html#method.stream_writable
+    /// [`writable()`]: struct.Connection.html#method.writable
+    pub fn stream_writable_next(&mut self) -> Option<u64> {
+        // If there is not enough connection-level send capacity, none of the
+        // streams are writable.
+        if self.tx_cap == 0 {
+            return None;
+        }
+
+        let mut cursor = self.streams.writable.front();
+
+        while let Some(priority_key) = cursor.clone_pointer() {
+            if let Some(stream) = self.streams.get(priority_key.id) {
+                let cap = match stream.send.cap() {
+                    Ok(v) => v,
+
+                    // Return the stream to the application immediately if it's
+                    // stopped.
+                    Err(_) =>
+                        return {
+                            self.streams.remove_writable(&priority_key);
+
+                            Some(priority_key.id)
+                        },
+                };
+
+                if cmp::min(self.tx_cap, cap) >= stream.send_lowat {
+                    self.streams.remove_writable(&priority_key);
+                    return Some(priority_key.id);
+                }
+            }
+
+            cursor.move_next();
+        }
+
+        None
+    }
+
+    /// Returns true if the stream has enough send capacity.
+    ///
+    /// When `len` more bytes can be buffered into the given stream's send
+    /// buffer, `true` will be returned, `false` otherwise.
+    ///
+    /// In the latter case, if the additional data can't be buffered due to
+    /// flow control limits, the peer will also be notified, and a "low send
+    /// watermark" will be set for the stream, such that it is not going to be
+    /// reported as writable again by [`stream_writable_next()`] until its send
+    /// capacity reaches `len`.
+    ///
+    /// If the specified stream doesn't exist (including when it has already
+    /// been completed and closed), the [`InvalidStreamState`] error will be
+    /// returned.
+    ///
+    /// In addition, if the peer has signalled that it doesn't want to receive
+    /// any more data from this stream by sending the `STOP_SENDING` frame, the
+    /// [`StreamStopped`] error will be returned.
+    ///
+    /// [`stream_writable_next()`]: struct.Connection.html#method.stream_writable_next
+    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
+    /// [`StreamStopped`]: enum.Error.html#variant.StreamStopped
+    #[inline]
+    pub fn stream_writable(
+        &mut self, stream_id: u64, len: usize,
+    ) -> Result<bool> {
+        if self.stream_capacity(stream_id)? >= len {
+            return Ok(true);
+        }
+
+        let stream = match self.streams.get_mut(stream_id) {
+            Some(v) => v,
+
+            None => return Err(Error::InvalidStreamState(stream_id)),
+        };
+
+        stream.send_lowat = cmp::max(1, len);
+
+        let is_writable = stream.is_writable();
+
+        let priority_key = Arc::clone(&stream.priority_key);
+
+        if self.max_tx_data - self.tx_data < len as u64 {
+            self.blocked_limit = Some(self.max_tx_data);
+        }
+
+        if stream.send.cap()? < len {
+            let max_off = stream.send.max_off();
+            if stream.send.blocked_at() != Some(max_off) {
+                stream.send.update_blocked_at(Some(max_off));
+                self.streams.insert_blocked(stream_id, max_off);
+            }
+        } else if is_writable {
+            // When `stream_writable_next()` returns a stream, the writable
+            // mark is removed, but because the stream is blocked by the
+            // connection-level send capacity it won't be marked as writable
+            // again once the capacity increases.
+            //
+            // Since the stream is writable already, mark it here instead.
+            self.streams.insert_writable(&priority_key);
+        }
+
+        Ok(false)
+    }
+
+    /// Returns true if all the data has been read from the specified stream.
+    ///
+    /// This instructs the application that all the data received from the
+    /// peer on the stream has been read, and there won't be anymore in the
+    /// future.
+    ///
+    /// Basically this returns true when the peer either set the `fin` flag
+    /// for the stream, or sent `RESET_STREAM`.
+    #[inline]
+    pub fn stream_finished(&self, stream_id: u64) -> bool {
+        let stream = match self.streams.get(stream_id) {
+            Some(v) => v,
+
+            None => return true,
+        };
+
+        stream.recv.is_fin()
+    }
+
+    /// Returns the number of bidirectional streams that can be created
+    /// before the peer's stream count limit is reached.
+    ///
+    /// This can be useful to know if it's possible to create a bidirectional
+    /// stream without trying it first.
+    #[inline]
+    pub fn peer_streams_left_bidi(&self) -> u64 {
+        self.streams.peer_streams_left_bidi()
+    }
+
+    /// Returns the number of unidirectional streams that can be created
+    /// before the peer's stream count limit is reached.
+    ///
+    /// This can be useful to know if it's possible to create a unidirectional
+    /// stream without trying it first.
+    #[inline]
+    pub fn peer_streams_left_uni(&self) -> u64 {
+        self.streams.peer_streams_left_uni()
+    }
+
+    /// Returns an iterator over streams that have outstanding data to read.
+    ///
+    /// Note that the iterator will only include streams that were readable at
+    /// the time the iterator itself was created (i.e. when `readable()` was
+    /// called). To account for newly readable streams, the iterator needs to
+    /// be created again.
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// // Iterate over readable streams.
+    /// for stream_id in conn.readable() {
+    ///     // Stream is readable, read until there's no more data.
+    ///     while let Ok((read, fin)) = conn.stream_recv(stream_id, &mut buf) {
+    ///         println!("Got {} bytes on stream {}", read, stream_id);
+    ///     }
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    #[inline]
+    pub fn readable(&self) -> StreamIter {
+        self.streams.readable()
+    }
+
+    /// Returns an iterator over streams that can be written in priority order.
+    ///
+    /// The priority order is based on RFC 9218 scheduling recommendations.
+    /// Stream priority can be controlled using [`stream_priority()`]. In order
+    /// to support fairness requirements, each time this method is called,
+    /// internal state is updated. Therefore the iterator ordering can change
+    /// between calls, even if no streams were added or removed.
+    ///
+    /// A "writable" stream is a stream that has enough flow control capacity to
+    /// send data to the peer. To avoid buffering an infinite amount of data,
+    /// streams are only allowed to buffer outgoing data up to the amount that
+    /// the peer allows to send.
+    ///
+    /// Note that the iterator will only include streams that were writable at
+    /// the time the iterator itself was created (i.e. when `writable()` was
+    /// called). To account for newly writable streams, the iterator needs to be
+    /// created again.
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let local = socket.local_addr().unwrap();
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// // Iterate over writable streams.
+    /// for stream_id in conn.writable() {
+    ///     // Stream is writable, write some data.
+    ///     if let Ok(written) = conn.stream_send(stream_id, &buf, false) {
+    ///         println!("Written {} bytes on stream {}", written, stream_id);
+    ///     }
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    /// [`stream_priority()`]: struct.Connection.html#method.stream_priority
+    #[inline]
+    pub fn writable(&self) -> StreamIter {
+        // If there is not enough connection-level send capacity, none of the
+        // streams are writable, so return an empty iterator.
+        if self.tx_cap == 0 {
+            return StreamIter::default();
+        }
+
+        self.streams.writable()
+    }
+
+    /// Returns the maximum possible size of egress UDP payloads.
+    ///
+    /// This is the maximum size of UDP payloads that can be sent, and depends
+    /// on both the configured maximum send payload size of the local endpoint
+    /// (as configured with [`set_max_send_udp_payload_size()`]), as well as
+    /// the transport parameter advertised by the remote peer.
+    ///
+    /// Note that this value can change during the lifetime of the connection,
+    /// but should remain stable across consecutive calls to [`send()`].
+    ///
+    /// [`set_max_send_udp_payload_size()`]:
+    ///     struct.Config.html#method.set_max_send_udp_payload_size
+    /// [`send()`]: struct.Connection.html#method.send
+    pub fn max_send_udp_payload_size(&self) -> usize {
+        let max_datagram_size = self
+            .paths
+            .get_active()
+            .ok()
+            .map(|p| p.recovery.max_datagram_size());
+
+        if let Some(max_datagram_size) = max_datagram_size {
+            if self.is_established() {
+                // We cap the maximum packet size to 16KB or so, so that it can be
+                // always encoded with a 2-byte varint.
+                return cmp::min(16383, max_datagram_size);
+            }
+        }
+
+        // Allow for 1200 bytes (minimum QUIC packet size) during the
+        // handshake.
+        MIN_CLIENT_INITIAL_LEN
+    }
+
+    /// Schedule an ack-eliciting packet on the active path.
+    ///
+    /// QUIC packets might not contain ack-eliciting frames during normal
+    /// operating conditions. If the packet would already contain
+    /// ack-eliciting frames, this method does not change any behavior.
+    /// However, if the packet would not ordinarily contain ack-eliciting
+    /// frames, this method ensures that a PING frame sent.
+    ///
+    /// Calling this method multiple times before [`send()`] has no effect.
+    ///
+    /// [`send()`]: struct.Connection.html#method.send
+    pub fn send_ack_eliciting(&mut self) -> Result<()> {
+        if self.is_closed() || self.is_draining() {
+            return Ok(());
+        }
+        self.paths.get_active_mut()?.needs_ack_eliciting = true;
+        Ok(())
+    }
+
+    /// Schedule an ack-eliciting packet on the specified path.
+    ///
+    /// See [`send_ack_eliciting()`] for more detail. [`InvalidState`] is
+    /// returned if there is no record of the path.
+    ///
+    /// [`send_ack_eliciting()`]: struct.Connection.html#method.send_ack_eliciting
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn send_ack_eliciting_on_path(
+        &mut self, local: SocketAddr, peer: SocketAddr,
+    ) -> Result<()> {
+        if self.is_closed() || self.is_draining() {
+            return Ok(());
+        }
+        let path_id = self
+            .paths
+            .path_id_from_addrs(&(local, peer))
+            .ok_or(Error::InvalidState)?;
+        self.paths.get_mut(path_id)?.needs_ack_eliciting = true;
+        Ok(())
+    }
+
+    /// Reads the first received DATAGRAM.
+    ///
+    /// On success the DATAGRAM's data is returned along with its size.
+    ///
+    /// [`Done`] is returned if there is no data to read.
+    ///
+    /// [`BufferTooShort`] is returned if the provided buffer is too small for
+    /// the DATAGRAM.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// let mut dgram_buf = [0; 512];
+    /// while let Ok((len)) = conn.dgram_recv(&mut dgram_buf) {
+    ///     println!("Got {} bytes of DATAGRAM", len);
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    #[inline]
+    pub fn dgram_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.dgram_recv_queue.pop() {
+            Some(d) => {
+                if d.len() > buf.len() {
+                    return Err(Error::BufferTooShort);
+                }
+
+                buf[..d.len()].copy_from_slice(&d);
+                Ok(d.len())
+            },
+
+            None => Err(Error::Done),
+        }
+    }
+
+    /// Reads the first received DATAGRAM.
+    ///
+    /// This is the same as [`dgram_recv()`] but returns the DATAGRAM as a
+    /// `Vec<u8>` instead of copying into the provided buffer.
+    ///
+    /// [`dgram_recv()`]: struct.Connection.html#method.dgram_recv
+    #[inline]
+    pub fn dgram_recv_vec(&mut self) -> Result<Vec<u8>> {
+        match self.dgram_recv_queue.pop() {
+            Some(d) => Ok(d),
+
+            None => Err(Error::Done),
+        }
+    }
+
+    /// Reads the first received DATAGRAM without removing it from the queue.
+    ///
+    /// On success the DATAGRAM's data is returned along with the actual number
+    /// of bytes peeked. The requested length cannot exceed the DATAGRAM's
+    /// actual length.
+    ///
+    /// [`Done`] is returned if there is no data to read.
+    ///
+    /// [`BufferTooShort`] is returned if the provided buffer is smaller the
+    /// number of bytes to peek.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
+    #[inline]
+    pub fn dgram_recv_peek(&self, buf: &mut [u8], len: usize) -> Result<usize> {
+        self.dgram_recv_queue.peek_front_bytes(buf, len)
+    }
+
+    /// Returns the length of the first stored DATAGRAM.
+    #[inline]
+    pub fn dgram_recv_front_len(&self) -> Option<usize> {
+        self.dgram_recv_queue.peek_front_len()
+    }
+
+    /// Returns the number of items in the DATAGRAM receive queue.
+    #[inline]
+    pub fn dgram_recv_queue_len(&self) -> usize {
+        self.dgram_recv_queue.len()
+    }
+
+    /// Returns the total size of all items in the DATAGRAM receive queue.
+    #[inline]
+    pub fn dgram_recv_queue_byte_size(&self) -> usize {
+        self.dgram_recv_queue.byte_size()
+    }
+
+    /// Returns the number of items in the DATAGRAM send queue.
+    #[inline]
+    pub fn dgram_send_queue_len(&self) -> usize {
+        self.dgram_send_queue.len()
+    }
+
+    /// Returns the total size of all items in the DATAGRAM send queue.
+    #[inline]
+    pub fn dgram_send_queue_byte_size(&self) -> usize {
+        self.dgram_send_queue.byte_size()
+    }
+
+    /// Returns whether or not the DATAGRAM send queue is full.
+    #[inline]
+    pub fn is_dgram_send_queue_full(&self) -> bool {
+        self.dgram_send_queue.is_full()
+    }
+
+    /// Returns whether or not the DATAGRAM recv queue is full.
+    #[inline]
+    pub fn is_dgram_recv_queue_full(&self) -> bool {
+        self.dgram_recv_queue.is_full()
+    }
+
+    /// Sends data in a DATAGRAM frame.
+    ///
+    /// [`Done`] is returned if no data was written.
+    /// [`InvalidState`] is returned if the peer does not support DATAGRAM.
+    /// [`BufferTooShort`] is returned if the DATAGRAM frame length is larger
+    /// than peer's supported DATAGRAM frame length. Use
+    /// [`dgram_max_writable_len()`] to get the largest supported DATAGRAM
+    /// frame length.
+    ///
+    /// Note that there is no flow control of DATAGRAM frames, so in order to
+    /// avoid buffering an infinite amount of frames we apply an internal
+    /// limit.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
+    /// [`dgram_max_writable_len()`]:
+    /// struct.Connection.html#method.dgram_max_writable_len
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// conn.dgram_send(b"hello")?;
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    pub fn dgram_send(&mut self, buf: &[u8]) -> Result<()> {
+        let max_payload_len = match self.dgram_max_writable_len() {
+            Some(v) => v,
+
+            None => return Err(Error::InvalidState),
+        };
+
+        if buf.len() > max_payload_len {
+            return Err(Error::BufferTooShort);
+        }
+
+        self.dgram_send_queue.push(buf.to_vec())?;
+
+        let active_path = self.paths.get_active_mut()?;
+
+        if self.dgram_send_queue.byte_size() >
+            active_path.recovery.cwnd_available()
+        {
+            active_path.recovery.update_app_limited(false);
+        }
+
+        Ok(())
+    }
+
+    /// Sends data in a DATAGRAM frame.
+    ///
+    /// This is the same as [`dgram_send()`] but takes a `Vec<u8>` instead of
+    /// a slice.
+    ///
+    /// [`dgram_send()`]: struct.Connection.html#method.dgram_send
+    pub fn dgram_send_vec(&mut self, buf: Vec<u8>) -> Result<()> {
+        let max_payload_len = match self.dgram_max_writable_len() {
+            Some(v) => v,
+
+            None => return Err(Error::InvalidState),
+        };
+
+        if buf.len() > max_payload_len {
+            return Err(Error::BufferTooShort);
+        }
+
+        self.dgram_send_queue.push(buf)?;
+
+        let active_path = self.paths.get_active_mut()?;
+
+        if self.dgram_send_queue.byte_size() >
+            active_path.recovery.cwnd_available()
+        {
+            active_path.recovery.update_app_limited(false);
+        }
+
+        Ok(())
+    }
+
+    /// Purges queued outgoing DATAGRAMs matching the predicate.
+    ///
+    /// In other words, remove all elements `e` such that `f(&e)` returns true.
+    ///
+    /// ## Examples:
+    /// ```no_run
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// conn.dgram_send(b"hello")?;
+    /// conn.dgram_purge_outgoing(&|d: &[u8]| -> bool { d[0] == 0 });
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    #[inline]
+    pub fn dgram_purge_outgoing<F: Fn(&[u8]) -> bool>(&mut self, f: F) {
+        self.dgram_send_queue.purge(f);
+    }
+
+    /// Returns the maximum DATAGRAM payload that can be sent.
+    ///
+    /// [`None`] is returned if the peer hasn't advertised a maximum DATAGRAM
+    /// frame size.
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut buf = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let local = socket.local_addr().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// if let Some(payload_size) = conn.dgram_max_writable_len() {
+    ///     if payload_size > 5 {
+    ///         conn.dgram_send(b"hello")?;
+    ///     }
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    #[inline]
+    pub fn dgram_max_writable_len(&self) -> Option<usize> {
+        match self.peer_transport_params.max_datagram_frame_size {
+            None => None,
+            Some(peer_frame_len) => {
+                let dcid = self.destination_id();
+                // Start from the maximum packet size...
+                let mut max_len = self.max_send_udp_payload_size();
+                // ...subtract the Short packet header overhead...
+                // (1 byte of pkt_len + len of dcid)
+                max_len = max_len.saturating_sub(1 + dcid.len());
+                // ...subtract the packet number (max len)...
+                max_len = max_len.saturating_sub(packet::MAX_PKT_NUM_LEN);
+                // ...subtract the crypto overhead...
+                max_len = max_len.saturating_sub(
+                    self.pkt_num_spaces[packet::Epoch::Application]
+                        .crypto_overhead()?,
+                );
+                // ...clamp to what peer can support...
+                max_len = cmp::min(peer_frame_len as usize, max_len);
+                // ...subtract frame overhead, checked for underflow.
+                // (1 byte of frame type + len of length )
+                max_len.checked_sub(1 + frame::MAX_DGRAM_OVERHEAD)
+            },
+        }
+    }
+
+    fn dgram_enabled(&self) -> bool {
+        self.local_transport_params
+            .max_datagram_frame_size
+            .is_some()
+    }
+
+    /// Returns when the next timeout event will occur.
+    ///
+    /// Once the timeout Instant has been reached, the [`on_timeout()`] method
+    /// should be called. A timeout of `None` means that the timer should be
+    /// disarmed.
+    ///
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    pub fn timeout_instant(&self) -> Option<time::Instant> {
+        if self.is_closed() {
+            return None;
+        }
+
+        if self.is_draining() {
+            // Draining timer takes precedence over all other timers. If it is
+            // set it means the connection is closing so there's no point in
+            // processing the other timers.
+            self.draining_timer
+        } else {
+            // Use the lowest timer value (i.e. "sooner") among idle and loss
+            // detection timers. If they are both unset (i.e. `None`) then the
+            // result is `None`, but if at least one of them is set then a
+            // `Some(...)` value is returned.
+            let path_timer = self
+                .paths
+                .iter()
+                .filter_map(|(_, p)| p.recovery.loss_detection_timer())
+                .min();
+
+            let key_update_timer = self.pkt_num_spaces
+                [packet::Epoch::Application]
+                .key_update
+                .as_ref()
+                .map(|key_update| key_update.timer);
+
+            let burst_timer = self
+                .paths
+                .iter()
+                .filter_map(|(_, p)| p.recovery.burst_timer())
+                .min();
+
+            let timers =
+                [self.idle_timer, path_timer, key_update_timer, burst_timer];
+
+            timers.iter().filter_map(|&x| x).min()
+        }
+    }
+
+    /// Returns the amount of time until the next timeout event.
+    ///
+    /// Once the given duration has elapsed, the [`on_timeout()`] method should
+    /// be called. A timeout of `None` means that the timer should be disarmed.
+    ///
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    pub fn timeout(&self) -> Option<time::Duration> {
+        self.timeout_instant().map(|timeout| {
+            let now = time::Instant::now();
+
+            if timeout <= now {
+                time::Duration::ZERO
+            } else {
+                timeout.duration_since(now)
+            }
+        })
+    }
+
+    /// Processes a timeout event.
+    ///
+    /// If no timeout has occurred it does nothing.
+    pub fn on_timeout(&mut self) {
+        let now = time::Instant::now();
+
+        if let Some(draining_timer) = self.draining_timer {
+            if draining_timer <= now {
+                trace!("{} draining timeout expired", self.trace_id);
+
+                self.mark_closed();
+            }
+
+            // Draining timer takes precedence over all other timers. If it is
+            // set it means the connection is closing so there's no point in
+            // processing the other timers.
+            return;
+        }
+
+        if let Some(timer) = self.idle_timer {
+            if timer <= now {
+                trace!("{} idle timeout expired", self.trace_id);
+
+                self.mark_closed();
+                self.timed_out = true;
+                return;
+            }
+        }
+
+        if let Some(timer) = self.pkt_num_spaces[packet::Epoch::Application]
+            .key_update
+            .as_ref()
+            .map(|key_update| key_update.timer)
+        {
+            if timer <= now {
+                // Discard previous key once key update timer expired.
+                let _ = self.pkt_num_spaces[packet::Epoch::Application]
+                    .key_update
+                    .take();
+            }
+        }
+
+        let handshake_status = self.handshake_status();
+
+        let mut update = None;
+        for (pid, p) in self.paths.iter_mut() {
+            p.recovery.release_burst(now);
+
+            if let Some(timer) = p.recovery.loss_detection_timer() {
+                if timer <= now {
+                    trace!("{} loss detection timeout expired", self.trace_id);
+
+                    let (lost_packets, lost_bytes) = p.on_loss_detection_timeout(
+                        handshake_status,
+                        now,
+                        self.is_server,
+                        &self.trace_id,
+                    );
+
+                    self.lost_count += lost_packets;
+                    self.lost_bytes += lost_bytes as u64;
+
+                    qlog_with_type!(QLOG_PACKET_LOST, self.qlog, q, {
+                        for ev_data in p.recovery.packet_loss_qlog() {
+                            self.qlog.stats.add_event_data(q, ev_data, now);
+                        }
+                    });
+                    qlog_with_type!(QLOG_METRICS, self.qlog, q, {
+                        let sampler = &mut self.qlog.metrics_sampler;
+
+                        if let Some(ev_data) =
+                            p.recovery.maybe_qlog(|| sampler.sample())
+                        {
+                            self.qlog.stats.add_event_data(q, ev_data, now);
+                        }
+                    });
+                    qlog_with_type!(QLOG_CR_PHASE, self.qlog, q, {
+                        if let Some(ev_data) = p.recovery.maybe_cr_qlog() {
+                            self.qlog.stats.add_event_data(q, ev_data, now);
+                        }
+                    });
+
+                    if p.active() {
+                        if let Some(cr_event) = p.recovery.maybe_cr_event(now) {
+                            update = Some((pid, cr_event));
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some((pid, cr_event)) = update {
+            self.update_cr_event(pid, cr_event);
+        }
+
+        self.invalidate_cr_params();
+
+        // Notify timeout events to the application.
+        self.paths.notify_failed_validations();
+
+        // If the active path failed, try to find a new candidate.
+        if self.paths.get_active_path_id().is_err() {
+            match self.paths.find_candidate_path() {
+                Some(pid) => {
+                    if self.set_active_path(pid, now).is_err() {
+                        // The connection cannot continue.
+                        self.mark_closed();
+                    }
+                },
+
+                // The connection cannot continue.
+                None => {
+                    self.mark_closed();
+                },
+            }
+        }
+    }
+
+    /// Requests the stack to perform path validation of the proposed 4-tuple.
+    ///
+    /// Probing new paths requires spare Connection IDs at both the host and the
+    /// peer sides. If it is not the case, it raises an [`OutOfIdentifiers`].
+    ///
+    /// The probing of new addresses can only be done by the client. The server
+    /// can only probe network paths that were previously advertised by
+    /// [`PathEvent::New`]. If the server tries to probe such an unseen network
+    /// path, this call raises an [`InvalidState`].
+    ///
+    /// The caller might also want to probe an existing path. In such case, it
+    /// triggers a PATH_CHALLENGE frame, but it does not require spare CIDs.
+    ///
+    /// A server always probes a new path it observes. Calling this method is
+    /// hence not required to validate a new path. However, a server can still
+    /// request an additional path validation of the proposed 4-tuple.
+    ///
+    /// Calling this method several times before calling [`send()`] or
+    /// [`send_on_path()`] results in a single probe being generated. An
+    /// application wanting to send multiple in-flight probes must call this
+    /// method again after having sent packets.
+    ///
+    /// Returns the Destination Connection ID sequence number associated to that
+    /// path.
+    ///
+    /// [`PathEvent::New`]: enum.PathEvent.html#variant.New
+    /// [`OutOfIdentifiers`]: enum.Error.html#OutOfIdentifiers
+    /// [`InvalidState`]: enum.Error.html#InvalidState
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`send_on_path()`]: struct.Connection.html#method.send_on_path
+    pub fn probe_path(
+        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
+    ) -> Result<u64> {
+        // We may want to probe an existing path.
+        let pid = match self.paths.path_id_from_addrs(&(local_addr, peer_addr)) {
+            Some(pid) => pid,
+            None => self.create_path_on_client(local_addr, peer_addr)?,
+        };
+
+        let path = self.paths.get_mut(pid)?;
+        path.request_validation();
+
+        path.active_dcid_seq.ok_or(Error::InvalidState)
+    }
+
+    /// Migrates the connection to a new local address `local_addr`.
+    ///
+    /// The behavior is similar to [`migrate()`], with the nuance that the
+    /// connection only changes the local address, but not the peer one.
+    ///
+    /// See [`migrate()`] for the full specification of this method.
+    ///
+    /// [`migrate()`]: struct.Connection.html#method.migrate
+    pub fn migrate_source(&mut self, local_addr: SocketAddr) -> Result<u64> {
+        let peer_addr = self.paths.get_active()?.peer_addr();
+        self.migrate(local_addr, peer_addr)
+    }
+
+    /// Migrates the connection over the given network path between `local_addr`
+    /// and `peer_addr`.
+    ///
+    /// Connection migration can only be initiated by the client. Calling this
+    /// method as a server returns [`InvalidState`].
+    ///
+    /// To initiate voluntary migration, there should be enough Connection IDs
+    /// at both sides. If this requirement is not satisfied, this call returns
+    /// [`OutOfIdentifiers`].
+    ///
+    /// Returns the Destination Connection ID associated to that migrated path.
+    ///
+    /// [`OutOfIdentifiers`]: enum.Error.html#OutOfIdentifiers
+    /// [`InvalidState`]: enum.Error.html#InvalidState
+    pub fn migrate(
+        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
+    ) -> Result<u64> {
+        if self.is_server {
+            return Err(Error::InvalidState);
+        }
+
+        // If the path already exists, mark it as the active one.
+        let (pid, dcid_seq) = if let Some(pid) =
+            self.paths.path_id_from_addrs(&(local_addr, peer_addr))
+        {
+            let path = self.paths.get_mut(pid)?;
+
+            // If it is already active, do nothing.
+            if path.active() {
+                return path.active_dcid_seq.ok_or(Error::OutOfIdentifiers);
+            }
+
+            // Ensures that a Source Connection ID has been dedicated to this
+            // path, or a free one is available. This is only required if the
+            // host uses non-zero length Source Connection IDs.
+            if !self.ids.zero_length_scid() &&
+                path.active_scid_seq.is_none() &&
+                self.ids.available_scids() == 0
+            {
+                return Err(Error::OutOfIdentifiers);
+            }
+
+            // Ensures that the migrated path has a Destination Connection ID.
+            let dcid_seq = if let Some(dcid_seq) = path.active_dcid_seq {
+                dcid_seq
+            } else {
+                let dcid_seq = self
+                    .ids
+                    .lowest_available_dcid_seq()
+                    .ok_or(Error::OutOfIdentifiers)?;
+
+                self.ids.link_dcid_to_path_id(dcid_seq, pid)?;
+                path.active_dcid_seq = Some(dcid_seq);
+
+                dcid_seq
+            };
+
+            (pid, dcid_seq)
+        } else {
+            let pid = self.create_path_on_client(local_addr, peer_addr)?;
+
+            let dcid_seq = self
+                .paths
+                .get(pid)?
+                .active_dcid_seq
+                .ok_or(Error::InvalidState)?;
+
+            (pid, dcid_seq)
+        };
+
+        // Change the active path.
+        self.set_active_path(pid, time::Instant::now())?;
+
+        Ok(dcid_seq)
+    }
+
+    /// Provides additional source Connection IDs that the peer can use to reach
+    /// this host.
+    ///
+    /// This triggers sending NEW_CONNECTION_ID frames if the provided Source
+    /// Connection ID is not already present. In the case the caller tries to
+    /// reuse a Connection ID with a different reset token, this raises an
+    /// `InvalidState`.
+    ///
+    /// At any time, the peer cannot have more Destination Connection IDs than
+    /// the maximum number of active Connection IDs it negotiated. In such case
+    /// (i.e., when [`scids_left()`] returns 0), if the host agrees to
+    /// request the removal of previous connection IDs, it sets the
+    /// `retire_if_needed` parameter. Otherwise, an [`IdLimit`] is returned.
+    ///
+    /// Note that setting `retire_if_needed` does not prevent this function from
+    /// returning an [`IdLimit`] in the case the caller wants to retire still
+    /// unannounced Connection IDs.
+    ///
+    /// The caller is responsible from ensuring that the provided `scid` is not
+    /// repeated several times over the connection. quiche ensures that as long
+    /// as the provided Connection ID is still in use (i.e., not retired), it
+    /// does not assign a different sequence number.
+    ///
+    /// Note that if the host uses zero-length Source Connection IDs, it cannot
+    /// advertise Source Connection IDs and calling this method returns an
+    /// [`InvalidState`].
+    ///
+    /// Returns the sequence number associated to the provided Connection ID.
+    ///
+    /// [`scids_left()`]: struct.Connection.html#method.scids_left
+    /// [`IdLimit`]: enum.Error.html#IdLimit
+    /// [`InvalidState`]: enum.Error.html#InvalidState
+    pub fn new_scid(
+        &mut self, scid: &ConnectionId, reset_token: u128, retire_if_needed: bool,
+    ) -> Result<u64> {
+        self.ids.new_scid(
+            scid.to_vec().into(),
+            Some(reset_token),
+            true,
+            None,
+            retire_if_needed,
+        )
+    }
+
+    /// Returns the number of source Connection IDs that are active. This is
+    /// only meaningful if the host uses non-zero length Source Connection IDs.
+    pub fn active_scids(&self) -> usize {
+        self.ids.active_source_cids()
+    }
+
+    /// Returns the number of source Connection IDs that should be provided
+    /// to the peer without exceeding the limit it advertised.
+    ///
+    /// This will automatically limit the number of Connection IDs to the
+    /// minimum between the locally configured active connection ID limit,
+    /// and the one sent by the peer.
+    ///
+    /// To obtain the maximum possible value allowed by the peer an application
+    /// can instead inspect the [`peer_active_conn_id_limit`] value.
+    ///
+    /// [`peer_active_conn_id_limit`]: struct.Stats.html#structfield.peer_active_conn_id_limit
+    #[inline]
+    pub fn scids_left(&self) -> usize {
+        let max_active_source_cids = cmp::min(
+            self.peer_transport_params.active_conn_id_limit,
+            self.local_transport_params.active_conn_id_limit,
+        ) as usize;
+
+        max_active_source_cids - self.active_scids()
+    }
+
+    /// Requests the retirement of the destination Connection ID used by the
+    /// host to reach its peer.
+    ///
+    /// This triggers sending RETIRE_CONNECTION_ID frames.
+    ///
+    /// If the application tries to retire a non-existing Destination Connection
+    /// ID sequence number, or if it uses zero-length Destination Connection ID,
+    /// this method returns an [`InvalidState`].
+    ///
+    /// At any time, the host must have at least one Destination ID. If the
+    /// application tries to retire the last one, or if the caller tries to
+    /// retire the destination Connection ID used by the current active path
+    /// while having neither spare Destination Connection IDs nor validated
+    /// network paths, this method returns an [`OutOfIdentifiers`]. This
+    /// behavior prevents the caller from stalling the connection due to the
+    /// lack of validated path to send non-probing packets.
+    ///
+    /// [`InvalidState`]: enum.Error.html#InvalidState
+    /// [`OutOfIdentifiers`]: enum.Error.html#OutOfIdentifiers
+    pub fn retire_dcid(&mut self, dcid_seq: u64) -> Result<()> {
+        if self.ids.zero_length_dcid() {
+            return Err(Error::InvalidState);
+        }
+
+        let active_path_dcid_seq = self
+            .paths
+            .get_active()?
+            .active_dcid_seq
+            .ok_or(Error::InvalidState)?;
+
+        let active_path_id = self.paths.get_active_path_id()?;
+
+        if active_path_dcid_seq == dcid_seq &&
+            self.ids.lowest_available_dcid_seq().is_none() &&
+            !self
+                .paths
+                .iter()
+                .any(|(pid, p)| pid != active_path_id && p.usable())
+        {
+            return Err(Error::OutOfIdentifiers);
+        }
+
+        if let Some(pid) = self.ids.retire_dcid(dcid_seq)? {
+            // The retired Destination CID was associated to a given path. Let's
+            // find an available DCID to associate to that path.
+            let path = self.paths.get_mut(pid)?;
+            let dcid_seq = self.ids.lowest_available_dcid_seq();
+
+            if let Some(dcid_seq) = dcid_seq {
+                self.ids.link_dcid_to_path_id(dcid_seq, pid)?;
+            }
+
+            path.active_dcid_seq = dcid_seq;
+        }
+
+        Ok(())
+    }
+
+    /// Processes path-specific events.
+    ///
+    /// On success it returns a [`PathEvent`], or `None` when there are no
+    /// events to report. Please refer to [`PathEvent`] for the exhaustive event
+    /// list.
+    ///
+    /// Note that all events are edge-triggered, meaning that once reported they
+    /// will not be reported again by calling this method again, until the event
+    /// is re-armed.
+    ///
+    /// [`PathEvent`]: enum.PathEvent.html
+    pub fn path_event_next(&mut self) -> Option<PathEvent> {
+        self.paths.pop_event()
+    }
+
+    /// Returns the number of source Connection IDs that are retired.
+    pub fn retired_scids(&self) -> usize {
+        self.ids.retired_source_cids()
+    }
+
+    /// Returns a source `ConnectionId` that has been retired.
+    ///
+    /// On success it returns a [`ConnectionId`], or `None` when there are no
+    /// more retired connection IDs.
+    ///
+    /// [`ConnectionId`]: struct.ConnectionId.html
+    pub fn retired_scid_next(&mut self) -> Option<ConnectionId<'static>> {
+        self.ids.pop_retired_scid()
+    }
+
+    /// Returns the number of spare Destination Connection IDs, i.e.,
+    /// Destination Connection IDs that are still unused.
+    ///
+    /// Note that this function returns 0 if the host uses zero length
+    /// Destination Connection IDs.
+    pub fn available_dcids(&self) -> usize {
+        self.ids.available_dcids()
+    }
+
+    /// Returns an iterator over destination `SockAddr`s whose association
+    /// with `from` forms a known QUIC path on which packets can be sent to.
+    ///
+    /// This function is typically used in combination with [`send_on_path()`].
+    ///
+    /// Note that the iterator includes all the possible combination of
+    /// destination `SockAddr`s, even those whose sending is not required now.
+    /// In other words, this is another way for the application to recall from
+    /// past [`PathEvent::New`] events.
+    ///
+    /// [`PathEvent::New`]: enum.PathEvent.html#variant.New
+    /// [`send_on_path()`]: struct.Connection.html#method.send_on_path
+    ///
+    /// ## Examples:
+    ///
+    /// ```no_run
+    /// # let mut out = [0; 512];
+    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
+    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
+    /// # let local = socket.local_addr().unwrap();
+    /// # let peer = "127.0.0.1:1234".parse().unwrap();
+    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
+    /// // Iterate over possible destinations for the given local `SockAddr`.
+    /// for dest in conn.paths_iter(local) {
+    ///     loop {
+    ///         let (write, send_info) =
+    ///             match conn.send_on_path(&mut out, Some(local), Some(dest)) {
+    ///                 Ok(v) => v,
+    ///
+    ///                 Err(quiche::Error::Done) => {
+    ///                     // Done writing for this destination.
+    ///                     break;
+    ///                 },
+    ///
+    ///                 Err(e) => {
+    ///                     // An error occurred, handle it.
+    ///                     break;
+    ///                 },
+    ///             };
+    ///
+    ///         socket.send_to(&out[..write], &send_info.to).unwrap();
+    ///     }
+    /// }
+    /// # Ok::<(), quiche::Error>(())
+    /// ```
+    #[inline]
+    pub fn paths_iter(&self, from: SocketAddr) -> SocketAddrIter {
+        // Instead of trying to identify whether packets will be sent on the
+        // given 4-tuple, simply filter paths that cannot be used.
+        SocketAddrIter {
+            sockaddrs: self
+                .paths
+                .iter()
+                .filter(|(_, p)| p.active_dcid_seq.is_some())
+                .filter(|(_, p)| p.usable() || p.probing_required())
+                .filter(|(_, p)| p.local_addr() == from)
+                .map(|(_, p)| p.peer_addr())
+                .collect(),
+
+            index: 0,
+        }
+    }
+
+    /// Closes the connection with the given error and reason.
+    ///
+    /// The `app` parameter specifies whether an application close should be
+    /// sent to the peer. Otherwise a normal connection close is sent.
+    ///
+    /// If `app` is true but the connection is not in a state that is safe to
+    /// send an application error (not established nor in early data), in
+    /// accordance with [RFC
+    /// 9000](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.2.3-3), the
+    /// error code is changed to APPLICATION_ERROR and the reason phrase is
+    /// cleared.
+    ///
+    /// Returns [`Done`] if the connection had already been closed.
+    ///
+    /// Note that the connection will not be closed immediately. An application
+    /// should continue calling the [`recv()`], [`send()`], [`timeout()`] and
+    /// [`on_timeout()`] methods as normal, until the [`is_closed()`] method
+    /// returns `true`.
+    ///
+    /// The Careful Resume parameters last observed on the active path are
+    /// reported by [`cr_events()`] as the connection starts closing, whether
+    /// it is closed by this method, by the peer or by the idle timeout, even
+    /// when they didn't change enough since the previous observation to be
+    /// reported otherwise.
+    ///
+    /// [`Done`]: enum.Error.html#variant.Done
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`timeout()`]: struct.Connection.html#method.timeout
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    /// [`is_closed()`]: struct.Connection.html#method.is_closed
+    /// [`cr_events()`]: struct.Connection.html#method.cr_events
+    pub fn close(&mut self, app: bool, err: u64, reason: &[u8]) -> Result<()> {
+        if self.is_closed() || self.is_draining() {
+            return Err(Error::Done);
+        }
+
+        if self.local_error.is_some() {
+            return Err(Error::Done);
+        }
+
+        self.final_cr_event(time::Instant::now());
+
+        let is_safe_to_send_app_data =
+            self.is_established() || self.is_in_early_data();
+
+        if app && !is_safe_to_send_app_data {
+            // Clear error information.
+            self.local_error = Some(ConnectionError {
+                is_app: false,
+                error_code: 0x0c,
+                reason: vec![],
+            });
+        } else {
+            self.local_error = Some(ConnectionError {
+                is_app: app,
+                error_code: err,
+                reason: reason.to_vec(),
+            });
+
+            if !app && err != 0 {
+                self.keep_close_decisions();
+            }
+        }
+
+        // When no packet was successfully processed close connection immediately.
+        if self.recv_count == 0 {
+            self.mark_closed();
+        }
+
+        Ok(())
+    }
+
+    /// Closes the connection like [`close()`], making sure that the data
+    /// collected about it is not lost if the process exits afterwards.
+    ///
+    /// The Careful Resume parameters last observed on the active path are
+    /// recorded, see [`cr_events()`], even when the connection was already
+    /// closing. The qlog writer, if any, is then flushed.
+    ///
+    /// As with [`close()`], the application should keep driving the
+    /// connection until the [`is_closed()`] method returns `true`, i.e. until
+    /// the draining period is over, before exiting. The events logged after
+    /// this call are only guaranteed to be written once the connection is
+    /// dropped.
+    ///
+    /// [`close()`]: struct.Connection.html#method.close
+    /// [`cr_events()`]: struct.Connection.html#method.cr_events
+    /// [`is_closed()`]: struct.Connection.html#method.is_closed
+    pub fn close_and_flush(
+        &mut self, app: bool, err: u64, reason: &[u8],
+    ) -> Result<()> {
+        self.final_cr_event(time::Instant::now());
+
+        let res = self.close(app, err, reason);
+
+        #[cfg(feature = "qlog")]
+        if let Some(q) = self.qlog.streamer.as_mut() {
+            if let Err(e) = q.flush() {
+                trace!("{} failed to flush qlog: {:?}", self.trace_id, e);
+            }
+        }
+
+        res
+    }
+
+    /// Returns a string uniquely representing the connection.
+    ///
+    /// This can be used for logging purposes to differentiate between multiple
+    /// connections.
+    #[inline]
+    pub fn trace_id(&self) -> &str {
+        &self.trace_id
+    }
+
+    /// Returns the negotiated ALPN protocol.
+    ///
+    /// If no protocol has been negotiated, the returned value is empty.
+    #[inline]
+    pub fn application_proto(&self) -> &[u8] {
+        self.alpn.as_ref()
+    }
+
+    /// Returns the server name requested by the client.
+    #[inline]
+    pub fn server_name(&self) -> Option<&str> {
+        self.handshake.server_name()
+    }
+
+    /// Returns the peer's leaf certificate (if any) as a DER-encoded buffer.
+    #[inline]
+    pub fn peer_cert(&self) -> Option<&[u8]> {
+        self.handshake.peer_cert()
+    }
+
+    /// Returns the peer's certificate chain (if any) as a vector of DER-encoded
+    /// buffers.
+    ///
+    /// The certificate at index 0 is the peer's leaf certificate, the other
+    /// certificates (if any) are the chain certificate authorities used to
+    /// sign the leaf certificate.
+    #[inline]
+    pub fn peer_cert_chain(&self) -> Option<Vec<&[u8]>> {
+        self.handshake.peer_cert_chain()
+    }
+
+    /// Returns the serialized cryptographic session for the connection.
+    ///
+    /// This can be used by a client to cache a connection's session, and resume
+    /// it later using the [`set_session()`] method.
+    ///
+    /// [`set_session()`]: struct.Connection.html#method.set_session
+    #[inline]
+    pub fn session(&self) -> Option<&[u8]> {
+        self.session.as_deref()
+    }
+
+    /// Returns the source connection ID.
+    ///
+    /// When there are multiple IDs, and if there is an active path, the ID used
+    /// on that path is returned. Otherwise the oldest ID is returned.
+    ///
+    /// Note that the value returned can change throughout the connection's
+    /// lifetime.
+    #[inline]
+    pub fn source_id(&self) -> ConnectionId {
+        if let Ok(path) = self.paths.get_active() {
+            if let Some(active_scid_seq) = path.active_scid_seq {
+                if let Ok(e) = self.ids.get_scid(active_scid_seq) {
+                    return ConnectionId::from_ref(e.cid.as_ref());
+                }
+            }
+        }
+
+        let e = self.ids.oldest_scid();
+        ConnectionId::from_ref(e.cid.as_ref())
+    }
+
+    /// Returns all active source connection IDs.
+    ///
+    /// An iterator is returned for all active IDs (i.e. ones that have not
+    /// been explicitly retired yet).
+    #[inline]
+    pub fn source_ids(&self) -> impl Iterator<Item = &ConnectionId> {
+        self.ids.scids_iter()
+    }
+
+    /// Returns the destination connection ID.
+    ///
+    /// Note that the value returned can change throughout the connection's
+    /// lifetime.
+    #[inline]
+    pub fn destination_id(&self) -> ConnectionId {
+        if let Ok(path) = self.paths.get_active() {
+            if let Some(active_dcid_seq) = path.active_dcid_seq {
+                if let Ok(e) = self.ids.get_dcid(active_dcid_seq) {
+                    return ConnectionId::from_ref(e.cid.as_ref());
+                }
+            }
+        }
+
+        let e = self.ids.oldest_dcid();
+        ConnectionId::from_ref(e.cid.as_ref())
+    }
+
+    /// Returns true if the connection handshake is complete.
+    #[inline]
+    pub fn is_established(&self) -> bool {
+        self.handshake_completed
+    }
+
+    /// Returns true if the connection is resumed.
+    #[inline]
+    pub fn is_resumed(&self) -> bool {
+        self.handshake.is_resumed()
+    }
+
+    /// Returns true if the connection has a pending handshake that has
+    /// progressed enough to send or receive early data.
+    #[inline]
+    pub fn is_in_early_data(&self) -> bool {
+        self.handshake.is_in_early_data()
+    }
+
+    /// Returns whether there is stream or DATAGRAM data available to read.
+    #[inline]
+    pub fn is_readable(&self) -> bool {
+        self.streams.has_readable() || self.dgram_recv_front_len().is_some()
+    }
+
+    /// Returns whether the network path with local address `from` and remote
+    /// address `peer` has been validated.
+    ///
+    /// If the 4-tuple does not exist over the connection, returns an
+    /// [`InvalidState`].
+    ///
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn is_path_validated(
+        &self, from: SocketAddr, to: SocketAddr,
+    ) -> Result<bool> {
+        let pid = self
+            .paths
+            .path_id_from_addrs(&(from, to))
+            .ok_or(Error::InvalidState)?;
+
+        Ok(self.paths.get(pid)?.validated())
+    }
+
+    /// Returns true if the connection is draining.
+    ///
+    /// If this returns `true`, the connection object cannot yet be dropped, but
+    /// no new application data can be sent or received. An application should
+    /// continue calling the [`recv()`], [`timeout()`], and [`on_timeout()`]
+    /// methods as normal, until the [`is_closed()`] method returns `true`.
+    ///
+    /// In contrast, once `is_draining()` returns `true`, calling [`send()`]
+    /// is not required because no new outgoing packets will be generated.
+    ///
+    /// [`recv()`]: struct.Connection.html#method.recv
+    /// [`send()`]: struct.Connection.html#method.send
+    /// [`timeout()`]: struct.Connection.html#method.timeout
+    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
+    /// [`is_closed()`]: struct.Connection.html#method.is_closed
+    #[inline]
+    pub fn is_draining(&self) -> bool {
+        self.draining_timer.is_some()
+    }
+
+    /// Returns true if the connection is closed.
+    ///
+    /// If this returns true, the connection object can be dropped.
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Returns true if the connection was closed due to the idle timeout.
+    #[inline]
+    pub fn is_timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// Returns the error received from the peer, if any.
+    ///
+    /// Note that a `Some` return value does not necessarily imply
+    /// [`is_closed()`] or any other connection state.
+    ///
+    /// [`is_closed()`]: struct.Connection.html#method.is_closed
+    #[inline]
+    pub fn peer_error(&self) -> Option<&ConnectionError> {
+        self.peer_error.as_ref()
+    }
+
+    /// Returns the error [`close()`] was called with, or internally
+    /// created quiche errors, if any.
+    ///
+    /// Note that a `Some` return value does not necessarily imply
+    /// [`is_closed()`] or any other connection state.
+    /// `Some` also does not guarantee that the error has been sent to
+    /// or received by the peer.
+    ///
+    /// [`close()`]: struct.Connection.html#method.close
+    /// [`is_closed()`]: struct.Connection.html#method.is_closed
+    #[inline]
+    pub fn local_error(&self) -> Option<&ConnectionError> {
+        self.local_error.as_ref()
+    }
+
+    /// Returns the last recovery and Careful Resume decisions taken on the
+    /// active path, if the connection was closed with a transport error by
+    /// either endpoint.
+    ///
+    /// The decisions give context to reports about connections that failed,
+    /// e.g. during a Careful Resume jump. They can be serialized with
+    /// [`to_bytes()`] to be attached to such reports.
+    ///
+    /// [`to_bytes()`]: struct.RecoveryDecisionLog.html#method.to_bytes
+    #[inline]
+    pub fn close_decisions(&self) -> Option<&RecoveryDecisionLog> {
+        self.close_decisions.as_ref()
+    }
+
+    /// Renders the last recovery and Careful Resume decisions as a Graphviz
+    /// DOT graph, for a quick look at the Careful Resume history of the
+    /// connection without a qlog viewer.
+    ///
+    /// Once the connection was closed with a transport error, these are the
+    /// decisions returned by [`close_decisions()`], and otherwise the ones
+    /// taken on the active path. `None` is returned when there is no active
+    /// path.
+    ///
+    /// [`close_decisions()`]: struct.Connection.html#method.close_decisions
+    #[cfg(feature = "cr-dot")]
+    pub fn cr_history_dot(&self) -> Option<String> {
+        if let Some(decisions) = &self.close_decisions {
+            return Some(decisions.to_dot());
+        }
+
+        let path = self.paths.get_active().ok()?;
+
+        Some(path.recovery.decision_log().to_dot())
+    }
+
+    fn keep_close_decisions(&mut self) {
+        if let Ok(path) = self.paths.get_active() {
+            let decisions = path.recovery.decision_log().clone();
+
+            debug!(
+                "{} closing with recovery decisions {:?}",
+                self.trace_id, decisions
+            );
+
+            self.close_decisions = Some(decisions);
+        }
+    }
+
+    /// Collects and returns statistics about the connection.
+    #[inline]
+    pub fn stats(&self) -> Stats {
+        #[cfg(feature = "qlog")]
+        let qlog_stats = self.qlog.stats;
+
+        #[cfg(not(feature = "qlog"))]
+        let qlog_stats = QlogStats::default();
+
+        let mut cr_counters = CrCounters::default();
+
+        for (_, p) in self.paths.iter() {
+            cr_counters.merge(&p.recovery.cr_counters());
+        }
+
+        let (app_limited_time, cwnd_limited_time) = self.paths.iter().fold(
+            (time::Duration::ZERO, time::Duration::ZERO),
+            |(app, cwnd), (_, p)| {
+                (
+                    app + p.recovery.app_limited_time(),
+                    cwnd + p.recovery.cwnd_limited_time(),
+                )
+            },
+        );
+
+        Stats {
+            recv: self.recv_count,
+            sent: self.sent_count,
+            lost: self.lost_count,
+            retrans: self.retrans_count,
+            sent_bytes: self.sent_bytes,
+            recv_bytes: self.recv_bytes,
+            acked_bytes: self.acked_bytes,
+            lost_bytes: self.lost_bytes,
+            stream_retrans_bytes: self.stream_retrans_bytes,
+            paths_count: self.paths.len(),
+            reset_stream_count_local: self.reset_stream_local_count,
+            stopped_stream_count_local: self.stopped_stream_local_count,
+            reset_stream_count_remote: self.reset_stream_remote_count,
+            stopped_stream_count_remote: self.stopped_stream_remote_count,
+            path_challenge_rx_count: self.path_challenge_rx_count,
+            qlog_metrics_updated_count: qlog_stats.metrics_updated,
+            qlog_cr_phase_updated_count: qlog_stats.cr_phase_updated,
+            qlog_packet_lost_count: qlog_stats.packet_lost,
+            qlog_recovery_time: qlog_stats.elapsed,
+            crypto_sealed_bytes: self.crypto_sealed_bytes,
+            crypto_opened_bytes: self.crypto_opened_bytes,
+            crypto_time: self.crypto_time,
+            app_limited_time,
+            cwnd_limited_time,
+            cr_counters,
+        }
+    }
+
+    /// Returns reference to peer's transport parameters. Returns `None` if we
+    /// have not yet processed the peer's transport parameters.
+    pub fn peer_transport_params(&self) -> Option<&TransportParams> {
+        if !self.parsed_peer_transport_params {
+            return None;
+        }
+
+        Some(&self.peer_transport_params)
+    }
+
+    /// Collects and returns statistics about each known path for the
+    /// connection.
+    pub fn path_stats(&self) -> impl Iterator<Item = PathStats> + '_ {
+        self.paths.iter().map(|(_, p)| p.stats())
+    }
+
+    /// Returns whether or not this is a server-side connection.
+    pub fn is_server(&self) -> bool {
+        self.is_server
+    }
+
+    /// Switches the connection to the first of the server's available
+    /// versions that the client also supports, if it isn't the version of
+    /// the client's first Initial packet (RFC 9368).
+    ///
+    /// Supported versions are all compatible with each other. The switch is
+    /// only made when the whole ClientHello is carried in `client_hello`,
+    /// since the rest of the client's first flight would be sent in the old
+    /// version and discarded.
+    fn maybe_switch_version(
+        &mut self, client_hello: &[u8], dcid: &[u8],
+    ) -> Result<()> {
+        let info = match client_hello_transport_params(client_hello)
+            .and_then(|raw| TransportParams::decode(raw, self.is_server).ok())
+            .and_then(|params| params.version_information)
+        {
+            Some(v) => v,
+
+            // Invalid parameters are rejected once the handshake processes
+            // them.
+            None => return Ok(()),
+        };
+
+        if info.chosen_version != self.version {
+            return Ok(());
+        }
+
+        let version = match self.available_versions.iter().find(|v| {
+            version_is_supported(**v) && info.available_versions.contains(v)
+        }) {
+            Some(&v) if v != self.version => v,
+
+            _ => return Ok(()),
+        };
+
+        trace!(
+            "{} switched to compatible version {:x} -> {:x}",
+            self.trace_id,
+            self.version,
+            version
+        );
+
+        let (aead_open, aead_seal) =
+            crypto::derive_initial_key_material(dcid, version, self.is_server)?;
+
+        self.original_version = Some(self.version);
+        self.version = version;
+
+        self.pkt_num_spaces[packet::Epoch::Initial].crypto_open = Some(aead_open);
+        self.pkt_num_spaces[packet::Epoch::Initial].crypto_seal = Some(aead_seal);
+
+        // The chosen version is part of the transport parameters.
+        self.encode_transport_params()
+    }
+
+    fn encode_transport_params(&mut self) -> Result<()> {
+        let mut available_versions = self.available_versions.clone();
+
+        if version_is_supported(self.version) &&
+            !available_versions.contains(&self.version)
+        {
+            available_versions.insert(0, self.version);
+        }
+
+        self.local_transport_params.version_information =
+            Some(VersionInformation {
+                chosen_version: self.version,
+                available_versions,
+            });
+
+        let mut raw_params = [0; 256];
+
+        let raw_params = TransportParams::encode(
+            &self.local_transport_params,
+            self.is_server,
+            &mut raw_params,
+        )?;
+
+        self.handshake.set_quic_transport_params(raw_params)?;
+
+        Ok(())
+    }
+
+    fn parse_peer_transport_params(
+        &mut self, peer_params: TransportParams,
+    ) -> Result<()> {
+        // Validate initial_source_connection_id.
+        match &peer_params.initial_source_connection_id {
+            Some(v) if v != &self.destination_id() =>
+                return Err(Error::InvalidTransportParam),
+
+            Some(_) => (),
+
+            // initial_source_connection_id must be sent by
+            // both endpoints.
+            None => return Err(Error::InvalidTransportParam),
+        }
+
+        // Validate original_destination_connection_id.
+        if let Some(odcid) = &self.odcid {
+            match &peer_params.original_destination_connection_id {
+                Some(v) if v != odcid =>
+                    return Err(Error::InvalidTransportParam),
+
+                Some(_) => (),
+
+                // original_destination_connection_id must be
+                // sent by the server.
+                None if !self.is_server =>
+                    return Err(Error::InvalidTransportParam),
+
+                None => (),
+            }
+        }
+
+        // Validate retry_source_connection_id.
+        if let Some(rscid) = &self.rscid {
+            match &peer_params.retry_source_connection_id {
+                Some(v) if v != rscid =>
+                    return Err(Error::InvalidTransportParam),
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    ack_eliciting = true;
-                    in_flight = true;
-                } else {
-                    // If there are other pending PATH_RESPONSE, don't lose them
-                    // now.
-                    break;
-                }
+                Some(_) => (),
+
+                // retry_source_connection_id must be sent by
+                // the server.
+                None => return Err(Error::InvalidTransportParam),
             }
+        }
 
-            // Create PATH_CHALLENGE frame if needed.
-            if path.validation_requested() {
-                // TODO: ensure that data is unique over paths.
-                let data = rand::rand_u64().to_be_bytes();
+        // Validate version_information, to detect version downgrades.
+        if let Some(info) = &peer_params.version_information {
+            // The client chose the version of its first Initial packet, even
+            // if the server then switched to a compatible version.
+            let chosen_version = self.original_version.unwrap_or(self.version);
 
-                let frame = frame::Frame::PathChallenge { data };
+            if info.chosen_version != chosen_version {
+                return Err(Error::UnknownVersion);
+            }
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    // Let's notify the path once we know the packet size.
-                    challenge_data = Some(data);
+            // After incompatible version negotiation, the client must have
+            // ended up with the version it would have picked from the
+            // versions the server actually supports.
+            if !self.is_server && self.did_version_negotiation {
+                let preferred = self
+                    .available_versions
+                    .iter()
+                    .find(|v| info.available_versions.contains(v));
 
-                    ack_eliciting = true;
-                    in_flight = true;
+                if preferred != Some(&self.version) {
+                    return Err(Error::UnknownVersion);
                 }
             }
-
-            if let Some(key_update) = pkt_space.key_update.as_mut() {
-                key_update.update_acked = true;
-            }
         }
 
-        let path = self.paths.get_mut(send_pid)?;
+        self.process_peer_transport_params(peer_params)?;
 
-        if pkt_type == packet::Type::Short && !is_closing {
-            // Create NEW_CONNECTION_ID frames as needed.
-            while let Some(seq_num) = self.ids.next_advertise_new_scid_seq() {
-                let frame = self.ids.get_new_connection_id_frame_for(seq_num)?;
+        self.parsed_peer_transport_params = true;
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.ids.mark_advertise_new_scid_seq(seq_num, false);
+        Ok(())
+    }
 
-                    ack_eliciting = true;
-                    in_flight = true;
-                } else {
-                    break;
-                }
-            }
+    fn process_peer_transport_params(
+        &mut self, peer_params: TransportParams,
+    ) -> Result<()> {
+        self.max_tx_data = peer_params.initial_max_data;
+
+        // Update send capacity.
+        self.update_tx_cap();
+
+        self.streams
+            .update_peer_max_streams_bidi(peer_params.initial_max_streams_bidi);
+        self.streams
+            .update_peer_max_streams_uni(peer_params.initial_max_streams_uni);
+
+        let max_ack_delay =
+            time::Duration::from_millis(peer_params.max_ack_delay);
+
+        self.recovery_config.max_ack_delay = max_ack_delay;
+
+        let active_path = self.paths.get_active_mut()?;
+
+        active_path.recovery.update_max_ack_delay(max_ack_delay);
+
+        if active_path.pmtud.get_probe_status() {
+            active_path.recovery.pmtud_update_max_datagram_size(
+                active_path
+                    .pmtud
+                    .get_probe_size()
+                    .min(peer_params.max_udp_payload_size as usize),
+            );
+        } else {
+            active_path.recovery.update_max_datagram_size(
+                peer_params.max_udp_payload_size as usize,
+            );
         }
 
-        if pkt_type == packet::Type::Short && !is_closing && path.active() {
-            // Create HANDSHAKE_DONE frame.
-            // self.should_send_handshake_done() but without the need to borrow
-            if self.handshake_completed &&
-                !self.handshake_done_sent &&
-                self.is_server
-            {
-                let frame = frame::Frame::HandshakeDone;
+        // Record the max_active_conn_id parameter advertised by the peer.
+        self.ids
+            .set_source_conn_id_limit(peer_params.active_conn_id_limit);
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.handshake_done_sent = true;
+        if self.is_server {
+            if let Some(blob) = &peer_params.careful_resume_blob {
+                self.process_resume_blob(blob);
+            }
 
-                    ack_eliciting = true;
-                    in_flight = true;
-                }
+            // The client may also present a blob sent in a NEW_TOKEN frame
+            // on a previous connection.
+            let token_blob = self
+                .peer_token
+                .as_deref()
+                .filter(|_| self.resume_new_token)
+                .and_then(resume_blob::from_token)
+                .map(<[u8]>::to_vec);
+
+            if let Some(blob) = token_blob {
+                self.process_resume_blob(&blob);
             }
+        }
 
-            // Create MAX_STREAMS_BIDI frame.
-            if self.streams.should_update_max_streams_bidi() {
-                let frame = frame::Frame::MaxStreamsBidi {
-                    max: self.streams.max_streams_bidi_next(),
-                };
+        self.peer_transport_params = peer_params;
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.streams.update_max_streams_bidi();
+        Ok(())
+    }
 
-                    ack_eliciting = true;
-                    in_flight = true;
-                }
-            }
+    /// Sets up careful resume from a stateless blob echoed by the client,
+    /// once the client's address is validated.
+    ///
+    /// Blobs that fail validation, or whose parameters are rejected by the
+    /// policy, are ignored, and the connection carries on without careful
+    /// resume.
+    fn process_resume_blob(&mut self, blob: &[u8]) {
+        let key = match &self.resume_blob_key {
+            Some(v) if !blob.is_empty() && self.recovery_config.resume => v,
 
-            // Create MAX_STREAMS_UNI frame.
-            if self.streams.should_update_max_streams_uni() {
-                let frame = frame::Frame::MaxStreamsUni {
-                    max: self.streams.max_streams_uni_next(),
-                };
+            _ => return,
+        };
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.streams.update_max_streams_uni();
+        let peer = match self.paths.get_active() {
+            Ok(v) => v.peer_addr(),
 
-                    ack_eliciting = true;
-                    in_flight = true;
-                }
-            }
+            Err(_) => return,
+        };
 
-            // Create DATA_BLOCKED frame.
-            if let Some(limit) = self.blocked_limit {
-                let frame = frame::Frame::DataBlocked { limit };
+        let params = match key.open(
+            blob,
+            peer.ip(),
+            time::SystemTime::now(),
+            self.resume_blob_max_age,
+        ) {
+            Ok(v) => v,
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.blocked_limit = None;
+            Err(e) => {
+                trace!(
+                    "{} ignoring invalid careful resume blob: {:?}",
+                    self.trace_id,
+                    e
+                );
 
-                    ack_eliciting = true;
-                    in_flight = true;
-                }
-            }
+                return;
+            },
+        };
 
-            // Create MAX_STREAM_DATA frames as needed.
-            for stream_id in self.streams.almost_full() {
-                let stream = match self.streams.get_mut(stream_id) {
-                    Some(v) => v,
+        trace!(
+            "{} accepted careful resume blob min_rtt={:?} cwnd={}",
+            self.trace_id,
+            params.min_rtt,
+            params.cwnd
+        );
 
-                    None => {
-                        // The stream doesn't exist anymore, so remove it from
-                        // the almost full set.
-                        self.streams.remove_almost_full(stream_id);
-                        continue;
-                    },
-                };
+        // The blob is echoed in the client's first flight, before its
+        // address is validated.
+        self.queue_server_cr_params(params, CrParamsSource::ResumeBlob);
+    }
 
-                // Autotune the stream window size.
-                stream.recv.autotune_window(now, path.recovery.rtt());
+    /// Accepts the Careful Resume parameters sealed in the token of a BDP
+    /// frame echoed by the client, with their age.
+    ///
+    /// Tokens that fail validation are ignored, as the parameters in clear in
+    /// the frame could have been changed by the client.
+    fn process_bdp_token(&mut self, token: &[u8]) {
+        let key = match &self.resume_blob_key {
+            Some(v) if !token.is_empty() => v,
 
-                let frame = frame::Frame::MaxStreamData {
-                    stream_id,
-                    max: stream.recv.max_data_next(),
-                };
+            _ => return,
+        };
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    let recv_win = stream.recv.window();
+        let peer = match self.paths.get_active() {
+            Ok(v) => v.peer_addr(),
 
-                    stream.recv.update_max_data(now);
+            Err(_) => return,
+        };
 
-                    self.streams.remove_almost_full(stream_id);
+        let (params, age) = match key.open_with_age(
+            token,
+            peer.ip(),
+            time::SystemTime::now(),
+            self.resume_blob_max_age,
+        ) {
+            Ok(v) => v,
 
-                    ack_eliciting = true;
-                    in_flight = true;
+            Err(e) => {
+                trace!(
+                    "{} ignoring invalid BDP frame token: {:?}",
+                    self.trace_id,
+                    e
+                );
 
-                    // Make sure the connection window always has some
-                    // room compared to the stream window.
-                    flow_control.ensure_window_lower_bound(
-                        (recv_win as f64 * CONNECTION_WINDOW_FACTOR) as u64,
-                    );
+                return;
+            },
+        };
 
-                    // Also send MAX_DATA when MAX_STREAM_DATA is sent, to avoid a
-                    // potential race condition.
-                    self.almost_full = true;
-                }
-            }
+        if let Some(params) =
+            self.check_cr_params(params, CrParamsSource::BdpFrame)
+        {
+            self.accept_peer_cr_params(params, age);
+        }
+    }
 
-            for stream_id in self.streams.force_update() {
-                let stream = match self.streams.get_mut(stream_id) {
-                    Some(v) => v,
-                    None => {
-                        self.streams.remove_force_update(stream_id);
-                        continue;
-                    },
-                };
+    /// Continues the handshake.
+    ///
+    /// If the connection is already established, it does nothing.
+    fn do_handshake(&mut self, now: time::Instant) -> Result<()> {
+        let mut ex_data = tls::ExData {
+            application_protos: &self.application_protos,
 
-                let frame = frame::Frame::MaxStreamData {
-                    stream_id,
-                    max: stream.recv.max_data()
-                };
+            pkt_num_spaces: &mut self.pkt_num_spaces,
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.streams.remove_force_update(stream_id);
+            session: &mut self.session,
 
-                    ack_eliciting = true;
-                    in_flight = true;
+            local_error: &mut self.local_error,
 
-                    self.almost_full = true;
-                }
-            }
+            keylog: self.keylog.as_mut(),
 
-            // Create MAX_DATA frame as needed.
-            if self.almost_full &&
-                flow_control.max_data() < flow_control.max_data_next()
-            {
-                // Autotune the connection window size.
-                flow_control.autotune_window(now, path.recovery.rtt());
+            trace_id: &self.trace_id,
 
-                let frame = frame::Frame::MaxData {
-                    max: flow_control.max_data_next(),
-                };
+            is_server: self.is_server,
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.almost_full = false;
+            version: self.version,
 
-                    // Commits the new max_rx_data limit.
-                    flow_control.update_max_data(now);
+            chacha20_only: self.chacha20_only,
+        };
 
-                    ack_eliciting = true;
-                    in_flight = true;
-                }
-            }
+        if self.handshake_completed {
+            return self.handshake.process_post_handshake(&mut ex_data);
+        }
 
-            // Create STOP_SENDING frames as needed.
-            for (stream_id, error_code) in self
-                .streams
-                .stopped()
-                .map(|(&k, &v)| (k, v))
-                .collect::<Vec<(u64, u64)>>()
-            {
-                let frame = frame::Frame::StopSending {
-                    stream_id,
-                    error_code,
-                };
+        match self.handshake.do_handshake(&mut ex_data) {
+            Ok(_) => (),
+
+            Err(Error::Done) => {
+                // Try to parse transport parameters as soon as the first flight
+                // of handshake data is processed.
+                //
+                // This is potentially dangerous as the handshake hasn't been
+                // completed yet, though it's required to be able to send data
+                // in 0.5 RTT.
+                let raw_params = self.handshake.quic_transport_params();
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.streams.remove_stopped(stream_id);
+                if !self.parsed_peer_transport_params && !raw_params.is_empty() {
+                    let peer_params =
+                        TransportParams::decode(raw_params, self.is_server)?;
 
-                    ack_eliciting = true;
-                    in_flight = true;
+                    self.parse_peer_transport_params(peer_params)?;
                 }
-            }
 
-            // Create RESET_STREAM frames as needed.
-            for (stream_id, (error_code, final_size)) in self
-                .streams
-                .reset()
-                .map(|(&k, &v)| (k, v))
-                .collect::<Vec<(u64, (u64, u64))>>()
-            {
-                let frame = frame::Frame::ResetStream {
-                    stream_id,
-                    error_code,
-                    final_size,
-                };
+                return Ok(());
+            },
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.streams.remove_reset(stream_id);
+            Err(e) => return Err(e),
+        };
 
-                    ack_eliciting = true;
-                    in_flight = true;
-                }
-            }
+        self.handshake_completed = self.handshake.is_completed();
 
-            // Create STREAM_DATA_BLOCKED frames as needed.
-            for (stream_id, limit) in self
-                .streams
-                .blocked()
-                .map(|(&k, &v)| (k, v))
-                .collect::<Vec<(u64, u64)>>()
-            {
-                let frame = frame::Frame::StreamDataBlocked { stream_id, limit };
+        self.alpn = self.handshake.alpn_protocol().to_vec();
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.streams.remove_blocked(stream_id);
+        let raw_params = self.handshake.quic_transport_params();
 
-                    ack_eliciting = true;
-                    in_flight = true;
-                }
+        if !self.parsed_peer_transport_params && !raw_params.is_empty() {
+            let peer_params =
+                TransportParams::decode(raw_params, self.is_server)?;
+
+            self.parse_peer_transport_params(peer_params)?;
+        }
+
+        if self.handshake_completed {
+            // The handshake is considered confirmed at the server when the
+            // handshake completes, at which point we can also drop the
+            // handshake epoch.
+            if self.is_server {
+                self.handshake_confirmed = true;
+
+                self.drop_epoch_state(packet::Epoch::Handshake, now);
             }
 
-            // Create RETIRE_CONNECTION_ID frames as needed.
-            while let Some(seq_num) = self.ids.next_retire_dcid_seq() {
-                // The sequence number specified in a RETIRE_CONNECTION_ID frame
-                // MUST NOT refer to the Destination Connection ID field of the
-                // packet in which the frame is contained.
-                let dcid_seq = path.active_dcid_seq.ok_or(Error::InvalidState)?;
+            // Once the handshake is completed there's no point in processing
+            // 0-RTT packets anymore, so clear the buffer now.
+            self.undecryptable_pkts.clear();
 
-                if seq_num == dcid_seq {
-                    continue;
-                }
+            self.maybe_setup_peer_careful_resume();
 
-                let frame = frame::Frame::RetireConnectionId { seq_num };
+            trace!("{} connection established: proto={:?} cipher={:?} curve={:?} sigalg={:?} resumed={} {:?}",
+                   &self.trace_id,
+                   std::str::from_utf8(self.application_proto()),
+                   self.handshake.cipher(),
+                   self.handshake.curve(),
+                   self.handshake.sigalg(),
+                   self.handshake.is_resumed(),
+                   self.peer_transport_params);
+        }
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.ids.mark_retire_dcid_seq(seq_num, false)?;
+        Ok(())
+    }
 
-                    ack_eliciting = true;
-                    in_flight = true;
-                } else {
-                    break;
-                }
-            }
+    /// Selects the packet type for the next outgoing packet.
+    fn write_pkt_type(&self, send_pid: usize) -> Result<packet::Type> {
+        // On error send packet in the latest epoch available, but only send
+        // 1-RTT ones when the handshake is completed.
+        if self
+            .local_error
+            .as_ref()
+            .map_or(false, |conn_err| !conn_err.is_app)
+        {
+            let epoch = match self.handshake.write_level() {
+                crypto::Level::Initial => packet::Epoch::Initial,
+                crypto::Level::ZeroRTT => unreachable!(),
+                crypto::Level::Handshake => packet::Epoch::Handshake,
+                crypto::Level::OneRTT => packet::Epoch::Application,
+            };
 
-            // Create NEW_TOKEN frame.
-            if let Some(token) = &self.new_token {
-                let frame = frame::Frame::NewToken { token: token.clone() };
+            if !self.is_established() {
+                match epoch {
+                    // Downgrade the epoch to Handshake as the handshake is not
+                    // completed yet.
+                    packet::Epoch::Application =>
+                        return Ok(packet::Type::Handshake),
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    self.new_token = None;
+                    // Downgrade the epoch to Initial as the remote peer might
+                    // not be able to decrypt handshake packets yet.
+                    packet::Epoch::Handshake
+                        if self.pkt_num_spaces[packet::Epoch::Initial]
+                            .has_keys() =>
+                        return Ok(packet::Type::Initial),
 
-                    ack_eliciting = true;
-                    in_flight = true;
-                }
+                    _ => (),
+                };
             }
-        }
 
-        // Create CONNECTION_CLOSE frame. Try to send this only on the active
-        // path, unless it is the last one available.
-        if path.active() || n_paths == 1 {
-            if let Some(conn_err) = self.local_error.as_ref() {
-                if conn_err.is_app {
-                    // Create ApplicationClose frame.
-                    if pkt_type == packet::Type::Short {
-                        let frame = frame::Frame::ApplicationClose {
-                            error_code: conn_err.error_code,
-                            reason: conn_err.reason.clone(),
-                        };
+            return Ok(packet::Type::from_epoch(epoch));
+        }
 
-                        if push_frame_to_pkt!(b, frames, frame, left) {
-                            let pto = path.recovery.pto();
-                            self.draining_timer = Some(now + (pto * 3));
+        for &epoch in packet::Epoch::epochs(
+            packet::Epoch::Initial..=packet::Epoch::Application,
+        ) {
+            // Only send packets in a space when we have the send keys for it.
+            if self.pkt_num_spaces[epoch].crypto_seal.is_none() {
+                continue;
+            }
 
-                            ack_eliciting = true;
-                            in_flight = true;
-                        }
-                    }
-                } else {
-                    // Create ConnectionClose frame.
-                    let frame = frame::Frame::ConnectionClose {
-                        error_code: conn_err.error_code,
-                        frame_type: 0,
-                        reason: conn_err.reason.clone(),
-                    };
+            // We are ready to send data for this packet number space.
+            if self.pkt_num_spaces[epoch].ready() {
+                return Ok(packet::Type::from_epoch(epoch));
+            }
 
-                    if push_frame_to_pkt!(b, frames, frame, left) {
-                        let pto = path.recovery.pto();
-                        self.draining_timer = Some(now + (pto * 3));
+            // There are lost frames in this packet number space.
+            for (_, p) in self.paths.iter() {
+                if p.recovery.has_lost_frames(epoch) {
+                    return Ok(packet::Type::from_epoch(epoch));
+                }
 
-                        ack_eliciting = true;
-                        in_flight = true;
-                    }
+                // We need to send PTO probe packets.
+                if p.recovery.loss_probes(epoch) > 0 {
+                    return Ok(packet::Type::from_epoch(epoch));
                 }
             }
         }
 
-        // Create CRYPTO frame.
-        if pkt_space.crypto_stream.is_flushable() &&
-            left > frame::MAX_CRYPTO_OVERHEAD &&
-            !is_closing &&
-            path.active()
+        // If there are flushable, almost full or blocked streams, use the
+        // Application epoch.
+        let send_path = self.paths.get(send_pid)?;
+        if (self.is_established() || self.is_in_early_data()) &&
+            (self.should_send_handshake_done() ||
+                self.almost_full ||
+                self.blocked_limit.is_some() ||
+                self.dgram_send_queue.has_pending() ||
+                self.local_error
+                    .as_ref()
+                    .map_or(false, |conn_err| conn_err.is_app) ||
+                self.streams.should_update_max_streams_bidi() ||
+                self.streams.should_update_max_streams_uni() ||
+                self.streams.has_flushable() ||
+                self.streams.has_almost_full() ||
+                self.streams.has_blocked() ||
+                self.streams.has_reset() ||
+                self.streams.has_stopped() ||
+                self.ids.has_new_scids() ||
+                self.ids.has_retire_dcids() ||
+                send_path.pmtud.get_probe_status() ||
+                send_path.needs_ack_eliciting ||
+                send_path.probing_required())
         {
-            let crypto_off = pkt_space.crypto_stream.send.off_front();
+            // Only clients can send 0-RTT packets.
+            if !self.is_server && self.is_in_early_data() {
+                return Ok(packet::Type::ZeroRTT);
+            }
 
-            // Encode the frame.
-            //
-            // Instead of creating a `frame::Frame` object, encode the frame
-            // directly into the packet buffer.
-            //
-            // First we reserve some space in the output buffer for writing the
-            // frame header (we assume the length field is always a 2-byte
-            // varint as we don't know the value yet).
-            //
-            // Then we emit the data from the crypto stream's send buffer.
-            //
-            // Finally we go back and encode the frame header with the now
-            // available information.
-            let hdr_off = b.off();
-            let hdr_len = 1 + // frame type
-                octets::varint_len(crypto_off) + // offset
-                2; // length, always encode as 2-byte varint
+            return Ok(packet::Type::Short);
+        }
+
+        Err(Error::Done)
+    }
+
+    /// Returns the mutable stream with the given ID if it exists, or creates
+    /// a new one otherwise.
+    fn get_or_create_stream(
+        &mut self, id: u64, local: bool,
+    ) -> Result<&mut stream::Stream> {
+        self.streams.get_or_create(
+            id,
+            &self.local_transport_params,
+            &self.peer_transport_params,
+            local,
+            self.is_server,
+            self.default_stream_window
+        )
+    }
 
-            if let Some(max_len) = left.checked_sub(hdr_len) {
-                let (mut crypto_hdr, mut crypto_payload) =
-                    b.split_at(hdr_off + hdr_len)?;
+    /// Processes an incoming frame.
+    fn process_frame(
+        &mut self, frame: frame::Frame, hdr: &packet::Header,
+        recv_path_id: usize, epoch: packet::Epoch, now: time::Instant,
+    ) -> Result<()> {
+        trace!("{} rx frm {:?}", self.trace_id, frame);
 
-                // Write stream data into the packet buffer.
-                let (len, _) = pkt_space
-                    .crypto_stream
-                    .send
-                    .emit(&mut crypto_payload.as_mut()[..max_len])?;
+        match frame {
+            frame::Frame::Padding { .. } => (),
 
-                // Encode the frame's header.
-                //
-                // Due to how `OctetsMut::split_at()` works, `crypto_hdr` starts
-                // from the initial offset of `b` (rather than the current
-                // offset), so it needs to be advanced to the
-                // initial frame offset.
-                crypto_hdr.skip(hdr_off)?;
+            frame::Frame::Ping { .. } => (),
 
-                frame::encode_crypto_header(
-                    crypto_off,
-                    len as u64,
-                    &mut crypto_hdr,
-                )?;
+            frame::Frame::ACK {
+                ranges,
+                ack_delay,
+                ecn_counts,
+            } => {
+                let ack_delay = ack_delay
+                    .checked_mul(2_u64.pow(
+                        self.peer_transport_params.ack_delay_exponent as u32,
+                    ))
+                    .ok_or(Error::InvalidFrame)?;
 
-                // Advance the packet buffer's offset.
-                b.skip(hdr_len + len)?;
+                if epoch == packet::Epoch::Handshake ||
+                    (epoch == packet::Epoch::Application &&
+                        self.is_established())
+                {
+                    self.peer_verified_initial_address = true;
+                }
 
-                let frame = frame::Frame::CryptoHeader {
-                    offset: crypto_off,
-                    length: len,
-                };
+                let handshake_status = self.handshake_status();
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    ack_eliciting = true;
-                    in_flight = true;
-                    has_data = true;
-                }
-            }
-        }
+                let ecn_ce_count = ecn_counts.map(|ecn| ecn.ecn_ce_count());
 
-        // The preference of data-bearing frame to include in a packet
-        // is managed by `self.emit_dgram`. However, whether any frames
-        // can be sent depends on the state of their buffers. In the case
-        // where one type is preferred but its buffer is empty, fall back
-        // to the other type in order not to waste this function call.
-        let mut dgram_emitted = false;
-        let dgrams_to_emit = max_dgram_len.is_some();
-        let stream_to_emit = self.streams.has_flushable();
+                let is_app_limited = self.delivery_rate_check_if_app_limited();
 
-        let mut do_dgram = self.emit_dgram && dgrams_to_emit;
-        let do_stream = !self.emit_dgram && stream_to_emit;
+                for (path_id, p) in self.paths.iter_mut() {
+                    if is_app_limited {
+                        p.recovery.delivery_rate_update_app_limited(true);
+                    }
 
-        if !do_stream && dgrams_to_emit {
-            do_dgram = true;
-        }
+                    let (lost_packets, lost_bytes, acked_bytes) =
+                        p.recovery.on_ack_received(
+                            &ranges,
+                            ack_delay,
+                            ecn_ce_count,
+                            epoch,
+                            handshake_status,
+                            now,
+                            &self.trace_id,
+                        )?;
 
-        // Create DATAGRAM frame.
-        if (pkt_type == packet::Type::Short || pkt_type == packet::Type::ZeroRTT) &&
-            left > frame::MAX_DGRAM_OVERHEAD &&
-            !is_closing &&
-            path.active() &&
-            do_dgram
-        {
-            if let Some(max_dgram_payload) = max_dgram_len {
-                while let Some(len) = self.dgram_send_queue.peek_front_len() {
-                    let hdr_off = b.off();
-                    let hdr_len = 1 + // frame type
-                        2; // length, always encode as 2-byte varint
+                    self.lost_count += lost_packets;
+                    self.lost_bytes += lost_bytes as u64;
+                    self.acked_bytes += acked_bytes as u64;
 
-                    if (hdr_len + len) <= left {
-                        // Front of the queue fits this packet, send it.
-                        match self.dgram_send_queue.pop() {
-                            Some(data) => {
-                                // Encode the frame.
-                                //
-                                // Instead of creating a `frame::Frame` object,
-                                // encode the frame directly into the packet
-                                // buffer.
-                                //
-                                // First we reserve some space in the output
-                                // buffer for writing the frame header (we
-                                // assume the length field is always a 2-byte
-                                // varint as we don't know the value yet).
-                                //
-                                // Then we emit the data from the DATAGRAM's
-                                // buffer.
-                                //
-                                // Finally we go back and encode the frame
-                                // header with the now available information.
-                                let (mut dgram_hdr, mut dgram_payload) =
-                                    b.split_at(hdr_off + hdr_len)?;
+                    // Only ACKs of new packets generate a sample.
+                    if acked_bytes > 0 {
+                        if let Some(w) = &mut self.rate_samples {
+                            let sample = p.recovery.rate_sample();
+                            w.write_sample(now, path_id, &sample);
+                        }
+                    }
 
-                                dgram_payload.as_mut()[..len]
-                                    .copy_from_slice(&data);
+                    qlog_with_type!(QLOG_PACKET_LOST, self.qlog, q, {
+                        for ev_data in p.recovery.packet_loss_qlog() {
+                            self.qlog.stats.add_event_data(q, ev_data, now);
+                        }
+                    });
+                }
+            },
 
-                                // Encode the frame's header.
-                                //
-                                // Due to how `OctetsMut::split_at()` works,
-                                // `dgram_hdr` starts from the initial offset
-                                // of `b` (rather than the current offset), so
-                                // it needs to be advanced to the initial frame
-                                // offset.
-                                dgram_hdr.skip(hdr_off)?;
+            frame::Frame::ResetStream {
+                stream_id,
+                error_code,
+                final_size,
+            } => {
+                // Peer can't send on our unidirectional streams.
+                if !stream::is_bidi(stream_id) &&
+                    stream::is_local(stream_id, self.is_server)
+                {
+                    return Err(Error::InvalidStreamState(stream_id));
+                }
 
-                                frame::encode_dgram_header(
-                                    len as u64,
-                                    &mut dgram_hdr,
-                                )?;
+                let max_rx_data_left = self.max_rx_data() - self.rx_data;
 
-                                // Advance the packet buffer's offset.
-                                b.skip(hdr_len + len)?;
+                // Get existing stream or create a new one, but if the stream
+                // has already been closed and collected, ignore the frame.
+                //
+                // This can happen if e.g. an ACK frame is lost, and the peer
+                // retransmits another frame before it realizes that the stream
+                // is gone.
+                //
+                // Note that it makes it impossible to check if the frame is
+                // illegal, since we have no state, but since we ignore the
+                // frame, it should be fine.
+                let stream = match self.get_or_create_stream(stream_id, false) {
+                    Ok(v) => v,
 
-                                let frame =
-                                    frame::Frame::DatagramHeader { length: len };
+                    Err(Error::Done) => return Ok(()),
 
-                                if push_frame_to_pkt!(b, frames, frame, left) {
-                                    ack_eliciting = true;
-                                    in_flight = true;
-                                    dgram_emitted = true;
-                                }
-                            },
+                    Err(e) => return Err(e),
+                };
 
-                            None => continue,
-                        };
-                    } else if len > max_dgram_payload {
-                        // This dgram frame will never fit. Let's purge it.
-                        self.dgram_send_queue.pop();
-                    } else {
-                        break;
-                    }
+                let was_readable = stream.is_readable();
+                let priority_key = Arc::clone(&stream.priority_key);
+
+                let max_off_delta =
+                    stream.recv.reset(error_code, final_size)? as u64;
+
+                if max_off_delta > max_rx_data_left {
+                    return Err(Error::FlowControl);
                 }
-            }
-        }
 
-        // Create a single STREAM frame for the first stream that is flushable.
-        if (pkt_type == packet::Type::Short || pkt_type == packet::Type::ZeroRTT) &&
-            left > frame::MAX_STREAM_OVERHEAD &&
-            !is_closing &&
-            path.active() &&
-            !dgram_emitted
-        {
-            while let Some(priority_key) = self.streams.peek_flushable() {
-                let stream_id = priority_key.id;
-                let stream = match self.streams.get_mut(stream_id) {
-                    // Avoid sending frames for streams that were already stopped.
-                    //
-                    // This might happen if stream data was buffered but not yet
-                    // flushed on the wire when a STOP_SENDING frame is received.
-                    Some(v) if !v.send.is_stopped() => v,
-                    _ => {
-                        self.streams.remove_flushable(&priority_key);
-                        continue;
-                    },
-                };
+                if !was_readable && stream.is_readable() {
+                    self.streams.insert_readable(&priority_key);
+                }
 
-                let stream_off = stream.send.off_front();
+                self.rx_data += max_off_delta;
 
-                // Encode the frame.
-                //
-                // Instead of creating a `frame::Frame` object, encode the frame
-                // directly into the packet buffer.
-                //
-                // First we reserve some space in the output buffer for writing
-                // the frame header (we assume the length field is always a
-                // 2-byte varint as we don't know the value yet).
-                //
-                // Then we emit the data from the stream's send buffer.
-                //
-                // Finally we go back and encode the frame header with the now
-                // available information.
-                let hdr_off = b.off();
-                let hdr_len = 1 + // frame type
-                    octets::varint_len(stream_id) + // stream_id
-                    octets::varint_len(stream_off) + // offset
-                    2; // length, always encode as 2-byte varint
+                self.reset_stream_remote_count =
+                    self.reset_stream_remote_count.saturating_add(1);
+            },
 
-                let max_len = match left.checked_sub(hdr_len) {
-                    Some(v) => v,
-                    None => {
-                        let priority_key = Arc::clone(&stream.priority_key);
-                        self.streams.remove_flushable(&priority_key);
+            frame::Frame::ResetStreamAt {
+                stream_id,
+                error_code,
+                final_size,
+                reliable_size,
+            } => {
+                if !self.local_transport_params.reset_stream_at {
+                    return Err(Error::InvalidState);
+                }
 
-                        continue;
-                    },
-                };
+                // Peer can't send on our unidirectional streams.
+                if !stream::is_bidi(stream_id) &&
+                    stream::is_local(stream_id, self.is_server)
+                {
+                    return Err(Error::InvalidStreamState(stream_id));
+                }
 
-                let (mut stream_hdr, mut stream_payload) =
-                    b.split_at(hdr_off + hdr_len)?;
+                let max_rx_data_left = self.max_rx_data() - self.rx_data;
 
-                // Write stream data into the packet buffer.
-                let (len, fin) =
-                    stream.send.emit(&mut stream_payload.as_mut()[..max_len])?;
+                // Get existing stream or create a new one, but if the stream
+                // has already been closed and collected, ignore the frame.
+                let stream = match self.get_or_create_stream(stream_id, false) {
+                    Ok(v) => v,
 
-                // Encode the frame's header.
-                //
-                // Due to how `OctetsMut::split_at()` works, `stream_hdr` starts
-                // from the initial offset of `b` (rather than the current
-                // offset), so it needs to be advanced to the initial frame
-                // offset.
-                stream_hdr.skip(hdr_off)?;
+                    Err(Error::Done) => return Ok(()),
 
-                frame::encode_stream_header(
-                    stream_id,
-                    stream_off,
-                    len as u64,
-                    fin,
-                    &mut stream_hdr,
-                )?;
+                    Err(e) => return Err(e),
+                };
 
-                // Advance the packet buffer's offset.
-                b.skip(hdr_len + len)?;
+                let was_readable = stream.is_readable();
+                let priority_key = Arc::clone(&stream.priority_key);
 
-                let frame = frame::Frame::StreamHeader {
-                    stream_id,
-                    offset: stream_off,
-                    length: len,
-                    fin,
-                };
+                let max_off_delta =
+                    stream
+                        .recv
+                        .reset_at(error_code, final_size, reliable_size)?
+                        as u64;
 
-                if push_frame_to_pkt!(b, frames, frame, left) {
-                    ack_eliciting = true;
-                    in_flight = true;
-                    has_data = true;
+                if max_off_delta > max_rx_data_left {
+                    return Err(Error::FlowControl);
                 }
 
-                let priority_key = Arc::clone(&stream.priority_key);
-                // If the stream is no longer flushable, remove it from the queue
-                if !stream.is_flushable() {
-                    self.streams.remove_flushable(&priority_key);
-                } else if stream.incremental {
-                    // Shuffle the incremental stream to the back of the
-                    // queue.
-                    self.streams.remove_flushable(&priority_key);
-                    self.streams.insert_flushable(&priority_key);
+                if !was_readable && stream.is_readable() {
+                    self.streams.insert_readable(&priority_key);
                 }
 
-                #[cfg(feature = "fuzzing")]
-                // Coalesce STREAM frames when fuzzing.
-                if left > frame::MAX_STREAM_OVERHEAD {
-                    continue;
+                self.rx_data += max_off_delta;
+
+                self.reset_stream_remote_count =
+                    self.reset_stream_remote_count.saturating_add(1);
+            },
+
+            frame::Frame::StopSending {
+                stream_id,
+                error_code,
+            } => {
+                // STOP_SENDING on a receive-only stream is a fatal error.
+                if !stream::is_local(stream_id, self.is_server) &&
+                    !stream::is_bidi(stream_id)
+                {
+                    return Err(Error::InvalidStreamState(stream_id));
                 }
 
-                break;
-            }
-        }
+                // Get existing stream or create a new one, but if the stream
+                // has already been closed and collected, ignore the frame.
+                //
+                // This can happen if e.g. an ACK frame is lost, and the peer
+                // retransmits another frame before it realizes that the stream
+                // is gone.
+                //
+                // Note that it makes it impossible to check if the frame is
+                // illegal, since we have no state, but since we ignore the
+                // frame, it should be fine.
+                let stream = match self.get_or_create_stream(stream_id, false) {
+                    Ok(v) => v,
 
-        // Alternate trying to send DATAGRAMs next time.
-        self.emit_dgram = !dgram_emitted;
+                    Err(Error::Done) => return Ok(()),
 
-        // If no other ack-eliciting frame is sent, include a PING frame
-        // - if PTO probe needed; OR
-        // - if we've sent too many non ack-eliciting packets without having
-        // sent an ACK eliciting one; OR
-        // - the application requested an ack-eliciting frame be sent.
-        if (ack_elicit_required || path.needs_ack_eliciting) &&
-            !ack_eliciting &&
-            left >= 1 &&
-            !is_closing
-        {
-            let frame = frame::Frame::Ping { mtu_probe: None };
+                    Err(e) => return Err(e),
+                };
 
-            if push_frame_to_pkt!(b, frames, frame, left) {
-                ack_eliciting = true;
-                in_flight = true;
-            }
-        }
+                let was_writable = stream.is_writable();
 
-        if ack_eliciting && !pmtud_probe {
-            path.needs_ack_eliciting = false;
-            path.recovery.ping_sent(epoch);
-        }
+                let priority_key = Arc::clone(&stream.priority_key);
 
-        if frames.is_empty() {
-            // When we reach this point we are not able to write more, so set
-            // app_limited to false.
-            path.recovery.update_app_limited(false);
-            return Err(Error::Done);
-        }
+                // Try stopping the stream.
+                if let Ok((final_size, unsent)) = stream.send.stop(error_code) {
+                    // Claw back some flow control allowance from data that was
+                    // buffered but not actually sent before the stream was
+                    // reset.
+                    //
+                    // Note that `tx_cap` will be updated later on, so no need
+                    // to touch it here.
+                    self.tx_data = self.tx_data.saturating_sub(unsent);
 
-        // When coalescing a 1-RTT packet, we can't add padding in the UDP
-        // datagram, so use PADDING frames instead.
-        //
-        // This is only needed if
-        // 1) an Initial packet has already been written to the UDP datagram,
-        // as Initial always requires padding.
-        //
-        // 2) this is a probing packet towards an unvalidated peer address.
-        if (has_initial || !path.validated()) &&
-            pkt_type == packet::Type::Short &&
-            left >= 1
-        {
-            let frame = frame::Frame::Padding { len: left };
+                    self.tx_buffered =
+                        self.tx_buffered.saturating_sub(unsent as usize);
 
-            if push_frame_to_pkt!(b, frames, frame, left) {
-                in_flight = true;
-            }
-        }
+                    self.streams.insert_reset(stream_id, error_code, final_size);
 
-        // Pad payload so that it's always at least 4 bytes.
-        if b.off() - payload_offset < PAYLOAD_MIN_LEN {
-            let payload_len = b.off() - payload_offset;
+                    if !was_writable {
+                        self.streams.insert_writable(&priority_key);
+                    }
 
-            let frame = frame::Frame::Padding {
-                len: PAYLOAD_MIN_LEN - payload_len,
-            };
+                    self.stopped_stream_remote_count =
+                        self.stopped_stream_remote_count.saturating_add(1);
+                    self.reset_stream_local_count =
+                        self.reset_stream_local_count.saturating_add(1);
+                }
+            },
 
-            #[allow(unused_assignments)]
-            if push_frame_to_pkt!(b, frames, frame, left) {
-                in_flight = true;
-            }
-        }
+            frame::Frame::Crypto { data } => {
+                if data.max_off() >= MAX_CRYPTO_STREAM_OFFSET {
+                    return Err(Error::CryptoBufferExceeded);
+                }
 
-        let payload_len = b.off() - payload_offset;
+                // The server can switch to a compatible version before
+                // processing the client's first flight.
+                if self.is_server &&
+                    epoch == packet::Epoch::Initial &&
+                    self.recv_count == 0 &&
+                    data.off() == 0
+                {
+                    self.maybe_switch_version(&data, &hdr.dcid)?;
+                }
 
-        // Fill in payload length.
-        if pkt_type != packet::Type::Short {
-            let len = pn_len + payload_len + crypto_overhead;
+                // Push the data to the stream so it can be re-ordered.
+                self.pkt_num_spaces[epoch].crypto_stream.recv.write(data)?;
 
-            let (_, mut payload_with_len) = b.split_at(header_offset)?;
-            payload_with_len
-                .put_varint_with_len(len as u64, PAYLOAD_LENGTH_LEN)?;
-        }
+                // Feed crypto data to the TLS state, if there's data
+                // available at the expected offset.
+                let mut crypto_buf = [0; 512];
 
-        trace!(
-            "{} tx pkt {} len={} pn={} {}",
-            self.trace_id,
-            hdr_trace.unwrap_or_default(),
-            payload_len,
-            pn,
-            AddrTupleFmt(path.local_addr(), path.peer_addr())
-        );
+                let level = crypto::Level::from_epoch(epoch);
 
-        #[cfg(feature = "qlog")]
-        let mut qlog_frames: SmallVec<
-            [qlog::events::quic::QuicFrame; 1],
-        > = SmallVec::with_capacity(frames.len());
+                let stream = &mut self.pkt_num_spaces[epoch].crypto_stream;
 
-        for frame in &mut frames {
-            trace!("{} tx frm {:?}", self.trace_id, frame);
+                while let Ok((read, _)) = stream.recv.emit(&mut crypto_buf) {
+                    let recv_buf = &crypto_buf[..read];
+                    self.handshake.provide_data(level, recv_buf)?;
+                }
 
-            qlog_with_type!(QLOG_PACKET_TX, self.qlog, _q, {
-                qlog_frames.push(frame.to_qlog());
-            });
-        }
+                self.do_handshake(now)?;
+            },
 
-        qlog_with_type!(QLOG_PACKET_TX, self.qlog, q, {
-            if let Some(header) = qlog_pkt_hdr {
-                // Qlog packet raw info described at
-                // https://datatracker.ietf.org/doc/html/draft-ietf-quic-qlog-main-schema-00#section-5.1
-                //
-                // `length` includes packet headers and trailers (AEAD tag).
-                let length = payload_len + payload_offset + crypto_overhead;
-                let qlog_raw_info = RawInfo {
-                    length: Some(length as u64),
-                    payload_length: Some(payload_len as u64),
-                    data: None,
-                };
+            frame::Frame::CryptoHeader { .. } => unreachable!(),
 
-                let send_at_time =
-                    now.duration_since(q.start_time()).as_secs_f32() * 1000.0;
+            frame::Frame::NewToken { token } => {
+                if self.is_server {
+                    return Err(Error::InvalidPacket);
+                }
+                self.received_tokens.push_back(token);
+            },
+
+            frame::Frame::Stream { stream_id, data } => {
+                // Peer can't send on our unidirectional streams.
+                if !stream::is_bidi(stream_id) &&
+                    stream::is_local(stream_id, self.is_server)
+                {
+                    return Err(Error::InvalidStreamState(stream_id));
+                }
+
+                let max_rx_data_left = self.max_rx_data() - self.rx_data;
 
-                let ev_data =
-                    EventData::PacketSent(qlog::events::quic::PacketSent {
-                        header,
-                        frames: Some(qlog_frames),
-                        is_coalesced: None,
-                        retry_token: None,
-                        stateless_reset_token: None,
-                        supported_versions: None,
-                        raw: Some(qlog_raw_info),
-                        datagram_id: None,
-                        send_at_time: Some(send_at_time),
-                        trigger: None,
-                    });
+                // Get existing stream or create a new one, but if the stream
+                // has already been closed and collected, ignore the frame.
+                //
+                // This can happen if e.g. an ACK frame is lost, and the peer
+                // retransmits another frame before it realizes that the stream
+                // is gone.
+                //
+                // Note that it makes it impossible to check if the frame is
+                // illegal, since we have no state, but since we ignore the
+                // frame, it should be fine.
+                let stream = match self.get_or_create_stream(stream_id, false) {
+                    Ok(v) => v,
 
-                q.add_event_data_with_instant(ev_data, now).ok();
-            }
-        });
+                    Err(Error::Done) => return Ok(()),
 
-        let aead = match pkt_space.crypto_seal {
-            Some(ref v) => v,
-            None => return Err(Error::InvalidState),
-        };
+                    Err(e) => return Err(e),
+                };
 
-        let written = packet::encrypt_pkt(
-            &mut b,
-            pn,
-            pn_len,
-            payload_len,
-            payload_offset,
-            None,
-            aead,
-        )?;
+                // Check for the connection-level flow control limit.
+                let max_off_delta =
+                    data.max_off().saturating_sub(stream.recv.max_off());
 
-        let sent_pkt = recovery::Sent {
-            pkt_num: pn,
-            frames,
-            time_sent: now,
-            time_acked: None,
-            time_lost: None,
-            size: if ack_eliciting { written } else { 0 },
-            ack_eliciting,
-            in_flight,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            tx_in_flight: 0,
-            lost: 0,
-            has_data,
-            pmtud: pmtud_probe,
-        };
+                if max_off_delta > max_rx_data_left {
+                    return Err(Error::FlowControl);
+                }
 
-        if in_flight && is_app_limited {
-            path.recovery.delivery_rate_update_app_limited(true);
-        }
+                let was_readable = stream.is_readable();
+                let priority_key = Arc::clone(&stream.priority_key);
 
-        pkt_space.next_pkt_num += 1;
+                let was_draining = stream.recv.is_draining();
 
-        let handshake_status = recovery::HandshakeStatus {
-            has_handshake_keys: self.pkt_num_spaces[packet::Epoch::Handshake]
-                .has_keys(),
-            peer_verified_address: self.peer_verified_initial_address,
-            completed: self.handshake_completed,
-        };
+                stream.recv.write(data)?;
 
-        path.recovery.on_packet_sent(
-            sent_pkt,
-            epoch,
-            handshake_status,
-            now,
-            &self.trace_id,
-        );
+                if !was_readable && stream.is_readable() {
+                    self.streams.insert_readable(&priority_key);
+                }
 
-        qlog_with_type!(QLOG_METRICS, self.qlog, q, {
-            if let Some(ev_data) = path.recovery.maybe_qlog() {
-                q.add_event_data_with_instant(ev_data, now).ok();
-            }
-        });
-        qlog_with_type!(QLOG_CR_PHASE, self.qlog, q, {
-            if let Some(ev_data) = path.recovery.maybe_cr_qlog() {
-                q.add_event_data_with_instant(ev_data, now).ok();
-            }
-        });
+                self.rx_data += max_off_delta;
 
-        // Record sent packet size if we probe the path.
-        if let Some(data) = challenge_data {
-            path.add_challenge_sent(data, written, now);
-        }
+                if was_draining {
+                    // When a stream is in draining state it will not queue
+                    // incoming data for the application to read, so consider
+                    // the received data as consumed, which might trigger a flow
+                    // control update.
+                    self.flow_control.add_consumed(max_off_delta);
 
-        self.sent_count += 1;
-        self.sent_bytes += written as u64;
-        path.sent_count += 1;
-        path.sent_bytes += written as u64;
+                    if self.should_update_max_data() {
+                        self.almost_full = true;
+                    }
+                }
+            },
 
-        if self.dgram_send_queue.byte_size() > path.recovery.cwnd_available() {
-            path.recovery.update_app_limited(false);
-        }
+            frame::Frame::StreamHeader { .. } => unreachable!(),
 
-        path.max_send_bytes = path.max_send_bytes.saturating_sub(written);
+            frame::Frame::MaxData { max } => {
+                self.max_tx_data = cmp::max(self.max_tx_data, max);
+            },
 
-        if path.active() {
-            if let Some(cr_event) = path.recovery.maybe_cr_event() {
-                self.update_cr_event(cr_event);
-            }
-        }
+            frame::Frame::MaxStreamData { stream_id, max } => {
+                // Peer can't receive on its own unidirectional streams.
+                if !stream::is_bidi(stream_id) &&
+                    !stream::is_local(stream_id, self.is_server)
+                {
+                    return Err(Error::InvalidStreamState(stream_id));
+                }
 
-        // On the client, drop initial state after sending an Handshake packet.
-        if !self.is_server && hdr_ty == packet::Type::Handshake {
-            self.drop_epoch_state(packet::Epoch::Initial, now);
-        }
+                // Get existing stream or create a new one, but if the stream
+                // has already been closed and collected, ignore the frame.
+                //
+                // This can happen if e.g. an ACK frame is lost, and the peer
+                // retransmits another frame before it realizes that the stream
+                // is gone.
+                //
+                // Note that it makes it impossible to check if the frame is
+                // illegal, since we have no state, but since we ignore the
+                // frame, it should be fine.
+                let stream = match self.get_or_create_stream(stream_id, false) {
+                    Ok(v) => v,
 
-        // (Re)start the idle timer if we are sending the first ack-eliciting
-        // packet since last receiving a packet.
-        if ack_eliciting && !self.ack_eliciting_sent {
-            if let Some(idle_timeout) = self.idle_timeout() {
-                self.idle_timer = Some(now + idle_timeout);
-            }
-        }
+                    Err(Error::Done) => return Ok(()),
 
-        if ack_eliciting {
-            self.ack_eliciting_sent = true;
-        }
+                    Err(e) => return Err(e),
+                };
 
-        let active_path = self.paths.get_active_mut()?;
-        if active_path.pmtud.is_enabled() {
-            active_path
-                .recovery
-                .pmtud_update_max_datagram_size(active_path.pmtud.get_current());
-        }
+                let was_flushable = stream.is_flushable();
 
-        Ok((pkt_type, written))
-    }
+                stream.send.update_max_data(max);
 
-    /// Returns the size of the send quantum, in bytes.
-    ///
-    /// This represents the maximum size of a packet burst as determined by the
-    /// congestion control algorithm in use.
-    ///
-    /// Applications can, for example, use it in conjunction with segmentation
-    /// offloading mechanisms as the maximum limit for outgoing aggregates of
-    /// multiple packets.
-    #[inline]
-    pub fn send_quantum(&self) -> usize {
-        match self.paths.get_active() {
-            Ok(p) => p.recovery.send_quantum(),
-            _ => 0,
-        }
-    }
+                let writable = stream.is_writable();
 
-    /// Returns the size of the send quantum over the given 4-tuple, in bytes.
-    ///
-    /// This represents the maximum size of a packet burst as determined by the
-    /// congestion control algorithm in use.
-    ///
-    /// Applications can, for example, use it in conjunction with segmentation
-    /// offloading mechanisms as the maximum limit for outgoing aggregates of
-    /// multiple packets.
-    ///
-    /// If the (`local_addr`, peer_addr`) 4-tuple relates to a non-existing
-    /// path, this method returns 0.
-    pub fn send_quantum_on_path(
-        &self, local_addr: SocketAddr, peer_addr: SocketAddr,
-    ) -> usize {
-        self.paths
-            .path_id_from_addrs(&(local_addr, peer_addr))
-            .and_then(|pid| self.paths.get(pid).ok())
-            .map(|path| path.recovery.send_quantum())
-            .unwrap_or(0)
-    }
+                let priority_key = Arc::clone(&stream.priority_key);
 
-    /// Reads contiguous data from a stream into the provided slice.
-    ///
-    /// The slice must be sized by the caller and will be populated up to its
-    /// capacity.
-    ///
-    /// On success the amount of bytes read and a flag indicating the fin state
-    /// is returned as a tuple, or [`Done`] if there is no data to read.
-    ///
-    /// Reading data from a stream may trigger queueing of control messages
-    /// (e.g. MAX_STREAM_DATA). [`send()`] should be called after reading.
-    ///
-    /// [`Done`]: enum.Error.html#variant.Done
-    /// [`send()`]: struct.Connection.html#method.send
-    ///
-    /// ## Examples:
-    ///
-    /// ```no_run
-    /// # let mut buf = [0; 512];
-    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
-    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
-    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let peer = "127.0.0.1:1234".parse().unwrap();
-    /// # let local = socket.local_addr().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
-    /// # let stream_id = 0;
-    /// while let Ok((read, fin)) = conn.stream_recv(stream_id, &mut buf) {
-    ///     println!("Got {} bytes on stream {}", read, stream_id);
-    /// }
-    /// # Ok::<(), quiche::Error>(())
-    /// ```
-    pub fn stream_recv(
-        &mut self, stream_id: u64, out: &mut [u8],
-    ) -> Result<(usize, bool)> {
-        // We can't read on our own unidirectional streams.
-        if !stream::is_bidi(stream_id) &&
-            stream::is_local(stream_id, self.is_server)
-        {
-            return Err(Error::InvalidStreamState(stream_id));
-        }
+                // If the stream is now flushable push it to the flushable queue,
+                // but only if it wasn't already queued.
+                if stream.is_flushable() && !was_flushable {
+                    let priority_key = Arc::clone(&stream.priority_key);
+                    self.streams.insert_flushable(&priority_key);
+                }
 
-        let stream = self
-            .streams
-            .get_mut(stream_id)
-            .ok_or(Error::InvalidStreamState(stream_id))?;
+                if writable {
+                    self.streams.insert_writable(&priority_key);
+                }
+            },
 
-        if !stream.is_readable() {
-            return Err(Error::Done);
-        }
+            frame::Frame::MaxStreamsBidi { max } => {
+                if max > MAX_STREAM_ID {
+                    return Err(Error::InvalidFrame);
+                }
 
-        let local = stream.local;
-        let priority_key = Arc::clone(&stream.priority_key);
+                self.streams.update_peer_max_streams_bidi(max);
+            },
 
-        #[cfg(feature = "qlog")]
-        let offset = stream.recv.off_front();
+            frame::Frame::MaxStreamsUni { max } => {
+                if max > MAX_STREAM_ID {
+                    return Err(Error::InvalidFrame);
+                }
 
-        let (read, fin) = match stream.recv.emit(out) {
-            Ok(v) => v,
+                self.streams.update_peer_max_streams_uni(max);
+            },
 
-            Err(e) => {
-                // Collect the stream if it is now complete. This can happen if
-                // we got a `StreamReset` error which will now be propagated to
-                // the application, so we don't need to keep the stream's state
-                // anymore.
-                if stream.is_complete() {
-                    self.streams.collect(stream_id, local);
+            frame::Frame::DataBlocked { .. } => (),
+
+            frame::Frame::StreamDataBlocked { .. } => (),
+
+            frame::Frame::StreamsBlockedBidi { limit } => {
+                if limit > MAX_STREAM_ID {
+                    return Err(Error::InvalidFrame);
                 }
+            },
 
-                self.streams.remove_readable(&priority_key);
-                return Err(e);
+            frame::Frame::StreamsBlockedUni { limit } => {
+                if limit > MAX_STREAM_ID {
+                    return Err(Error::InvalidFrame);
+                }
             },
-        };
 
-        self.flow_control.add_consumed(read as u64);
+            frame::Frame::NewConnectionId {
+                seq_num,
+                retire_prior_to,
+                conn_id,
+                reset_token,
+            } => {
+                if self.ids.zero_length_dcid() {
+                    return Err(Error::InvalidState);
+                }
 
-        let readable = stream.is_readable();
+                let mut retired_path_ids = SmallVec::new();
 
-        let complete = stream.is_complete();
+                // Retire pending path IDs before propagating the error code to
+                // make sure retired connection IDs are not in use anymore.
+                let new_dcid_res = self.ids.new_dcid(
+                    conn_id.into(),
+                    seq_num,
+                    u128::from_be_bytes(reset_token),
+                    retire_prior_to,
+                    &mut retired_path_ids,
+                );
 
-        if stream.recv.almost_full() {
-            self.streams.insert_almost_full(stream_id);
-        }
+                for (dcid_seq, pid) in retired_path_ids {
+                    let path = self.paths.get_mut(pid)?;
 
-        if !readable {
-            self.streams.remove_readable(&priority_key);
-        }
+                    // Maybe the path already switched to another DCID.
+                    if path.active_dcid_seq != Some(dcid_seq) {
+                        continue;
+                    }
 
-        if complete {
-            self.streams.collect(stream_id, local);
-        }
+                    if let Some(new_dcid_seq) =
+                        self.ids.lowest_available_dcid_seq()
+                    {
+                        path.active_dcid_seq = Some(new_dcid_seq);
 
-        qlog_with_type!(QLOG_DATA_MV, self.qlog, q, {
-            let ev_data = EventData::DataMoved(qlog::events::quic::DataMoved {
-                stream_id: Some(stream_id),
-                offset: Some(offset),
-                length: Some(read as u64),
-                from: Some(DataRecipient::Transport),
-                to: Some(DataRecipient::Application),
-                raw: None,
-            });
+                        self.ids.link_dcid_to_path_id(new_dcid_seq, pid)?;
 
-            let now = time::Instant::now();
-            q.add_event_data_with_instant(ev_data, now).ok();
-        });
+                        trace!(
+                            "{} path ID {} changed DCID: old seq num {} new seq num {}",
+                            self.trace_id, pid, dcid_seq, new_dcid_seq,
+                        );
+                    } else {
+                        // We cannot use this path anymore for now.
+                        path.active_dcid_seq = None;
 
-        if self.should_update_max_data() {
-            self.almost_full = true;
-        }
+                        trace!(
+                            "{} path ID {} cannot be used; DCID seq num {} has been retired",
+                            self.trace_id, pid, dcid_seq,
+                        );
+                    }
+                }
 
-        if priority_key.incremental && readable {
-            // Shuffle the incremental stream to the back of the queue.
-            self.streams.remove_readable(&priority_key);
-            self.streams.insert_readable(&priority_key);
-        }
+                // Propagate error (if any) now...
+                new_dcid_res?;
+            },
 
-        Ok((read, fin))
-    }
+            frame::Frame::RetireConnectionId { seq_num } => {
+                if self.ids.zero_length_scid() {
+                    return Err(Error::InvalidState);
+                }
 
-    /// Writes data to a stream.
-    ///
-    /// On success the number of bytes written is returned, or [`Done`] if no
-    /// data was written (e.g. because the stream has no capacity).
-    ///
-    /// Applications can provide a 0-length buffer with the fin flag set to
-    /// true. This will lead to a 0-length FIN STREAM frame being sent at the
-    /// latest offset. The `Ok(0)` value is only returned when the application
-    /// provided a 0-length buffer.
-    ///
-    /// In addition, if the peer has signalled that it doesn't want to receive
-    /// any more data from this stream by sending the `STOP_SENDING` frame, the
-    /// [`StreamStopped`] error will be returned instead of any data.
-    ///
-    /// Note that in order to avoid buffering an infinite amount of data in the
-    /// stream's send buffer, streams are only allowed to buffer outgoing data
-    /// up to the amount that the peer allows it to send (that is, up to the
-    /// stream's outgoing flow control capacity).
-    ///
-    /// This means that the number of written bytes returned can be lower than
-    /// the length of the input buffer when the stream doesn't have enough
-    /// capacity for the operation to complete. The application should retry the
-    /// operation once the stream is reported as writable again.
-    ///
-    /// Applications should call this method only after the handshake is
-    /// completed (whenever [`is_established()`] returns `true`) or during
-    /// early data if enabled (whenever [`is_in_early_data()`] returns `true`).
-    ///
-    /// [`Done`]: enum.Error.html#variant.Done
-    /// [`StreamStopped`]: enum.Error.html#variant.StreamStopped
-    /// [`is_established()`]: struct.Connection.html#method.is_established
-    /// [`is_in_early_data()`]: struct.Connection.html#method.is_in_early_data
-    ///
-    /// ## Examples:
-    ///
-    /// ```no_run
-    /// # let mut buf = [0; 512];
-    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
-    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
-    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let peer = "127.0.0.1:1234".parse().unwrap();
-    /// # let local = "127.0.0.1:4321".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
-    /// # let stream_id = 0;
-    /// conn.stream_send(stream_id, b"hello", true)?;
-    /// # Ok::<(), quiche::Error>(())
-    /// ```
-    pub fn stream_send(
-        &mut self, stream_id: u64, buf: &[u8], fin: bool,
-    ) -> Result<usize> {
-        // We can't write on the peer's unidirectional streams.
-        if !stream::is_bidi(stream_id) &&
-            !stream::is_local(stream_id, self.is_server)
-        {
-            return Err(Error::InvalidStreamState(stream_id));
-        }
+                if let Some(pid) = self.ids.retire_scid(seq_num, &hdr.dcid)? {
+                    let path = self.paths.get_mut(pid)?;
 
-        // Mark the connection as blocked if the connection-level flow control
-        // limit doesn't let us buffer all the data.
-        //
-        // Note that this is separate from "send capacity" as that also takes
-        // congestion control into consideration.
-        if self.max_tx_data - self.tx_data < buf.len() as u64 {
-            self.blocked_limit = Some(self.max_tx_data);
-        }
+                    // Maybe we already linked a new SCID to that path.
+                    if path.active_scid_seq == Some(seq_num) {
+                        // XXX: We do not remove unused paths now, we instead
+                        // wait until we need to maintain more paths than the
+                        // host is willing to.
+                        path.active_scid_seq = None;
+                    }
+                }
+            },
 
-        let cap = self.tx_cap;
+            frame::Frame::PathChallenge { data } => {
+                self.path_challenge_rx_count += 1;
 
-        // Get existing stream or create a new one.
-        let stream = self.get_or_create_stream(stream_id, true)?;
+                self.paths
+                    .get_mut(recv_path_id)?
+                    .on_challenge_received(data);
+            },
+
+            frame::Frame::PathResponse { data } => {
+                self.paths.on_response_received(data)?;
+            },
+
+            frame::Frame::ConnectionClose {
+                error_code, reason, ..
+            } => {
+                self.peer_error = Some(ConnectionError {
+                    is_app: false,
+                    error_code,
+                    reason,
+                });
+
+                if error_code != 0 {
+                    self.keep_close_decisions();
+                }
+
+                self.final_cr_event(now);
+
+                let path = self.paths.get_active()?;
+                self.draining_timer = Some(now + (path.recovery.pto() * 3));
+            },
+
+            frame::Frame::ApplicationClose { error_code, reason } => {
+                self.peer_error = Some(ConnectionError {
+                    is_app: true,
+                    error_code,
+                    reason,
+                });
+
+                self.final_cr_event(now);
+
+                let path = self.paths.get_active()?;
+                self.draining_timer = Some(now + (path.recovery.pto() * 3));
+            },
+
+            frame::Frame::HandshakeDone => {
+                if self.is_server {
+                    return Err(Error::InvalidPacket);
+                }
 
-        #[cfg(feature = "qlog")]
-        let offset = stream.send.off_back();
+                self.peer_verified_initial_address = true;
 
-        let was_writable = stream.is_writable();
+                self.handshake_confirmed = true;
 
-        let was_flushable = stream.is_flushable();
+                // Once the handshake is confirmed, we can drop Handshake keys.
+                self.drop_epoch_state(packet::Epoch::Handshake, now);
+            },
 
-        let priority_key = Arc::clone(&stream.priority_key);
+            frame::Frame::Datagram { data } => {
+                // Close the connection if DATAGRAMs are not enabled.
+                // quiche always advertises support for 64K sized DATAGRAM
+                // frames, as recommended by the standard, so we don't need a
+                // size check.
+                if !self.dgram_enabled() {
+                    return Err(Error::InvalidState);
+                }
 
-        // Truncate the input buffer based on the connection's send capacity if
-        // necessary.
-        //
-        // When the cap is zero, the method returns Ok(0) *only* when the passed
-        // buffer is empty. We return Error::Done otherwise.
-        if cap == 0 && !buf.is_empty() {
-            if was_writable {
-                // When `stream_writable_next()` returns a stream, the writable
-                // mark is removed, but because the stream is blocked by the
-                // connection-level send capacity it won't be marked as writable
-                // again once the capacity increases.
-                //
-                // Since the stream is writable already, mark it here instead.
-                self.streams.insert_writable(&priority_key);
-            }
+                // If recv queue is full, discard oldest
+                if self.dgram_recv_queue.is_full() {
+                    self.dgram_recv_queue.pop();
+                }
 
-            return Err(Error::Done);
-        }
+                self.dgram_recv_queue.push(data)?;
+            },
 
-        let (buf, fin, blocked_by_cap) = if cap < buf.len() {
-            (&buf[..cap], false, true)
-        } else {
-            (buf, fin, false)
-        };
+            frame::Frame::DatagramHeader { .. } => unreachable!(),
 
-        let sent = match stream.send.write(buf, fin) {
-            Ok(v) => v,
+            frame::Frame::Bdp {
+                lifetime,
+                saved_capacity,
+                saved_rtt,
+                token,
+            } => {
+                if !self.local_transport_params.bdp_frame {
+                    return Err(Error::InvalidState);
+                }
 
-            Err(e) => {
-                self.streams.remove_writable(&priority_key);
-                return Err(e);
+                if self.is_server {
+                    // The client echoes parameters sent on a previous
+                    // connection, which are only trusted as sealed in the
+                    // token.
+                    self.process_bdp_token(&token);
+                } else {
+                    self.peer_bdp_frame = Some(BdpFrame {
+                        lifetime: time::Duration::from_secs(lifetime),
+                        saved_capacity: saved_capacity as usize,
+                        saved_rtt: time::Duration::from_micros(saved_rtt),
+                        token,
+                    });
+                }
             },
-        };
 
-        let incremental = stream.incremental;
-        let priority_key = Arc::clone(&stream.priority_key);
+            frame::Frame::CrHints {
+                seq,
+                lifetime,
+                capacity,
+                min_rtt,
+                max_jump,
+            } => {
+                if self.is_server || !self.local_transport_params.cr_hints {
+                    return Err(Error::InvalidState);
+                }
 
-        let flushable = stream.is_flushable();
+                // Frames can be reordered or retransmitted, so only hints
+                // newer than the ones already received are reported.
+                if seq > self.cr_hints_seq {
+                    let hints = CrHints {
+                        lifetime: time::Duration::from_secs(lifetime),
+                        capacity: capacity as usize,
+                        min_rtt: time::Duration::from_micros(min_rtt),
+                        max_jump: max_jump as usize,
+                    };
 
-        let writable = stream.is_writable();
+                    if let Some(handler) = &self.cr_hints_handler {
+                        handler.on_cr_hints(&self.source_id(), &hints);
+                    }
 
-        let empty_fin = buf.is_empty() && fin;
+                    self.cr_hints_seq = seq;
+                    self.peer_cr_hints = Some(hints);
+                }
+            },
+        }
 
-        if sent < buf.len() {
-            let max_off = stream.send.max_off();
+        Ok(())
+    }
 
-            if stream.send.blocked_at() != Some(max_off) {
-                stream.send.update_blocked_at(Some(max_off));
-                self.streams.insert_blocked(stream_id, max_off);
-            }
-        } else {
-            stream.send.update_blocked_at(None);
-            self.streams.remove_blocked(stream_id);
+    /// Drops the keys and recovery state for the given epoch.
+    fn drop_epoch_state(&mut self, epoch: packet::Epoch, now: time::Instant) {
+        if self.pkt_num_spaces[epoch].crypto_open.is_none() {
+            return;
         }
 
-        // If the stream is now flushable push it to the flushable queue, but
-        // only if it wasn't already queued.
-        //
-        // Consider the stream flushable also when we are sending a zero-length
-        // frame that has the fin flag set.
-        if (flushable || empty_fin) && !was_flushable {
-            self.streams.insert_flushable(&priority_key);
-        }
+        self.pkt_num_spaces[epoch].crypto_open = None;
+        self.pkt_num_spaces[epoch].crypto_seal = None;
+        self.pkt_num_spaces[epoch].clear();
 
-        if !writable {
-            self.streams.remove_writable(&priority_key);
-        } else if was_writable && blocked_by_cap {
-            // When `stream_writable_next()` returns a stream, the writable
-            // mark is removed, but because the stream is blocked by the
-            // connection-level send capacity it won't be marked as writable
-            // again once the capacity increases.
-            //
-            // Since the stream is writable already, mark it here instead.
-            self.streams.insert_writable(&priority_key);
+        let handshake_status = self.handshake_status();
+        for (_, p) in self.paths.iter_mut() {
+            p.recovery
+                .on_pkt_num_space_discarded(epoch, handshake_status, now);
         }
 
-        self.tx_cap -= sent;
-
-        self.tx_data += sent as u64;
+        trace!("{} dropped epoch {} state", self.trace_id, epoch);
+    }
 
-        self.tx_buffered += sent;
+    /// Returns true if the connection-level flow control needs to be updated.
+    ///
+    /// This happens when the new max data limit is at least double the amount
+    /// of data that can be received before blocking.
+    fn should_update_max_data(&self) -> bool {
+        self.flow_control.should_update_max_data()
+    }
 
-        qlog_with_type!(QLOG_DATA_MV, self.qlog, q, {
-            let ev_data = EventData::DataMoved(qlog::events::quic::DataMoved {
-                stream_id: Some(stream_id),
-                offset: Some(offset),
-                length: Some(sent as u64),
-                from: Some(DataRecipient::Application),
-                to: Some(DataRecipient::Transport),
-                raw: None,
-            });
+    /// Returns the connection level flow control limit.
+    fn max_rx_data(&self) -> u64 {
+        self.flow_control.max_data()
+    }
 
-            let now = time::Instant::now();
-            q.add_event_data_with_instant(ev_data, now).ok();
-        });
+    /// Returns true if the HANDSHAKE_DONE frame needs to be sent.
+    fn should_send_handshake_done(&self) -> bool {
+        self.is_established() && !self.handshake_done_sent && self.is_server
+    }
 
-        if sent == 0 && !buf.is_empty() {
-            return Err(Error::Done);
+    /// Returns the idle timeout value.
+    ///
+    /// `None` is returned if both end-points disabled the idle timeout.
+    fn idle_timeout(&mut self) -> Option<time::Duration> {
+        // If the transport parameter is set to 0, then the respective endpoint
+        // decided to disable the idle timeout. If both are disabled we should
+        // not set any timeout.
+        if self.local_transport_params.max_idle_timeout == 0 &&
+            self.peer_transport_params.max_idle_timeout == 0
+        {
+            return None;
         }
 
-        if incremental && writable {
-            // Shuffle the incremental stream to the back of the queue.
-            self.streams.remove_writable(&priority_key);
-            self.streams.insert_writable(&priority_key);
-        }
+        // If the local endpoint or the peer disabled the idle timeout, use the
+        // other peer's value, otherwise use the minimum of the two values.
+        let idle_timeout = if self.local_transport_params.max_idle_timeout == 0 {
+            self.peer_transport_params.max_idle_timeout
+        } else if self.peer_transport_params.max_idle_timeout == 0 {
+            self.local_transport_params.max_idle_timeout
+        } else {
+            cmp::min(
+                self.local_transport_params.max_idle_timeout,
+                self.peer_transport_params.max_idle_timeout,
+            )
+        };
 
-        Ok(sent)
-    }
+        let path_pto = match self.paths.get_active() {
+            Ok(p) => p.recovery.pto(),
+            Err(_) => time::Duration::ZERO,
+        };
 
-    /// Sets the priority for a stream.
-    ///
-    /// A stream's priority determines the order in which stream data is sent
-    /// on the wire (streams with lower priority are sent first). Streams are
-    /// created with a default priority of `127`.
-    ///
-    /// The target stream is created if it did not exist before calling this
-    /// method.
-    pub fn stream_priority(
-        &mut self, stream_id: u64, urgency: u8, incremental: bool,
-    ) -> Result<()> {
-        // Get existing stream or create a new one, but if the stream
-        // has already been closed and collected, ignore the prioritization.
-        let stream = match self.get_or_create_stream(stream_id, true) {
-            Ok(v) => v,
+        let idle_timeout = time::Duration::from_millis(idle_timeout);
+        let idle_timeout = cmp::max(idle_timeout, 3 * path_pto);
 
-            Err(Error::Done) => return Ok(()),
+        Some(idle_timeout)
+    }
 
-            Err(e) => return Err(e),
-        };
+    /// Returns the connection's handshake status for use in loss recovery.
+    fn handshake_status(&self) -> recovery::HandshakeStatus {
+        recovery::HandshakeStatus {
+            has_handshake_keys: self.pkt_num_spaces[packet::Epoch::Handshake]
+                .has_keys(),
 
-        if stream.urgency == urgency && stream.incremental == incremental {
-            return Ok(());
-        }
+            peer_verified_address: self.peer_verified_initial_address,
 
-        stream.urgency = urgency;
-        stream.incremental = incremental;
+            completed: self.is_established(),
+        }
+    }
 
-        let new_priority_key = Arc::new(StreamPriorityKey {
-            urgency: stream.urgency,
-            incremental: stream.incremental,
-            id: stream_id,
-            ..Default::default()
-        });
+    /// Updates send capacity.
+    fn update_tx_cap(&mut self) {
+        let cwin_available = match self.paths.get_active() {
+            Ok(p) => p.recovery.cwnd_available() as u64,
+            Err(_) => 0,
+        };
 
-        let old_priority_key =
-            std::mem::replace(&mut stream.priority_key, new_priority_key.clone());
+        self.tx_cap =
+            cmp::min(cwin_available, self.max_tx_data - self.tx_data) as usize;
+    }
 
-        self.streams
-            .update_priority(&old_priority_key, &new_priority_key);
+    fn delivery_rate_check_if_app_limited(&self) -> bool {
+        // Enter the app-limited phase of delivery rate when these conditions
+        // are met:
+        //
+        // - The remaining capacity is higher than available bytes in cwnd (there
+        //   is more room to send).
+        // - New data since the last send() is smaller than available bytes in
+        //   cwnd (we queued less than what we can send).
+        // - There is room to send more data in cwnd.
+        //
+        // In application-limited phases the transmission rate is limited by the
+        // application rather than the congestion control algorithm.
+        //
+        // Note that this is equivalent to CheckIfApplicationLimited() from the
+        // delivery rate draft. This is also separate from `recovery.app_limited`
+        // and only applies to delivery rate calculation.
+        let cwin_available = self
+            .paths
+            .iter()
+            .filter(|&(_, p)| p.active())
+            .map(|(_, p)| p.recovery.cwnd_available())
+            .sum();
 
-        Ok(())
+        ((self.tx_buffered + self.dgram_send_queue_byte_size()) < cwin_available) &&
+            (self.tx_data.saturating_sub(self.last_tx_data)) <
+                cwin_available as u64 &&
+            cwin_available > 0
     }
 
-    /// Sets the data flow credit limit for a stream. This will not reduce a flow credit to lower
-    /// than it already is. Causes a `MAX_STREAM_DATA` frame to be sent.
-    pub fn stream_max_data(
-        &mut self, stream_id: u64, max_data: u64
+    fn set_initial_dcid(
+        &mut self, cid: ConnectionId<'static>, reset_token: Option<u128>,
+        path_id: usize,
     ) -> Result<()> {
-        let stream = match self.get_or_create_stream(stream_id, true) {
-            Ok(v) => v,
-            Err(Error::Done) => return Ok(()),
-            Err(e) => return Err(e),
-        };
-
-        stream.recv.set_max_data(max_data, time::Instant::now());
-        self.streams.insert_force_update(stream_id);
+        self.ids.set_initial_dcid(cid, reset_token, Some(path_id));
+        self.paths.get_mut(path_id)?.active_dcid_seq = Some(0);
 
         Ok(())
     }
 
-    /// Shuts down reading or writing from/to the specified stream.
-    ///
-    /// When the `direction` argument is set to [`Shutdown::Read`], outstanding
-    /// data in the stream's receive buffer is dropped, and no additional data
-    /// is added to it. Data received after calling this method is still
-    /// validated and acked but not stored, and [`stream_recv()`] will not
-    /// return it to the application. In addition, a `STOP_SENDING` frame will
-    /// be sent to the peer to signal it to stop sending data.
-    ///
-    /// When the `direction` argument is set to [`Shutdown::Write`], outstanding
-    /// data in the stream's send buffer is dropped, and no additional data is
-    /// added to it. Data passed to [`stream_send()`] after calling this method
-    /// will be ignored. In addition, a `RESET_STREAM` frame will be sent to the
-    /// peer to signal the reset.
-    ///
-    /// Locally-initiated unidirectional streams can only be closed in the
-    /// [`Shutdown::Write`] direction. Remotely-initiated unidirectional streams
-    /// can only be closed in the [`Shutdown::Read`] direction. Using an
-    /// incorrect direction will return [`InvalidStreamState`].
-    ///
-    /// [`Shutdown::Read`]: enum.Shutdown.html#variant.Read
-    /// [`Shutdown::Write`]: enum.Shutdown.html#variant.Write
-    /// [`stream_recv()`]: struct.Connection.html#method.stream_recv
-    /// [`stream_send()`]: struct.Connection.html#method.stream_send
-    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
-    pub fn stream_shutdown(
-        &mut self, stream_id: u64, direction: Shutdown, err: u64,
-    ) -> Result<()> {
-        // Don't try to stop a local unidirectional stream.
-        if direction == Shutdown::Read &&
-            stream::is_local(stream_id, self.is_server) &&
-            !stream::is_bidi(stream_id)
-        {
-            return Err(Error::InvalidStreamState(stream_id));
-        }
+    /// Selects the path that the incoming packet belongs to, or creates a new
+    /// one if no existing path matches.
+    fn get_or_create_recv_path_id(
+        &mut self, recv_pid: Option<usize>, dcid: &ConnectionId, buf_len: usize,
+        info: &RecvInfo,
+    ) -> Result<usize> {
+        let ids = &mut self.ids;
 
-        // Dont' try to reset a remote unidirectional stream.
-        if direction == Shutdown::Write &&
-            !stream::is_local(stream_id, self.is_server) &&
-            !stream::is_bidi(stream_id)
-        {
-            return Err(Error::InvalidStreamState(stream_id));
-        }
+        let (in_scid_seq, mut in_scid_pid) =
+            ids.find_scid_seq(dcid).ok_or(Error::InvalidState)?;
 
-        // Get existing stream.
-        let stream = self.streams.get_mut(stream_id).ok_or(Error::Done)?;
+        if let Some(recv_pid) = recv_pid {
+            // If the path observes a change of SCID used, note it.
+            let recv_path = self.paths.get_mut(recv_pid)?;
 
-        let priority_key = Arc::clone(&stream.priority_key);
+            let cid_entry =
+                recv_path.active_scid_seq.and_then(|v| ids.get_scid(v).ok());
 
-        match direction {
-            Shutdown::Read => {
-                stream.recv.shutdown()?;
+            if cid_entry.map(|e| &e.cid) != Some(dcid) {
+                let incoming_cid_entry = ids.get_scid(in_scid_seq)?;
 
-                if !stream.recv.is_fin() {
-                    self.streams.insert_stopped(stream_id, err);
+                let prev_recv_pid =
+                    incoming_cid_entry.path_id.unwrap_or(recv_pid);
+
+                if prev_recv_pid != recv_pid {
+                    trace!(
+                        "{} peer reused CID {:?} from path {} on path {}",
+                        self.trace_id,
+                        dcid,
+                        prev_recv_pid,
+                        recv_pid
+                    );
+
+                    // TODO: reset congestion control.
                 }
 
-                // Once shutdown, the stream is guaranteed to be non-readable.
-                self.streams.remove_readable(&priority_key);
+                trace!(
+                    "{} path ID {} now see SCID with seq num {}",
+                    self.trace_id,
+                    recv_pid,
+                    in_scid_seq
+                );
 
-                self.stopped_stream_local_count =
-                    self.stopped_stream_local_count.saturating_add(1);
-            },
+                recv_path.active_scid_seq = Some(in_scid_seq);
+                ids.link_scid_to_path_id(in_scid_seq, recv_pid)?;
+            }
 
-            Shutdown::Write => {
-                let (final_size, unsent) = stream.send.shutdown()?;
+            return Ok(recv_pid);
+        }
 
-                // Claw back some flow control allowance from data that was
-                // buffered but not actually sent before the stream was reset.
-                self.tx_data = self.tx_data.saturating_sub(unsent);
+        // This is a new 4-tuple. See if the CID has not been assigned on
+        // another path.
 
-                self.tx_buffered =
-                    self.tx_buffered.saturating_sub(unsent as usize);
+        // Ignore this step if are using zero-length SCID.
+        if ids.zero_length_scid() {
+            in_scid_pid = None;
+        }
 
-                // Update send capacity.
-                self.update_tx_cap();
+        if let Some(in_scid_pid) = in_scid_pid {
+            // This CID has been used by another path. If we have the
+            // room to do so, create a new `Path` structure holding this
+            // new 4-tuple. Otherwise, drop the packet.
+            let old_path = self.paths.get_mut(in_scid_pid)?;
+            let old_local_addr = old_path.local_addr();
+            let old_peer_addr = old_path.peer_addr();
 
-                self.streams.insert_reset(stream_id, err, final_size);
+            trace!(
+                "{} reused CID seq {} of ({},{}) (path {}) on ({},{})",
+                self.trace_id,
+                in_scid_seq,
+                old_local_addr,
+                old_peer_addr,
+                in_scid_pid,
+                info.to,
+                info.from
+            );
 
-                // Once shutdown, the stream is guaranteed to be non-writable.
-                self.streams.remove_writable(&priority_key);
+            // Notify the application.
+            self.paths
+                .notify_event(path::PathEvent::ReusedSourceConnectionId(
+                    in_scid_seq,
+                    (old_local_addr, old_peer_addr),
+                    (info.to, info.from),
+                ));
+        }
 
-                self.reset_stream_local_count =
-                    self.reset_stream_local_count.saturating_add(1);
-            },
+        // This is a new path using an unassigned CID; create it!
+        let mut path = path::Path::new(
+            info.to,
+            info.from,
+            &self.trace_id,
+            &self.recovery_config,
+            self.path_challenge_recv_max_queue_len,
+            MIN_CLIENT_INITIAL_LEN,
+            false,
+        );
+
+        path.max_send_bytes = buf_len * self.max_amplification_factor;
+        path.active_scid_seq = Some(in_scid_seq);
+
+        // Automatically probes the new path.
+        path.request_validation();
+
+        let pid = self.paths.insert_path(path, self.is_server)?;
+
+        // Do not record path reuse.
+        if in_scid_pid.is_none() {
+            ids.link_scid_to_path_id(in_scid_seq, pid)?;
         }
 
-        Ok(())
+        Ok(pid)
     }
 
-    /// Returns the stream's send capacity in bytes.
-    ///
-    /// If the specified stream doesn't exist (including when it has already
-    /// been completed and closed), the [`InvalidStreamState`] error will be
-    /// returned.
-    ///
-    /// In addition, if the peer has signalled that it doesn't want to receive
-    /// any more data from this stream by sending the `STOP_SENDING` frame, the
-    /// [`StreamStopped`] error will be returned.
-    ///
-    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
-    /// [`StreamStopped`]: enum.Error.html#variant.StreamStopped
-    #[inline]
-    pub fn stream_capacity(&self, stream_id: u64) -> Result<usize> {
-        if let Some(stream) = self.streams.get(stream_id) {
-            let cap = cmp::min(self.tx_cap, stream.send.cap()?);
-            return Ok(cap);
-        };
+    /// Selects the path on which the next packet must be sent.
+    fn get_send_path_id(
+        &self, from: Option<SocketAddr>, to: Option<SocketAddr>,
+    ) -> Result<usize> {
+        // A probing packet must be sent, but only if the connection is fully
+        // established.
+        if self.is_established() {
+            let mut probing = self
+                .paths
+                .iter()
+                .filter(|(_, p)| from.is_none() || Some(p.local_addr()) == from)
+                .filter(|(_, p)| to.is_none() || Some(p.peer_addr()) == to)
+                .filter(|(_, p)| p.active_dcid_seq.is_some())
+                .filter(|(_, p)| p.probing_required())
+                .map(|(pid, _)| pid);
 
-        Err(Error::InvalidStreamState(stream_id))
-    }
+            if let Some(pid) = probing.next() {
+                return Ok(pid);
+            }
+        }
 
-    /// Returns the next stream that has data to read.
-    ///
-    /// Note that once returned by this method, a stream ID will not be returned
-    /// again until it is "re-armed".
-    ///
-    /// The application will need to read all of the pending data on the stream,
-    /// and new data has to be received before the stream is reported again.
-    ///
-    /// This is unlike the [`readable()`] method, that returns the same list of
-    /// readable streams when called multiple times in succession.
-    ///
-    /// [`readable()`]: struct.Connection.html#method.readable
-    pub fn stream_readable_next(&mut self) -> Option<u64> {
-        let priority_key = self.streams.readable.front().clone_pointer()?;
+        if let Some((pid, p)) = self.paths.get_active_with_pid() {
+            if from.is_some() && Some(p.local_addr()) != from {
+                return Err(Error::Done);
+            }
 
-        self.streams.remove_readable(&priority_key);
+            if to.is_some() && Some(p.peer_addr()) != to {
+                return Err(Error::Done);
+            }
+
+            return Ok(pid);
+        };
 
-        Some(priority_key.id)
+        Err(Error::InvalidState)
     }
 
-    /// Returns true if the stream has data that can be read.
-    pub fn stream_readable(&self, stream_id: u64) -> bool {
-        let stream = match self.streams.get(stream_id) {
-            Some(v) => v,
+    /// Sets the path with identifier 'path_id' to be active.
+    fn set_active_path(
+        &mut self, path_id: usize, now: time::Instant,
+    ) -> Result<()> {
+        if let Ok(old_active_path) = self.paths.get_active_mut() {
+            for &e in packet::Epoch::epochs(
+                packet::Epoch::Initial..=packet::Epoch::Application,
+            ) {
+                let (lost_packets, lost_bytes) = old_active_path
+                    .recovery
+                    .on_path_change(e, now, &self.trace_id);
 
-            None => return false,
-        };
+                self.lost_count += lost_packets;
+                self.lost_bytes += lost_bytes as u64;
 
-        stream.is_readable()
-    }
+                qlog_with_type!(QLOG_PACKET_LOST, self.qlog, q, {
+                    for ev_data in old_active_path.recovery.packet_loss_qlog() {
+                        self.qlog.stats.add_event_data(q, ev_data, now);
+                    }
+                });
+            }
 
-    /// Returns the next stream that can be written to.
-    ///
-    /// Note that once returned by this method, a stream ID will not be returned
-    /// again until it is "re-armed".
-    ///
-    /// This is unlike the [`writable()`] method, that returns the same list of
-    /// writable streams when called multiple times in succession. It is not
-    /// advised to use both `stream_writable_next()` and [`writable()`] on the
-    /// same connection, as it may lead to unexpected results.
-    ///
-    /// The [`stream_writable()`] method can also be used to fine-tune when a
-    /// stream is reported as writable again.
-    ///
-    /// [`stream_writable()`]: struct.Connection.html#method.stream_writable
-    /// [`writable()`]: struct.Connection.html#method.writable
-    pub fn stream_writable_next(&mut self) -> Option<u64> {
-        // If there is not enough connection-level send capacity, none of the
-        // streams are writable.
-        if self.tx_cap == 0 {
-            return None;
+            // Careful resume parameters don't apply to the new path.
+            old_active_path.recovery.on_cr_path_change(now);
+
+            qlog_with_type!(QLOG_CR_PHASE, self.qlog, q, {
+                if let Some(ev_data) = old_active_path.recovery.maybe_cr_qlog() {
+                    self.qlog.stats.add_event_data(q, ev_data, now);
+                }
+            });
         }
 
-        let mut cursor = self.streams.writable.front();
+        self.paths.set_active_path(path_id)
+    }
 
-        while let Some(priority_key) = cursor.clone_pointer() {
-            if let Some(stream) = self.streams.get(priority_key.id) {
-                let cap = match stream.send.cap() {
-                    Ok(v) => v,
+    /// Handles potential connection migration.
+    fn on_peer_migrated(
+        &mut self, new_pid: usize, disable_dcid_reuse: bool, now: time::Instant,
+    ) -> Result<()> {
+        let active_path_id = self.paths.get_active_path_id()?;
 
-                    // Return the stream to the application immediately if it's
-                    // stopped.
-                    Err(_) =>
-                        return {
-                            self.streams.remove_writable(&priority_key);
+        if active_path_id == new_pid {
+            return Ok(());
+        }
 
-                            Some(priority_key.id)
-                        },
-                };
+        self.set_active_path(new_pid, now)?;
 
-                if cmp::min(self.tx_cap, cap) >= stream.send_lowat {
-                    self.streams.remove_writable(&priority_key);
-                    return Some(priority_key.id);
-                }
-            }
+        let no_spare_dcid =
+            self.paths.get_mut(new_pid)?.active_dcid_seq.is_none();
 
-            cursor.move_next();
+        if no_spare_dcid && !disable_dcid_reuse {
+            self.paths.get_mut(new_pid)?.active_dcid_seq =
+                self.paths.get_mut(active_path_id)?.active_dcid_seq;
         }
 
-        None
+        Ok(())
     }
 
-    /// Returns true if the stream has enough send capacity.
-    ///
-    /// When `len` more bytes can be buffered into the given stream's send
-    /// buffer, `true` will be returned, `false` otherwise.
-    ///
-    /// In the latter case, if the additional data can't be buffered due to
-    /// flow control limits, the peer will also be notified, and a "low send
-    /// watermark" will be set for the stream, such that it is not going to be
-    /// reported as writable again by [`stream_writable_next()`] until its send
-    /// capacity reaches `len`.
-    ///
-    /// If the specified stream doesn't exist (including when it has already
-    /// been completed and closed), the [`InvalidStreamState`] error will be
-    /// returned.
-    ///
-    /// In addition, if the peer has signalled that it doesn't want to receive
-    /// any more data from this stream by sending the `STOP_SENDING` frame, the
-    /// [`StreamStopped`] error will be returned.
-    ///
-    /// [`stream_writable_next()`]: struct.Connection.html#method.stream_writable_next
-    /// [`InvalidStreamState`]: enum.Error.html#variant.InvalidStreamState
-    /// [`StreamStopped`]: enum.Error.html#variant.StreamStopped
-    #[inline]
-    pub fn stream_writable(
-        &mut self, stream_id: u64, len: usize,
-    ) -> Result<bool> {
-        if self.stream_capacity(stream_id)? >= len {
-            return Ok(true);
+    /// Creates a new client-side path.
+    fn create_path_on_client(
+        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
+    ) -> Result<usize> {
+        if self.is_server {
+            return Err(Error::InvalidState);
         }
 
-        let stream = match self.streams.get_mut(stream_id) {
-            Some(v) => v,
+        // If we use zero-length SCID and go over our local active CID limit,
+        // the `insert_path()` call will raise an error.
+        if !self.ids.zero_length_scid() && self.ids.available_scids() == 0 {
+            return Err(Error::OutOfIdentifiers);
+        }
 
-            None => return Err(Error::InvalidStreamState(stream_id)),
+        // Do we have a spare DCID? If we are using zero-length DCID, just use
+        // the default having sequence 0 (note that if we exceed our local CID
+        // limit, the `insert_path()` call will raise an error.
+        let dcid_seq = if self.ids.zero_length_dcid() {
+            0
+        } else {
+            self.ids
+                .lowest_available_dcid_seq()
+                .ok_or(Error::OutOfIdentifiers)?
         };
 
-        stream.send_lowat = cmp::max(1, len);
+        let mut path = path::Path::new(
+            local_addr,
+            peer_addr,
+            &self.trace_id,
+            &self.recovery_config,
+            self.path_challenge_recv_max_queue_len,
+            MIN_CLIENT_INITIAL_LEN,
+            false,
+        );
+        path.active_dcid_seq = Some(dcid_seq);
 
-        let is_writable = stream.is_writable();
+        let pid = self
+            .paths
+            .insert_path(path, false)
+            .map_err(|_| Error::OutOfIdentifiers)?;
+        self.ids.link_dcid_to_path_id(dcid_seq, pid)?;
 
-        let priority_key = Arc::clone(&stream.priority_key);
+        Ok(pid)
+    }
 
-        if self.max_tx_data - self.tx_data < len as u64 {
-            self.blocked_limit = Some(self.max_tx_data);
-        }
+    // Marks the connection as closed and does any related tidyup.
+    fn mark_closed(&mut self) {
+        // Covers the idle timeout and stateless resets, where the connection
+        // closes without draining.
+        self.final_cr_event(time::Instant::now());
 
-        if stream.send.cap()? < len {
-            let max_off = stream.send.max_off();
-            if stream.send.blocked_at() != Some(max_off) {
-                stream.send.update_blocked_at(Some(max_off));
-                self.streams.insert_blocked(stream_id, max_off);
-            }
-        } else if is_writable {
-            // When `stream_writable_next()` returns a stream, the writable
-            // mark is removed, but because the stream is blocked by the
-            // connection-level send capacity it won't be marked as writable
-            // again once the capacity increases.
-            //
-            // Since the stream is writable already, mark it here instead.
-            self.streams.insert_writable(&priority_key);
-        }
+        #[cfg(feature = "qlog")]
+        {
+            let cc = match (self.is_established(), self.timed_out, &self.peer_error, &self.local_error) {
+                (false, _, _, _) => qlog::events::connectivity::ConnectionClosed {
+                    owner: Some(TransportOwner::Local),
+                    connection_code: None,
+                    application_code: None,
+                    internal_code: None,
+                    reason: Some("Failed to establish connection".to_string()),
+                    trigger: Some(qlog::events::connectivity::ConnectionClosedTrigger::HandshakeTimeout)
+                },
 
-        Ok(false)
-    }
+                (true, true, _, _) => qlog::events::connectivity::ConnectionClosed {
+                    owner: Some(TransportOwner::Local),
+                    connection_code: None,
+                    application_code: None,
+                    internal_code: None,
+                    reason: Some("Idle timeout".to_string()),
+                    trigger: Some(qlog::events::connectivity::ConnectionClosedTrigger::IdleTimeout)
+                },
 
-    /// Returns true if all the data has been read from the specified stream.
-    ///
-    /// This instructs the application that all the data received from the
-    /// peer on the stream has been read, and there won't be anymore in the
-    /// future.
-    ///
-    /// Basically this returns true when the peer either set the `fin` flag
-    /// for the stream, or sent `RESET_STREAM`.
-    #[inline]
-    pub fn stream_finished(&self, stream_id: u64) -> bool {
-        let stream = match self.streams.get(stream_id) {
-            Some(v) => v,
+                (true, false, Some(peer_error), None) => {
+                    let (connection_code, application_code) = if peer_error.is_app {
+                        (None, Some(qlog::events::ApplicationErrorCode::Value(peer_error.error_code)))
+                    } else {
+                        (Some(qlog::events::ConnectionErrorCode::Value(peer_error.error_code)), None)
+                    };
 
-            None => return true,
-        };
+                    qlog::events::connectivity::ConnectionClosed {
+                        owner: Some(TransportOwner::Remote),
+                        connection_code,
+                        application_code,
+                        internal_code: None,
+                        reason: Some(String::from_utf8_lossy(&peer_error.reason).to_string()),
+                        trigger: Some(qlog::events::connectivity::ConnectionClosedTrigger::Error),
+                    }
+                },
+
+                (true, false, None, Some(local_error)) => {
+                    let (connection_code, application_code) = if local_error.is_app {
+                        (None, Some(qlog::events::ApplicationErrorCode::Value(local_error.error_code)))
+                    } else {
+                        (Some(qlog::events::ConnectionErrorCode::Value(local_error.error_code)), None)
+                    };
+
+                    qlog::events::connectivity::ConnectionClosed {
+                        owner: Some(TransportOwner::Local),
+                        connection_code,
+                        application_code,
+                        internal_code: None,
+                        reason: Some(String::from_utf8_lossy(&local_error.reason).to_string()),
+                        trigger: Some(qlog::events::connectivity::ConnectionClosedTrigger::Error),
+                    }
+                },
+
+                _ => qlog::events::connectivity::ConnectionClosed {
+                    owner: None,
+                    connection_code: None,
+                    application_code: None,
+                    internal_code: None,
+                    reason: None,
+                    trigger: None,
+                },
+            };
 
-        stream.recv.is_fin()
-    }
+            qlog_with_type!(QLOG_CONNECTION_CLOSED, self.qlog, q, {
+                let ev_data = qlog::events::EventData::ConnectionClosed(cc);
 
-    /// Returns the number of bidirectional streams that can be created
-    /// before the peer's stream count limit is reached.
-    ///
-    /// This can be useful to know if it's possible to create a bidirectional
-    /// stream without trying it first.
-    #[inline]
-    pub fn peer_streams_left_bidi(&self) -> u64 {
-        self.streams.peer_streams_left_bidi()
+                q.add_event_data_now(ev_data).ok();
+            });
+            self.qlog.streamer = None;
+        }
+        self.closed = true;
     }
 
-    /// Returns the number of unidirectional streams that can be created
-    /// before the peer's stream count limit is reached.
+    /// Returns the key of the parameters observed on a path to `peer`.
     ///
-    /// This can be useful to know if it's possible to create a unidirectional
-    /// stream without trying it first.
-    #[inline]
-    pub fn peer_streams_left_uni(&self) -> u64 {
-        self.streams.peer_streams_left_uni()
+    /// Parameters are properties of the path, so a path the connection
+    /// migrated to is keyed by its own peer address, rather than by the one
+    /// the connection started with.
+    fn cr_store_key(
+        &self, server_name: Option<&str>, peer: SocketAddr,
+    ) -> String {
+        if self.is_server {
+            cr_store::server_key(peer.ip())
+        } else if let Some(origin) = &self.cr_origin {
+            cr_store::origin_key(origin, peer.ip())
+        } else {
+            cr_store::client_key(server_name, peer.ip())
+        }
     }
 
-    /// Returns an iterator over streams that have outstanding data to read.
-    ///
-    /// Note that the iterator will only include streams that were readable at
-    /// the time the iterator itself was created (i.e. when `readable()` was
-    /// called). To account for newly readable streams, the iterator needs to
-    /// be created again.
-    ///
-    /// ## Examples:
+    /// Sets up careful resume on the initial path with the parameters stored
+    /// for its peer, and stores the parameters observed from now on.
     ///
-    /// ```no_run
-    /// # let mut buf = [0; 512];
-    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
-    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
-    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let peer = "127.0.0.1:1234".parse().unwrap();
-    /// # let local = socket.local_addr().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
-    /// // Iterate over readable streams.
-    /// for stream_id in conn.readable() {
-    ///     // Stream is readable, read until there's no more data.
-    ///     while let Ok((read, fin)) = conn.stream_recv(stream_id, &mut buf) {
-    ///         println!("Got {} bytes on stream {}", read, stream_id);
-    ///     }
-    /// }
-    /// # Ok::<(), quiche::Error>(())
-    /// ```
-    #[inline]
-    pub fn readable(&self) -> StreamIter {
-        self.streams.readable()
-    }
+    /// Returns whether parameters were stored for the peer.
+    fn restore_cr_params(
+        &mut self, store: Arc<dyn CrStore>, server_name: Option<String>,
+    ) -> bool {
+        let peer = match self.paths.get_active() {
+            Ok(p) => p.peer_addr(),
 
-    /// Returns an iterator over streams that can be written in priority order.
-    ///
-    /// The priority order is based on RFC 9218 scheduling recommendations.
-    /// Stream priority can be controlled using [`stream_priority()`]. In order
-    /// to support fairness requirements, each time this method is called,
-    /// internal state is updated. Therefore the iterator ordering can change
-    /// between calls, even if no streams were added or removed.
-    ///
-    /// A "writable" stream is a stream that has enough flow control capacity to
-    /// send data to the peer. To avoid buffering an infinite amount of data,
-    /// streams are only allowed to buffer outgoing data up to the amount that
-    /// the peer allows to send.
-    ///
-    /// Note that the iterator will only include streams that were writable at
-    /// the time the iterator itself was created (i.e. when `writable()` was
-    /// called). To account for newly writable streams, the iterator needs to be
-    /// created again.
-    ///
-    /// ## Examples:
-    ///
-    /// ```no_run
-    /// # let mut buf = [0; 512];
-    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
-    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
-    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let local = socket.local_addr().unwrap();
-    /// # let peer = "127.0.0.1:1234".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
-    /// // Iterate over writable streams.
-    /// for stream_id in conn.writable() {
-    ///     // Stream is writable, write some data.
-    ///     if let Ok(written) = conn.stream_send(stream_id, &buf, false) {
-    ///         println!("Written {} bytes on stream {}", written, stream_id);
-    ///     }
-    /// }
-    /// # Ok::<(), quiche::Error>(())
-    /// ```
-    /// [`stream_priority()`]: struct.Connection.html#method.stream_priority
-    #[inline]
-    pub fn writable(&self) -> StreamIter {
-        // If there is not enough connection-level send capacity, none of the
-        // streams are writable, so return an empty iterator.
-        if self.tx_cap == 0 {
-            return StreamIter::default();
-        }
+            Err(_) => return false,
+        };
 
-        self.streams.writable()
-    }
+        let key = self.cr_store_key(server_name.as_deref(), peer);
 
-    /// Returns the maximum possible size of egress UDP payloads.
-    ///
-    /// This is the maximum size of UDP payloads that can be sent, and depends
-    /// on both the configured maximum send payload size of the local endpoint
-    /// (as configured with [`set_max_send_udp_payload_size()`]), as well as
-    /// the transport parameter advertised by the remote peer.
-    ///
-    /// Note that this value can change during the lifetime of the connection,
-    /// but should remain stable across consecutive calls to [`send()`].
-    ///
-    /// [`set_max_send_udp_payload_size()`]:
-    ///     struct.Config.html#method.set_max_send_udp_payload_size
-    /// [`send()`]: struct.Connection.html#method.send
-    pub fn max_send_udp_payload_size(&self) -> usize {
-        let max_datagram_size = self
-            .paths
-            .get_active()
-            .ok()
-            .map(|p| p.recovery.max_datagram_size());
+        let params = match &self.cr_origin {
+            Some(origin) => store.get_in_origin(origin, &key),
 
-        if let Some(max_datagram_size) = max_datagram_size {
-            if self.is_established() {
-                // We cap the maximum packet size to 16KB or so, so that it can be
-                // always encoded with a 2-byte varint.
-                return cmp::min(16383, max_datagram_size);
-            }
-        }
+            None => store.get(&key),
+        };
 
-        // Allow for 1200 bytes (minimum QUIC packet size) during the
-        // handshake.
-        MIN_CLIENT_INITIAL_LEN
-    }
+        if let Some(params) = params {
+            trace!(
+                "{} restored careful resume parameters for {} min_rtt={:?} cwnd={}",
+                self.trace_id,
+                key,
+                params.min_rtt,
+                params.cwnd
+            );
 
-    /// Schedule an ack-eliciting packet on the active path.
-    ///
-    /// QUIC packets might not contain ack-eliciting frames during normal
-    /// operating conditions. If the packet would already contain
-    /// ack-eliciting frames, this method does not change any behavior.
-    /// However, if the packet would not ordinarily contain ack-eliciting
-    /// frames, this method ensures that a PING frame sent.
-    ///
-    /// Calling this method multiple times before [`send()`] has no effect.
-    ///
-    /// [`send()`]: struct.Connection.html#method.send
-    pub fn send_ack_eliciting(&mut self) -> Result<()> {
-        if self.is_closed() || self.is_draining() {
-            return Ok(());
+            self.setup_cr_params(params, CrParamsSource::Store);
         }
-        self.paths.get_active_mut()?.needs_ack_eliciting = true;
-        Ok(())
-    }
 
-    /// Schedule an ack-eliciting packet on the specified path.
-    ///
-    /// See [`send_ack_eliciting()`] for more detail. [`InvalidState`] is
-    /// returned if there is no record of the path.
-    ///
-    /// [`send_ack_eliciting()`]: struct.Connection.html#method.send_ack_eliciting
-    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
-    pub fn send_ack_eliciting_on_path(
-        &mut self, local: SocketAddr, peer: SocketAddr,
-    ) -> Result<()> {
-        if self.is_closed() || self.is_draining() {
-            return Ok(());
-        }
-        let path_id = self
-            .paths
-            .path_id_from_addrs(&(local, peer))
-            .ok_or(Error::InvalidState)?;
-        self.paths.get_mut(path_id)?.needs_ack_eliciting = true;
-        Ok(())
+        self.cr_store = Some((store, server_name));
+
+        params.is_some()
     }
 
-    /// Reads the first received DATAGRAM.
-    ///
-    /// On success the DATAGRAM's data is returned along with its size.
-    ///
-    /// [`Done`] is returned if there is no data to read.
-    ///
-    /// [`BufferTooShort`] is returned if the provided buffer is too small for
-    /// the DATAGRAM.
-    ///
-    /// [`Done`]: enum.Error.html#variant.Done
-    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
-    ///
-    /// ## Examples:
-    ///
-    /// ```no_run
-    /// # let mut buf = [0; 512];
-    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
-    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
-    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let peer = "127.0.0.1:1234".parse().unwrap();
-    /// # let local = socket.local_addr().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
-    /// let mut dgram_buf = [0; 512];
-    /// while let Ok((len)) = conn.dgram_recv(&mut dgram_buf) {
-    ///     println!("Got {} bytes of DATAGRAM", len);
-    /// }
-    /// # Ok::<(), quiche::Error>(())
-    /// ```
-    #[inline]
-    pub fn dgram_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-        match self.dgram_recv_queue.pop() {
-            Some(d) => {
-                if d.len() > buf.len() {
-                    return Err(Error::BufferTooShort);
-                }
+    /// Counts the connection on the path of the initial peer in the shared
+    /// parameters cache, and sets up careful resume with its share of the
+    /// path when `restored` is false.
+    fn join_cr_path_cache(&mut self, cache: Arc<CrPathCache>, restored: bool) {
+        let peer = match self.paths.get_active() {
+            Ok(p) => p.peer_addr(),
 
-                buf[..d.len()].copy_from_slice(&d);
-                Ok(d.len())
-            },
+            Err(_) => return,
+        };
 
-            None => Err(Error::Done),
-        }
-    }
+        let key = cr_path_cache::key(self.is_server, peer.ip());
 
-    /// Reads the first received DATAGRAM.
-    ///
-    /// This is the same as [`dgram_recv()`] but returns the DATAGRAM as a
-    /// `Vec<u8>` instead of copying into the provided buffer.
-    ///
-    /// [`dgram_recv()`]: struct.Connection.html#method.dgram_recv
-    #[inline]
-    pub fn dgram_recv_vec(&mut self) -> Result<Vec<u8>> {
-        match self.dgram_recv_queue.pop() {
-            Some(d) => Ok(d),
+        if !restored {
+            if let Some(params) = cache.get(&key) {
+                trace!(
+                    "{} shared careful resume parameters for {} min_rtt={:?} cwnd={}",
+                    self.trace_id,
+                    key,
+                    params.min_rtt,
+                    params.cwnd
+                );
 
-            None => Err(Error::Done),
+                self.setup_cr_params(params, CrParamsSource::PathCache);
+            }
         }
-    }
 
-    /// Reads the first received DATAGRAM without removing it from the queue.
-    ///
-    /// On success the DATAGRAM's data is returned along with the actual number
-    /// of bytes peeked. The requested length cannot exceed the DATAGRAM's
-    /// actual length.
-    ///
-    /// [`Done`] is returned if there is no data to read.
-    ///
-    /// [`BufferTooShort`] is returned if the provided buffer is smaller the
-    /// number of bytes to peek.
-    ///
-    /// [`Done`]: enum.Error.html#variant.Done
-    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
-    #[inline]
-    pub fn dgram_recv_peek(&self, buf: &mut [u8], len: usize) -> Result<usize> {
-        self.dgram_recv_queue.peek_front_bytes(buf, len)
+        self.cr_path_lease = Some(cache.join(key));
     }
 
-    /// Returns the length of the first stored DATAGRAM.
-    #[inline]
-    pub fn dgram_recv_front_len(&self) -> Option<usize> {
-        self.dgram_recv_queue.peek_front_len()
-    }
+    /// Sets up careful resume with parameters observed on previous
+    /// connections.
+    fn setup_cr_params(&mut self, params: CREvent, source: CrParamsSource) {
+        if self.is_server {
+            if self.recovery_config.resume {
+                self.queue_server_cr_params(params, source);
+            }
+        } else {
+            let cwnd = match self.paths.get_active() {
+                Ok(p) =>
+                    params.cwnd_for_datagram_size(p.recovery.max_datagram_size()),
+
+                Err(_) => params.cwnd,
+            };
 
-    /// Returns the number of items in the DATAGRAM receive queue.
-    #[inline]
-    pub fn dgram_recv_queue_len(&self) -> usize {
-        self.dgram_recv_queue.len()
+            self.setup_careful_resume(params.min_rtt, cwnd, params.pacing_rate)
+                .ok();
+        }
     }
 
-    /// Returns the total size of all items in the DATAGRAM receive queue.
-    #[inline]
-    pub fn dgram_recv_queue_byte_size(&self) -> usize {
-        self.dgram_recv_queue.byte_size()
-    }
+    /// Records the last parameters observed on the active path, once, as the
+    /// connection closes.
+    ///
+    /// Short connections may close before their congestion window changed
+    /// enough to be reported, so the rate limiting of the observe phase is
+    /// skipped.
+    fn final_cr_event(&mut self, now: time::Instant) {
+        if self.cr_final_event_done {
+            return;
+        }
 
-    /// Returns the number of items in the DATAGRAM send queue.
-    #[inline]
-    pub fn dgram_send_queue_len(&self) -> usize {
-        self.dgram_send_queue.len()
-    }
+        self.cr_final_event_done = true;
 
-    /// Returns the total size of all items in the DATAGRAM send queue.
-    #[inline]
-    pub fn dgram_send_queue_byte_size(&self) -> usize {
-        self.dgram_send_queue.byte_size()
-    }
+        let pid = match self.paths.get_active_path_id() {
+            Ok(v) => v,
 
-    /// Returns whether or not the DATAGRAM send queue is full.
-    #[inline]
-    pub fn is_dgram_send_queue_full(&self) -> bool {
-        self.dgram_send_queue.is_full()
-    }
+            Err(_) => return,
+        };
 
-    /// Returns whether or not the DATAGRAM recv queue is full.
-    #[inline]
-    pub fn is_dgram_recv_queue_full(&self) -> bool {
-        self.dgram_recv_queue.is_full()
+        let event = match self.paths.get_mut(pid) {
+            Ok(p) => p.recovery.final_cr_event(now),
+
+            Err(_) => return,
+        };
+
+        if let Some(event) = event {
+            self.update_cr_event(pid, event);
+        }
     }
 
-    /// Sends data in a DATAGRAM frame.
-    ///
-    /// [`Done`] is returned if no data was written.
-    /// [`InvalidState`] is returned if the peer does not support DATAGRAM.
-    /// [`BufferTooShort`] is returned if the DATAGRAM frame length is larger
-    /// than peer's supported DATAGRAM frame length. Use
-    /// [`dgram_max_writable_len()`] to get the largest supported DATAGRAM
-    /// frame length.
-    ///
-    /// Note that there is no flow control of DATAGRAM frames, so in order to
-    /// avoid buffering an infinite amount of frames we apply an internal
-    /// limit.
-    ///
-    /// [`Done`]: enum.Error.html#variant.Done
-    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
-    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
-    /// [`dgram_max_writable_len()`]:
-    /// struct.Connection.html#method.dgram_max_writable_len
-    ///
-    /// ## Examples:
-    ///
-    /// ```no_run
-    /// # let mut buf = [0; 512];
-    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
-    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
-    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let peer = "127.0.0.1:1234".parse().unwrap();
-    /// # let local = socket.local_addr().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
-    /// conn.dgram_send(b"hello")?;
-    /// # Ok::<(), quiche::Error>(())
-    /// ```
-    pub fn dgram_send(&mut self, buf: &[u8]) -> Result<()> {
-        let max_payload_len = match self.dgram_max_writable_len() {
-            Some(v) => v,
+    /// Records the parameters observed on the path `path_id`.
+    fn update_cr_event(&mut self, path_id: usize, event: CREvent) {
+        if !self.cr_observe {
+            return;
+        }
 
-            None => return Err(Error::InvalidState),
+        qlog_with_type!(QLOG_CR_PARAMS_STORED, self.qlog, q, {
+            let stored = CarefulResumeParametersStored::new(
+                event.cwnd.into(),
+                event.min_rtt.into(),
+            )
+            .with_post_congestion(event.post_congestion);
+
+            let stored = if event.pacing_rate != 0 {
+                stored.with_pacing_rate(event.pacing_rate)
+            } else {
+                stored
+            };
+
+            let ev_data = EventData::CarefulResumeParametersStored(stored);
+
+            q.add_event_data_now(ev_data).ok();
+        });
+
+        let peer = match self.paths.get_mut(path_id) {
+            Ok(p) => {
+                p.latest_cr_event.replace(event);
+
+                p.peer_addr()
+            },
+
+            Err(_) => return,
         };
 
-        if buf.len() > max_payload_len {
-            return Err(Error::BufferTooShort);
+        // Keep the most recent events when the application doesn't read them.
+        if self.cr_events.len() == MAX_PENDING_CR_EVENTS {
+            self.cr_events.pop_front();
         }
 
-        self.dgram_send_queue.push(buf.to_vec())?;
+        self.cr_events.push_back(event);
 
-        let active_path = self.paths.get_active_mut()?;
+        if let Some((store, server_name)) = &self.cr_store {
+            store.put(&self.cr_store_key(server_name.as_deref(), peer), event);
+        }
 
-        if self.dgram_send_queue.byte_size() >
-            active_path.recovery.cwnd_available()
-        {
-            active_path.recovery.update_app_limited(false);
+        if let Some(lease) = &self.cr_path_lease {
+            let key = cr_path_cache::key(self.is_server, peer.ip());
+
+            lease.cache().update(&key, event);
         }
 
-        Ok(())
+        if self.is_server {
+            self.bdp_frame_pending = true;
+
+            if self.resume_new_token {
+                if let Some(blob) = self.resume_blob() {
+                    self.send_new_token(&resume_blob::to_token(&blob));
+                }
+            }
+        }
     }
 
-    /// Sends data in a DATAGRAM frame.
-    ///
-    /// This is the same as [`dgram_send()`] but takes a `Vec<u8>` instead of
-    /// a slice.
-    ///
-    /// [`dgram_send()`]: struct.Connection.html#method.dgram_send
-    pub fn dgram_send_vec(&mut self, buf: Vec<u8>) -> Result<()> {
-        let max_payload_len = match self.dgram_max_writable_len() {
+    /// Marks the parameters careful resume retreated from as invalid in the
+    /// store, so that future connections don't jump with them again.
+    fn invalidate_cr_params(&mut self) {
+        let invalid: Vec<(SocketAddr, CREvent)> = self
+            .paths
+            .iter_mut()
+            .filter_map(|(_, p)| {
+                let params = p.recovery.take_invalid_cr_params()?;
+
+                Some((p.peer_addr(), params))
+            })
+            .collect();
+
+        let (store, server_name) = match &self.cr_store {
             Some(v) => v,
 
-            None => return Err(Error::InvalidState),
+            None => return,
         };
 
-        if buf.len() > max_payload_len {
-            return Err(Error::BufferTooShort);
-        }
-
-        self.dgram_send_queue.push(buf)?;
+        for (peer, params) in invalid {
+            let key = self.cr_store_key(server_name.as_deref(), peer);
 
-        let active_path = self.paths.get_active_mut()?;
+            trace!(
+                "{} invalidated careful resume parameters for {} min_rtt={:?} cwnd={}",
+                self.trace_id,
+                key,
+                params.min_rtt,
+                params.cwnd
+            );
 
-        if self.dgram_send_queue.byte_size() >
-            active_path.recovery.cwnd_available()
-        {
-            active_path.recovery.update_app_limited(false);
+            store.invalidate(&key, params);
         }
-
-        Ok(())
     }
 
-    /// Purges queued outgoing DATAGRAMs matching the predicate.
+    /// Returns the next [`CREvent`], or None when there are no events to report. Please refer to [`CREvent`] for event details.
     ///
-    /// In other words, remove all elements `e` such that `f(&e)` returns true.
+    /// Each event observed on the connection is reported once, oldest first.
+    /// Only the 16 most recent events are kept until they are reported.
     ///
-    /// ## Examples:
-    /// ```no_run
-    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
-    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
-    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let peer = "127.0.0.1:1234".parse().unwrap();
-    /// # let local = socket.local_addr().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
-    /// conn.dgram_send(b"hello")?;
-    /// conn.dgram_purge_outgoing(&|d: &[u8]| -> bool { d[0] == 0 });
-    /// # Ok::<(), quiche::Error>(())
-    /// ```
-    #[inline]
-    pub fn dgram_purge_outgoing<F: Fn(&[u8]) -> bool>(&mut self, f: F) {
-        self.dgram_send_queue.purge(f);
+    /// [`CREvent`]: struct.CREvent.html
+    pub fn cr_event_next(&mut self) -> Option<CREvent> {
+        self.cr_events.pop_front()
     }
 
-    /// Returns the maximum DATAGRAM payload that can be sent.
+    /// Returns an iterator over the [`CREvent`]s observed since the last call
+    /// to this method or to [`cr_event_next()`], oldest first.
     ///
-    /// [`None`] is returned if the peer hasn't advertised a maximum DATAGRAM
-    /// frame size.
+    /// The application is expected to store the last one, e.g. along with
+    /// the TLS session, and pass it to [`setup_careful_resume()`] on a future
+    /// connection to the same peer. Alternatively a [`CrStore`] can be set
+    /// with [`Config::set_cr_store()`] to have this done automatically.
     ///
     /// ## Examples:
     ///
     /// ```no_run
-    /// # let mut buf = [0; 512];
-    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
     /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
     /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
     /// # let peer = "127.0.0.1:1234".parse().unwrap();
-    /// # let local = socket.local_addr().unwrap();
+    /// # let local = "127.0.0.1:4321".parse().unwrap();
     /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
-    /// if let Some(payload_size) = conn.dgram_max_writable_len() {
-    ///     if payload_size > 5 {
-    ///         conn.dgram_send(b"hello")?;
-    ///     }
+    /// # let mut saved = None;
+    /// for event in conn.cr_events() {
+    ///     saved = Some(event);
     /// }
     /// # Ok::<(), quiche::Error>(())
     /// ```
-    #[inline]
-    pub fn dgram_max_writable_len(&self) -> Option<usize> {
-        match self.peer_transport_params.max_datagram_frame_size {
-            None => None,
-            Some(peer_frame_len) => {
-                let dcid = self.destination_id();
-                // Start from the maximum packet size...
-                let mut max_len = self.max_send_udp_payload_size();
-                // ...subtract the Short packet header overhead...
-                // (1 byte of pkt_len + len of dcid)
-                max_len = max_len.saturating_sub(1 + dcid.len());
-                // ...subtract the packet number (max len)...
-                max_len = max_len.saturating_sub(packet::MAX_PKT_NUM_LEN);
-                // ...subtract the crypto overhead...
-                max_len = max_len.saturating_sub(
-                    self.pkt_num_spaces[packet::Epoch::Application]
-                        .crypto_overhead()?,
-                );
-                // ...clamp to what peer can support...
-                max_len = cmp::min(peer_frame_len as usize, max_len);
-                // ...subtract frame overhead, checked for underflow.
-                // (1 byte of frame type + len of length )
-                max_len.checked_sub(1 + frame::MAX_DGRAM_OVERHEAD)
-            },
-        }
+    ///
+    /// [`CREvent`]: struct.CREvent.html
+    /// [`cr_event_next()`]: struct.Connection.html#method.cr_event_next
+    /// [`setup_careful_resume()`]: struct.Connection.html#method.setup_careful_resume
+    /// [`CrStore`]: trait.CrStore.html
+    /// [`Config::set_cr_store()`]: struct.Config.html#method.set_cr_store
+    pub fn cr_events(&mut self) -> impl Iterator<Item = CREvent> + '_ {
+        self.cr_events.drain(..)
     }
+}
 
-    fn dgram_enabled(&self) -> bool {
-        self.local_transport_params
-            .max_datagram_frame_size
-            .is_some()
+#[cfg(feature = "boringssl-boring-crate")]
+impl AsMut<boring::ssl::SslRef> for Connection {
+    fn as_mut(&mut self) -> &mut boring::ssl::SslRef {
+        self.handshake.ssl_mut()
     }
+}
 
-    /// Returns when the next timeout event will occur.
+/// Maps an `Error` to `Error::Done`, or itself.
+///
+/// When a received packet that hasn't yet been authenticated triggers a failure
+/// it should, in most cases, be ignored, instead of raising a connection error,
+/// to avoid potential man-in-the-middle and man-on-the-side attacks.
+///
+/// However, if no other packet was previously received, the connection should
+/// indeed be closed as the received packet might just be network background
+/// noise, and it shouldn't keep resources occupied indefinitely.
+///
+/// This function maps an error to `Error::Done` to ignore a packet failure
+/// without aborting the connection, except when no other packet was previously
+/// received, in which case the error itself is returned, but only on the
+/// server-side as the client will already have armed the idle timer.
+///
+/// This must only be used for errors preceding packet authentication. Failures
+/// happening after a packet has been authenticated should still cause the
+/// connection to be aborted.
+fn drop_pkt_on_err(
+    e: Error, recv_count: usize, is_server: bool, trace_id: &str,
+) -> Error {
+    // On the server, if no other packet has been successfully processed, abort
+    // the connection to avoid keeping the connection open when only junk is
+    // received.
+    if is_server && recv_count == 0 {
+        return e;
+    }
+
+    trace!("{} dropped invalid packet", trace_id);
+
+    // Ignore other invalid packets that haven't been authenticated to prevent
+    // man-in-the-middle and man-on-the-side attacks.
+    Error::Done
+}
+
+struct AddrTupleFmt(SocketAddr, SocketAddr);
+
+impl std::fmt::Display for AddrTupleFmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let AddrTupleFmt(src, dst) = &self;
+
+        if src.ip().is_unspecified() || dst.ip().is_unspecified() {
+            return Ok(());
+        }
+
+        f.write_fmt(format_args!("src:{src} dst:{dst}"))
+    }
+}
+
+/// Transport state of a previous connection that is used to resume a new
+/// connection to the same peer.
+///
+/// This is produced by [`saved_transport()`] and consumed by
+/// [`resume_from()`]. It can be stored across process restarts using
+/// [`to_bytes()`] and [`from_bytes()`].
+///
+/// [`saved_transport()`]: struct.Connection.html#method.saved_transport
+/// [`resume_from()`]: struct.Connection.html#method.resume_from
+/// [`to_bytes()`]: struct.SavedTransport.html#method.to_bytes
+/// [`from_bytes()`]: struct.SavedTransport.html#method.from_bytes
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SavedTransport {
+    /// The serialized TLS session and peer transport parameters, as returned
+    /// by [`session()`].
     ///
-    /// Once the timeout Instant has been reached, the [`on_timeout()`] method
-    /// should be called. A timeout of `None` means that the timer should be
-    /// disarmed.
+    /// [`session()`]: struct.Connection.html#method.session
+    pub session: Option<Vec<u8>>,
+
+    /// The smoothed RTT of the previous connection, used to seed the initial
+    /// RTT.
+    pub rtt: Option<Duration>,
+
+    /// The parameters last observed for Careful Resume.
+    pub careful_resume: Option<CREvent>,
+}
+
+impl SavedTransport {
+    /// Serializes the saved state.
+    ///
+    /// ```text
+    /// Saved Transport {
+    ///   Session Length (i),
+    ///   Session (..),
+    ///   RTT (i),           // microseconds, 0 if unknown
+    ///   CR Min RTT (i),    // microseconds
+    ///   CR Cwnd (i),       // bytes, 0 if unknown
+    ///   CR Pacing Rate (i), // bytes per second, 0 if unknown
+    /// }
+    /// ```
     ///
-    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
-    pub fn timeout_instant(&self) -> Option<time::Instant> {
-        if self.is_closed() {
-            return None;
-        }
+    /// State serialized before the pacing rate was added can still be
+    /// parsed, with an unknown pacing rate.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let session = self.session.as_deref().unwrap_or_default();
 
-        if self.is_draining() {
-            // Draining timer takes precedence over all other timers. If it is
-            // set it means the connection is closing so there's no point in
-            // processing the other timers.
-            self.draining_timer
-        } else {
-            // Use the lowest timer value (i.e. "sooner") among idle and loss
-            // detection timers. If they are both unset (i.e. `None`) then the
-            // result is `None`, but if at least one of them is set then a
-            // `Some(...)` value is returned.
-            let path_timer = self
-                .paths
-                .iter()
-                .filter_map(|(_, p)| p.recovery.loss_detection_timer())
-                .min();
+        let mut out = vec![0; session.len() + 8 * 5];
+        let mut b = octets::OctetsMut::with_slice(&mut out);
 
-            let key_update_timer = self.pkt_num_spaces
-                [packet::Epoch::Application]
-                .key_update
-                .as_ref()
-                .map(|key_update| key_update.timer);
+        // Writes can't fail as the buffer is large enough for all fields.
+        b.put_varint(session.len() as u64).unwrap();
+        b.put_bytes(session).unwrap();
 
-            let timers = [self.idle_timer, path_timer, key_update_timer];
+        b.put_varint(self.rtt.map_or(0, |v| v.as_micros() as u64))
+            .unwrap();
 
-            timers.iter().filter_map(|&x| x).min()
-        }
+        let (min_rtt, cwnd, pacing_rate) =
+            self.careful_resume.map_or((0, 0, 0), |cr| {
+                (cr.min_rtt.as_micros() as u64, cr.cwnd as u64, cr.pacing_rate)
+            });
+
+        b.put_varint(min_rtt).unwrap();
+        b.put_varint(cwnd).unwrap();
+        b.put_varint(pacing_rate).unwrap();
+
+        let len = b.off();
+        out.truncate(len);
+
+        out
     }
 
-    /// Returns the amount of time until the next timeout event.
+    /// Parses state serialized with [`to_bytes()`].
     ///
-    /// Once the given duration has elapsed, the [`on_timeout()`] method should
-    /// be called. A timeout of `None` means that the timer should be disarmed.
-    ///
-    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
-    pub fn timeout(&self) -> Option<time::Duration> {
-        self.timeout_instant().map(|timeout| {
-            let now = time::Instant::now();
+    /// [`to_bytes()`]: struct.SavedTransport.html#method.to_bytes
+    pub fn from_bytes(buf: &[u8]) -> Result<SavedTransport> {
+        let mut b = octets::Octets::with_slice(buf);
 
-            if timeout <= now {
-                time::Duration::ZERO
-            } else {
-                timeout.duration_since(now)
-            }
+        let session = b.get_bytes_with_varint_length()?.to_vec();
+        let session = (!session.is_empty()).then_some(session);
+
+        let rtt = match b.get_varint()? {
+            0 => None,
+
+            v => Some(Duration::from_micros(v)),
+        };
+
+        let min_rtt = b.get_varint()?;
+        let cwnd = b.get_varint()?;
+
+        let pacing_rate = if b.cap() > 0 { b.get_varint()? } else { 0 };
+
+        let careful_resume = (cwnd > 0).then(|| CREvent {
+            min_rtt: Duration::from_micros(min_rtt),
+            cwnd: cwnd as usize,
+            pacing_rate,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        });
+
+        Ok(SavedTransport {
+            session,
+            rtt,
+            careful_resume,
         })
     }
+}
 
-    /// Processes a timeout event.
-    ///
-    /// If no timeout has occurred it does nothing.
-    pub fn on_timeout(&mut self) {
-        let now = time::Instant::now();
+/// Statistics about the connection.
+///
+/// A connection's statistics can be collected using the [`stats()`] method.
+///
+/// [`stats()`]: struct.Connection.html#method.stats
+#[derive(Clone, Default)]
+pub struct Stats {
+    /// The number of QUIC packets received.
+    pub recv: usize,
 
-        if let Some(draining_timer) = self.draining_timer {
-            if draining_timer <= now {
-                trace!("{} draining timeout expired", self.trace_id);
+    /// The number of QUIC packets sent.
+    pub sent: usize,
 
-                self.mark_closed();
-            }
+    /// The number of QUIC packets that were lost.
+    pub lost: usize,
 
-            // Draining timer takes precedence over all other timers. If it is
-            // set it means the connection is closing so there's no point in
-            // processing the other timers.
-            return;
-        }
+    /// The number of sent QUIC packets with retransmitted data.
+    pub retrans: usize,
 
-        if let Some(timer) = self.idle_timer {
-            if timer <= now {
-                trace!("{} idle timeout expired", self.trace_id);
+    /// The number of sent bytes.
+    pub sent_bytes: u64,
 
-                self.mark_closed();
-                self.timed_out = true;
-                return;
-            }
-        }
+    /// The number of received bytes.
+    pub recv_bytes: u64,
 
-        if let Some(timer) = self.pkt_num_spaces[packet::Epoch::Application]
-            .key_update
-            .as_ref()
-            .map(|key_update| key_update.timer)
-        {
-            if timer <= now {
-                // Discard previous key once key update timer expired.
-                let _ = self.pkt_num_spaces[packet::Epoch::Application]
-                    .key_update
-                    .take();
-            }
-        }
+    /// The number of bytes sent acked.
+    pub acked_bytes: u64,
 
-        let handshake_status = self.handshake_status();
+    /// The number of bytes sent lost.
+    pub lost_bytes: u64,
 
-        let mut update = None;
-        for (_, p) in self.paths.iter_mut() {
-            if let Some(timer) = p.recovery.loss_detection_timer() {
-                if timer <= now {
-                    trace!("{} loss detection timeout expired", self.trace_id);
+    /// The number of stream bytes retransmitted.
+    pub stream_retrans_bytes: u64,
 
-                    let (lost_packets, lost_bytes) = p.on_loss_detection_timeout(
-                        handshake_status,
-                        now,
-                        self.is_server,
-                        &self.trace_id,
-                    );
+    /// The number of known paths for the connection.
+    pub paths_count: usize,
 
-                    self.lost_count += lost_packets;
-                    self.lost_bytes += lost_bytes as u64;
+    /// The number of streams reset by local.
+    pub reset_stream_count_local: u64,
 
-                    qlog_with_type!(QLOG_PACKET_LOST, self.qlog, q, {
-                        for ev_data in p.recovery.packet_loss_qlog() {
-                            q.add_event_data_with_instant(ev_data, now).ok();
-                        }
-                    });
-                    qlog_with_type!(QLOG_METRICS, self.qlog, q, {
-                        if let Some(ev_data) = p.recovery.maybe_qlog() {
-                            q.add_event_data_with_instant(ev_data, now).ok();
-                        }
-                    });
-                    qlog_with_type!(QLOG_CR_PHASE, self.qlog, q, {
-                        if let Some(ev_data) = p.recovery.maybe_cr_qlog() {
-                            q.add_event_data_with_instant(ev_data, now).ok();
-                        }
-                    });
+    /// The number of streams stopped by local.
+    pub stopped_stream_count_local: u64,
 
-                    if p.active() {
-                        if let Some(cr_event) = p.recovery.maybe_cr_event() {
-                            update = Some(cr_event);
-                        }
-                    }
-                }
-            }
-        }
+    /// The number of streams reset by remote.
+    pub reset_stream_count_remote: u64,
 
-        if let Some(cr_event) = update {
-            self.update_cr_event(cr_event);
-        }
+    /// The number of streams stopped by remote.
+    pub stopped_stream_count_remote: u64,
 
-        // Notify timeout events to the application.
-        self.paths.notify_failed_validations();
+    /// The total number of PATH_CHALLENGE frames that were received.
+    pub path_challenge_rx_count: u64,
 
-        // If the active path failed, try to find a new candidate.
-        if self.paths.get_active_path_id().is_err() {
-            match self.paths.find_candidate_path() {
-                Some(pid) => {
-                    if self.set_active_path(pid, now).is_err() {
-                        // The connection cannot continue.
-                        self.mark_closed();
-                    }
-                },
+    /// The number of `MetricsUpdated` events written to qlog.
+    pub qlog_metrics_updated_count: u64,
 
-                // The connection cannot continue.
-                None => {
-                    self.mark_closed();
-                },
-            }
-        }
-    }
+    /// The number of `CarefulResumePhaseUpdated` events written to qlog.
+    pub qlog_cr_phase_updated_count: u64,
 
-    /// Requests the stack to perform path validation of the proposed 4-tuple.
-    ///
-    /// Probing new paths requires spare Connection IDs at both the host and the
-    /// peer sides. If it is not the case, it raises an [`OutOfIdentifiers`].
-    ///
-    /// The probing of new addresses can only be done by the client. The server
-    /// can only probe network paths that were previously advertised by
-    /// [`PathEvent::New`]. If the server tries to probe such an unseen network
-    /// path, this call raises an [`InvalidState`].
-    ///
-    /// The caller might also want to probe an existing path. In such case, it
-    /// triggers a PATH_CHALLENGE frame, but it does not require spare CIDs.
-    ///
-    /// A server always probes a new path it observes. Calling this method is
-    /// hence not required to validate a new path. However, a server can still
-    /// request an additional path validation of the proposed 4-tuple.
-    ///
-    /// Calling this method several times before calling [`send()`] or
-    /// [`send_on_path()`] results in a single probe being generated. An
-    /// application wanting to send multiple in-flight probes must call this
-    /// method again after having sent packets.
+    /// The number of `PacketLost` events written to qlog.
+    pub qlog_packet_lost_count: u64,
+
+    /// The time spent writing the above events to qlog.
+    pub qlog_recovery_time: Duration,
+
+    /// The number of payload bytes protected by packet protection.
+    pub crypto_sealed_bytes: u64,
+
+    /// The number of payload bytes unprotected by packet protection.
+    pub crypto_opened_bytes: u64,
+
+    /// The time spent protecting and unprotecting packets.
     ///
-    /// Returns the Destination Connection ID sequence number associated to that
-    /// path.
+    /// Together with the above this gives the cost of packet protection
+    /// for the connection, which can limit its throughput on hosts without
+    /// hardware support for the negotiated cipher.
+    pub crypto_time: Duration,
+
+    /// The time spent sending with room left in the congestion window, on
+    /// all paths.
+    pub app_limited_time: Duration,
+
+    /// The time spent sending with the congestion window full, on all paths.
     ///
-    /// [`PathEvent::New`]: enum.PathEvent.html#variant.New
-    /// [`OutOfIdentifiers`]: enum.Error.html#OutOfIdentifiers
-    /// [`InvalidState`]: enum.Error.html#InvalidState
-    /// [`send()`]: struct.Connection.html#method.send
-    /// [`send_on_path()`]: struct.Connection.html#method.send_on_path
-    pub fn probe_path(
-        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
-    ) -> Result<u64> {
-        // We may want to probe an existing path.
-        let pid = match self.paths.path_id_from_addrs(&(local_addr, peer_addr)) {
-            Some(pid) => pid,
-            None => self.create_path_on_client(local_addr, peer_addr)?,
-        };
+    /// Careful Resume outcomes and the parameters observed on a connection
+    /// only reflect the capacity of the path if the sender was cwnd-limited
+    /// for some of the connection.
+    pub cwnd_limited_time: Duration,
+
+    /// Counts of what Careful Resume did, on all paths.
+    pub cr_counters: CrCounters,
+}
+
+impl std::fmt::Debug for Stats {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "recv={} sent={} lost={} retrans={}",
+            self.recv, self.sent, self.lost, self.retrans,
+        )?;
+
+        write!(
+            f,
+            " sent_bytes={} recv_bytes={} lost_bytes={}",
+            self.sent_bytes, self.recv_bytes, self.lost_bytes,
+        )?;
+
+        Ok(())
+    }
+}
+
+/// QUIC Transport Parameters
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransportParams {
+    /// Value of Destination CID field from first Initial packet sent by client
+    pub original_destination_connection_id: Option<ConnectionId<'static>>,
+    /// The maximum idle timeout.
+    pub max_idle_timeout: u64,
+    /// Token used for verifying stateless resets
+    pub stateless_reset_token: Option<u128>,
+    /// The maximum UDP payload size.
+    pub max_udp_payload_size: u64,
+    /// The initial flow control maximum data for the connection.
+    pub initial_max_data: u64,
+    /// The initial flow control maximum data for local bidirectional streams.
+    pub initial_max_stream_data_bidi_local: u64,
+    /// The initial flow control maximum data for remote bidirectional streams.
+    pub initial_max_stream_data_bidi_remote: u64,
+    /// The initial flow control maximum data for unidirectional streams.
+    pub initial_max_stream_data_uni: u64,
+    /// The initial maximum bidirectional streams.
+    pub initial_max_streams_bidi: u64,
+    /// The initial maximum unidirectional streams.
+    pub initial_max_streams_uni: u64,
+    /// The ACK delay exponent.
+    pub ack_delay_exponent: u64,
+    /// The max ACK delay.
+    pub max_ack_delay: u64,
+    /// Whether active migration is disabled.
+    pub disable_active_migration: bool,
+    /// The active connection ID limit.
+    pub active_conn_id_limit: u64,
+    /// The value that the endpoint included in the Source CID field of a Retry
+    /// Packet.
+    pub initial_source_connection_id: Option<ConnectionId<'static>>,
+    /// The value that the server included in the Source CID field of a Retry
+    /// Packet.
+    pub retry_source_connection_id: Option<ConnectionId<'static>>,
+    /// DATAGRAM frame extension parameter, if any.
+    pub max_datagram_frame_size: Option<u64>,
+    // pub preferred_address: ...,
+    /// Address validation tokens contain BDP data
+    pub bdp_tokens: bool,
+    /// Careful Resume parameters are exchanged in BDP frames.
+    pub bdp_frame: bool,
+    /// Careful Resume hints can be sent in CR_HINTS frames.
+    pub cr_hints: bool,
+    /// Streams can be reset with RESET_STREAM_AT frames.
+    pub reset_stream_at: bool,
+    /// Stateless Careful Resume blob echoed by a client, or an empty value
+    /// when a server advertises that it accepts blobs.
+    pub careful_resume_blob: Option<Vec<u8>>,
+    /// Version information, used to authenticate version negotiation.
+    pub version_information: Option<VersionInformation>,
+}
 
-        let path = self.paths.get_mut(pid)?;
-        path.request_validation();
+/// The Version Information transport parameter, as defined in RFC 9368.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionInformation {
+    /// The version used for the packets carrying the transport parameters.
+    pub chosen_version: u32,
+    /// The versions supported by the endpoint, in order of preference.
+    pub available_versions: Vec<u32>,
+}
 
-        path.active_dcid_seq.ok_or(Error::InvalidState)
+impl Default for TransportParams {
+    fn default() -> TransportParams {
+        TransportParams {
+            original_destination_connection_id: None,
+            max_idle_timeout: 0,
+            stateless_reset_token: None,
+            max_udp_payload_size: 65527,
+            initial_max_data: 0,
+            initial_max_stream_data_bidi_local: 0,
+            initial_max_stream_data_bidi_remote: 0,
+            initial_max_stream_data_uni: 0,
+            initial_max_streams_bidi: 0,
+            initial_max_streams_uni: 0,
+            ack_delay_exponent: 3,
+            max_ack_delay: 25,
+            disable_active_migration: false,
+            active_conn_id_limit: 2,
+            initial_source_connection_id: None,
+            retry_source_connection_id: None,
+            max_datagram_frame_size: None,
+            bdp_tokens: false,
+            bdp_frame: false,
+            cr_hints: false,
+            reset_stream_at: false,
+            careful_resume_blob: None,
+            version_information: None,
+        }
     }
+}
 
-    /// Migrates the connection to a new local address `local_addr`.
-    ///
-    /// The behavior is similar to [`migrate()`], with the nuance that the
-    /// connection only changes the local address, but not the peer one.
-    ///
-    /// See [`migrate()`] for the full specification of this method.
-    ///
-    /// [`migrate()`]: struct.Connection.html#method.migrate
-    pub fn migrate_source(&mut self, local_addr: SocketAddr) -> Result<u64> {
-        let peer_addr = self.paths.get_active()?.peer_addr();
-        self.migrate(local_addr, peer_addr)
-    }
+impl TransportParams {
+    fn decode(buf: &[u8], is_server: bool) -> Result<TransportParams> {
+        let mut params = octets::Octets::with_slice(buf);
+        let mut seen_params = HashSet::new();
 
-    /// Migrates the connection over the given network path between `local_addr`
-    /// and `peer_addr`.
-    ///
-    /// Connection migration can only be initiated by the client. Calling this
-    /// method as a server returns [`InvalidState`].
-    ///
-    /// To initiate voluntary migration, there should be enough Connection IDs
-    /// at both sides. If this requirement is not satisfied, this call returns
-    /// [`OutOfIdentifiers`].
-    ///
-    /// Returns the Destination Connection ID associated to that migrated path.
-    ///
-    /// [`OutOfIdentifiers`]: enum.Error.html#OutOfIdentifiers
-    /// [`InvalidState`]: enum.Error.html#InvalidState
-    pub fn migrate(
-        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
-    ) -> Result<u64> {
-        if self.is_server {
-            return Err(Error::InvalidState);
-        }
+        let mut tp = TransportParams::default();
 
-        // If the path already exists, mark it as the active one.
-        let (pid, dcid_seq) = if let Some(pid) =
-            self.paths.path_id_from_addrs(&(local_addr, peer_addr))
-        {
-            let path = self.paths.get_mut(pid)?;
+        while params.cap() > 0 {
+            let id = params.get_varint()?;
 
-            // If it is already active, do nothing.
-            if path.active() {
-                return path.active_dcid_seq.ok_or(Error::OutOfIdentifiers);
+            if seen_params.contains(&id) {
+                return Err(Error::InvalidTransportParam);
             }
+            seen_params.insert(id);
 
-            // Ensures that a Source Connection ID has been dedicated to this
-            // path, or a free one is available. This is only required if the
-            // host uses non-zero length Source Connection IDs.
-            if !self.ids.zero_length_scid() &&
-                path.active_scid_seq.is_none() &&
-                self.ids.available_scids() == 0
-            {
-                return Err(Error::OutOfIdentifiers);
-            }
+            let mut val = params.get_bytes_with_varint_length()?;
 
-            // Ensures that the migrated path has a Destination Connection ID.
-            let dcid_seq = if let Some(dcid_seq) = path.active_dcid_seq {
-                dcid_seq
-            } else {
-                let dcid_seq = self
-                    .ids
-                    .lowest_available_dcid_seq()
-                    .ok_or(Error::OutOfIdentifiers)?;
+            match id {
+                0x0000 => {
+                    if is_server {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-                self.ids.link_dcid_to_path_id(dcid_seq, pid)?;
-                path.active_dcid_seq = Some(dcid_seq);
+                    tp.original_destination_connection_id =
+                        Some(val.to_vec().into());
+                },
 
-                dcid_seq
-            };
+                0x0001 => {
+                    tp.max_idle_timeout = val.get_varint()?;
+                },
 
-            (pid, dcid_seq)
-        } else {
-            let pid = self.create_path_on_client(local_addr, peer_addr)?;
+                0x0002 => {
+                    if is_server {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-            let dcid_seq = self
-                .paths
-                .get(pid)?
-                .active_dcid_seq
-                .ok_or(Error::InvalidState)?;
+                    tp.stateless_reset_token = Some(u128::from_be_bytes(
+                        val.get_bytes(16)?
+                            .to_vec()
+                            .try_into()
+                            .map_err(|_| Error::BufferTooShort)?,
+                    ));
+                },
 
-            (pid, dcid_seq)
-        };
+                0x0003 => {
+                    tp.max_udp_payload_size = val.get_varint()?;
 
-        // Change the active path.
-        self.set_active_path(pid, time::Instant::now())?;
+                    if tp.max_udp_payload_size < 1200 {
+                        return Err(Error::InvalidTransportParam);
+                    }
+                },
 
-        Ok(dcid_seq)
-    }
+                0x0004 => {
+                    tp.initial_max_data = val.get_varint()?;
+                },
 
-    /// Provides additional source Connection IDs that the peer can use to reach
-    /// this host.
-    ///
-    /// This triggers sending NEW_CONNECTION_ID frames if the provided Source
-    /// Connection ID is not already present. In the case the caller tries to
-    /// reuse a Connection ID with a different reset token, this raises an
-    /// `InvalidState`.
-    ///
-    /// At any time, the peer cannot have more Destination Connection IDs than
-    /// the maximum number of active Connection IDs it negotiated. In such case
-    /// (i.e., when [`scids_left()`] returns 0), if the host agrees to
-    /// request the removal of previous connection IDs, it sets the
-    /// `retire_if_needed` parameter. Otherwise, an [`IdLimit`] is returned.
-    ///
-    /// Note that setting `retire_if_needed` does not prevent this function from
-    /// returning an [`IdLimit`] in the case the caller wants to retire still
-    /// unannounced Connection IDs.
-    ///
-    /// The caller is responsible from ensuring that the provided `scid` is not
-    /// repeated several times over the connection. quiche ensures that as long
-    /// as the provided Connection ID is still in use (i.e., not retired), it
-    /// does not assign a different sequence number.
-    ///
-    /// Note that if the host uses zero-length Source Connection IDs, it cannot
-    /// advertise Source Connection IDs and calling this method returns an
-    /// [`InvalidState`].
-    ///
-    /// Returns the sequence number associated to the provided Connection ID.
-    ///
-    /// [`scids_left()`]: struct.Connection.html#method.scids_left
-    /// [`IdLimit`]: enum.Error.html#IdLimit
-    /// [`InvalidState`]: enum.Error.html#InvalidState
-    pub fn new_scid(
-        &mut self, scid: &ConnectionId, reset_token: u128, retire_if_needed: bool,
-    ) -> Result<u64> {
-        self.ids.new_scid(
-            scid.to_vec().into(),
-            Some(reset_token),
-            true,
-            None,
-            retire_if_needed,
-        )
-    }
+                0x0005 => {
+                    tp.initial_max_stream_data_bidi_local = val.get_varint()?;
+                },
 
-    /// Returns the number of source Connection IDs that are active. This is
-    /// only meaningful if the host uses non-zero length Source Connection IDs.
-    pub fn active_scids(&self) -> usize {
-        self.ids.active_source_cids()
-    }
+                0x0006 => {
+                    tp.initial_max_stream_data_bidi_remote = val.get_varint()?;
+                },
 
-    /// Returns the number of source Connection IDs that should be provided
-    /// to the peer without exceeding the limit it advertised.
-    ///
-    /// This will automatically limit the number of Connection IDs to the
-    /// minimum between the locally configured active connection ID limit,
-    /// and the one sent by the peer.
-    ///
-    /// To obtain the maximum possible value allowed by the peer an application
-    /// can instead inspect the [`peer_active_conn_id_limit`] value.
-    ///
-    /// [`peer_active_conn_id_limit`]: struct.Stats.html#structfield.peer_active_conn_id_limit
-    #[inline]
-    pub fn scids_left(&self) -> usize {
-        let max_active_source_cids = cmp::min(
-            self.peer_transport_params.active_conn_id_limit,
-            self.local_transport_params.active_conn_id_limit,
-        ) as usize;
+                0x0007 => {
+                    tp.initial_max_stream_data_uni = val.get_varint()?;
+                },
 
-        max_active_source_cids - self.active_scids()
-    }
+                0x0008 => {
+                    let max = val.get_varint()?;
 
-    /// Requests the retirement of the destination Connection ID used by the
-    /// host to reach its peer.
-    ///
-    /// This triggers sending RETIRE_CONNECTION_ID frames.
-    ///
-    /// If the application tries to retire a non-existing Destination Connection
-    /// ID sequence number, or if it uses zero-length Destination Connection ID,
-    /// this method returns an [`InvalidState`].
-    ///
-    /// At any time, the host must have at least one Destination ID. If the
-    /// application tries to retire the last one, or if the caller tries to
-    /// retire the destination Connection ID used by the current active path
-    /// while having neither spare Destination Connection IDs nor validated
-    /// network paths, this method returns an [`OutOfIdentifiers`]. This
-    /// behavior prevents the caller from stalling the connection due to the
-    /// lack of validated path to send non-probing packets.
-    ///
-    /// [`InvalidState`]: enum.Error.html#InvalidState
-    /// [`OutOfIdentifiers`]: enum.Error.html#OutOfIdentifiers
-    pub fn retire_dcid(&mut self, dcid_seq: u64) -> Result<()> {
-        if self.ids.zero_length_dcid() {
-            return Err(Error::InvalidState);
-        }
+                    if max > MAX_STREAM_ID {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        let active_path_dcid_seq = self
-            .paths
-            .get_active()?
-            .active_dcid_seq
-            .ok_or(Error::InvalidState)?;
+                    tp.initial_max_streams_bidi = max;
+                },
 
-        let active_path_id = self.paths.get_active_path_id()?;
+                0x0009 => {
+                    let max = val.get_varint()?;
 
-        if active_path_dcid_seq == dcid_seq &&
-            self.ids.lowest_available_dcid_seq().is_none() &&
-            !self
-                .paths
-                .iter()
-                .any(|(pid, p)| pid != active_path_id && p.usable())
-        {
-            return Err(Error::OutOfIdentifiers);
-        }
+                    if max > MAX_STREAM_ID {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        if let Some(pid) = self.ids.retire_dcid(dcid_seq)? {
-            // The retired Destination CID was associated to a given path. Let's
-            // find an available DCID to associate to that path.
-            let path = self.paths.get_mut(pid)?;
-            let dcid_seq = self.ids.lowest_available_dcid_seq();
+                    tp.initial_max_streams_uni = max;
+                },
 
-            if let Some(dcid_seq) = dcid_seq {
-                self.ids.link_dcid_to_path_id(dcid_seq, pid)?;
-            }
+                0x000a => {
+                    let ack_delay_exponent = val.get_varint()?;
 
-            path.active_dcid_seq = dcid_seq;
-        }
+                    if ack_delay_exponent > 20 {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        Ok(())
-    }
+                    tp.ack_delay_exponent = ack_delay_exponent;
+                },
 
-    /// Processes path-specific events.
-    ///
-    /// On success it returns a [`PathEvent`], or `None` when there are no
-    /// events to report. Please refer to [`PathEvent`] for the exhaustive event
-    /// list.
-    ///
-    /// Note that all events are edge-triggered, meaning that once reported they
-    /// will not be reported again by calling this method again, until the event
-    /// is re-armed.
-    ///
-    /// [`PathEvent`]: enum.PathEvent.html
-    pub fn path_event_next(&mut self) -> Option<PathEvent> {
-        self.paths.pop_event()
-    }
+                0x000b => {
+                    let max_ack_delay = val.get_varint()?;
 
-    /// Returns the number of source Connection IDs that are retired.
-    pub fn retired_scids(&self) -> usize {
-        self.ids.retired_source_cids()
-    }
+                    if max_ack_delay >= 2_u64.pow(14) {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-    /// Returns a source `ConnectionId` that has been retired.
-    ///
-    /// On success it returns a [`ConnectionId`], or `None` when there are no
-    /// more retired connection IDs.
-    ///
-    /// [`ConnectionId`]: struct.ConnectionId.html
-    pub fn retired_scid_next(&mut self) -> Option<ConnectionId<'static>> {
-        self.ids.pop_retired_scid()
-    }
+                    tp.max_ack_delay = max_ack_delay;
+                },
 
-    /// Returns the number of spare Destination Connection IDs, i.e.,
-    /// Destination Connection IDs that are still unused.
-    ///
-    /// Note that this function returns 0 if the host uses zero length
-    /// Destination Connection IDs.
-    pub fn available_dcids(&self) -> usize {
-        self.ids.available_dcids()
-    }
+                0x000c => {
+                    tp.disable_active_migration = true;
+                },
 
-    /// Returns an iterator over destination `SockAddr`s whose association
-    /// with `from` forms a known QUIC path on which packets can be sent to.
-    ///
-    /// This function is typically used in combination with [`send_on_path()`].
-    ///
-    /// Note that the iterator includes all the possible combination of
-    /// destination `SockAddr`s, even those whose sending is not required now.
-    /// In other words, this is another way for the application to recall from
-    /// past [`PathEvent::New`] events.
-    ///
-    /// [`PathEvent::New`]: enum.PathEvent.html#variant.New
-    /// [`send_on_path()`]: struct.Connection.html#method.send_on_path
-    ///
-    /// ## Examples:
-    ///
-    /// ```no_run
-    /// # let mut out = [0; 512];
-    /// # let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
-    /// # let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION)?;
-    /// # let scid = quiche::ConnectionId::from_ref(&[0xba; 16]);
-    /// # let local = socket.local_addr().unwrap();
-    /// # let peer = "127.0.0.1:1234".parse().unwrap();
-    /// # let mut conn = quiche::accept(&scid, None, local, peer, &mut config)?;
-    /// // Iterate over possible destinations for the given local `SockAddr`.
-    /// for dest in conn.paths_iter(local) {
-    ///     loop {
-    ///         let (write, send_info) =
-    ///             match conn.send_on_path(&mut out, Some(local), Some(dest)) {
-    ///                 Ok(v) => v,
-    ///
-    ///                 Err(quiche::Error::Done) => {
-    ///                     // Done writing for this destination.
-    ///                     break;
-    ///                 },
-    ///
-    ///                 Err(e) => {
-    ///                     // An error occurred, handle it.
-    ///                     break;
-    ///                 },
-    ///             };
-    ///
-    ///         socket.send_to(&out[..write], &send_info.to).unwrap();
-    ///     }
-    /// }
-    /// # Ok::<(), quiche::Error>(())
-    /// ```
-    #[inline]
-    pub fn paths_iter(&self, from: SocketAddr) -> SocketAddrIter {
-        // Instead of trying to identify whether packets will be sent on the
-        // given 4-tuple, simply filter paths that cannot be used.
-        SocketAddrIter {
-            sockaddrs: self
-                .paths
-                .iter()
-                .filter(|(_, p)| p.active_dcid_seq.is_some())
-                .filter(|(_, p)| p.usable() || p.probing_required())
-                .filter(|(_, p)| p.local_addr() == from)
-                .map(|(_, p)| p.peer_addr())
-                .collect(),
+                0x000d => {
+                    if is_server {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-            index: 0,
-        }
-    }
+                    // TODO: decode preferred_address
+                },
 
-    /// Closes the connection with the given error and reason.
-    ///
-    /// The `app` parameter specifies whether an application close should be
-    /// sent to the peer. Otherwise a normal connection close is sent.
-    ///
-    /// If `app` is true but the connection is not in a state that is safe to
-    /// send an application error (not established nor in early data), in
-    /// accordance with [RFC
-    /// 9000](https://www.rfc-editor.org/rfc/rfc9000.html#section-10.2.3-3), the
-    /// error code is changed to APPLICATION_ERROR and the reason phrase is
-    /// cleared.
-    ///
-    /// Returns [`Done`] if the connection had already been closed.
-    ///
-    /// Note that the connection will not be closed immediately. An application
-    /// should continue calling the [`recv()`], [`send()`], [`timeout()`] and
-    /// [`on_timeout()`] methods as normal, until the [`is_closed()`] method
-    /// returns `true`.
-    ///
-    /// [`Done`]: enum.Error.html#variant.Done
-    /// [`recv()`]: struct.Connection.html#method.recv
-    /// [`send()`]: struct.Connection.html#method.send
-    /// [`timeout()`]: struct.Connection.html#method.timeout
-    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
-    /// [`is_closed()`]: struct.Connection.html#method.is_closed
-    pub fn close(&mut self, app: bool, err: u64, reason: &[u8]) -> Result<()> {
-        if self.is_closed() || self.is_draining() {
-            return Err(Error::Done);
-        }
+                0x000e => {
+                    let limit = val.get_varint()?;
 
-        if self.local_error.is_some() {
-            return Err(Error::Done);
-        }
+                    if limit < 2 {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-        let is_safe_to_send_app_data =
-            self.is_established() || self.is_in_early_data();
+                    tp.active_conn_id_limit = limit;
+                },
 
-        if app && !is_safe_to_send_app_data {
-            // Clear error information.
-            self.local_error = Some(ConnectionError {
-                is_app: false,
-                error_code: 0x0c,
-                reason: vec![],
-            });
-        } else {
-            self.local_error = Some(ConnectionError {
-                is_app: app,
-                error_code: err,
-                reason: reason.to_vec(),
-            });
-        }
+                0x000f => {
+                    tp.initial_source_connection_id = Some(val.to_vec().into());
+                },
+
+                0x00010 => {
+                    if is_server {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    tp.retry_source_connection_id = Some(val.to_vec().into());
+                },
+
+                0x0020 => {
+                    tp.max_datagram_frame_size = Some(val.get_varint()?);
+                },
 
-        // When no packet was successfully processed close connection immediately.
-        if self.recv_count == 0 {
-            self.mark_closed();
-        }
+                0x0011 => {
+                    let chosen_version = val.get_u32()?;
 
-        Ok(())
-    }
+                    if chosen_version == 0 {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-    /// Returns a string uniquely representing the connection.
-    ///
-    /// This can be used for logging purposes to differentiate between multiple
-    /// connections.
-    #[inline]
-    pub fn trace_id(&self) -> &str {
-        &self.trace_id
-    }
+                    let mut available_versions = Vec::new();
 
-    /// Returns the negotiated ALPN protocol.
-    ///
-    /// If no protocol has been negotiated, the returned value is empty.
-    #[inline]
-    pub fn application_proto(&self) -> &[u8] {
-        self.alpn.as_ref()
-    }
+                    while val.cap() > 0 {
+                        let version = val.get_u32()?;
 
-    /// Returns the server name requested by the client.
-    #[inline]
-    pub fn server_name(&self) -> Option<&str> {
-        self.handshake.server_name()
-    }
+                        if version == 0 {
+                            return Err(Error::InvalidTransportParam);
+                        }
 
-    /// Returns the peer's leaf certificate (if any) as a DER-encoded buffer.
-    #[inline]
-    pub fn peer_cert(&self) -> Option<&[u8]> {
-        self.handshake.peer_cert()
-    }
+                        available_versions.push(version);
+                    }
 
-    /// Returns the peer's certificate chain (if any) as a vector of DER-encoded
-    /// buffers.
-    ///
-    /// The certificate at index 0 is the peer's leaf certificate, the other
-    /// certificates (if any) are the chain certificate authorities used to
-    /// sign the leaf certificate.
-    #[inline]
-    pub fn peer_cert_chain(&self) -> Option<Vec<&[u8]>> {
-        self.handshake.peer_cert_chain()
-    }
+                    tp.version_information = Some(VersionInformation {
+                        chosen_version,
+                        available_versions,
+                    });
+                },
 
-    /// Returns the serialized cryptographic session for the connection.
-    ///
-    /// This can be used by a client to cache a connection's session, and resume
-    /// it later using the [`set_session()`] method.
-    ///
-    /// [`set_session()`]: struct.Connection.html#method.set_session
-    #[inline]
-    pub fn session(&self) -> Option<&[u8]> {
-        self.session.as_deref()
-    }
+                0x1312 => {
+                    let bdp_tokens = val.get_varint()?;
 
-    /// Returns the source connection ID.
-    ///
-    /// When there are multiple IDs, and if there is an active path, the ID used
-    /// on that path is returned. Otherwise the oldest ID is returned.
-    ///
-    /// Note that the value returned can change throughout the connection's
-    /// lifetime.
-    #[inline]
-    pub fn source_id(&self) -> ConnectionId {
-        if let Ok(path) = self.paths.get_active() {
-            if let Some(active_scid_seq) = path.active_scid_seq {
-                if let Ok(e) = self.ids.get_scid(active_scid_seq) {
-                    return ConnectionId::from_ref(e.cid.as_ref());
+                    if bdp_tokens > 1 {
+                        return Err(Error::InvalidTransportParam);
+                    }
+
+                    tp.bdp_tokens = bdp_tokens != 0;
                 }
-            }
-        }
 
-        let e = self.ids.oldest_scid();
-        ConnectionId::from_ref(e.cid.as_ref())
-    }
+                0x1314 => {
+                    let bdp_frame = val.get_varint()?;
 
-    /// Returns all active source connection IDs.
-    ///
-    /// An iterator is returned for all active IDs (i.e. ones that have not
-    /// been explicitly retired yet).
-    #[inline]
-    pub fn source_ids(&self) -> impl Iterator<Item = &ConnectionId> {
-        self.ids.scids_iter()
-    }
+                    if bdp_frame > 1 {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-    /// Returns the destination connection ID.
-    ///
-    /// Note that the value returned can change throughout the connection's
-    /// lifetime.
-    #[inline]
-    pub fn destination_id(&self) -> ConnectionId {
-        if let Ok(path) = self.paths.get_active() {
-            if let Some(active_dcid_seq) = path.active_dcid_seq {
-                if let Ok(e) = self.ids.get_dcid(active_dcid_seq) {
-                    return ConnectionId::from_ref(e.cid.as_ref());
-                }
-            }
-        }
+                    tp.bdp_frame = bdp_frame != 0;
+                },
 
-        let e = self.ids.oldest_dcid();
-        ConnectionId::from_ref(e.cid.as_ref())
-    }
+                0x1315 => {
+                    let cr_hints = val.get_varint()?;
 
-    /// Returns true if the connection handshake is complete.
-    #[inline]
-    pub fn is_established(&self) -> bool {
-        self.handshake_completed
-    }
+                    if cr_hints > 1 {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-    /// Returns true if the connection is resumed.
-    #[inline]
-    pub fn is_resumed(&self) -> bool {
-        self.handshake.is_resumed()
-    }
+                    tp.cr_hints = cr_hints != 0;
+                },
 
-    /// Returns true if the connection has a pending handshake that has
-    /// progressed enough to send or receive early data.
-    #[inline]
-    pub fn is_in_early_data(&self) -> bool {
-        self.handshake.is_in_early_data()
-    }
+                0x1313 => {
+                    // Only clients echo blobs back, servers can only advertise
+                    // that they accept them.
+                    if !is_server && val.cap() > 0 {
+                        return Err(Error::InvalidTransportParam);
+                    }
 
-    /// Returns whether there is stream or DATAGRAM data available to read.
-    #[inline]
-    pub fn is_readable(&self) -> bool {
-        self.streams.has_readable() || self.dgram_recv_front_len().is_some()
-    }
+                    tp.careful_resume_blob = Some(val.to_vec());
+                },
 
-    /// Returns whether the network path with local address `from` and remote
-    /// address `peer` has been validated.
-    ///
-    /// If the 4-tuple does not exist over the connection, returns an
-    /// [`InvalidState`].
-    ///
-    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
-    pub fn is_path_validated(
-        &self, from: SocketAddr, to: SocketAddr,
-    ) -> Result<bool> {
-        let pid = self
-            .paths
-            .path_id_from_addrs(&(from, to))
-            .ok_or(Error::InvalidState)?;
+                0x17f7586d2cb571 => {
+                    tp.reset_stream_at = true;
+                },
 
-        Ok(self.paths.get(pid)?.validated())
-    }
+                // Ignore unknown parameters.
+                _ => (),
+            }
+        }
 
-    /// Returns true if the connection is draining.
-    ///
-    /// If this returns `true`, the connection object cannot yet be dropped, but
-    /// no new application data can be sent or received. An application should
-    /// continue calling the [`recv()`], [`timeout()`], and [`on_timeout()`]
-    /// methods as normal, until the [`is_closed()`] method returns `true`.
-    ///
-    /// In contrast, once `is_draining()` returns `true`, calling [`send()`]
-    /// is not required because no new outgoing packets will be generated.
-    ///
-    /// [`recv()`]: struct.Connection.html#method.recv
-    /// [`send()`]: struct.Connection.html#method.send
-    /// [`timeout()`]: struct.Connection.html#method.timeout
-    /// [`on_timeout()`]: struct.Connection.html#method.on_timeout
-    /// [`is_closed()`]: struct.Connection.html#method.is_closed
-    #[inline]
-    pub fn is_draining(&self) -> bool {
-        self.draining_timer.is_some()
+        Ok(tp)
     }
 
-    /// Returns true if the connection is closed.
-    ///
-    /// If this returns true, the connection object can be dropped.
-    #[inline]
-    pub fn is_closed(&self) -> bool {
-        self.closed
-    }
+    fn encode_param(
+        b: &mut octets::OctetsMut, ty: u64, len: usize,
+    ) -> Result<()> {
+        b.put_varint(ty)?;
+        b.put_varint(len as u64)?;
 
-    /// Returns true if the connection was closed due to the idle timeout.
-    #[inline]
-    pub fn is_timed_out(&self) -> bool {
-        self.timed_out
+        Ok(())
     }
 
-    /// Returns the error received from the peer, if any.
-    ///
-    /// Note that a `Some` return value does not necessarily imply
-    /// [`is_closed()`] or any other connection state.
-    ///
-    /// [`is_closed()`]: struct.Connection.html#method.is_closed
-    #[inline]
-    pub fn peer_error(&self) -> Option<&ConnectionError> {
-        self.peer_error.as_ref()
-    }
+    fn encode<'a>(
+        tp: &TransportParams, is_server: bool, out: &'a mut [u8],
+    ) -> Result<&'a mut [u8]> {
+        let mut b = octets::OctetsMut::with_slice(out);
 
-    /// Returns the error [`close()`] was called with, or internally
-    /// created quiche errors, if any.
-    ///
-    /// Note that a `Some` return value does not necessarily imply
-    /// [`is_closed()`] or any other connection state.
-    /// `Some` also does not guarantee that the error has been sent to
-    /// or received by the peer.
-    ///
-    /// [`close()`]: struct.Connection.html#method.close
-    /// [`is_closed()`]: struct.Connection.html#method.is_closed
-    #[inline]
-    pub fn local_error(&self) -> Option<&ConnectionError> {
-        self.local_error.as_ref()
-    }
+        if is_server {
+            if let Some(ref odcid) = tp.original_destination_connection_id {
+                TransportParams::encode_param(&mut b, 0x0000, odcid.len())?;
+                b.put_bytes(odcid)?;
+            }
+        };
 
-    /// Collects and returns statistics about the connection.
-    #[inline]
-    pub fn stats(&self) -> Stats {
-        Stats {
-            recv: self.recv_count,
-            sent: self.sent_count,
-            lost: self.lost_count,
-            retrans: self.retrans_count,
-            sent_bytes: self.sent_bytes,
-            recv_bytes: self.recv_bytes,
-            acked_bytes: self.acked_bytes,
-            lost_bytes: self.lost_bytes,
-            stream_retrans_bytes: self.stream_retrans_bytes,
-            paths_count: self.paths.len(),
-            reset_stream_count_local: self.reset_stream_local_count,
-            stopped_stream_count_local: self.stopped_stream_local_count,
-            reset_stream_count_remote: self.reset_stream_remote_count,
-            stopped_stream_count_remote: self.stopped_stream_remote_count,
-            path_challenge_rx_count: self.path_challenge_rx_count,
+        if tp.max_idle_timeout != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0001,
+                octets::varint_len(tp.max_idle_timeout),
+            )?;
+            b.put_varint(tp.max_idle_timeout)?;
         }
-    }
 
-    /// Returns reference to peer's transport parameters. Returns `None` if we
-    /// have not yet processed the peer's transport parameters.
-    pub fn peer_transport_params(&self) -> Option<&TransportParams> {
-        if !self.parsed_peer_transport_params {
-            return None;
+        if is_server {
+            if let Some(ref token) = tp.stateless_reset_token {
+                TransportParams::encode_param(&mut b, 0x0002, 16)?;
+                b.put_bytes(&token.to_be_bytes())?;
+            }
         }
 
-        Some(&self.peer_transport_params)
-    }
+        if tp.max_udp_payload_size != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0003,
+                octets::varint_len(tp.max_udp_payload_size),
+            )?;
+            b.put_varint(tp.max_udp_payload_size)?;
+        }
 
-    /// Collects and returns statistics about each known path for the
-    /// connection.
-    pub fn path_stats(&self) -> impl Iterator<Item = PathStats> + '_ {
-        self.paths.iter().map(|(_, p)| p.stats())
-    }
+        if tp.initial_max_data != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0004,
+                octets::varint_len(tp.initial_max_data),
+            )?;
+            b.put_varint(tp.initial_max_data)?;
+        }
 
-    /// Returns whether or not this is a server-side connection.
-    pub fn is_server(&self) -> bool {
-        self.is_server
-    }
+        if tp.initial_max_stream_data_bidi_local != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0005,
+                octets::varint_len(tp.initial_max_stream_data_bidi_local),
+            )?;
+            b.put_varint(tp.initial_max_stream_data_bidi_local)?;
+        }
 
-    fn encode_transport_params(&mut self) -> Result<()> {
-        let mut raw_params = [0; 128];
+        if tp.initial_max_stream_data_bidi_remote != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0006,
+                octets::varint_len(tp.initial_max_stream_data_bidi_remote),
+            )?;
+            b.put_varint(tp.initial_max_stream_data_bidi_remote)?;
+        }
 
-        let raw_params = TransportParams::encode(
-            &self.local_transport_params,
-            self.is_server,
-            &mut raw_params,
-        )?;
+        if tp.initial_max_stream_data_uni != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0007,
+                octets::varint_len(tp.initial_max_stream_data_uni),
+            )?;
+            b.put_varint(tp.initial_max_stream_data_uni)?;
+        }
 
-        self.handshake.set_quic_transport_params(raw_params)?;
+        if tp.initial_max_streams_bidi != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0008,
+                octets::varint_len(tp.initial_max_streams_bidi),
+            )?;
+            b.put_varint(tp.initial_max_streams_bidi)?;
+        }
 
-        Ok(())
-    }
+        if tp.initial_max_streams_uni != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x0009,
+                octets::varint_len(tp.initial_max_streams_uni),
+            )?;
+            b.put_varint(tp.initial_max_streams_uni)?;
+        }
 
-    fn parse_peer_transport_params(
-        &mut self, peer_params: TransportParams,
-    ) -> Result<()> {
-        // Validate initial_source_connection_id.
-        match &peer_params.initial_source_connection_id {
-            Some(v) if v != &self.destination_id() =>
-                return Err(Error::InvalidTransportParam),
+        if tp.ack_delay_exponent != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x000a,
+                octets::varint_len(tp.ack_delay_exponent),
+            )?;
+            b.put_varint(tp.ack_delay_exponent)?;
+        }
 
-            Some(_) => (),
+        if tp.max_ack_delay != 0 {
+            TransportParams::encode_param(
+                &mut b,
+                0x000b,
+                octets::varint_len(tp.max_ack_delay),
+            )?;
+            b.put_varint(tp.max_ack_delay)?;
+        }
 
-            // initial_source_connection_id must be sent by
-            // both endpoints.
-            None => return Err(Error::InvalidTransportParam),
+        if tp.disable_active_migration {
+            TransportParams::encode_param(&mut b, 0x000c, 0)?;
         }
 
-        // Validate original_destination_connection_id.
-        if let Some(odcid) = &self.odcid {
-            match &peer_params.original_destination_connection_id {
-                Some(v) if v != odcid =>
-                    return Err(Error::InvalidTransportParam),
+        // TODO: encode preferred_address
 
-                Some(_) => (),
+        if tp.active_conn_id_limit != 2 {
+            TransportParams::encode_param(
+                &mut b,
+                0x000e,
+                octets::varint_len(tp.active_conn_id_limit),
+            )?;
+            b.put_varint(tp.active_conn_id_limit)?;
+        }
 
-                // original_destination_connection_id must be
-                // sent by the server.
-                None if !self.is_server =>
-                    return Err(Error::InvalidTransportParam),
+        if let Some(scid) = &tp.initial_source_connection_id {
+            TransportParams::encode_param(&mut b, 0x000f, scid.len())?;
+            b.put_bytes(scid)?;
+        }
 
-                None => (),
+        if is_server {
+            if let Some(scid) = &tp.retry_source_connection_id {
+                TransportParams::encode_param(&mut b, 0x0010, scid.len())?;
+                b.put_bytes(scid)?;
             }
         }
 
-        // Validate retry_source_connection_id.
-        if let Some(rscid) = &self.rscid {
-            match &peer_params.retry_source_connection_id {
-                Some(v) if v != rscid =>
-                    return Err(Error::InvalidTransportParam),
-
-                Some(_) => (),
+        if let Some(max_datagram_frame_size) = tp.max_datagram_frame_size {
+            TransportParams::encode_param(
+                &mut b,
+                0x0020,
+                octets::varint_len(max_datagram_frame_size),
+            )?;
+            b.put_varint(max_datagram_frame_size)?;
+        }
 
-                // retry_source_connection_id must be sent by
-                // the server.
-                None => return Err(Error::InvalidTransportParam),
-            }
+        if tp.bdp_tokens {
+            TransportParams::encode_param(
+                &mut b,
+                0x1312,
+                octets::varint_len(1),
+            )?;
+            b.put_varint(1)?;
         }
 
-        self.process_peer_transport_params(peer_params)?;
+        if tp.bdp_frame {
+            TransportParams::encode_param(
+                &mut b,
+                0x1314,
+                octets::varint_len(1),
+            )?;
+            b.put_varint(1)?;
+        }
 
-        self.parsed_peer_transport_params = true;
+        if tp.cr_hints {
+            TransportParams::encode_param(&mut b, 0x1315, octets::varint_len(1))?;
+            b.put_varint(1)?;
+        }
 
-        Ok(())
-    }
+        if let Some(blob) = &tp.careful_resume_blob {
+            TransportParams::encode_param(&mut b, 0x1313, blob.len())?;
+            b.put_bytes(blob)?;
+        }
 
-    fn process_peer_transport_params(
-        &mut self, peer_params: TransportParams,
-    ) -> Result<()> {
-        self.max_tx_data = peer_params.initial_max_data;
+        if tp.reset_stream_at {
+            TransportParams::encode_param(&mut b, 0x17f7586d2cb571, 0)?;
+        }
 
-        // Update send capacity.
-        self.update_tx_cap();
+        if let Some(info) = &tp.version_information {
+            TransportParams::encode_param(
+                &mut b,
+                0x0011,
+                4 + 4 * info.available_versions.len(),
+            )?;
+            b.put_u32(info.chosen_version)?;
 
-        self.streams
-            .update_peer_max_streams_bidi(peer_params.initial_max_streams_bidi);
-        self.streams
-            .update_peer_max_streams_uni(peer_params.initial_max_streams_uni);
+            for &v in &info.available_versions {
+                b.put_u32(v)?;
+            }
+        }
 
-        let max_ack_delay =
-            time::Duration::from_millis(peer_params.max_ack_delay);
+        let out_len = b.off();
 
-        self.recovery_config.max_ack_delay = max_ack_delay;
+        Ok(&mut out[..out_len])
+    }
 
-        let active_path = self.paths.get_active_mut()?;
+    /// Creates a qlog event for connection transport parameters and TLS fields
+    #[cfg(feature = "qlog")]
+    pub fn to_qlog(
+        &self, owner: TransportOwner, cipher: Option<crypto::Algorithm>,
+    ) -> EventData {
+        let original_destination_connection_id = qlog::HexSlice::maybe_string(
+            self.original_destination_connection_id.as_ref(),
+        );
 
-        active_path.recovery.update_max_ack_delay(max_ack_delay);
+        let stateless_reset_token = qlog::HexSlice::maybe_string(
+            self.stateless_reset_token.map(|s| s.to_be_bytes()).as_ref(),
+        );
 
-        if active_path.pmtud.get_probe_status() {
-            active_path.recovery.pmtud_update_max_datagram_size(
-                active_path
-                    .pmtud
-                    .get_probe_size()
-                    .min(peer_params.max_udp_payload_size as usize),
-            );
-        } else {
-            active_path.recovery.update_max_datagram_size(
-                peer_params.max_udp_payload_size as usize,
-            );
-        }
+        EventData::TransportParametersSet(
+            qlog::events::quic::TransportParametersSet {
+                owner: Some(owner),
+                resumption_allowed: None,
+                early_data_enabled: None,
+                tls_cipher: Some(format!("{cipher:?}")),
+                aead_tag_length: None,
+                original_destination_connection_id,
+                initial_source_connection_id: None,
+                retry_source_connection_id: None,
+                stateless_reset_token,
+                disable_active_migration: Some(self.disable_active_migration),
+                max_idle_timeout: Some(self.max_idle_timeout),
+                max_udp_payload_size: Some(self.max_udp_payload_size as u32),
+                ack_delay_exponent: Some(self.ack_delay_exponent as u16),
+                max_ack_delay: Some(self.max_ack_delay as u16),
+                active_connection_id_limit: Some(
+                    self.active_conn_id_limit as u32,
+                ),
 
-        // Record the max_active_conn_id parameter advertised by the peer.
-        self.ids
-            .set_source_conn_id_limit(peer_params.active_conn_id_limit);
+                initial_max_data: Some(self.initial_max_data),
+                initial_max_stream_data_bidi_local: Some(
+                    self.initial_max_stream_data_bidi_local,
+                ),
+                initial_max_stream_data_bidi_remote: Some(
+                    self.initial_max_stream_data_bidi_remote,
+                ),
+                initial_max_stream_data_uni: Some(
+                    self.initial_max_stream_data_uni,
+                ),
+                initial_max_streams_bidi: Some(self.initial_max_streams_bidi),
+                initial_max_streams_uni: Some(self.initial_max_streams_uni),
 
-        self.peer_transport_params = peer_params;
+                preferred_address: None,
 
-        Ok(())
+                bdp_tokens: Some(self.bdp_tokens),
+            },
+        )
     }
+}
 
-    /// Continues the handshake.
-    ///
-    /// If the connection is already established, it does nothing.
-    fn do_handshake(&mut self, now: time::Instant) -> Result<()> {
-        let mut ex_data = tls::ExData {
-            application_protos: &self.application_protos,
-
-            pkt_num_spaces: &mut self.pkt_num_spaces,
-
-            session: &mut self.session,
+#[doc(hidden)]
+pub mod testing {
+    use super::*;
 
-            local_error: &mut self.local_error,
+    /// Makes the randomness used by quiche on the current thread
+    /// deterministic, or random again when `seed` is `None`.
+    ///
+    /// This covers the initial destination connection ID of clients, the
+    /// connection IDs and reset tokens generated by [`Pipe`] and
+    /// [`create_cid_and_reset_token()`], PATH_CHALLENGE data, the probing
+    /// cycles of the BBR congestion controllers, the nonces of Careful Resume
+    /// blobs and HTTP/3 GREASE values, so that tests and simulations seeded
+    /// the same way take the same decisions and produce the same traces. The
+    /// TLS handshake still draws from the TLS library's own generator.
+    ///
+    /// This is only available in quiche's own tests and with the
+    /// `deterministic-rand` feature, which must never be enabled in
+    /// production.
+    #[cfg(any(test, feature = "deterministic-rand"))]
+    pub fn set_rand_seed(seed: Option<u64>) {
+        rand::set_seed(seed);
+    }
 
-            keylog: self.keylog.as_mut(),
+    pub struct Pipe {
+        pub client: Connection,
+        pub server: Connection,
+    }
 
-            trace_id: &self.trace_id,
+    impl Pipe {
+        pub fn new() -> Result<Pipe> {
+            let mut config = Config::new(crate::PROTOCOL_VERSION)?;
+            config.load_cert_chain_from_pem_file("examples/cert.crt")?;
+            config.load_priv_key_from_pem_file("examples/cert.key")?;
+            config.set_application_protos(&[b"proto1", b"proto2"])?;
+            config.set_initial_max_data(30);
+            config.set_initial_max_stream_data_bidi_local(15);
+            config.set_initial_max_stream_data_bidi_remote(15);
+            config.set_initial_max_stream_data_uni(10);
+            config.set_initial_max_streams_bidi(3);
+            config.set_initial_max_streams_uni(3);
+            config.set_max_idle_timeout(180_000);
+            config.verify_peer(false);
+            config.set_ack_delay_exponent(8);
 
-            is_server: self.is_server,
-        };
+            Pipe::with_config(&mut config)
+        }
 
-        if self.handshake_completed {
-            return self.handshake.process_post_handshake(&mut ex_data);
+        pub fn client_addr() -> SocketAddr {
+            "127.0.0.1:1234".parse().unwrap()
         }
 
-        match self.handshake.do_handshake(&mut ex_data) {
-            Ok(_) => (),
+        pub fn server_addr() -> SocketAddr {
+            "127.0.0.1:4321".parse().unwrap()
+        }
 
-            Err(Error::Done) => {
-                // Try to parse transport parameters as soon as the first flight
-                // of handshake data is processed.
-                //
-                // This is potentially dangerous as the handshake hasn't been
-                // completed yet, though it's required to be able to send data
-                // in 0.5 RTT.
-                let raw_params = self.handshake.quic_transport_params();
+        pub fn with_config(config: &mut Config) -> Result<Pipe> {
+            let mut client_scid = [0; 16];
+            rand::rand_bytes(&mut client_scid[..]);
+            let client_scid = ConnectionId::from_ref(&client_scid);
+            let client_addr = Pipe::client_addr();
 
-                if !self.parsed_peer_transport_params && !raw_params.is_empty() {
-                    let peer_params =
-                        TransportParams::decode(raw_params, self.is_server)?;
+            let mut server_scid = [0; 16];
+            rand::rand_bytes(&mut server_scid[..]);
+            let server_scid = ConnectionId::from_ref(&server_scid);
+            let server_addr = Pipe::server_addr();
 
-                    self.parse_peer_transport_params(peer_params)?;
-                }
+            Ok(Pipe {
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    client_addr,
+                    server_addr,
+                    config,
+                )?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    config,
+                )?,
+            })
+        }
 
-                return Ok(());
-            },
+        pub fn with_config_and_scid_lengths(
+            config: &mut Config, client_scid_len: usize, server_scid_len: usize,
+        ) -> Result<Pipe> {
+            let mut client_scid = vec![0; client_scid_len];
+            rand::rand_bytes(&mut client_scid[..]);
+            let client_scid = ConnectionId::from_ref(&client_scid);
+            let client_addr = Pipe::client_addr();
 
-            Err(e) => return Err(e),
-        };
+            let mut server_scid = vec![0; server_scid_len];
+            rand::rand_bytes(&mut server_scid[..]);
+            let server_scid = ConnectionId::from_ref(&server_scid);
+            let server_addr = Pipe::server_addr();
 
-        self.handshake_completed = self.handshake.is_completed();
+            Ok(Pipe {
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    client_addr,
+                    server_addr,
+                    config,
+                )?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    config,
+                )?,
+            })
+        }
 
-        self.alpn = self.handshake.alpn_protocol().to_vec();
+        pub fn with_client_config(client_config: &mut Config) -> Result<Pipe> {
+            let mut client_scid = [0; 16];
+            rand::rand_bytes(&mut client_scid[..]);
+            let client_scid = ConnectionId::from_ref(&client_scid);
+            let client_addr = Pipe::client_addr();
 
-        let raw_params = self.handshake.quic_transport_params();
+            let mut server_scid = [0; 16];
+            rand::rand_bytes(&mut server_scid[..]);
+            let server_scid = ConnectionId::from_ref(&server_scid);
+            let server_addr = Pipe::server_addr();
 
-        if !self.parsed_peer_transport_params && !raw_params.is_empty() {
-            let peer_params =
-                TransportParams::decode(raw_params, self.is_server)?;
+            let mut config = Config::new(crate::PROTOCOL_VERSION)?;
+            config.load_cert_chain_from_pem_file("examples/cert.crt")?;
+            config.load_priv_key_from_pem_file("examples/cert.key")?;
+            config.set_application_protos(&[b"proto1", b"proto2"])?;
+            config.set_initial_max_data(30);
+            config.set_initial_max_stream_data_bidi_local(15);
+            config.set_initial_max_stream_data_bidi_remote(15);
+            config.set_initial_max_streams_bidi(3);
+            config.set_initial_max_streams_uni(3);
+            config.set_ack_delay_exponent(8);
 
-            self.parse_peer_transport_params(peer_params)?;
+            Ok(Pipe {
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    client_addr,
+                    server_addr,
+                    client_config,
+                )?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    &mut config,
+                )?,
+            })
         }
 
-        if self.handshake_completed {
-            // The handshake is considered confirmed at the server when the
-            // handshake completes, at which point we can also drop the
-            // handshake epoch.
-            if self.is_server {
-                self.handshake_confirmed = true;
+        pub fn with_server_config(server_config: &mut Config) -> Result<Pipe> {
+            let mut client_scid = [0; 16];
+            rand::rand_bytes(&mut client_scid[..]);
+            let client_scid = ConnectionId::from_ref(&client_scid);
+            let client_addr = Pipe::client_addr();
 
-                self.drop_epoch_state(packet::Epoch::Handshake, now);
-            }
+            let mut server_scid = [0; 16];
+            rand::rand_bytes(&mut server_scid[..]);
+            let server_scid = ConnectionId::from_ref(&server_scid);
+            let server_addr = Pipe::server_addr();
 
-            // Once the handshake is completed there's no point in processing
-            // 0-RTT packets anymore, so clear the buffer now.
-            self.undecryptable_pkts.clear();
+            let mut config = Config::new(crate::PROTOCOL_VERSION)?;
+            config.set_application_protos(&[b"proto1", b"proto2"])?;
+            config.set_initial_max_data(30);
+            config.set_initial_max_stream_data_bidi_local(15);
+            config.set_initial_max_stream_data_bidi_remote(15);
+            config.set_initial_max_streams_bidi(3);
+            config.set_initial_max_streams_uni(3);
+            config.set_ack_delay_exponent(8);
 
-            trace!("{} connection established: proto={:?} cipher={:?} curve={:?} sigalg={:?} resumed={} {:?}",
-                   &self.trace_id,
-                   std::str::from_utf8(self.application_proto()),
-                   self.handshake.cipher(),
-                   self.handshake.curve(),
-                   self.handshake.sigalg(),
-                   self.handshake.is_resumed(),
-                   self.peer_transport_params);
+            Ok(Pipe {
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    client_addr,
+                    server_addr,
+                    &mut config,
+                )?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    server_config,
+                )?,
+            })
         }
 
-        Ok(())
-    }
-
-    /// Selects the packet type for the next outgoing packet.
-    fn write_pkt_type(&self, send_pid: usize) -> Result<packet::Type> {
-        // On error send packet in the latest epoch available, but only send
-        // 1-RTT ones when the handshake is completed.
-        if self
-            .local_error
-            .as_ref()
-            .map_or(false, |conn_err| !conn_err.is_app)
-        {
-            let epoch = match self.handshake.write_level() {
-                crypto::Level::Initial => packet::Epoch::Initial,
-                crypto::Level::ZeroRTT => unreachable!(),
-                crypto::Level::Handshake => packet::Epoch::Handshake,
-                crypto::Level::OneRTT => packet::Epoch::Application,
-            };
+        pub fn with_client_and_server_config(
+            client_config: &mut Config, server_config: &mut Config,
+        ) -> Result<Pipe> {
+            let mut client_scid = [0; 16];
+            rand::rand_bytes(&mut client_scid[..]);
+            let client_scid = ConnectionId::from_ref(&client_scid);
+            let client_addr = Pipe::client_addr();
 
-            if !self.is_established() {
-                match epoch {
-                    // Downgrade the epoch to Handshake as the handshake is not
-                    // completed yet.
-                    packet::Epoch::Application =>
-                        return Ok(packet::Type::Handshake),
+            let mut server_scid = [0; 16];
+            rand::rand_bytes(&mut server_scid[..]);
+            let server_scid = ConnectionId::from_ref(&server_scid);
+            let server_addr = Pipe::server_addr();
 
-                    // Downgrade the epoch to Initial as the remote peer might
-                    // not be able to decrypt handshake packets yet.
-                    packet::Epoch::Handshake
-                        if self.pkt_num_spaces[packet::Epoch::Initial]
-                            .has_keys() =>
-                        return Ok(packet::Type::Initial),
+            Ok(Pipe {
+                client: connect(
+                    Some("quic.tech"),
+                    &client_scid,
+                    client_addr,
+                    server_addr,
+                    client_config,
+                )?,
+                server: accept(
+                    &server_scid,
+                    None,
+                    server_addr,
+                    client_addr,
+                    server_config,
+                )?,
+            })
+        }
 
-                    _ => (),
-                };
+        pub fn handshake(&mut self) -> Result<()> {
+            while !self.client.is_established() || !self.server.is_established() {
+                let flight = emit_flight(&mut self.client)?;
+                process_flight(&mut self.server, flight)?;
+
+                let flight = emit_flight(&mut self.server)?;
+                process_flight(&mut self.client, flight)?;
             }
 
-            return Ok(packet::Type::from_epoch(epoch));
+            Ok(())
         }
 
-        for &epoch in packet::Epoch::epochs(
-            packet::Epoch::Initial..=packet::Epoch::Application,
-        ) {
-            // Only send packets in a space when we have the send keys for it.
-            if self.pkt_num_spaces[epoch].crypto_seal.is_none() {
-                continue;
-            }
+        pub fn advance(&mut self) -> Result<()> {
+            let mut client_done = false;
+            let mut server_done = false;
 
-            // We are ready to send data for this packet number space.
-            if self.pkt_num_spaces[epoch].ready() {
-                return Ok(packet::Type::from_epoch(epoch));
-            }
+            while !client_done || !server_done {
+                match emit_flight(&mut self.client) {
+                    Ok(flight) => process_flight(&mut self.server, flight)?,
 
-            // There are lost frames in this packet number space.
-            for (_, p) in self.paths.iter() {
-                if p.recovery.has_lost_frames(epoch) {
-                    return Ok(packet::Type::from_epoch(epoch));
-                }
+                    Err(Error::Done) => client_done = true,
 
-                // We need to send PTO probe packets.
-                if p.recovery.loss_probes(epoch) > 0 {
-                    return Ok(packet::Type::from_epoch(epoch));
-                }
-            }
-        }
+                    Err(e) => return Err(e),
+                };
 
-        // If there are flushable, almost full or blocked streams, use the
-        // Application epoch.
-        let send_path = self.paths.get(send_pid)?;
-        if (self.is_established() || self.is_in_early_data()) &&
-            (self.should_send_handshake_done() ||
-                self.almost_full ||
-                self.blocked_limit.is_some() ||
-                self.dgram_send_queue.has_pending() ||
-                self.local_error
-                    .as_ref()
-                    .map_or(false, |conn_err| conn_err.is_app) ||
-                self.streams.should_update_max_streams_bidi() ||
-                self.streams.should_update_max_streams_uni() ||
-                self.streams.has_flushable() ||
-                self.streams.has_almost_full() ||
-                self.streams.has_blocked() ||
-                self.streams.has_reset() ||
-                self.streams.has_stopped() ||
-                self.ids.has_new_scids() ||
-                self.ids.has_retire_dcids() ||
-                send_path.pmtud.get_probe_status() ||
-                send_path.needs_ack_eliciting ||
-                send_path.probing_required())
-        {
-            // Only clients can send 0-RTT packets.
-            if !self.is_server && self.is_in_early_data() {
-                return Ok(packet::Type::ZeroRTT);
-            }
+                match emit_flight(&mut self.server) {
+                    Ok(flight) => process_flight(&mut self.client, flight)?,
 
-            return Ok(packet::Type::Short);
-        }
+                    Err(Error::Done) => server_done = true,
 
-        Err(Error::Done)
-    }
+                    Err(e) => return Err(e),
+                };
+            }
 
-    /// Returns the mutable stream with the given ID if it exists, or creates
-    /// a new one otherwise.
-    fn get_or_create_stream(
-        &mut self, id: u64, local: bool,
-    ) -> Result<&mut stream::Stream> {
-        self.streams.get_or_create(
-            id,
-            &self.local_transport_params,
-            &self.peer_transport_params,
-            local,
-            self.is_server,
-            self.default_stream_window
-        )
-    }
+            Ok(())
+        }
 
-    /// Processes an incoming frame.
-    fn process_frame(
-        &mut self, frame: frame::Frame, hdr: &packet::Header,
-        recv_path_id: usize, epoch: packet::Epoch, now: time::Instant,
-    ) -> Result<()> {
-        trace!("{} rx frm {:?}", self.trace_id, frame);
+        pub fn client_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let server_path = &self.server.paths.get_active().unwrap();
+            let info = RecvInfo {
+                to: server_path.peer_addr(),
+                from: server_path.local_addr(),
+            };
 
-        match frame {
-            frame::Frame::Padding { .. } => (),
+            self.client.recv(buf, info)
+        }
 
-            frame::Frame::Ping { .. } => (),
+        pub fn server_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let client_path = &self.client.paths.get_active().unwrap();
+            let info = RecvInfo {
+                to: client_path.peer_addr(),
+                from: client_path.local_addr(),
+            };
 
-            frame::Frame::ACK {
-                ranges, ack_delay, ..
-            } => {
-                let ack_delay = ack_delay
-                    .checked_mul(2_u64.pow(
-                        self.peer_transport_params.ack_delay_exponent as u32,
-                    ))
-                    .ok_or(Error::InvalidFrame)?;
+            self.server.recv(buf, info)
+        }
 
-                if epoch == packet::Epoch::Handshake ||
-                    (epoch == packet::Epoch::Application &&
-                        self.is_established())
-                {
-                    self.peer_verified_initial_address = true;
-                }
+        pub fn send_pkt_to_server(
+            &mut self, pkt_type: packet::Type, frames: &[frame::Frame],
+            buf: &mut [u8],
+        ) -> Result<usize> {
+            let written = encode_pkt(&mut self.client, pkt_type, frames, buf)?;
+            recv_send(&mut self.server, buf, written)
+        }
 
-                let handshake_status = self.handshake_status();
+        pub fn client_update_key(&mut self) -> Result<()> {
+            let space =
+                &mut self.client.pkt_num_spaces[packet::Epoch::Application];
 
-                let is_app_limited = self.delivery_rate_check_if_app_limited();
+            let open_next = space
+                .crypto_open
+                .as_ref()
+                .unwrap()
+                .derive_next_packet_key()
+                .unwrap();
 
-                for (_, p) in self.paths.iter_mut() {
-                    if is_app_limited {
-                        p.recovery.delivery_rate_update_app_limited(true);
-                    }
+            let seal_next = space
+                .crypto_seal
+                .as_ref()
+                .unwrap()
+                .derive_next_packet_key()?;
 
-                    let (lost_packets, lost_bytes, acked_bytes) =
-                        p.recovery.on_ack_received(
-                            &ranges,
-                            ack_delay,
-                            epoch,
-                            handshake_status,
-                            now,
-                            &self.trace_id,
-                        )?;
+            let open_prev = space.crypto_open.replace(open_next);
+            space.crypto_seal.replace(seal_next);
 
-                    self.lost_count += lost_packets;
-                    self.lost_bytes += lost_bytes as u64;
-                    self.acked_bytes += acked_bytes as u64;
+            space.key_update = Some(packet::KeyUpdate {
+                crypto_open: open_prev.unwrap(),
+                pn_on_update: space.next_pkt_num,
+                update_acked: true,
+                timer: time::Instant::now(),
+            });
 
-                    qlog_with_type!(QLOG_PACKET_LOST, self.qlog, q, {
-                        for ev_data in p.recovery.packet_loss_qlog() {
-                            q.add_event_data_with_instant(ev_data, now).ok();
-                        }
-                    });
-                }
-            },
+            self.client.key_phase = !self.client.key_phase;
 
-            frame::Frame::ResetStream {
-                stream_id,
-                error_code,
-                final_size,
-            } => {
-                // Peer can't send on our unidirectional streams.
-                if !stream::is_bidi(stream_id) &&
-                    stream::is_local(stream_id, self.is_server)
-                {
-                    return Err(Error::InvalidStreamState(stream_id));
-                }
+            Ok(())
+        }
+    }
 
-                let max_rx_data_left = self.max_rx_data() - self.rx_data;
+    pub fn recv_send(
+        conn: &mut Connection, buf: &mut [u8], len: usize,
+    ) -> Result<usize> {
+        let active_path = conn.paths.get_active()?;
+        let info = RecvInfo {
+            to: active_path.local_addr(),
+            from: active_path.peer_addr(),
+        };
 
-                // Get existing stream or create a new one, but if the stream
-                // has already been closed and collected, ignore the frame.
-                //
-                // This can happen if e.g. an ACK frame is lost, and the peer
-                // retransmits another frame before it realizes that the stream
-                // is gone.
-                //
-                // Note that it makes it impossible to check if the frame is
-                // illegal, since we have no state, but since we ignore the
-                // frame, it should be fine.
-                let stream = match self.get_or_create_stream(stream_id, false) {
-                    Ok(v) => v,
+        conn.recv(&mut buf[..len], info)?;
 
-                    Err(Error::Done) => return Ok(()),
+        let mut off = 0;
 
-                    Err(e) => return Err(e),
-                };
+        match conn.send(&mut buf[off..]) {
+            Ok((write, _)) => off += write,
 
-                let was_readable = stream.is_readable();
-                let priority_key = Arc::clone(&stream.priority_key);
+            Err(Error::Done) => (),
 
-                let max_off_delta =
-                    stream.recv.reset(error_code, final_size)? as u64;
+            Err(e) => return Err(e),
+        }
 
-                if max_off_delta > max_rx_data_left {
-                    return Err(Error::FlowControl);
-                }
+        Ok(off)
+    }
 
-                if !was_readable && stream.is_readable() {
-                    self.streams.insert_readable(&priority_key);
-                }
+    pub fn process_flight(
+        conn: &mut Connection, flight: Vec<(Vec<u8>, SendInfo)>,
+    ) -> Result<()> {
+        for (mut pkt, si) in flight {
+            let info = RecvInfo {
+                to: si.to,
+                from: si.from,
+            };
 
-                self.rx_data += max_off_delta;
+            conn.recv(&mut pkt, info)?;
+        }
 
-                self.reset_stream_remote_count =
-                    self.reset_stream_remote_count.saturating_add(1);
-            },
+        Ok(())
+    }
 
-            frame::Frame::StopSending {
-                stream_id,
-                error_code,
-            } => {
-                // STOP_SENDING on a receive-only stream is a fatal error.
-                if !stream::is_local(stream_id, self.is_server) &&
-                    !stream::is_bidi(stream_id)
-                {
-                    return Err(Error::InvalidStreamState(stream_id));
-                }
+    pub fn emit_flight_with_max_buffer(
+        conn: &mut Connection, out_size: usize, from: Option<SocketAddr>,
+        to: Option<SocketAddr>,
+    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
+        let mut flight = Vec::new();
 
-                // Get existing stream or create a new one, but if the stream
-                // has already been closed and collected, ignore the frame.
-                //
-                // This can happen if e.g. an ACK frame is lost, and the peer
-                // retransmits another frame before it realizes that the stream
-                // is gone.
-                //
-                // Note that it makes it impossible to check if the frame is
-                // illegal, since we have no state, but since we ignore the
-                // frame, it should be fine.
-                let stream = match self.get_or_create_stream(stream_id, false) {
-                    Ok(v) => v,
+        loop {
+            let mut out = vec![0u8; out_size];
 
-                    Err(Error::Done) => return Ok(()),
+            let info = match conn.send_on_path(&mut out, from, to) {
+                Ok((written, info)) => {
+                    out.truncate(written);
+                    info
+                },
 
-                    Err(e) => return Err(e),
-                };
+                Err(Error::Done) => break,
 
-                let was_writable = stream.is_writable();
+                Err(e) => return Err(e),
+            };
 
-                let priority_key = Arc::clone(&stream.priority_key);
+            flight.push((out, info));
+        }
 
-                // Try stopping the stream.
-                if let Ok((final_size, unsent)) = stream.send.stop(error_code) {
-                    // Claw back some flow control allowance from data that was
-                    // buffered but not actually sent before the stream was
-                    // reset.
-                    //
-                    // Note that `tx_cap` will be updated later on, so no need
-                    // to touch it here.
-                    self.tx_data = self.tx_data.saturating_sub(unsent);
+        if flight.is_empty() {
+            return Err(Error::Done);
+        }
 
-                    self.tx_buffered =
-                        self.tx_buffered.saturating_sub(unsent as usize);
+        Ok(flight)
+    }
 
-                    self.streams.insert_reset(stream_id, error_code, final_size);
+    pub fn emit_flight_on_path(
+        conn: &mut Connection, from: Option<SocketAddr>, to: Option<SocketAddr>,
+    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
+        emit_flight_with_max_buffer(conn, 65535, from, to)
+    }
 
-                    if !was_writable {
-                        self.streams.insert_writable(&priority_key);
-                    }
+    pub fn emit_flight(
+        conn: &mut Connection,
+    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
+        emit_flight_on_path(conn, None, None)
+    }
 
-                    self.stopped_stream_remote_count =
-                        self.stopped_stream_remote_count.saturating_add(1);
-                    self.reset_stream_local_count =
-                        self.reset_stream_local_count.saturating_add(1);
-                }
-            },
+    pub fn encode_pkt(
+        conn: &mut Connection, pkt_type: packet::Type, frames: &[frame::Frame],
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let mut b = octets::OctetsMut::with_slice(buf);
 
-            frame::Frame::Crypto { data } => {
-                if data.max_off() >= MAX_CRYPTO_STREAM_OFFSET {
-                    return Err(Error::CryptoBufferExceeded);
-                }
+        let epoch = pkt_type.to_epoch()?;
 
-                // Push the data to the stream so it can be re-ordered.
-                self.pkt_num_spaces[epoch].crypto_stream.recv.write(data)?;
+        let space = &mut conn.pkt_num_spaces[epoch];
 
-                // Feed crypto data to the TLS state, if there's data
-                // available at the expected offset.
-                let mut crypto_buf = [0; 512];
+        let pn = space.next_pkt_num;
+        let pn_len = 4;
 
-                let level = crypto::Level::from_epoch(epoch);
+        let send_path = conn.paths.get_active()?;
+        let active_dcid_seq = send_path
+            .active_dcid_seq
+            .as_ref()
+            .ok_or(Error::InvalidState)?;
+        let active_scid_seq = send_path
+            .active_scid_seq
+            .as_ref()
+            .ok_or(Error::InvalidState)?;
 
-                let stream = &mut self.pkt_num_spaces[epoch].crypto_stream;
+        let hdr = Header {
+            ty: pkt_type,
+            version: conn.version,
+            dcid: ConnectionId::from_ref(
+                conn.ids.get_dcid(*active_dcid_seq)?.cid.as_ref(),
+            ),
+            scid: ConnectionId::from_ref(
+                conn.ids.get_scid(*active_scid_seq)?.cid.as_ref(),
+            ),
+            pkt_num: pn,
+            pkt_num_len: pn_len,
+            token: conn.token.clone(),
+            versions: None,
+            key_phase: conn.key_phase,
+        };
 
-                while let Ok((read, _)) = stream.recv.emit(&mut crypto_buf) {
-                    let recv_buf = &crypto_buf[..read];
-                    self.handshake.provide_data(level, recv_buf)?;
-                }
+        hdr.to_bytes(&mut b)?;
 
-                self.do_handshake(now)?;
-            },
+        let payload_len = frames.iter().fold(0, |acc, x| acc + x.wire_len());
 
-            frame::Frame::CryptoHeader { .. } => unreachable!(),
+        if pkt_type != packet::Type::Short {
+            let len = pn_len + payload_len + space.crypto_overhead().unwrap();
+            b.put_varint(len as u64)?;
+        }
 
-            frame::Frame::NewToken { token } => {
-                if self.is_server {
-                    return Err(Error::InvalidPacket);
-                }
-                self.received_tokens.push_back(token);
-            },
+        // Always encode packet number in 4 bytes, to allow encoding packets
+        // with empty payloads.
+        b.put_u32(pn as u32)?;
 
-            frame::Frame::Stream { stream_id, data } => {
-                // Peer can't send on our unidirectional streams.
-                if !stream::is_bidi(stream_id) &&
-                    stream::is_local(stream_id, self.is_server)
-                {
-                    return Err(Error::InvalidStreamState(stream_id));
-                }
+        let payload_offset = b.off();
 
-                let max_rx_data_left = self.max_rx_data() - self.rx_data;
+        for frame in frames {
+            frame.to_bytes(&mut b)?;
+        }
 
-                // Get existing stream or create a new one, but if the stream
-                // has already been closed and collected, ignore the frame.
-                //
-                // This can happen if e.g. an ACK frame is lost, and the peer
-                // retransmits another frame before it realizes that the stream
-                // is gone.
-                //
-                // Note that it makes it impossible to check if the frame is
-                // illegal, since we have no state, but since we ignore the
-                // frame, it should be fine.
-                let stream = match self.get_or_create_stream(stream_id, false) {
-                    Ok(v) => v,
+        let aead = match space.crypto_seal {
+            Some(ref v) => v,
+            None => return Err(Error::InvalidState),
+        };
 
-                    Err(Error::Done) => return Ok(()),
+        let written = packet::encrypt_pkt(
+            &mut b,
+            pn,
+            pn_len,
+            payload_len,
+            payload_offset,
+            None,
+            aead,
+        )?;
 
-                    Err(e) => return Err(e),
-                };
+        space.next_pkt_num += 1;
 
-                // Check for the connection-level flow control limit.
-                let max_off_delta =
-                    data.max_off().saturating_sub(stream.recv.max_off());
+        Ok(written)
+    }
 
-                if max_off_delta > max_rx_data_left {
-                    return Err(Error::FlowControl);
-                }
+    pub fn decode_pkt(
+        conn: &mut Connection, buf: &mut [u8],
+    ) -> Result<Vec<frame::Frame>> {
+        let mut b = octets::OctetsMut::with_slice(buf);
 
-                let was_readable = stream.is_readable();
-                let priority_key = Arc::clone(&stream.priority_key);
+        let mut hdr = Header::from_bytes(&mut b, conn.source_id().len()).unwrap();
 
-                let was_draining = stream.recv.is_draining();
+        let epoch = hdr.ty.to_epoch()?;
 
-                stream.recv.write(data)?;
+        let aead = conn.pkt_num_spaces[epoch].crypto_open.as_ref().unwrap();
 
-                if !was_readable && stream.is_readable() {
-                    self.streams.insert_readable(&priority_key);
-                }
+        let payload_len = b.cap();
 
-                self.rx_data += max_off_delta;
+        packet::decrypt_hdr(&mut b, &mut hdr, aead).unwrap();
+
+        let pn = packet::decode_pkt_num(
+            conn.pkt_num_spaces[epoch].largest_rx_pkt_num,
+            hdr.pkt_num,
+            hdr.pkt_num_len,
+        );
 
-                if was_draining {
-                    // When a stream is in draining state it will not queue
-                    // incoming data for the application to read, so consider
-                    // the received data as consumed, which might trigger a flow
-                    // control update.
-                    self.flow_control.add_consumed(max_off_delta);
+        let mut payload =
+            packet::decrypt_pkt(&mut b, pn, hdr.pkt_num_len, payload_len, aead)
+                .unwrap();
 
-                    if self.should_update_max_data() {
-                        self.almost_full = true;
-                    }
-                }
-            },
+        let mut frames = Vec::new();
 
-            frame::Frame::StreamHeader { .. } => unreachable!(),
+        while payload.cap() > 0 {
+            let frame = frame::Frame::from_bytes(&mut payload, hdr.ty)?;
+            frames.push(frame);
+        }
 
-            frame::Frame::MaxData { max } => {
-                self.max_tx_data = cmp::max(self.max_tx_data, max);
-            },
+        Ok(frames)
+    }
 
-            frame::Frame::MaxStreamData { stream_id, max } => {
-                // Peer can't receive on its own unidirectional streams.
-                if !stream::is_bidi(stream_id) &&
-                    !stream::is_local(stream_id, self.is_server)
-                {
-                    return Err(Error::InvalidStreamState(stream_id));
-                }
+    pub fn create_cid_and_reset_token(
+        cid_len: usize,
+    ) -> (ConnectionId<'static>, u128) {
+        let mut cid = vec![0; cid_len];
+        rand::rand_bytes(&mut cid[..]);
+        let cid = ConnectionId::from_ref(&cid).into_owned();
 
-                // Get existing stream or create a new one, but if the stream
-                // has already been closed and collected, ignore the frame.
-                //
-                // This can happen if e.g. an ACK frame is lost, and the peer
-                // retransmits another frame before it realizes that the stream
-                // is gone.
-                //
-                // Note that it makes it impossible to check if the frame is
-                // illegal, since we have no state, but since we ignore the
-                // frame, it should be fine.
-                let stream = match self.get_or_create_stream(stream_id, false) {
-                    Ok(v) => v,
+        let mut reset_token = [0; 16];
+        rand::rand_bytes(&mut reset_token);
+        let reset_token = u128::from_be_bytes(reset_token);
 
-                    Err(Error::Done) => return Ok(()),
+        (cid, reset_token)
+    }
+}
 
-                    Err(e) => return Err(e),
-                };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                let was_flushable = stream.is_flushable();
+    #[test]
+    fn rand_seed() {
+        testing::set_rand_seed(Some(1));
+        let pipe = testing::Pipe::new().unwrap();
 
-                stream.send.update_max_data(max);
+        testing::set_rand_seed(Some(1));
+        let seeded = testing::Pipe::new().unwrap();
 
-                let writable = stream.is_writable();
+        testing::set_rand_seed(None);
+        let random = testing::Pipe::new().unwrap();
 
-                let priority_key = Arc::clone(&stream.priority_key);
+        assert_eq!(pipe.client.source_id(), seeded.client.source_id());
+        assert_eq!(pipe.client.destination_id(), seeded.client.destination_id());
+        assert_eq!(pipe.server.source_id(), seeded.server.source_id());
 
-                // If the stream is now flushable push it to the flushable queue,
-                // but only if it wasn't already queued.
-                if stream.is_flushable() && !was_flushable {
-                    let priority_key = Arc::clone(&stream.priority_key);
-                    self.streams.insert_flushable(&priority_key);
-                }
+        assert_ne!(pipe.client.source_id(), random.client.source_id());
+    }
 
-                if writable {
-                    self.streams.insert_writable(&priority_key);
-                }
-            },
+    #[test]
+    fn transport_params() {
+        // Server encodes, client decodes.
+        let tp = TransportParams {
+            original_destination_connection_id: None,
+            max_idle_timeout: 30,
+            stateless_reset_token: Some(u128::from_be_bytes([0xba; 16])),
+            max_udp_payload_size: 23_421,
+            initial_max_data: 424_645_563,
+            initial_max_stream_data_bidi_local: 154_323_123,
+            initial_max_stream_data_bidi_remote: 6_587_456,
+            initial_max_stream_data_uni: 2_461_234,
+            initial_max_streams_bidi: 12_231,
+            initial_max_streams_uni: 18_473,
+            ack_delay_exponent: 20,
+            max_ack_delay: 2_u64.pow(14) - 1,
+            disable_active_migration: true,
+            active_conn_id_limit: 8,
+            initial_source_connection_id: Some(b"woot woot".to_vec().into()),
+            retry_source_connection_id: Some(b"retry".to_vec().into()),
+            max_datagram_frame_size: Some(32),
+            bdp_tokens: true,
+            bdp_frame: true,
+            cr_hints: true,
+            reset_stream_at: true,
+            careful_resume_blob: None,
+            version_information: None,
+        };
 
-            frame::Frame::MaxStreamsBidi { max } => {
-                if max > MAX_STREAM_ID {
-                    return Err(Error::InvalidFrame);
-                }
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, true, &mut raw_params).unwrap();
+        assert_eq!(raw_params.len(), 115);
 
-                self.streams.update_peer_max_streams_bidi(max);
-            },
+        let new_tp = TransportParams::decode(raw_params, false).unwrap();
 
-            frame::Frame::MaxStreamsUni { max } => {
-                if max > MAX_STREAM_ID {
-                    return Err(Error::InvalidFrame);
-                }
+        assert_eq!(new_tp, tp);
 
-                self.streams.update_peer_max_streams_uni(max);
-            },
+        // Client encodes, server decodes.
+        let tp = TransportParams {
+            original_destination_connection_id: None,
+            max_idle_timeout: 30,
+            stateless_reset_token: None,
+            max_udp_payload_size: 23_421,
+            initial_max_data: 424_645_563,
+            initial_max_stream_data_bidi_local: 154_323_123,
+            initial_max_stream_data_bidi_remote: 6_587_456,
+            initial_max_stream_data_uni: 2_461_234,
+            initial_max_streams_bidi: 12_231,
+            initial_max_streams_uni: 18_473,
+            ack_delay_exponent: 20,
+            max_ack_delay: 2_u64.pow(14) - 1,
+            disable_active_migration: true,
+            active_conn_id_limit: 8,
+            initial_source_connection_id: Some(b"woot woot".to_vec().into()),
+            retry_source_connection_id: None,
+            max_datagram_frame_size: Some(32),
+            bdp_tokens: true,
+            bdp_frame: true,
+            cr_hints: true,
+            reset_stream_at: true,
+            careful_resume_blob: None,
+            version_information: None,
+        };
 
-            frame::Frame::DataBlocked { .. } => (),
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
+        assert_eq!(raw_params.len(), 90);
 
-            frame::Frame::StreamDataBlocked { .. } => (),
+        let new_tp = TransportParams::decode(raw_params, true).unwrap();
 
-            frame::Frame::StreamsBlockedBidi { limit } => {
-                if limit > MAX_STREAM_ID {
-                    return Err(Error::InvalidFrame);
-                }
-            },
+        assert_eq!(new_tp, tp);
+    }
 
-            frame::Frame::StreamsBlockedUni { limit } => {
-                if limit > MAX_STREAM_ID {
-                    return Err(Error::InvalidFrame);
-                }
-            },
+    #[test]
+    fn transport_params_forbid_duplicates() {
+        // Given an encoded param.
+        let initial_source_connection_id = b"id";
+        let initial_source_connection_id_raw = [
+            15,
+            initial_source_connection_id.len() as u8,
+            initial_source_connection_id[0],
+            initial_source_connection_id[1],
+        ];
 
-            frame::Frame::NewConnectionId {
-                seq_num,
-                retire_prior_to,
-                conn_id,
-                reset_token,
-            } => {
-                if self.ids.zero_length_dcid() {
-                    return Err(Error::InvalidState);
-                }
+        // No error when decoding the param.
+        let tp = TransportParams::decode(
+            initial_source_connection_id_raw.as_slice(),
+            true,
+        )
+        .unwrap();
 
-                let mut retired_path_ids = SmallVec::new();
+        assert_eq!(
+            tp.initial_source_connection_id,
+            Some(initial_source_connection_id.to_vec().into())
+        );
 
-                // Retire pending path IDs before propagating the error code to
-                // make sure retired connection IDs are not in use anymore.
-                let new_dcid_res = self.ids.new_dcid(
-                    conn_id.into(),
-                    seq_num,
-                    u128::from_be_bytes(reset_token),
-                    retire_prior_to,
-                    &mut retired_path_ids,
-                );
+        // Duplicate the param.
+        let mut raw_params = Vec::new();
+        raw_params.append(&mut initial_source_connection_id_raw.to_vec());
+        raw_params.append(&mut initial_source_connection_id_raw.to_vec());
 
-                for (dcid_seq, pid) in retired_path_ids {
-                    let path = self.paths.get_mut(pid)?;
+        // Decoding fails.
+        assert_eq!(
+            TransportParams::decode(raw_params.as_slice(), true),
+            Err(Error::InvalidTransportParam)
+        );
+    }
 
-                    // Maybe the path already switched to another DCID.
-                    if path.active_dcid_seq != Some(dcid_seq) {
-                        continue;
-                    }
+    #[test]
+    fn transport_params_careful_resume_blob() {
+        // Client echoes a blob.
+        let tp = TransportParams {
+            careful_resume_blob: Some(vec![0xba; 40]),
+            ..Default::default()
+        };
 
-                    if let Some(new_dcid_seq) =
-                        self.ids.lowest_available_dcid_seq()
-                    {
-                        path.active_dcid_seq = Some(new_dcid_seq);
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
 
-                        self.ids.link_dcid_to_path_id(new_dcid_seq, pid)?;
+        let new_tp = TransportParams::decode(raw_params, true).unwrap();
+        assert_eq!(new_tp.careful_resume_blob, Some(vec![0xba; 40]));
 
-                        trace!(
-                            "{} path ID {} changed DCID: old seq num {} new seq num {}",
-                            self.trace_id, pid, dcid_seq, new_dcid_seq,
-                        );
-                    } else {
-                        // We cannot use this path anymore for now.
-                        path.active_dcid_seq = None;
+        // Server can't send a blob.
+        assert_eq!(
+            TransportParams::decode(raw_params, false),
+            Err(Error::InvalidTransportParam)
+        );
 
-                        trace!(
-                            "{} path ID {} cannot be used; DCID seq num {} has been retired",
-                            self.trace_id, pid, dcid_seq,
-                        );
-                    }
-                }
+        // Server advertises support.
+        let tp = TransportParams {
+            careful_resume_blob: Some(Vec::new()),
+            ..Default::default()
+        };
 
-                // Propagate error (if any) now...
-                new_dcid_res?;
-            },
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, true, &mut raw_params).unwrap();
 
-            frame::Frame::RetireConnectionId { seq_num } => {
-                if self.ids.zero_length_scid() {
-                    return Err(Error::InvalidState);
-                }
+        let new_tp = TransportParams::decode(raw_params, false).unwrap();
+        assert_eq!(new_tp.careful_resume_blob, Some(Vec::new()));
+    }
 
-                if let Some(pid) = self.ids.retire_scid(seq_num, &hdr.dcid)? {
-                    let path = self.paths.get_mut(pid)?;
+    #[test]
+    fn stateless_resume_blob() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        assert_eq!(config.set_resume_blob_key(&[0xba; 16]), Ok(()));
+
+        let key = resume_blob::BlobKey::new(&[0xba; 16]).unwrap();
+        let blob = key
+            .seal(
+                &CREvent {
+                    min_rtt: Duration::from_millis(50),
+                    cwnd: 120_000,
+                    pacing_rate: 0,
+                    post_congestion: false,
+                    max_datagram_size: 0,
+                    loss_rate_ppm: 0,
+                },
+                testing::Pipe::client_addr().ip(),
+                time::SystemTime::now(),
+            )
+            .unwrap();
 
-                    // Maybe we already linked a new SCID to that path.
-                    if path.active_scid_seq == Some(seq_num) {
-                        // XXX: We do not remove unused paths now, we instead
-                        // wait until we need to maintain more paths than the
-                        // host is willing to.
-                        path.active_scid_seq = None;
-                    }
-                }
-            },
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_resume_blob(&blob), Ok(()));
+        assert_eq!(pipe.handshake(), Ok(()));
 
-            frame::Frame::PathChallenge { data } => {
-                self.path_challenge_rx_count += 1;
+        assert!(pipe.client.peer_accepts_resume_blob());
+        assert!(pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
 
-                self.paths
-                    .get_mut(recv_path_id)?
-                    .on_challenge_received(data);
-            },
+        // Blobs can't be set once the handshake started.
+        assert_eq!(pipe.client.set_resume_blob(&blob), Err(Error::InvalidState));
+        assert_eq!(pipe.server.set_resume_blob(&blob), Err(Error::InvalidState));
+    }
 
-            frame::Frame::PathResponse { data } => {
-                self.paths.on_response_received(data)?;
-            },
+    #[test]
+    fn resume_blob_too_long() {
+        let mut pipe = testing::Pipe::new().unwrap();
 
-            frame::Frame::ConnectionClose {
-                error_code, reason, ..
-            } => {
-                self.peer_error = Some(ConnectionError {
-                    is_app: false,
-                    error_code,
-                    reason,
-                });
+        assert_eq!(pipe.client.set_resume_blob(&[0xba; 64]), Ok(()));
 
-                let path = self.paths.get_active()?;
-                self.draining_timer = Some(now + (path.recovery.pto() * 3));
-            },
+        // Longer blobs are rejected, and the previous one is kept.
+        assert_eq!(
+            pipe.client
+                .set_resume_blob(&[0xab; MAX_RESUME_BLOB_LEN + 1]),
+            Err(Error::BufferTooShort)
+        );
+        assert_eq!(
+            pipe.client.local_transport_params.careful_resume_blob,
+            Some(vec![0xba; 64])
+        );
 
-            frame::Frame::ApplicationClose { error_code, reason } => {
-                self.peer_error = Some(ConnectionError {
-                    is_app: true,
-                    error_code,
-                    reason,
-                });
+        assert_eq!(
+            pipe.client.set_resume_blob(&[0xab; MAX_RESUME_BLOB_LEN]),
+            Ok(())
+        );
+        assert_eq!(
+            pipe.client.local_transport_params.careful_resume_blob,
+            Some(vec![0xab; MAX_RESUME_BLOB_LEN])
+        );
+    }
 
-                let path = self.paths.get_active()?;
-                self.draining_timer = Some(now + (path.recovery.pto() * 3));
-            },
+    #[test]
+    fn resume_new_token() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        assert_eq!(config.set_resume_blob_key(&[0xba; 16]), Ok(()));
+        config.enable_resume_new_token(true);
 
-            frame::Frame::HandshakeDone => {
-                if self.is_server {
-                    return Err(Error::InvalidPacket);
-                }
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-                self.peer_verified_initial_address = true;
+        assert!(pipe.client.peer_transport_params.bdp_tokens);
 
-                self.handshake_confirmed = true;
+        // The server sends a token for the parameters it observed.
+        pipe.server.update_cr_event(0, CREvent {
+            min_rtt: Duration::from_millis(50),
+            cwnd: 120_000,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        });
 
-                // Once the handshake is confirmed, we can drop Handshake keys.
-                self.drop_epoch_state(packet::Epoch::Handshake, now);
-            },
+        assert_eq!(pipe.advance(), Ok(()));
 
-            frame::Frame::Datagram { data } => {
-                // Close the connection if DATAGRAMs are not enabled.
-                // quiche always advertises support for 64K sized DATAGRAM
-                // frames, as recommended by the standard, so we don't need a
-                // size check.
-                if !self.dgram_enabled() {
-                    return Err(Error::InvalidState);
-                }
+        let token = pipe.client.recv_new_token().unwrap();
+        assert!(is_resume_token(&token));
+        assert_eq!(pipe.client.recv_new_token(), None);
 
-                // If recv queue is full, discard oldest
-                if self.dgram_recv_queue.is_full() {
-                    self.dgram_recv_queue.pop();
-                }
+        // The client presents the token on its next connection, and the
+        // server resumes with it.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        pipe.client.set_token(&token);
+        assert_eq!(pipe.handshake(), Ok(()));
 
-                self.dgram_recv_queue.push(data)?;
-            },
+        assert_eq!(pipe.server.peer_token(), Some(&token[..]));
+        assert!(pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
 
-            frame::Frame::DatagramHeader { .. } => unreachable!(),
-        }
+        // Other tokens are left to the application.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        pipe.client.set_token(b"quiche");
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        Ok(())
+        assert!(!pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
     }
 
-    /// Drops the keys and recovery state for the given epoch.
-    fn drop_epoch_state(&mut self, epoch: packet::Epoch, now: time::Instant) {
-        if self.pkt_num_spaces[epoch].crypto_open.is_none() {
-            return;
-        }
+    #[test]
+    fn cr_events() {
+        let mut pipe = testing::Pipe::new().unwrap();
 
-        self.pkt_num_spaces[epoch].crypto_open = None;
-        self.pkt_num_spaces[epoch].crypto_seal = None;
-        self.pkt_num_spaces[epoch].clear();
+        let event = |cwnd| CREvent {
+            min_rtt: Duration::from_millis(50),
+            cwnd,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        };
 
-        let handshake_status = self.handshake_status();
-        for (_, p) in self.paths.iter_mut() {
-            p.recovery
-                .on_pkt_num_space_discarded(epoch, handshake_status, now);
+        for i in 1..=3 {
+            pipe.server.update_cr_event(0, event(i * 10_000));
         }
 
-        trace!("{} dropped epoch {} state", self.trace_id, epoch);
-    }
+        // Every event is reported, oldest first.
+        assert_eq!(pipe.server.cr_event_next(), Some(event(10_000)));
+        assert_eq!(
+            pipe.server.cr_events().collect::<Vec<_>>(),
+            vec![event(20_000), event(30_000)]
+        );
+        assert_eq!(pipe.server.cr_event_next(), None);
+        assert_eq!(pipe.server.cr_events().next(), None);
 
-    /// Returns true if the connection-level flow control needs to be updated.
-    ///
-    /// This happens when the new max data limit is at least double the amount
-    /// of data that can be received before blocking.
-    fn should_update_max_data(&self) -> bool {
-        self.flow_control.should_update_max_data()
-    }
+        // Only the most recent events are kept when they are not read.
+        for i in 1..=MAX_PENDING_CR_EVENTS + 2 {
+            pipe.server.update_cr_event(0, event(i * 10_000));
+        }
 
-    /// Returns the connection level flow control limit.
-    fn max_rx_data(&self) -> u64 {
-        self.flow_control.max_data()
+        assert_eq!(pipe.server.cr_events().count(), MAX_PENDING_CR_EVENTS);
+        assert_eq!(
+            pipe.server.paths.get(0).unwrap().latest_cr_event,
+            Some(event(180_000))
+        );
     }
 
-    /// Returns true if the HANDSHAKE_DONE frame needs to be sent.
-    fn should_send_handshake_done(&self) -> bool {
-        self.is_established() && !self.handshake_done_sent && self.is_server
-    }
+    #[test]
+    fn cr_store() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
 
-    /// Returns the idle timeout value.
-    ///
-    /// `None` is returned if both end-points disabled the idle timeout.
-    fn idle_timeout(&mut self) -> Option<time::Duration> {
-        // If the transport parameter is set to 0, then the respective endpoint
-        // decided to disable the idle timeout. If both are disabled we should
-        // not set any timeout.
-        if self.local_transport_params.max_idle_timeout == 0 &&
-            self.peer_transport_params.max_idle_timeout == 0
-        {
-            return None;
-        }
+        let store = Arc::new(MemoryCrStore::new(Duration::from_secs(3600)));
+        config.set_cr_store(store.clone());
 
-        // If the local endpoint or the peer disabled the idle timeout, use the
-        // other peer's value, otherwise use the minimum of the two values.
-        let idle_timeout = if self.local_transport_params.max_idle_timeout == 0 {
-            self.peer_transport_params.max_idle_timeout
-        } else if self.peer_transport_params.max_idle_timeout == 0 {
-            self.local_transport_params.max_idle_timeout
-        } else {
-            cmp::min(
-                self.local_transport_params.max_idle_timeout,
-                self.peer_transport_params.max_idle_timeout,
-            )
-        };
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let path_pto = match self.paths.get_active() {
-            Ok(p) => p.recovery.pto(),
-            Err(_) => time::Duration::ZERO,
+        assert!(!pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
+
+        // Observed parameters are stored for the peer.
+        let params = CREvent {
+            min_rtt: Duration::from_millis(50),
+            cwnd: 120_000,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
         };
 
-        let idle_timeout = time::Duration::from_millis(idle_timeout);
-        let idle_timeout = cmp::max(idle_timeout, 3 * path_pto);
+        pipe.server.update_cr_event(0, params);
+        pipe.client.update_cr_event(0, params);
 
-        Some(idle_timeout)
+        let server_key = cr_store::server_key(testing::Pipe::client_addr().ip());
+        assert_eq!(store.get(&server_key), Some(params));
+        assert_eq!(store.get("quic.tech"), Some(params));
+
+        // Both endpoints resume with them on the next connection.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert!(pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
+        assert!(pipe
+            .client
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
     }
 
-    /// Returns the connection's handshake status for use in loss recovery.
-    fn handshake_status(&self) -> recovery::HandshakeStatus {
-        recovery::HandshakeStatus {
-            has_handshake_keys: self.pkt_num_spaces[packet::Epoch::Handshake]
-                .has_keys(),
+    #[test]
+    fn cr_final_event_on_close() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_cr_observe_min_cwnd(0);
 
-            peer_verified_address: self.peer_verified_initial_address,
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.client.cr_event_next(), None);
 
-            completed: self.is_established(),
-        }
+        // The congestion window is reported as the connection closes, even
+        // though the observe phase didn't report it yet.
+        assert_eq!(pipe.client.close(false, 0x1, b"bye"), Ok(()));
+
+        let cwnd = pipe.client.paths.get_active().unwrap().recovery.cwnd();
+        assert_eq!(pipe.client.cr_event_next().map(|e| e.cwnd), Some(cwnd));
+
+        // Only once.
+        assert_eq!(
+            pipe.client.close_and_flush(false, 0x1, b"bye"),
+            Err(Error::Done)
+        );
+        assert_eq!(pipe.client.cr_event_next(), None);
     }
 
-    /// Updates send capacity.
-    fn update_tx_cap(&mut self) {
-        let cwin_available = match self.paths.get_active() {
-            Ok(p) => p.recovery.cwnd_available() as u64,
-            Err(_) => 0,
+    #[test]
+    fn cr_store_origin() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+
+        let store = Arc::new(MemoryCrStore::new(Duration::from_secs(3600)));
+        config.set_cr_store(store.clone());
+
+        let params = CREvent {
+            min_rtt: Duration::from_millis(50),
+            cwnd: 120_000,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
         };
 
-        self.tx_cap =
-            cmp::min(cwin_available, self.max_tx_data - self.tx_data) as usize;
-    }
+        let other = "192.0.2.1".parse().unwrap();
+        store.put(&cr_store::origin_key("quic.tech", other), params);
 
-    fn delivery_rate_check_if_app_limited(&self) -> bool {
-        // Enter the app-limited phase of delivery rate when these conditions
-        // are met:
-        //
-        // - The remaining capacity is higher than available bytes in cwnd (there
-        //   is more room to send).
-        // - New data since the last send() is smaller than available bytes in
-        //   cwnd (we queued less than what we can send).
-        // - There is room to send more data in cwnd.
-        //
-        // In application-limited phases the transmission rate is limited by the
-        // application rather than the congestion control algorithm.
-        //
-        // Note that this is equivalent to CheckIfApplicationLimited() from the
-        // delivery rate draft. This is also separate from `recovery.app_limited`
-        // and only applies to delivery rate calculation.
-        let cwin_available = self
-            .paths
-            .iter()
-            .filter(|&(_, p)| p.active())
-            .map(|(_, p)| p.recovery.cwnd_available())
-            .sum();
+        let scid = ConnectionId::from_ref(&[0xba; 16]);
+        let local = testing::Pipe::client_addr();
+        let peer: SocketAddr = "192.0.2.2:443".parse().unwrap();
 
-        ((self.tx_buffered + self.dgram_send_queue_byte_size()) < cwin_available) &&
-            (self.tx_data.saturating_sub(self.last_tx_data)) <
-                cwin_available as u64 &&
-            cwin_available > 0
-    }
+        // Without an origin, the parameters of another address aren't used.
+        let conn =
+            connect(Some("quic.tech"), &scid, local, peer, &mut config).unwrap();
 
-    fn set_initial_dcid(
-        &mut self, cid: ConnectionId<'static>, reset_token: Option<u128>,
-        path_id: usize,
-    ) -> Result<()> {
-        self.ids.set_initial_dcid(cid, reset_token, Some(path_id));
-        self.paths.get_mut(path_id)?.active_dcid_seq = Some(0);
+        assert!(!conn.paths.get_active().unwrap().recovery.resume_enabled());
 
-        Ok(())
-    }
+        let mut conn = connect_with_cr_origin(
+            Some("quic.tech"),
+            "quic.tech",
+            &scid,
+            local,
+            peer,
+            &mut config,
+        )
+        .unwrap();
 
-    /// Selects the path that the incoming packet belongs to, or creates a new
-    /// one if no existing path matches.
-    fn get_or_create_recv_path_id(
-        &mut self, recv_pid: Option<usize>, dcid: &ConnectionId, buf_len: usize,
-        info: &RecvInfo,
-    ) -> Result<usize> {
-        let ids = &mut self.ids;
+        assert!(conn.paths.get_active().unwrap().recovery.resume_enabled());
 
-        let (in_scid_seq, mut in_scid_pid) =
-            ids.find_scid_seq(dcid).ok_or(Error::InvalidState)?;
+        // Parameters observed are stored for the address itself.
+        conn.update_cr_event(0, params);
 
-        if let Some(recv_pid) = recv_pid {
-            // If the path observes a change of SCID used, note it.
-            let recv_path = self.paths.get_mut(recv_pid)?;
+        let key = cr_store::origin_key("quic.tech", peer.ip());
+        assert_eq!(store.get(&key), Some(params));
+        assert_eq!(store.get("quic.tech"), None);
+    }
 
-            let cid_entry =
-                recv_path.active_scid_seq.and_then(|v| ids.get_scid(v).ok());
+    #[test]
+    fn cr_path_cache() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_resume(true);
 
-            if cid_entry.map(|e| &e.cid) != Some(dcid) {
-                let incoming_cid_entry = ids.get_scid(in_scid_seq)?;
+        let cache = Arc::new(CrPathCache::new(Duration::from_secs(3600)));
+        config.set_cr_path_cache(cache.clone());
 
-                let prev_recv_pid =
-                    incoming_cid_entry.path_id.unwrap_or(recv_pid);
+        let client_key =
+            cr_path_cache::key(false, testing::Pipe::server_addr().ip());
+        let server_key =
+            cr_path_cache::key(true, testing::Pipe::client_addr().ip());
 
-                if prev_recv_pid != recv_pid {
-                    trace!(
-                        "{} peer reused CID {:?} from path {} on path {}",
-                        self.trace_id,
-                        dcid,
-                        prev_recv_pid,
-                        recv_pid
-                    );
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(cache.connections(&client_key), 1);
+        assert_eq!(cache.connections(&server_key), 1);
 
-                    // TODO: reset congestion control.
-                }
+        assert!(!pipe
+            .client
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
+
+        let params = CREvent {
+            min_rtt: Duration::from_millis(50),
+            cwnd: 120_000,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        };
 
-                trace!(
-                    "{} path ID {} now see SCID with seq num {}",
-                    self.trace_id,
-                    recv_pid,
-                    in_scid_seq
-                );
+        pipe.client.update_cr_event(0, params);
 
-                recv_path.active_scid_seq = Some(in_scid_seq);
-                ids.link_scid_to_path_id(in_scid_seq, recv_pid)?;
-            }
+        // A concurrent connection to the same server resumes with its share
+        // of the path.
+        let pipe2 = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(cache.connections(&client_key), 2);
 
-            return Ok(recv_pid);
-        }
+        assert!(pipe2
+            .client
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
 
-        // This is a new 4-tuple. See if the CID has not been assigned on
-        // another path.
+        drop(pipe);
+        drop(pipe2);
+        assert_eq!(cache.connections(&client_key), 0);
+        assert_eq!(cache.get(&client_key).map(|p| p.cwnd), Some(120_000));
+    }
 
-        // Ignore this step if are using zero-length SCID.
-        if ids.zero_length_scid() {
-            in_scid_pid = None;
-        }
+    #[test]
+    fn cr_store_per_path() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_resume(true);
 
-        if let Some(in_scid_pid) = in_scid_pid {
-            // This CID has been used by another path. If we have the
-            // room to do so, create a new `Path` structure holding this
-            // new 4-tuple. Otherwise, drop the packet.
-            let old_path = self.paths.get_mut(in_scid_pid)?;
-            let old_local_addr = old_path.local_addr();
-            let old_peer_addr = old_path.peer_addr();
+        let store = Arc::new(MemoryCrStore::new(Duration::from_secs(3600)));
+        config.set_cr_store(store.clone());
 
-            trace!(
-                "{} reused CID seq {} of ({},{}) (path {}) on ({},{})",
-                self.trace_id,
-                in_scid_seq,
-                old_local_addr,
-                old_peer_addr,
-                in_scid_pid,
-                info.to,
-                info.from
-            );
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
-            // Notify the application.
-            self.paths
-                .notify_event(path::PathEvent::ReusedSourceConnectionId(
-                    in_scid_seq,
-                    (old_local_addr, old_peer_addr),
-                    (info.to, info.from),
-                ));
-        }
+        let params = CREvent {
+            min_rtt: Duration::from_millis(50),
+            cwnd: 120_000,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        };
 
-        // This is a new path using an unassigned CID; create it!
-        let mut path = path::Path::new(
-            info.to,
-            info.from,
-            &self.trace_id,
-            &self.recovery_config,
-            self.path_challenge_recv_max_queue_len,
+        assert_eq!(
+            pipe.server.setup_careful_resume(
+                params.min_rtt,
+                params.cwnd,
+                params.pacing_rate
+            ),
+            Ok(())
+        );
+
+        // A path from another network, e.g. after the client migrated.
+        let peer = "198.51.100.7:1234".parse().unwrap();
+        let path = path::Path::new(
+            testing::Pipe::server_addr(),
+            peer,
+            "",
+            &pipe.server.recovery_config,
+            1,
             MIN_CLIENT_INITIAL_LEN,
             false,
         );
+        let pid = pipe.server.paths.insert_path(path, true).unwrap();
 
-        path.max_send_bytes = buf_len * self.max_amplification_factor;
-        path.active_scid_seq = Some(in_scid_seq);
+        // Careful resume was only set up on the initial path, whose RTT is
+        // compared with the stored one.
+        assert!(pipe.server.paths.get(0).unwrap().recovery.resume_enabled());
+        assert!(!pipe.server.paths.get(pid).unwrap().recovery.resume_enabled());
 
-        // Automatically probes the new path.
-        path.request_validation();
+        // Parameters observed on the new path are kept for it, and stored
+        // for its network.
+        pipe.server.update_cr_event(pid, params);
 
-        let pid = self.paths.insert_path(path, self.is_server)?;
+        assert_eq!(pipe.server.paths.get(0).unwrap().latest_cr_event, None);
+        assert_eq!(
+            pipe.server.paths.get(pid).unwrap().latest_cr_event,
+            Some(params)
+        );
 
-        // Do not record path reuse.
-        if in_scid_pid.is_none() {
-            ids.link_scid_to_path_id(in_scid_seq, pid)?;
-        }
+        let client_key = cr_store::server_key(testing::Pipe::client_addr().ip());
+        assert_eq!(store.get(&client_key), None);
+        assert_eq!(store.get(&cr_store::server_key(peer.ip())), Some(params));
 
-        Ok(pid)
+        // They are not reported for the active path.
+        assert_eq!(pipe.server.saved_transport().careful_resume, None);
     }
 
-    /// Selects the path on which the next packet must be sent.
-    fn get_send_path_id(
-        &self, from: Option<SocketAddr>, to: Option<SocketAddr>,
-    ) -> Result<usize> {
-        // A probing packet must be sent, but only if the connection is fully
-        // established.
-        if self.is_established() {
-            let mut probing = self
-                .paths
-                .iter()
-                .filter(|(_, p)| from.is_none() || Some(p.local_addr()) == from)
-                .filter(|(_, p)| to.is_none() || Some(p.peer_addr()) == to)
-                .filter(|(_, p)| p.active_dcid_seq.is_some())
-                .filter(|(_, p)| p.probing_required())
-                .map(|(pid, _)| pid);
-
-            if let Some(pid) = probing.next() {
-                return Ok(pid);
-            }
+    #[test]
+    fn cr_store_server_sender() {
+        fn config(store: Arc<MemoryCrStore>) -> Config {
+            let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+            config
+                .load_cert_chain_from_pem_file("examples/cert.crt")
+                .unwrap();
+            config
+                .load_priv_key_from_pem_file("examples/cert.key")
+                .unwrap();
+            config
+                .set_application_protos(&[b"proto1", b"proto2"])
+                .unwrap();
+            config.set_initial_max_data(10_000_000);
+            config.set_initial_max_stream_data_bidi_local(10_000_000);
+            config.set_initial_max_stream_data_bidi_remote(10_000_000);
+            config.set_initial_max_streams_bidi(3);
+            config.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+            config.verify_peer(false);
+            config.set_cr_store(store);
+            config
         }
 
-        if let Some((pid, p)) = self.paths.get_active_with_pid() {
-            if from.is_some() && Some(p.local_addr()) != from {
-                return Err(Error::Done);
-            }
+        let mut buf = [0; 65535];
 
-            if to.is_some() && Some(p.peer_addr()) != to {
-                return Err(Error::Done);
-            }
+        let client_store =
+            Arc::new(MemoryCrStore::new(Duration::from_secs(3600)));
+        let server_store =
+            Arc::new(MemoryCrStore::new(Duration::from_secs(3600)));
 
-            return Ok(pid);
-        };
+        let mut client_config = config(client_store.clone());
+        let mut server_config = config(server_store.clone());
 
-        Err(Error::InvalidState)
-    }
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config,
+            &mut server_config,
+        )
+        .unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-    /// Sets the path with identifier 'path_id' to be active.
-    fn set_active_path(
-        &mut self, path_id: usize, now: time::Instant,
-    ) -> Result<()> {
-        if let Ok(old_active_path) = self.paths.get_active_mut() {
-            for &e in packet::Epoch::epochs(
-                packet::Epoch::Initial..=packet::Epoch::Application,
-            ) {
-                let (lost_packets, lost_bytes) = old_active_path
-                    .recovery
-                    .on_path_change(e, now, &self.trace_id);
+        let iw = pipe.server.paths.get_active().unwrap().recovery.cwnd();
+
+        // The client requests a download, the server sends all of the data.
+        assert_eq!(pipe.client.stream_send(0, b"GET /", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let body = vec![0xba; 500_000];
+        let mut sent = 0;
+        let mut fin = false;
+
+        for _ in 0..200 {
+            if sent < body.len() {
+                match pipe.server.stream_send(0, &body[sent..], true) {
+                    Ok(v) => sent += v,
+
+                    Err(Error::Done) => (),
+
+                    Err(e) => panic!("stream_send failed: {:?}", e),
+                }
+            }
+
+            assert_eq!(pipe.advance(), Ok(()));
 
-                self.lost_count += lost_packets;
-                self.lost_bytes += lost_bytes as u64;
+            while let Ok((_, f)) = pipe.client.stream_recv(0, &mut buf) {
+                fin = f;
+            }
 
-                qlog_with_type!(QLOG_PACKET_LOST, self.qlog, q, {
-                    for ev_data in old_active_path.recovery.packet_loss_qlog() {
-                        q.add_event_data_with_instant(ev_data, now).ok();
-                    }
-                });
+            if fin {
+                break;
             }
         }
 
-        self.paths.set_active_path(path_id)
-    }
-
-    /// Handles potential connection migration.
-    fn on_peer_migrated(
-        &mut self, new_pid: usize, disable_dcid_reuse: bool, now: time::Instant,
-    ) -> Result<()> {
-        let active_path_id = self.paths.get_active_path_id()?;
+        assert!(fin);
 
-        if active_path_id == new_pid {
-            return Ok(());
-        }
+        assert_eq!(pipe.server.close(false, 0x00, b""), Ok(()));
 
-        self.set_active_path(new_pid, now)?;
+        // Only the server observed the path, and stored its parameters for
+        // the network of the client.
+        let key = cr_store::server_key(testing::Pipe::client_addr().ip());
+        let params = server_store.get(&key).unwrap();
+        assert!(params.cwnd > iw);
+        assert!(params.min_rtt > Duration::ZERO);
 
-        let no_spare_dcid =
-            self.paths.get_mut(new_pid)?.active_dcid_seq.is_none();
+        assert!(client_store.is_empty());
 
-        if no_spare_dcid && !disable_dcid_reuse {
-            self.paths.get_mut(new_pid)?.active_dcid_seq =
-                self.paths.get_mut(active_path_id)?.active_dcid_seq;
-        }
+        // A repeat visitor resumes the download with them.
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config,
+            &mut server_config,
+        )
+        .unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        Ok(())
+        assert!(pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
+        assert!(!pipe
+            .client
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
     }
 
-    /// Creates a new client-side path.
-    fn create_path_on_client(
-        &mut self, local_addr: SocketAddr, peer_addr: SocketAddr,
-    ) -> Result<usize> {
-        if self.is_server {
-            return Err(Error::InvalidState);
-        }
+    #[test]
+    fn setup_server_careful_resume() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_resume(true);
 
-        // If we use zero-length SCID and go over our local active CID limit,
-        // the `insert_path()` call will raise an error.
-        if !self.ids.zero_length_scid() && self.ids.available_scids() == 0 {
-            return Err(Error::OutOfIdentifiers);
-        }
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
-        // Do we have a spare DCID? If we are using zero-length DCID, just use
-        // the default having sequence 0 (note that if we exceed our local CID
-        // limit, the `insert_path()` call will raise an error.
-        let dcid_seq = if self.ids.zero_length_dcid() {
-            0
-        } else {
-            self.ids
-                .lowest_available_dcid_seq()
-                .ok_or(Error::OutOfIdentifiers)?
-        };
+        assert_eq!(
+            pipe.client.setup_server_careful_resume(
+                Duration::from_millis(50),
+                120_000,
+                0
+            ),
+            Err(Error::InvalidState)
+        );
 
-        let mut path = path::Path::new(
-            local_addr,
-            peer_addr,
-            &self.trace_id,
-            &self.recovery_config,
-            self.path_challenge_recv_max_queue_len,
-            MIN_CLIENT_INITIAL_LEN,
-            false,
+        assert_eq!(
+            pipe.server.setup_server_careful_resume(
+                Duration::from_millis(50),
+                120_000,
+                0
+            ),
+            Ok(())
         );
-        path.active_dcid_seq = Some(dcid_seq);
 
-        let pid = self
-            .paths
-            .insert_path(path, false)
-            .map_err(|_| Error::OutOfIdentifiers)?;
-        self.ids.link_dcid_to_path_id(dcid_seq, pid)?;
+        // The client's address is not validated yet.
+        let path = pipe.server.paths.get_active().unwrap();
+        assert!(!path.verified_peer_address);
+        assert!(!path.recovery.resume_enabled());
 
-        Ok(pid)
-    }
+        // Careful resume is set up once it is.
+        let path = pipe.server.paths.get_active_mut().unwrap();
+        path.verified_peer_address = true;
+        pipe.server.maybe_setup_server_careful_resume();
 
-    // Marks the connection as closed and does any related tidyup.
-    fn mark_closed(&mut self) {
-        #[cfg(feature = "qlog")]
-        {
-            let cc = match (self.is_established(), self.timed_out, &self.peer_error, &self.local_error) {
-                (false, _, _, _) => qlog::events::connectivity::ConnectionClosed {
-                    owner: Some(TransportOwner::Local),
-                    connection_code: None,
-                    application_code: None,
-                    internal_code: None,
-                    reason: Some("Failed to establish connection".to_string()),
-                    trigger: Some(qlog::events::connectivity::ConnectionClosedTrigger::HandshakeTimeout)
-                },
+        let path = pipe.server.paths.get_active().unwrap();
+        assert!(path.recovery.resume_enabled());
+        assert_eq!(pipe.server.pending_server_cr_params, None);
+    }
 
-                (true, true, _, _) => qlog::events::connectivity::ConnectionClosed {
-                    owner: Some(TransportOwner::Local),
-                    connection_code: None,
-                    application_code: None,
-                    internal_code: None,
-                    reason: Some("Idle timeout".to_string()),
-                    trigger: Some(qlog::events::connectivity::ConnectionClosedTrigger::IdleTimeout)
+    #[test]
+    fn resume_blob_unvalidated() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_resume(true);
+        assert_eq!(config.set_resume_blob_key(&[0xba; 16]), Ok(()));
+
+        let key = resume_blob::BlobKey::new(&[0xba; 16]).unwrap();
+        let blob = key
+            .seal(
+                &CREvent {
+                    min_rtt: Duration::from_millis(50),
+                    cwnd: 120_000,
+                    pacing_rate: 0,
+                    post_congestion: false,
+                    max_datagram_size: 0,
+                    loss_rate_ppm: 0,
                 },
+                testing::Pipe::client_addr().ip(),
+                time::SystemTime::now(),
+            )
+            .unwrap();
 
-                (true, false, Some(peer_error), None) => {
-                    let (connection_code, application_code) = if peer_error.is_app {
-                        (None, Some(qlog::events::ApplicationErrorCode::Value(peer_error.error_code)))
-                    } else {
-                        (Some(qlog::events::ConnectionErrorCode::Value(peer_error.error_code)), None)
-                    };
+        // Blobs are echoed in the client's first flight, either in transport
+        // parameters or in NEW_TOKEN tokens.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        pipe.server.process_resume_blob(&blob);
 
-                    qlog::events::connectivity::ConnectionClosed {
-                        owner: Some(TransportOwner::Remote),
-                        connection_code,
-                        application_code,
-                        internal_code: None,
-                        reason: Some(String::from_utf8_lossy(&peer_error.reason).to_string()),
-                        trigger: Some(qlog::events::connectivity::ConnectionClosedTrigger::Error),
-                    }
-                },
+        // The client's address is not validated yet.
+        let path = pipe.server.paths.get_active().unwrap();
+        assert!(!path.verified_peer_address);
+        assert!(!path.recovery.resume_enabled());
+        assert_eq!(
+            pipe.server.pending_server_cr_params.map(|p| p.cwnd),
+            Some(120_000)
+        );
 
-                (true, false, None, Some(local_error)) => {
-                    let (connection_code, application_code) = if local_error.is_app {
-                        (None, Some(qlog::events::ApplicationErrorCode::Value(local_error.error_code)))
-                    } else {
-                        (Some(qlog::events::ConnectionErrorCode::Value(local_error.error_code)), None)
-                    };
+        // Careful resume is set up once it is.
+        let path = pipe.server.paths.get_active_mut().unwrap();
+        path.verified_peer_address = true;
+        pipe.server.maybe_setup_server_careful_resume();
 
-                    qlog::events::connectivity::ConnectionClosed {
-                        owner: Some(TransportOwner::Local),
-                        connection_code,
-                        application_code,
-                        internal_code: None,
-                        reason: Some(String::from_utf8_lossy(&local_error.reason).to_string()),
-                        trigger: Some(qlog::events::connectivity::ConnectionClosedTrigger::Error),
-                    }
-                },
+        let path = pipe.server.paths.get_active().unwrap();
+        assert!(path.recovery.resume_enabled());
+        assert_eq!(pipe.server.pending_server_cr_params, None);
+    }
 
-                _ => qlog::events::connectivity::ConnectionClosed {
-                    owner: None,
-                    connection_code: None,
-                    application_code: None,
-                    internal_code: None,
-                    reason: None,
-                    trigger: None,
-                },
-            };
+    #[test]
+    fn cr_params_policy() {
+        // Caps the jump of each client, and rejects parameters supplied by
+        // the client above a sanity limit.
+        struct Policy;
+
+        impl CrParamsPolicy for Policy {
+            fn check(
+                &self, _peer: SocketAddr, source: CrParamsSource,
+                params: &CREvent,
+            ) -> Option<CREvent> {
+                if source.is_peer() && params.cwnd > 1_000_000 {
+                    return None;
+                }
 
-            qlog_with_type!(QLOG_CONNECTION_CLOSED, self.qlog, q, {
-                let ev_data = qlog::events::EventData::ConnectionClosed(cc);
+                let mut params = *params;
+                params.cwnd = params.cwnd.min(60_000);
 
-                q.add_event_data_now(ev_data).ok();
-            });
-            self.qlog.streamer = None;
+                Some(params)
+            }
         }
-        self.closed = true;
-    }
 
-    fn update_cr_event(&mut self, event: CREvent) {
-        self.cr_event.replace(event);
-    }
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_resume(true);
+        config.set_cr_params_policy(Arc::new(Policy));
 
-    /// Returns a [`CREvent`], or None when there are no events to report. Please refer to [`CREvent`] for event details.
-    ///
-    /// Once reported events will not be reported again by calling this method again, until a new event is available.
-    ///
-    /// [`CREvent`]: struct.CREvent.html
-    pub fn cr_event_next(&mut self) -> Option<CREvent> {
-        self.cr_event.take()
-    }
-}
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
-#[cfg(feature = "boringssl-boring-crate")]
-impl AsMut<boring::ssl::SslRef> for Connection {
-    fn as_mut(&mut self) -> &mut boring::ssl::SslRef {
-        self.handshake.ssl_mut()
-    }
-}
+        let params = CREvent {
+            min_rtt: Duration::from_millis(50),
+            cwnd: 2_000_000,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        };
 
-/// Maps an `Error` to `Error::Done`, or itself.
-///
-/// When a received packet that hasn't yet been authenticated triggers a failure
-/// it should, in most cases, be ignored, instead of raising a connection error,
-/// to avoid potential man-in-the-middle and man-on-the-side attacks.
-///
-/// However, if no other packet was previously received, the connection should
-/// indeed be closed as the received packet might just be network background
-/// noise, and it shouldn't keep resources occupied indefinitely.
-///
-/// This function maps an error to `Error::Done` to ignore a packet failure
-/// without aborting the connection, except when no other packet was previously
-/// received, in which case the error itself is returned, but only on the
-/// server-side as the client will already have armed the idle timer.
-///
-/// This must only be used for errors preceding packet authentication. Failures
-/// happening after a packet has been authenticated should still cause the
-/// connection to be aborted.
-fn drop_pkt_on_err(
-    e: Error, recv_count: usize, is_server: bool, trace_id: &str,
-) -> Error {
-    // On the server, if no other packet has been successfully processed, abort
-    // the connection to avoid keeping the connection open when only junk is
-    // received.
-    if is_server && recv_count == 0 {
-        return e;
+        // Parameters from the application are clamped.
+        assert_eq!(
+            pipe.server.setup_server_careful_resume(
+                params.min_rtt,
+                params.cwnd,
+                params.pacing_rate
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            pipe.server.pending_server_cr_params.map(|p| p.cwnd),
+            Some(60_000)
+        );
+        assert_eq!(pipe.server.cr_rejection_next(), None);
+
+        // Parameters from the client are rejected.
+        assert_eq!(
+            pipe.server
+                .check_cr_params(params, CrParamsSource::BdpFrame),
+            None
+        );
+        assert_eq!(
+            pipe.server.cr_rejection_next(),
+            Some(CrRejectReason::Policy)
+        );
+
+        // The policy doesn't apply to clients.
+        assert_eq!(
+            pipe.client
+                .check_cr_params(params, CrParamsSource::BdpFrame),
+            Some(params)
+        );
     }
 
-    trace!("{} dropped invalid packet", trace_id);
+    #[test]
+    fn cr_phase_observer() {
+        #[derive(Default)]
+        struct Observer(std::sync::Mutex<Vec<CarefulResumeState>>);
+
+        impl CrPhaseObserver for Observer {
+            fn on_cr_phase_change(
+                &self, _old: CarefulResumeState, new: CarefulResumeState,
+                _trigger: qlog::events::resume::CarefulResumeTrigger,
+                _pipesize: usize,
+            ) {
+                self.0.lock().unwrap().push(new);
+            }
+        }
 
-    // Ignore other invalid packets that haven't been authenticated to prevent
-    // man-in-the-middle and man-on-the-side attacks.
-    Error::Done
-}
+        let config_observer = Arc::new(Observer::default());
+        let conn_observer = Arc::new(Observer::default());
 
-struct AddrTupleFmt(SocketAddr, SocketAddr);
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_resume(true);
+        config.set_cr_phase_observer(config_observer.clone());
 
-impl std::fmt::Display for AddrTupleFmt {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let AddrTupleFmt(src, dst) = &self;
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
-        if src.ip().is_unspecified() || dst.ip().is_unspecified() {
-            return Ok(());
-        }
+        assert_eq!(
+            pipe.client.setup_careful_resume(
+                Duration::from_millis(50),
+                120_000,
+                0
+            ),
+            Ok(())
+        );
+        pipe.client.abandon_careful_resume();
 
-        f.write_fmt(format_args!("src:{src} dst:{dst}"))
+        assert_eq!(*config_observer.0.lock().unwrap(), vec![
+            CarefulResumeState::Normal
+        ]);
+
+        // An observer set on the connection replaces the configured one.
+        pipe.server.set_cr_phase_observer(conn_observer.clone());
+
+        assert_eq!(
+            pipe.server.setup_careful_resume(
+                Duration::from_millis(50),
+                120_000,
+                0
+            ),
+            Ok(())
+        );
+        pipe.server.abandon_careful_resume();
+
+        assert_eq!(config_observer.0.lock().unwrap().len(), 1);
+        assert_eq!(*conn_observer.0.lock().unwrap(), vec![
+            CarefulResumeState::Normal
+        ]);
     }
-}
 
-/// Statistics about the connection.
-///
-/// A connection's statistics can be collected using the [`stats()`] method.
-///
-/// [`stats()`]: struct.Connection.html#method.stats
-#[derive(Clone, Default)]
-pub struct Stats {
-    /// The number of QUIC packets received.
-    pub recv: usize,
+    #[test]
+    fn cr_reset_on_retry() {
+        let mut buf = [0; 65535];
 
-    /// The number of QUIC packets sent.
-    pub sent: usize,
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
 
-    /// The number of QUIC packets that were lost.
-    pub lost: usize,
+        let mut pipe = testing::Pipe::with_server_config(&mut config).unwrap();
 
-    /// The number of sent QUIC packets with retransmitted data.
-    pub retrans: usize,
+        assert_eq!(
+            pipe.client.setup_careful_resume(
+                Duration::from_millis(50),
+                120_000,
+                0
+            ),
+            Ok(())
+        );
 
-    /// The number of sent bytes.
-    pub sent_bytes: u64,
+        // Client sends initial flight.
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
 
-    /// The number of received bytes.
-    pub recv_bytes: u64,
+        // Server sends Retry packet.
+        let hdr = Header::from_slice(&mut buf[..len], MAX_CONN_ID_LEN).unwrap();
 
-    /// The number of bytes sent acked.
-    pub acked_bytes: u64,
+        let odcid = hdr.dcid.clone();
 
-    /// The number of bytes sent lost.
-    pub lost_bytes: u64,
+        let mut scid = [0; MAX_CONN_ID_LEN];
+        rand::rand_bytes(&mut scid[..]);
+        let scid = ConnectionId::from_ref(&scid);
 
-    /// The number of stream bytes retransmitted.
-    pub stream_retrans_bytes: u64,
+        len = packet::retry(
+            &hdr.scid,
+            &hdr.dcid,
+            &scid,
+            b"quiche test retry token",
+            hdr.version,
+            &mut buf,
+        )
+        .unwrap();
 
-    /// The number of known paths for the connection.
-    pub paths_count: usize,
+        // Careful resume starts over with the same parameters.
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(
+            pipe.client.careful_resume_state(),
+            Some(CarefulResumeState::Reconnaissance)
+        );
 
-    /// The number of streams reset by local.
-    pub reset_stream_count_local: u64,
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
-    /// The number of streams stopped by local.
-    pub stopped_stream_count_local: u64,
+        // Server accepts connection.
+        let from = "127.0.0.1:1234".parse().unwrap();
+        pipe.server = accept(
+            &scid,
+            Some(&odcid),
+            testing::Pipe::server_addr(),
+            from,
+            &mut config,
+        )
+        .unwrap();
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
 
-    /// The number of streams reset by remote.
-    pub reset_stream_count_remote: u64,
+        assert_eq!(pipe.advance(), Ok(()));
 
-    /// The number of streams stopped by remote.
-    pub stopped_stream_count_remote: u64,
+        assert!(pipe.client.is_established());
+        assert!(pipe.server.is_established());
+    }
 
-    /// The total number of PATH_CHALLENGE frames that were received.
-    pub path_challenge_rx_count: u64,
-}
+    #[test]
+    fn cr_reset_on_migration() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_active_connection_id_limit(3);
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
 
-impl std::fmt::Debug for Stats {
-    #[inline]
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(
-            f,
-            "recv={} sent={} lost={} retrans={}",
-            self.recv, self.sent, self.lost, self.retrans,
-        )?;
+        let mut pipe = pipe_with_exchanged_cids(&mut config, 16, 16, 2);
 
-        write!(
-            f,
-            " sent_bytes={} recv_bytes={} lost_bytes={}",
-            self.sent_bytes, self.recv_bytes, self.lost_bytes,
-        )?;
+        let server_addr = testing::Pipe::server_addr();
+        let client_addr_2 = "127.0.0.1:5678".parse().unwrap();
 
-        Ok(())
+        assert_eq!(
+            pipe.client.setup_careful_resume(
+                Duration::from_millis(50),
+                120_000,
+                0
+            ),
+            Ok(())
+        );
+        let old_pid = pipe.client.paths.get_active_path_id().unwrap();
+
+        assert_eq!(pipe.client.probe_path(client_addr_2, server_addr), Ok(1));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.migrate(client_addr_2, server_addr), Ok(1));
+
+        // Careful resume is over on the old path, and nothing of it is
+        // carried over to the new one.
+        let recovery = &pipe.client.paths.get(old_pid).unwrap().recovery;
+        assert_eq!(recovery.cr_state(), Some(CarefulResumeState::Normal));
+        assert_eq!(recovery.cr_last_jump(), None);
+        assert_eq!(pipe.client.careful_resume_state(), None);
+
+        assert_eq!(pipe.client.stream_send(0, b"data", true), Ok(4));
+        assert_eq!(pipe.advance(), Ok(()));
+        assert_eq!(pipe.client.careful_resume_state(), None);
     }
-}
 
-/// QUIC Transport Parameters
-#[derive(Clone, Debug, PartialEq)]
-pub struct TransportParams {
-    /// Value of Destination CID field from first Initial packet sent by client
-    pub original_destination_connection_id: Option<ConnectionId<'static>>,
-    /// The maximum idle timeout.
-    pub max_idle_timeout: u64,
-    /// Token used for verifying stateless resets
-    pub stateless_reset_token: Option<u128>,
-    /// The maximum UDP payload size.
-    pub max_udp_payload_size: u64,
-    /// The initial flow control maximum data for the connection.
-    pub initial_max_data: u64,
-    /// The initial flow control maximum data for local bidirectional streams.
-    pub initial_max_stream_data_bidi_local: u64,
-    /// The initial flow control maximum data for remote bidirectional streams.
-    pub initial_max_stream_data_bidi_remote: u64,
-    /// The initial flow control maximum data for unidirectional streams.
-    pub initial_max_stream_data_uni: u64,
-    /// The initial maximum bidirectional streams.
-    pub initial_max_streams_bidi: u64,
-    /// The initial maximum unidirectional streams.
-    pub initial_max_streams_uni: u64,
-    /// The ACK delay exponent.
-    pub ack_delay_exponent: u64,
-    /// The max ACK delay.
-    pub max_ack_delay: u64,
-    /// Whether active migration is disabled.
-    pub disable_active_migration: bool,
-    /// The active connection ID limit.
-    pub active_conn_id_limit: u64,
-    /// The value that the endpoint included in the Source CID field of a Retry
-    /// Packet.
-    pub initial_source_connection_id: Option<ConnectionId<'static>>,
-    /// The value that the server included in the Source CID field of a Retry
-    /// Packet.
-    pub retry_source_connection_id: Option<ConnectionId<'static>>,
-    /// DATAGRAM frame extension parameter, if any.
-    pub max_datagram_frame_size: Option<u64>,
-    // pub preferred_address: ...,
-    /// Address validation tokens contain BDP data
-    pub bdp_tokens: bool,
-}
+    #[test]
+    fn cr_key_update() {
+        use qlog::events::resume::CarefulResumeTrigger;
 
-impl Default for TransportParams {
-    fn default() -> TransportParams {
-        TransportParams {
-            original_destination_connection_id: None,
-            max_idle_timeout: 0,
-            stateless_reset_token: None,
-            max_udp_payload_size: 65527,
-            initial_max_data: 0,
-            initial_max_stream_data_bidi_local: 0,
-            initial_max_stream_data_bidi_remote: 0,
-            initial_max_stream_data_uni: 0,
-            initial_max_streams_bidi: 0,
-            initial_max_streams_uni: 0,
-            ack_delay_exponent: 3,
-            max_ack_delay: 25,
-            disable_active_migration: false,
-            active_conn_id_limit: 2,
-            initial_source_connection_id: None,
-            retry_source_connection_id: None,
-            max_datagram_frame_size: None,
-            bdp_tokens: false,
+        #[derive(Default)]
+        struct Observer(std::sync::Mutex<Vec<CarefulResumeTrigger>>);
+
+        impl CrPhaseObserver for Observer {
+            fn on_cr_phase_change(
+                &self, _old: CarefulResumeState, _new: CarefulResumeState,
+                trigger: CarefulResumeTrigger, _pipesize: usize,
+            ) {
+                self.0.lock().unwrap().push(trigger);
+            }
         }
-    }
-}
 
-impl TransportParams {
-    fn decode(buf: &[u8], is_server: bool) -> Result<TransportParams> {
-        let mut params = octets::Octets::with_slice(buf);
-        let mut seen_params = HashSet::new();
+        let observer = Arc::new(Observer::default());
+
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        pipe.server.set_cr_phase_observer(observer.clone());
+        assert_eq!(
+            pipe.server.setup_careful_resume(
+                Duration::from_millis(50),
+                120_000,
+                0
+            ),
+            Ok(())
+        );
+        assert_eq!(pipe.advance(), Ok(()));
 
-        let mut tp = TransportParams::default();
+        let state = pipe.server.careful_resume_state();
+        assert!(state.is_some());
 
-        while params.cap() > 0 {
-            let id = params.get_varint()?;
+        // The server updates its keys on receiving the client's packet, but
+        // the recovery state, and so careful resume, is kept.
+        assert_eq!(pipe.client_update_key(), Ok(()));
+        assert_eq!(pipe.client.stream_send(4, b"hello", false), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
 
-            if seen_params.contains(&id) {
-                return Err(Error::InvalidTransportParam);
-            }
-            seen_params.insert(id);
+        assert_eq!(pipe.server.careful_resume_state(), state);
+        assert!(!observer
+            .0
+            .lock()
+            .unwrap()
+            .contains(&CarefulResumeTrigger::Reset));
+    }
 
-            let mut val = params.get_bytes_with_varint_length()?;
+    #[test]
+    fn reconfigure() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_resume(true);
 
-            match id {
-                0x0000 => {
-                    if is_server {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
-                    tp.original_destination_connection_id =
-                        Some(val.to_vec().into());
-                },
+        assert_eq!(
+            pipe.server.setup_careful_resume(
+                Duration::from_millis(50),
+                120_000,
+                0
+            ),
+            Ok(())
+        );
 
-                0x0001 => {
-                    tp.max_idle_timeout = val.get_varint()?;
-                },
+        let path = pipe.server.paths.get_active().unwrap();
+        let mds = path.recovery.max_datagram_size();
+        assert_eq!(path.recovery.cwnd(), mds * 10);
 
-                0x0002 => {
-                    if is_server {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        // The congestion controller is restarted with the new settings, and
+        // careful resume stays set up.
+        let mut delta = ConfigDelta::default();
+        delta.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        delta.set_initial_congestion_window_packets(20);
+        assert_eq!(pipe.server.reconfigure(&delta), Ok(()));
 
-                    tp.stateless_reset_token = Some(u128::from_be_bytes(
-                        val.get_bytes(16)?
-                            .to_vec()
-                            .try_into()
-                            .map_err(|_| Error::BufferTooShort)?,
-                    ));
-                },
+        let path = pipe.server.paths.get_active().unwrap();
+        assert_eq!(path.recovery.cwnd(), mds * 20);
+        assert!(path.recovery.resume_enabled());
 
-                0x0003 => {
-                    tp.max_udp_payload_size = val.get_varint()?;
+        // Invalid careful resume settings are rejected.
+        let mut cr = CrConfig::default();
+        cr.enable_pacing(false);
 
-                    if tp.max_udp_payload_size < 1200 {
-                        return Err(Error::InvalidTransportParam);
-                    }
-                },
+        let mut delta = ConfigDelta::default();
+        delta.set_cr_config(cr);
+        assert_eq!(
+            pipe.server.reconfigure(&delta),
+            Err(Error::CongestionControl)
+        );
 
-                0x0004 => {
-                    tp.initial_max_data = val.get_varint()?;
-                },
+        let path = pipe.server.paths.get_active().unwrap();
+        assert_eq!(path.recovery.cwnd(), mds * 20);
 
-                0x0005 => {
-                    tp.initial_max_stream_data_bidi_local = val.get_varint()?;
-                },
+        // Careful resume can be disabled for this connection only.
+        let mut cr = CrConfig::default();
+        cr.enable_resume(false);
 
-                0x0006 => {
-                    tp.initial_max_stream_data_bidi_remote = val.get_varint()?;
-                },
+        let mut delta = ConfigDelta::default();
+        delta.set_cr_config(cr);
+        assert_eq!(pipe.server.reconfigure(&delta), Ok(()));
 
-                0x0007 => {
-                    tp.initial_max_stream_data_uni = val.get_varint()?;
-                },
+        let path = pipe.server.paths.get_active().unwrap();
+        assert!(!path.recovery.resume_enabled());
+        assert_eq!(
+            pipe.server.setup_careful_resume(
+                Duration::from_millis(50),
+                120_000,
+                0
+            ),
+            Err(Error::InvalidState)
+        );
 
-                0x0008 => {
-                    let max = val.get_varint()?;
+        assert!(pipe.client.recovery_config.resume);
 
-                    if max > MAX_STREAM_ID {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        // Settings can't change once the handshake is completed.
+        pipe.server.handshake_completed = true;
+        assert_eq!(
+            pipe.server.reconfigure(&ConfigDelta::default()),
+            Err(Error::InvalidState)
+        );
+    }
 
-                    tp.initial_max_streams_bidi = max;
+    #[test]
+    fn stateless_resume_blob_wrong_key() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        assert_eq!(config.set_resume_blob_key(&[0xba; 16]), Ok(()));
+
+        let key = resume_blob::BlobKey::new(&[0xab; 16]).unwrap();
+        let blob = key
+            .seal(
+                &CREvent {
+                    min_rtt: Duration::from_millis(50),
+                    cwnd: 120_000,
+                    pacing_rate: 0,
+                    post_congestion: false,
+                    max_datagram_size: 0,
+                    loss_rate_ppm: 0,
                 },
+                testing::Pipe::client_addr().ip(),
+                time::SystemTime::now(),
+            )
+            .unwrap();
 
-                0x0009 => {
-                    let max = val.get_varint()?;
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.set_resume_blob(&blob), Ok(()));
+        assert_eq!(pipe.handshake(), Ok(()));
 
-                    if max > MAX_STREAM_ID {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        assert!(!pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
+    }
 
-                    tp.initial_max_streams_uni = max;
-                },
+    #[test]
+    fn saved_transport_serialization() {
+        let saved = SavedTransport {
+            session: Some(vec![0xba; 300]),
+            rtt: Some(Duration::from_millis(42)),
+            careful_resume: Some(CREvent {
+                min_rtt: Duration::from_millis(40),
+                cwnd: 120_000,
+                pacing_rate: 3_750_000,
+                post_congestion: false,
+                max_datagram_size: 0,
+                loss_rate_ppm: 0,
+            }),
+        };
 
-                0x000a => {
-                    let ack_delay_exponent = val.get_varint()?;
+        assert_eq!(
+            SavedTransport::from_bytes(&saved.to_bytes()),
+            Ok(saved.clone())
+        );
 
-                    if ack_delay_exponent > 20 {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        // State serialized without the pacing rate.
+        let mut legacy = saved.clone();
+        legacy.careful_resume.as_mut().unwrap().pacing_rate = 0;
 
-                    tp.ack_delay_exponent = ack_delay_exponent;
-                },
+        let mut bytes = legacy.to_bytes();
+        bytes.pop();
 
-                0x000b => {
-                    let max_ack_delay = val.get_varint()?;
+        assert_eq!(SavedTransport::from_bytes(&bytes), Ok(legacy));
 
-                    if max_ack_delay >= 2_u64.pow(14) {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        let empty = SavedTransport::default();
+        assert_eq!(SavedTransport::from_bytes(&empty.to_bytes()), Ok(empty));
 
-                    tp.max_ack_delay = max_ack_delay;
-                },
+        assert_eq!(
+            SavedTransport::from_bytes(&[0x05, 0xba]),
+            Err(Error::BufferTooShort)
+        );
+    }
 
-                0x000c => {
-                    tp.disable_active_migration = true;
-                },
+    #[test]
+    fn resume_from() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.enable_resume(true);
+        config.verify_peer(false);
 
-                0x000d => {
-                    if is_server {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-                    // TODO: decode preferred_address
-                },
+        let mut saved = pipe.client.saved_transport();
+        assert!(saved.session.is_some());
+        assert!(saved.rtt.is_some());
+
+        saved.careful_resume = Some(CREvent {
+            min_rtt: Duration::from_millis(50),
+            cwnd: 120_000,
+            pacing_rate: 3_000_000,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        });
 
-                0x000e => {
-                    let limit = val.get_varint()?;
+        let saved = SavedTransport::from_bytes(&saved.to_bytes()).unwrap();
 
-                    if limit < 2 {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.client.resume_from(&saved), Ok(()));
+        assert!(pipe
+            .client
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
 
-                    tp.active_conn_id_limit = limit;
-                },
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert!(pipe.client.is_resumed());
 
-                0x000f => {
-                    tp.initial_source_connection_id = Some(val.to_vec().into());
-                },
+        // State can't be resumed once the handshake started.
+        assert_eq!(pipe.client.resume_from(&saved), Err(Error::InvalidState));
+    }
 
-                0x00010 => {
-                    if is_server {
-                        return Err(Error::InvalidTransportParam);
-                    }
+    #[test]
+    fn pacing_rate() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
 
-                    tp.retry_source_connection_id = Some(val.to_vec().into());
-                },
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
-                0x0020 => {
-                    tp.max_datagram_frame_size = Some(val.get_varint()?);
-                },
+        // The rate is only known once there is an RTT sample.
+        assert_eq!(pipe.client.pacing_rate(), None);
 
-                0x1312 => {
-                    let bdp_tokens = val.get_varint()?;
+        assert_eq!(pipe.handshake(), Ok(()));
 
-                    if bdp_tokens > 1 {
-                        return Err(Error::InvalidTransportParam);
-                    }
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
 
-                    tp.bdp_tokens = bdp_tokens != 0;
-                }
+        assert!(pipe.client.pacing_rate().is_some());
 
-                // Ignore unknown parameters.
-                _ => (),
-            }
-        }
+        // No rate is reported when pacing is disabled.
+        config.enable_pacing(false);
 
-        Ok(tp)
-    }
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-    fn encode_param(
-        b: &mut octets::OctetsMut, ty: u64, len: usize,
-    ) -> Result<()> {
-        b.put_varint(ty)?;
-        b.put_varint(len as u64)?;
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        Ok(())
+        assert_eq!(pipe.client.pacing_rate(), None);
     }
 
-    fn encode<'a>(
-        tp: &TransportParams, is_server: bool, out: &'a mut [u8],
-    ) -> Result<&'a mut [u8]> {
-        let mut b = octets::OctetsMut::with_slice(out);
+    #[test]
+    fn bdp_frame() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_bdp_frame(true);
+        config.set_resume_blob_max_age(Duration::from_secs(3600));
+        assert_eq!(config.set_resume_blob_key(&[0xba; 16]), Ok(()));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        if is_server {
-            if let Some(ref odcid) = tp.original_destination_connection_id {
-                TransportParams::encode_param(&mut b, 0x0000, odcid.len())?;
-                b.put_bytes(odcid)?;
-            }
-        };
+        let min_rtt = pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .min_rtt()
+            .unwrap();
 
-        if tp.max_idle_timeout != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0001,
-                octets::varint_len(tp.max_idle_timeout),
-            )?;
-            b.put_varint(tp.max_idle_timeout)?;
-        }
+        // The server sends the parameters it observed.
+        pipe.server.update_cr_event(0, CREvent {
+            min_rtt,
+            cwnd: 120_000,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        });
 
-        if is_server {
-            if let Some(ref token) = tp.stateless_reset_token {
-                TransportParams::encode_param(&mut b, 0x0002, 16)?;
-                b.put_bytes(&token.to_be_bytes())?;
-            }
-        }
+        assert_eq!(pipe.advance(), Ok(()));
 
-        if tp.max_udp_payload_size != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0003,
-                octets::varint_len(tp.max_udp_payload_size),
-            )?;
-            b.put_varint(tp.max_udp_payload_size)?;
-        }
+        let bdp = pipe.client.bdp_frame_next().unwrap();
+        assert_eq!(bdp.lifetime, Duration::from_secs(3600));
+        assert_eq!(bdp.saved_capacity, 120_000);
+        assert_eq!(bdp.saved_rtt, min_rtt);
+        assert!(!bdp.token.is_empty());
+        assert_eq!(pipe.client.bdp_frame_next(), None);
 
-        if tp.initial_max_data != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0004,
-                octets::varint_len(tp.initial_max_data),
-            )?;
-            b.put_varint(tp.initial_max_data)?;
-        }
+        assert_eq!(
+            pipe.server.echo_bdp_frame(&bdp, Duration::ZERO),
+            Err(Error::InvalidState)
+        );
 
-        if tp.initial_max_stream_data_bidi_local != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0005,
-                octets::varint_len(tp.initial_max_stream_data_bidi_local),
-            )?;
-            b.put_varint(tp.initial_max_stream_data_bidi_local)?;
-        }
+        // Expired frames are not echoed.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(
+            pipe.client.echo_bdp_frame(&bdp, Duration::from_secs(3601)),
+            Ok(())
+        );
+        assert_eq!(
+            pipe.client.cr_rejection_next(),
+            Some(CrRejectReason::Expired)
+        );
 
-        if tp.initial_max_stream_data_bidi_remote != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0006,
-                octets::varint_len(tp.initial_max_stream_data_bidi_remote),
-            )?;
-            b.put_varint(tp.initial_max_stream_data_bidi_remote)?;
-        }
+        // The client echoes the frame on its next connection, and the server
+        // resumes with it.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(
+            pipe.client.echo_bdp_frame(&bdp, Duration::from_secs(60)),
+            Ok(())
+        );
 
-        if tp.initial_max_stream_data_uni != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0007,
-                octets::varint_len(tp.initial_max_stream_data_uni),
-            )?;
-            b.put_varint(tp.initial_max_stream_data_uni)?;
-        }
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        if tp.initial_max_streams_bidi != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0008,
-                octets::varint_len(tp.initial_max_streams_bidi),
-            )?;
-            b.put_varint(tp.initial_max_streams_bidi)?;
-        }
+        assert_eq!(pipe.server.cr_rejection_next(), None);
+        assert!(pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
 
-        if tp.initial_max_streams_uni != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x0009,
-                octets::varint_len(tp.initial_max_streams_uni),
-            )?;
-            b.put_varint(tp.initial_max_streams_uni)?;
-        }
+        // The parameters in clear are not trusted by the server, which uses
+        // the sealed ones instead.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        let inflated = BdpFrame {
+            saved_capacity: 1 << 40,
+            ..bdp.clone()
+        };
+        assert_eq!(
+            pipe.client
+                .echo_bdp_frame(&inflated, Duration::from_secs(60)),
+            Ok(())
+        );
 
-        if tp.ack_delay_exponent != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x000a,
-                octets::varint_len(tp.ack_delay_exponent),
-            )?;
-            b.put_varint(tp.ack_delay_exponent)?;
-        }
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        if tp.max_ack_delay != 0 {
-            TransportParams::encode_param(
-                &mut b,
-                0x000b,
-                octets::varint_len(tp.max_ack_delay),
-            )?;
-            b.put_varint(tp.max_ack_delay)?;
-        }
+        assert_eq!(pipe.server.cr_rejection_next(), None);
+        assert!(pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
 
-        if tp.disable_active_migration {
-            TransportParams::encode_param(&mut b, 0x000c, 0)?;
-        }
+        // Frames whose token was tampered with are ignored.
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        let mut tampered = bdp.clone();
+        tampered.token[0] ^= 0x01;
+        assert_eq!(
+            pipe.client
+                .echo_bdp_frame(&tampered, Duration::from_secs(60)),
+            Ok(())
+        );
 
-        // TODO: encode preferred_address
+        assert_eq!(pipe.handshake(), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        if tp.active_conn_id_limit != 2 {
-            TransportParams::encode_param(
-                &mut b,
-                0x000e,
-                octets::varint_len(tp.active_conn_id_limit),
-            )?;
-            b.put_varint(tp.active_conn_id_limit)?;
-        }
+        assert_eq!(pipe.server.cr_rejection_next(), None);
+        assert!(!pipe
+            .server
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .resume_enabled());
+    }
 
-        if let Some(scid) = &tp.initial_source_connection_id {
-            TransportParams::encode_param(&mut b, 0x000f, scid.len())?;
-            b.put_bytes(scid)?;
-        }
+    #[test]
+    fn cr_hints() {
+        #[derive(Default)]
+        struct Handler(std::sync::Mutex<Vec<CrHints>>);
 
-        if is_server {
-            if let Some(scid) = &tp.retry_source_connection_id {
-                TransportParams::encode_param(&mut b, 0x0010, scid.len())?;
-                b.put_bytes(scid)?;
+        impl CrHintsHandler for Handler {
+            fn on_cr_hints(&self, _scid: &[u8], hints: &CrHints) {
+                self.0.lock().unwrap().push(*hints);
             }
         }
 
-        if let Some(max_datagram_frame_size) = tp.max_datagram_frame_size {
-            TransportParams::encode_param(
-                &mut b,
-                0x0020,
-                octets::varint_len(max_datagram_frame_size),
-            )?;
-            b.put_varint(max_datagram_frame_size)?;
-        }
-
-        if tp.bdp_tokens {
-            TransportParams::encode_param(
-                &mut b,
-                0x1312,
-                octets::varint_len(1),
-            )?;
-            b.put_varint(1)?;
-        }
-
-        let out_len = b.off();
+        let mut buf = [0; 65535];
 
-        Ok(&mut out[..out_len])
-    }
+        let handler = Arc::new(Handler::default());
 
-    /// Creates a qlog event for connection transport parameters and TLS fields
-    #[cfg(feature = "qlog")]
-    pub fn to_qlog(
-        &self, owner: TransportOwner, cipher: Option<crypto::Algorithm>,
-    ) -> EventData {
-        let original_destination_connection_id = qlog::HexSlice::maybe_string(
-            self.original_destination_connection_id.as_ref(),
-        );
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_cr_hints(true);
+        config.set_cr_hints_handler(handler.clone());
 
-        let stateless_reset_token = qlog::HexSlice::maybe_string(
-            self.stateless_reset_token.map(|s| s.to_be_bytes()).as_ref(),
-        );
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        EventData::TransportParametersSet(
-            qlog::events::quic::TransportParametersSet {
-                owner: Some(owner),
-                resumption_allowed: None,
-                early_data_enabled: None,
-                tls_cipher: Some(format!("{cipher:?}")),
-                aead_tag_length: None,
-                original_destination_connection_id,
-                initial_source_connection_id: None,
-                retry_source_connection_id: None,
-                stateless_reset_token,
-                disable_active_migration: Some(self.disable_active_migration),
-                max_idle_timeout: Some(self.max_idle_timeout),
-                max_udp_payload_size: Some(self.max_udp_payload_size as u32),
-                ack_delay_exponent: Some(self.ack_delay_exponent as u16),
-                max_ack_delay: Some(self.max_ack_delay as u16),
-                active_connection_id_limit: Some(
-                    self.active_conn_id_limit as u32,
-                ),
+        let hints = CrHints {
+            lifetime: Duration::from_secs(600),
+            capacity: 200_000,
+            min_rtt: Duration::from_millis(40),
+            max_jump: 150_000,
+        };
 
-                initial_max_data: Some(self.initial_max_data),
-                initial_max_stream_data_bidi_local: Some(
-                    self.initial_max_stream_data_bidi_local,
-                ),
-                initial_max_stream_data_bidi_remote: Some(
-                    self.initial_max_stream_data_bidi_remote,
-                ),
-                initial_max_stream_data_uni: Some(
-                    self.initial_max_stream_data_uni,
-                ),
-                initial_max_streams_bidi: Some(self.initial_max_streams_bidi),
-                initial_max_streams_uni: Some(self.initial_max_streams_uni),
+        assert_eq!(pipe.client.send_cr_hints(hints), Err(Error::InvalidState));
 
-                preferred_address: None,
+        // Only the latest hints are sent.
+        let mut old = hints;
+        old.capacity = 100_000;
 
-                bdp_tokens: Some(self.bdp_tokens),
-            },
+        assert_eq!(pipe.server.send_cr_hints(old), Ok(()));
+        assert_eq!(pipe.server.send_cr_hints(hints), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(*handler.0.lock().unwrap(), vec![hints]);
+        assert_eq!(pipe.client.cr_hints_next(), Some(hints));
+        assert_eq!(pipe.client.cr_hints_next(), None);
+
+        // Older hints received later are ignored.
+        let frames = [frame::Frame::CrHints {
+            seq: 1,
+            lifetime: 600,
+            capacity: 100_000,
+            min_rtt: 40_000,
+            max_jump: 150_000,
+        }];
+
+        let written = testing::encode_pkt(
+            &mut pipe.server,
+            packet::Type::Short,
+            &frames,
+            &mut buf,
         )
-    }
-}
+        .unwrap();
 
-#[doc(hidden)]
-pub mod testing {
-    use super::*;
+        assert_eq!(pipe.client_recv(&mut buf[..written]), Ok(written));
+        assert_eq!(handler.0.lock().unwrap().len(), 1);
+        assert_eq!(pipe.client.cr_hints_next(), None);
 
-    pub struct Pipe {
-        pub client: Connection,
-        pub server: Connection,
-    }
+        // Clients can't send hints.
+        let written = testing::encode_pkt(
+            &mut pipe.client,
+            packet::Type::Short,
+            &frames,
+            &mut buf,
+        )
+        .unwrap();
 
-    impl Pipe {
-        pub fn new() -> Result<Pipe> {
-            let mut config = Config::new(crate::PROTOCOL_VERSION)?;
-            config.load_cert_chain_from_pem_file("examples/cert.crt")?;
-            config.load_priv_key_from_pem_file("examples/cert.key")?;
-            config.set_application_protos(&[b"proto1", b"proto2"])?;
-            config.set_initial_max_data(30);
-            config.set_initial_max_stream_data_bidi_local(15);
-            config.set_initial_max_stream_data_bidi_remote(15);
-            config.set_initial_max_stream_data_uni(10);
-            config.set_initial_max_streams_bidi(3);
-            config.set_initial_max_streams_uni(3);
-            config.set_max_idle_timeout(180_000);
-            config.verify_peer(false);
-            config.set_ack_delay_exponent(8);
+        assert_eq!(
+            pipe.server_recv(&mut buf[..written]),
+            Err(Error::InvalidState)
+        );
+    }
 
-            Pipe::with_config(&mut config)
-        }
+    #[test]
+    fn cr_hints_not_negotiated() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.enable_cr_hints(true);
 
-        pub fn client_addr() -> SocketAddr {
-            "127.0.0.1:1234".parse().unwrap()
-        }
+        let mut client_config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        client_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        client_config.verify_peer(false);
 
-        pub fn server_addr() -> SocketAddr {
-            "127.0.0.1:4321".parse().unwrap()
-        }
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config,
+            &mut config,
+        )
+        .unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        pub fn with_config(config: &mut Config) -> Result<Pipe> {
-            let mut client_scid = [0; 16];
-            rand::rand_bytes(&mut client_scid[..]);
-            let client_scid = ConnectionId::from_ref(&client_scid);
-            let client_addr = Pipe::client_addr();
+        // Hints are not sent to clients that don't support them.
+        assert_eq!(pipe.server.send_cr_hints(CrHints::default()), Ok(()));
+        assert_eq!(pipe.advance(), Ok(()));
 
-            let mut server_scid = [0; 16];
-            rand::rand_bytes(&mut server_scid[..]);
-            let server_scid = ConnectionId::from_ref(&server_scid);
-            let server_addr = Pipe::server_addr();
+        assert_eq!(pipe.client.cr_hints_next(), None);
+    }
 
-            Ok(Pipe {
-                client: connect(
-                    Some("quic.tech"),
-                    &client_scid,
-                    client_addr,
-                    server_addr,
-                    config,
-                )?,
-                server: accept(
-                    &server_scid,
-                    None,
-                    server_addr,
-                    client_addr,
-                    config,
-                )?,
-            })
-        }
+    #[test]
+    fn setup_careful_resume_from_server() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.enable_resume(true);
+        config.verify_peer(false);
+        config.set_cr_peer_params_max_age(Duration::from_secs(60));
+        config.set_cr_peer_params_max_cwnd(1_000_000);
+
+        let params = CREvent {
+            min_rtt: Duration::from_secs(10),
+            cwnd: 120_000,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        };
 
-        pub fn with_config_and_scid_lengths(
-            config: &mut Config, client_scid_len: usize, server_scid_len: usize,
-        ) -> Result<Pipe> {
-            let mut client_scid = vec![0; client_scid_len];
-            rand::rand_bytes(&mut client_scid[..]);
-            let client_scid = ConnectionId::from_ref(&client_scid);
-            let client_addr = Pipe::client_addr();
+        let resume_enabled = |conn: &Connection| {
+            conn.paths.get_active().unwrap().recovery.resume_enabled()
+        };
 
-            let mut server_scid = vec![0; server_scid_len];
-            rand::rand_bytes(&mut server_scid[..]);
-            let server_scid = ConnectionId::from_ref(&server_scid);
-            let server_addr = Pipe::server_addr();
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
-            Ok(Pipe {
-                client: connect(
-                    Some("quic.tech"),
-                    &client_scid,
-                    client_addr,
-                    server_addr,
-                    config,
-                )?,
-                server: accept(
-                    &server_scid,
-                    None,
-                    server_addr,
-                    client_addr,
-                    config,
-                )?,
-            })
-        }
+        assert_eq!(
+            pipe.server
+                .setup_careful_resume_from_server(&params, Duration::ZERO),
+            Err(Error::InvalidState)
+        );
 
-        pub fn with_client_config(client_config: &mut Config) -> Result<Pipe> {
-            let mut client_scid = [0; 16];
-            rand::rand_bytes(&mut client_scid[..]);
-            let client_scid = ConnectionId::from_ref(&client_scid);
-            let client_addr = Pipe::client_addr();
+        // Too old.
+        assert_eq!(
+            pipe.client.setup_careful_resume_from_server(
+                &params,
+                Duration::from_secs(61)
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            pipe.client.cr_rejection_next(),
+            Some(CrRejectReason::Expired)
+        );
 
-            let mut server_scid = [0; 16];
-            rand::rand_bytes(&mut server_scid[..]);
-            let server_scid = ConnectionId::from_ref(&server_scid);
-            let server_addr = Pipe::server_addr();
+        // Window above the cap.
+        let large = CREvent {
+            cwnd: 1_000_001,
+            ..params
+        };
+        assert_eq!(
+            pipe.client
+                .setup_careful_resume_from_server(&large, Duration::ZERO),
+            Ok(())
+        );
+        assert_eq!(
+            pipe.client.cr_rejection_next(),
+            Some(CrRejectReason::CwndAboveCap)
+        );
 
-            let mut config = Config::new(crate::PROTOCOL_VERSION)?;
-            config.load_cert_chain_from_pem_file("examples/cert.crt")?;
-            config.load_priv_key_from_pem_file("examples/cert.key")?;
-            config.set_application_protos(&[b"proto1", b"proto2"])?;
-            config.set_initial_max_data(30);
-            config.set_initial_max_stream_data_bidi_local(15);
-            config.set_initial_max_stream_data_bidi_remote(15);
-            config.set_initial_max_streams_bidi(3);
-            config.set_initial_max_streams_uni(3);
-            config.set_ack_delay_exponent(8);
+        // The RTT can only be checked once the handshake measured it.
+        assert_eq!(
+            pipe.client
+                .setup_careful_resume_from_server(&params, Duration::ZERO),
+            Ok(())
+        );
+        assert_eq!(pipe.client.cr_rejection_next(), None);
 
-            Ok(Pipe {
-                client: connect(
-                    Some("quic.tech"),
-                    &client_scid,
-                    client_addr,
-                    server_addr,
-                    client_config,
-                )?,
-                server: accept(
-                    &server_scid,
-                    None,
-                    server_addr,
-                    client_addr,
-                    &mut config,
-                )?,
-            })
-        }
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        pub fn with_server_config(server_config: &mut Config) -> Result<Pipe> {
-            let mut client_scid = [0; 16];
-            rand::rand_bytes(&mut client_scid[..]);
-            let client_scid = ConnectionId::from_ref(&client_scid);
-            let client_addr = Pipe::client_addr();
+        assert_eq!(
+            pipe.client.cr_rejection_next(),
+            Some(CrRejectReason::RttImplausible)
+        );
+        assert_eq!(pipe.client.cr_rejection_next(), None);
+        assert!(!resume_enabled(&pipe.client));
 
-            let mut server_scid = [0; 16];
-            rand::rand_bytes(&mut server_scid[..]);
-            let server_scid = ConnectionId::from_ref(&server_scid);
-            let server_addr = Pipe::server_addr();
+        // Plausible parameters are used straight away once the RTT is known.
+        let handshake_rtt = pipe
+            .client
+            .paths
+            .get_active()
+            .unwrap()
+            .recovery
+            .min_rtt()
+            .unwrap();
+        let valid = CREvent {
+            min_rtt: handshake_rtt,
+            ..params
+        };
+        assert_eq!(
+            pipe.client
+                .setup_careful_resume_from_server(&valid, Duration::ZERO),
+            Ok(())
+        );
+        assert_eq!(pipe.client.cr_rejection_next(), None);
+        assert!(resume_enabled(&pipe.client));
+    }
 
-            let mut config = Config::new(crate::PROTOCOL_VERSION)?;
-            config.set_application_protos(&[b"proto1", b"proto2"])?;
-            config.set_initial_max_data(30);
-            config.set_initial_max_stream_data_bidi_local(15);
-            config.set_initial_max_stream_data_bidi_remote(15);
-            config.set_initial_max_streams_bidi(3);
-            config.set_initial_max_streams_uni(3);
-            config.set_ack_delay_exponent(8);
+    /// A qlog writer whose output can be read back once the connection is done.
+    #[cfg(feature = "qlog")]
+    #[derive(Clone, Default)]
+    struct SharedQlog(Arc<std::sync::Mutex<Vec<u8>>>);
 
-            Ok(Pipe {
-                client: connect(
-                    Some("quic.tech"),
-                    &client_scid,
-                    client_addr,
-                    server_addr,
-                    &mut config,
-                )?,
-                server: accept(
-                    &server_scid,
-                    None,
-                    server_addr,
-                    client_addr,
-                    server_config,
-                )?,
-            })
+    #[cfg(feature = "qlog")]
+    impl std::io::Write for SharedQlog {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
         }
 
-        pub fn with_client_and_server_config(
-            client_config: &mut Config, server_config: &mut Config,
-        ) -> Result<Pipe> {
-            let mut client_scid = [0; 16];
-            rand::rand_bytes(&mut client_scid[..]);
-            let client_scid = ConnectionId::from_ref(&client_scid);
-            let client_addr = Pipe::client_addr();
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
 
-            let mut server_scid = [0; 16];
-            rand::rand_bytes(&mut server_scid[..]);
-            let server_scid = ConnectionId::from_ref(&server_scid);
-            let server_addr = Pipe::server_addr();
+    #[cfg(feature = "qlog")]
+    impl SharedQlog {
+        fn cr_phases(&self) -> Vec<qlog::events::resume::CarefulResumePhase> {
+            let buf = self.0.lock().unwrap().clone();
+            let reader =
+                qlog::reader::QlogSeqReader::new(Box::new(std::io::Cursor::new(
+                    buf,
+                )))
+                .unwrap();
 
-            Ok(Pipe {
-                client: connect(
-                    Some("quic.tech"),
-                    &client_scid,
-                    client_addr,
-                    server_addr,
-                    client_config,
-                )?,
-                server: accept(
-                    &server_scid,
-                    None,
-                    server_addr,
-                    client_addr,
-                    server_config,
-                )?,
-            })
+            reader
+                .filter_map(|ev| match ev {
+                    qlog::reader::Event::Qlog(qlog::events::Event {
+                        data: EventData::CarefulResumePhaseUpdated(u),
+                        ..
+                    }) => Some(u.new),
+
+                    _ => None,
+                })
+                .collect()
         }
+    }
 
-        pub fn handshake(&mut self) -> Result<()> {
-            while !self.client.is_established() || !self.server.is_established() {
-                let flight = emit_flight(&mut self.client)?;
-                process_flight(&mut self.server, flight)?;
+    #[cfg(feature = "qlog")]
+    #[test]
+    fn close_and_flush() {
+        let mut pipe = testing::Pipe::new().unwrap();
 
-                let flight = emit_flight(&mut self.server)?;
-                process_flight(&mut self.client, flight)?;
-            }
+        let qlog = SharedQlog::default();
+        pipe.client.set_qlog(
+            Box::new(std::io::BufWriter::with_capacity(1 << 20, qlog.clone())),
+            "title".to_string(),
+            "description".to_string(),
+        );
 
-            Ok(())
-        }
+        // The log is buffered until it is flushed.
+        assert!(qlog.0.lock().unwrap().is_empty());
 
-        pub fn advance(&mut self) -> Result<()> {
+        assert_eq!(pipe.client.close_and_flush(false, 0x1, b"bye"), Ok(()));
+        assert!(!qlog.0.lock().unwrap().is_empty());
+
+        assert_eq!(
+            pipe.client.close_and_flush(false, 0x1, b"bye"),
+            Err(Error::Done)
+        );
+    }
+
+    #[cfg(feature = "qlog")]
+    #[test]
+    fn careful_resume_server_sender() {
+        use qlog::events::resume::CarefulResumePhase;
+
+        // Each flight spends half of this in transit, so that RTT samples are
+        // close to the RTT restored by the server.
+        const RTT: Duration = Duration::from_millis(20);
+
+        fn advance_delayed(pipe: &mut testing::Pipe) -> Result<()> {
             let mut client_done = false;
             let mut server_done = false;
 
             while !client_done || !server_done {
-                match emit_flight(&mut self.client) {
-                    Ok(flight) => process_flight(&mut self.server, flight)?,
+                match testing::emit_flight(&mut pipe.client) {
+                    Ok(flight) => {
+                        std::thread::sleep(RTT / 2);
+                        testing::process_flight(&mut pipe.server, flight)?
+                    },
 
                     Err(Error::Done) => client_done = true,
 
                     Err(e) => return Err(e),
                 };
 
-                match emit_flight(&mut self.server) {
-                    Ok(flight) => process_flight(&mut self.client, flight)?,
+                match testing::emit_flight(&mut pipe.server) {
+                    Ok(flight) => {
+                        std::thread::sleep(RTT / 2);
+                        testing::process_flight(&mut pipe.client, flight)?
+                    },
 
                     Err(Error::Done) => server_done = true,
 
@@ -8918,429 +14586,555 @@ pub mod testing {
             Ok(())
         }
 
-        pub fn client_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-            let server_path = &self.server.paths.get_active().unwrap();
-            let info = RecvInfo {
-                to: server_path.peer_addr(),
-                from: server_path.local_addr(),
-            };
+        let mut buf = [0; 65535];
 
-            self.client.recv(buf, info)
-        }
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(10_000_000);
+        config.set_initial_max_stream_data_bidi_local(10_000_000);
+        config.set_initial_max_stream_data_bidi_remote(10_000_000);
+        config.set_initial_max_streams_bidi(3);
+        config.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        config.enable_resume(true);
+        config.verify_peer(false);
 
-        pub fn server_recv(&mut self, buf: &mut [u8]) -> Result<usize> {
-            let client_path = &self.client.paths.get_active().unwrap();
-            let info = RecvInfo {
-                to: client_path.peer_addr(),
-                from: client_path.local_addr(),
-            };
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
-            self.server.recv(buf, info)
-        }
+        let client_qlog = SharedQlog::default();
+        let server_qlog = SharedQlog::default();
 
-        pub fn send_pkt_to_server(
-            &mut self, pkt_type: packet::Type, frames: &[frame::Frame],
-            buf: &mut [u8],
-        ) -> Result<usize> {
-            let written = encode_pkt(&mut self.client, pkt_type, frames, buf)?;
-            recv_send(&mut self.server, buf, written)
-        }
+        pipe.client.set_qlog(
+            Box::new(client_qlog.clone()),
+            "client".to_string(),
+            "careful resume".to_string(),
+        );
+        pipe.server.set_qlog(
+            Box::new(server_qlog.clone()),
+            "server".to_string(),
+            "careful resume".to_string(),
+        );
 
-        pub fn client_update_key(&mut self) -> Result<()> {
-            let space =
-                &mut self.client.pkt_num_spaces[packet::Epoch::Application];
+        // Only the server restores the parameters of a previous connection,
+        // before the handshake so the jump is subject to the initial window
+        // and address validation.
+        assert_eq!(pipe.server.setup_careful_resume(RTT, 200_000, 0), Ok(()));
 
-            let open_next = space
-                .crypto_open
-                .as_ref()
-                .unwrap()
-                .derive_next_packet_key()
-                .unwrap();
+        advance_delayed(&mut pipe).unwrap();
+        assert!(pipe.client.is_established());
+        assert!(pipe.server.is_established());
 
-            let seal_next = space
-                .crypto_seal
-                .as_ref()
-                .unwrap()
-                .derive_next_packet_key()?;
+        assert_eq!(pipe.client.stream_send(0, b"GET /", true), Ok(5));
+        advance_delayed(&mut pipe).unwrap();
 
-            let open_prev = space.crypto_open.replace(open_next);
-            space.crypto_seal.replace(seal_next);
+        let body = vec![0xba; 500_000];
+        let mut sent = 0;
+        let mut recv = 0;
+        let mut fin = false;
 
-            space.key_update = Some(packet::KeyUpdate {
-                crypto_open: open_prev.unwrap(),
-                pn_on_update: space.next_pkt_num,
-                update_acked: true,
-                timer: time::Instant::now(),
-            });
+        for _ in 0..200 {
+            if sent < body.len() {
+                match pipe.server.stream_send(0, &body[sent..], true) {
+                    Ok(v) => sent += v,
 
-            self.client.key_phase = !self.client.key_phase;
+                    Err(Error::Done) => (),
 
-            Ok(())
+                    Err(e) => panic!("stream_send failed: {:?}", e),
+                }
+            }
+
+            advance_delayed(&mut pipe).unwrap();
+
+            while let Ok((len, f)) = pipe.client.stream_recv(0, &mut buf) {
+                recv += len;
+                fin = f;
+            }
+
+            if fin {
+                break;
+            }
         }
-    }
 
-    pub fn recv_send(
-        conn: &mut Connection, buf: &mut [u8], len: usize,
-    ) -> Result<usize> {
-        let active_path = conn.paths.get_active()?;
-        let info = RecvInfo {
-            to: active_path.local_addr(),
-            from: active_path.peer_addr(),
-        };
+        assert!(fin);
+        assert_eq!(recv, body.len());
 
-        conn.recv(&mut buf[..len], info)?;
+        let server_phases = server_qlog.cr_phases();
+        assert_eq!(
+            server_phases.first(),
+            Some(&CarefulResumePhase::Reconnaissance)
+        );
+        assert!(server_phases.contains(&CarefulResumePhase::Unvalidated));
+        assert!(!server_phases.contains(&CarefulResumePhase::SafeRetreat));
+        assert_eq!(server_phases.last(), Some(&CarefulResumePhase::Normal));
 
-        let mut off = 0;
+        // The client didn't restore anything, so it never leaves
+        // reconnaissance.
+        assert_eq!(client_qlog.cr_phases(), vec![
+            CarefulResumePhase::Reconnaissance
+        ]);
+    }
 
-        match conn.send(&mut buf[off..]) {
-            Ok((write, _)) => off += write,
+    #[cfg(feature = "qlog")]
+    #[test]
+    fn qlog_stats() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        pipe.client.set_qlog(
+            Box::new(Vec::new()),
+            "title".to_string(),
+            "description".to_string(),
+        );
 
-            Err(Error::Done) => (),
+        assert_eq!(pipe.handshake(), Ok(()));
 
-            Err(e) => return Err(e),
-        }
+        let stats = pipe.client.stats();
+        assert!(stats.qlog_metrics_updated_count > 0);
 
-        Ok(off)
+        // Qlog is not enabled on the server.
+        let stats = pipe.server.stats();
+        assert_eq!(stats.qlog_metrics_updated_count, 0);
+        assert_eq!(stats.qlog_recovery_time, Duration::ZERO);
     }
 
-    pub fn process_flight(
-        conn: &mut Connection, flight: Vec<(Vec<u8>, SendInfo)>,
-    ) -> Result<()> {
-        for (mut pkt, si) in flight {
-            let info = RecvInfo {
-                to: si.to,
-                from: si.from,
-            };
+    #[test]
+    fn crypto_stats() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-            conn.recv(&mut pkt, info)?;
-        }
+        let client = pipe.client.stats();
+        assert!(client.crypto_sealed_bytes > 0);
+        assert!(client.crypto_opened_bytes > 0);
 
-        Ok(())
+        let server = pipe.server.stats();
+
+        assert_eq!(pipe.client.stream_send(0, b"aaaaaaaaaa", true), Ok(10));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let stats = pipe.client.stats();
+        assert!(stats.crypto_sealed_bytes >= client.crypto_sealed_bytes + 10);
+
+        let stats = pipe.server.stats();
+        assert!(stats.crypto_opened_bytes >= server.crypto_opened_bytes + 10);
     }
 
-    pub fn emit_flight_with_max_buffer(
-        conn: &mut Connection, out_size: usize, from: Option<SocketAddr>,
-        to: Option<SocketAddr>,
-    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
-        let mut flight = Vec::new();
+    #[test]
+    fn chacha20_only() {
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        assert_eq!(config.set_chacha20_only(true), Ok(()));
 
-        loop {
-            let mut out = vec![0u8; out_size];
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
 
-            let info = match conn.send_on_path(&mut out, from, to) {
-                Ok((written, info)) => {
-                    out.truncate(written);
-                    info
-                },
+        // Depending on the TLS library and on the hardware, the peers may not
+        // agree on ChaCha20-Poly1305, in which case the handshake fails.
+        match pipe.handshake() {
+            Ok(()) => assert_eq!(
+                pipe.client.handshake.cipher(),
+                Some(crypto::Algorithm::ChaCha20_Poly1305)
+            ),
 
-                Err(Error::Done) => break,
+            Err(_) => assert!(!pipe.client.is_established()),
+        }
 
-                Err(e) => return Err(e),
+        assert_eq!(config.set_chacha20_only(false), Ok(()));
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+    }
+
+    #[cfg(feature = "qlog")]
+    #[test]
+    fn qlog_metrics_sampling_events() {
+        let config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        let mut r = recovery::Recovery::new(&config, "");
+        let mut sampler = QlogSampler::new(3);
+
+        let now = time::Instant::now();
+        let mut written = Vec::new();
+
+        for pkt_num in 0..9 {
+            let sent = recovery::Sent {
+                pkt_num,
+                frames: SmallVec::new(),
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
             };
 
-            flight.push((out, info));
-        }
+            r.on_packet_sent(
+                sent,
+                packet::Epoch::Application,
+                recovery::HandshakeStatus::default(),
+                now,
+                "",
+            );
 
-        if flight.is_empty() {
-            return Err(Error::Done);
+            // Only events with changes are sampled.
+            for _ in 0..2 {
+                if let Some(EventData::MetricsUpdated(ev)) =
+                    r.maybe_qlog(|| sampler.sample())
+                {
+                    written.push((pkt_num, ev.bytes_in_flight));
+                }
+            }
         }
 
-        Ok(flight)
+        // Exactly one in every 3 events is written, with the changes of the
+        // events that were not.
+        assert_eq!(written, [(0, Some(1000)), (3, Some(4000)), (6, Some(7000))]);
     }
 
-    pub fn emit_flight_on_path(
-        conn: &mut Connection, from: Option<SocketAddr>, to: Option<SocketAddr>,
-    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
-        emit_flight_with_max_buffer(conn, 65535, from, to)
-    }
+    #[cfg(feature = "qlog")]
+    #[test]
+    fn qlog_metrics_sampling() {
+        let mut sampler = QlogSampler::new(3);
+        let selected: Vec<bool> = (0..6).map(|_| sampler.sample()).collect();
+        assert_eq!(selected, [true, false, false, true, false, false]);
 
-    pub fn emit_flight(
-        conn: &mut Connection,
-    ) -> Result<Vec<(Vec<u8>, SendInfo)>> {
-        emit_flight_on_path(conn, None, None)
-    }
+        let mut sampler = QlogSampler::new(0);
+        assert!(sampler.sample());
+        assert!(sampler.sample());
 
-    pub fn encode_pkt(
-        conn: &mut Connection, pkt_type: packet::Type, frames: &[frame::Frame],
-        buf: &mut [u8],
-    ) -> Result<usize> {
-        let mut b = octets::OctetsMut::with_slice(buf);
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        config.set_qlog_metrics_sampling(usize::MAX);
 
-        let epoch = pkt_type.to_epoch()?;
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        pipe.client.set_qlog(
+            Box::new(Vec::new()),
+            "title".to_string(),
+            "description".to_string(),
+        );
 
-        let space = &mut conn.pkt_num_spaces[epoch];
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let pn = space.next_pkt_num;
-        let pn_len = 4;
+        // Only the first metrics update is written.
+        assert_eq!(pipe.client.stats().qlog_metrics_updated_count, 1);
+    }
 
-        let send_path = conn.paths.get_active()?;
-        let active_dcid_seq = send_path
-            .active_dcid_seq
-            .as_ref()
-            .ok_or(Error::InvalidState)?;
-        let active_scid_seq = send_path
-            .active_scid_seq
-            .as_ref()
-            .ok_or(Error::InvalidState)?;
+    #[test]
+    fn unknown_version() {
+        let mut config = Config::new(0xbabababa).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
 
-        let hdr = Header {
-            ty: pkt_type,
-            version: conn.version,
-            dcid: ConnectionId::from_ref(
-                conn.ids.get_dcid(*active_dcid_seq)?.cid.as_ref(),
-            ),
-            scid: ConnectionId::from_ref(
-                conn.ids.get_scid(*active_scid_seq)?.cid.as_ref(),
-            ),
-            pkt_num: pn,
-            pkt_num_len: pn_len,
-            token: conn.token.clone(),
-            versions: None,
-            key_phase: conn.key_phase,
-        };
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Err(Error::UnknownVersion));
+    }
 
-        hdr.to_bytes(&mut b)?;
+    #[test]
+    fn config_version_reserved() {
+        Config::new(0xbabababa).unwrap();
+        Config::new(0x1a2a3a4a).unwrap();
+    }
 
-        let payload_len = frames.iter().fold(0, |acc, x| acc + x.wire_len());
+    #[test]
+    fn config_version_invalid() {
+        assert_eq!(
+            Config::new(0xb1bababa).err().unwrap(),
+            Error::UnknownVersion
+        );
+    }
 
-        if pkt_type != packet::Type::Short {
-            let len = pn_len + payload_len + space.crypto_overhead().unwrap();
-            b.put_varint(len as u64)?;
-        }
+    #[test]
+    fn version_negotiation() {
+        let mut buf = [0; 65535];
 
-        // Always encode packet number in 4 bytes, to allow encoding packets
-        // with empty payloads.
-        b.put_u32(pn as u32)?;
+        let mut config = Config::new(0xbabababa).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
 
-        let payload_offset = b.off();
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
 
-        for frame in frames {
-            frame.to_bytes(&mut b)?;
-        }
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
 
-        let aead = match space.crypto_seal {
-            Some(ref v) => v,
-            None => return Err(Error::InvalidState),
-        };
+        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
+        len = crate::negotiate_version(&hdr.scid, &hdr.dcid, &mut buf).unwrap();
 
-        let written = packet::encrypt_pkt(
-            &mut b,
-            pn,
-            pn_len,
-            payload_len,
-            payload_offset,
-            None,
-            aead,
-        )?;
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
 
-        space.next_pkt_num += 1;
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        Ok(written)
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION);
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION);
     }
 
-    pub fn decode_pkt(
-        conn: &mut Connection, buf: &mut [u8],
-    ) -> Result<Vec<frame::Frame>> {
-        let mut b = octets::OctetsMut::with_slice(buf);
-
-        let mut hdr = Header::from_bytes(&mut b, conn.source_id().len()).unwrap();
+    #[test]
+    fn version_negotiation_preference() {
+        let mut buf = [0; 65535];
 
-        let epoch = hdr.ty.to_epoch()?;
+        let mut config = Config::new(0xbabababa).unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.verify_peer(false);
+        assert_eq!(
+            config.set_available_versions(&[
+                PROTOCOL_VERSION_V2,
+                PROTOCOL_VERSION_V1
+            ]),
+            Ok(())
+        );
 
-        let aead = conn.pkt_num_spaces[epoch].crypto_open.as_ref().unwrap();
+        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
 
-        let payload_len = b.cap();
+        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
 
-        packet::decrypt_hdr(&mut b, &mut hdr, aead).unwrap();
+        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
+        len = crate::negotiate_version(&hdr.scid, &hdr.dcid, &mut buf).unwrap();
 
-        let pn = packet::decode_pkt_num(
-            conn.pkt_num_spaces[epoch].largest_rx_pkt_num,
-            hdr.pkt_num,
-            hdr.pkt_num_len,
-        );
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
 
-        let mut payload =
-            packet::decrypt_pkt(&mut b, pn, hdr.pkt_num_len, payload_len, aead)
-                .unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-        let mut frames = Vec::new();
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V2);
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_V2);
+    }
 
-        while payload.cap() > 0 {
-            let frame = frame::Frame::from_bytes(&mut payload, hdr.ty)?;
-            frames.push(frame);
-        }
+    #[test]
+    fn handshake_v2() {
+        let mut config = Config::new(PROTOCOL_VERSION_V2).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.verify_peer(false);
 
-        Ok(frames)
-    }
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
 
-    pub fn create_cid_and_reset_token(
-        cid_len: usize,
-    ) -> (ConnectionId<'static>, u128) {
-        let mut cid = vec![0; cid_len];
-        rand::rand_bytes(&mut cid[..]);
-        let cid = ConnectionId::from_ref(&cid).into_owned();
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V2);
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_V2);
 
-        let mut reset_token = [0; 16];
-        rand::rand_bytes(&mut reset_token);
-        let reset_token = u128::from_be_bytes(reset_token);
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
 
-        (cid, reset_token)
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"hello");
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
 
     #[test]
-    fn transport_params() {
-        // Server encodes, client decodes.
-        let tp = TransportParams {
-            original_destination_connection_id: None,
-            max_idle_timeout: 30,
-            stateless_reset_token: Some(u128::from_be_bytes([0xba; 16])),
-            max_udp_payload_size: 23_421,
-            initial_max_data: 424_645_563,
-            initial_max_stream_data_bidi_local: 154_323_123,
-            initial_max_stream_data_bidi_remote: 6_587_456,
-            initial_max_stream_data_uni: 2_461_234,
-            initial_max_streams_bidi: 12_231,
-            initial_max_streams_uni: 18_473,
-            ack_delay_exponent: 20,
-            max_ack_delay: 2_u64.pow(14) - 1,
-            disable_active_migration: true,
-            active_conn_id_limit: 8,
-            initial_source_connection_id: Some(b"woot woot".to_vec().into()),
-            retry_source_connection_id: Some(b"retry".to_vec().into()),
-            max_datagram_frame_size: Some(32),
-            bdp_tokens: true,
-        };
-
-        let mut raw_params = [42; 256];
-        let raw_params =
-            TransportParams::encode(&tp, true, &mut raw_params).unwrap();
-        assert_eq!(raw_params.len(), 98);
+    fn compatible_version_negotiation() {
+        let mut buf = [0; 65535];
 
-        let new_tp = TransportParams::decode(raw_params, false).unwrap();
+        let mut client_config = Config::new(PROTOCOL_VERSION_V1).unwrap();
+        client_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        client_config.set_initial_max_data(30);
+        client_config.set_initial_max_stream_data_bidi_local(15);
+        client_config.set_initial_max_stream_data_bidi_remote(15);
+        client_config.set_initial_max_streams_bidi(3);
+        client_config.verify_peer(false);
 
-        assert_eq!(new_tp, tp);
+        let mut server_config = Config::new(PROTOCOL_VERSION_V1).unwrap();
+        server_config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        server_config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        server_config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        server_config.set_initial_max_data(30);
+        server_config.set_initial_max_stream_data_bidi_local(15);
+        server_config.set_initial_max_stream_data_bidi_remote(15);
+        server_config.set_initial_max_streams_bidi(3);
+        assert_eq!(
+            server_config.set_available_versions(&[
+                PROTOCOL_VERSION_V2,
+                PROTOCOL_VERSION_V1
+            ]),
+            Ok(())
+        );
 
-        // Client encodes, server decodes.
-        let tp = TransportParams {
-            original_destination_connection_id: None,
-            max_idle_timeout: 30,
-            stateless_reset_token: None,
-            max_udp_payload_size: 23_421,
-            initial_max_data: 424_645_563,
-            initial_max_stream_data_bidi_local: 154_323_123,
-            initial_max_stream_data_bidi_remote: 6_587_456,
-            initial_max_stream_data_uni: 2_461_234,
-            initial_max_streams_bidi: 12_231,
-            initial_max_streams_uni: 18_473,
-            ack_delay_exponent: 20,
-            max_ack_delay: 2_u64.pow(14) - 1,
-            disable_active_migration: true,
-            active_conn_id_limit: 8,
-            initial_source_connection_id: Some(b"woot woot".to_vec().into()),
-            retry_source_connection_id: None,
-            max_datagram_frame_size: Some(32),
-            bdp_tokens: true,
-        };
+        let mut pipe = testing::Pipe::with_client_and_server_config(
+            &mut client_config,
+            &mut server_config,
+        )
+        .unwrap();
 
-        let mut raw_params = [42; 256];
-        let raw_params =
-            TransportParams::encode(&tp, false, &mut raw_params).unwrap();
-        assert_eq!(raw_params.len(), 73);
+        // The client's first flight uses version 1, and the server switches
+        // to version 2 when processing it.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
 
-        let new_tp = TransportParams::decode(raw_params, true).unwrap();
+        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
+        assert_eq!(hdr.version, PROTOCOL_VERSION_V1);
 
-        assert_eq!(new_tp, tp);
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_V2);
+
+        let (len, _) = pipe.server.send(&mut buf).unwrap();
+
+        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
+        assert_eq!(hdr.version, PROTOCOL_VERSION_V2);
+
+        // A server Initial that doesn't decrypt doesn't switch the client to
+        // the new version.
+        let mut corrupted = buf[..len].to_vec();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 0xff;
+
+        let _ = pipe.client_recv(&mut corrupted);
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V1);
+
+        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V2);
+
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.version, PROTOCOL_VERSION_V2);
+        assert_eq!(pipe.server.version, PROTOCOL_VERSION_V2);
+
+        assert_eq!(pipe.client.stream_send(0, b"hello", true), Ok(5));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        let mut b = [0; 15];
+        assert_eq!(pipe.server.stream_recv(0, &mut b), Ok((5, true)));
+        assert_eq!(&b[..5], b"hello");
     }
 
     #[test]
-    fn transport_params_forbid_duplicates() {
-        // Given an encoded param.
-        let initial_source_connection_id = b"id";
-        let initial_source_connection_id_raw = [
-            15,
-            initial_source_connection_id.len() as u8,
-            initial_source_connection_id[0],
-            initial_source_connection_id[1],
+    fn client_hello_transport_params() {
+        let params = [0x01, 0x04, 0x6b, 0x33, 0x43, 0xcf];
+
+        let mut hello = vec![
+            0x01, // msg_type
+            0x00, 0x00, 0x00, // length
+            0x03, 0x03, // legacy_version
         ];
+        hello.extend_from_slice(&[0x2a; 32]); // random
+        hello.extend_from_slice(&[0x00]); // legacy_session_id
+        hello.extend_from_slice(&[0x00, 0x02, 0x13, 0x01]); // cipher_suites
+        hello.extend_from_slice(&[0x01, 0x00]); // compression_methods
 
-        // No error when decoding the param.
-        let tp = TransportParams::decode(
-            initial_source_connection_id_raw.as_slice(),
-            true,
-        )
-        .unwrap();
+        // supported_groups and quic_transport_parameters.
+        let mut extensions = vec![0x00, 0x0a, 0x00, 0x02, 0x00, 0x1d];
+        extensions.extend_from_slice(&[0x00, 0x39, 0x00, params.len() as u8]);
+        extensions.extend_from_slice(&params);
 
-        assert_eq!(
-            tp.initial_source_connection_id,
-            Some(initial_source_connection_id.to_vec().into())
-        );
+        hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&extensions);
 
-        // Duplicate the param.
-        let mut raw_params = Vec::new();
-        raw_params.append(&mut initial_source_connection_id_raw.to_vec());
-        raw_params.append(&mut initial_source_connection_id_raw.to_vec());
+        let len = hello.len() - 4;
+        hello[1..4].copy_from_slice(&(len as u32).to_be_bytes()[1..]);
 
-        // Decoding fails.
         assert_eq!(
-            TransportParams::decode(raw_params.as_slice(), true),
-            Err(Error::InvalidTransportParam)
+            crate::client_hello_transport_params(&hello),
+            Some(&params[..])
         );
-    }
 
-    #[test]
-    fn unknown_version() {
-        let mut config = Config::new(0xbabababa).unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.verify_peer(false);
+        // Truncated ClientHello.
+        assert_eq!(
+            crate::client_hello_transport_params(&hello[..hello.len() - 1]),
+            None
+        );
 
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
-        assert_eq!(pipe.handshake(), Err(Error::UnknownVersion));
+        // Not a ClientHello.
+        hello[0] = 0x02;
+        assert_eq!(crate::client_hello_transport_params(&hello), None);
     }
 
     #[test]
-    fn config_version_reserved() {
-        Config::new(0xbabababa).unwrap();
-        Config::new(0x1a2a3a4a).unwrap();
-    }
+    fn available_versions() {
+        let mut config = Config::new(PROTOCOL_VERSION).unwrap();
 
-    #[test]
-    fn config_version_invalid() {
         assert_eq!(
-            Config::new(0xb1bababa).err().unwrap(),
-            Error::UnknownVersion
+            config.set_available_versions(&[]),
+            Err(Error::UnknownVersion)
+        );
+        assert_eq!(
+            config.set_available_versions(&[PROTOCOL_VERSION_V1, 0xbabababa]),
+            Err(Error::UnknownVersion)
+        );
+        assert_eq!(
+            config.set_available_versions(&[PROTOCOL_VERSION_V2]),
+            Ok(())
         );
     }
 
     #[test]
-    fn version_negotiation() {
-        let mut buf = [0; 65535];
-
-        let mut config = Config::new(0xbabababa).unwrap();
-        config
-            .set_application_protos(&[b"proto1", b"proto2"])
-            .unwrap();
-        config.verify_peer(false);
-
-        let mut pipe = testing::Pipe::with_client_config(&mut config).unwrap();
+    fn transport_params_version_information() {
+        let tp = TransportParams {
+            version_information: Some(VersionInformation {
+                chosen_version: PROTOCOL_VERSION_V2,
+                available_versions: vec![
+                    PROTOCOL_VERSION_V2,
+                    PROTOCOL_VERSION_V1,
+                ],
+            }),
+            ..Default::default()
+        };
 
-        let (mut len, _) = pipe.client.send(&mut buf).unwrap();
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, true, &mut raw_params).unwrap();
 
-        let hdr = packet::Header::from_slice(&mut buf[..len], 0).unwrap();
-        len = crate::negotiate_version(&hdr.scid, &hdr.dcid, &mut buf).unwrap();
+        let new_tp = TransportParams::decode(raw_params, false).unwrap();
+        assert_eq!(new_tp.version_information, tp.version_information);
 
-        assert_eq!(pipe.client_recv(&mut buf[..len]), Ok(len));
+        // The chosen version can't be zero.
+        let tp = TransportParams {
+            version_information: Some(VersionInformation {
+                chosen_version: 0,
+                available_versions: vec![],
+            }),
+            ..Default::default()
+        };
 
-        assert_eq!(pipe.handshake(), Ok(()));
+        let mut raw_params = [42; 256];
+        let raw_params =
+            TransportParams::encode(&tp, true, &mut raw_params).unwrap();
 
-        assert_eq!(pipe.client.version, PROTOCOL_VERSION);
-        assert_eq!(pipe.server.version, PROTOCOL_VERSION);
+        assert_eq!(
+            TransportParams::decode(raw_params, false),
+            Err(Error::InvalidTransportParam)
+        );
     }
 
     #[test]
@@ -11888,6 +17682,182 @@ mod tests {
         );
     }
 
+    #[test]
+    fn stream_reset_at() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_reset_stream_at(true);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client buffers some data, and resets the stream before sending it.
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", false), Ok(12));
+
+        assert_eq!(
+            pipe.client.stream_reset_at(4, 42, 13),
+            Err(Error::InvalidStreamState(4))
+        );
+        assert_eq!(pipe.client.stream_reset_at(4, 42, 5), Ok(()));
+        assert_eq!(pipe.client.stream_reset_at(4, 42, 5), Err(Error::Done));
+
+        // Flow control is restored for the data past the reliable size.
+        assert_eq!(pipe.client.tx_data, 5);
+
+        let mut w = pipe.client.writable();
+        assert_eq!(w.next(), None);
+
+        assert_eq!(
+            pipe.client.stream_send(4, b"bye", false),
+            Err(Error::FinalSize)
+        );
+
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+        let mut iter = frames.iter();
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::ResetStreamAt {
+                stream_id: 4,
+                error_code: 42,
+                final_size: 5,
+                reliable_size: 5,
+            })
+        );
+
+        assert_eq!(
+            iter.next(),
+            Some(&frame::Frame::Stream {
+                stream_id: 4,
+                data: stream::RangeBuf::from(b"hello", 0, true),
+            })
+        );
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        // Server reads the data up to the reliable size, then the reset.
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), Some(4));
+        assert_eq!(r.next(), None);
+
+        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((5, false)));
+        assert_eq!(&buf[..5], b"hello");
+
+        assert_eq!(
+            pipe.server.stream_recv(4, &mut buf),
+            Err(Error::StreamReset(42))
+        );
+
+        let mut r = pipe.server.readable();
+        assert_eq!(r.next(), None);
+    }
+
+    #[test]
+    /// Tests that lost data is only retransmitted up to the reliable size.
+    fn stream_reset_at_retransmit() {
+        let mut buf = [0; 65535];
+
+        let mut config = Config::new(crate::PROTOCOL_VERSION).unwrap();
+        config
+            .load_cert_chain_from_pem_file("examples/cert.crt")
+            .unwrap();
+        config
+            .load_priv_key_from_pem_file("examples/cert.key")
+            .unwrap();
+        config
+            .set_application_protos(&[b"proto1", b"proto2"])
+            .unwrap();
+        config.set_initial_max_data(30);
+        config.set_initial_max_stream_data_bidi_local(15);
+        config.set_initial_max_stream_data_bidi_remote(15);
+        config.set_initial_max_streams_bidi(3);
+        config.enable_reset_stream_at(true);
+        config.verify_peer(false);
+
+        let mut pipe = testing::Pipe::with_config(&mut config).unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        // Client sends some data, but the packet is lost.
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", false), Ok(12));
+        assert!(pipe.client.send(&mut buf).is_ok());
+
+        // The final size covers the data that was already sent.
+        assert_eq!(pipe.client.stream_reset_at(4, 42, 5), Ok(()));
+        assert_eq!(pipe.client.tx_data, 12);
+
+        // Wait until PTO expires. Since the RTT is very low, wait a bit more.
+        let timer = pipe.client.timeout().unwrap();
+        std::thread::sleep(timer + time::Duration::from_millis(1));
+
+        pipe.client.on_timeout();
+
+        // Client retransmits stream data up to the reliable size only.
+        let (len, _) = pipe.client.send(&mut buf).unwrap();
+
+        let frames =
+            testing::decode_pkt(&mut pipe.server, &mut buf[..len]).unwrap();
+
+        assert!(frames.contains(&frame::Frame::ResetStreamAt {
+            stream_id: 4,
+            error_code: 42,
+            final_size: 12,
+            reliable_size: 5,
+        }));
+
+        assert!(frames.contains(&frame::Frame::Stream {
+            stream_id: 4,
+            data: stream::RangeBuf::from(b"hello", 0, false),
+        }));
+
+        assert_eq!(pipe.server_recv(&mut buf[..len]), Ok(len));
+        assert_eq!(pipe.advance(), Ok(()));
+
+        assert_eq!(pipe.server.stream_recv(4, &mut buf), Ok((5, false)));
+        assert_eq!(&buf[..5], b"hello");
+
+        assert_eq!(
+            pipe.server.stream_recv(4, &mut buf),
+            Err(Error::StreamReset(42))
+        );
+
+        // The send side of the stream is complete once the reliable data
+        // is acked.
+        let stream = pipe.client.streams.get(4).unwrap();
+        assert!(stream.send.is_complete());
+    }
+
+    #[test]
+    fn stream_reset_at_not_negotiated() {
+        let mut pipe = testing::Pipe::new().unwrap();
+        assert_eq!(pipe.handshake(), Ok(()));
+
+        assert_eq!(pipe.client.stream_send(4, b"hello, world", false), Ok(12));
+
+        assert_eq!(
+            pipe.client.stream_reset_at(4, 42, 5),
+            Err(Error::InvalidState)
+        );
+    }
+
     #[test]
     /// Tests that shutting down a stream restores flow control for unsent data.
     fn stream_shutdown_write_unsent_tx_cap() {
@@ -17602,13 +23572,54 @@ pub use crate::path::PathStats;
 pub use crate::path::SocketAddrIter;
 
 pub use crate::recovery::congestion::CongestionControlAlgorithm;
+pub use crate::recovery::congestion::CongestionPhase;
+#[cfg(feature = "cr-dot")]
+pub use crate::recovery::decisions_to_dot;
+pub use crate::recovery::BdpFrame;
+pub use crate::recovery::CarefulResumeState;
+pub use crate::cr_config::ConfigDelta;
+pub use crate::cr_config::CrConfig;
+pub use crate::cr_config::CrConfigError;
+pub use crate::cr_config::CrProfile;
+pub use crate::cr_hints::CrHints;
+pub use crate::cr_hints::CrHintsHandler;
+pub use crate::cr_path_cache::CrPathCache;
+pub use crate::cr_policy::CrParamsPolicy;
+pub use crate::cr_policy::CrParamsSource;
+pub use crate::cr_store::CrStore;
+pub use crate::cr_store::FileCrStore;
+pub use crate::cr_store::MemoryCrStore;
 pub use crate::recovery::CREvent;
+pub use crate::recovery::CongestionSignalSource;
+pub use crate::recovery::CrCounters;
+pub use crate::recovery::CrJumpLimiter;
+pub use crate::recovery::CrHystartPolicy;
+pub use crate::recovery::CrJumpPacing;
+pub use crate::recovery::CrObservePolicy;
+pub use crate::recovery::CrPhaseDurations;
+pub use crate::recovery::CrPhaseObserver;
+pub use crate::recovery::CrRejectReason;
+pub use crate::recovery::CrRetreatPolicy;
+pub use crate::recovery::CrValidatingPolicy;
+pub use crate::recovery::CrWorkloadHint;
+pub use crate::recovery::RecoveryDecision;
+pub use crate::recovery::RecoveryDecisionKind;
+pub use crate::recovery::RecoveryDecisionLog;
+pub use crate::recovery::StoredCREvent;
+pub use crate::recovery::DECISION_LOG_CAPACITY;
 
 pub use crate::stream::StreamIter;
 
 mod cid;
+mod cr_config;
+mod cr_hints;
+mod cr_path_cache;
+mod cr_policy;
+mod cr_store;
 mod crypto;
 mod dgram;
+#[cfg(feature = "env-config")]
+mod env_config;
 #[cfg(feature = "ffi")]
 mod ffi;
 mod flowcontrol;
@@ -17617,9 +23628,13 @@ pub mod h3;
 mod minmax;
 mod packet;
 mod path;
+mod pcap;
 mod pmtud;
 mod rand;
 mod ranges;
+mod rate_samples;
 mod recovery;
+pub mod resume;
+mod resume_blob;
 mod stream;
 mod tls;
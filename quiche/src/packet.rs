@@ -371,11 +371,17 @@ impl<'a> Header<'a> {
         let ty = if version == 0 {
             Type::VersionNegotiation
         } else {
-            match (first & TYPE_MASK) >> 4 {
-                0x00 => Type::Initial,
-                0x01 => Type::ZeroRTT,
-                0x02 => Type::Handshake,
-                0x03 => Type::Retry,
+            // QUIC v2 uses different long header packet type values.
+            match ((first & TYPE_MASK) >> 4, version) {
+                (0x01, crate::PROTOCOL_VERSION_V2) => Type::Initial,
+                (0x02, crate::PROTOCOL_VERSION_V2) => Type::ZeroRTT,
+                (0x03, crate::PROTOCOL_VERSION_V2) => Type::Handshake,
+                (0x00, crate::PROTOCOL_VERSION_V2) => Type::Retry,
+
+                (0x00, _) => Type::Initial,
+                (0x01, _) => Type::ZeroRTT,
+                (0x02, _) => Type::Handshake,
+                (0x03, _) => Type::Retry,
                 _ => return Err(Error::InvalidPacket),
             }
         };
@@ -467,11 +473,16 @@ impl<'a> Header<'a> {
         }
 
         // Encode long header.
-        let ty: u8 = match self.ty {
-            Type::Initial => 0x00,
-            Type::ZeroRTT => 0x01,
-            Type::Handshake => 0x02,
-            Type::Retry => 0x03,
+        let ty: u8 = match (self.ty, self.version) {
+            (Type::Initial, crate::PROTOCOL_VERSION_V2) => 0x01,
+            (Type::ZeroRTT, crate::PROTOCOL_VERSION_V2) => 0x02,
+            (Type::Handshake, crate::PROTOCOL_VERSION_V2) => 0x03,
+            (Type::Retry, crate::PROTOCOL_VERSION_V2) => 0x00,
+
+            (Type::Initial, _) => 0x00,
+            (Type::ZeroRTT, _) => 0x01,
+            (Type::Handshake, _) => 0x02,
+            (Type::Retry, _) => 0x03,
             _ => return Err(Error::InvalidPacket),
         };
 
@@ -517,7 +528,7 @@ impl<'a> Header<'a> {
     /// Returns true if the packet has a long header.
     ///
     /// The `b` parameter represents the first byte of the QUIC header.
-    fn is_long(b: u8) -> bool {
+    pub(crate) fn is_long(b: u8) -> bool {
         b & FORM_BIT != 0
     }
 }
@@ -738,6 +749,7 @@ pub fn negotiate_version(
     b.put_u8(dcid.len() as u8)?;
     b.put_bytes(dcid)?;
     b.put_u32(crate::PROTOCOL_VERSION_V1)?;
+    b.put_u32(crate::PROTOCOL_VERSION_V2)?;
 
     Ok(b.off())
 }
@@ -799,10 +811,22 @@ fn compute_retry_integrity_tag(
         0x46, 0x15, 0x99, 0xd3, 0x5d, 0x63, 0x2b, 0xf2, 0x23, 0x98, 0x25, 0xbb,
     ];
 
+    const RETRY_INTEGRITY_KEY_V2: [u8; 16] = [
+        0x8f, 0xb4, 0xb0, 0x1b, 0x56, 0xac, 0x48, 0xe2, 0x60, 0xfb, 0xcb, 0xce,
+        0xad, 0x7c, 0xcc, 0x92,
+    ];
+
+    const RETRY_INTEGRITY_NONCE_V2: [u8; aead::NONCE_LEN] = [
+        0xd8, 0x69, 0x69, 0xbc, 0x2d, 0x7c, 0x6d, 0x99, 0x90, 0xef, 0xb0, 0x4a,
+    ];
+
     let (key, nonce) = match version {
         crate::PROTOCOL_VERSION_V1 =>
             (&RETRY_INTEGRITY_KEY_V1, RETRY_INTEGRITY_NONCE_V1),
 
+        crate::PROTOCOL_VERSION_V2 =>
+            (&RETRY_INTEGRITY_KEY_V2, RETRY_INTEGRITY_NONCE_V2),
+
         _ => (&RETRY_INTEGRITY_KEY_V1, RETRY_INTEGRITY_NONCE_V1),
     };
 
@@ -1011,6 +1035,75 @@ mod tests {
         assert_eq!(Header::from_bytes(&mut b, 9).unwrap(), hdr);
     }
 
+    #[test]
+    fn retry_v2_integrity() {
+        // RFC 9369, Appendix A.4.
+        let mut pkt = [
+            0xcf, 0x6b, 0x33, 0x43, 0xcf, 0x00, 0x08, 0xf0, 0x67, 0xa5, 0x50,
+            0x2a, 0x42, 0x62, 0xb5, 0x74, 0x6f, 0x6b, 0x65, 0x6e, 0xc8, 0x64,
+            0x6c, 0xe8, 0xbf, 0xe3, 0x39, 0x52, 0xd9, 0x55, 0x54, 0x36, 0x65,
+            0xdc, 0xc7, 0xb6,
+        ];
+
+        let odcid = [0x83, 0x94, 0xc8, 0xf0, 0x3e, 0x51, 0x57, 0x08];
+
+        let mut b = octets::OctetsMut::with_slice(&mut pkt);
+
+        let hdr = Header::from_bytes(&mut b, 0).unwrap();
+        assert_eq!(hdr.ty, Type::Retry);
+        assert_eq!(hdr.version, crate::PROTOCOL_VERSION_V2);
+        assert_eq!(hdr.token, Some(b"token".to_vec()));
+
+        assert_eq!(
+            verify_retry_integrity(&b, &odcid, crate::PROTOCOL_VERSION_V2),
+            Ok(())
+        );
+
+        assert_eq!(
+            verify_retry_integrity(&b, &odcid, crate::PROTOCOL_VERSION_V1),
+            Err(Error::CryptoFail)
+        );
+    }
+
+    #[test]
+    fn long_header_types_v2() {
+        for (ty, bits) in [
+            (Type::Initial, 0x01),
+            (Type::ZeroRTT, 0x02),
+            (Type::Handshake, 0x03),
+            (Type::Retry, 0x00),
+        ] {
+            let hdr = Header {
+                ty,
+                version: crate::PROTOCOL_VERSION_V2,
+                dcid: vec![0xba; 9].into(),
+                scid: vec![0xbb; 7].into(),
+                pkt_num: 0,
+                pkt_num_len: 0,
+                token: Some(vec![0xba; 24]),
+                versions: None,
+                key_phase: false,
+            };
+
+            let mut d = [0; 70];
+
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            assert!(hdr.to_bytes(&mut b).is_ok());
+
+            if ty == Type::Retry {
+                // Add fake retry integrity token.
+                b.put_bytes(&[0xba; 16]).unwrap();
+            }
+
+            assert_eq!((d[0] & TYPE_MASK) >> 4, bits);
+
+            let mut b = octets::OctetsMut::with_slice(&mut d);
+            let parsed = Header::from_bytes(&mut b, 9).unwrap();
+            assert_eq!(parsed.ty, ty);
+            assert_eq!(parsed.version, crate::PROTOCOL_VERSION_V2);
+        }
+    }
+
     #[test]
     fn initial() {
         let hdr = Header {
@@ -1526,7 +1619,12 @@ mod tests {
 
         let alg = crypto::Algorithm::ChaCha20_Poly1305;
 
-        let aead = crypto::Open::from_secret(alg, secret.into()).unwrap();
+        let aead = crypto::Open::from_secret(
+            alg,
+            crate::PROTOCOL_VERSION_V1,
+            secret.into(),
+        )
+        .unwrap();
 
         let mut hdr = Header::from_bytes(&mut b, 0).unwrap();
         assert_eq!(hdr.ty, Type::Short);
@@ -1894,7 +1992,12 @@ mod tests {
 
         let alg = crypto::Algorithm::ChaCha20_Poly1305;
 
-        let aead = crypto::Seal::from_secret(alg, secret.into()).unwrap();
+        let aead = crypto::Seal::from_secret(
+            alg,
+            crate::PROTOCOL_VERSION_V1,
+            secret.into(),
+        )
+        .unwrap();
 
         let pn = 654_360_564;
         let pn_len = 3;
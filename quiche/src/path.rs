@@ -34,6 +34,9 @@ use smallvec::SmallVec;
 
 use slab::Slab;
 
+use crate::CongestionPhase;
+use crate::CrCounters;
+use crate::CrPhaseDurations;
 use crate::Error;
 use crate::Result;
 
@@ -139,6 +142,9 @@ pub struct Path {
     /// Path MTU discovery state.
     pub pmtud: pmtud::Pmtud,
 
+    /// The latest Careful Resume parameters observed on the path.
+    pub latest_cr_event: Option<recovery::CREvent>,
+
     /// Pending challenge data with the size of the packet containing them and
     /// when they were sent.
     in_flight_challenges: VecDeque<([u8; 8], usize, time::Instant)>,
@@ -218,6 +224,13 @@ impl Path {
 
         let trace_id = format!("{} - {}<->{}", trace_id, local_addr, peer_addr);
 
+        let mut recovery =
+            recovery::Recovery::new_with_config(recovery_config, &trace_id);
+
+        if let Some(limiter) = &recovery_config.cr_jump_limiter {
+            recovery.set_cr_jump_limiter(limiter, local_addr.ip());
+        }
+
         Self {
             local_addr,
             peer_addr,
@@ -225,8 +238,9 @@ impl Path {
             active_dcid_seq,
             state,
             active: false,
-            recovery: recovery::Recovery::new_with_config(recovery_config, &trace_id),
+            recovery,
             pmtud: pmtud::Pmtud::new(pmtud_init),
+            latest_cr_event: None,
             in_flight_challenges: VecDeque::new(),
             max_challenge_size: 0,
             probing_lost: 0,
@@ -489,12 +503,23 @@ impl Path {
             min_rtt: self.recovery.min_rtt(),
             rttvar: self.recovery.rttvar(),
             cwnd: self.recovery.cwnd(),
+            ssthresh: self.recovery.ssthresh(),
+            congestion_phase: self.recovery.congestion_phase(),
             sent_bytes: self.sent_bytes,
             recv_bytes: self.recv_bytes,
             lost_bytes: self.recovery.bytes_lost,
             stream_retrans_bytes: self.stream_retrans_bytes,
             pmtu: self.recovery.max_datagram_size(),
             delivery_rate: self.recovery.delivery_rate(),
+            initial_window_acked: self.recovery.initial_window_acked(),
+            ack_aggregation_count: self.recovery.ack_aggregation_count(),
+            ack_aggregation_bytes: self.recovery.ack_aggregation_bytes(),
+            cr_phase_durations: self.recovery.cr_phase_durations(),
+            cr_counters: self.recovery.cr_counters(),
+            cr_jump_fraction: self.recovery.cr_jump_fraction(),
+            external_congestion_count: self.recovery.external_congestion_count(),
+            app_limited_time: self.recovery.app_limited_time(),
+            cwnd_limited_time: self.recovery.cwnd_limited_time(),
         }
     }
 }
@@ -870,6 +895,15 @@ pub struct PathStats {
     /// The size of the connection's congestion window in bytes.
     pub cwnd: usize,
 
+    /// The slow start threshold in bytes, or `None` until it is set by a
+    /// congestion event, or a Careful Resume safe retreat. BBR and BBRv2
+    /// don't use one.
+    pub ssthresh: Option<usize>,
+
+    /// The phase of the congestion controller, or `None` for BBR and BBRv2,
+    /// which have their own state machine.
+    pub congestion_phase: Option<CongestionPhase>,
+
     /// The number of sent bytes.
     pub sent_bytes: u64,
 
@@ -894,6 +928,45 @@ pub struct PathStats {
     /// [`SendInfo.at`]: struct.SendInfo.html#structfield.at
     /// [Pacing]: index.html#pacing
     pub delivery_rate: u64,
+
+    /// Whether all packets sent within the initial congestion window have
+    /// been acknowledged, which Careful Resume waits for before jumping.
+    pub initial_window_acked: bool,
+
+    /// The number of ACKs that acknowledged data faster than it was sent,
+    /// because of ACK aggregation.
+    pub ack_aggregation_count: u64,
+
+    /// The number of bytes acknowledged faster than they were sent, because
+    /// of ACK aggregation.
+    pub ack_aggregation_bytes: u64,
+
+    /// The time spent in each Careful Resume phase.
+    pub cr_phase_durations: CrPhaseDurations,
+
+    /// Counts of what Careful Resume did on the path.
+    pub cr_counters: CrCounters,
+
+    /// The congestion window reached by the Careful Resume jump, as a
+    /// fraction of the congestion window of the previous connection, or
+    /// `None` if there was no jump.
+    ///
+    /// The jump nominally reaches half of the previous congestion window.
+    /// A lower fraction means the jump was held back, so the parameters
+    /// observed on this connection were not measured after a full jump.
+    pub cr_jump_fraction: Option<f64>,
+
+    /// The number of congestion signals reported from outside the transport
+    /// with [`Connection::on_external_congestion()`].
+    ///
+    /// [`Connection::on_external_congestion()`]: crate::Connection::on_external_congestion
+    pub external_congestion_count: u64,
+
+    /// The time spent sending with room left in the congestion window.
+    pub app_limited_time: time::Duration,
+
+    /// The time spent sending with the congestion window full.
+    pub cwnd_limited_time: time::Duration,
 }
 
 impl std::fmt::Debug for PathStats {
@@ -915,6 +988,12 @@ impl std::fmt::Debug for PathStats {
             self.recv, self.sent, self.lost, self.retrans, self.rtt, self.min_rtt, self.rttvar, self.cwnd,
         )?;
 
+        write!(
+            f,
+            " ssthresh={:?} congestion_phase={:?}",
+            self.ssthresh, self.congestion_phase,
+        )?;
+
         write!(
             f,
             " sent_bytes={} recv_bytes={} lost_bytes={}",
@@ -925,6 +1004,31 @@ impl std::fmt::Debug for PathStats {
             f,
             " stream_retrans_bytes={} pmtu={} delivery_rate={}",
             self.stream_retrans_bytes, self.pmtu, self.delivery_rate,
+        )?;
+
+        write!(
+            f,
+            " ack_aggregation_count={} ack_aggregation_bytes={}",
+            self.ack_aggregation_count, self.ack_aggregation_bytes,
+        )?;
+
+        write!(f, " cr_phase_durations={:?}", self.cr_phase_durations)?;
+        write!(f, " cr_counters={:?}", self.cr_counters)?;
+
+        if let Some(v) = self.cr_jump_fraction {
+            write!(f, " cr_jump_fraction={v:.3}")?;
+        }
+
+        write!(
+            f,
+            " external_congestion_count={}",
+            self.external_congestion_count
+        )?;
+
+        write!(
+            f,
+            " app_limited_time={:?} cwnd_limited_time={:?}",
+            self.app_limited_time, self.cwnd_limited_time,
         )
     }
 }
@@ -0,0 +1,343 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Packet capture of the datagrams sent and received by a connection.
+//!
+//! The capture is written in the pcap format with nanosecond timestamps and
+//! the raw IP link type, so that the timing of the datagrams, e.g. of the
+//! bursts sent by a Careful Resume jump, can be analysed with standard tools
+//! when a capture at the network interface isn't possible.
+//!
+//! Each datagram is recorded with synthesized IP and UDP headers, followed by
+//! the invariant header (RFC 8999) of its first QUIC packet. The rest of the
+//! datagram is left out of the capture, but counted in its original length.
+//! Sent datagrams are timestamped with the time they are to be sent at, as
+//! reported in [`SendInfo`].
+//!
+//! [`SendInfo`]: crate::SendInfo
+
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::time::Instant;
+use std::time::SystemTime;
+
+const MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+
+const LINKTYPE_RAW: u32 = 101;
+
+const SNAPLEN: u32 = 65535;
+
+const IPV4_HEADER_LEN: usize = 20;
+
+const IPV6_HEADER_LEN: usize = 40;
+
+const UDP_HEADER_LEN: usize = 8;
+
+const TTL: u8 = 64;
+
+const PROTO_UDP: u8 = 17;
+
+pub(crate) struct PcapWriter {
+    writer: Box<dyn Write + Send + Sync>,
+
+    // The wall clock time at `start`, used to convert instants to timestamps.
+    start_wall: SystemTime,
+
+    start: Instant,
+}
+
+impl PcapWriter {
+    pub fn new(mut writer: Box<dyn Write + Send + Sync>) -> Self {
+        let mut hdr = Vec::with_capacity(24);
+
+        hdr.extend_from_slice(&MAGIC_NANOS.to_le_bytes());
+        hdr.extend_from_slice(&2_u16.to_le_bytes()); // version major
+        hdr.extend_from_slice(&4_u16.to_le_bytes()); // version minor
+        hdr.extend_from_slice(&0_i32.to_le_bytes()); // thiszone
+        hdr.extend_from_slice(&0_u32.to_le_bytes()); // sigfigs
+        hdr.extend_from_slice(&SNAPLEN.to_le_bytes());
+        hdr.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+
+        writer.write_all(&hdr).ok();
+
+        PcapWriter {
+            writer,
+            start_wall: SystemTime::now(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Records the UDP datagram `buf` sent from `from` to `to` at `at`.
+    ///
+    /// `dcid_len` is the length of the destination connection ID of short
+    /// header packets.
+    pub fn write_datagram(
+        &mut self, buf: &[u8], from: SocketAddr, to: SocketAddr, dcid_len: usize,
+        at: Instant,
+    ) {
+        let ip_header_len = match (from.ip(), to.ip()) {
+            (IpAddr::V4(_), IpAddr::V4(_)) => IPV4_HEADER_LEN,
+
+            (IpAddr::V6(_), IpAddr::V6(_)) => IPV6_HEADER_LEN,
+
+            // Mixed address families can't be represented.
+            _ => return,
+        };
+
+        let udp_len = UDP_HEADER_LEN + buf.len();
+        let orig_len = ip_header_len + udp_len;
+
+        let quic_header = &buf[..invariant_header_len(buf, dcid_len)];
+
+        let mut pkt = Vec::with_capacity(
+            16 + ip_header_len + UDP_HEADER_LEN + quic_header.len(),
+        );
+
+        let ts = if at >= self.start {
+            self.start_wall + (at - self.start)
+        } else {
+            self.start_wall - (self.start - at)
+        };
+
+        let ts = ts
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let incl_len = ip_header_len + UDP_HEADER_LEN + quic_header.len();
+
+        // Record header.
+        pkt.extend_from_slice(&(ts.as_secs() as u32).to_le_bytes());
+        pkt.extend_from_slice(&ts.subsec_nanos().to_le_bytes());
+        pkt.extend_from_slice(&(incl_len as u32).to_le_bytes());
+        pkt.extend_from_slice(&(orig_len as u32).to_le_bytes());
+
+        match (from.ip(), to.ip()) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                let start = pkt.len();
+
+                pkt.push(0x45); // version and header length
+                pkt.push(0); // DSCP and ECN
+                pkt.extend_from_slice(&(orig_len as u16).to_be_bytes());
+                pkt.extend_from_slice(&[0, 0]); // identification
+                pkt.extend_from_slice(&[0x40, 0]); // don't fragment
+                pkt.push(TTL);
+                pkt.push(PROTO_UDP);
+                pkt.extend_from_slice(&[0, 0]); // checksum
+                pkt.extend_from_slice(&src.octets());
+                pkt.extend_from_slice(&dst.octets());
+
+                let checksum = ipv4_checksum(&pkt[start..]);
+                pkt[start + 10..start + 12]
+                    .copy_from_slice(&checksum.to_be_bytes());
+            },
+
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                pkt.extend_from_slice(&[0x60, 0, 0, 0]); // version
+                pkt.extend_from_slice(&(udp_len as u16).to_be_bytes());
+                pkt.push(PROTO_UDP);
+                pkt.push(TTL);
+                pkt.extend_from_slice(&src.octets());
+                pkt.extend_from_slice(&dst.octets());
+            },
+
+            _ => unreachable!(),
+        }
+
+        pkt.extend_from_slice(&from.port().to_be_bytes());
+        pkt.extend_from_slice(&to.port().to_be_bytes());
+        pkt.extend_from_slice(&(udp_len as u16).to_be_bytes());
+        pkt.extend_from_slice(&[0, 0]); // checksum, not computed
+
+        pkt.extend_from_slice(quic_header);
+
+        self.writer.write_all(&pkt).ok();
+    }
+}
+
+impl std::fmt::Debug for PcapWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "PcapWriter")
+    }
+}
+
+// Returns the length of the invariant header of the QUIC packet at the start
+// of `buf`, or of as much of it as `buf` contains.
+fn invariant_header_len(buf: &[u8], dcid_len: usize) -> usize {
+    let mut b = octets::Octets::with_slice(buf);
+
+    let first = match b.get_u8() {
+        Ok(v) => v,
+
+        Err(_) => return 0,
+    };
+
+    if !crate::packet::Header::is_long(first) {
+        return buf.len().min(1 + dcid_len);
+    }
+
+    let mut long_header = || -> Result<(), octets::BufferTooShortError> {
+        b.skip(4)?; // version
+
+        let dcid_len = b.get_u8()?;
+        b.skip(dcid_len as usize)?;
+
+        let scid_len = b.get_u8()?;
+        b.skip(scid_len as usize)?;
+
+        Ok(())
+    };
+
+    match long_header() {
+        Ok(()) => b.off(),
+
+        Err(_) => buf.len(),
+    }
+}
+
+fn ipv4_checksum(hdr: &[u8]) -> u16 {
+    let mut sum: u32 = hdr
+        .chunks(2)
+        .map(|w| u32::from(u16::from_be_bytes([w[0], w[1]])))
+        .sum();
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryInto;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ipv4_short_header() {
+        let out = SharedBuf::default();
+        let mut pcap = PcapWriter::new(Box::new(out.clone()));
+
+        let from = "127.0.0.1:4433".parse().unwrap();
+        let to = "127.0.0.1:1234".parse().unwrap();
+
+        // Short header packet with an 8 bytes DCID.
+        let mut dgram = vec![0x40];
+        dgram.extend_from_slice(&[0xba; 8]);
+        dgram.extend_from_slice(&[0xff; 1191]);
+
+        pcap.write_datagram(&dgram, from, to, 8, Instant::now());
+
+        let out = out.0.lock().unwrap();
+
+        assert_eq!(&out[..4], &MAGIC_NANOS.to_le_bytes());
+        assert_eq!(&out[20..24], &LINKTYPE_RAW.to_le_bytes());
+
+        let rec = &out[24..];
+
+        let incl_len = u32::from_le_bytes(rec[8..12].try_into().unwrap());
+        let orig_len = u32::from_le_bytes(rec[12..16].try_into().unwrap());
+
+        // Only the IP, UDP and QUIC headers are captured.
+        assert_eq!(incl_len, 20 + 8 + 9);
+        assert_eq!(orig_len, 20 + 8 + 1200);
+        assert_eq!(rec.len(), 16 + incl_len as usize);
+
+        let ip = &rec[16..36];
+        assert_eq!(ip[0], 0x45);
+        assert_eq!(u16::from_be_bytes([ip[2], ip[3]]), 1228);
+        assert_eq!(ipv4_checksum(ip), 0);
+
+        let udp = &rec[36..44];
+        assert_eq!(u16::from_be_bytes([udp[0], udp[1]]), 4433);
+        assert_eq!(u16::from_be_bytes([udp[2], udp[3]]), 1234);
+        assert_eq!(u16::from_be_bytes([udp[4], udp[5]]), 1208);
+
+        assert_eq!(&rec[44..], &dgram[..9]);
+    }
+
+    #[test]
+    fn ipv6_long_header() {
+        let out = SharedBuf::default();
+        let mut pcap = PcapWriter::new(Box::new(out.clone()));
+
+        let from = "[::1]:4433".parse().unwrap();
+        let to = "[::1]:1234".parse().unwrap();
+
+        // Initial packet with a 4 bytes DCID and a 2 bytes SCID.
+        let mut dgram = vec![0xc0, 0, 0, 0, 1, 4, 1, 2, 3, 4, 2, 5, 6];
+        dgram.extend_from_slice(&[0; 1187]);
+
+        pcap.write_datagram(&dgram, from, to, 8, Instant::now());
+
+        let out = out.0.lock().unwrap();
+        let rec = &out[24..];
+
+        let incl_len = u32::from_le_bytes(rec[8..12].try_into().unwrap());
+        let orig_len = u32::from_le_bytes(rec[12..16].try_into().unwrap());
+
+        assert_eq!(incl_len, 40 + 8 + 13);
+        assert_eq!(orig_len, 40 + 8 + 1200);
+
+        let ip = &rec[16..56];
+        assert_eq!(ip[0], 0x60);
+        assert_eq!(u16::from_be_bytes([ip[4], ip[5]]), 1208);
+        assert_eq!(ip[6], PROTO_UDP);
+
+        assert_eq!(&rec[64..], &dgram[..13]);
+    }
+
+    #[test]
+    fn mixed_families() {
+        let out = SharedBuf::default();
+        let mut pcap = PcapWriter::new(Box::new(out.clone()));
+
+        let from = "127.0.0.1:4433".parse().unwrap();
+        let to = "[::1]:1234".parse().unwrap();
+
+        pcap.write_datagram(&[0x40; 100], from, to, 8, Instant::now());
+
+        // Only the file header is written.
+        assert_eq!(out.0.lock().unwrap().len(), 24);
+    }
+}
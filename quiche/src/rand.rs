@@ -24,12 +24,60 @@
 // NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
 // SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
 
+#[cfg(any(test, feature = "deterministic-rand"))]
+use std::cell::Cell;
+
+#[cfg(any(test, feature = "deterministic-rand"))]
+thread_local! {
+    /// The state of the deterministic generator used instead of the TLS
+    /// library's one on this thread, if it was seeded.
+    static SEEDED: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Makes the randomness used on the current thread deterministic, drawing it
+/// from a generator seeded with `seed`, or random again when `None`.
+#[cfg(any(test, feature = "deterministic-rand"))]
+pub fn set_seed(seed: Option<u64>) {
+    SEEDED.with(|s| s.set(seed));
+}
+
 pub fn rand_bytes(buf: &mut [u8]) {
+    #[cfg(any(test, feature = "deterministic-rand"))]
+    if SEEDED.with(|s| seeded_bytes(s, buf)) {
+        return;
+    }
+
     unsafe {
         RAND_bytes(buf.as_mut_ptr(), buf.len());
     }
 }
 
+/// Fills `buf` from the SplitMix64 generator whose state is `state`, if it
+/// was seeded.
+#[cfg(any(test, feature = "deterministic-rand"))]
+fn seeded_bytes(state: &Cell<Option<u64>>, buf: &mut [u8]) -> bool {
+    let mut x = match state.get() {
+        Some(v) => v,
+
+        None => return false,
+    };
+
+    for chunk in buf.chunks_mut(8) {
+        x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+        let mut z = x;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        chunk.copy_from_slice(&z.to_le_bytes()[..chunk.len()]);
+    }
+
+    state.set(Some(x));
+
+    true
+}
+
 pub fn rand_u8() -> u8 {
     let mut buf = [0; 1];
 
@@ -62,3 +110,30 @@ pub fn rand_u64_uniform(max: u64) -> u64 {
 extern {
     fn RAND_bytes(buf: *mut u8, len: libc::size_t) -> libc::c_int;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded() {
+        let draw = || {
+            let mut buf = [0; 13];
+            rand_bytes(&mut buf);
+
+            (buf, rand_u64())
+        };
+
+        set_seed(Some(42));
+        let first = draw();
+
+        set_seed(Some(42));
+        assert_eq!(draw(), first);
+
+        set_seed(Some(43));
+        assert_ne!(draw(), first);
+
+        set_seed(None);
+        assert_ne!(draw(), first);
+    }
+}
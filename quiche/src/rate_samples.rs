@@ -0,0 +1,149 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Export of the delivery rate samples seen by the congestion controller.
+//!
+//! Each ACK that acknowledges new packets generates a delivery rate sample,
+//! as described in
+//! <https://tools.ietf.org/html/draft-cheng-iccrg-delivery-rate-estimation-01>.
+//! The samples are written as CSV, one line per ACK and path, along with the
+//! congestion window and the Careful Resume phase they were consumed in, so
+//! that they can be analysed without reconstructing them from qlog.
+//!
+//! ```text
+//! time_us,path_id,delivered,interval_us,delivery_rate,rtt_us,app_limited,cwnd,bytes_in_flight,cr_phase
+//! ```
+//!
+//! The time is relative to the creation of the writer. An interval of 0 means
+//! that the sample was taken over less than the minimum RTT, and its delivery
+//! rate is the one of the last reliable sample.
+
+use std::io::Write;
+use std::time::Instant;
+
+use crate::recovery::RateSample;
+
+const HEADER: &str = "time_us,path_id,delivered,interval_us,delivery_rate,\
+                      rtt_us,app_limited,cwnd,bytes_in_flight,cr_phase";
+
+pub(crate) struct RateSampleWriter {
+    writer: Box<dyn Write + Send + Sync>,
+
+    start: Instant,
+}
+
+impl RateSampleWriter {
+    pub fn new(mut writer: Box<dyn Write + Send + Sync>) -> Self {
+        writeln!(writer, "{HEADER}").ok();
+
+        RateSampleWriter {
+            writer,
+            start: Instant::now(),
+        }
+    }
+
+    /// Records the sample generated at `now` on the path `path_id`.
+    pub fn write_sample(
+        &mut self, now: Instant, path_id: usize, sample: &RateSample,
+    ) {
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{},{},{},{}",
+            now.saturating_duration_since(self.start).as_micros(),
+            path_id,
+            sample.delivered,
+            sample.interval.as_micros(),
+            sample.delivery_rate,
+            sample.rtt.as_micros(),
+            sample.is_app_limited as u8,
+            sample.cwnd,
+            sample.bytes_in_flight,
+            sample.cr_phase,
+        )
+        .ok();
+    }
+}
+
+impl std::fmt::Debug for RateSampleWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "RateSampleWriter")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn csv() {
+        let out = SharedBuf::default();
+        let mut w = RateSampleWriter::new(Box::new(out.clone()));
+
+        let sample = RateSample {
+            delivered: 12_000,
+            interval: Duration::from_millis(50),
+            delivery_rate: 240_000,
+            rtt: Duration::from_micros(50_250),
+            is_app_limited: true,
+            cwnd: 24_000,
+            bytes_in_flight: 6_000,
+            cr_phase: "unvalidated",
+        };
+
+        w.write_sample(w.start + Duration::from_millis(100), 1, &sample);
+
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let mut lines = out.lines();
+
+        let header = lines.next().unwrap();
+        let line = lines.next().unwrap();
+        assert_eq!(lines.next(), None);
+
+        assert_eq!(header.split(',').count(), line.split(',').count());
+        assert_eq!(
+            line,
+            "100000,1,12000,50000,240000,50250,1,24000,6000,unvalidated"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! ACK aggregation detection.
+//!
+//! Wi-Fi and cellular links, as well as middleboxes and receivers batching
+//! ACKs, return acknowledgements in bunches: after a gap, ACKs arrive back to
+//! back, acknowledging data faster than it was sent. Window based congestion
+//! controllers and Careful Resume credit every acknowledged byte as evidence
+//! of path capacity as soon as it is acknowledged, so bunching over-credits
+//! the path.
+//!
+//! Aggregation is detected by comparing the time over which ACKs arrived with
+//! the time over which the packets they acknowledge were sent. ACKs can't
+//! legitimately return faster than the data was sent, so the share of the
+//! bytes that arrived faster is reported as excess. The comparison doesn't
+//! depend on a bandwidth estimate, so it isn't thrown off when the sending
+//! rate jumps.
+//!
+//! An aggregation epoch starts with each ACK that arrives no faster than the
+//! data it acknowledges was sent, and the excess is measured over the ACKs
+//! received since.
+
+use std::time::Instant;
+
+/// Bytes, in full-sized packets, acknowledged in an epoch that are not
+/// considered excess, to absorb jitter in ACK arrival times.
+const TOLERANCE_PACKETS: usize = 2;
+
+pub struct AckAggregation {
+    // Whether congestion window growth and Careful Resume accounting are
+    // compensated for the excess.
+    compensate: bool,
+
+    // When the last ACK was received, and when the largest packet it newly
+    // acknowledged was sent.
+    last_ack: Option<(Instant, Instant)>,
+
+    // The same for the ACK that started the epoch.
+    epoch_start: (Instant, Instant),
+
+    // Bytes acknowledged since the start of the epoch.
+    epoch_acked: usize,
+
+    // Excess already reported during the epoch.
+    epoch_excess: usize,
+
+    // The number of ACKs that acknowledged excess bytes.
+    count: u64,
+
+    // The total excess bytes acknowledged.
+    bytes: u64,
+}
+
+impl AckAggregation {
+    pub fn new(compensate: bool) -> Self {
+        let now = Instant::now();
+
+        Self {
+            compensate,
+
+            last_ack: None,
+
+            epoch_start: (now, now),
+
+            epoch_acked: 0,
+
+            epoch_excess: 0,
+
+            count: 0,
+
+            bytes: 0,
+        }
+    }
+
+    pub fn compensate(&self) -> bool {
+        self.compensate
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Records an ACK received at `now` that newly acknowledged `acked`
+    /// bytes, the largest of which was sent at `time_sent`, and returns how
+    /// many of these bytes arrived faster than they were sent.
+    pub fn on_ack(
+        &mut self, acked: usize, time_sent: Instant, max_datagram_size: usize,
+        now: Instant,
+    ) -> usize {
+        let last_ack = self.last_ack.replace((now, time_sent));
+
+        let compressed = match last_ack {
+            Some((last_ack, last_sent)) =>
+                now.saturating_duration_since(last_ack) <
+                    time_sent.saturating_duration_since(last_sent),
+
+            None => false,
+        };
+
+        if !compressed {
+            self.epoch_start = (now, time_sent);
+            self.epoch_acked = 0;
+            self.epoch_excess = 0;
+            return 0;
+        }
+
+        let (ack_start, send_start) = self.epoch_start;
+
+        let ack_elapsed = now.saturating_duration_since(ack_start);
+        let send_elapsed = time_sent.saturating_duration_since(send_start);
+
+        self.epoch_acked += acked;
+
+        // Only the share of the bytes matching the rate at which they were
+        // sent is explained.
+        let explained = (self.epoch_acked as f64 * ack_elapsed.as_secs_f64() /
+            send_elapsed.as_secs_f64()) as usize +
+            TOLERANCE_PACKETS * max_datagram_size;
+
+        let extra = self.epoch_acked.saturating_sub(explained);
+        let excess = extra.saturating_sub(self.epoch_excess).min(acked);
+
+        self.epoch_excess = self.epoch_excess.max(extra);
+
+        if excess > 0 {
+            self.count += 1;
+            self.bytes += excess as u64;
+        }
+
+        excess
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    const MSS: usize = 1200;
+
+    #[test]
+    fn ack_clocked() {
+        let mut a = AckAggregation::new(false);
+        let start = Instant::now();
+
+        // 2 packets sent every 2ms, and acked one RTT later.
+        for i in 0..100 {
+            let sent = start + Duration::from_millis(2 * i);
+            let now = sent + Duration::from_millis(50);
+
+            assert_eq!(a.on_ack(2 * MSS, sent, MSS, now), 0);
+        }
+
+        assert_eq!(a.count(), 0);
+        assert_eq!(a.bytes(), 0);
+    }
+
+    #[test]
+    fn stretch_acks() {
+        let mut a = AckAggregation::new(false);
+        let start = Instant::now();
+
+        // 1 packet sent every 1ms, acked 10 at a time, with some jitter.
+        for i in 0..20 {
+            let sent = start + Duration::from_millis(10 * i);
+            let jitter = Duration::from_micros(500 * (i % 2));
+            let now = sent + Duration::from_millis(50) - jitter;
+
+            assert_eq!(a.on_ack(10 * MSS, sent, MSS, now), 0);
+        }
+
+        assert_eq!(a.count(), 0);
+    }
+
+    #[test]
+    fn bunched_acks() {
+        let mut a = AckAggregation::new(true);
+        let start = Instant::now();
+        let rtt = Duration::from_millis(50);
+
+        // 1 packet sent every 1ms.
+        let sent = |pkt: u64| start + Duration::from_millis(pkt);
+
+        assert_eq!(a.on_ack(MSS, sent(0), MSS, sent(0) + rtt), 0);
+
+        // ACKs for packets 1 to 20 are held for 20ms, then arrive back to
+        // back, 5 packets at a time. The first ACK of the bunch arrives after
+        // the gap, so is explained, the rest arrive faster than sent.
+        let now = sent(20) + rtt;
+
+        assert_eq!(a.on_ack(5 * MSS, sent(5), MSS, now), 0);
+        assert_eq!(a.on_ack(5 * MSS, sent(10), MSS, now), 3 * MSS);
+        assert_eq!(a.on_ack(5 * MSS, sent(15), MSS, now), 5 * MSS);
+        assert_eq!(a.on_ack(5 * MSS, sent(20), MSS, now), 5 * MSS);
+
+        assert_eq!(a.count(), 3);
+        assert_eq!(a.bytes(), 13 * MSS as u64);
+
+        // Once ACKs arrive no faster than sent, no excess is reported.
+        assert_eq!(a.on_ack(2 * MSS, sent(22), MSS, sent(22) + rtt), 0);
+        assert_eq!(a.on_ack(2 * MSS, sent(24), MSS, sent(24) + rtt), 0);
+
+        assert_eq!(a.count(), 3);
+    }
+}
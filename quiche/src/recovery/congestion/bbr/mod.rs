@@ -44,6 +44,8 @@ pub(crate) static BBR: CongestionControlOps = CongestionControlOps {
     checkpoint,
     rollback,
     has_custom_pacing,
+    on_cr_jump,
+    on_cr_retreat,
     debug_fmt,
 };
 
@@ -337,6 +339,47 @@ fn has_custom_pacing() -> bool {
     true
 }
 
+// Careful Resume: the jumped window is the one of the previous connection, so
+// seed the bandwidth estimate with the rate it delivered at, for the target
+// cwnd to cover the jumped window. The pacing rate is the one Careful Resume
+// uses while the jump is unvalidated.
+fn on_cr_jump(r: &mut Congestion, now: Instant) {
+    let rate = r.resume.previous_rate();
+    let bbr = &mut r.bbr_state;
+
+    bbr.btlbw = bbr.btlbwfilter.running_max(
+        BTLBW_FILTER_LEN,
+        bbr.start_time + Duration::from_secs(bbr.round_count),
+        rate,
+    );
+
+    per_ack::bbr_update_target_cwnd(r);
+
+    let rate = r.resume.pacing_rate(r.bbr_state.pacing_rate);
+    r.set_pacing_rate(rate, now);
+}
+
+// Careful Resume: the seeded bandwidth wasn't validated, so replace it with
+// the rate of the retreated window. The loss already started a recovery
+// episode, make its end restore the retreated window rather than the jumped
+// one.
+fn on_cr_retreat(r: &mut Congestion, now: Instant) {
+    let bbr = &mut r.bbr_state;
+
+    let rate = (r.congestion_window as f64 / bbr.rtprop.as_secs_f64()) as u64;
+
+    bbr.btlbw = bbr
+        .btlbwfilter
+        .reset(bbr.start_time + Duration::from_secs(bbr.round_count), rate);
+
+    bbr.prior_cwnd = r.congestion_window;
+    bbr.pacing_rate = (bbr.pacing_gain * bbr.btlbw as f64) as u64;
+
+    per_ack::bbr_update_target_cwnd(r);
+
+    r.set_pacing_rate(r.bbr_state.pacing_rate, now);
+}
+
 fn debug_fmt(r: &Congestion, f: &mut std::fmt::Formatter) -> std::fmt::Result {
     let bbr = &r.bbr_state;
 
@@ -435,6 +478,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -458,6 +502,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -502,6 +547,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -525,6 +571,7 @@ mod tests {
                 r.on_ack_received(
                     &acked,
                     25,
+                    None,
                     packet::Epoch::Application,
                     HandshakeStatus::default(),
                     now,
@@ -553,6 +600,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -579,6 +627,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -623,6 +672,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -643,6 +693,7 @@ mod tests {
                 r.on_ack_received(
                     &acked,
                     25,
+                    None,
                     packet::Epoch::Application,
                     HandshakeStatus::default(),
                     now,
@@ -692,6 +743,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -714,6 +766,7 @@ mod tests {
                 r.on_ack_received(
                     &acked,
                     25,
+                    None,
                     packet::Epoch::Application,
                     HandshakeStatus::default(),
                     now,
@@ -746,6 +799,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -770,6 +824,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -781,6 +836,155 @@ mod tests {
         assert_eq!(r.congestion.bbr_state.state, BBRStateMachine::ProbeRTT);
         assert_eq!(r.congestion.bbr_state.pacing_gain, 1.0);
     }
+
+    fn send_packet(r: &mut Recovery, pkt_num: u64, now: Instant) {
+        let p = Sent {
+            pkt_num,
+            frames: smallvec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            tx_in_flight: 0,
+            lost: 0,
+            has_data: false,
+            pmtud: false,
+            retransmission: false,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::Epoch::Application,
+            HandshakeStatus::default(),
+            now,
+            "",
+        );
+    }
+
+    fn ack_packets(r: &mut Recovery, acked: &[(u64, u64)], now: Instant) {
+        let mut ranges = ranges::RangeSet::default();
+
+        for &(start, end) in acked {
+            ranges.insert(start..end);
+        }
+
+        r.on_ack_received(
+            &ranges,
+            25,
+            None,
+            packet::Epoch::Application,
+            HandshakeStatus::default(),
+            now,
+            "",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn valid_rtt_full_bbr() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::BBR);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+        for pn in 0..5 {
+            send_packet(&mut r, pn, now);
+        }
+
+        assert_eq!(r.congestion.resume.phase_name(), "reconnaissance");
+
+        now += Duration::from_millis(50);
+
+        ack_packets(&mut r, &[(0, 5)], now);
+
+        // BBR grows the window by the acknowledged bytes in Startup.
+        assert_eq!(r.cwnd(), 17_000);
+
+        // The jump is made once the window is filled.
+        for pn in 5..21 {
+            send_packet(&mut r, pn, now);
+        }
+
+        assert_eq!(r.congestion.resume.phase_name(), "reconnaissance");
+
+        send_packet(&mut r, 21, now);
+
+        assert_eq!(r.congestion.resume.phase_name(), "unvalidated");
+        assert_eq!(r.cwnd(), 40_000);
+
+        // The bandwidth estimate is seeded with the rate of the previous
+        // connection, which the jumped window is paced at.
+        assert_eq!(r.congestion.bbr_state.btlbw, 1_600_000);
+        assert_eq!(r.congestion.pacer.rate(), 1_600_000);
+        assert!(r.congestion.bbr_state.target_cwnd >= 40_000);
+
+        // The jumped window is kept while the ACKs are processed by BBR.
+        now += Duration::from_millis(50);
+
+        ack_packets(&mut r, &[(5, 10)], now);
+
+        assert_eq!(r.congestion.resume.phase_name(), "unvalidated");
+        assert!(r.cwnd() >= 40_000);
+        assert_eq!(r.congestion.pacer.rate(), 1_600_000);
+    }
+
+    #[test]
+    fn safe_retreat_full_bbr() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::BBR);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+        for pn in 0..5 {
+            send_packet(&mut r, pn, now);
+        }
+
+        now += Duration::from_millis(50);
+
+        ack_packets(&mut r, &[(0, 5)], now);
+
+        for pn in 5..40 {
+            send_packet(&mut r, pn, now);
+        }
+
+        assert_eq!(r.congestion.resume.phase_name(), "unvalidated");
+
+        now += Duration::from_millis(50);
+
+        // Packet 15 is lost, once late by more than the reordering window.
+        ack_packets(&mut r, &[(5, 15), (16, 19)], now);
+
+        let loss_time = r.epochs[packet::Epoch::Application].loss_time.unwrap();
+        r.on_loss_detection_timeout(HandshakeStatus::default(), loss_time, "");
+
+        assert_eq!(r.congestion.resume.phase_name(), "safe_retreat");
+        assert!(r.congestion.bbr_state.in_recovery);
+
+        // The seeded bandwidth is replaced with the rate of the retreated
+        // window, which is restored when the recovery episode ends.
+        let cwnd = r.congestion.bbr_state.prior_cwnd;
+        assert!(cwnd < 40_000);
+        assert!(r.congestion.bbr_state.btlbw < 1_600_000);
+
+        r.congestion.congestion_window = r.max_datagram_size;
+        bbr_exit_recovery(&mut r.congestion);
+
+        assert_eq!(r.cwnd(), cwnd);
+    }
 }
 
 mod init;
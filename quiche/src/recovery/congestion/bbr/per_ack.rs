@@ -61,7 +61,8 @@ pub fn bbr_update_control_parameters(
 
     // Set outgoing packet pacing rate
     // It is called here because send_quantum may be updated too.
-    r.set_pacing_rate(r.bbr_state.pacing_rate, now);
+    let rate = r.resume.pacing_rate(r.bbr_state.pacing_rate);
+    r.set_pacing_rate(rate, now);
 
     bbr_set_cwnd(r, bytes_in_flight);
 }
@@ -140,7 +141,7 @@ fn bbr_inflight(r: &mut Congestion, gain: f64) -> usize {
     (gain * estimated_bdp) as usize + quanta
 }
 
-fn bbr_update_target_cwnd(r: &mut Congestion) {
+pub fn bbr_update_target_cwnd(r: &mut Congestion) {
     r.bbr_state.target_cwnd = bbr_inflight(r, r.bbr_state.cwnd_gain);
 }
 
@@ -45,6 +45,8 @@ pub(crate) static BBR2: CongestionControlOps = CongestionControlOps {
     checkpoint,
     rollback,
     has_custom_pacing,
+    on_cr_jump,
+    on_cr_retreat,
     debug_fmt,
 };
 
@@ -611,6 +613,68 @@ fn has_custom_pacing() -> bool {
     true
 }
 
+// Careful Resume: start from the path model of the previous connection. Its
+// rate seeds max_bw, and it and its window become the upper bounds that
+// BBR.bw_hi and BBR.inflight_hi would otherwise only learn from losses, so
+// probing later resumes from there.
+fn on_cr_jump(r: &mut Congestion, now: Instant) {
+    let rate = r.resume.previous_rate();
+    let cwnd = r.resume.previous_cwnd();
+
+    let max_bw_filter_len = r
+        .delivery_rate
+        .sample_rtt()
+        .saturating_mul(MIN_RTT_FILTER_LEN);
+
+    let bbr = &mut r.bbr2_state;
+
+    bbr.max_bw = bbr.max_bw_filter.running_max(
+        max_bw_filter_len,
+        bbr.start_time + Duration::from_secs(bbr.cycle_count),
+        rate,
+    );
+
+    bbr.bw_hi = rate;
+    bbr.inflight_hi = cwnd;
+
+    per_loss::bbr2_bound_bw_for_model(r);
+    per_ack::bbr2_update_max_inflight(r);
+    pacing::bbr2_set_pacing_rate(r);
+
+    let rate = r.resume.pacing_rate(r.bbr2_state.pacing_rate);
+    r.set_pacing_rate(rate, now);
+}
+
+// Careful Resume: the restored model wasn't validated. Collapse it to the
+// retreated window instead of letting the loss be handled as the result of
+// probing: the upper bounds and max_bw are reset to the retreated window and
+// its rate, Startup ends, and the recovery episode restores the retreated
+// window rather than the jumped one.
+fn on_cr_retreat(r: &mut Congestion, now: Instant) {
+    let cwnd = r.congestion_window;
+    let bbr = &mut r.bbr2_state;
+
+    let rate = (cwnd as f64 / bbr.min_rtt.as_secs_f64()) as u64;
+
+    bbr.max_bw = bbr
+        .max_bw_filter
+        .reset(bbr.start_time + Duration::from_secs(bbr.cycle_count), rate);
+
+    bbr.bw_hi = rate;
+    bbr.inflight_hi = cwnd;
+    bbr.bw_probe_samples = false;
+    bbr.filled_pipe = true;
+    bbr.prior_cwnd = cwnd;
+
+    per_loss::bbr2_bound_bw_for_model(r);
+    per_ack::bbr2_update_max_inflight(r);
+
+    // With the pipe filled, the pacing rate follows the collapsed bw down.
+    pacing::bbr2_set_pacing_rate(r);
+
+    r.set_pacing_rate(r.bbr2_state.pacing_rate, now);
+}
+
 // rate -> kbit/sec. if inf, return -1
 fn rate_kbps(rate: u64) -> isize {
     if rate == u64::MAX {
@@ -709,6 +773,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -731,6 +796,7 @@ mod tests {
             .on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -776,6 +842,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -799,6 +866,7 @@ mod tests {
             .on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -846,6 +914,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -869,6 +938,7 @@ mod tests {
                 .on_ack_received(
                     &acked,
                     25,
+                    None,
                     packet::Epoch::Application,
                     HandshakeStatus::default(),
                     now,
@@ -896,6 +966,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -922,6 +993,7 @@ mod tests {
             .on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -966,6 +1038,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -988,6 +1061,7 @@ mod tests {
                 .on_ack_received(
                     &acked,
                     25,
+                    None,
                     packet::Epoch::Application,
                     HandshakeStatus::default(),
                     now,
@@ -1022,6 +1096,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1046,6 +1121,7 @@ mod tests {
             .on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1056,6 +1132,157 @@ mod tests {
         assert_eq!(r.congestion.bbr2_state.state, BBR2StateMachine::ProbeRTT);
         assert_eq!(r.congestion.bbr2_state.pacing_gain, 1.0);
     }
+
+    fn send_packet(r: &mut Recovery, pkt_num: u64, now: Instant) {
+        let p = Sent {
+            pkt_num,
+            frames: smallvec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            tx_in_flight: 0,
+            lost: 0,
+            has_data: false,
+            pmtud: false,
+            retransmission: false,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::Epoch::Application,
+            HandshakeStatus::default(),
+            now,
+            "",
+        );
+    }
+
+    fn ack_packets(r: &mut Recovery, acked: &[(u64, u64)], now: Instant) {
+        let mut ranges = ranges::RangeSet::default();
+
+        for &(start, end) in acked {
+            ranges.insert(start..end);
+        }
+
+        r.on_ack_received(
+            &ranges,
+            25,
+            None,
+            packet::Epoch::Application,
+            HandshakeStatus::default(),
+            now,
+            "",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn valid_rtt_full_bbr2() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::BBR2);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+        for pn in 0..5 {
+            send_packet(&mut r, pn, now);
+        }
+
+        now += Duration::from_millis(50);
+
+        ack_packets(&mut r, &[(0, 5)], now);
+
+        let mut pn = 5;
+        while r.congestion.resume.phase_name() == "reconnaissance" {
+            send_packet(&mut r, pn, now);
+            pn += 1;
+        }
+
+        assert_eq!(r.congestion.resume.phase_name(), "unvalidated");
+        assert_eq!(r.cwnd(), 40_000);
+
+        // The model is initialized from the previous connection, and the
+        // jumped window is paced at its rate.
+        assert_eq!(r.congestion.bbr2_state.max_bw, 1_600_000);
+        assert_eq!(r.congestion.bbr2_state.bw_hi, 1_600_000);
+        assert_eq!(r.congestion.bbr2_state.inflight_hi, 80_000);
+        assert_eq!(r.congestion.pacer.rate(), 1_600_000);
+
+        // The jumped window is kept while the ACKs are processed by BBRv2.
+        now += Duration::from_millis(50);
+
+        ack_packets(&mut r, &[(5, 10)], now);
+
+        assert_eq!(r.congestion.resume.phase_name(), "unvalidated");
+        assert!(r.cwnd() >= 40_000);
+        assert_eq!(r.congestion.pacer.rate(), 1_600_000);
+    }
+
+    #[test]
+    fn safe_retreat_full_bbr2() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(recovery::CongestionControlAlgorithm::BBR2);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+        for pn in 0..5 {
+            send_packet(&mut r, pn, now);
+        }
+
+        now += Duration::from_millis(50);
+
+        ack_packets(&mut r, &[(0, 5)], now);
+
+        for pn in 5..40 {
+            send_packet(&mut r, pn, now);
+        }
+
+        assert_eq!(r.congestion.resume.phase_name(), "unvalidated");
+
+        now += Duration::from_millis(50);
+
+        // Packet 15 is lost, once late by more than the reordering window.
+        ack_packets(&mut r, &[(5, 15), (16, 19)], now);
+
+        let loss_time = r.epochs[packet::Epoch::Application].loss_time.unwrap();
+        r.on_loss_detection_timeout(HandshakeStatus::default(), loss_time, "");
+
+        assert_eq!(r.congestion.resume.phase_name(), "safe_retreat");
+        assert!(r.congestion.bbr2_state.in_recovery);
+
+        // The restored model is collapsed to the retreated window instead of
+        // being probed, and Startup ends.
+        let cwnd = r.cwnd();
+        assert!(cwnd < 40_000);
+        assert_eq!(r.congestion.bbr2_state.inflight_hi, cwnd);
+        assert_eq!(r.congestion.bbr2_state.prior_cwnd, cwnd);
+        assert!(r.congestion.bbr2_state.bw_hi < 1_600_000);
+        assert_eq!(
+            r.congestion.bbr2_state.max_bw,
+            r.congestion.bbr2_state.bw_hi
+        );
+        assert!(r.congestion.bbr2_state.filled_pipe);
+        assert!(!r.congestion.bbr2_state.bw_probe_samples);
+
+        // The recovery episode restores the retreated window.
+        r.congestion.congestion_window = r.max_datagram_size;
+        bbr2_exit_recovery(&mut r.congestion);
+
+        assert_eq!(r.cwnd(), cwnd);
+    }
 }
 
 mod init;
@@ -62,7 +62,8 @@ pub fn bbr2_update_control_parameters(
 
     // Set outgoing packet pacing rate
     // It is called here because send_quantum may be updated too.
-    r.set_pacing_rate(r.bbr2_state.pacing_rate, now);
+    let rate = r.resume.pacing_rate(r.bbr2_state.pacing_rate);
+    r.set_pacing_rate(rate, now);
 
     bbr2_set_cwnd(r, in_flight);
 }
@@ -677,7 +678,7 @@ fn bbr2_inflight(r: &mut Congestion, bw: u64, gain: f64) -> usize {
     bbr2_quantization_budget(r, inflight)
 }
 
-fn bbr2_update_max_inflight(r: &mut Congestion) {
+pub fn bbr2_update_max_inflight(r: &mut Congestion) {
     // TODO: not implemented (not in the draft)
     // bbr2_update_aggregation_budget(r);
 
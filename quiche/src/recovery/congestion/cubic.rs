@@ -53,6 +53,8 @@ pub(crate) static CUBIC: CongestionControlOps = CongestionControlOps {
     checkpoint,
     rollback,
     has_custom_pacing,
+    on_cr_jump,
+    on_cr_retreat,
     debug_fmt,
 };
 
@@ -407,6 +409,10 @@ fn has_custom_pacing() -> bool {
     false
 }
 
+fn on_cr_jump(_r: &mut Congestion, _now: Instant) {}
+
+fn on_cr_retreat(_r: &mut Congestion, _now: Instant) {}
+
 fn debug_fmt(r: &Congestion, f: &mut std::fmt::Formatter) -> std::fmt::Result {
     write!(
         f,
@@ -722,6 +728,8 @@ mod tests {
             first_sent_time: sender.time,
             is_app_limited: false,
             rtt: Duration::ZERO,
+            ecn_ce: false,
+            retransmission: false,
         };
 
         // Trigger detecting spurious congestion event
@@ -752,6 +760,8 @@ mod tests {
             first_sent_time: sender.time,
             is_app_limited: false,
             rtt: Duration::ZERO,
+            ecn_ce: false,
+            retransmission: false,
         };
 
         // Trigger detecting spurious congestion event.
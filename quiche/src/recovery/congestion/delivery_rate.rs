@@ -172,6 +172,10 @@ impl Rate {
         self.rate_sample.delivery_rate
     }
 
+    pub fn sample_interval(&self) -> Duration {
+        self.rate_sample.interval
+    }
+
     pub fn sample_rtt(&self) -> Duration {
         self.rate_sample.rtt
     }
@@ -245,6 +249,7 @@ mod tests {
                 tx_in_flight: 0,
                 lost: 0,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -270,6 +275,8 @@ mod tests {
                 delivered_time: now,
                 first_sent_time: now.checked_sub(rtt).unwrap(),
                 is_app_limited: false,
+                ecn_ce: false,
+                retransmission: false,
             };
 
             r.congestion.delivery_rate.update_rate_sample(&acked, now);
@@ -312,6 +319,7 @@ mod tests {
                 tx_in_flight: 0,
                 lost: 0,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -354,6 +362,7 @@ mod tests {
                 tx_in_flight: 0,
                 lost: 0,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -375,6 +384,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -0,0 +1,317 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Golden trace tests for the qlog events emitted by careful resume.
+//!
+//! Each test runs a scripted scenario through [`Recovery`], captures the qlog
+//! events it emits in memory, and compares them with the JSON file of the
+//! same name under `tests/golden/`. Any change to the content or the order of
+//! the events makes the test fail, so that it is reviewed explicitly.
+//!
+//! After an intended change, regenerate the files with:
+//!
+//! ```text
+//! QUICHE_UPDATE_GOLDEN=1 cargo test --features qlog golden
+//! ```
+
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+use qlog::events::EventData;
+use smallvec::smallvec;
+
+use crate::packet;
+use crate::ranges;
+use crate::recovery::HandshakeStatus;
+use crate::recovery::Recovery;
+use crate::recovery::Sent;
+use crate::CongestionControlAlgorithm;
+
+const PACKET_SIZE: usize = 1000;
+
+pub(crate) struct Scenario {
+    recovery: Recovery,
+    now: Instant,
+    next_pkt: u64,
+    events: Vec<EventData>,
+}
+
+impl Scenario {
+    /// Creates a scenario resuming from a previous connection with the given
+    /// RTT and congestion window.
+    pub(crate) fn new(
+        config: &crate::Config, previous_rtt: Duration, previous_cwnd: usize,
+    ) -> Self {
+        let mut recovery = Recovery::new(config, "");
+        recovery.setup_careful_resume(previous_rtt, previous_cwnd, 0);
+
+        let mut s = Scenario {
+            recovery,
+            now: Instant::now(),
+            next_pkt: 0,
+            events: Vec::new(),
+        };
+
+        s.collect();
+        s
+    }
+
+    pub(crate) fn advance(&mut self, d: Duration) {
+        self.now += d;
+    }
+
+    /// Sends `count` packets.
+    pub(crate) fn send(&mut self, count: u64) {
+        for _ in 0..count {
+            let p = Sent {
+                pkt_num: self.next_pkt,
+                frames: smallvec![],
+                time_sent: self.now,
+                time_acked: None,
+                time_lost: None,
+                size: PACKET_SIZE,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: self.now,
+                first_sent_time: self.now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            self.recovery.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                self.now,
+                "",
+            );
+
+            self.next_pkt += 1;
+            self.collect();
+        }
+    }
+
+    /// Receives an ACK frame for the packets in `range`.
+    pub(crate) fn ack(&mut self, range: Range<u64>) {
+        self.ack_with_ecn(range, None);
+    }
+
+    /// Receives an ACK frame for the packets in `range`, carrying the given
+    /// ECN-CE count.
+    pub(crate) fn ack_with_ecn(
+        &mut self, range: Range<u64>, ecn_ce_count: Option<u64>,
+    ) {
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(range);
+
+        self.recovery
+            .on_ack_received(
+                &acked,
+                0,
+                ecn_ce_count,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                self.now,
+                "",
+            )
+            .unwrap();
+
+        self.collect();
+    }
+
+    /// Fires the loss detection timer.
+    pub(crate) fn loss_timeout(&mut self) {
+        self.now = self.recovery.loss_detection_timer().unwrap();
+
+        self.recovery.on_loss_detection_timeout(
+            HandshakeStatus::default(),
+            self.now,
+            "",
+        );
+
+        self.collect();
+    }
+
+    fn collect(&mut self) {
+        self.events.extend(self.recovery.packet_loss_qlog());
+
+        while let Some(ev) = self.recovery.maybe_cr_qlog() {
+            self.events.push(ev);
+        }
+    }
+
+    /// Compares the captured events with `tests/golden/<name>.json`, or
+    /// overwrites the file when `QUICHE_UPDATE_GOLDEN` is set.
+    pub(crate) fn assert_golden(&self, name: &str) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{}.json", name));
+
+        let actual = serde_json::to_string_pretty(&self.events).unwrap() + "\n";
+
+        if std::env::var_os("QUICHE_UPDATE_GOLDEN").is_some() {
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, actual).unwrap();
+            return;
+        }
+
+        let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| {
+            panic!("failed to read {}: {}", path.display(), e)
+        });
+
+        if actual != expected {
+            let diff = diff_lines(&expected, &actual);
+
+            panic!(
+                "qlog trace differs from {}, set QUICHE_UPDATE_GOLDEN=1 to \
+                 update it if the change is intended\n{}",
+                path.display(),
+                diff
+            );
+        }
+    }
+}
+
+// Returns the lines that differ between `expected` and `actual`, prefixed
+// with their line number.
+fn diff_lines(expected: &str, actual: &str) -> String {
+    let mut expected = expected.lines();
+    let mut actual = actual.lines();
+    let mut diff = String::new();
+    let mut line = 1;
+
+    loop {
+        match (expected.next(), actual.next()) {
+            (None, None) => break,
+
+            (e, a) if e != a => {
+                if let Some(e) = e {
+                    diff += &format!("{:>5} - {}\n", line, e);
+                }
+
+                if let Some(a) = a {
+                    diff += &format!("{:>5} + {}\n", line, a);
+                }
+            },
+
+            _ => (),
+        }
+
+        line += 1;
+    }
+
+    diff
+}
+
+fn config() -> crate::Config {
+    let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+    cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+    cfg.enable_resume(true);
+    cfg
+}
+
+// Reconnaissance, then a jump to half the previous window, which is
+// validated without congestion.
+#[test]
+fn golden_validated() {
+    let mut s = Scenario::new(&config(), Duration::from_millis(30), 120_000);
+
+    s.send(4);
+    s.advance(Duration::from_millis(25));
+    s.ack(0..4);
+
+    s.send(40);
+    s.advance(Duration::from_millis(25));
+    s.ack(4..16);
+    s.advance(Duration::from_millis(25));
+    s.ack(16..44);
+
+    s.assert_golden("cr_validated");
+}
+
+// A loss during the jump, declared once the missing packets are late by more
+// than the reordering window, leads to safe retreat, which ends once the last
+// packet sent while unvalidated is acknowledged.
+#[test]
+fn golden_safe_retreat() {
+    let mut s = Scenario::new(&config(), Duration::from_millis(30), 120_000);
+
+    s.send(4);
+    s.advance(Duration::from_millis(25));
+    s.ack(0..4);
+
+    s.send(40);
+    s.advance(Duration::from_millis(25));
+    s.ack(4..10);
+    s.ack(14..20);
+    s.loss_timeout();
+
+    s.send(4);
+    s.advance(Duration::from_millis(25));
+    s.ack(20..48);
+
+    s.assert_golden("cr_safe_retreat");
+}
+
+// A CE mark during the unvalidated phase leads to safe retreat.
+#[test]
+fn golden_ecn_ce() {
+    let mut s = Scenario::new(&config(), Duration::from_millis(30), 120_000);
+
+    s.send(4);
+    s.advance(Duration::from_millis(25));
+    s.ack_with_ecn(0..4, Some(0));
+
+    s.send(40);
+    s.advance(Duration::from_millis(25));
+    s.ack_with_ecn(4..12, Some(1));
+    s.advance(Duration::from_millis(25));
+    s.ack_with_ecn(12..44, Some(1));
+
+    s.assert_golden("cr_ecn_ce");
+}
+
+// The RTT differs too much from that of the previous connection, so no
+// jump is made.
+#[test]
+fn golden_rtt_not_validated() {
+    let mut s = Scenario::new(&config(), Duration::from_millis(200), 120_000);
+
+    s.send(4);
+    s.advance(Duration::from_millis(25));
+    s.ack(0..4);
+
+    s.send(20);
+
+    s.assert_golden("cr_rtt_not_validated");
+}
@@ -64,6 +64,8 @@ pub struct Hystart {
     css_start_time: Option<Instant>,
 
     css_round_count: usize,
+
+    suspended: bool,
 }
 
 impl std::fmt::Debug for Hystart {
@@ -74,7 +76,8 @@ impl std::fmt::Debug for Hystart {
         write!(f, "css_baseline_min_rtt={:?} ", self.css_baseline_min_rtt)?;
         write!(f, "rtt_sample_count={:?} ", self.rtt_sample_count)?;
         write!(f, "css_start_time={:?} ", self.css_start_time)?;
-        write!(f, "css_round_count={:?}", self.css_round_count)?;
+        write!(f, "css_round_count={:?} ", self.css_round_count)?;
+        write!(f, "suspended={:?}", self.suspended)?;
 
         Ok(())
     }
@@ -107,7 +110,30 @@ impl Hystart {
         self.enabled && self.css_start_time().is_some()
     }
 
+    // Stops sampling RTTs, e.g. while the congestion window is set by Careful
+    // Resume rather than grown by slow start. The samples taken so far are
+    // dropped, so sampling starts over once resumed.
+    pub fn suspend(&mut self) {
+        *self = Self {
+            suspended: true,
+
+            ..Self::new(self.enabled)
+        };
+    }
+
+    pub fn resume(&mut self) {
+        self.suspended = false;
+    }
+
+    pub fn suspended(&self) -> bool {
+        self.suspended
+    }
+
     pub fn start_round(&mut self, pkt_num: u64) {
+        if self.suspended {
+            return;
+        }
+
         if self.window_end.is_none() {
             self.window_end = Some(pkt_num);
 
@@ -123,7 +149,7 @@ impl Hystart {
     pub fn on_packet_acked(
         &mut self, packet: &recovery::Acked, rtt: Duration, now: Instant,
     ) -> bool {
-        if !self.enabled {
+        if !self.enabled || self.suspended {
             return false;
         }
 
@@ -238,4 +264,48 @@ mod tests {
 
         assert_eq!(hspp.window_end, None);
     }
+
+    #[test]
+    fn suspend() {
+        let mut hspp = Hystart::new(true);
+        let now = Instant::now();
+
+        hspp.start_round(100);
+        hspp.css_start_time = Some(now);
+        assert!(hspp.in_css());
+
+        // The samples and the CSS taken before are dropped.
+        hspp.suspend();
+
+        assert!(hspp.suspended());
+        assert!(!hspp.in_css());
+        assert_eq!(hspp.window_end, None);
+
+        // No rounds are started and no RTTs are sampled while suspended.
+        hspp.start_round(200);
+        assert_eq!(hspp.window_end, None);
+
+        let acked = recovery::Acked {
+            pkt_num: 200,
+            time_sent: now,
+            size: 1200,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            rtt: Duration::ZERO,
+            ecn_ce: false,
+            retransmission: false,
+        };
+
+        assert!(!hspp.on_packet_acked(&acked, Duration::from_millis(10), now));
+        assert_eq!(hspp.rtt_sample_count, 0);
+        assert_eq!(hspp.current_round_min_rtt, Duration::MAX);
+
+        hspp.resume();
+
+        assert!(!hspp.suspended());
+        hspp.start_round(300);
+        assert_eq!(hspp.window_end, Some(300));
+    }
 }
@@ -0,0 +1,300 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Endpoint-wide limit on Careful Resume jumps.
+//!
+//! When a busy server restarts, thousands of clients may resume towards it at
+//! once, each jumping to the congestion window of its previous connection.
+//! Every jump is safe on its own, but together the unvalidated bursts can
+//! overwhelm the access link of the endpoint.
+//!
+//! A [`CrJumpLimiter`] shared between connections bounds how many of them may
+//! be in the Unvalidated phase at the same time through each local interface,
+//! identified by its IP address. Connections that are ready to jump while the
+//! limit is reached wait in reconnaissance, and jump in the order in which
+//! they became ready as other connections leave the Unvalidated phase.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// Limits how many connections may be in the Careful Resume Unvalidated
+/// phase at the same time through each local interface.
+///
+/// The limiter is shared by cloning it, and applies to the connections
+/// created with the [`Config`] it is set on with
+/// [`set_cr_jump_limiter()`].
+///
+/// [`Config`]: crate::Config
+/// [`set_cr_jump_limiter()`]: crate::Config::set_cr_jump_limiter
+#[derive(Clone)]
+pub struct CrJumpLimiter {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    max_unvalidated: usize,
+
+    next_ticket: u64,
+
+    interfaces: HashMap<IpAddr, Interface>,
+}
+
+#[derive(Default)]
+struct Interface {
+    // Connections in the Unvalidated phase.
+    unvalidated: usize,
+
+    // Tickets of the connections waiting to jump, oldest first.
+    queue: VecDeque<u64>,
+}
+
+impl CrJumpLimiter {
+    /// Creates a limiter allowing up to `max_unvalidated` connections in the
+    /// Unvalidated phase per local interface. A value of 0 is treated as 1.
+    pub fn new(max_unvalidated: usize) -> Self {
+        let inner = Inner {
+            max_unvalidated: max_unvalidated.max(1),
+
+            next_ticket: 0,
+
+            interfaces: HashMap::new(),
+        };
+
+        CrJumpLimiter {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+
+    /// Returns the number of connections in the Unvalidated phase through
+    /// the local interface `ip`.
+    pub fn unvalidated(&self, ip: IpAddr) -> usize {
+        let inner = self.inner.lock().unwrap();
+
+        inner.interfaces.get(&ip).map_or(0, |i| i.unvalidated)
+    }
+
+    /// Returns the number of connections waiting to jump through the local
+    /// interface `ip`.
+    pub fn queued(&self, ip: IpAddr) -> usize {
+        let inner = self.inner.lock().unwrap();
+
+        inner.interfaces.get(&ip).map_or(0, |i| i.queue.len())
+    }
+
+    pub(crate) fn slot(&self, ip: IpAddr) -> JumpSlot {
+        let ticket = {
+            let mut inner = self.inner.lock().unwrap();
+
+            inner.next_ticket += 1;
+            inner.next_ticket
+        };
+
+        JumpSlot {
+            limiter: self.clone(),
+            ip,
+            ticket,
+            state: SlotState::Idle,
+        }
+    }
+}
+
+impl std::fmt::Debug for CrJumpLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let inner = self.inner.lock().unwrap();
+
+        write!(f, "max_unvalidated={} ", inner.max_unvalidated)?;
+        write!(f, "interfaces={}", inner.interfaces.len())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SlotState {
+    Idle,
+    Queued,
+    Held,
+}
+
+/// A connection's place in a [`CrJumpLimiter`].
+pub(crate) struct JumpSlot {
+    limiter: CrJumpLimiter,
+
+    ip: IpAddr,
+
+    ticket: u64,
+
+    state: SlotState,
+}
+
+impl JumpSlot {
+    /// Returns whether the connection may jump now, or queues it otherwise.
+    pub fn try_acquire(&mut self) -> bool {
+        if self.state == SlotState::Held {
+            return true;
+        }
+
+        let mut inner = self.limiter.inner.lock().unwrap();
+        let max_unvalidated = inner.max_unvalidated;
+
+        let interface = inner.interfaces.entry(self.ip).or_default();
+
+        let first = match interface.queue.front() {
+            Some(&ticket) => ticket == self.ticket,
+
+            None => true,
+        };
+
+        if first && interface.unvalidated < max_unvalidated {
+            if self.state == SlotState::Queued {
+                interface.queue.pop_front();
+            }
+
+            interface.unvalidated += 1;
+            self.state = SlotState::Held;
+
+            return true;
+        }
+
+        if self.state == SlotState::Idle {
+            interface.queue.push_back(self.ticket);
+            self.state = SlotState::Queued;
+        }
+
+        false
+    }
+
+    /// Leaves the Unvalidated phase, or the queue.
+    pub fn release(&mut self) {
+        if self.state == SlotState::Idle {
+            return;
+        }
+
+        let mut inner = self.limiter.inner.lock().unwrap();
+
+        if let Some(interface) = inner.interfaces.get_mut(&self.ip) {
+            match self.state {
+                SlotState::Held => interface.unvalidated -= 1,
+
+                SlotState::Queued =>
+                    interface.queue.retain(|&ticket| ticket != self.ticket),
+
+                SlotState::Idle => (),
+            }
+
+            if interface.unvalidated == 0 && interface.queue.is_empty() {
+                inner.interfaces.remove(&self.ip);
+            }
+        }
+
+        self.state = SlotState::Idle;
+    }
+}
+
+impl Drop for JumpSlot {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn limit_per_interface() {
+        let limiter = CrJumpLimiter::new(2);
+
+        let ip_a = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let ip_b = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+        let mut a1 = limiter.slot(ip_a);
+        let mut a2 = limiter.slot(ip_a);
+        let mut a3 = limiter.slot(ip_a);
+        let mut b1 = limiter.slot(ip_b);
+
+        assert!(a1.try_acquire());
+        assert!(a2.try_acquire());
+        assert!(!a3.try_acquire());
+        assert!(b1.try_acquire());
+
+        assert_eq!(limiter.unvalidated(ip_a), 2);
+        assert_eq!(limiter.queued(ip_a), 1);
+        assert_eq!(limiter.unvalidated(ip_b), 1);
+
+        // Acquiring again doesn't take another place.
+        assert!(a1.try_acquire());
+        assert_eq!(limiter.unvalidated(ip_a), 2);
+
+        a1.release();
+        assert!(a3.try_acquire());
+
+        assert_eq!(limiter.unvalidated(ip_a), 2);
+        assert_eq!(limiter.queued(ip_a), 0);
+
+        drop(a2);
+        drop(a3);
+        drop(b1);
+
+        assert_eq!(limiter.unvalidated(ip_a), 0);
+        assert_eq!(limiter.unvalidated(ip_b), 0);
+        assert!(limiter.inner.lock().unwrap().interfaces.is_empty());
+    }
+
+    #[test]
+    fn queue_order() {
+        let limiter = CrJumpLimiter::new(1);
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+        let mut s1 = limiter.slot(ip);
+        let mut s2 = limiter.slot(ip);
+        let mut s3 = limiter.slot(ip);
+        let mut s4 = limiter.slot(ip);
+
+        assert!(s1.try_acquire());
+        assert!(!s3.try_acquire());
+        assert!(!s2.try_acquire());
+        assert!(!s4.try_acquire());
+
+        assert_eq!(limiter.queued(ip), 3);
+
+        // The place goes to the connection that queued first.
+        s1.release();
+        assert!(!s2.try_acquire());
+        assert!(!s4.try_acquire());
+        assert!(s3.try_acquire());
+
+        // Leaving the queue lets the next connection jump.
+        s3.release();
+        s2.release();
+        assert!(s4.try_acquire());
+
+        assert_eq!(limiter.queued(ip), 0);
+    }
+}
@@ -41,6 +41,9 @@ pub struct Congestion {
     // HyStart++.
     pub(crate) hystart: hystart::Hystart,
 
+    // ACK aggregation.
+    pub(crate) ack_aggregation: ack_aggregation::AckAggregation,
+
     // Pacing.
     pub(crate) pacer: pacer::Pacer,
 
@@ -83,6 +86,7 @@ pub struct Congestion {
     //Careful resume
     pub(crate) resume: resume::Resume,
     pub(crate) cr_metrics: resume::CRMetrics,
+    cr_hystart_policy: resume::CrHystartPolicy,
 }
 
 impl Congestion {
@@ -122,6 +126,10 @@ impl Congestion {
 
             hystart: hystart::Hystart::new(recovery_config.hystart),
 
+            ack_aggregation: ack_aggregation::AckAggregation::new(
+                recovery_config.ack_aggregation_compensation,
+            ),
+
             pacer: pacer::Pacer::new(
                 recovery_config.pacing,
                 initial_congestion_window,
@@ -136,10 +144,45 @@ impl Congestion {
 
             bbr2_state: bbr2::State::new(),
 
-            resume: resume::Resume::new(trace_id),
-            cr_metrics: resume::CRMetrics::new(trace_id, initial_congestion_window),
+            resume: resume::Resume::new(
+                trace_id,
+                recovery_config.cr_min_rtts_before_jump,
+                recovery_config.cr_validating_policy,
+                recovery_config.cr_reconnaissance_timeout,
+            ),
+            cr_metrics: resume::CRMetrics::new(
+                trace_id,
+                initial_congestion_window,
+                recovery_config.resume_retreat_export,
+                recovery_config.cr_observe_thresholds,
+            ),
+            cr_hystart_policy: recovery_config.cr_hystart_policy,
         };
 
+        cc.resume.set_jump_pacing(recovery_config.cr_jump_pacing);
+        cc.resume.set_retreat_policy(
+            recovery_config.cr_retreat_policy,
+            initial_congestion_window,
+        );
+        cc.resume.set_thresholds(
+            recovery_config.cr_thresholds,
+            initial_congestion_window,
+        );
+        cc.resume.set_phase_timeouts(
+            recovery_config.cr_unvalidated_timeout,
+            recovery_config.cr_validating_timeout,
+        );
+        cc.resume.set_unvalidated_timeout_rtts(
+            recovery_config.cr_unvalidated_timeout_rtts,
+        );
+        cc.resume
+            .set_idle_threshold(recovery_config.cr_idle_threshold);
+        cc.resume.set_early_data(recovery_config.cr_early_data);
+
+        if let Some(observer) = &recovery_config.cr_phase_observer {
+            cc.resume.set_phase_observer(observer.clone());
+        }
+
         (cc.cc_ops.on_init)(&mut cc);
 
         cc
@@ -158,6 +201,32 @@ impl Congestion {
         self.delivery_rate.sample_delivery_rate()
     }
 
+    pub(crate) fn ssthresh(&self) -> Option<usize> {
+        if self.ssthresh == usize::MAX {
+            return None;
+        }
+
+        Some(self.ssthresh)
+    }
+
+    pub(crate) fn phase(&self) -> Option<CongestionPhase> {
+        // BBR and BBRv2 don't use a slow start threshold, but their own
+        // state machine.
+        if (self.cc_ops.has_custom_pacing)() {
+            return None;
+        }
+
+        if self.congestion_window >= self.ssthresh {
+            return Some(CongestionPhase::CongestionAvoidance);
+        }
+
+        if self.hystart.in_css() {
+            return Some(CongestionPhase::ConservativeSlowStart);
+        }
+
+        Some(CongestionPhase::SlowStart)
+    }
+
     pub(crate) fn send_quantum(&self) -> usize {
         self.send_quantum
     }
@@ -166,6 +235,25 @@ impl Congestion {
         self.pacer.update(self.send_quantum, rate, now);
     }
 
+    // Scales down the growth of the congestion window from `cwnd_before` by
+    // the share of the `acked` bytes that were aggregated. Congestion
+    // controllers doing their own pacing, i.e. BBR, account for aggregation
+    // themselves.
+    pub(crate) fn limit_aggregated_growth(
+        &mut self, cwnd_before: usize, acked: usize, aggregated: usize,
+    ) {
+        if (self.cc_ops.has_custom_pacing)() ||
+            self.congestion_window <= cwnd_before
+        {
+            return;
+        }
+
+        let growth = self.congestion_window - cwnd_before;
+
+        self.congestion_window =
+            cwnd_before + growth * acked.saturating_sub(aggregated) / acked;
+    }
+
     pub(crate) fn congestion_window(&self) -> usize {
         self.congestion_window
     }
@@ -179,6 +267,8 @@ impl Congestion {
         &mut self, bytes_in_flight: usize, sent_bytes: usize, now: Instant,
         pkt: &mut Sent, rtt_stats: &RttStats, bytes_lost: u64, in_flight: bool,
     ) {
+        self.update_hystart();
+
         if in_flight {
             self.update_app_limited(
                 (bytes_in_flight + sent_bytes) < self.congestion_window,
@@ -200,7 +290,8 @@ impl Congestion {
         {
             let rate = PACING_MULTIPLIER * self.congestion_window as f64 /
                 rtt_stats.rtt().as_secs_f64();
-            self.set_pacing_rate(rate as u64, now);
+            let rate = self.resume.pacing_rate(rate as u64);
+            self.set_pacing_rate(rate, now);
         }
 
         self.schedule_next_packet(now, sent_bytes);
@@ -224,6 +315,8 @@ impl Congestion {
         // Fill in a rate sample.
         self.delivery_rate.generate_rate_sample(*rtt_stats.min_rtt);
 
+        self.update_hystart();
+
         // Call congestion control hooks.
         (self.cc_ops.on_packets_acked)(
             self,
@@ -234,6 +327,31 @@ impl Congestion {
         );
     }
 
+    // HyStart++ is suspended during the Careful Resume jump, and carries on
+    // as set by the policy once the jump completes. After a safe retreat the
+    // congestion controller is in congestion avoidance anyway.
+    fn update_hystart(&mut self) {
+        if !self.hystart.enabled() {
+            return;
+        }
+
+        let in_jump = self.resume.in_jump();
+
+        if in_jump && !self.hystart.suspended() {
+            self.hystart.suspend();
+        } else if !in_jump && self.hystart.suspended() {
+            self.hystart.resume();
+
+            if self.cr_hystart_policy ==
+                resume::CrHystartPolicy::CongestionAvoidance &&
+                !self.resume.in_safe_retreat() &&
+                self.congestion_window < self.ssthresh
+            {
+                self.ssthresh = self.congestion_window;
+            }
+        }
+    }
+
     fn schedule_next_packet(&mut self, now: Instant, packet_size: usize) {
         // Don't pace in any of these cases:
         //   * Packet contains no data.
@@ -291,6 +409,22 @@ impl FromStr for CongestionControlAlgorithm {
     }
 }
 
+/// The phase of a window based congestion controller, i.e. Reno or CUBIC.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CongestionPhase {
+    /// The congestion window is below the slow start threshold, and grows
+    /// by the number of bytes acknowledged.
+    SlowStart,
+    /// HyStart++ detected an RTT increase during slow start, and slows down
+    /// the growth of the congestion window until it decides whether to exit
+    /// slow start.
+    ConservativeSlowStart,
+    /// The congestion window reached the slow start threshold, and grows by
+    /// about one packet per RTT for Reno, or following the cubic function
+    /// for CUBIC.
+    CongestionAvoidance,
+}
+
 pub(crate) struct CongestionControlOps {
     pub on_init: fn(r: &mut Congestion),
 
@@ -323,6 +457,13 @@ pub(crate) struct CongestionControlOps {
 
     pub has_custom_pacing: fn() -> bool,
 
+    // Careful Resume made its jump, already added to the congestion window.
+    pub on_cr_jump: fn(r: &mut Congestion, now: Instant),
+
+    // Careful Resume entered the safe retreat phase, and already reduced the
+    // congestion window.
+    pub on_cr_retreat: fn(r: &mut Congestion, now: Instant),
+
     pub debug_fmt: fn(
         r: &Congestion,
         formatter: &mut std::fmt::Formatter,
@@ -340,15 +481,19 @@ impl From<CongestionControlAlgorithm> for &'static CongestionControlOps {
     }
 }
 
+mod ack_aggregation;
 mod bbr;
 mod bbr2;
 mod cubic;
 mod delivery_rate;
 mod hystart;
+pub(crate) mod jump_limiter;
 pub(crate) mod pacer;
 mod prr;
 mod reno;
 
 #[cfg(test)]
 mod test_sender;
+#[cfg(all(test, feature = "qlog"))]
+mod golden;
 pub mod resume;
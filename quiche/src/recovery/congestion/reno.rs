@@ -48,6 +48,8 @@ pub(crate) static RENO: CongestionControlOps = CongestionControlOps {
     checkpoint,
     rollback,
     has_custom_pacing,
+    on_cr_jump,
+    on_cr_retreat,
     debug_fmt,
 };
 
@@ -146,6 +148,10 @@ fn has_custom_pacing() -> bool {
     false
 }
 
+fn on_cr_jump(_r: &mut Congestion, _now: Instant) {}
+
+fn on_cr_retreat(_r: &mut Congestion, _now: Instant) {}
+
 fn debug_fmt(_r: &Congestion, _f: &mut std::fmt::Formatter) -> std::fmt::Result {
     Ok(())
 }
@@ -1,9 +1,23 @@
-use std::time::{Duration, Instant};
+use std::cmp;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use qlog::events::EventData;
 use qlog::events::resume::*;
+use qlog::units::ByteCount;
 use crate::recovery::Acked;
+use crate::recovery::congestion::jump_limiter::{CrJumpLimiter, JumpSlot};
+use crate::ranges::RangeSet;
+use crate::minmax::Minmax;
 
-const CR_EVENT_MAXIMUM_GAP: Duration = Duration::from_secs(60);
+// How many ranges of packets counted in the pipesize are remembered. Older
+// packets are forgotten first, they are the least likely to be reported again
+const PIPESIZE_PKT_RANGES: usize = 64;
+
+// How many previous RTTs the sender may be idle during reconnaissance before
+// its RTT sample is considered stale, when no idle threshold is configured
+const IDLE_THRESHOLD_RTTS: u32 = 8;
 
 // No observe state as that always applies to the previous connection and never the current connection
 #[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
@@ -18,25 +32,469 @@ pub enum CrState {
     Normal,
 }
 
+/// The phase of Careful Resume on a path.
+///
+/// Returned by [`Connection::careful_resume_state()`]. The unvalidated,
+/// validating and safe retreat phases carry their `cr_mark`, the packet
+/// number whose acknowledgement ends the phase.
+///
+/// [`Connection::careful_resume_state()`]: struct.Connection.html#method.careful_resume_state
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CarefulResumeState {
+    /// The path is observed before deciding whether to jump.
+    Reconnaissance,
+
+    /// The congestion window jumped and the jump isn't validated yet.
+    Unvalidated {
+        /// The packet number whose acknowledgement ends the phase.
+        cr_mark: u64,
+    },
+
+    /// The data sent in the jumped window is being acknowledged.
+    Validating {
+        /// The packet number whose acknowledgement ends the phase.
+        cr_mark: u64,
+    },
+
+    /// The jump led to congestion and the congestion window was reduced.
+    SafeRetreat {
+        /// The packet number whose acknowledgement ends the phase.
+        cr_mark: u64,
+    },
+
+    /// Careful Resume is over and congestion control is back to normal.
+    Normal,
+}
+
+impl From<CrState> for CarefulResumeState {
+    fn from(state: CrState) -> Self {
+        match state {
+            CrState::Reconnaissance => CarefulResumeState::Reconnaissance,
+            CrState::Unvalidated(cr_mark) => CarefulResumeState::Unvalidated { cr_mark },
+            CrState::Validating(cr_mark) => CarefulResumeState::Validating { cr_mark },
+            CrState::SafeRetreat(cr_mark) => CarefulResumeState::SafeRetreat { cr_mark },
+            CrState::Normal => CarefulResumeState::Normal,
+        }
+    }
+}
+
+/// Observes the Careful Resume phase changes of a path as they happen.
+///
+/// Unlike the `careful_resume_phase_updated` qlog event, observers are called
+/// whether qlog is enabled or not, so that experiment harnesses and metrics
+/// systems can react to transitions in real time.
+///
+/// Set with [`Config::set_cr_phase_observer()`] or
+/// [`Connection::set_cr_phase_observer()`].
+///
+/// [`Config::set_cr_phase_observer()`]: crate::Config::set_cr_phase_observer
+/// [`Connection::set_cr_phase_observer()`]: crate::Connection::set_cr_phase_observer
+pub trait CrPhaseObserver: Send + Sync {
+    /// Called when the phase of a path changes from `old` to `new` because
+    /// of `trigger`, with the pipesize measured so far in bytes.
+    ///
+    /// This is called while the connection processes packets, so it should
+    /// not block.
+    fn on_cr_phase_change(
+        &self, old: CarefulResumeState, new: CarefulResumeState,
+        trigger: CarefulResumeTrigger, pipesize: usize,
+    );
+}
+
+/// How the congestion window may grow during the Careful Resume validating
+/// phase.
+///
+/// On entering the validating phase the congestion window is reduced to the
+/// flightsize. The policy decides what happens to it until the phase ends.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CrValidatingPolicy {
+    /// The congestion window is held at the flightsize. `freeze` in a string
+    /// form.
+    Freeze,
+    /// The congestion window grows with acknowledgements, but not beyond the
+    /// pipesize measured so far. `pipesize` in a string form.
+    PipesizeLimited,
+    /// The congestion controller grows the congestion window from the
+    /// flightsize as it would in slow start (default). `slow_start` in a
+    /// string form.
+    #[default]
+    SlowStart,
+}
+
+impl FromStr for CrValidatingPolicy {
+    type Err = crate::Error;
+
+    /// Converts a string to `CrValidatingPolicy`.
+    ///
+    /// If `name` is not valid, `Error::CongestionControl` is returned.
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name {
+            "freeze" => Ok(CrValidatingPolicy::Freeze),
+            "pipesize" => Ok(CrValidatingPolicy::PipesizeLimited),
+            "slow_start" => Ok(CrValidatingPolicy::SlowStart),
+
+            _ => Err(crate::Error::CongestionControl),
+        }
+    }
+}
+
+/// How the congestion window added by the Careful Resume jump is released
+/// during the unvalidated phase.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CrJumpPacing {
+    /// The jumped window is paced at the rate of the previous connection,
+    /// i.e. its congestion window over its RTT, bounded by its pacing rate
+    /// when known (default). `paced` in a string form.
+    #[default]
+    Paced,
+    /// The jumped window is sent as fast as the congestion window allows.
+    /// `burst` in a string form.
+    Burst,
+}
+
+impl FromStr for CrJumpPacing {
+    type Err = crate::Error;
+
+    /// Converts a string to `CrJumpPacing`.
+    ///
+    /// If `name` is not valid, `Error::CongestionControl` is returned.
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name {
+            "paced" => Ok(CrJumpPacing::Paced),
+            "burst" => Ok(CrJumpPacing::Burst),
+
+            _ => Err(crate::Error::CongestionControl),
+        }
+    }
+}
+
+/// How HyStart++ carries on once the Careful Resume jump completes.
+///
+/// HyStart++ doesn't sample RTTs while the jumped window is unvalidated or
+/// validating, as the congestion window isn't grown by slow start and the
+/// jumped window inflates the RTT.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CrHystartPolicy {
+    /// HyStart++ starts sampling RTTs again, and slow start carries on from
+    /// the validated congestion window (default). `resume` in a string form.
+    #[default]
+    Resume,
+    /// Slow start ends with the jump, and the congestion controller moves
+    /// straight to congestion avoidance. `congestion_avoidance` in a string
+    /// form.
+    CongestionAvoidance,
+}
+
+impl FromStr for CrHystartPolicy {
+    type Err = crate::Error;
+
+    /// Converts a string to `CrHystartPolicy`.
+    ///
+    /// If `name` is not valid, `Error::CongestionControl` is returned.
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name {
+            "resume" => Ok(CrHystartPolicy::Resume),
+            "congestion_avoidance" => Ok(CrHystartPolicy::CongestionAvoidance),
+
+            _ => Err(crate::Error::CongestionControl),
+        }
+    }
+}
+
+/// The slow start threshold set when Careful Resume exits the safe retreat
+/// phase.
+///
+/// How aggressively the sender should carry on after the jumped window
+/// caused congestion is an open question, these policies let it be explored.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CrRetreatPolicy {
+    /// The slow start threshold is set to the pipesize, i.e. the bytes
+    /// delivered since the jump (default). `pipesize` in a string form.
+    #[default]
+    Pipesize,
+    /// The slow start threshold is set to half the pipesize, like a loss in
+    /// congestion avoidance. `half_pipesize` in a string form.
+    HalfPipesize,
+    /// The slow start threshold is set to the pipesize, but no lower than the
+    /// given multiple of the initial congestion window. `iw_floor:N` in a
+    /// string form.
+    InitialWindowFloor(usize),
+    /// The slow start threshold the sender had before the jump is restored,
+    /// so slow start carries on unless it had already ended. `pre_jump` in a
+    /// string form.
+    PreJump,
+}
+
+impl FromStr for CrRetreatPolicy {
+    type Err = crate::Error;
+
+    /// Converts a string to `CrRetreatPolicy`.
+    ///
+    /// If `name` is not valid, `Error::CongestionControl` is returned.
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name {
+            "pipesize" => Ok(CrRetreatPolicy::Pipesize),
+            "half_pipesize" => Ok(CrRetreatPolicy::HalfPipesize),
+            "pre_jump" => Ok(CrRetreatPolicy::PreJump),
+
+            _ => match name.strip_prefix("iw_floor:").map(str::parse) {
+                Some(Ok(iw_multiple)) => Ok(CrRetreatPolicy::InitialWindowFloor(iw_multiple)),
+
+                _ => Err(crate::Error::CongestionControl),
+            },
+        }
+    }
+}
+
+/// When the parameters observed on a path are reported, as [`CREvent`]s.
+///
+/// [`CREvent`]: crate::CREvent
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CrObservePolicy {
+    /// The parameters are reported when they change significantly, with the
+    /// change required getting smaller as time passes since the previous
+    /// report (default). `significant_change` in a string form.
+    #[default]
+    SignificantChange,
+    /// The parameters are reported at a fixed interval, whether they changed
+    /// or not. `periodic` in a string form.
+    Periodic,
+}
+
+impl FromStr for CrObservePolicy {
+    type Err = crate::Error;
+
+    /// Converts a string to `CrObservePolicy`.
+    ///
+    /// If `name` is not valid, `Error::CongestionControl` is returned.
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name {
+            "significant_change" => Ok(CrObservePolicy::SignificantChange),
+            "periodic" => Ok(CrObservePolicy::Periodic),
+
+            _ => Err(crate::Error::CongestionControl),
+        }
+    }
+}
+
+/// The kind of workload the application is running, as reported with
+/// [`Connection::set_cr_workload_hint()`].
+///
+/// [`Connection::set_cr_workload_hint()`]: crate::Connection::set_cr_workload_hint
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CrWorkloadHint {
+    /// Bulk transfers that benefit from the Careful Resume jump (default).
+    #[default]
+    Bulk,
+    /// Latency-critical exchanges, e.g. on control streams, that don't need
+    /// the jump. Careful Resume stays in the reconnaissance phase until the
+    /// workload becomes bulk.
+    Interactive,
+}
+
+/// The time a path spent in each Careful Resume phase.
+///
+/// The time spent in a phase is counted from when the phase was entered to
+/// when it was left, or to the last packet sent or acknowledged while it is
+/// still the current phase.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CrPhaseDurations {
+    /// The time spent in the reconnaissance phase.
+    pub reconnaissance: Duration,
+    /// The time spent in the unvalidated phase.
+    pub unvalidated: Duration,
+    /// The time spent in the validating phase.
+    pub validating: Duration,
+    /// The time spent in the safe retreat phase.
+    pub safe_retreat: Duration,
+}
+
+impl CrPhaseDurations {
+    fn add(&mut self, state: CrState, d: Duration) {
+        match state {
+            CrState::Reconnaissance => self.reconnaissance += d,
+            CrState::Unvalidated(_) => self.unvalidated += d,
+            CrState::Validating(_) => self.validating += d,
+            CrState::SafeRetreat(_) => self.safe_retreat += d,
+            CrState::Normal => (),
+        }
+    }
+}
+
+/// Counts of what Careful Resume did on a path.
+///
+/// Unlike the qlog events, these are kept for the lifetime of the connection
+/// and are cheap to read, e.g. to compare Careful Resume settings between
+/// groups of connections.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CrCounters {
+    /// The number of jumps, i.e. of times the unvalidated phase was entered.
+    pub jumps_attempted: u64,
+    /// The number of jumps that were validated, i.e. after which the normal
+    /// phase was entered once the congestion window was acknowledged.
+    pub jumps_completed: u64,
+    /// The number of jumps after which the normal phase was entered without
+    /// validating the congestion window or retreating, e.g. because the
+    /// application abandoned careful resume, the path changed or the
+    /// validating phase timed out.
+    pub jumps_abandoned: u64,
+    /// The number of times the safe retreat phase was entered.
+    pub retreats: u64,
+    /// The number of times no jump was made because the RTT differed too
+    /// much from the RTT of the previous connection.
+    pub rtt_mismatches: u64,
+    /// The number of bytes sent in the unvalidated phase.
+    pub unvalidated_bytes: u64,
+}
+
+impl CrCounters {
+    fn on_phase_change(&mut self, old: CrState, new: CrState, trigger: CarefulResumeTrigger) {
+        match new {
+            CrState::Unvalidated(_) => self.jumps_attempted += 1,
+            CrState::SafeRetreat(_) => self.retreats += 1,
+            CrState::Normal => match old {
+                CrState::Unvalidated(_) | CrState::Validating(_) if trigger == CarefulResumeTrigger::CrMarkAcknowledged =>
+                    self.jumps_completed += 1,
+                CrState::Unvalidated(_) | CrState::Validating(_) => self.jumps_abandoned += 1,
+                CrState::Reconnaissance if trigger == CarefulResumeTrigger::RttNotValidated =>
+                    self.rtt_mismatches += 1,
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    pub(crate) fn merge(&mut self, other: &CrCounters) {
+        self.jumps_attempted += other.jumps_attempted;
+        self.jumps_completed += other.jumps_completed;
+        self.jumps_abandoned += other.jumps_abandoned;
+        self.retreats += other.retreats;
+        self.rtt_mismatches += other.rtt_mismatches;
+        self.unvalidated_bytes += other.unvalidated_bytes;
+    }
+}
+
+/// The origin of a congestion signal reported from outside the transport
+/// with [`Connection::on_external_congestion()`].
+///
+/// [`Connection::on_external_congestion()`]: crate::Connection::on_external_congestion
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CongestionSignalSource {
+    /// A report from an active queue management scheme on the path, e.g. on
+    /// a router.
+    Aqm,
+    /// Buffer status exported by the link layer, e.g. by a modem or a
+    /// satellite terminal.
+    LinkLayer,
+    /// Any other source.
+    Other,
+}
+
 pub struct Resume {
     trace_id: String,
     enabled: bool,
     cr_state: CrState,
     previous_rtt: Duration,
     previous_cwnd: usize,
+    // Pacing rate of the previous connection in bytes per second, 0 if unknown
+    previous_pacing_rate: u64,
     pipesize: usize,
-
+    // The packets counted in the pipesize, so that a packet reported as
+    // acknowledged more than once is only counted once
+    pipesize_pkts: RangeSet,
+    thresholds: CrThresholds,
+    // The smallest previous congestion window a jump is attempted from
+    min_previous_cwnd: usize,
+    // RTT samples required, and taken, during reconnaissance before jumping
+    min_rtt_samples: usize,
+    rtt_samples: usize,
+    validating_policy: CrValidatingPolicy,
+    jump_pacing: CrJumpPacing,
+    // The slow start threshold set on exiting the safe retreat phase, the
+    // initial window it may be floored at, and the slow start threshold
+    // before the jump
+    retreat_policy: CrRetreatPolicy,
+    initial_window: usize,
+    pre_jump_ssthresh: usize,
+    // Whether the application allows the jump for its current workload
+    workload: CrWorkloadHint,
+    // How long reconnaissance may last before careful resume is abandoned,
+    // zero if unbounded, and when the first packet was sent during it
+    reconnaissance_timeout: Duration,
+    reconnaissance_start: Option<Instant>,
+    // How long the sender may be idle during reconnaissance before the RTT
+    // sample is stale, zero for a multiple of the previous RTT, since when the
+    // sender has had nothing in flight, and whether the RTT sample is stale
+    idle_threshold: Duration,
+    idle_since: Option<Instant>,
+    rtt_stale: bool,
+    // How long the unvalidated and validating phases may last, zero if
+    // unbounded
+    unvalidated_timeout: Duration,
+    validating_timeout: Duration,
+    // How long the unvalidated phase may last in RTTs, zero if unbounded, and
+    // the RTT sample the jump was made with
+    unvalidated_timeout_rtts: u32,
+    jump_rtt: Duration,
+    // Whether the jump may be made once the handshake completes, without
+    // waiting for the 0-RTT packets of the initial window to be acknowledged
+    early_data: bool,
+    // The phase seen by the last `update_phase_clock()` and when, when that
+    // phase was entered, and the time spent in each phase so far
+    phase_clock: Option<(CrState, Instant)>,
+    phase_start: Option<Instant>,
+    phase_durations: CrPhaseDurations,
+    counters: CrCounters,
+    // Whether the sender was app-limited when it last sent a packet
+    app_limited: bool,
+    // Place in the endpoint-wide limit on connections in the Unvalidated phase
+    jump_slot: Option<JumpSlot>,
+    // Whether the jump led to congestion, and the previous parameters still
+    // have to be reported as invalid
+    invalidated: bool,
+    // The congestion window reached by the jump, as a fraction of the
+    // previous congestion window
+    jump_fraction: Option<f64>,
+    // The jump made on entering the unvalidated phase, in bytes
+    last_jump: Option<usize>,
+    // The largest congestion window the sender can use given the flow
+    // control limits of the peer, if known
+    flow_control_limit: Option<usize>,
+    // Whether the jump was reduced to the flow control limit
+    flow_control_limited: bool,
+    // The latest delivery rate sample, in bytes per second, or 0 if unknown
+    delivery_rate: u64,
+    // Whether the jump was capped by the plausibility check
+    plausibility_capped: bool,
+    // Called on every phase change
+    phase_observer: Option<Arc<dyn CrPhaseObserver>>,
+    // Whether the jump was validated, and the time it took has to be
+    // recorded by the next `update_phase_clock()`
+    #[cfg(feature = "metrics")]
+    jump_validated: bool,
+
+    // The phase last reported in a qlog event
     #[cfg(feature = "qlog")]
-    qlog_metrics: QlogMetrics,
+    qlog_state: Option<CrState>,
     #[cfg(feature = "qlog")]
     last_trigger: Option<CarefulResumeTrigger>,
 }
 
+#[cfg(feature = "metrics")]
+impl Drop for Resume {
+    fn drop(&mut self) {
+        if self.enabled {
+            crate::recovery::metrics::leave_phase(self.cr_state);
+        }
+    }
+}
+
 impl std::fmt::Debug for Resume {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "cr_state={:?} ", self.cr_state)?;
         write!(f, "previous_rtt={:?} ", self.previous_rtt)?;
         write!(f, "previous_cwnd={:?} ", self.previous_cwnd)?;
+        write!(f, "previous_pacing_rate={:?} ", self.previous_pacing_rate)?;
         write!(f, "pipesize={:?} ", self.pipesize)?;
 
         Ok(())
@@ -44,29 +502,225 @@ impl std::fmt::Debug for Resume {
 }
 
 impl Resume {
-    pub fn new(trace_id: &str) -> Self {
+    pub fn new(
+        trace_id: &str, min_rtt_samples: usize, validating_policy: CrValidatingPolicy,
+        reconnaissance_timeout: Duration,
+    ) -> Self {
         Self {
             trace_id: trace_id.to_string(),
             enabled: false,
             cr_state: CrState::default(),
             previous_rtt: Duration::ZERO,
             previous_cwnd: 0,
+            previous_pacing_rate: 0,
             pipesize: 0,
+            pipesize_pkts: RangeSet::new(PIPESIZE_PKT_RANGES),
+            thresholds: CrThresholds::default(),
+            min_previous_cwnd: 0,
+            min_rtt_samples,
+            rtt_samples: 0,
+            validating_policy,
+            jump_pacing: CrJumpPacing::default(),
+            retreat_policy: CrRetreatPolicy::default(),
+            initial_window: 0,
+            pre_jump_ssthresh: usize::MAX,
+            workload: CrWorkloadHint::default(),
+            reconnaissance_timeout,
+            reconnaissance_start: None,
+            idle_threshold: Duration::ZERO,
+            idle_since: None,
+            rtt_stale: false,
+            unvalidated_timeout: Duration::ZERO,
+            validating_timeout: Duration::ZERO,
+            unvalidated_timeout_rtts: 0,
+            jump_rtt: Duration::ZERO,
+            early_data: false,
+            phase_clock: None,
+            phase_start: None,
+            phase_durations: CrPhaseDurations::default(),
+            counters: CrCounters::default(),
+            app_limited: false,
+            jump_slot: None,
+            invalidated: false,
+            jump_fraction: None,
+            last_jump: None,
+            flow_control_limit: None,
+            flow_control_limited: false,
+            delivery_rate: 0,
+            plausibility_capped: false,
+            phase_observer: None,
+            #[cfg(feature = "metrics")]
+            jump_validated: false,
 
             #[cfg(feature = "qlog")]
-            qlog_metrics: QlogMetrics::default(),
+            qlog_state: None,
             #[cfg(feature = "qlog")]
             last_trigger: None
         }
     }
 
-    pub fn setup(&mut self, previous_rtt: Duration, previous_cwnd: usize) {
+    pub fn setup(&mut self, previous_rtt: Duration, previous_cwnd: usize, previous_pacing_rate: u64) {
+        #[cfg(feature = "metrics")]
+        if !self.enabled {
+            crate::recovery::metrics::enter_phase(self.cr_state);
+        }
         self.enabled = true;
         self.previous_rtt = previous_rtt;
         self.previous_cwnd = previous_cwnd;
+        self.previous_pacing_rate = previous_pacing_rate;
         trace!("{} careful resume configured", self.trace_id);
     }
 
+    pub fn set_jump_pacing(&mut self, jump_pacing: CrJumpPacing) {
+        self.jump_pacing = jump_pacing;
+    }
+
+    pub fn set_retreat_policy(&mut self, retreat_policy: CrRetreatPolicy, initial_window: usize) {
+        self.retreat_policy = retreat_policy;
+        self.initial_window = initial_window;
+    }
+
+    // Records the slow start threshold the sender had when it jumped
+    pub fn set_pre_jump_ssthresh(&mut self, ssthresh: usize) {
+        self.pre_jump_ssthresh = ssthresh;
+    }
+
+    // Returns the slow start threshold to set on exiting the safe retreat
+    // phase
+    fn retreat_ssthresh(&self) -> usize {
+        match self.retreat_policy {
+            CrRetreatPolicy::Pipesize => self.pipesize,
+            CrRetreatPolicy::HalfPipesize => self.pipesize / 2,
+            CrRetreatPolicy::InitialWindowFloor(iw_multiple) =>
+                self.pipesize.max(self.initial_window.saturating_mul(iw_multiple)),
+            CrRetreatPolicy::PreJump => self.pre_jump_ssthresh,
+        }
+    }
+
+    pub fn pipesize(&self) -> usize {
+        self.pipesize
+    }
+
+    pub fn set_thresholds(&mut self, thresholds: CrThresholds, initial_window: usize) {
+        self.thresholds = thresholds;
+        self.min_previous_cwnd = thresholds.min_previous_cwnd_iw.saturating_mul(initial_window);
+    }
+
+    pub fn set_phase_timeouts(&mut self, unvalidated: Duration, validating: Duration) {
+        self.unvalidated_timeout = unvalidated;
+        self.validating_timeout = validating;
+    }
+
+    pub fn set_unvalidated_timeout_rtts(&mut self, rtts: u32) {
+        self.unvalidated_timeout_rtts = rtts;
+    }
+
+    pub fn set_flow_control_limit(&mut self, limit: Option<usize>) {
+        self.flow_control_limit = limit;
+    }
+
+    pub fn set_delivery_rate(&mut self, rate: u64) {
+        self.delivery_rate = rate;
+    }
+
+    // The largest congestion window the capacity measured during
+    // reconnaissance makes plausible, or None if the check is disabled or
+    // there is no delivery rate sample yet
+    fn plausible_cwnd(&self) -> Option<usize> {
+        if self.thresholds.plausibility_factor == 0.0 || self.delivery_rate == 0 {
+            return None;
+        }
+
+        let capacity = self.delivery_rate as f64 * self.previous_rtt.as_secs_f64();
+
+        Some((capacity * self.thresholds.plausibility_factor) as usize)
+    }
+
+    pub fn set_idle_threshold(&mut self, v: Duration) {
+        self.idle_threshold = v;
+    }
+
+    fn idle_threshold(&self) -> Duration {
+        if self.idle_threshold.is_zero() {
+            self.previous_rtt * IDLE_THRESHOLD_RTTS
+        } else {
+            self.idle_threshold
+        }
+    }
+
+    // Records when the sender had nothing left in flight during
+    // reconnaissance
+    pub fn on_idle(&mut self, now: Instant) {
+        if self.cr_state == CrState::Reconnaissance {
+            self.idle_since.get_or_insert(now);
+        }
+    }
+
+    // Restarts reconnaissance when the sender resumes sending after having
+    // nothing in flight for longer than the idle threshold, as the RTT sample
+    // it would jump with may no longer reflect the path. The jump then waits
+    // for a sample of the packets sent after the idle period, which is
+    // compared with the previous RTT again
+    pub fn check_idle(&mut self, now: Instant) {
+        let idle_since = match self.idle_since.take() {
+            Some(v) => v,
+
+            None => return,
+        };
+
+        let elapsed = now.saturating_duration_since(idle_since);
+
+        if self.cr_state != CrState::Reconnaissance || elapsed < self.idle_threshold() {
+            return;
+        }
+
+        trace!(
+            "{} idle during reconnaissance - waiting for a new RTT sample; idle={:?}",
+            self.trace_id, elapsed
+        );
+
+        self.rtt_stale = true;
+        self.rtt_samples = 0;
+        self.reconnaissance_start = None;
+    }
+
+    pub fn set_early_data(&mut self, v: bool) {
+        self.early_data = v;
+    }
+
+    pub fn early_data(&self) -> bool {
+        self.early_data
+    }
+
+    // The earliest of the unvalidated phase timeouts, or None if the phase is
+    // unbounded
+    fn unvalidated_deadline(&self) -> Option<Duration> {
+        let rtts = Some(self.jump_rtt.saturating_mul(self.unvalidated_timeout_rtts))
+            .filter(|_| self.unvalidated_timeout_rtts > 0);
+        let timeout = Some(self.unvalidated_timeout).filter(|v| !v.is_zero());
+
+        match (rtts, timeout) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    pub fn set_workload_hint(&mut self, workload: CrWorkloadHint) {
+        self.workload = workload;
+    }
+
+    pub fn workload_hint(&self) -> CrWorkloadHint {
+        self.workload
+    }
+
+    pub fn set_jump_limiter(&mut self, limiter: &CrJumpLimiter, local_ip: IpAddr) {
+        self.jump_slot = Some(limiter.slot(local_ip));
+    }
+
+    pub fn set_phase_observer(&mut self, observer: Arc<dyn CrPhaseObserver>) {
+        self.phase_observer = Some(observer);
+    }
+
     pub fn enabled(&self) -> bool {
         if self.enabled {
             self.cr_state != CrState::Normal
@@ -75,9 +729,110 @@ impl Resume {
         }
     }
 
+    // The phase, or `None` if careful resume wasn't set up
+    pub fn state(&self) -> Option<CarefulResumeState> {
+        self.enabled.then(|| self.cr_state.into())
+    }
+
+    // The name of the current phase, or "disabled" when careful resume
+    // wasn't set up
+    pub fn phase_name(&self) -> &'static str {
+        if !self.enabled {
+            return "disabled";
+        }
+
+        match self.cr_state {
+            CrState::Reconnaissance => "reconnaissance",
+            CrState::Unvalidated(_) => "unvalidated",
+            CrState::Validating(_) => "validating",
+            CrState::SafeRetreat(_) => "safe_retreat",
+            CrState::Normal => "normal",
+        }
+    }
+
+    // Whether the jumped window is being validated
+    pub fn in_jump(&self) -> bool {
+        self.enabled && matches!(self.cr_state, CrState::Unvalidated(_) | CrState::Validating(_))
+    }
+
+    pub fn in_unvalidated(&self) -> bool {
+        self.enabled && matches!(self.cr_state, CrState::Unvalidated(_))
+    }
+
+    pub fn in_safe_retreat(&self) -> bool {
+        matches!(self.cr_state, CrState::SafeRetreat(_))
+    }
+
+    pub fn phase_durations(&self) -> CrPhaseDurations {
+        self.phase_durations
+    }
+
+    pub fn counters(&self) -> CrCounters {
+        self.counters
+    }
+
+    // Counts the bytes sent while the jump isn't validated
+    pub fn on_bytes_sent(&mut self, bytes: usize) {
+        if matches!(self.cr_state, CrState::Unvalidated(_)) {
+            self.counters.unvalidated_bytes += bytes as u64;
+        }
+    }
+
+    // Accounts the time since the last call to the phase that was current
+    // then, and notes when a new phase is entered. Phases change while
+    // packets are sent and acknowledged, so this is called along with them
+    pub fn update_phase_clock(&mut self, now: Instant) {
+        if !self.enabled {
+            return;
+        }
+
+        if let Some((state, last)) = self.phase_clock {
+            self.phase_durations.add(state, now.saturating_duration_since(last));
+
+            if state == self.cr_state {
+                self.phase_clock = Some((state, now));
+                return;
+            }
+        }
+
+        #[cfg(feature = "metrics")]
+        if std::mem::take(&mut self.jump_validated) {
+            crate::recovery::metrics::jump_validated(
+                self.phase_durations.unvalidated + self.phase_durations.validating,
+            );
+        }
+
+        self.phase_clock = Some((self.cr_state, now));
+        self.phase_start = Some(now);
+    }
+
+    pub fn on_rtt_sample(&mut self) {
+        if self.cr_state == CrState::Reconnaissance {
+            self.rtt_samples += 1;
+            self.rtt_stale = false;
+        }
+    }
+
     #[inline]
     fn change_state(&mut self, state: CrState, trigger: CarefulResumeTrigger) {
+        let old = self.cr_state;
         self.cr_state = state;
+        if !matches!(state, CrState::Unvalidated(_)) {
+            if let Some(slot) = &mut self.jump_slot {
+                slot.release();
+            }
+        }
+        if let Some(observer) = &self.phase_observer {
+            observer.on_cr_phase_change(old.into(), state.into(), trigger, self.pipesize);
+        }
+        self.counters.on_phase_change(old, state, trigger);
+        #[cfg(feature = "metrics")]
+        if self.enabled {
+            crate::recovery::metrics::leave_phase(old);
+            crate::recovery::metrics::enter_phase(state);
+            self.jump_validated = state == CrState::Normal &&
+                matches!(old, CrState::Unvalidated(_) | CrState::Validating(_));
+        }
         #[cfg(feature = "qlog")] {
             self.last_trigger = Some(trigger);
         }
@@ -87,9 +842,43 @@ impl Resume {
     pub fn process_ack(
         &mut self, largest_pkt_sent: u64, packet: &Acked, flightsize: usize
     ) -> (Option<usize>, Option<usize>) {
+        // A CE mark is a congestion signal for the packet that carried it, so
+        // react to it as to a loss, before the packet is counted in the pipesize
+        if packet.ecn_ce && matches!(
+            self.cr_state,
+            CrState::Reconnaissance | CrState::Unvalidated(_) | CrState::Validating(_)
+        ) {
+            let new_cwnd = self.on_congestion(largest_pkt_sent, CarefulResumeTrigger::EcnCe);
+            return (Some(new_cwnd).filter(|&c| c != 0), None);
+        }
+
+        let counting = matches!(
+            self.cr_state,
+            CrState::Unvalidated(_) | CrState::Validating(_) | CrState::SafeRetreat(_)
+        );
+
+        // The pipesize only grows by the bytes actually delivered, and a
+        // duplicate report has already been handled
+        if counting && !self.count_in_pipesize(packet.pkt_num) {
+            trace!("{} packet {} already counted in the pipesize", self.trace_id, packet.pkt_num);
+            return (None, None);
+        }
+
+        // Retransmitted data may also have been delivered by the packet it
+        // was first sent in, so counting it could overestimate the capacity
+        let delivered = if packet.retransmission {
+            if counting {
+                trace!("{} packet {} is a retransmission, not counted in the pipesize", self.trace_id, packet.pkt_num);
+            }
+
+            0
+        } else {
+            packet.size
+        };
+
         match self.cr_state {
             CrState::Unvalidated(first_packet) => {
-                self.pipesize += packet.size;
+                self.pipesize += delivered;
                 if packet.pkt_num >= first_packet {
                     if flightsize <= self.pipesize {
                         trace!("{} careful resume complete", self.trace_id);
@@ -106,7 +895,7 @@ impl Resume {
                 }
             }
             CrState::Validating(last_packet) => {
-                self.pipesize += packet.size;
+                self.pipesize += delivered;
                 if packet.pkt_num >= last_packet {
                     trace!("{} careful resume complete", self.trace_id);
                     self.change_state(CrState::Normal, CarefulResumeTrigger::CrMarkAcknowledged);
@@ -115,11 +904,15 @@ impl Resume {
             }
             CrState::SafeRetreat(last_packet) => {
                 if packet.pkt_num >= last_packet {
-                    trace!("{} careful resume complete", self.trace_id);
+                    let ssthresh = self.retreat_ssthresh();
+                    trace!(
+                        "{} careful resume complete; pipesize={} ssthresh={} retreat_policy={:?}",
+                        self.trace_id, self.pipesize, ssthresh, self.retreat_policy
+                    );
                     self.change_state(CrState::Normal, CarefulResumeTrigger::ExitRecovery);
-                    (None, Some(self.pipesize))
+                    (None, Some(ssthresh))
                 } else {
-                    self.pipesize += packet.size;
+                    self.pipesize += delivered;
                     (None, None)
                 }
             }
@@ -127,478 +920,3731 @@ impl Resume {
         }
     }
 
-    pub fn send_packet(
-        &mut self, rtt_sample: Option<Duration>, cwnd: usize, largest_pkt_sent: u64, app_limited: bool,
-    ) -> usize {
-        // Do nothing when data limited to avoid having insufficient data
-        // to be able to validate transmission at a higher rate
-        if app_limited {
-            return 0;
+    // Records `pkt_num` as counted in the pipesize. Returns false if it
+    // already was
+    fn count_in_pipesize(&mut self, pkt_num: u64) -> bool {
+        if self.pipesize_pkts.iter().any(|r| r.contains(&pkt_num)) {
+            return false;
         }
 
-        if self.cr_state == CrState::Reconnaissance {
-            let jump = (self.previous_cwnd / 2).saturating_sub(cwnd);
-
-            if jump == 0 {
-                self.change_state(CrState::Normal, CarefulResumeTrigger::CwndLimited);
-                return 0;
-            }
+        self.pipesize_pkts.insert(pkt_num..pkt_num + 1);
+        true
+    }
 
-            let current_rtt = match rtt_sample {
-                Some(s) => s,
-                None => {
-                    // Don't make any decisions until we have an RTT sample
-                    return 0;
-                }
-            };
+    // Bytes acknowledged faster than they were sent are not evidence of path
+    // capacity, so they are not counted in the pipesize. The bytes of the ACK
+    // are credited afterwards by `process_ack()`
+    pub fn on_ack_aggregation(&mut self, excess: usize) {
+        if matches!(self.cr_state, CrState::Unvalidated(_) | CrState::Validating(_) | CrState::SafeRetreat(_)) {
+            self.pipesize = self.pipesize.saturating_sub(excess);
+        }
+    }
 
-            // Confirm RTT is similar to that of the previous connection
-            if current_rtt <= self.previous_rtt / 2 || current_rtt >= self.previous_rtt * 10 {
-                trace!(
-                    "{} current RTT too divergent from previous RTT - not using careful resume; \
-                    rtt_sample={:?} previous_rtt={:?}",
-                    self.trace_id, current_rtt, self.previous_rtt
-                );
-                self.change_state(CrState::Normal, CarefulResumeTrigger::RttNotValidated);
-                return 0;
-            }
+    // Returns the congestion window to use after the congestion controller
+    // grew it from `cwnd_before` to `cwnd_after` while validating
+    pub fn limit_validating_growth(&self, cwnd_before: usize, cwnd_after: usize) -> usize {
+        if !matches!(self.cr_state, CrState::Validating(_)) {
+            return cwnd_after;
+        }
 
-            // Store the first packet number that was sent in the Unvalidated Phase
-            trace!("{} entering careful resume unvalidated phase", self.trace_id);
-            self.change_state(CrState::Unvalidated(largest_pkt_sent), CarefulResumeTrigger::CwndLimited);
-            self.pipesize = cwnd;
-            // we return the jump in window, CC code handles the increase in cwnd
-            return jump;
+        match self.validating_policy {
+            CrValidatingPolicy::Freeze => cwnd_before,
+            CrValidatingPolicy::PipesizeLimited => cwnd_after.min(self.pipesize.max(cwnd_before)),
+            CrValidatingPolicy::SlowStart => cwnd_after,
         }
+    }
 
-        0
+    // The congestion window of the previous connection
+    pub fn previous_cwnd(&self) -> usize {
+        self.previous_cwnd
     }
 
-    pub fn congestion_event(&mut self, largest_pkt_sent: u64) -> usize {
-        match self.cr_state {
-            CrState::Unvalidated(_) => {
-                trace!("{} congestion during unvalidated phase", self.trace_id);
+    // The rate the previous connection delivered at, in bytes per second
+    pub fn previous_rate(&self) -> u64 {
+        (self.previous_cwnd as f64 / self.previous_rtt.as_secs_f64()) as u64
+    }
 
-                // TODO: mark used CR parameters as invalid for future connections
+    // Returns the pacing rate to use instead of `rate`, the one derived from
+    // the current congestion window. While the jumped window is unvalidated it
+    // is released at the rate of the previous connection, or unpaced, with a
+    // rate of 0, when configured to burst. A previous connection that was
+    // ack-clocked well below its congestion window never sent at that rate, so
+    // don't exceed the rate it actually paced at
+    pub fn pacing_rate(&self, rate: u64) -> u64 {
+        if !matches!(self.cr_state, CrState::Unvalidated(_)) {
+            return rate;
+        }
 
-                self.change_state(CrState::SafeRetreat(largest_pkt_sent), CarefulResumeTrigger::PacketLoss);
-                self.pipesize / 2
-            }
-            CrState::Validating(p) => {
+        match self.jump_pacing {
+            CrJumpPacing::Burst => 0,
+
+            CrJumpPacing::Paced => {
+                let previous_rate = self.previous_rate();
+
+                if self.previous_pacing_rate > 0 {
+                    previous_rate.min(self.previous_pacing_rate)
+                } else {
+                    previous_rate
+                }
+            },
+        }
+    }
+
+    // Abandons careful resume when reconnaissance hasn't completed within the
+    // timeout, counted from the first packet sent during it. The trigger tells
+    // whether the sender was app-limited, i.e. the workload never filled the
+    // initial window, or had data to send but the path didn't allow the jump
+    pub fn check_reconnaissance_timeout(&mut self, now: Instant) {
+        if self.cr_state != CrState::Reconnaissance || self.reconnaissance_timeout.is_zero() {
+            return;
+        }
+
+        let start = *self.reconnaissance_start.get_or_insert(now);
+
+        if now.saturating_duration_since(start) < self.reconnaissance_timeout {
+            return;
+        }
+
+        trace!(
+            "{} reconnaissance timed out - abandoning careful resume; app_limited={}",
+            self.trace_id, self.app_limited
+        );
+
+        let trigger = if self.app_limited {
+            CarefulResumeTrigger::AppLimited
+        } else {
+            CarefulResumeTrigger::Timeout
+        };
+
+        self.change_state(CrState::Normal, trigger);
+    }
+
+    // Checks the time limits of the reconnaissance, unvalidated and validating
+    // phases. An unvalidated phase that lasts too long is treated as
+    // congestion, as the jumped window isn't being acknowledged, while a
+    // validating phase that lasts too long ends careful resume with the
+    // window it reached. Returns the new cwnd, or 0 if it should not be
+    // changed
+    pub fn check_phase_timeouts(&mut self, now: Instant, largest_pkt_sent: u64) -> usize {
+        self.update_phase_clock(now);
+        self.check_reconnaissance_timeout(now);
+
+        let start = match self.phase_start {
+            Some(v) => v,
+            None => return 0,
+        };
+
+        let elapsed = now.saturating_duration_since(start);
+
+        let new_cwnd = match self.cr_state {
+            CrState::Unvalidated(_) if self.unvalidated_deadline().map_or(false, |d| elapsed >= d) => {
+                trace!(
+                    "{} unvalidated phase timed out - retreating; elapsed={:?}",
+                    self.trace_id, elapsed
+                );
+
+                self.on_congestion(largest_pkt_sent, CarefulResumeTrigger::Timeout)
+            }
+            CrState::Validating(_) if !self.validating_timeout.is_zero() &&
+                elapsed >= self.validating_timeout =>
+            {
+                trace!(
+                    "{} validating phase timed out - careful resume complete; elapsed={:?}",
+                    self.trace_id, elapsed
+                );
+
+                self.change_state(CrState::Normal, CarefulResumeTrigger::Timeout);
+                0
+            }
+            _ => return 0,
+        };
+
+        self.update_phase_clock(now);
+
+        new_cwnd
+    }
+
+    pub fn send_packet(
+        &mut self, rtt_sample: Option<Duration>, cwnd: usize, largest_pkt_sent: u64, iw_acked: bool,
+        app_limited: bool,
+    ) -> usize {
+        self.app_limited = app_limited;
+
+        // Do nothing when data limited to avoid having insufficient data
+        // to be able to validate transmission at a higher rate
+        if app_limited {
+            return 0;
+        }
+
+        if self.cr_state == CrState::Reconnaissance {
+            if self.previous_cwnd < self.min_previous_cwnd {
+                trace!(
+                    "{} previous cwnd too small - not using careful resume; previous_cwnd={} min_previous_cwnd={}",
+                    self.trace_id, self.previous_cwnd, self.min_previous_cwnd
+                );
+                self.change_state(CrState::Normal, CarefulResumeTrigger::CwndLimited);
+                return 0;
+            }
+
+            let target = (self.previous_cwnd as f64 * self.thresholds.jump_factor) as usize;
+            let jump = target.saturating_sub(cwnd);
+
+            if jump == 0 {
+                self.change_state(CrState::Normal, CarefulResumeTrigger::CwndLimited);
+                return 0;
+            }
+
+            let current_rtt = match rtt_sample {
+                Some(s) => s,
+                None => {
+                    // Don't make any decisions until we have an RTT sample
+                    return 0;
+                }
+            };
+
+            // Nor until all of the initial window has been acknowledged
+            if !iw_acked {
+                return 0;
+            }
+
+            // Nor until enough samples have been taken for the RTT comparison
+            // to be meaningful, having a sample means there is at least one
+            // unless it was taken before the sender went idle
+            if self.rtt_stale || self.rtt_samples.max(1) < self.min_rtt_samples {
+                return 0;
+            }
+
+            // Nor while the application reports an interactive workload, the
+            // RTT is compared once it becomes bulk
+            if self.workload == CrWorkloadHint::Interactive {
+                return 0;
+            }
+
+            // Confirm RTT is similar to that of the previous connection
+            if !self.thresholds.rtt_similar(self.previous_rtt, current_rtt) {
+                trace!(
+                    "{} current RTT too divergent from previous RTT - not using careful resume; \
+                    rtt_sample={:?} previous_rtt={:?}",
+                    self.trace_id, current_rtt, self.previous_rtt
+                );
+                self.change_state(CrState::Normal, CarefulResumeTrigger::RttNotValidated);
+                return 0;
+            }
+
+            // A window beyond what flow control lets the sender put in flight
+            // would only leave it blocked, so the jump is reduced to it. Wait
+            // for more credit when there is none to jump into
+            let jump = match self.flow_control_limit {
+                Some(limit) if cwnd + jump > limit => {
+                    let limited = limit.saturating_sub(cwnd);
+
+                    trace!(
+                        "{} careful resume jump limited by flow control; jump={} limited={}",
+                        self.trace_id, jump, limited
+                    );
+
+                    if limited == 0 {
+                        return 0;
+                    }
+
+                    self.flow_control_limited = true;
+                    limited
+                },
+
+                _ => jump,
+            };
+
+            // Parameters from a much faster path with a coincidentally
+            // similar RTT would jump far above what the initial window was
+            // delivered at, so the jump is capped at a multiple of it
+            let jump = match self.plausible_cwnd() {
+                Some(plausible) if cwnd + jump > plausible => {
+                    let capped = plausible.saturating_sub(cwnd);
+
+                    trace!(
+                        "{} careful resume jump capped by plausibility check; jump={} capped={} delivery_rate={} previous_rtt={:?}",
+                        self.trace_id, jump, capped, self.delivery_rate, self.previous_rtt
+                    );
+
+                    self.plausibility_capped = true;
+
+                    if capped == 0 {
+                        self.change_state(CrState::Normal, CarefulResumeTrigger::CwndLimited);
+                        return 0;
+                    }
+
+                    capped
+                },
+
+                _ => jump,
+            };
+
+            // Wait for a place among the connections jumping through the same
+            // local interface, the RTT is compared again when it is granted
+            if let Some(slot) = &mut self.jump_slot {
+                if !slot.try_acquire() {
+                    trace!("{} careful resume jump queued", self.trace_id);
+                    return 0;
+                }
+            }
+
+            // Record how much of the previous congestion window the jump
+            // actually reaches, as the guardrails above can keep it below the
+            // nominal fraction
+            let jump_fraction = (cwnd + jump) as f64 / self.previous_cwnd as f64;
+            self.jump_fraction = Some(jump_fraction);
+            self.last_jump = Some(jump);
+            self.jump_rtt = current_rtt;
+
+            // Store the first packet number that was sent in the Unvalidated Phase
+            trace!(
+                "{} entering careful resume unvalidated phase; jump={} jump_factor={} jump_fraction={:.3}",
+                self.trace_id, jump, self.thresholds.jump_factor, jump_fraction
+            );
+            self.pipesize = cwnd;
+            self.change_state(CrState::Unvalidated(largest_pkt_sent), CarefulResumeTrigger::CwndLimited);
+            // we return the jump in window, CC code handles the increase in cwnd
+            return jump;
+        }
+
+        0
+    }
+
+    // Compares the previous RTT with the RTT stored with the session, before
+    // any RTT sample is taken. The jump is still only made once a sample
+    // confirms it
+    pub fn check_provisional_rtt(&mut self, rtt: Duration) {
+        if self.cr_state == CrState::Reconnaissance && !self.thresholds.rtt_similar(self.previous_rtt, rtt) {
+            trace!(
+                "{} session RTT too divergent from previous RTT - not using careful resume; \
+                session_rtt={:?} previous_rtt={:?}",
+                self.trace_id, rtt, self.previous_rtt
+            );
+            self.change_state(CrState::Normal, CarefulResumeTrigger::RttNotValidated);
+        }
+    }
+
+    // Skips the jump when the data left to send already fits in the
+    // congestion window, as it would only risk loss without speeding up the
+    // transfer
+    pub fn check_transfer_size(&mut self, remaining: usize, cwnd: usize) {
+        if self.cr_state == CrState::Reconnaissance && remaining <= cwnd {
+            trace!(
+                "{} transfer too small - not using careful resume; remaining={} cwnd={}",
+                self.trace_id, remaining, cwnd
+            );
+            self.change_state(CrState::Normal, CarefulResumeTrigger::TransferTooSmall);
+        }
+    }
+
+    pub fn congestion_event(&mut self, largest_pkt_sent: u64) -> usize {
+        self.on_congestion(largest_pkt_sent, CarefulResumeTrigger::PacketLoss)
+    }
+
+    // A congestion signal from outside the transport is handled as a CE mark
+    pub fn external_congestion_event(&mut self, largest_pkt_sent: u64, source: CongestionSignalSource) -> usize {
+        trace!("{} external congestion signal; source={:?}", self.trace_id, source);
+
+        self.on_congestion(largest_pkt_sent, CarefulResumeTrigger::ExternalCongestion)
+    }
+
+    // A PTO while the jumped window isn't validated suggests the jump was too
+    // large for the path, so it is handled as a loss. In other phases the PTO
+    // is left to the congestion controller.
+    pub fn pto_event(&mut self, largest_pkt_sent: u64) -> usize {
+        match self.cr_state {
+            CrState::Unvalidated(_) | CrState::Validating(_) => {
+                trace!("{} PTO during the jump", self.trace_id);
+
+                self.on_congestion(largest_pkt_sent, CarefulResumeTrigger::PtoExpired)
+            }
+            _ => 0,
+        }
+    }
+
+    // The parameters were validated against the path the connection used, so
+    // careful resume is abandoned when the connection migrates away from it.
+    // A jumped window that wasn't validated yet is reduced to the pipesize.
+    // Returns the new cwnd, or 0 if it should not be changed
+    pub fn on_path_change(&mut self) -> usize {
+        let new_cwnd = self.abandon(CarefulResumeTrigger::PathChange);
+        self.reset(false);
+        new_cwnd
+    }
+
+    // Abandons careful resume at the request of the application
+    pub fn on_abandon(&mut self) -> usize {
+        self.abandon(CarefulResumeTrigger::Abandoned)
+    }
+
+    // Moves to the normal phase whatever the current phase, e.g. when the
+    // path the previous parameters were observed on may no longer be in use.
+    // Returns the pipesize to reduce the congestion window to if the jumped
+    // window wasn't validated yet, or 0 otherwise
+    fn abandon(&mut self, trigger: CarefulResumeTrigger) -> usize {
+        if !self.enabled() {
+            return 0;
+        }
+
+        trace!("{} abandoning careful resume; state={:?} trigger={:?}", self.trace_id, self.cr_state, trigger);
+
+        let new_cwnd = match self.cr_state {
+            CrState::Unvalidated(_) => self.pipesize,
+            _ => 0,
+        };
+
+        self.change_state(CrState::Normal, trigger);
+
+        new_cwnd
+    }
+
+    // Resets careful resume along with the recovery state, e.g. after a Retry,
+    // clearing the marks and samples taken so far. With `preserve_params`, a
+    // careful resume still in progress starts again from the reconnaissance
+    // phase with the previous parameters, otherwise it moves to the normal
+    // phase. Returns the pipesize to reduce the congestion window to if the
+    // jumped window wasn't validated yet, or 0 otherwise
+    pub fn reset(&mut self, preserve_params: bool) -> usize {
+        if !self.enabled {
+            return 0;
+        }
+
+        trace!("{} resetting careful resume; state={:?} preserve_params={}", self.trace_id, self.cr_state, preserve_params);
+
+        let new_cwnd = match self.cr_state {
+            CrState::Unvalidated(_) => self.pipesize,
+            _ => 0,
+        };
+
+        self.pipesize = 0;
+        self.pipesize_pkts = RangeSet::new(PIPESIZE_PKT_RANGES);
+        self.rtt_samples = 0;
+        self.rtt_stale = false;
+        self.idle_since = None;
+        self.reconnaissance_start = None;
+        self.pre_jump_ssthresh = usize::MAX;
+        self.jump_rtt = Duration::ZERO;
+        self.jump_fraction = None;
+        self.last_jump = None;
+        self.flow_control_limited = false;
+        self.plausibility_capped = false;
+
+        let state = if preserve_params && self.cr_state != CrState::Normal {
+            CrState::Reconnaissance
+        } else {
+            CrState::Normal
+        };
+
+        if state != self.cr_state {
+            self.change_state(state, CarefulResumeTrigger::Reset);
+        }
+
+        new_cwnd
+    }
+
+    // The congestion window reached by the jump as a fraction of the previous
+    // congestion window, or None if there was no jump
+    pub fn jump_fraction(&self) -> Option<f64> {
+        self.jump_fraction
+    }
+
+    // The jump made on entering the unvalidated phase, in bytes, or None if
+    // there was no jump
+    pub fn last_jump(&self) -> Option<usize> {
+        self.last_jump
+    }
+
+    // Returns the previous parameters careful resume was set up with, while
+    // it is still enabled
+    pub fn setup_params(&self) -> Option<CREvent> {
+        if !self.enabled() {
+            return None;
+        }
+
+        Some(CREvent {
+            min_rtt: self.previous_rtt,
+            cwnd: self.previous_cwnd,
+            pacing_rate: self.previous_pacing_rate,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        })
+    }
+
+    // Returns the previous parameters once after the jump with them led to
+    // congestion
+    pub fn take_invalid_params(&mut self) -> Option<CREvent> {
+        if !std::mem::take(&mut self.invalidated) {
+            return None;
+        }
+
+        Some(CREvent {
+            min_rtt: self.previous_rtt,
+            cwnd: self.previous_cwnd,
+            pacing_rate: self.previous_pacing_rate,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        })
+    }
+
+    // Returns the new cwnd, or 0 if it should not be changed
+    fn on_congestion(&mut self, largest_pkt_sent: u64, trigger: CarefulResumeTrigger) -> usize {
+        match self.cr_state {
+            CrState::Unvalidated(_) => {
+                trace!("{} congestion during unvalidated phase", self.trace_id);
+
+                // The parameters overestimated the path, so future connections
+                // shouldn't jump with them again
+                self.invalidated = true;
+
+                self.change_state(CrState::SafeRetreat(largest_pkt_sent), trigger);
+                self.pipesize / 2
+            }
+            CrState::Validating(p) => {
                 trace!("{} congestion during validating phase", self.trace_id);
 
-                // TODO: mark used CR parameters as invalid for future connections
+                self.invalidated = true;
+
+                self.change_state(CrState::SafeRetreat(p), trigger);
+                self.pipesize / 2
+            }
+            CrState::Reconnaissance => {
+                trace!("{} congestion during reconnaissance - abandoning careful resume", self.trace_id);
+
+                self.change_state(CrState::Normal, trigger);
+                0
+            }
+            _ => {
+                0
+            }
+        }
+    }
+
+    // Returns a qlog event when the phase changed since the last one. This is
+    // called for every packet sent and received, so nothing is built or
+    // copied until there is an event to log, which is then made from the
+    // current state directly
+    #[cfg(feature = "qlog")]
+    pub fn maybe_qlog(&mut self, cwnd: usize, ssthresh: usize, iw_acked: bool) -> Option<EventData> {
+        if self.qlog_state == Some(self.cr_state) {
+            return None;
+        }
+
+        let old_state = self.qlog_state.replace(self.cr_state);
+
+        Some(self.qlog_phase_updated(old_state, cwnd as u64, ssthresh as u64, iw_acked))
+    }
+
+    #[cfg(feature = "qlog")]
+    fn qlog_phase_updated(
+        &self, old_state: Option<CrState>, cwnd: u64, ssthresh: u64, iw_acked: bool,
+    ) -> EventData {
+        let new_state = self.cr_state;
+
+        let cr_mark = match new_state {
+            CrState::Reconnaissance | CrState::Normal => 0,
+            CrState::Unvalidated(m) | CrState::Validating(m) | CrState::SafeRetreat(m) => m,
+        };
+
+        let state_data = CarefulResumeStateParameters::new(ByteCount(self.pipesize as u64), cr_mark)
+            .with_congestion_window(ByteCount(cwnd))
+            .with_ssthresh(ByteCount(ssthresh))
+            .with_iw_acked(iw_acked)
+            .with_rtt_samples(self.rtt_samples as u64)
+            .with_min_rtt_samples(self.min_rtt_samples as u64)
+            .with_validating_policy(self.validating_policy.into());
+
+        let restored_data = if self.previous_rtt != Duration::ZERO || self.previous_cwnd != 0 {
+            let restored = CarefulResumeRestoredParameters::new(ByteCount(self.previous_cwnd as u64), self.previous_rtt.into());
+
+            let restored = if self.previous_pacing_rate != 0 {
+                restored.with_previous_pacing_rate(self.previous_pacing_rate)
+            } else {
+                restored
+            };
+
+            let restored = restored.with_jump_factor(self.thresholds.jump_factor as f32);
+
+            let restored = match self.jump_fraction {
+                Some(v) => restored.with_jump_fraction(v as f32),
+                None => restored,
+            };
+
+            let restored = match self.last_jump {
+                Some(v) => restored.with_jump(ByteCount(v as u64)),
+                None => restored,
+            };
+
+            let restored = if self.flow_control_limited {
+                restored.with_flow_control_limited(true)
+            } else {
+                restored
+            };
+
+            Some(if self.plausibility_capped {
+                restored.with_plausibility_capped(true)
+            } else {
+                restored
+            })
+        } else {
+            None
+        };
+
+        // The time spent in each phase is summarized once careful resume is
+        // complete
+        let phase_durations = if new_state == CrState::Normal {
+            let d = self.phase_durations;
+
+            Some(CarefulResumePhaseDurations {
+                reconnaissance: d.reconnaissance.into(),
+                unvalidated: d.unvalidated.into(),
+                validating: d.validating.into(),
+                safe_retreat: d.safe_retreat.into(),
+            })
+        } else {
+            None
+        };
+
+        EventData::CarefulResumePhaseUpdated(
+            CarefulResumePhaseUpdated::new(old_state.map(Into::into), new_state.into(), state_data)
+                .with_restored_data(restored_data)
+                .with_trigger(self.last_trigger)
+                .with_phase_durations(phase_durations),
+        )
+    }
+}
+
+// The thresholds that decide whether and how far careful resume jumps. They
+// are fixed by the draft, but can be changed to study their effect
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct CrThresholds {
+    // The current RTT must be above the first and below the second ratio of
+    // the previous RTT
+    pub rtt_ratios: (f64, f64),
+    // The jump brings the congestion window to this fraction of the previous
+    // one
+    pub jump_factor: f64,
+    // The previous congestion window must be at least this many initial
+    // windows for a jump to be attempted
+    pub min_previous_cwnd_iw: usize,
+    // The jump is capped at this multiple of the capacity estimated from the
+    // delivery rate of the initial window over the previous RTT, or not
+    // capped when 0
+    pub plausibility_factor: f64,
+}
+
+impl Default for CrThresholds {
+    fn default() -> Self {
+        CrThresholds {
+            rtt_ratios: (0.5, 10.0),
+            jump_factor: 0.5,
+            min_previous_cwnd_iw: 0,
+            plausibility_factor: 0.0,
+        }
+    }
+}
+
+impl CrThresholds {
+    // Whether `current_rtt` is close enough to `previous_rtt` for the
+    // parameters of the previous connection to apply to the current path
+    pub fn rtt_similar(&self, previous_rtt: Duration, current_rtt: Duration) -> bool {
+        current_rtt > previous_rtt.mul_f64(self.rtt_ratios.0) &&
+            current_rtt < previous_rtt.mul_f64(self.rtt_ratios.1)
+    }
+}
+
+// The thresholds that decide when the parameters observed on a path are
+// reported. The defaults are an initial guess that needs further research
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct CrObserveThresholds {
+    pub policy: CrObservePolicy,
+    // The longest time between two reports with the significant change
+    // policy, and the time between reports with the periodic policy
+    pub max_gap: Duration,
+    // The congestion window must be at least this many initial windows to be
+    // reported
+    pub min_cwnd_iw: usize,
+    // The relative change of the min RTT and of the congestion window that is
+    // significant one second after the previous report. The change needed is
+    // inversely proportional to the time since that report
+    pub rtt_change: f64,
+    pub cwnd_change: f64,
+    // The window over which the min RTT and the max congestion window are
+    // filtered before being compared or reported
+    pub filter_window: Duration,
+}
+
+impl Default for CrObserveThresholds {
+    fn default() -> Self {
+        CrObserveThresholds {
+            policy: CrObservePolicy::SignificantChange,
+            max_gap: Duration::from_secs(60),
+            min_cwnd_iw: 4,
+            rtt_change: 1.0,
+            cwnd_change: 1.0,
+            filter_window: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The reason careful resume parameters provided by the peer were rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrRejectReason {
+    /// The parameters are older than the configured maximum age.
+    Expired,
+    /// The congestion window is above the configured cap.
+    CwndAboveCap,
+    /// The RTT is too different from the RTT measured during the handshake.
+    RttImplausible,
+    /// The parameters were rejected by the policy set on the server.
+    Policy,
+}
+
+/// The congestion controller state a congestion window is sampled in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CwndSampleState {
+    /// The congestion window reflects the capacity of the path.
+    Steady,
+    /// The congestion window was reduced in response to congestion, and
+    /// packets sent before the reduction are still being acknowledged.
+    Recovery,
+}
+
+pub struct CRMetrics {
+    trace_id: String,
+    iw: usize,
+    min_rtt: Duration,
+    cwnd: usize,
+    pacing_rate: u64,
+    last_update: Instant,
+    retreat_export: bool,
+    thresholds: CrObserveThresholds,
+    // Pipesize at the end of safe retreat, waiting to be reported
+    post_congestion_cwnd: Option<usize>,
+    // The samples are filtered so that a transient dip of the RTT or peak of
+    // the congestion window isn't mistaken for the path capacity. The pacing
+    // rate follows the congestion window, so it is filtered the same way
+    min_rtt_filter: Minmax<Duration>,
+    max_cwnd_filter: Minmax<usize>,
+    max_pacing_rate_filter: Minmax<u64>,
+}
+
+impl CRMetrics {
+    pub(crate) fn new(trace_id: &str, iw: usize, retreat_export: bool, thresholds: CrObserveThresholds) -> Self {
+        Self {
+            trace_id: trace_id.to_string(),
+            iw,
+            min_rtt: Duration::ZERO,
+            cwnd: 0,
+            pacing_rate: 0,
+            last_update: Instant::now(),
+            retreat_export,
+            thresholds,
+            post_congestion_cwnd: None,
+            min_rtt_filter: Minmax::new(Duration::ZERO),
+            max_cwnd_filter: Minmax::new(0),
+            max_pacing_rate_filter: Minmax::new(0),
+        }
+    }
+
+    // Adds a sample to the filters, and returns the min RTT, the max
+    // congestion window and the max pacing rate over the filter window
+    fn filter(&mut self, now: Instant, min_rtt: Duration, cwnd: usize, pacing_rate: u64) -> (Duration, usize, u64) {
+        let win = self.thresholds.filter_window;
+
+        let min_rtt = if *self.min_rtt_filter == Duration::ZERO {
+            self.min_rtt_filter.reset(now, min_rtt)
+        } else {
+            self.min_rtt_filter.running_min(win, now, min_rtt)
+        };
+
+        (
+            min_rtt,
+            self.max_cwnd_filter.running_max(win, now, cwnd),
+            self.max_pacing_rate_filter.running_max(win, now, pacing_rate),
+        )
+    }
+
+    // Drops the congestion windows and pacing rates filtered so far, and
+    // restarts the filters from the given samples
+    fn reset_filters(&mut self, now: Instant, cwnd: usize, pacing_rate: u64) {
+        self.max_cwnd_filter.reset(now, cwnd);
+        self.max_pacing_rate_filter.reset(now, pacing_rate);
+    }
+
+    // The pipesize measured during safe retreat is the best capacity estimate
+    // available after congestion, so report it regardless of how it compares
+    // to the last update
+    pub fn on_safe_retreat_exit(&mut self, pipesize: usize) {
+        if self.retreat_export {
+            self.post_congestion_cwnd = Some(pipesize);
+        }
+    }
+
+    // Implementation of the CR observe phase
+    // The pacing rate is reported along with the other parameters, but changes
+    // to it alone don't cause an update, as it is derived from the congestion
+    // window and the RTT
+    // A congestion window sampled during recovery is temporarily reduced, and
+    // would be a pessimistic estimate of the path capacity, so it is skipped
+    // The min RTT and the max congestion window over the filter window are
+    // compared with the last update, rather than the latest samples
+    pub(crate) fn maybe_update(
+        &mut self, new_min_rtt: Duration, new_cwnd: usize, new_pacing_rate: u64,
+        state: CwndSampleState, now: Instant,
+    ) -> Option<CREvent> {
+        // Initial guess at something that might work, needs further research
+        if let Some(cwnd) = self.post_congestion_cwnd.take() {
+            trace!(
+                "{} maybe_update(new_min_rtt={:?}, post_congestion_cwnd={}); updating=true",
+                self.trace_id, new_min_rtt, cwnd
+            );
+
+            self.min_rtt = new_min_rtt;
+            self.cwnd = cwnd;
+            self.pacing_rate = new_pacing_rate;
+            self.last_update = now;
+
+            // Larger windows filtered before congestion overshot the path
+            // capacity
+            self.min_rtt_filter.reset(now, new_min_rtt);
+            self.reset_filters(now, cwnd, new_pacing_rate);
+
+            return Some(CREvent {
+                cwnd,
+                min_rtt: new_min_rtt,
+                pacing_rate: new_pacing_rate,
+                post_congestion: true,
+                max_datagram_size: 0,
+                loss_rate_ppm: 0,
+            });
+        }
+
+        if state == CwndSampleState::Recovery {
+            trace!(
+                "{} maybe_update(new_min_rtt={:?}, new_cwnd={}); skipped in recovery",
+                self.trace_id, new_min_rtt, new_cwnd
+            );
+
+            // The congestion windows filtered before the congestion event
+            // overshot the path capacity
+            self.reset_filters(now, 0, 0);
+
+            return None;
+        }
+
+        let (new_min_rtt, new_cwnd, new_pacing_rate) = self.filter(now, new_min_rtt, new_cwnd, new_pacing_rate);
+
+        let time_since_last_update = now.saturating_duration_since(self.last_update);
+
+        let should_update = if new_cwnd < self.iw * self.thresholds.min_cwnd_iw {
+            false
+        } else if time_since_last_update > self.thresholds.max_gap {
+            true
+        } else if self.thresholds.policy == CrObservePolicy::Periodic {
+            // Nothing was reported yet
+            self.cwnd == 0
+        } else {
+            let secs_since_last_update = time_since_last_update.as_secs_f64();
+            if secs_since_last_update == 0.0 {
+                false
+            } else {
+                let range = 1.0f64 / secs_since_last_update;
+
+                let min_rtt_micros = self.min_rtt.as_micros() as f64;
+                let min_rtt_range_spread = min_rtt_micros * range * self.thresholds.rtt_change;
+                let min_rtt_range_min = min_rtt_micros - min_rtt_range_spread;
+                let min_rtt_range_max = min_rtt_micros + min_rtt_range_spread;
+
+                let cwnd = self.cwnd as f64;
+                let cwnd_range_spread = cwnd * range * self.thresholds.cwnd_change;
+                let cwnd_range_min = cwnd - cwnd_range_spread;
+                let cwnd_range_max = cwnd + cwnd_range_spread;
+
+                let new_min_rtt_micros = new_min_rtt.as_micros() as f64;
+                let new_cwnd_float = new_cwnd as f64;
+
+                new_min_rtt_micros < min_rtt_range_min || new_min_rtt_micros > min_rtt_range_max ||
+                    new_cwnd_float < cwnd_range_min || new_cwnd_float > cwnd_range_max
+            }
+        };
+
+        trace!(
+            "{} maybe_update(new_min_rtt={:?}, new_cwnd={}); updating={}",
+            self.trace_id, new_min_rtt, new_cwnd, should_update
+        );
+
+        if should_update {
+            self.min_rtt = new_min_rtt;
+            self.cwnd = new_cwnd;
+            self.pacing_rate = new_pacing_rate;
+            self.last_update = now;
+
+            Some(CREvent {
+                cwnd: new_cwnd,
+                min_rtt: new_min_rtt,
+                pacing_rate: new_pacing_rate,
+                post_congestion: false,
+                max_datagram_size: 0,
+                loss_rate_ppm: 0,
+            })
+        } else {
+            None
+        }
+    }
+
+    // Reports the latest parameters when the connection is closing, whenever
+    // they differ from the last update. The changes held back by the range
+    // check of maybe_update() would otherwise be lost with the connection
+    pub(crate) fn final_update(
+        &mut self, new_min_rtt: Duration, new_cwnd: usize, new_pacing_rate: u64,
+        state: CwndSampleState, now: Instant,
+    ) -> Option<CREvent> {
+        if self.post_congestion_cwnd.is_some() {
+            return self.maybe_update(new_min_rtt, new_cwnd, new_pacing_rate, state, now);
+        }
+
+        if state == CwndSampleState::Recovery {
+            return None;
+        }
+
+        let (new_min_rtt, new_cwnd, new_pacing_rate) = self.filter(now, new_min_rtt, new_cwnd, new_pacing_rate);
+
+        if new_cwnd < self.iw * self.thresholds.min_cwnd_iw {
+            return None;
+        }
+
+        if new_min_rtt == self.min_rtt && new_cwnd == self.cwnd {
+            return None;
+        }
+
+        trace!(
+            "{} final_update(new_min_rtt={:?}, new_cwnd={})",
+            self.trace_id, new_min_rtt, new_cwnd
+        );
+
+        self.min_rtt = new_min_rtt;
+        self.cwnd = new_cwnd;
+        self.pacing_rate = new_pacing_rate;
+        self.last_update = now;
+
+        Some(CREvent {
+            cwnd: new_cwnd,
+            min_rtt: new_min_rtt,
+            pacing_rate: new_pacing_rate,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        })
+    }
+}
+
+/// An update in Careful Resume observed parameters to be stored/transmitted for future connections
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CREvent {
+    /// A windowed minimum round-trip-time observation
+    pub min_rtt: Duration,
+    /// The current congestion window, in bytes
+    pub cwnd: usize,
+    /// The current pacing rate, in bytes per second, or 0 if unknown
+    pub pacing_rate: u64,
+    /// Whether `cwnd` is the pipesize measured when leaving safe retreat,
+    /// rather than a congestion window observed without congestion
+    pub post_congestion: bool,
+    /// The maximum datagram size `cwnd` was measured with, in bytes, or 0 if
+    /// unknown
+    pub max_datagram_size: usize,
+    /// The fraction of the bytes sent that were lost, in parts per million
+    pub loss_rate_ppm: u32,
+}
+
+const CR_EVENT_VERSION: u8 = 1;
+
+// The largest value of a varint, that larger fields are clamped to
+const CR_EVENT_MAX_VARINT: u64 = (1 << 62) - 1;
+
+const CR_EVENT_POST_CONGESTION: u8 = 0x01;
+
+impl CREvent {
+    /// Returns the congestion window to resume with on a path with the given
+    /// maximum datagram size.
+    ///
+    /// A congestion window is a number of packets as much as a number of
+    /// bytes, so when the datagram size it was measured with is known and
+    /// differs, it is scaled to the same number of packets of the new size,
+    /// e.g. a window learned with 1500 bytes datagrams is reduced on a path
+    /// limited to 1280 bytes.
+    pub fn cwnd_for_datagram_size(&self, max_datagram_size: usize) -> usize {
+        if self.max_datagram_size == 0 ||
+            max_datagram_size == 0 ||
+            self.max_datagram_size == max_datagram_size
+        {
+            return self.cwnd;
+        }
+
+        (self.cwnd as u128 * max_datagram_size as u128 /
+            self.max_datagram_size as u128) as usize
+    }
+
+    /// Serializes the parameters, as observed on a path to `endpoint` and
+    /// stored at `stored_at`.
+    ///
+    /// This is the format shared by the careful resume stores and the
+    /// stateless blobs, and can be used by applications that keep or
+    /// transmit the parameters themselves. `endpoint` identifies the peer
+    /// the parameters apply to, e.g. a store key or an IP address.
+    ///
+    /// ```text
+    /// CR Event {
+    ///   Version (8) = 1,
+    ///   Endpoint Length (i),
+    ///   Endpoint (..),         // UTF-8
+    ///   Stored At (i),         // seconds since the UNIX epoch
+    ///   Min RTT (i),           // microseconds
+    ///   Congestion Window (i), // bytes
+    ///   Pacing Rate (i),       // bytes per second, 0 if unknown
+    ///   Max Datagram Size (i), // bytes, 0 if unknown
+    ///   Loss Rate (i),         // parts per million
+    ///   Flags (8),             // 0x01 if post congestion
+    /// }
+    /// ```
+    ///
+    /// Values that don't fit in a varint, i.e. above 2^62-1, are clamped to
+    /// the largest varint.
+    pub fn to_bytes(&self, endpoint: &str, stored_at: SystemTime) -> Vec<u8> {
+        let varint = |v: u128| v.min(u128::from(CR_EVENT_MAX_VARINT)) as u64;
+
+        let stored_at = stored_at.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        let mut out = vec![0; endpoint.len() + 8 * 7 + 2];
+        let mut b = octets::OctetsMut::with_slice(&mut out);
+
+        // Writes can't fail: the buffer is large enough for all fields, and
+        // every value written as a varint is clamped to the varint range.
+        b.put_u8(CR_EVENT_VERSION).unwrap();
+        b.put_varint(endpoint.len() as u64).unwrap();
+        b.put_bytes(endpoint.as_bytes()).unwrap();
+        b.put_varint(varint(stored_at.into())).unwrap();
+        b.put_varint(varint(self.min_rtt.as_micros())).unwrap();
+        b.put_varint(varint(self.cwnd as u128)).unwrap();
+        b.put_varint(varint(self.pacing_rate.into())).unwrap();
+        b.put_varint(varint(self.max_datagram_size as u128)).unwrap();
+        b.put_varint(u64::from(self.loss_rate_ppm)).unwrap();
+
+        let flags = if self.post_congestion { CR_EVENT_POST_CONGESTION } else { 0 };
+        b.put_u8(flags).unwrap();
+
+        let len = b.off();
+        out.truncate(len);
+
+        out
+    }
+
+    /// Parses parameters serialized with [`to_bytes()`].
+    ///
+    /// Returns [`UnknownVersion`] for another version of the format,
+    /// [`BufferTooShort`] when `buf` is truncated, and [`CongestionControl`]
+    /// when the parameters are invalid, e.g. a zero congestion window or
+    /// RTT. Whether the parameters are recent enough, or apply to the
+    /// expected endpoint, is left to the caller.
+    ///
+    /// [`to_bytes()`]: struct.CREvent.html#method.to_bytes
+    /// [`UnknownVersion`]: enum.Error.html#variant.UnknownVersion
+    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
+    /// [`CongestionControl`]: enum.Error.html#variant.CongestionControl
+    pub fn from_bytes(buf: &[u8]) -> crate::Result<StoredCREvent> {
+        let mut b = octets::Octets::with_slice(buf);
+
+        let version = b.get_u8()?;
+
+        if version != CR_EVENT_VERSION {
+            return Err(crate::Error::UnknownVersion);
+        }
+
+        let endpoint = b.get_bytes_with_varint_length()?.to_vec();
+        let endpoint = String::from_utf8(endpoint).map_err(|_| crate::Error::CongestionControl)?;
+
+        let stored_at = b.get_varint()?;
+        let min_rtt = b.get_varint()?;
+        let cwnd = b.get_varint()?;
+        let pacing_rate = b.get_varint()?;
+        let max_datagram_size = b.get_varint()?;
+        let loss_rate_ppm = b.get_varint()?;
+
+        let flags = b.get_u8()?;
+
+        if b.cap() > 0 || min_rtt == 0 || cwnd == 0 || flags & !CR_EVENT_POST_CONGESTION != 0 ||
+            loss_rate_ppm > 1_000_000
+        {
+            return Err(crate::Error::CongestionControl);
+        }
+
+        let stored_at = SystemTime::UNIX_EPOCH
+            .checked_add(Duration::from_secs(stored_at))
+            .ok_or(crate::Error::CongestionControl)?;
+
+        Ok(StoredCREvent {
+            params: CREvent {
+                min_rtt: Duration::from_micros(min_rtt),
+                cwnd: cwnd as usize,
+                pacing_rate,
+                post_congestion: flags & CR_EVENT_POST_CONGESTION != 0,
+                max_datagram_size: max_datagram_size as usize,
+                loss_rate_ppm: loss_rate_ppm as u32,
+            },
+            endpoint,
+            stored_at,
+        })
+    }
+}
+
+/// Careful Resume parameters parsed by [`CREvent::from_bytes()`], with the
+/// endpoint they apply to and when they were stored.
+///
+/// [`CREvent::from_bytes()`]: struct.CREvent.html#method.from_bytes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoredCREvent {
+    /// The parameters
+    pub params: CREvent,
+    /// The peer the parameters apply to
+    pub endpoint: String,
+    /// When the parameters were stored, with a resolution of one second
+    pub stored_at: SystemTime,
+}
+
+/// Careful Resume parameters carried in a BDP frame.
+///
+/// A server sends the parameters it observed on a connection to the client in
+/// BDP frames. The client echoes the last one back early in its next
+/// connection, so that the server can resume without storing any state.
+///
+/// The parameters are in clear for the client, and also sealed in the token,
+/// which is the only part of an echoed frame the server trusts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BdpFrame {
+    /// How long the parameters may be used for after they were sent
+    pub lifetime: Duration,
+    /// The congestion window observed by the sender, in bytes
+    pub saved_capacity: usize,
+    /// The minimum round-trip-time observed by the sender
+    pub saved_rtt: Duration,
+    /// The parameters and the time they were sent, sealed by the server
+    pub token: Vec<u8>,
+}
+
+impl BdpFrame {
+    pub(crate) fn new(event: &CREvent, lifetime: Duration, token: Vec<u8>) -> Self {
+        BdpFrame {
+            lifetime,
+            saved_capacity: event.cwnd,
+            saved_rtt: event.min_rtt,
+            token,
+        }
+    }
+}
+
+#[cfg(feature = "qlog")]
+impl From<CrState> for CarefulResumePhase {
+    fn from(state: CrState) -> Self {
+        match state {
+            CrState::Reconnaissance => CarefulResumePhase::Reconnaissance,
+            CrState::Unvalidated(_) => CarefulResumePhase::Unvalidated,
+            CrState::Validating(_) => CarefulResumePhase::Validating,
+            CrState::SafeRetreat(_) => CarefulResumePhase::SafeRetreat,
+            CrState::Normal => CarefulResumePhase::Normal,
+        }
+    }
+}
+
+#[cfg(feature = "qlog")]
+impl From<CrValidatingPolicy> for CarefulResumeValidatingPolicy {
+    fn from(policy: CrValidatingPolicy) -> Self {
+        match policy {
+            CrValidatingPolicy::Freeze => CarefulResumeValidatingPolicy::Freeze,
+            CrValidatingPolicy::PipesizeLimited => CarefulResumeValidatingPolicy::PipesizeLimited,
+            CrValidatingPolicy::SlowStart => CarefulResumeValidatingPolicy::SlowStart,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::smallvec;
+    use crate::{CongestionControlAlgorithm, packet, ranges};
+    use crate::recovery::{CongestionSignalSource, HandshakeStatus, Recovery, Sent};
+    use super::*;
+    use qlog::units::Milliseconds;
+
+    // for cwnd > jump window, check crstate moves to normal
+    #[test]
+    fn cwnd_larger_than_jump() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.send_packet(Some(Duration::from_millis(50)), 45_000, 50, true, false);
+
+        assert_eq!(r.cr_state, CrState::Normal);
+    }
+
+    // for a set rtt that does not meet the conditions, check crstate moves to normal
+    #[test]
+    fn rtt_less_than_half() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.send_packet(Some(Duration::from_millis(10)), 30_000, 10, true, false);
+
+        assert_eq!(r.cr_state, CrState::Normal);
+    }
+
+    #[test]
+    fn rtt_greater_than_10() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.send_packet(Some(Duration::from_millis(600)), 30_000, 10, true, false);
+
+        assert_eq!(r.cr_state, CrState::Normal);
+    }
+
+    // the RTT similarity check keeps its bounds with data center RTTs
+    #[test]
+    fn sub_millisecond_rtt() {
+        let previous_rtt = Duration::from_micros(50);
+
+        for (rtt, similar) in [(25, false), (26, true), (60, true), (499, true), (500, false)] {
+            assert_eq!(
+                CrThresholds::default().rtt_similar(previous_rtt, Duration::from_micros(rtt)),
+                similar, "rtt={}µs", rtt
+            );
+        }
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(previous_rtt, 80_000, 0);
+        let jump = r.send_packet(Some(Duration::from_micros(60)), 20_500, 20, true, false);
+        assert_eq!(jump, 19_500);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(previous_rtt, 80_000, 0);
+        r.send_packet(Some(Duration::from_micros(20)), 20_500, 20, true, false);
+        assert_eq!(r.cr_state, CrState::Normal);
+    }
+
+    // for a set rtt that meets the conditions and assuming cwnd = jump window already, check we move to unvalidated
+    // the RTT bounds, the jump factor and the minimum previous window can be
+    // changed from those of the draft
+    #[test]
+    fn thresholds() {
+        let thresholds = CrThresholds {
+            rtt_ratios: (0.8, 1.25),
+            jump_factor: 0.25,
+            min_previous_cwnd_iw: 10,
+            plausibility_factor: 0.0,
+        };
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 120_000, 0);
+        r.set_thresholds(thresholds, 12_000);
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_000, 20, true, false), 10_000);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+
+        // Outside of the narrower RTT bounds
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 120_000, 0);
+        r.set_thresholds(thresholds, 12_000);
+        assert_eq!(r.send_packet(Some(Duration::from_millis(63)), 20_000, 20, true, false), 0);
+        assert_eq!(r.cr_state, CrState::Normal);
+
+        // Below the minimum previous window, even though the jump would grow
+        // the window
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 100_000, 0);
+        r.set_thresholds(thresholds, 12_000);
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 12_000, 20, true, false), 0);
+        assert_eq!(r.cr_state, CrState::Normal);
+        #[cfg(feature = "qlog")]
+        assert_eq!(r.last_trigger, Some(CarefulResumeTrigger::CwndLimited));
+    }
+
+    // the jump factor sets how much of the previous window is jumped to, and
+    // is logged along with the restored parameters
+    #[test]
+    fn jump_factor() {
+        let thresholds = CrThresholds {
+            jump_factor: 0.75,
+            ..CrThresholds::default()
+        };
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 120_000, 0);
+        r.set_thresholds(thresholds, 12_000);
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_000, 20, true, false), 70_000);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+        assert_eq!(r.jump_fraction(), Some(0.75));
+
+        #[cfg(feature = "qlog")]
+        match r.maybe_qlog(90_000, usize::MAX, true) {
+            Some(EventData::CarefulResumePhaseUpdated(ev)) => {
+                let restored = ev.restored_data.unwrap();
+                assert_eq!(restored.jump_factor, Some(0.75));
+                assert_eq!(restored.jump_fraction, Some(0.75));
+            }
+            _ => panic!("expected a phase update"),
+        }
+    }
+
+    // a phase update is logged once per phase, whatever else changed since
+    #[cfg(feature = "qlog")]
+    #[test]
+    fn qlog_once_per_phase() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+
+        assert!(r.maybe_qlog(12_000, usize::MAX, false).is_some());
+        assert!(r.maybe_qlog(14_000, usize::MAX, true).is_none());
+
+        r.send_packet(Some(Duration::from_millis(60)), 20_000, 20, true, false);
+        r.pipesize = 22_000;
+
+        let Some(EventData::CarefulResumePhaseUpdated(ev)) = r.maybe_qlog(40_000, usize::MAX, true) else {
+            panic!("expected a phase update");
+        };
+        assert_eq!(ev.old, Some(CarefulResumePhase::Reconnaissance));
+        assert_eq!(ev.new, CarefulResumePhase::Unvalidated);
+        assert_eq!(ev.state_data.pipesize, ByteCount(22_000));
+        assert_eq!(ev.state_data.congestion_window, Some(ByteCount(40_000)));
+
+        r.pipesize = 24_000;
+        assert!(r.maybe_qlog(42_000, usize::MAX, true).is_none());
+    }
+
+    #[test]
+    fn valid_rtt() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        let jump = r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false);
+        assert_eq!(jump, 19_500);
+
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+        assert_eq!(r.pipesize, 20_500);
+    }
+
+    // the jumped window is paced at the rate of the previous connection, bounded by its pacing rate if known
+    #[test]
+    fn pacing_rate() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 500_000);
+        assert_eq!(r.pacing_rate(2_000_000), 2_000_000);
+
+        r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+        assert_eq!(r.pacing_rate(2_000_000), 500_000);
+        assert_eq!(r.pacing_rate(400_000), 500_000);
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+        assert_eq!(r.pacing_rate(2_000_000), 1_600_000);
+
+        // Once validating, pacing is derived from the congestion window again
+        r.change_state(CrState::Validating(30), CarefulResumeTrigger::CrMarkAcknowledged);
+        assert_eq!(r.pacing_rate(2_000_000), 2_000_000);
+    }
+
+    // the jumped window isn't paced when configured to burst
+    #[test]
+    fn pacing_rate_burst() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.set_jump_pacing(CrJumpPacing::Burst);
+        r.setup(Duration::from_millis(50), 80_000, 500_000);
+        assert_eq!(r.pacing_rate(2_000_000), 2_000_000);
+
+        r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+        assert_eq!(r.pacing_rate(2_000_000), 0);
+    }
+
+    // no jump is made while the workload is interactive
+    #[test]
+    fn workload_hint() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.set_workload_hint(CrWorkloadHint::Interactive);
+
+        let jump = r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false);
+        assert_eq!(jump, 0);
+        assert_eq!(r.cr_state, CrState::Reconnaissance);
+
+        r.set_workload_hint(CrWorkloadHint::Bulk);
+
+        let jump = r.send_packet(Some(Duration::from_millis(60)), 20_500, 21, true, false);
+        assert_eq!(jump, 19_500);
+        assert_eq!(r.cr_state, CrState::Unvalidated(21));
+    }
+
+    // the workload hint set by the application survives a reconfiguration
+    #[test]
+    fn workload_hint_reconfigure() {
+        let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(p, packet::Epoch::Application, HandshakeStatus::default(), now, "");
+        };
+
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+        r.set_cr_workload_hint(CrWorkloadHint::Interactive);
+
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+        r.reconfigure(&crate::recovery::RecoveryConfig::from_config(&cfg), "");
+        assert_eq!(r.congestion.resume.workload_hint(), CrWorkloadHint::Interactive);
+
+        for i in 0..5 {
+            send(&mut r, i, now);
+        }
+
+        now += Duration::from_millis(50);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..5);
+        r.on_ack_received(&acked, 25, None, packet::Epoch::Application, HandshakeStatus::default(), now, "")
+            .unwrap();
+
+        // The window is full, but the jump is still held back
+        for i in 5..17 {
+            send(&mut r, i, now);
+        }
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+        assert_eq!(r.cr_last_jump(), None);
+
+        r.set_cr_workload_hint(CrWorkloadHint::Bulk);
+        send(&mut r, 17, now);
+
+        assert!(matches!(r.congestion.resume.cr_state, CrState::Unvalidated(_)));
+        assert!(r.cr_last_jump().is_some());
+    }
+
+    // no jump is made until the initial window has been acknowledged
+    #[test]
+    fn iw_not_acked() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        let jump = r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, false, false);
+        assert_eq!(jump, 0);
+
+        assert_eq!(r.cr_state, CrState::Reconnaissance);
+    }
+
+    // no jump is made until the configured number of RTT samples were taken
+    #[test]
+    fn min_rtt_samples() {
+        let mut r = Resume::new("", 3, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+
+        for _ in 0..2 {
+            r.on_rtt_sample();
+            let jump = r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false);
+            assert_eq!(jump, 0);
+            assert_eq!(r.cr_state, CrState::Reconnaissance);
+        }
+
+        r.on_rtt_sample();
+        let jump = r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false);
+        assert_eq!(jump, 19_500);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+
+        // Samples are no longer counted once reconnaissance is over
+        r.on_rtt_sample();
+        assert_eq!(r.rtt_samples, 3);
+
+        #[cfg(feature = "qlog")]
+        {
+            let Some(EventData::CarefulResumePhaseUpdated(ev)) = r.maybe_qlog(40_000, usize::MAX, true) else {
+                panic!("no phase update");
+            };
+            assert_eq!(ev.state_data.rtt_samples, Some(3));
+            assert_eq!(ev.state_data.min_rtt_samples, Some(3));
+        }
+    }
+
+    // reconnaissance is abandoned after the timeout, with a trigger telling
+    // whether the sender was app-limited
+    #[test]
+    fn reconnaissance_timeout() {
+        let timeout = Duration::from_millis(500);
+        let start = Instant::now();
+
+        for (app_limited, trigger) in [(true, CarefulResumeTrigger::AppLimited), (false, CarefulResumeTrigger::Timeout)] {
+            let mut r = Resume::new("", 1, CrValidatingPolicy::default(), timeout);
+            r.setup(Duration::from_millis(50), 80_000, 0);
+
+            r.check_reconnaissance_timeout(start);
+            r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, false, app_limited);
+
+            r.check_reconnaissance_timeout(start + timeout - Duration::from_millis(1));
+            assert_eq!(r.cr_state, CrState::Reconnaissance);
+
+            r.check_reconnaissance_timeout(start + timeout);
+            assert_eq!(r.cr_state, CrState::Normal);
+            #[cfg(feature = "qlog")]
+            assert_eq!(r.last_trigger, Some(trigger));
+
+            // No jump once abandoned
+            assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_500, 21, true, false), 0);
+        }
+
+        // The timeout doesn't apply once the jump was made, nor when disabled
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), timeout);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.check_reconnaissance_timeout(start);
+        r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false);
+        r.check_reconnaissance_timeout(start + timeout);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.check_reconnaissance_timeout(start);
+        r.check_reconnaissance_timeout(start + Duration::from_secs(3600));
+        assert_eq!(r.cr_state, CrState::Reconnaissance);
+    }
+
+    // the counters accumulate over the phases of the connection
+    #[test]
+    fn counters() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+
+        r.on_bytes_sent(1_200);
+        assert_eq!(r.send_packet(Some(Duration::from_millis(600)), 20_500, 20, true, false), 0);
+        assert_eq!(r.counters(), CrCounters { rtt_mismatches: 1, ..Default::default() });
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false), 19_500);
+        r.on_bytes_sent(1_200);
+        r.on_bytes_sent(1_200);
+        assert_ne!(r.congestion_event(25), 0);
+        assert_eq!(r.cr_state, CrState::SafeRetreat(25));
+
+        // Only the bytes sent while unvalidated are counted
+        r.on_bytes_sent(1_200);
+
+        assert_eq!(r.counters(), CrCounters {
+            jumps_attempted: 1,
+            retreats: 1,
+            unvalidated_bytes: 2_400,
+            ..Default::default()
+        });
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false), 19_500);
+        r.change_state(CrState::Validating(30), CarefulResumeTrigger::CrMarkAcknowledged);
+        r.change_state(CrState::Normal, CarefulResumeTrigger::CrMarkAcknowledged);
+
+        let mut counters = r.counters();
+        assert_eq!(counters, CrCounters { jumps_attempted: 1, jumps_completed: 1, ..Default::default() });
+
+        counters.merge(&r.counters());
+        assert_eq!(counters.jumps_completed, 2);
+
+        // Jumps that end without being validated are not completed
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false), 19_500);
+        r.abandon(CarefulResumeTrigger::Abandoned);
+
+        assert_eq!(r.counters(), CrCounters { jumps_attempted: 1, jumps_abandoned: 1, ..Default::default() });
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false), 19_500);
+        r.change_state(CrState::Validating(30), CarefulResumeTrigger::CrMarkAcknowledged);
+        r.change_state(CrState::Normal, CarefulResumeTrigger::Timeout);
+
+        counters.merge(&r.counters());
+        assert_eq!(counters.jumps_attempted, 3);
+        assert_eq!(counters.jumps_completed, 2);
+        assert_eq!(counters.jumps_abandoned, 1);
+    }
+
+    // a long idle period during reconnaissance makes the jump wait for a new
+    // RTT sample, which is compared with the previous RTT again
+    #[test]
+    fn idle_reconnaissance() {
+        let start = Instant::now();
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.on_rtt_sample();
+
+        // Shorter than 8 previous RTTs
+        r.on_idle(start);
+        r.check_idle(start + Duration::from_millis(399));
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, false, false), 0);
+        assert!(!r.rtt_stale);
+
+        r.on_idle(start);
+        r.check_idle(start + Duration::from_millis(400));
+        assert!(r.rtt_stale);
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false), 0);
+        assert_eq!(r.cr_state, CrState::Reconnaissance);
+
+        // The RTT measured after the idle period no longer matches
+        r.on_rtt_sample();
+        assert_eq!(r.send_packet(Some(Duration::from_millis(600)), 20_500, 21, true, false), 0);
+        assert_eq!(r.cr_state, CrState::Normal);
+
+        // A configured threshold replaces the default
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.set_idle_threshold(Duration::from_secs(2));
+        r.on_rtt_sample();
+
+        r.on_idle(start);
+        r.check_idle(start + Duration::from_secs(1));
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false), 19_500);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+    }
+
+    // an unvalidated phase that lasts too long retreats, a validating phase
+    // that lasts too long completes
+    #[test]
+    fn phase_timeouts() {
+        let timeout = Duration::from_millis(500);
+        let start = Instant::now();
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.set_phase_timeouts(timeout, timeout);
+
+        assert_eq!(r.check_phase_timeouts(start, 19), 0);
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_000, 20, true, false), 20_000);
+
+        // The phase is timed from when it was first seen
+        assert_eq!(r.check_phase_timeouts(start + Duration::from_millis(100), 20), 0);
+        assert_eq!(r.check_phase_timeouts(start + Duration::from_millis(599), 40), 0);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+
+        assert_eq!(r.check_phase_timeouts(start + Duration::from_millis(600), 40), 10_000);
+        assert_eq!(r.cr_state, CrState::SafeRetreat(40));
+        assert!(r.take_invalid_params().is_some());
+        #[cfg(feature = "qlog")]
+        assert_eq!(r.last_trigger, Some(CarefulResumeTrigger::Timeout));
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.set_phase_timeouts(Duration::ZERO, timeout);
+
+        r.change_state(CrState::Validating(35), CarefulResumeTrigger::CrMarkAcknowledged);
+        assert_eq!(r.check_phase_timeouts(start, 40), 0);
+        assert_eq!(r.check_phase_timeouts(start + timeout, 40), 0);
+        assert_eq!(r.cr_state, CrState::Normal);
+        #[cfg(feature = "qlog")]
+        assert_eq!(r.last_trigger, Some(CarefulResumeTrigger::Timeout));
+
+        // The phases are not limited by default
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+
+        r.change_state(CrState::Unvalidated(20), CarefulResumeTrigger::CwndLimited);
+        assert_eq!(r.check_phase_timeouts(start, 20), 0);
+        assert_eq!(r.check_phase_timeouts(start + Duration::from_secs(3600), 40), 0);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+    }
+
+    // the unvalidated phase can be bounded in RTTs of the sample the jump was
+    // made with, and the earliest bound applies
+    #[test]
+    fn unvalidated_timeout_rtts() {
+        let start = Instant::now();
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.set_unvalidated_timeout_rtts(3);
+
+        assert_eq!(r.check_phase_timeouts(start, 19), 0);
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_000, 20, true, false), 20_000);
+
+        assert_eq!(r.check_phase_timeouts(start + Duration::from_millis(10), 20), 0);
+        assert_eq!(r.check_phase_timeouts(start + Duration::from_millis(189), 40), 0);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+
+        assert_eq!(r.check_phase_timeouts(start + Duration::from_millis(190), 40), 10_000);
+        assert_eq!(r.cr_state, CrState::SafeRetreat(40));
+        assert!(r.take_invalid_params().is_some());
+        #[cfg(feature = "qlog")]
+        assert_eq!(r.last_trigger, Some(CarefulResumeTrigger::Timeout));
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.set_unvalidated_timeout_rtts(3);
+        r.set_phase_timeouts(Duration::from_millis(100), Duration::ZERO);
+
+        assert_eq!(r.send_packet(Some(Duration::from_millis(60)), 20_000, 20, true, false), 20_000);
+        assert_eq!(r.check_phase_timeouts(start, 20), 0);
+        assert_eq!(r.check_phase_timeouts(start + Duration::from_millis(100), 40), 10_000);
+        assert_eq!(r.cr_state, CrState::SafeRetreat(40));
+    }
+
+    // the time spent in each phase is accounted, and summarized in qlog once
+    // careful resume is complete
+    #[test]
+    fn phase_durations() {
+        let start = Instant::now();
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+
+        // Nothing is accounted without careful resume
+        r.update_phase_clock(start);
+        r.update_phase_clock(start + Duration::from_millis(100));
+        assert_eq!(r.phase_durations(), CrPhaseDurations::default());
+
+        r.setup(Duration::from_millis(50), 80_000, 0);
+
+        r.update_phase_clock(start);
+        r.send_packet(Some(Duration::from_millis(60)), 20_000, 20, true, false);
+        r.update_phase_clock(start + Duration::from_millis(100));
+
+        r.change_state(CrState::Validating(35), CarefulResumeTrigger::CrMarkAcknowledged);
+        r.update_phase_clock(start + Duration::from_millis(160));
+
+        // The current phase is accounted up to the last update
+        r.update_phase_clock(start + Duration::from_millis(200));
+        assert_eq!(r.phase_durations().validating, Duration::from_millis(40));
+
+        r.change_state(CrState::Normal, CarefulResumeTrigger::CrMarkAcknowledged);
+        r.update_phase_clock(start + Duration::from_millis(250));
+        r.update_phase_clock(start + Duration::from_millis(900));
+
+        assert_eq!(r.phase_durations(), CrPhaseDurations {
+            reconnaissance: Duration::from_millis(100),
+            unvalidated: Duration::from_millis(60),
+            validating: Duration::from_millis(90),
+            safe_retreat: Duration::ZERO,
+        });
+
+        #[cfg(feature = "qlog")]
+        {
+            let Some(EventData::CarefulResumePhaseUpdated(ev)) = r.maybe_qlog(40_000, usize::MAX, true) else {
+                panic!("no phase update");
+            };
+            let d = ev.phase_durations.unwrap();
+            assert_eq!(d.reconnaissance, Milliseconds(100.0));
+            assert_eq!(d.unvalidated, Milliseconds(60.0));
+            assert_eq!(d.validating, Milliseconds(90.0));
+            assert_eq!(d.safe_retreat, Milliseconds(0.0));
+        }
+    }
+
+    // jumps through the same local interface wait for a place in the limiter,
+    // which is given back when the unvalidated phase ends
+    #[test]
+    fn jump_limiter() {
+        let limiter = CrJumpLimiter::new(1);
+        let ip = IpAddr::from([192, 0, 2, 1]);
+        let other_ip = IpAddr::from([192, 0, 2, 2]);
+
+        let new_resume = |ip| {
+            let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+            r.setup(Duration::from_millis(50), 80_000, 0);
+            r.set_jump_limiter(&limiter, ip);
+            r
+        };
+
+        let mut r1 = new_resume(ip);
+        let mut r2 = new_resume(ip);
+        let mut r3 = new_resume(other_ip);
+
+        assert_eq!(r1.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false), 19_500);
+        assert_eq!(r1.cr_state, CrState::Unvalidated(20));
+
+        // The second connection is queued, not the one on another interface
+        assert_eq!(r2.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false), 0);
+        assert_eq!(r2.cr_state, CrState::Reconnaissance);
+        assert_eq!(limiter.queued(ip), 1);
+
+        assert_eq!(r3.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false), 19_500);
+        assert_eq!(limiter.unvalidated(other_ip), 1);
+
+        // Validating frees the place
+        r1.change_state(CrState::Validating(40), CarefulResumeTrigger::CrMarkAcknowledged);
+        assert_eq!(limiter.unvalidated(ip), 0);
+
+        assert_eq!(r2.send_packet(Some(Duration::from_millis(60)), 20_500, 30, true, false), 19_500);
+        assert_eq!(r2.cr_state, CrState::Unvalidated(30));
+        assert_eq!(limiter.unvalidated(ip), 1);
+        assert_eq!(limiter.queued(ip), 0);
+
+        // As does dropping the connection
+        drop(r2);
+        drop(r3);
+        assert_eq!(limiter.unvalidated(ip), 0);
+        assert_eq!(limiter.unvalidated(other_ip), 0);
+    }
+
+    // observers see every phase change, with its trigger and the pipesize
+    #[test]
+    fn phase_observer() {
+        #[derive(Default)]
+        struct Observer(std::sync::Mutex<Vec<(CarefulResumeState, CarefulResumeState, CarefulResumeTrigger, usize)>>);
+
+        impl CrPhaseObserver for Observer {
+            fn on_cr_phase_change(
+                &self, old: CarefulResumeState, new: CarefulResumeState,
+                trigger: CarefulResumeTrigger, pipesize: usize,
+            ) {
+                self.0.lock().unwrap().push((old, new, trigger, pipesize));
+            }
+        }
+
+        let observer = Arc::new(Observer::default());
+
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.set_phase_observer(observer.clone());
+
+        r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+
+        r.change_state(CrState::Normal, CarefulResumeTrigger::Abandoned);
+
+        assert_eq!(*observer.0.lock().unwrap(), vec![
+            (
+                CarefulResumeState::Reconnaissance,
+                CarefulResumeState::Unvalidated { cr_mark: 20 },
+                CarefulResumeTrigger::CwndLimited,
+                20_500,
+            ),
+            (
+                CarefulResumeState::Unvalidated { cr_mark: 20 },
+                CarefulResumeState::Normal,
+                CarefulResumeTrigger::Abandoned,
+                20_500,
+            ),
+        ]);
+    }
+
+    // bytes acknowledged faster than sent are not counted in the pipesize
+    #[test]
+    fn ack_aggregation() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+
+        r.on_ack_aggregation(5_000);
+        assert_eq!(r.pipesize, 0);
+
+        r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, true, false);
+        assert_eq!(r.cr_state, CrState::Unvalidated(20));
+        assert_eq!(r.pipesize, 20_500);
+
+        r.on_ack_aggregation(5_000);
+        assert_eq!(r.pipesize, 15_500);
+    }
+
+    #[test]
+    fn packet_loss_recon() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.congestion_event(20);
+        assert_eq!(r.cr_state, CrState::Normal);
+    }
+
+    // the pipesize at the end of safe retreat is only reported when enabled
+    #[test]
+    fn safe_retreat_export() {
+        let now = Instant::now();
+
+        let mut m = CRMetrics::new("", 12_000, false, CrObserveThresholds::default());
+        m.on_safe_retreat_exit(30_000);
+        assert_eq!(m.maybe_update(Duration::from_millis(50), 20_000, 100_000, CwndSampleState::Steady, now), None);
+
+        let mut m = CRMetrics::new("", 12_000, true, CrObserveThresholds::default());
+        m.on_safe_retreat_exit(30_000);
+        assert_eq!(
+            m.maybe_update(Duration::from_millis(50), 20_000, 100_000, CwndSampleState::Steady, now),
+            Some(CREvent {
+                min_rtt: Duration::from_millis(50),
+                cwnd: 30_000,
+                pacing_rate: 100_000,
+                post_congestion: true,
+                max_datagram_size: 0,
+                loss_rate_ppm: 0,
+            })
+        );
+        assert_eq!(m.maybe_update(Duration::from_millis(50), 20_000, 100_000, CwndSampleState::Steady, now), None);
+    }
+
+    // congestion windows sampled during recovery are not reported
+    #[test]
+    fn recovery_samples_skipped() {
+        let mut m = CRMetrics::new("", 12_000, false, CrObserveThresholds::default());
+        let now = Instant::now() + Duration::from_millis(1);
+        let min_rtt = Duration::from_millis(50);
+
+        assert_eq!(m.maybe_update(min_rtt, 60_000, 0, CwndSampleState::Recovery, now), None);
+        assert_eq!(
+            m.maybe_update(min_rtt, 120_000, 0, CwndSampleState::Steady, now),
+            Some(CREvent {
+                min_rtt,
+                cwnd: 120_000,
+                pacing_rate: 0,
+                post_congestion: false,
+                max_datagram_size: 0,
+                loss_rate_ppm: 0,
+            })
+        );
+    }
+
+    // the final update reports any change held back by the range check, but
+    // still skips small and recovery windows
+    #[test]
+    fn final_update() {
+        let mut m = CRMetrics::new("", 12_000, false, CrObserveThresholds::default());
+        let now = Instant::now() + Duration::from_millis(1);
+        let min_rtt = Duration::from_millis(50);
+
+        assert_eq!(m.final_update(min_rtt, 40_000, 0, CwndSampleState::Steady, now), None);
+
+        assert!(m.maybe_update(min_rtt, 120_000, 0, CwndSampleState::Steady, now).is_some());
+        assert_eq!(m.maybe_update(min_rtt, 121_000, 0, CwndSampleState::Steady, now), None);
+
+        assert_eq!(m.final_update(min_rtt, 130_000, 0, CwndSampleState::Recovery, now), None);
+        assert_eq!(
+            m.final_update(min_rtt, 121_000, 0, CwndSampleState::Steady, now),
+            Some(CREvent {
+                min_rtt,
+                cwnd: 121_000,
+                pacing_rate: 0,
+                post_congestion: false,
+                max_datagram_size: 0,
+                loss_rate_ppm: 0,
+            })
+        );
+        assert_eq!(m.final_update(min_rtt, 121_000, 0, CwndSampleState::Steady, now), None);
+    }
+
+    // the reported parameters are the min RTT, the max congestion window and
+    // the max pacing rate over the filter window, until a congestion event
+    #[test]
+    fn observe_filter() {
+        let thresholds = CrObserveThresholds {
+            policy: CrObservePolicy::Periodic,
+            max_gap: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let mut m = CRMetrics::new("", 12_000, false, thresholds);
+        let mut now = Instant::now();
+        let min_rtt = Duration::from_millis(50);
+
+        assert!(m.maybe_update(min_rtt, 100_000, 2_000_000, CwndSampleState::Steady, now).is_some());
+        assert_eq!(m.maybe_update(min_rtt * 2, 60_000, 600_000, CwndSampleState::Steady, now), None);
+
+        now += Duration::from_millis(15);
+
+        // The dip of the congestion window and the RTT spike are filtered,
+        // along with the pacing rate that follows them
+        assert_eq!(
+            m.maybe_update(min_rtt * 2, 60_000, 600_000, CwndSampleState::Steady, now),
+            Some(CREvent {
+                min_rtt,
+                cwnd: 100_000,
+                pacing_rate: 2_000_000,
+                post_congestion: false,
+                max_datagram_size: 0,
+                loss_rate_ppm: 0,
+            })
+        );
+
+        // Windows filtered before a congestion event are dropped
+        assert_eq!(m.maybe_update(min_rtt, 30_000, 600_000, CwndSampleState::Recovery, now), None);
+        assert_eq!(m.final_update(min_rtt, 60_000, 1_200_000, CwndSampleState::Steady, now), Some(CREvent {
+            min_rtt,
+            cwnd: 60_000,
+            pacing_rate: 1_200_000,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        }));
+    }
+
+    // the periodic policy reports once per gap however much the parameters
+    // change in between
+    #[test]
+    fn observe_periodic() {
+        let thresholds = CrObserveThresholds {
+            policy: CrObservePolicy::Periodic,
+            max_gap: Duration::from_millis(10),
+            min_cwnd_iw: 1,
+            ..Default::default()
+        };
+        let mut m = CRMetrics::new("", 12_000, false, thresholds);
+        let mut now = Instant::now();
+        let min_rtt = Duration::from_millis(50);
+
+        assert_eq!(m.maybe_update(min_rtt, 11_000, 0, CwndSampleState::Steady, now), None);
+        assert!(m.maybe_update(min_rtt, 20_000, 0, CwndSampleState::Steady, now).is_some());
+        assert_eq!(m.maybe_update(min_rtt * 4, 200_000, 0, CwndSampleState::Steady, now), None);
+
+        now += Duration::from_millis(15);
+
+        assert_eq!(
+            m.maybe_update(min_rtt * 4, 200_000, 0, CwndSampleState::Steady, now),
+            Some(CREvent {
+                min_rtt,
+                cwnd: 200_000,
+                pacing_rate: 0,
+                post_congestion: false,
+                max_datagram_size: 0,
+                loss_rate_ppm: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn observe_policy_from_str() {
+        assert_eq!(CrObservePolicy::from_str("significant_change"), Ok(CrObservePolicy::SignificantChange));
+        assert_eq!(CrObservePolicy::from_str("periodic"), Ok(CrObservePolicy::Periodic));
+        assert_eq!(CrObservePolicy::from_str("sometimes"), Err(crate::Error::CongestionControl));
+    }
+
+    #[test]
+    fn no_rtt_sample() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_hystart(true);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+
+        for i in 0..10 {
+            let p = Sent {
+                pkt_num: i as u64,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1200,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
+            assert_eq!(r.bytes_in_flight, 1200 * (i + 1));
+        }
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+    }
+    #[test]
+    fn iw_acked_full() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+        let mut pkt_num = 0;
+        let mut send = |r: &mut Recovery, count: u64, now: Instant| {
+            for _ in 0..count {
+                let p = Sent {
+                    pkt_num,
+                    frames: smallvec![],
+                    time_sent: now,
+                    time_acked: None,
+                    time_lost: None,
+                    size: 1000,
+                    ack_eliciting: true,
+                    in_flight: true,
+                    delivered: 0,
+                    delivered_time: now,
+                    first_sent_time: now,
+                    is_app_limited: false,
+                    tx_in_flight: 0,
+                    lost: 0,
+                    has_data: false,
+                    pmtud: false,
+                    retransmission: false,
+                };
+
+                r.on_packet_sent(
+                    p,
+                    packet::Epoch::Application,
+                    HandshakeStatus::default(),
+                    now,
+                    "",
+                );
+                pkt_num += 1;
+            }
+        };
+
+        // Fill the 12_000 bytes initial window, and then some
+        send(&mut r, 15, now);
+        assert!(!r.initial_window_acked());
+
+        now += Duration::from_millis(50);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..6);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 1000 * 6))
+        );
+
+        // Half of the initial window is still unacknowledged
+        assert!(!r.initial_window_acked());
+
+        // Still waiting even though the CWND is full
+        send(&mut r, 11, now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(6..12);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 1000 * 6))
+        );
+
+        assert!(r.initial_window_acked());
+
+        // Send more than the CWND to not be app limited
+        send(&mut r, 25, now);
+        assert!(matches!(r.congestion.resume.cr_state, CrState::Unvalidated(_)));
+    }
+
+    #[test]
+    fn valid_rtt_full_reno() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_hystart(true);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+
+        for i in 0..5 {
+            let p = Sent {
+                pkt_num: i as u64,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
+            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
+        }
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+
+        now += Duration::from_millis(50);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..5);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 1000 * 5))
+        );
+
+        assert_eq!(r.cwnd(), 12_000);
+        assert_eq!(r.cr_jump_fraction(), None);
+        assert_eq!(r.cr_last_jump(), None);
+
+        // Send significantly more than the CWND to enter app limited
+        for i in 0..16 {
+            let p = Sent {
+                pkt_num: 5 + i as u64,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
+            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
+        }
+
+        assert_eq!(r.cwnd(), 40_000);
+        assert_eq!(r.cr_jump_fraction(), Some(0.5));
+        assert_eq!(r.cr_last_jump(), Some(28_000));
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(15));
+        assert_eq!(r.congestion.resume.pipesize, 12_000);
+    }
+
+
+    #[test]
+    fn pacing_rate_full() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 100_000);
+
+        let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        };
+
+        for i in 0..5 {
+            send(&mut r, i, now);
+        }
+
+        now += Duration::from_millis(50);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..5);
+
+        r.on_ack_received(
+            &acked,
+            25,
+            None,
+            packet::Epoch::Application,
+            HandshakeStatus::default(),
+            now,
+            "",
+        )
+        .unwrap();
+
+        // Reconnaissance paces at the rate derived from cwnd/rtt
+        for i in 5..17 {
+            send(&mut r, i, now);
+        }
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(15));
+        assert!(r.congestion.pacer.rate() > 100_000);
+
+        // Once the jump is made, pacing is bounded by the previous rate
+        send(&mut r, 17, now);
+
+        assert_eq!(r.cwnd(), 40_000);
+        assert_eq!(r.congestion.pacer.rate(), 100_000);
+    }
+
+    // with the burst policy the jumped window is sent without pacing
+    #[test]
+    fn pacing_rate_burst_full() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+        cfg.set_cr_jump_pacing(CrJumpPacing::Burst);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 100_000);
+
+        let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        };
+
+        for i in 0..5 {
+            send(&mut r, i, now);
+        }
+
+        now += Duration::from_millis(50);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..5);
+
+        r.on_ack_received(
+            &acked,
+            25,
+            None,
+            packet::Epoch::Application,
+            HandshakeStatus::default(),
+            now,
+            "",
+        )
+        .unwrap();
+
+        for i in 5..17 {
+            send(&mut r, i, now);
+        }
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(15));
+
+        // The whole jumped window can be sent at once
+        for i in 17..40 {
+            send(&mut r, i, now);
+            assert_eq!(r.get_packet_send_time(), now);
+        }
+
+        assert_eq!(r.cwnd(), 40_000);
+        assert_eq!(r.congestion.pacer.rate(), 0);
+    }
+
+    // reordering during the jump doesn't cause spurious losses, which would
+    // end it, while packets late by more than the reordering window are lost
+    #[test]
+    fn reordering_during_jump() {
+        let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        };
+
+        let ack = |r: &mut Recovery, range: std::ops::Range<u64>, now: Instant| {
+            let mut acked = ranges::RangeSet::default();
+            acked.insert(range);
+
+            r.on_ack_received(
+                &acked,
+                0,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            )
+            .unwrap();
+        };
+
+        // Sends 60 packets after the first RTT, and acks packets 5 to 30 but
+        // packet 20 one RTT later, which leaves careful resume validating the
+        // jump. Returns the recovery and the time of the ACK.
+        let reorder = |resume: bool| {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+            cfg.enable_resume(true);
+
+            let mut r = Recovery::new(&cfg, "");
+            let mut now = Instant::now();
+
+            if resume {
+                r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+            }
+
+            for i in 0..5 {
+                send(&mut r, i, now);
+            }
+
+            now += Duration::from_millis(50);
+            ack(&mut r, 0..5, now);
+
+            for i in 5..60 {
+                send(&mut r, i, now);
+            }
+
+            // Pacing spreads the burst, so leave room for all the packets to
+            // have been sent.
+            now += Duration::from_millis(80);
+            ack(&mut r, 5..20, now);
+            ack(&mut r, 21..30, now);
+
+            (r, now)
+        };
+
+        // Without careful resume, packet 20 is lost by packet threshold.
+        let (r, _) = reorder(false);
+        assert_eq!(r.congestion.lost_count, 1);
+
+        // During the jump it is not, and is acked later.
+        let (mut r, now) = reorder(true);
+        assert_eq!(r.congestion.lost_count, 0);
+        assert!(r.congestion.resume.in_jump());
+
+        ack(&mut r, 20..21, now + Duration::from_millis(5));
+        assert_eq!(r.congestion.lost_count, 0);
+        assert_eq!(r.lost_spurious_count, 0);
+        assert!(r.congestion.resume.in_jump());
+
+        // Unless it is late by more than the reordering window, min_rtt/4.
+        let (mut r, now) = reorder(true);
+        let window = r.cr_reordering_window();
+        assert!(window > Duration::ZERO && window < r.rtt());
+
+        let loss_time =
+            r.epochs[packet::Epoch::Application].loss_time.unwrap();
+        assert!(loss_time > now);
+
+        r.detect_lost_packets(
+            packet::Epoch::Application,
+            loss_time - Duration::from_millis(1),
+            "",
+        );
+        assert_eq!(r.congestion.lost_count, 0);
+
+        r.detect_lost_packets(packet::Epoch::Application, loss_time, "");
+        assert_eq!(r.congestion.lost_count, 1);
+        assert!(matches!(r.congestion.resume.cr_state, CrState::SafeRetreat(_)));
+    }
+
+    #[test]
+    fn sub_millisecond_rtt_full() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_micros(50), 80_000, 0);
+        r.update_max_ack_delay(Duration::from_millis(25));
+
+        let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        };
+
+        for i in 0..5 {
+            send(&mut r, i, now);
+        }
+
+        now += Duration::from_micros(55);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..5);
+
+        // The ack delay is in microseconds
+        r.on_ack_received(
+            &acked,
+            5,
+            None,
+            packet::Epoch::Application,
+            HandshakeStatus::default(),
+            now,
+            "",
+        )
+        .unwrap();
+
+        assert_eq!(r.rtt_stats.latest_adjusted_rtt(), Some(Duration::from_micros(50)));
+        assert_eq!(r.min_rtt(), Some(Duration::from_micros(55)));
+
+        for i in 5..17 {
+            send(&mut r, i, now);
+        }
+
+        assert_eq!(r.cwnd(), 40_000);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(15));
+
+        // RTTs are reported exactly, in milliseconds
+        #[cfg(feature = "qlog")]
+        {
+            let Some(EventData::CarefulResumePhaseUpdated(ev)) = r.maybe_cr_qlog() else {
+                panic!("no phase update");
+            };
+            assert_eq!(ev.restored_data.unwrap().previous_rtt, Milliseconds(0.05));
+
+            let json = serde_json::to_string(&ev.restored_data).unwrap();
+            assert!(json.contains(r#""previous_rtt":0.05"#), "{}", json);
+
+            let Some(EventData::MetricsUpdated(ev)) = r.maybe_qlog(|| true) else {
+                panic!("no metrics update");
+            };
+            assert_eq!(ev.min_rtt, Some(0.055));
+        }
+    }
+
+    #[test]
+    fn valid_rtt_full_cubic() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+        cfg.enable_hystart(true);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+
+        for i in 0..5 {
+            let p = Sent {
+                pkt_num: i as u64,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
+            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
+        }
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+
+        now += Duration::from_millis(50);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..5);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 1000 * 5))
+        );
+
+        // Send significantly more than the CWND to enter app limited
+        for i in 0..16 {
+            let p = Sent {
+                pkt_num: 5 + i as u64,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
+            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
+        }
+
+        assert_eq!(r.cwnd(), 40_000);
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(15));
+        assert_eq!(r.congestion.resume.pipesize, 12_000);
+    }
+    #[test]
+    fn invalid_rtt_full() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+
+        for i in 0..4 {
+            let p = Sent {
+                pkt_num: i as u64,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1200,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
+            assert_eq!(r.bytes_in_flight, 1200 * (i + 1));
+        }
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+
+        now += Duration::from_millis(600);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..4);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 1200 * 4))
+        );
+
+        // Send significantly more than the CWND to enter app limited
+        for i in 0..20 {
+            let p = Sent {
+                pkt_num: 4 + i as u64,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
+            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
+        }
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+    }
+
+    #[test]
+    fn cwnd_larger_than_jump_full() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+
+        for i in 0..37 {
+            let p = Sent {
+                pkt_num: i as u64,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1200,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
+            assert_eq!(r.bytes_in_flight, 1200 * (i + 1));
+        }
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+
+        now += Duration::from_millis(50);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..37);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 1200 * 37))
+        );
+
+        // Send significantly more than the CWND to enter app limited
+        for i in 0..60 {
+            let p = Sent {
+                pkt_num: 37 + i as u64,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1200,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
+            assert_eq!(r.bytes_in_flight, 1200 * (i + 1));
+            assert_eq!(r.congestion.congestion_window, 56_400);
+
+        }
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+    }
+
+    #[test]
+    fn packet_loss_recon_full_reno() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+        cfg.enable_hystart(true);
+
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+
+        for i in 0..10 {
+            let p = Sent {
+                pkt_num: i as u64,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
+            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
+        }
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+
+        now += Duration::from_millis(50);
+
+        // Ack with one missing
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..5);
+        acked.insert(6..9);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((1, 1000, 1000 * 8))
+        );
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+    }
+    #[test]
+    fn packet_loss_recon_full_cubic() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
+        cfg.enable_resume(true);
+        cfg.enable_hystart(true);
+
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+
+        for i in 0..10 {
+            let p = Sent {
+                pkt_num: i as u64,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
+            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
+        }
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+
+        now += Duration::from_millis(50);
+
+        // Ack with one missing
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..5);
+        acked.insert(6..9);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((1, 1000, 1000 * 8))
+        );
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+    }
+
+    #[test]
+    fn pipesize_update_unval() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        let now = Instant::now();
+
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.change_state(CrState::Unvalidated(30), CarefulResumeTrigger::CwndLimited);
+
+        let p = Acked {
+           pkt_num: 29,
+           // To exit from recovery
+           time_sent: now,
+           // More than cur_cwnd to increase cwnd
+           size: 2000,
+           delivered: 0,
+           delivered_time: now,
+           first_sent_time: now,
+           is_app_limited: false,
+           rtt: Duration::ZERO,
+           ecn_ce: false,
+           retransmission: false,
+       };
+        r.process_ack(35, &p, 5_000);
+
+        let p = Acked {
+            pkt_num: 30,
+            // To exit from recovery
+            time_sent: now,
+            // More than cur_cwnd to increase cwnd
+            size: 2000,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            rtt: Duration::ZERO,
+            ecn_ce: false,
+            retransmission: false,
+        };
+        r.process_ack(35, &p, 5_000);
+        assert_eq!(r.pipesize, 4_000);
+        assert_eq!(r.cr_state, CrState::Validating(35));
+
+        // A packet reported as acknowledged again isn't counted again
+        r.process_ack(35, &p, 5_000);
+        assert_eq!(r.pipesize, 4_000);
+    }
+
+    // ACK frames repeating the ranges of previous ones don't grow the
+    // pipesize beyond the bytes delivered
+    #[test]
+    fn duplicate_acks() {
+        let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
 
-                self.change_state(CrState::SafeRetreat(p), CarefulResumeTrigger::PacketLoss);
-                self.pipesize / 2
-            }
-            CrState::Reconnaissance => {
-                trace!("{} congestion during reconnaissance - abandoning careful resume", self.trace_id);
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        };
 
-                self.change_state(CrState::Normal, CarefulResumeTrigger::PacketLoss);
-                0
-            }
-            _ => {
-                0
+        let ack = |r: &mut Recovery, ranges: &[(u64, u64)], now: Instant| {
+            let mut acked = ranges::RangeSet::default();
+            for &(start, end) in ranges {
+                acked.insert(start..end);
             }
-        }
-    }
 
-    #[cfg(feature = "qlog")]
-    pub fn maybe_qlog(&mut self, cwnd: usize, ssthresh: usize) -> Option<EventData> {
-        let qlog_metrics = QlogMetrics {
-            state: Some(self.cr_state),
-            pipesize: self.pipesize as u64,
-            cwnd: cwnd as u64,
-            ssthresh: ssthresh as u64,
-            trigger: self.last_trigger,
-            previous_rtt: self.previous_rtt,
-            previous_cwnd: self.previous_cwnd as u64,
+            r.on_ack_received(
+                &acked,
+                0,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            )
+            .unwrap();
         };
 
-        self.qlog_metrics.maybe_update(qlog_metrics)
-    }
-}
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
 
-pub struct CRMetrics {
-    trace_id: String,
-    iw: usize,
-    min_rtt: Duration,
-    cwnd: usize,
-    last_update: Instant,
-}
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
 
-impl CRMetrics {
-    pub fn new(trace_id: &str, iw: usize) -> Self {
-        Self {
-            trace_id: trace_id.to_string(),
-            iw,
-            min_rtt: Duration::ZERO,
-            cwnd: 0,
-            last_update: Instant::now(),
-        }
-    }
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
 
-    // Implementation of the CR observe phase
-    pub fn maybe_update(&mut self, new_min_rtt: Duration, new_cwnd: usize) -> Option<CREvent> {
-        // Initial guess at something that might work, needs further research
-        let now = Instant::now();
-        let time_since_last_update = now - self.last_update;
+        for i in 0..5 {
+            send(&mut r, i, now);
+        }
 
-        let should_update = if new_cwnd < self.iw * 4 {
-            false
-        } else if time_since_last_update > CR_EVENT_MAXIMUM_GAP {
-            true
-        } else {
-            let secs_since_last_update = time_since_last_update.as_secs_f64();
-            if secs_since_last_update == 0.0 {
-                false
-            } else {
-                let range = 1.0f64 / secs_since_last_update;
+        now += Duration::from_millis(50);
+        ack(&mut r, &[(0, 5)], now);
 
-                let min_rtt_micros = self.min_rtt.as_micros() as f64;
-                let min_rtt_range_spread = min_rtt_micros * range;
-                let min_rtt_range_min = min_rtt_micros - min_rtt_range_spread;
-                let min_rtt_range_max = min_rtt_micros + min_rtt_range_spread;
+        for i in 5..60 {
+            send(&mut r, i, now);
+        }
 
-                let cwnd = self.cwnd as f64;
-                let cwnd_range_spread = cwnd * range;
-                let cwnd_range_min = cwnd - cwnd_range_spread;
-                let cwnd_range_max = cwnd + cwnd_range_spread;
+        now += Duration::from_millis(80);
+        ack(&mut r, &[(5, 20)], now);
+        assert!(r.congestion.resume.in_jump());
 
-                let new_min_rtt_micros = new_min_rtt.as_micros() as f64;
-                let new_cwnd_float = new_cwnd as f64;
+        let pipesize = r.congestion.resume.pipesize;
 
-                new_min_rtt_micros < min_rtt_range_min || new_min_rtt_micros > min_rtt_range_max ||
-                    new_cwnd_float < cwnd_range_min || new_cwnd_float > cwnd_range_max
-            }
-        };
+        // The same ranges again
+        ack(&mut r, &[(5, 20)], now);
+        assert_eq!(r.congestion.resume.pipesize, pipesize);
 
-        trace!(
-            "{} maybe_update(new_min_rtt={:?}, new_cwnd={}); updating={}",
-            self.trace_id, new_min_rtt, new_cwnd, should_update
-        );
+        // Overlapping ranges only count the packets not acknowledged yet
+        ack(&mut r, &[(10, 25)], now + Duration::from_millis(1));
+        assert!(r.congestion.resume.pipesize <= pipesize + 5_000);
 
-        if should_update {
-            self.min_rtt = new_min_rtt;
-            self.cwnd = new_cwnd;
-            self.last_update = now;
+        let pipesize = r.congestion.resume.pipesize;
 
-            Some(CREvent {
-                cwnd: new_cwnd,
-                min_rtt: new_min_rtt,
-            })
-        } else {
-            None
-        }
+        ack(&mut r, &[(0, 3), (5, 25)], now + Duration::from_millis(2));
+        assert_eq!(r.congestion.resume.pipesize, pipesize);
+        assert!(r.congestion.resume.in_jump());
     }
-}
 
-/// An update in Careful Resume observed parameters to be stored/transmitted for future connections
-#[derive(Clone, Copy, Debug)]
-pub struct CREvent {
-    /// A windowed minimum round-trip-time observation
-    pub min_rtt: Duration,
-    /// The current congestion window, in bytes
-    pub cwnd: usize,
-}
+    // a CE mark on an acknowledged packet moves unvalidated to safe retreat
+    #[test]
+    fn ecn_ce_unval() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        let now = Instant::now();
 
-#[derive(Default)]
-#[cfg(feature = "qlog")]
-struct QlogMetrics {
-    state: Option<CrState>,
-    pipesize: u64,
-    cwnd: u64,
-    ssthresh: u64,
-    trigger: Option<CarefulResumeTrigger>,
-    previous_rtt: Duration,
-    previous_cwnd: u64,
-}
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.change_state(CrState::Unvalidated(30), CarefulResumeTrigger::CwndLimited);
+        r.pipesize = 20_000;
 
-#[cfg(feature = "qlog")]
-impl QlogMetrics {
-    fn map_state(state: CrState) -> CarefulResumePhase {
-        match state {
-            CrState::Reconnaissance => CarefulResumePhase::Reconnaissance,
-            CrState::Unvalidated(_) => CarefulResumePhase::Unvalidated,
-            CrState::Validating(_) => CarefulResumePhase::Validating,
-            CrState::SafeRetreat(_) => CarefulResumePhase::SafeRetreat,
-            CrState::Normal => CarefulResumePhase::Normal,
-        }
+        let mut p = Acked {
+            pkt_num: 29,
+            time_sent: now,
+            size: 2000,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            rtt: Duration::ZERO,
+            ecn_ce: false,
+            retransmission: false,
+        };
+        assert_eq!(r.process_ack(35, &p, 30_000), (None, None));
+        assert_eq!(r.pipesize, 22_000);
+
+        p.pkt_num = 30;
+        p.ecn_ce = true;
+        assert_eq!(r.process_ack(35, &p, 30_000), (Some(11_000), None));
+        assert_eq!(r.cr_state, CrState::SafeRetreat(35));
+        #[cfg(feature = "qlog")]
+        assert_eq!(r.last_trigger, Some(CarefulResumeTrigger::EcnCe));
+
+        // Once retreating, further marks are handled as any other ACK
+        p.pkt_num = 31;
+        assert_eq!(r.process_ack(35, &p, 30_000), (None, None));
+        assert_eq!(r.cr_state, CrState::SafeRetreat(35));
     }
 
-    fn map_cr_mark(state: CrState) -> u64 {
-        match state {
-            CrState::Reconnaissance | CrState::Normal => 0,
-            CrState::Unvalidated(m) | CrState::Validating(m) | CrState::SafeRetreat(m) => m,
-        }
-    }
+    // the slow start threshold set on exiting safe retreat depends on the
+    // retreat policy
+    #[test]
+    fn retreat_policy() {
+        let now = Instant::now();
 
-    fn maybe_update(&mut self, latest: Self) -> Option<EventData> {
-        if let Some(new_state) = latest.state {
-            if self.state != Some(new_state) {
-                let old_state = self.state;
-                self.state = Some(new_state);
-                self.pipesize = latest.pipesize;
-                self.trigger = latest.trigger;
-                self.cwnd = latest.cwnd;
-                self.ssthresh = latest.ssthresh;
-                self.previous_rtt = latest.previous_rtt;
-                self.previous_cwnd = latest.previous_cwnd;
-
-                Some(EventData::CarefulResumePhaseUpdated(CarefulResumePhaseUpdated {
-                    old: old_state.map(Self::map_state),
-                    new: Self::map_state(new_state),
-                    state_data: CarefulResumeStateParameters {
-                        pipesize: latest.pipesize,
-                        cr_mark: Self::map_cr_mark(new_state),
-                        congestion_window: Some(latest.cwnd),
-                        ssthresh: Some(latest.ssthresh),
-                    },
-                    restored_data: if latest.previous_rtt != Duration::ZERO || latest.previous_cwnd != 0 {
-                        Some(CarefulResumeRestoredParameters {
-                            previous_congestion_window: latest.previous_cwnd,
-                            previous_rtt: latest.previous_rtt.as_secs_f32() * 1000.0
-                        })
-                    } else {
-                        None
-                    },
-                    trigger: latest.trigger,
-                }))
-            } else {
-                None
-            }
-        } else {
-            None
+        for (policy, ssthresh) in [
+            (CrRetreatPolicy::Pipesize, 20_000),
+            (CrRetreatPolicy::HalfPipesize, 10_000),
+            (CrRetreatPolicy::InitialWindowFloor(1), 20_000),
+            (CrRetreatPolicy::InitialWindowFloor(4), 48_000),
+            (CrRetreatPolicy::PreJump, 30_000),
+        ] {
+            let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+            r.setup(Duration::from_millis(50), 80_000, 0);
+            r.set_retreat_policy(policy, 12_000);
+            r.set_pre_jump_ssthresh(30_000);
+
+            r.change_state(CrState::SafeRetreat(35), CarefulResumeTrigger::PacketLoss);
+            r.pipesize = 18_000;
+
+            let mut p = Acked {
+                pkt_num: 34,
+                time_sent: now,
+                size: 2000,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                rtt: Duration::ZERO,
+                ecn_ce: false,
+                retransmission: false,
+            };
+            assert_eq!(r.process_ack(40, &p, 30_000), (None, None));
+            assert_eq!(r.pipesize, 20_000);
+
+            p.pkt_num = 35;
+            assert_eq!(r.process_ack(40, &p, 30_000), (None, Some(ssthresh)));
+            assert_eq!(r.cr_state, CrState::Normal);
         }
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use smallvec::smallvec;
-    use crate::{CongestionControlAlgorithm, packet, ranges};
-    use crate::recovery::{HandshakeStatus, Recovery, Sent};
-    use super::*;
+        assert_eq!("half_pipesize".parse(), Ok(CrRetreatPolicy::HalfPipesize));
+        assert_eq!("iw_floor:4".parse(), Ok(CrRetreatPolicy::InitialWindowFloor(4)));
+        assert_eq!("pre_jump".parse(), Ok(CrRetreatPolicy::PreJump));
+        assert!("iw_floor".parse::<CrRetreatPolicy>().is_err());
+        assert!("iw_floor:x".parse::<CrRetreatPolicy>().is_err());
+    }
 
-    // for cwnd > jump window, check crstate moves to normal
+    // a CE mark while validating retreats too, to the end of the validating
+    // phase as for a loss
     #[test]
-    fn cwnd_larger_than_jump() {
-        let mut r = Resume::new("");
-        r.setup(Duration::from_millis(50), 80_000);
-        r.send_packet(Some(Duration::from_millis(50)), 45_000, 50, false);
+    fn ecn_ce_validating() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        let now = Instant::now();
 
-        assert_eq!(r.cr_state, CrState::Normal);
-    }
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.change_state(CrState::Validating(40), CarefulResumeTrigger::CrMarkAcknowledged);
+        r.pipesize = 30_000;
 
-    // for a set rtt that does not meet the conditions, check crstate moves to normal
-    #[test]
-    fn rtt_less_than_half() {
-        let mut r = Resume::new("");
-        r.setup(Duration::from_millis(50), 80_000);
-        r.send_packet(Some(Duration::from_millis(10)), 30_000, 10, false);
+        let p = Acked {
+            pkt_num: 35,
+            time_sent: now,
+            size: 2000,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            rtt: Duration::ZERO,
+            ecn_ce: true,
+            retransmission: false,
+        };
+        assert_eq!(r.process_ack(45, &p, 30_000), (Some(15_000), None));
+        assert_eq!(r.cr_state, CrState::SafeRetreat(40));
+        #[cfg(feature = "qlog")]
+        assert_eq!(r.last_trigger, Some(CarefulResumeTrigger::EcnCe));
 
+        // The same mark in reconnaissance abandons careful resume
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        r.setup(Duration::from_millis(50), 80_000, 0);
+
+        assert_eq!(r.process_ack(45, &p, 30_000), (None, None));
         assert_eq!(r.cr_state, CrState::Normal);
+        #[cfg(feature = "qlog")]
+        assert_eq!(r.last_trigger, Some(CarefulResumeTrigger::EcnCe));
     }
 
+    // lost packets retransmitted while validating don't count towards the
+    // pipesize, but still complete the validating phase
     #[test]
-    fn rtt_greater_than_10() {
-        let mut r = Resume::new("");
-        r.setup(Duration::from_millis(50), 80_000);
-        r.send_packet(Some(Duration::from_millis(600)), 30_000, 10, false);
+    fn retransmission_validating() {
+        let mut r = Resume::new("", 1, CrValidatingPolicy::default(), Duration::ZERO);
+        let now = Instant::now();
+
+        r.setup(Duration::from_millis(50), 80_000, 0);
+        r.change_state(CrState::Validating(40), CarefulResumeTrigger::CrMarkAcknowledged);
+        r.pipesize = 30_000;
 
+        let mut p = Acked {
+            pkt_num: 35,
+            time_sent: now,
+            size: 2000,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            rtt: Duration::ZERO,
+            ecn_ce: false,
+            retransmission: false,
+        };
+        assert_eq!(r.process_ack(45, &p, 30_000), (None, None));
+        assert_eq!(r.pipesize, 32_000);
+
+        // Packet 36 carries data lost from an earlier packet
+        p.pkt_num = 36;
+        p.retransmission = true;
+        assert_eq!(r.process_ack(45, &p, 30_000), (None, None));
+        assert_eq!(r.pipesize, 32_000);
+
+        p.pkt_num = 40;
+        assert_eq!(r.process_ack(45, &p, 30_000), (None, None));
+        assert_eq!(r.pipesize, 32_000);
         assert_eq!(r.cr_state, CrState::Normal);
     }
 
-    // for a set rtt that meets the conditions and assuming cwnd = jump window already, check we move to unvalidated
+    // congestion signaled from outside the transport retreats as a CE mark,
+    // and is counted even when careful resume isn't in use
     #[test]
-    fn valid_rtt() {
-        let mut r = Resume::new("");
-        r.setup(Duration::from_millis(50), 80_000);
-        let jump = r.send_packet(Some(Duration::from_millis(60)), 20_500, 20, false);
-        assert_eq!(jump, 19_500);
+    fn external_congestion() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
 
-        assert_eq!(r.cr_state, CrState::Unvalidated(20));
-        assert_eq!(r.pipesize, 20_500);
+        let mut r = Recovery::new(&cfg, "");
+        let now = Instant::now();
+
+        r.on_external_congestion(CongestionSignalSource::Aqm, now);
+        assert_eq!(r.external_congestion_count(), 1);
+        assert_eq!(r.congestion.congestion_window, r.congestion.initial_window);
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+        r.congestion.resume.change_state(CrState::Unvalidated(10), CarefulResumeTrigger::CwndLimited);
+        r.congestion.resume.pipesize = 60_000;
+        r.congestion.congestion_window = 40_000;
+
+        r.on_external_congestion(CongestionSignalSource::LinkLayer, now);
+        assert_eq!(r.external_congestion_count(), 2);
+        assert_eq!(r.congestion.resume.cr_state, CrState::SafeRetreat(0));
+        assert_eq!(r.congestion.congestion_window, 30_000);
+        #[cfg(feature = "qlog")]
+        assert_eq!(r.congestion.resume.last_trigger, Some(CarefulResumeTrigger::ExternalCongestion));
+
+        // Further signals don't reduce the window again
+        r.on_external_congestion(CongestionSignalSource::Other, now);
+        assert_eq!(r.external_congestion_count(), 3);
+        assert_eq!(r.congestion.congestion_window, 30_000);
     }
 
+    // a PTO while the jumped window isn't validated retreats as a loss, with
+    // its own trigger
     #[test]
-    fn packet_loss_recon() {
-        let mut r = Resume::new("");
-        r.setup(Duration::from_millis(50), 80_000);
-        r.congestion_event(20);
-        assert_eq!(r.cr_state, CrState::Normal);
+    fn pto_during_jump() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let now = Instant::now();
+
+        // A PTO in reconnaissance is left to the congestion controller
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+        r.on_loss_detection_timeout(HandshakeStatus::default(), now, "");
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+        assert_eq!(r.congestion.congestion_window, r.congestion.initial_window);
+
+        r.congestion.resume.change_state(CrState::Unvalidated(10), CarefulResumeTrigger::CwndLimited);
+        r.congestion.resume.pipesize = 60_000;
+        r.congestion.congestion_window = 40_000;
+
+        r.on_loss_detection_timeout(HandshakeStatus::default(), now, "");
+        assert_eq!(r.congestion.resume.cr_state, CrState::SafeRetreat(0));
+        assert_eq!(r.congestion.congestion_window, 30_000);
+        #[cfg(feature = "qlog")]
+        assert_eq!(r.congestion.resume.last_trigger, Some(CarefulResumeTrigger::PtoExpired));
+
+        // The parameters aren't used again
+        assert!(r.congestion.resume.take_invalid_params().is_some());
+
+        // Further PTOs don't reduce the window again
+        r.on_loss_detection_timeout(HandshakeStatus::default(), now, "");
+        assert_eq!(r.congestion.resume.cr_state, CrState::SafeRetreat(0));
+        assert_eq!(r.congestion.congestion_window, 30_000);
+
+        // A PTO while validating retreats to the end of the validating phase
+        let mut r = Recovery::new(&cfg, "");
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+        r.congestion.resume.change_state(CrState::Validating(40), CarefulResumeTrigger::CrMarkAcknowledged);
+        r.congestion.resume.pipesize = 50_000;
+        r.congestion.congestion_window = 50_000;
+
+        r.on_loss_detection_timeout(HandshakeStatus::default(), now, "");
+        assert_eq!(r.congestion.resume.cr_state, CrState::SafeRetreat(40));
+        assert_eq!(r.congestion.congestion_window, 25_000);
     }
 
+    // Recovery decisions are logged with the Careful Resume phase they led to
     #[test]
-    fn no_rtt_sample() {
+    fn decision_log() {
+        use crate::recovery::RecoveryDecisionKind;
+
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
-        cfg.enable_hystart(true);
         cfg.enable_resume(true);
 
         let mut r = Recovery::new(&cfg, "");
         let now = Instant::now();
 
-        r.setup_careful_resume(Duration::from_millis(50), 80_000);
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+        r.on_loss_detection_timeout(HandshakeStatus::default(), now, "");
 
-        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+        r.congestion.resume.change_state(CrState::Unvalidated(10), CarefulResumeTrigger::CwndLimited);
+        r.congestion.resume.pipesize = 60_000;
+        r.congestion.congestion_window = 40_000;
 
-        for i in 0..10 {
-            let p = Sent {
-                pkt_num: i as u64,
-                frames: smallvec![],
-                time_sent: now,
-                time_acked: None,
-                time_lost: None,
-                size: 1200,
-                ack_eliciting: true,
-                in_flight: true,
-                delivered: 0,
-                delivered_time: now,
-                first_sent_time: now,
-                is_app_limited: false,
-                tx_in_flight: 0,
-                lost: 0,
-                has_data: false,
-                pmtud: false,
-            };
+        // The state was changed outside of recovery, so only the retreat
+        // it leads to is logged
+        r.on_external_congestion(CongestionSignalSource::Aqm, now + Duration::from_millis(5));
+        r.on_loss_detection_timeout(HandshakeStatus::default(), now + Duration::from_millis(10), "");
 
-            r.on_packet_sent(
-                p,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            );
-            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
-            assert_eq!(r.bytes_in_flight, 1200 * (i + 1));
-        }
+        let decisions = r.decision_log().decisions();
+        assert_eq!(decisions.len(), 3);
+
+        assert_eq!(decisions[0].kind, RecoveryDecisionKind::ProbeTimeout);
+        assert_eq!(decisions[0].cr_phase, "reconnaissance");
+        assert_eq!(decisions[0].elapsed, Duration::ZERO);
+        assert_eq!(decisions[0].cwnd, r.congestion.initial_window);
 
-        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+        assert_eq!(decisions[1].kind, RecoveryDecisionKind::CrPhaseChange);
+        assert_eq!(decisions[1].cr_phase, "safe_retreat");
+        assert_eq!(decisions[1].elapsed, Duration::from_millis(5));
+        assert_eq!(decisions[1].cwnd, 30_000);
+
+        assert_eq!(decisions[2].kind, RecoveryDecisionKind::ProbeTimeout);
+        assert_eq!(decisions[2].cr_phase, "safe_retreat");
+        assert_eq!(decisions[2].elapsed, Duration::from_millis(10));
+
+        assert_eq!(
+            crate::RecoveryDecisionLog::from_bytes(&r.decision_log().to_bytes()),
+            Ok(decisions)
+        );
     }
+
     #[test]
-    fn valid_rtt_full_reno() {
+    fn public_state() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
-        cfg.enable_hystart(true);
+
+        let r = Recovery::new(&cfg, "");
+        assert_eq!(r.cr_state(), None);
+
         cfg.enable_resume(true);
 
         let mut r = Recovery::new(&cfg, "");
-        let mut now = Instant::now();
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+        assert_eq!(r.cr_state(), Some(CarefulResumeState::Reconnaissance));
 
-        r.setup_careful_resume(Duration::from_millis(50), 80_000);
+        r.congestion.resume.change_state(CrState::Unvalidated(10), CarefulResumeTrigger::CwndLimited);
+        assert_eq!(r.cr_state(), Some(CarefulResumeState::Unvalidated { cr_mark: 10 }));
 
-        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+        r.congestion.resume.change_state(CrState::Validating(40), CarefulResumeTrigger::CrMarkAcknowledged);
+        assert_eq!(r.cr_state(), Some(CarefulResumeState::Validating { cr_mark: 40 }));
 
-        for i in 0..5 {
-            let p = Sent {
-                pkt_num: i as u64,
-                frames: smallvec![],
-                time_sent: now,
-                time_acked: None,
-                time_lost: None,
-                size: 1000,
-                ack_eliciting: true,
-                in_flight: true,
-                delivered: 0,
-                delivered_time: now,
-                first_sent_time: now,
-                is_app_limited: false,
-                tx_in_flight: 0,
-                lost: 0,
-                has_data: false,
-                pmtud: false,
-            };
+        r.congestion.resume.pipesize = 50_000;
+        r.on_loss_detection_timeout(HandshakeStatus::default(), Instant::now(), "");
+        assert_eq!(r.cr_state(), Some(CarefulResumeState::SafeRetreat { cr_mark: 40 }));
 
-            r.on_packet_sent(
-                p,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            );
-            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
-            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
+        r.congestion.resume.change_state(CrState::Normal, CarefulResumeTrigger::CrMarkAcknowledged);
+        assert_eq!(r.cr_state(), Some(CarefulResumeState::Normal));
+    }
+
+    // HyStart++ doesn't sample during the jump, and then either resumes or
+    // gives way to congestion avoidance
+    #[test]
+    fn hystart_policy() {
+        use crate::recovery::congestion::CongestionPhase;
+
+        let recovery = |policy| {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+            cfg.enable_resume(true);
+            cfg.enable_hystart(true);
+            cfg.set_cr_hystart_policy(policy);
+
+            let mut r = Recovery::new(&cfg, "");
+            r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+            r
+        };
+
+        for (policy, phase) in [
+            (CrHystartPolicy::Resume, CongestionPhase::SlowStart),
+            (CrHystartPolicy::CongestionAvoidance, CongestionPhase::CongestionAvoidance),
+        ] {
+            let mut r = recovery(policy);
+
+            r.congestion.update_hystart();
+            assert!(!r.congestion.hystart.suspended());
+
+            r.congestion.resume.change_state(CrState::Unvalidated(10), CarefulResumeTrigger::CwndLimited);
+            r.congestion.update_hystart();
+            assert!(r.congestion.hystart.suspended());
+
+            r.congestion.resume.change_state(CrState::Validating(20), CarefulResumeTrigger::CrMarkAcknowledged);
+            r.congestion.update_hystart();
+            assert!(r.congestion.hystart.suspended());
+
+            r.congestion.congestion_window = 50_000;
+            r.congestion.resume.change_state(CrState::Normal, CarefulResumeTrigger::CrMarkAcknowledged);
+            r.congestion.update_hystart();
+            assert!(!r.congestion.hystart.suspended());
+            assert_eq!(r.congestion.phase(), Some(phase));
         }
 
-        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+        // HyStart++ ended slow start before the jump, which doesn't restart it
+        let mut r = recovery(CrHystartPolicy::CongestionAvoidance);
+        r.congestion.ssthresh = 20_000;
 
-        now += Duration::from_millis(50);
+        r.congestion.resume.change_state(CrState::Unvalidated(10), CarefulResumeTrigger::CwndLimited);
+        r.congestion.update_hystart();
+        r.congestion.congestion_window = 50_000;
+        r.congestion.resume.change_state(CrState::Normal, CarefulResumeTrigger::CrMarkAcknowledged);
+        r.congestion.update_hystart();
+        assert_eq!(r.congestion.ssthresh, 20_000);
 
-        let mut acked = ranges::RangeSet::default();
-        acked.insert(0..5);
+        // The safe retreat sets the slow start threshold itself
+        let mut r = recovery(CrHystartPolicy::CongestionAvoidance);
 
-        assert_eq!(
-            r.on_ack_received(
-                &acked,
-                25,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            ),
-            Ok((0, 0, 1000 * 5))
-        );
+        r.congestion.resume.change_state(CrState::Unvalidated(10), CarefulResumeTrigger::CwndLimited);
+        r.congestion.update_hystart();
+        r.congestion.resume.change_state(CrState::SafeRetreat(10), CarefulResumeTrigger::PacketLoss);
+        r.congestion.update_hystart();
+        assert!(!r.congestion.hystart.suspended());
+        assert_eq!(r.congestion.ssthresh, usize::MAX);
 
-        assert_eq!(r.cwnd(), 12_000);
+        // Without HyStart++ there is nothing to suspend
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_hystart(false);
+        cfg.set_cr_hystart_policy(CrHystartPolicy::CongestionAvoidance);
 
-        // Send significantly more than the CWND to enter app limited
-        for i in 0..16 {
-            let p = Sent {
-                pkt_num: 5 + i as u64,
-                frames: smallvec![],
-                time_sent: now,
-                time_acked: None,
-                time_lost: None,
-                size: 1000,
-                ack_eliciting: true,
-                in_flight: true,
-                delivered: 0,
-                delivered_time: now,
-                first_sent_time: now,
-                is_app_limited: false,
-                tx_in_flight: 0,
-                lost: 0,
-                has_data: false,
-                pmtud: false,
-            };
+        let mut r = Recovery::new(&cfg, "");
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+        r.congestion.resume.change_state(CrState::Unvalidated(10), CarefulResumeTrigger::CwndLimited);
+        r.congestion.update_hystart();
+        assert!(!r.congestion.hystart.suspended());
+    }
 
-            r.on_packet_sent(
-                p,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            );
-            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
-            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
+    // careful resume is abandoned when the connection migrates to another path
+    #[test]
+    fn path_change() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let now = Instant::now();
+
+        // Nothing to abandon when careful resume isn't used
+        r.on_cr_path_change(now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+        assert_eq!(r.congestion.congestion_window, r.congestion.initial_window);
+
+        // The unvalidated jumped window is reduced to the pipesize
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+        r.congestion.resume.change_state(CrState::Unvalidated(10), CarefulResumeTrigger::CwndLimited);
+        r.congestion.resume.pipesize = 30_000;
+        r.congestion.congestion_window = 40_000;
+
+        r.on_cr_path_change(now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+        assert_eq!(r.congestion.congestion_window, 30_000);
+        assert_eq!(r.congestion.resume.pipesize, 0);
+        #[cfg(feature = "qlog")]
+        {
+            let Some(EventData::CarefulResumePhaseUpdated(ev)) = r.maybe_cr_qlog() else {
+                panic!("no phase update");
+            };
+            assert_eq!(ev.new, CarefulResumePhase::Normal);
+            assert_eq!(ev.trigger, Some(CarefulResumeTrigger::PathChange));
         }
 
-        assert_eq!(r.cwnd(), 40_000);
+        // Reconnaissance is abandoned without changing the window
+        let mut r = Recovery::new(&cfg, "");
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
 
-        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(15));
-        assert_eq!(r.congestion.resume.pipesize, 12_000);
+        r.on_cr_path_change(now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+        assert_eq!(r.congestion.congestion_window, r.congestion.initial_window);
     }
 
-
+    // the application can abandon careful resume in any phase
     #[test]
-    fn valid_rtt_full_cubic() {
+    fn abandon() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        cfg.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
-        cfg.enable_hystart(true);
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
         cfg.enable_resume(true);
 
+        let now = Instant::now();
+
+        // The unvalidated jumped window is reduced to the pipesize, but not
+        // below the initial window
+        for (pipesize, cwnd) in [(30_000, 30_000), (1_000, 12_000)] {
+            let mut r = Recovery::new(&cfg, "");
+            r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+            r.congestion.resume.change_state(CrState::Unvalidated(10), CarefulResumeTrigger::CwndLimited);
+            r.congestion.resume.pipesize = pipesize;
+            r.congestion.congestion_window = 40_000;
+
+            r.abandon_careful_resume(now);
+            assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+            assert_eq!(r.congestion.congestion_window, cwnd);
+            #[cfg(feature = "qlog")]
+            {
+                let Some(EventData::CarefulResumePhaseUpdated(ev)) = r.maybe_cr_qlog() else {
+                    panic!("no phase update");
+                };
+                assert_eq!(ev.new, CarefulResumePhase::Normal);
+                assert_eq!(ev.trigger, Some(CarefulResumeTrigger::Abandoned));
+            }
+        }
+
+        // A validating window is kept
         let mut r = Recovery::new(&cfg, "");
-        let mut now = Instant::now();
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+        r.congestion.resume.change_state(CrState::Validating(20), CarefulResumeTrigger::CrMarkAcknowledged);
+        r.congestion.congestion_window = 40_000;
 
-        r.setup_careful_resume(Duration::from_millis(50), 80_000);
+        r.abandon_careful_resume(now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+        assert_eq!(r.congestion.congestion_window, 40_000);
 
-        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+        // Abandoning again does nothing
+        r.abandon_careful_resume(now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+        #[cfg(feature = "qlog")]
+        {
+            assert!(r.maybe_cr_qlog().is_some());
+            assert!(r.maybe_cr_qlog().is_none());
+        }
+    }
 
-        for i in 0..5 {
+    // careful resume is abandoned before the jump when the data the
+    // application expects to send fits in the congestion window
+    // the jump doesn't go beyond what flow control lets the sender send
+    #[test]
+    fn flow_control_limit() {
+        let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
             let p = Sent {
-                pkt_num: i as u64,
+                pkt_num,
                 frames: smallvec![],
                 time_sent: now,
                 time_acked: None,
@@ -614,42 +4660,72 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
-            r.on_packet_sent(
-                p,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            );
-            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
-            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
-        }
+            r.on_packet_sent(p, packet::Epoch::Application, HandshakeStatus::default(), now, "");
+        };
 
-        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+        // Sends the initial window, has it acked, then sends `count` more
+        // packets with the given flow control credit left
+        let run = |credit: usize, count: u64| {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+            cfg.enable_resume(true);
 
-        now += Duration::from_millis(50);
+            let mut r = Recovery::new(&cfg, "");
+            let mut now = Instant::now();
 
-        let mut acked = ranges::RangeSet::default();
-        acked.insert(0..5);
+            r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
 
-        assert_eq!(
-            r.on_ack_received(
-                &acked,
-                25,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            ),
-            Ok((0, 0, 1000 * 5))
-        );
+            for i in 0..5 {
+                send(&mut r, i, now);
+            }
 
-        // Send significantly more than the CWND to enter app limited
-        for i in 0..16 {
+            now += Duration::from_millis(50);
+
+            let mut acked = ranges::RangeSet::default();
+            acked.insert(0..5);
+            r.on_ack_received(&acked, 25, None, packet::Epoch::Application, HandshakeStatus::default(), now, "")
+                .unwrap();
+
+            r.set_cr_flow_control_credit(credit);
+
+            for i in 5..5 + count {
+                send(&mut r, i, now);
+            }
+
+            r
+        };
+
+        // The jump is made with 12 packets in flight, with 20,000 bytes of
+        // credit left the window can't usefully go beyond 32,000 bytes
+        let r = run(20_000, 16);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(15));
+        assert_eq!(r.cwnd(), 32_000);
+        assert_eq!(r.cr_last_jump(), Some(20_000));
+        assert!(r.congestion.resume.flow_control_limited);
+
+        // Enough credit for the whole jump
+        let r = run(1_000_000, 16);
+        assert_eq!(r.cwnd(), 40_000);
+        assert_eq!(r.cr_last_jump(), Some(28_000));
+        assert!(!r.congestion.resume.flow_control_limited);
+
+        // Without any credit left there is nothing to jump into yet, and no
+        // more than the congestion window can be sent
+        let r = run(0, 12);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+        assert_eq!(r.cr_last_jump(), None);
+    }
+
+    // bursts are limited while the jump is unvalidated, and the packets held
+    // back are released after the burst interval
+    #[test]
+    fn unvalidated_burst_limit() {
+        let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
             let p = Sent {
-                pkt_num: 5 + i as u64,
+                pkt_num,
                 frames: smallvec![],
                 time_sent: now,
                 time_acked: None,
@@ -665,91 +4741,71 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
-            r.on_packet_sent(
-                p,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            );
-            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
-            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
-        }
-
-        assert_eq!(r.cwnd(), 40_000);
+            r.on_packet_sent(p, packet::Epoch::Application, HandshakeStatus::default(), now, "");
+        };
 
-        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(15));
-        assert_eq!(r.congestion.resume.pipesize, 12_000);
-    }
-    #[test]
-    fn invalid_rtt_full() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
         cfg.enable_resume(true);
+        cfg.set_cr_max_unvalidated_burst(4);
 
         let mut r = Recovery::new(&cfg, "");
         let mut now = Instant::now();
 
-        r.setup_careful_resume(Duration::from_millis(50), 80_000);
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
 
-        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+        for i in 0..5 {
+            send(&mut r, i, now);
+        }
 
-        for i in 0..4 {
-            let p = Sent {
-                pkt_num: i as u64,
-                frames: smallvec![],
-                time_sent: now,
-                time_acked: None,
-                time_lost: None,
-                size: 1200,
-                ack_eliciting: true,
-                in_flight: true,
-                delivered: 0,
-                delivered_time: now,
-                first_sent_time: now,
-                is_app_limited: false,
-                tx_in_flight: 0,
-                lost: 0,
-                has_data: false,
-                pmtud: false,
-            };
+        now += Duration::from_millis(50);
 
-            r.on_packet_sent(
-                p,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            );
-            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
-            assert_eq!(r.bytes_in_flight, 1200 * (i + 1));
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..5);
+        r.on_ack_received(&acked, 25, None, packet::Epoch::Application, HandshakeStatus::default(), now, "")
+            .unwrap();
+
+        // No limit before the jump
+        assert_eq!(r.send_quantum(), 12_000);
+
+        for i in 5..17 {
+            send(&mut r, i, now);
         }
 
-        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(15));
+        assert_eq!(r.cwnd(), 40_000);
+        assert_eq!(r.send_quantum(), 4_800);
 
-        now += Duration::from_millis(600);
+        // The packet that made the jump starts the burst
+        for i in 17..21 {
+            assert!(r.cwnd_available() > 0);
+            send(&mut r, i, now);
+        }
 
-        let mut acked = ranges::RangeSet::default();
-        acked.insert(0..4);
+        assert_eq!(r.cwnd_available(), 0);
 
-        assert_eq!(
-            r.on_ack_received(
-                &acked,
-                25,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            ),
-            Ok((0, 0, 1200 * 4))
-        );
+        // 4,800 of the 40,000 bytes of window, over the RTT
+        let release = r.burst_timer().unwrap();
+        assert_eq!(release, now + r.rtt().mul_f64(0.12));
+
+        r.release_burst(release - Duration::from_micros(1));
+        assert_eq!(r.cwnd_available(), 0);
+
+        r.release_burst(release);
+        assert_eq!(r.burst_timer(), None);
+        assert!(r.cwnd_available() > 0);
+    }
 
-        // Send significantly more than the CWND to enter app limited
-        for i in 0..20 {
+    // resetting the recovery state, e.g. after a Retry, starts careful resume
+    // over without the marks and samples taken before the reset
+    #[test]
+    fn reset() {
+        let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
             let p = Sent {
-                pkt_num: 4 + i as u64,
+                pkt_num,
                 frames: smallvec![],
                 time_sent: now,
                 time_acked: None,
@@ -765,23 +4821,12 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
-            r.on_packet_sent(
-                p,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            );
-            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
-            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
-        }
-        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
-    }
+            r.on_packet_sent(p, packet::Epoch::Application, HandshakeStatus::default(), now, "");
+        };
 
-    #[test]
-    fn cwnd_larger_than_jump_full() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
         cfg.enable_resume(true);
@@ -789,18 +4834,73 @@ mod tests {
         let mut r = Recovery::new(&cfg, "");
         let mut now = Instant::now();
 
-        r.setup_careful_resume(Duration::from_millis(50), 80_000);
+        // Nothing to reset when careful resume isn't used
+        r.reset_careful_resume(true, now);
+        assert_eq!(r.cr_state(), None);
 
-        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
 
-        for i in 0..37 {
+        for i in 0..5 {
+            send(&mut r, i, now);
+        }
+
+        now += Duration::from_millis(50);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..5);
+        r.on_ack_received(&acked, 25, None, packet::Epoch::Application, HandshakeStatus::default(), now, "")
+            .unwrap();
+
+        for i in 5..17 {
+            send(&mut r, i, now);
+        }
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(15));
+        assert_eq!(r.cwnd(), 40_000);
+        assert!(r.initial_window_acked());
+
+        // The unvalidated jump is undone, and careful resume waits for the
+        // initial window to be acknowledged and for new RTT samples
+        r.reset_careful_resume(true, now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+        assert_eq!(r.cwnd(), r.congestion.initial_window);
+        assert_eq!(r.congestion.resume.pipesize, 0);
+        assert_eq!(r.congestion.resume.rtt_samples, 0);
+        assert_eq!(r.cr_last_jump(), None);
+        assert!(!r.initial_window_acked());
+        assert_eq!(r.congestion.resume.setup_params().map(|p| p.cwnd), Some(80_000));
+        assert_eq!(r.cr_counters().jumps_attempted, 1);
+        #[cfg(feature = "qlog")]
+        {
+            let Some(EventData::CarefulResumePhaseUpdated(ev)) = r.maybe_cr_qlog() else {
+                panic!("no phase update");
+            };
+            assert_eq!(ev.new, CarefulResumePhase::Reconnaissance);
+            assert_eq!(ev.trigger, Some(CarefulResumeTrigger::Reset));
+        }
+
+        // Without the parameters, careful resume is over
+        r.reset_careful_resume(false, now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+        assert_eq!(r.congestion.resume.setup_params(), None);
+
+        // A careful resume that is already over isn't started again
+        r.reset_careful_resume(true, now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+    }
+
+    // the jump is capped at a multiple of the capacity the initial window was
+    // delivered at
+    #[test]
+    fn plausibility_check() {
+        let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
             let p = Sent {
-                pkt_num: i as u64,
+                pkt_num,
                 frames: smallvec![],
                 time_sent: now,
                 time_acked: None,
                 time_lost: None,
-                size: 1200,
+                size: 1000,
                 ack_eliciting: true,
                 in_flight: true,
                 delivered: 0,
@@ -811,47 +4911,86 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
-            r.on_packet_sent(
-                p,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            );
-            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
-            assert_eq!(r.bytes_in_flight, 1200 * (i + 1));
-        }
+            r.on_packet_sent(p, packet::Epoch::Application, HandshakeStatus::default(), now, "");
+        };
 
-        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+        // Sends the initial window, has it acked after one RTT, then sends
+        // enough packets to reach the jump
+        let run = |factor: f64| {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+            cfg.enable_resume(true);
+            cfg.set_cr_plausibility_factor(factor).unwrap();
 
-        now += Duration::from_millis(50);
+            let mut r = Recovery::new(&cfg, "");
+            let mut now = Instant::now();
 
-        let mut acked = ranges::RangeSet::default();
-        acked.insert(0..37);
+            r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
 
-        assert_eq!(
-            r.on_ack_received(
-                &acked,
-                25,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            ),
-            Ok((0, 0, 1200 * 37))
-        );
+            for i in 0..5 {
+                send(&mut r, i, now);
+            }
 
-        // Send significantly more than the CWND to enter app limited
-        for i in 0..60 {
+            now += Duration::from_millis(50);
+
+            let mut acked = ranges::RangeSet::default();
+            acked.insert(0..5);
+            r.on_ack_received(&acked, 25, None, packet::Epoch::Application, HandshakeStatus::default(), now, "")
+                .unwrap();
+
+            for i in 5..17 {
+                send(&mut r, i, now);
+            }
+
+            r
+        };
+
+        // The initial window was delivered at 100,000 bytes per second, i.e.
+        // 5,000 bytes per previous RTT. The jump is made with 12 packets in
+        // flight and can't take the window beyond four times that
+        let r = run(4.0);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(15));
+        assert_eq!(r.cwnd(), 20_000);
+        assert_eq!(r.cr_last_jump(), Some(8_000));
+        assert!(r.congestion.resume.plausibility_capped);
+
+        // Without the check the jump reaches half the previous window
+        let r = run(0.0);
+        assert_eq!(r.cwnd(), 40_000);
+        assert_eq!(r.cr_last_jump(), Some(28_000));
+        assert!(!r.congestion.resume.plausibility_capped);
+
+        // The window is already beyond what is plausible, careful resume is
+        // abandoned
+        let r = run(2.0);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+        assert_eq!(r.cr_last_jump(), None);
+        assert!(r.congestion.resume.plausibility_capped);
+    }
+
+    #[test]
+    fn transfer_size_hint() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+        r.set_cr_transfer_size_hint(Some(20_000));
+
+        for i in 0..5 {
             let p = Sent {
-                pkt_num: 37 + i as u64,
+                pkt_num: i,
                 frames: smallvec![],
                 time_sent: now,
                 time_acked: None,
                 time_lost: None,
-                size: 1200,
+                size: 1000,
                 ack_eliciting: true,
                 in_flight: true,
                 delivered: 0,
@@ -862,41 +5001,20 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
-            r.on_packet_sent(
-                p,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            );
-            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
-            assert_eq!(r.bytes_in_flight, 1200 * (i + 1));
-            assert_eq!(r.congestion.congestion_window, 56_400);
-
+            r.on_packet_sent(p, packet::Epoch::Application, HandshakeStatus::default(), now, "");
         }
-        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
-    }
-
-    #[test]
-    fn packet_loss_recon_full_reno() {
-        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
-        cfg.enable_resume(true);
-        cfg.enable_hystart(true);
-
-
-        let mut r = Recovery::new(&cfg, "");
-        let mut now = Instant::now();
 
-        r.setup_careful_resume(Duration::from_millis(50), 80_000);
-
-        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+        // 15,000 bytes are left, more than the initial window
+        assert_eq!(r.congestion.congestion_window, 12_000);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
 
-        for i in 0..10 {
+        // Once the rest fits in the window there is nothing to jump for
+        for i in 5..9 {
             let p = Sent {
-                pkt_num: i as u64,
+                pkt_num: i,
                 frames: smallvec![],
                 time_sent: now,
                 time_acked: None,
@@ -912,60 +5030,139 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
-            r.on_packet_sent(
-                p,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            );
-            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
-            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
+            r.on_packet_sent(p, packet::Epoch::Application, HandshakeStatus::default(), now, "");
         }
 
-        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
-
-        now += Duration::from_millis(50);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+        assert_eq!(r.congestion.congestion_window, 12_000);
+        #[cfg(feature = "qlog")]
+        assert_eq!(r.congestion.resume.last_trigger, Some(CarefulResumeTrigger::TransferTooSmall));
 
-        // Ack with one missing
-        let mut acked = ranges::RangeSet::default();
-        acked.insert(0..5);
-        acked.insert(6..9);
+        // Without a hint the transfer size is unknown
+        let mut r = Recovery::new(&cfg, "");
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+        r.set_cr_transfer_size_hint(Some(1000));
+        r.set_cr_transfer_size_hint(None);
 
-        assert_eq!(
-            r.on_ack_received(
-                &acked,
-                25,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            ),
-            Ok((1, 1000, 1000 * 8))
-        );
+        let p = Sent {
+            pkt_num: 0,
+            frames: smallvec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            tx_in_flight: 0,
+            lost: 0,
+            has_data: false,
+            pmtud: false,
+            retransmission: false,
+        };
 
-        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+        r.on_packet_sent(p, packet::Epoch::Application, HandshakeStatus::default(), now, "");
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
     }
-    #[test]
-    fn packet_loss_recon_full_cubic() {
-        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
-        cfg.set_cc_algorithm(CongestionControlAlgorithm::CUBIC);
-        cfg.enable_resume(true);
-        cfg.enable_hystart(true);
 
+    // a congestion window is resumed with as many packets of the new
+    // datagram size
+    #[test]
+    fn cwnd_for_datagram_size() {
+        let params = CREvent {
+            min_rtt: Duration::from_millis(50),
+            cwnd: 150_000,
+            pacing_rate: 0,
+            post_congestion: false,
+            max_datagram_size: 1500,
+            loss_rate_ppm: 0,
+        };
 
-        let mut r = Recovery::new(&cfg, "");
-        let mut now = Instant::now();
+        assert_eq!(params.cwnd_for_datagram_size(1500), 150_000);
+        assert_eq!(params.cwnd_for_datagram_size(1280), 128_000);
+        assert_eq!(params.cwnd_for_datagram_size(0), 150_000);
 
-        r.setup_careful_resume(Duration::from_millis(50), 80_000);
+        let unknown = CREvent { max_datagram_size: 0, ..params };
+        assert_eq!(unknown.cwnd_for_datagram_size(1280), 150_000);
+    }
 
-        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+    #[test]
+    fn cr_event_bytes() {
+        let params = CREvent {
+            min_rtt: Duration::from_micros(600_250),
+            cwnd: 1_250_000,
+            pacing_rate: 6_250_000,
+            post_congestion: true,
+            max_datagram_size: 1350,
+            loss_rate_ppm: 2_500,
+        };
+        let stored_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let bytes = params.to_bytes("quic.tech", stored_at + Duration::from_millis(500));
+
+        // The time is stored in seconds
+        assert_eq!(CREvent::from_bytes(&bytes), Ok(StoredCREvent {
+            params,
+            endpoint: "quic.tech".to_string(),
+            stored_at,
+        }));
+
+        assert_eq!(CREvent::from_bytes(&bytes[..bytes.len() - 1]), Err(crate::Error::BufferTooShort));
+        assert_eq!(CREvent::from_bytes(&[]), Err(crate::Error::BufferTooShort));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(CREvent::from_bytes(&trailing), Err(crate::Error::CongestionControl));
+
+        let mut version = bytes.clone();
+        version[0] = 2;
+        assert_eq!(CREvent::from_bytes(&version), Err(crate::Error::UnknownVersion));
+
+        // Values beyond the varint range are clamped rather than overflowing
+        let max = CR_EVENT_MAX_VARINT;
+        let huge = CREvent {
+            min_rtt: Duration::MAX,
+            cwnd: usize::MAX,
+            pacing_rate: u64::MAX,
+            max_datagram_size: usize::MAX,
+            ..params
+        };
+        assert_eq!(CREvent::from_bytes(&huge.to_bytes("quic.tech", stored_at)), Ok(StoredCREvent {
+            params: CREvent {
+                min_rtt: Duration::from_micros(max),
+                cwnd: max as usize,
+                pacing_rate: max,
+                max_datagram_size: max as usize,
+                ..params
+            },
+            endpoint: "quic.tech".to_string(),
+            stored_at,
+        }));
+
+        let loss = CREvent { loss_rate_ppm: 1_000_001, ..params }.to_bytes("quic.tech", stored_at);
+        assert_eq!(CREvent::from_bytes(&loss), Err(crate::Error::CongestionControl));
+
+        let mut flags = bytes.clone();
+        *flags.last_mut().unwrap() = 0x02;
+        assert_eq!(CREvent::from_bytes(&flags), Err(crate::Error::CongestionControl));
+
+        let zero_cwnd = CREvent { cwnd: 0, ..params }.to_bytes("quic.tech", stored_at);
+        assert_eq!(CREvent::from_bytes(&zero_cwnd), Err(crate::Error::CongestionControl));
+    }
 
-        for i in 0..10 {
+    // with 0-RTT data the handshake confirms the path, and the rejected 0-RTT
+    // packets aren't a congestion signal
+    #[test]
+    fn early_data() {
+        let send = |r: &mut Recovery, epoch: packet::Epoch, pkt_num: u64, completed: bool, now: Instant| {
             let p = Sent {
-                pkt_num: i as u64,
+                pkt_num,
                 frames: smallvec![],
                 time_sent: now,
                 time_acked: None,
@@ -981,81 +5178,157 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
-            r.on_packet_sent(
-                p,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            );
-            assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), i + 1);
-            assert_eq!(r.bytes_in_flight, 1000 * (i + 1));
-        }
+            let handshake_status = HandshakeStatus {
+                completed,
+                ..HandshakeStatus::default()
+            };
 
-        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+            r.on_packet_sent(p, epoch, handshake_status, now, "");
+        };
 
-        now += Duration::from_millis(50);
+        let ack = |r: &mut Recovery, epoch: packet::Epoch, range: std::ops::Range<u64>, now: Instant| {
+            let mut acked = ranges::RangeSet::default();
+            acked.insert(range);
 
-        // Ack with one missing
-        let mut acked = ranges::RangeSet::default();
-        acked.insert(0..5);
-        acked.insert(6..9);
+            r.on_ack_received(&acked, 0, None, epoch, HandshakeStatus::default(), now, "")
+                .unwrap();
+        };
 
-        assert_eq!(
-            r.on_ack_received(
-                &acked,
-                25,
-                packet::Epoch::Application,
-                HandshakeStatus::default(),
-                now,
-                "",
-            ),
-            Ok((1, 1000, 1000 * 8))
-        );
+        // Sends 0-RTT data, completes the handshake, then sends 1-RTT data
+        // whose acknowledgement declares the 0-RTT packets lost
+        let run = |cfg: &crate::Config, session_rtt: Duration| {
+            let mut r = Recovery::new(cfg, "");
+            let mut now = Instant::now();
+
+            r.set_initial_rtt(session_rtt);
+            r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
+
+            for i in 0..4 {
+                send(&mut r, packet::Epoch::Application, i, false, now);
+            }
+
+            send(&mut r, packet::Epoch::Handshake, 0, false, now);
+            now += Duration::from_millis(50);
+            ack(&mut r, packet::Epoch::Handshake, 0..1, now);
+
+            for i in 4..20 {
+                send(&mut r, packet::Epoch::Application, i, true, now);
+            }
+
+            let before_loss = r.congestion.resume.cr_state;
+
+            now += Duration::from_millis(50);
+            ack(&mut r, packet::Epoch::Application, 4..10, now);
+
+            (before_loss, r)
+        };
+
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
 
+        // Without the mode the 0-RTT packets hold the jump back, and their
+        // loss ends careful resume
+        let (before_loss, r) = run(&cfg, Duration::from_millis(50));
+        assert_eq!(before_loss, CrState::Reconnaissance);
         assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
-    }
 
-    #[test]
-    fn pipesize_update_unval() {
-        let mut r = Resume::new("");
+        cfg.enable_cr_early_data(true);
+
+        let (before_loss, r) = run(&cfg, Duration::from_millis(50));
+        assert!(matches!(before_loss, CrState::Unvalidated(_)));
+        assert_eq!(r.congestion.resume.cr_state, before_loss);
+        assert!(!r.congestion.resume.in_safe_retreat());
+
+        // A session RTT too far from the previous one abandons careful resume
+        // before the handshake completes
+        let mut r = Recovery::new(&cfg, "");
         let now = Instant::now();
 
-        r.setup(Duration::from_millis(50), 80_000);
-        r.change_state(CrState::Unvalidated(30), CarefulResumeTrigger::CwndLimited);
+        r.set_initial_rtt(Duration::from_millis(500));
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 0);
 
-        let p = Acked {
-           pkt_num: 29,
-           // To exit from recovery
-           time_sent: now,
-           // More than cur_cwnd to increase cwnd
-           size: 2000,
-           delivered: 0,
-           delivered_time: now,
-           first_sent_time: now,
-           is_app_limited: false,
-           rtt: Duration::ZERO,
-       };
-        r.process_ack(35, &p, 5_000);
+        send(&mut r, packet::Epoch::Application, 0, false, now);
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+        #[cfg(feature = "qlog")]
+        assert_eq!(r.congestion.resume.last_trigger, Some(CarefulResumeTrigger::RttNotValidated));
+    }
 
-        let p = Acked {
-            pkt_num: 30,
-            // To exit from recovery
-            time_sent: now,
-            // More than cur_cwnd to increase cwnd
-            size: 2000,
-            delivered: 0,
-            delivered_time: now,
-            first_sent_time: now,
-            is_app_limited: false,
-            rtt: Duration::ZERO,
-        };
-        r.process_ack(35, &p, 5_000);
-        assert_eq!(r.pipesize, 4_000);
-        assert_eq!(r.cr_state, CrState::Validating(35));
+    // the parameters a jump ended in safe retreat with are reported once
+    #[test]
+    fn invalid_params() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        r.setup_careful_resume(Duration::from_millis(50), 80_000, 1_000_000);
+
+        // Congestion during reconnaissance doesn't invalidate the parameters
+        r.congestion.resume.congestion_event(5);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+        assert_eq!(r.take_invalid_cr_params(), None);
+
+        for state in [CrState::Unvalidated(10), CrState::Validating(20)] {
+            let mut r = Recovery::new(&cfg, "");
+            r.setup_careful_resume(Duration::from_millis(50), 80_000, 1_000_000);
+            r.congestion.resume.change_state(state, CarefulResumeTrigger::CwndLimited);
+            r.congestion.resume.pipesize = 30_000;
+
+            assert_eq!(r.congestion.resume.congestion_event(30), 15_000);
+            assert!(r.congestion.resume.in_safe_retreat());
+
+            assert_eq!(r.take_invalid_cr_params(), Some(CREvent {
+                min_rtt: Duration::from_millis(50),
+                cwnd: 80_000,
+                pacing_rate: 1_000_000,
+                post_congestion: false,
+                max_datagram_size: 0,
+                loss_rate_ppm: 0,
+            }));
+            assert_eq!(r.take_invalid_cr_params(), None);
+        }
+    }
+
+    // the congestion window growth allowed while validating depends on the policy
+    #[test]
+    fn validating_policy() {
+        assert_eq!("freeze".parse(), Ok(CrValidatingPolicy::Freeze));
+        assert_eq!("pipesize".parse(), Ok(CrValidatingPolicy::PipesizeLimited));
+        assert_eq!("slow_start".parse(), Ok(CrValidatingPolicy::SlowStart));
+        assert_eq!("reno".parse::<CrValidatingPolicy>(), Err(crate::Error::CongestionControl));
+
+        for (policy, expected, expected_small_pipesize) in [
+            (CrValidatingPolicy::Freeze, 20_000, 20_000),
+            (CrValidatingPolicy::PipesizeLimited, 24_000, 20_000),
+            (CrValidatingPolicy::SlowStart, 30_000, 30_000),
+        ] {
+            let mut r = Resume::new("", 1, policy, Duration::ZERO);
+            r.setup(Duration::from_millis(50), 80_000, 0);
+
+            // Growth is never limited outside of the validating phase
+            assert_eq!(r.limit_validating_growth(20_000, 30_000), 30_000);
+
+            r.change_state(CrState::Validating(35), CarefulResumeTrigger::CrMarkAcknowledged);
+            r.pipesize = 24_000;
+            assert_eq!(r.limit_validating_growth(20_000, 30_000), expected);
+
+            // The window is not reduced when the pipesize is below it
+            r.pipesize = 10_000;
+            assert_eq!(r.limit_validating_growth(20_000, 30_000), expected_small_pipesize);
 
+            #[cfg(feature = "qlog")]
+            {
+                let Some(EventData::CarefulResumePhaseUpdated(ev)) = r.maybe_qlog(20_000, usize::MAX, true) else {
+                    panic!("no phase update");
+                };
+                assert_eq!(ev.state_data.validating_policy, Some(policy.into()));
+            }
+        }
     }
 
     #[test]
@@ -1067,7 +5340,7 @@ mod tests {
         let mut r = Recovery::new(&cfg, "");
         let mut now = Instant::now();
 
-        r.setup_careful_resume(Duration::from_millis(30), 120_000);
+        r.setup_careful_resume(Duration::from_millis(30), 120_000, 0);
         assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
 
         for i in 0..4 {
@@ -1088,6 +5361,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -1112,6 +5386,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1139,6 +5414,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -1164,6 +5440,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1181,6 +5458,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1200,6 +5478,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1211,16 +5490,328 @@ mod tests {
         assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
     }
 
+    // with the freeze policy the window stays at the flightsize while validating
+    #[test]
+    fn validating_policy_full() {
+        for policy in [CrValidatingPolicy::Freeze, CrValidatingPolicy::SlowStart] {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+            cfg.enable_resume(true);
+            cfg.set_cr_validating_policy(policy);
+
+            let mut r = Recovery::new(&cfg, "");
+            let mut now = Instant::now();
+
+            r.setup_careful_resume(Duration::from_millis(30), 120_000, 0);
+
+            let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
+                let p = Sent {
+                    pkt_num,
+                    frames: smallvec![],
+                    time_sent: now,
+                    time_acked: None,
+                    time_lost: None,
+                    size: 1000,
+                    ack_eliciting: true,
+                    in_flight: true,
+                    delivered: 0,
+                    delivered_time: now,
+                    first_sent_time: now,
+                    is_app_limited: false,
+                    tx_in_flight: 0,
+                    lost: 0,
+                    has_data: false,
+                    pmtud: false,
+                    retransmission: false,
+                };
+
+                r.on_packet_sent(
+                    p,
+                    packet::Epoch::Application,
+                    HandshakeStatus::default(),
+                    now,
+                    "",
+                );
+            };
+
+            let ack = |r: &mut Recovery, range: std::ops::Range<u64>, now: Instant| {
+                let mut acked = ranges::RangeSet::default();
+                acked.insert(range);
+
+                r.on_ack_received(
+                    &acked,
+                    25,
+                    None,
+                    packet::Epoch::Application,
+                    HandshakeStatus::default(),
+                    now,
+                    "",
+                )
+                .unwrap();
+            };
+
+            for i in 0..4 {
+                send(&mut r, i, now);
+            }
+
+            now += Duration::from_millis(25);
+            ack(&mut r, 0..4, now);
+
+            for i in 4..44 {
+                send(&mut r, i, now);
+            }
+
+            assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(14));
+
+            now += Duration::from_millis(25);
+            ack(&mut r, 4..16, now);
+
+            assert_eq!(r.congestion.resume.cr_state, CrState::Validating(43));
+            let cwnd = r.congestion.congestion_window;
+
+            // Fill the window so that the sender is not application limited
+            for i in 44..56 {
+                send(&mut r, i, now);
+            }
+
+            ack(&mut r, 16..20, now);
+
+            assert_eq!(r.congestion.resume.cr_state, CrState::Validating(43));
+            match policy {
+                CrValidatingPolicy::Freeze => assert_eq!(r.congestion.congestion_window, cwnd),
+                _ => assert!(r.congestion.congestion_window > cwnd),
+            }
+
+            ack(&mut r, 20..56, now);
+
+            assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+        }
+    }
+
+    // an increased ECN-CE count in an ACK frame is attributed to the largest
+    // newly acked packet, and only counts once, even when the ACK frame
+    // carrying it acknowledges no new packets
+    #[test]
+    fn ecn_ce_full() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(30), 120_000, 0);
+
+        let send = |r: &mut Recovery, pkt_num: u64, now: Instant| {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        };
+
+        let ack = |r: &mut Recovery, range: std::ops::Range<u64>, ecn_ce_count: Option<u64>, now: Instant| {
+            let mut acked = ranges::RangeSet::default();
+            acked.insert(range);
+
+            r.on_ack_received(
+                &acked,
+                25,
+                ecn_ce_count,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            )
+            .unwrap();
+        };
+
+        for i in 0..4 {
+            send(&mut r, i, now);
+        }
+
+        // No CE marks before the jump
+        now += Duration::from_millis(25);
+        ack(&mut r, 0..4, Some(0), now);
+
+        for i in 4..44 {
+            send(&mut r, i, now);
+        }
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(14));
+
+        // The same count reported again is not a new mark
+        now += Duration::from_millis(25);
+        ack(&mut r, 4..10, Some(0), now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(14));
+
+        // An increase without newly acked packets marks none, and isn't
+        // attributed to the next ACK frame either
+        ack(&mut r, 4..10, Some(1), now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(14));
+
+        ack(&mut r, 10..12, Some(1), now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(14));
+
+        ack(&mut r, 12..14, Some(2), now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::SafeRetreat(43));
+        // Half of the pipesize is below the initial window
+        assert_eq!(r.congestion.congestion_window, r.congestion.initial_window);
+    }
+
+    // A peer delaying ACKs by up to its 100ms max_ack_delay must not make the
+    // path look like it has a much larger RTT than the previous connection
+    #[test]
+    fn large_ack_delay_full() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.update_max_ack_delay(Duration::from_millis(100));
+        r.setup_careful_resume(Duration::from_millis(10), 120_000, 0);
+
+        let mut pkt_num = 0;
+        let mut send = |r: &mut Recovery, count: u64, now: Instant| {
+            for _ in 0..count {
+                let p = Sent {
+                    pkt_num,
+                    frames: smallvec![],
+                    time_sent: now,
+                    time_acked: None,
+                    time_lost: None,
+                    size: 1000,
+                    ack_eliciting: true,
+                    in_flight: true,
+                    delivered: 0,
+                    delivered_time: now,
+                    first_sent_time: now,
+                    is_app_limited: false,
+                    tx_in_flight: 0,
+                    lost: 0,
+                    has_data: false,
+                    pmtud: false,
+                    retransmission: false,
+                };
+
+                r.on_packet_sent(
+                    p,
+                    packet::Epoch::Application,
+                    HandshakeStatus::default(),
+                    now,
+                    "",
+                );
+                pkt_num += 1;
+            }
+        };
+
+        send(&mut r, 4, now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Reconnaissance);
+
+        // 10ms of path RTT plus 100ms of ack delay
+        now += Duration::from_millis(110);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..4);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                100_000,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 1000 * 4))
+        );
+        assert_eq!(r.rtt_stats.latest_adjusted_rtt(), Some(Duration::from_millis(10)));
+
+        // The smoothed RTT starts from the first sample with its ack delay, as
+        // RFC 9002 requires, so comparing it with the previous RTT would have
+        // prevented the jump
+        assert_eq!(r.rtt_stats.smoothed_rtt, Some(Duration::from_millis(110)));
+        assert!(!CrThresholds::default().rtt_similar(Duration::from_millis(10), r.rtt_stats.smoothed_rtt.unwrap()));
+
+        send(&mut r, 40, now);
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(14));
+        assert_eq!(r.congestion.congestion_window, 60_000);
+
+        now += Duration::from_millis(110);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(4..16);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                100_000,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 1000 * 12))
+        );
+        assert_eq!(r.congestion.resume.cr_state, CrState::Validating(43));
+
+        now += Duration::from_millis(110);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(16..44);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                100_000,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 1000 * 28))
+        );
+        assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
+    }
+
     #[test]
     fn congestion_full() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
         cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
         cfg.enable_resume(true);
+        cfg.enable_resume_retreat_export(true);
 
         let mut r = Recovery::new(&cfg, "");
         let mut now = Instant::now();
 
-        r.setup_careful_resume(Duration::from_millis(30), 120_000);
+        r.setup_careful_resume(Duration::from_millis(30), 120_000, 0);
 
         assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
 
@@ -1242,6 +5833,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -1266,6 +5858,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1295,6 +5888,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -1314,48 +5908,74 @@ mod tests {
 
         now += Duration::from_millis(25);
 
-        // Ack with one missing
+        // Ack with one missing, before the jump is validated
         let mut acked = ranges::RangeSet::default();
-        acked.insert(5..15);
+        acked.insert(5..14);
 
         assert_eq!(
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
                 "",
             ),
-            Ok((1, 1000, 1000 * 10))
+            Ok((0, 0, 1000 * 9))
+        );
+
+        // During the jump, the missing packet is only declared lost once it
+        // is late by more than the reordering window
+        now = r.loss_detection_timer().unwrap();
+
+        assert_eq!(
+            r.on_loss_detection_timeout(HandshakeStatus::default(), now, ""),
+            (1, 1000)
         );
 
         assert_eq!(r.congestion.resume.cr_state, CrState::SafeRetreat(23));
         assert_eq!(r.congestion.congestion_window, 12_000);
-        expected_pipesize += 10_000;
+        expected_pipesize += 9_000;
         assert_eq!(r.congestion.resume.pipesize, expected_pipesize);
 
         now += Duration::from_millis(25);
 
         let mut acked = ranges::RangeSet::default();
+        acked.insert(14..15);
         acked.insert(16..24);
 
         assert_eq!(
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
                 "",
             ),
-            Ok((1, 1000, 1000 * 8))
+            Ok((1, 1000, 1000 * 9))
         );
 
         assert_eq!(r.congestion.resume.cr_state, CrState::Normal);
-        expected_pipesize += 7_000;
+        expected_pipesize += 8_000;
         assert_eq!(r.congestion.resume.pipesize, expected_pipesize);
         assert_eq!(r.congestion.ssthresh, expected_pipesize);
+
+        // 1,000 of the 12,000 bytes sent were lost
+        let pacing_rate = r.congestion.pacer.rate();
+        assert_eq!(
+            r.maybe_cr_event(now),
+            Some(CREvent {
+                min_rtt: Duration::from_millis(25),
+                cwnd: expected_pipesize,
+                pacing_rate,
+                post_congestion: true,
+                max_datagram_size: 1200,
+                loss_rate_ppm: 83_333,
+            })
+        );
     }
 
     #[test]
@@ -1367,7 +5987,7 @@ mod tests {
         let mut r = Recovery::new(&cfg, "");
         let mut now = Instant::now();
 
-        r.setup_careful_resume(Duration::from_millis(30), 120_000);
+        r.setup_careful_resume(Duration::from_millis(30), 120_000, 0);
 
         assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
 
@@ -1389,6 +6009,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -1413,6 +6034,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1442,6 +6064,7 @@ mod tests {
                 lost: 0,
                 has_data: false,
                 pmtud: false,
+                retransmission: false,
             };
 
             r.on_packet_sent(
@@ -1468,6 +6091,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1489,12 +6113,20 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
                 "",
             ),
-            Ok((1, 1000, 1000 * 3))
+            Ok((0, 0, 1000 * 3))
+        );
+
+        now = r.loss_detection_timer().unwrap();
+
+        assert_eq!(
+            r.on_loss_detection_timeout(HandshakeStatus::default(), now, ""),
+            (1, 1000)
         );
 
         assert_eq!(r.congestion.resume.cr_state, CrState::SafeRetreat(43));
@@ -1510,6 +6142,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1523,4 +6156,103 @@ mod tests {
         assert_eq!(r.congestion.resume.pipesize, expected_pipesize);
         assert_eq!(r.congestion.ssthresh, expected_pipesize);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn coalesced_handshake_full() {
+        let send = |r: &mut Recovery, epoch: packet::Epoch, pkt_num: u64, now: Instant| {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(p, epoch, HandshakeStatus::default(), now, "");
+        };
+
+        let ack = |r: &mut Recovery, epoch: packet::Epoch, range: std::ops::Range<u64>, now: Instant| {
+            let mut acked = ranges::RangeSet::default();
+            acked.insert(range);
+
+            r.on_ack_received(&acked, 0, None, epoch, HandshakeStatus::default(), now, "")
+                .unwrap();
+        };
+
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+        cfg.enable_resume(true);
+
+        let mut r = Recovery::new(&cfg, "");
+        let mut now = Instant::now();
+
+        r.setup_careful_resume(Duration::from_millis(50), 120_000, 0);
+
+        // The first 1-RTT packets are coalesced with Handshake packets.
+        for i in 0..4 {
+            send(&mut r, packet::Epoch::Handshake, i, now);
+            send(&mut r, packet::Epoch::Application, i, now);
+        }
+
+        now += Duration::from_millis(50);
+        ack(&mut r, packet::Epoch::Handshake, 0..4, now);
+        ack(&mut r, packet::Epoch::Application, 0..4, now);
+
+        // All the Application packets were acked and drained, the mark is
+        // still taken after the largest one sent.
+        assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
+
+        let mut pn = 4;
+        while r.congestion.resume.cr_state == CrState::Reconnaissance {
+            send(&mut r, packet::Epoch::Application, pn, now);
+            pn += 1;
+        }
+
+        let mark = match r.congestion.resume.cr_state {
+            CrState::Unvalidated(mark) => mark,
+
+            state => panic!("unexpected state {:?}", state),
+        };
+        assert!(mark >= 4);
+
+        for i in 0..30 {
+            send(&mut r, packet::Epoch::Application, pn, now);
+            pn += 1;
+
+            // Handshake packets carry numbers above the mark in their own
+            // packet number space.
+            send(&mut r, packet::Epoch::Handshake, 4 + i, now);
+        }
+
+        let pipesize = r.congestion.resume.pipesize;
+
+        now += Duration::from_millis(50);
+        ack(&mut r, packet::Epoch::Handshake, 4..30, now);
+
+        // Acking them doesn't acknowledge the mark, nor count in the pipesize.
+        assert_eq!(r.congestion.resume.cr_state, CrState::Unvalidated(mark));
+        assert_eq!(r.congestion.resume.pipesize, pipesize);
+
+        // Losing one is a congestion signal, the safe retreat lasts until the
+        // Application packets sent so far are acked.
+        now += Duration::from_millis(200);
+        send(&mut r, packet::Epoch::Handshake, 34, now);
+
+        now += Duration::from_millis(50);
+        ack(&mut r, packet::Epoch::Handshake, 34..35, now);
+
+        assert_eq!(r.congestion.resume.cr_state, CrState::SafeRetreat(pn - 1));
+    }
+}
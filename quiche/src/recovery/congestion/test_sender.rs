@@ -82,6 +82,7 @@ impl TestSender {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         self.cc.on_packet_sent(
@@ -127,6 +128,8 @@ impl TestSender {
                 delivered_time: unacked.delivered_time,
                 first_sent_time: unacked.first_sent_time,
                 is_app_limited: unacked.is_app_limited,
+                ecn_ce: false,
+                retransmission: unacked.retransmission,
             });
 
             self.next_ack += 1;
@@ -0,0 +1,504 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! A log of the last decisions taken by loss recovery and congestion control.
+//!
+//! The log is a ring buffer of fixed size binary records, so keeping it
+//! around costs neither allocations nor more than a few hundred bytes per
+//! path. When a connection is closed with a transport error the log of its
+//! active path is kept, so that it can be attached to bug reports.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::Error;
+use crate::Result;
+
+/// The number of decisions kept in a log.
+pub const DECISION_LOG_CAPACITY: usize = 32;
+
+const DECISION_LOG_VERSION: u8 = 1;
+
+// Elapsed milliseconds, kind, Careful Resume phase, congestion window and
+// bytes in flight.
+const RECORD_LEN: usize = 4 + 1 + 1 + 4 + 4;
+
+// The Careful Resume phases, as named by `Resume::phase_name()`, indexed by
+// their code in the records.
+const CR_PHASES: [&str; 6] = [
+    "disabled",
+    "reconnaissance",
+    "unvalidated",
+    "validating",
+    "normal",
+    "safe_retreat",
+];
+
+/// A kind of decision taken by loss recovery and congestion control.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryDecisionKind {
+    /// Packets were declared lost, and the congestion controller reacted.
+    CongestionEvent,
+
+    /// The probe timeout expired.
+    ProbeTimeout,
+
+    /// The Careful Resume jump increased the congestion window.
+    CrJump,
+
+    /// Careful Resume entered another phase.
+    CrPhaseChange,
+}
+
+impl RecoveryDecisionKind {
+    fn to_wire(self) -> u8 {
+        match self {
+            RecoveryDecisionKind::CongestionEvent => 1,
+            RecoveryDecisionKind::ProbeTimeout => 2,
+            RecoveryDecisionKind::CrJump => 3,
+            RecoveryDecisionKind::CrPhaseChange => 4,
+        }
+    }
+
+    fn from_wire(v: u8) -> Result<Self> {
+        match v {
+            1 => Ok(RecoveryDecisionKind::CongestionEvent),
+            2 => Ok(RecoveryDecisionKind::ProbeTimeout),
+            3 => Ok(RecoveryDecisionKind::CrJump),
+            4 => Ok(RecoveryDecisionKind::CrPhaseChange),
+
+            _ => Err(Error::InvalidState),
+        }
+    }
+
+    #[cfg(feature = "cr-dot")]
+    fn name(self) -> &'static str {
+        match self {
+            RecoveryDecisionKind::CongestionEvent => "congestion_event",
+            RecoveryDecisionKind::ProbeTimeout => "probe_timeout",
+            RecoveryDecisionKind::CrJump => "cr_jump",
+            RecoveryDecisionKind::CrPhaseChange => "cr_phase_change",
+        }
+    }
+}
+
+/// A decision recorded in a [`RecoveryDecisionLog`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecoveryDecision {
+    /// When the decision was taken since the first packet was sent on the
+    /// path, in milliseconds.
+    pub elapsed: Duration,
+
+    /// What was decided.
+    pub kind: RecoveryDecisionKind,
+
+    /// The Careful Resume phase after the decision, e.g. `unvalidated`, or
+    /// `disabled` when Careful Resume isn't used.
+    pub cr_phase: &'static str,
+
+    /// The congestion window after the decision, in bytes.
+    pub cwnd: usize,
+
+    /// The bytes in flight when the decision was taken.
+    pub bytes_in_flight: usize,
+}
+
+/// The last decisions taken by loss recovery and congestion control on a
+/// path.
+///
+/// At most [`DECISION_LOG_CAPACITY`] decisions are kept, older ones being
+/// overwritten by newer ones.
+///
+/// [`DECISION_LOG_CAPACITY`]: constant.DECISION_LOG_CAPACITY.html
+#[derive(Clone)]
+pub struct RecoveryDecisionLog {
+    start: Option<Instant>,
+
+    records: [[u8; RECORD_LEN]; DECISION_LOG_CAPACITY],
+
+    // The index of the next record to write, and how many were written.
+    next: usize,
+
+    len: usize,
+
+    cr_phase: u8,
+}
+
+impl Default for RecoveryDecisionLog {
+    fn default() -> Self {
+        RecoveryDecisionLog {
+            start: None,
+            records: [[0; RECORD_LEN]; DECISION_LOG_CAPACITY],
+            next: 0,
+            len: 0,
+            cr_phase: 0,
+        }
+    }
+}
+
+impl RecoveryDecisionLog {
+    /// Sets the time the elapsed time of the decisions is counted from, if
+    /// not set yet.
+    pub(crate) fn start(&mut self, now: Instant) {
+        self.start.get_or_insert(now);
+    }
+
+    /// Returns whether `cr_phase` differs from the phase of the last
+    /// decision.
+    pub(crate) fn cr_phase_changed(&self, cr_phase: &str) -> bool {
+        CR_PHASES[self.cr_phase as usize] != cr_phase
+    }
+
+    pub(crate) fn record(
+        &mut self, now: Instant, kind: RecoveryDecisionKind, cr_phase: &str,
+        cwnd: usize, bytes_in_flight: usize,
+    ) {
+        let start = *self.start.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(start).as_millis();
+
+        self.cr_phase =
+            CR_PHASES.iter().position(|p| *p == cr_phase).unwrap_or(0) as u8;
+
+        let record = &mut self.records[self.next];
+        let mut b = octets::OctetsMut::with_slice(record);
+
+        // Writes can't fail as the record is large enough for all fields.
+        b.put_u32(u32::try_from(elapsed).unwrap_or(u32::MAX))
+            .unwrap();
+        b.put_u8(kind.to_wire()).unwrap();
+        b.put_u8(self.cr_phase).unwrap();
+        b.put_u32(u32::try_from(cwnd).unwrap_or(u32::MAX)).unwrap();
+        b.put_u32(u32::try_from(bytes_in_flight).unwrap_or(u32::MAX))
+            .unwrap();
+
+        self.next = (self.next + 1) % DECISION_LOG_CAPACITY;
+        self.len = (self.len + 1).min(DECISION_LOG_CAPACITY);
+    }
+
+    /// Returns the number of decisions in the log.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether no decision was recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the decisions, oldest first.
+    pub fn decisions(&self) -> Vec<RecoveryDecision> {
+        self.records_oldest_first()
+            .filter_map(|record| parse_record(record).ok())
+            .collect()
+    }
+
+    fn records_oldest_first(&self) -> impl Iterator<Item = &[u8]> {
+        let first = (self.next + DECISION_LOG_CAPACITY - self.len) %
+            DECISION_LOG_CAPACITY;
+
+        (0..self.len)
+            .map(move |i| &self.records[(first + i) % DECISION_LOG_CAPACITY][..])
+    }
+
+    /// Serializes the decisions, oldest first, e.g. to attach them to a bug
+    /// report.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(1 + self.len * RECORD_LEN);
+
+        out.push(DECISION_LOG_VERSION);
+
+        for record in self.records_oldest_first() {
+            out.extend_from_slice(record);
+        }
+
+        out
+    }
+
+    /// Parses decisions serialized with [`to_bytes()`].
+    ///
+    /// Returns [`UnknownVersion`] for another version of the format,
+    /// [`BufferTooShort`] when `buf` is truncated, and [`InvalidState`] for
+    /// an unknown decision.
+    ///
+    /// [`to_bytes()`]: struct.RecoveryDecisionLog.html#method.to_bytes
+    /// [`UnknownVersion`]: enum.Error.html#variant.UnknownVersion
+    /// [`BufferTooShort`]: enum.Error.html#variant.BufferTooShort
+    /// [`InvalidState`]: enum.Error.html#variant.InvalidState
+    pub fn from_bytes(buf: &[u8]) -> Result<Vec<RecoveryDecision>> {
+        let (version, records) =
+            buf.split_first().ok_or(Error::BufferTooShort)?;
+
+        if *version != DECISION_LOG_VERSION {
+            return Err(Error::UnknownVersion);
+        }
+
+        if records.len() % RECORD_LEN != 0 {
+            return Err(Error::BufferTooShort);
+        }
+
+        records.chunks(RECORD_LEN).map(parse_record).collect()
+    }
+
+    /// Renders the decisions, oldest first, as a Graphviz DOT graph.
+    ///
+    /// See [`decisions_to_dot()`].
+    ///
+    /// [`decisions_to_dot()`]: fn.decisions_to_dot.html
+    #[cfg(feature = "cr-dot")]
+    pub fn to_dot(&self) -> String {
+        decisions_to_dot(&self.decisions())
+    }
+}
+
+/// Renders decisions as a Graphviz DOT graph, e.g. the ones parsed by
+/// [`RecoveryDecisionLog::from_bytes()`].
+///
+/// Each decision is a node showing the Careful Resume phase after it, when
+/// it was taken, the congestion window and the bytes in flight. Nodes are
+/// chained in order by edges labelled with the kind of decision. The graph
+/// can be rendered with e.g. `dot -Tsvg`.
+///
+/// [`RecoveryDecisionLog::from_bytes()`]: struct.RecoveryDecisionLog.html#method.from_bytes
+#[cfg(feature = "cr-dot")]
+pub fn decisions_to_dot(decisions: &[RecoveryDecision]) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+
+    // Writing to a String can't fail.
+    writeln!(out, "digraph recovery_decisions {{").unwrap();
+    writeln!(out, "    rankdir=LR;").unwrap();
+    writeln!(out, "    node [shape=box];").unwrap();
+    writeln!(out, "    start [shape=point];").unwrap();
+
+    let mut prev = "start".to_string();
+
+    for (i, d) in decisions.iter().enumerate() {
+        let node = format!("d{i}");
+
+        writeln!(
+            out,
+            "    {} [label=\"{}\\n{} ms\\ncwnd {} B\\nin flight {} B\"];",
+            node,
+            d.cr_phase,
+            d.elapsed.as_millis(),
+            d.cwnd,
+            d.bytes_in_flight
+        )
+        .unwrap();
+
+        writeln!(
+            out,
+            "    {} -> {} [label=\"{}\"];",
+            prev,
+            node,
+            d.kind.name()
+        )
+        .unwrap();
+
+        prev = node;
+    }
+
+    writeln!(out, "}}").unwrap();
+
+    out
+}
+
+fn parse_record(record: &[u8]) -> Result<RecoveryDecision> {
+    let mut b = octets::Octets::with_slice(record);
+
+    let elapsed = Duration::from_millis(b.get_u32()?.into());
+    let kind = RecoveryDecisionKind::from_wire(b.get_u8()?)?;
+
+    let cr_phase = CR_PHASES
+        .get(b.get_u8()? as usize)
+        .ok_or(Error::InvalidState)?;
+
+    let cwnd = b.get_u32()? as usize;
+    let bytes_in_flight = b.get_u32()? as usize;
+
+    Ok(RecoveryDecision {
+        elapsed,
+        kind,
+        cr_phase,
+        cwnd,
+        bytes_in_flight,
+    })
+}
+
+impl fmt::Debug for RecoveryDecisionLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.decisions()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let mut log = RecoveryDecisionLog::default();
+        let now = Instant::now();
+
+        assert!(log.is_empty());
+        assert!(!log.cr_phase_changed("disabled"));
+        assert!(log.cr_phase_changed("reconnaissance"));
+
+        log.start(now);
+        log.record(
+            now + Duration::from_millis(20),
+            RecoveryDecisionKind::CrJump,
+            "unvalidated",
+            40_000,
+            12_000,
+        );
+
+        assert_eq!(log.len(), 1);
+        assert!(!log.cr_phase_changed("unvalidated"));
+        assert_eq!(log.decisions(), vec![RecoveryDecision {
+            elapsed: Duration::from_millis(20),
+            kind: RecoveryDecisionKind::CrJump,
+            cr_phase: "unvalidated",
+            cwnd: 40_000,
+            bytes_in_flight: 12_000,
+        }]);
+
+        let bytes = log.to_bytes();
+        assert_eq!(bytes.len(), 1 + RECORD_LEN);
+        assert_eq!(RecoveryDecisionLog::from_bytes(&bytes), Ok(log.decisions()));
+    }
+
+    #[test]
+    fn wrap_around() {
+        let mut log = RecoveryDecisionLog::default();
+        let now = Instant::now();
+
+        for i in 0..DECISION_LOG_CAPACITY + 5 {
+            log.record(
+                now + Duration::from_millis(i as u64),
+                RecoveryDecisionKind::CongestionEvent,
+                "normal",
+                i,
+                0,
+            );
+        }
+
+        assert_eq!(log.len(), DECISION_LOG_CAPACITY);
+
+        // The oldest decisions were overwritten.
+        let decisions = log.decisions();
+        assert_eq!(decisions.len(), DECISION_LOG_CAPACITY);
+        assert_eq!(decisions[0].cwnd, 5);
+        assert_eq!(decisions[0].elapsed, Duration::from_millis(5));
+        assert_eq!(decisions.last().unwrap().cwnd, DECISION_LOG_CAPACITY + 4);
+
+        assert_eq!(
+            RecoveryDecisionLog::from_bytes(&log.to_bytes()),
+            Ok(decisions)
+        );
+    }
+
+    #[cfg(feature = "cr-dot")]
+    #[test]
+    fn to_dot() {
+        let mut log = RecoveryDecisionLog::default();
+        let now = Instant::now();
+
+        assert_eq!(
+            log.to_dot(),
+            "digraph recovery_decisions {\n    rankdir=LR;\n    node \
+             [shape=box];\n    start [shape=point];\n}\n"
+        );
+
+        log.record(
+            now,
+            RecoveryDecisionKind::CrJump,
+            "unvalidated",
+            40_000,
+            12_000,
+        );
+        log.record(
+            now + Duration::from_millis(30),
+            RecoveryDecisionKind::CongestionEvent,
+            "safe_retreat",
+            20_000,
+            38_000,
+        );
+
+        let dot = log.to_dot();
+
+        assert!(dot.contains(
+            "    d0 [label=\"unvalidated\\n0 ms\\ncwnd 40000 B\\nin flight \
+             12000 B\"];\n    start -> d0 [label=\"cr_jump\"];\n"
+        ));
+        assert!(dot.contains(
+            "    d1 [label=\"safe_retreat\\n30 ms\\ncwnd 20000 B\\nin flight \
+             38000 B\"];\n    d0 -> d1 [label=\"congestion_event\"];\n"
+        ));
+        assert_eq!(decisions_to_dot(&log.decisions()), dot);
+    }
+
+    #[test]
+    fn from_bytes_errors() {
+        let mut log = RecoveryDecisionLog::default();
+        log.record(
+            Instant::now(),
+            RecoveryDecisionKind::ProbeTimeout,
+            "normal",
+            12_000,
+            12_000,
+        );
+
+        let bytes = log.to_bytes();
+
+        assert_eq!(
+            RecoveryDecisionLog::from_bytes(&[]),
+            Err(Error::BufferTooShort)
+        );
+        assert_eq!(
+            RecoveryDecisionLog::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(Error::BufferTooShort)
+        );
+
+        let mut other_version = bytes.clone();
+        other_version[0] = 2;
+        assert_eq!(
+            RecoveryDecisionLog::from_bytes(&other_version),
+            Err(Error::UnknownVersion)
+        );
+
+        let mut unknown_kind = bytes;
+        unknown_kind[5] = 42;
+        assert_eq!(
+            RecoveryDecisionLog::from_bytes(&unknown_kind),
+            Err(Error::InvalidState)
+        );
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Careful Resume metrics, exported through the [`metrics`] facade.
+//!
+//! With the `metrics` feature, the recovery of each path reports what
+//! Careful Resume does to the recorder installed by the application, e.g. a
+//! Prometheus exporter, so that operators get fleet-wide dashboards without
+//! observing each connection themselves:
+//!
+//! * `cr_jumps_total`: counter of jumps, i.e. of paths entering the unvalidated
+//!   phase.
+//! * `cr_retreats_total`: counter of paths entering the safe retreat phase.
+//! * `cr_validation_seconds`: histogram of the time from a jump until Careful
+//!   Resume returned to the normal phase without retreating.
+//! * `cr_current_phase`: gauge of the number of paths in each phase, labelled
+//!   with the `phase`.
+//!
+//! Only paths where Careful Resume was set up are counted.
+//!
+//! [`metrics`]: https://docs.rs/metrics
+
+use std::time::Duration;
+
+use metrics::counter;
+use metrics::gauge;
+use metrics::histogram;
+
+use super::congestion::resume::CrState;
+
+const JUMPS_TOTAL: &str = "cr_jumps_total";
+
+const RETREATS_TOTAL: &str = "cr_retreats_total";
+
+const VALIDATION_SECONDS: &str = "cr_validation_seconds";
+
+const CURRENT_PHASE: &str = "cr_current_phase";
+
+/// Returns the value of the `phase` label of `state`, as in qlog.
+fn phase_label(state: CrState) -> &'static str {
+    match state {
+        CrState::Reconnaissance => "reconnaissance",
+        CrState::Unvalidated(_) => "unvalidated",
+        CrState::Validating(_) => "validating",
+        CrState::SafeRetreat(_) => "safe_retreat",
+        CrState::Normal => "normal",
+    }
+}
+
+/// Counts a path entering `state`.
+pub(crate) fn enter_phase(state: CrState) {
+    gauge!(CURRENT_PHASE, "phase" => phase_label(state)).increment(1.0);
+
+    match state {
+        CrState::Unvalidated(_) => counter!(JUMPS_TOTAL).increment(1),
+
+        CrState::SafeRetreat(_) => counter!(RETREATS_TOTAL).increment(1),
+
+        _ => (),
+    }
+}
+
+/// Counts a path leaving `state`.
+pub(crate) fn leave_phase(state: CrState) {
+    gauge!(CURRENT_PHASE, "phase" => phase_label(state)).decrement(1.0);
+}
+
+/// Records the time a jump took to be validated.
+pub(crate) fn jump_validated(d: Duration) {
+    histogram!(VALIDATION_SECONDS).record(d.as_secs_f64());
+}
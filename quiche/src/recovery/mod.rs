@@ -26,6 +26,10 @@
 
 use std::cmp;
 
+use std::net::IpAddr;
+
+use std::sync::Arc;
+
 use std::time::Duration;
 use std::time::Instant;
 
@@ -34,6 +38,7 @@ use std::collections::VecDeque;
 use crate::packet::Epoch;
 use crate::ranges::RangeSet;
 use crate::Config;
+use crate::ConfigDelta;
 use crate::CongestionControlAlgorithm;
 use crate::Result;
 
@@ -46,9 +51,34 @@ use qlog::events::EventData;
 
 use smallvec::SmallVec;
 use congestion::resume;
+pub(crate) use congestion::resume::CrThresholds;
+pub(crate) use congestion::resume::CrObserveThresholds;
+pub use congestion::jump_limiter::CrJumpLimiter;
+pub use congestion::resume::BdpFrame;
+pub use congestion::resume::CarefulResumeState;
 pub use congestion::resume::CREvent;
+pub use congestion::resume::CongestionSignalSource;
+pub use congestion::resume::CrCounters;
+pub use congestion::resume::CrRejectReason;
+pub use congestion::resume::CrHystartPolicy;
+pub use congestion::resume::CrObservePolicy;
+pub use congestion::resume::CrPhaseObserver;
+pub use congestion::resume::CrJumpPacing;
+pub use congestion::resume::CrPhaseDurations;
+pub use congestion::resume::CrRetreatPolicy;
+pub use congestion::resume::CrValidatingPolicy;
+pub use congestion::resume::CrWorkloadHint;
+pub use congestion::resume::StoredCREvent;
+pub use decision_log::RecoveryDecision;
+pub use decision_log::RecoveryDecisionKind;
+pub use decision_log::RecoveryDecisionLog;
+pub use decision_log::DECISION_LOG_CAPACITY;
+#[cfg(feature = "cr-dot")]
+pub use decision_log::decisions_to_dot;
 use self::congestion::pacer;
 use self::congestion::Congestion;
+use self::peer_resume::PeerResumeDetector;
+pub(crate) use self::peer_resume::SuspectedPeerJump;
 use self::rtt::RttStats;
 
 // Loss Recovery
@@ -58,6 +88,10 @@ const MAX_PACKET_THRESHOLD: u64 = 20;
 
 const INITIAL_TIME_THRESHOLD: f64 = 9.0 / 8.0;
 
+// The reordering window used during a Careful Resume jump is this multiple of
+// min_rtt/4 at most, but never more than the smoothed RTT.
+const MAX_CR_REORDERING_WINDOW_MULT: u32 = 4;
+
 const GRANULARITY: Duration = Duration::from_millis(1);
 
 const PERSISTENT_CONGESTION_THRESHOLD: u32 = 3;
@@ -91,6 +125,13 @@ struct RecoveryEpoch {
     /// about them.
     sent_packets: VecDeque<Sent>,
 
+    /// The largest ECN-CE count reported by the peer in the packet number
+    /// space so far.
+    ecn_ce_count: u64,
+
+    /// The largest packet number sent in the packet number space so far.
+    largest_sent_packet: Option<u64>,
+
     loss_probes: usize,
     in_flight_count: usize,
 
@@ -178,6 +219,8 @@ impl RecoveryEpoch {
                         delivered_time: unacked.delivered_time,
                         first_sent_time: unacked.first_sent_time,
                         is_app_limited: unacked.is_app_limited,
+                        ecn_ce: false,
+                        retransmission: unacked.retransmission,
                     });
 
                     trace!("{} packet newly acked {}", trace_id, unacked.pkt_num);
@@ -203,7 +246,7 @@ impl RecoveryEpoch {
     }
 
     fn detect_lost_packets(
-        &mut self, loss_delay: Duration, pkt_thresh: u64, now: Instant,
+        &mut self, loss_delay: Duration, pkt_thresh: Option<u64>, now: Instant,
         trace_id: &str, epoch: Epoch,
     ) -> LossDetectionResult {
         self.loss_time = None;
@@ -229,10 +272,14 @@ impl RecoveryEpoch {
         .filter(|p| p.time_acked.is_none() && p.time_lost.is_none());
 
         for unacked in unacked_iter {
+            let reordered_past_thresh = match pkt_thresh {
+                Some(t) => largest_acked >= unacked.pkt_num + t,
+
+                None => false,
+            };
+
             // Mark packet as lost, or set time when it should be marked.
-            if unacked.time_sent <= lost_send_time ||
-                largest_acked >= unacked.pkt_num + pkt_thresh
-            {
+            if unacked.time_sent <= lost_send_time || reordered_past_thresh {
                 self.lost_frames.extend(unacked.frames.drain(..));
 
                 unacked.time_lost = Some(now);
@@ -338,6 +385,10 @@ pub struct Recovery {
 
     time_thresh: f64,
 
+    // Multiple of min_rtt/4 used as the reordering window during a Careful
+    // Resume jump, increased by spurious losses.
+    cr_reordering_window_mult: u32,
+
     bytes_in_flight: usize,
 
     bytes_sent: usize,
@@ -347,9 +398,12 @@ pub struct Recovery {
     max_datagram_size: usize,
 
 
+    // The metrics last written to qlog, and last seen when looking for changes
     #[cfg(feature = "qlog")]
     qlog_metrics: QlogMetrics,
     #[cfg(feature = "qlog")]
+    qlog_metrics_seen: QlogMetrics,
+    #[cfg(feature = "qlog")]
     qlog_packet_loss: VecDeque<EventData>,
 
     /// How many non-ack-eliciting packets have been sent.
@@ -359,16 +413,91 @@ pub struct Recovery {
 
     /// A resusable list of acks.
     newly_acked: Vec<Acked>,
+
+    /// Bytes sent in the Application epoch within the initial window.
+    iw_bytes_sent: usize,
+
+    /// The last Application packet sent within the initial window.
+    iw_last_pkt: Option<u64>,
+
+    /// Whether all packets sent within the initial window were acknowledged.
+    iw_acked: bool,
+
+    /// How many congestion signals were reported from outside the transport.
+    external_congestion_count: u64,
+
+    /// Whether the sender was limited by the congestion window as of the
+    /// last update of the limited time, and when that was.
+    limited_clock: Option<(bool, Instant)>,
+
+    /// The time spent with room left in the congestion window.
+    app_limited_time: Duration,
+
+    /// The time spent with the congestion window full.
+    cwnd_limited_time: Duration,
+
+    /// When the largest acknowledged Application packet was sent, to tell
+    /// when congestion recovery is over.
+    largest_acked_time_sent: Option<Instant>,
+
+    /// The value of `bytes_sent` once the data the application expects to
+    /// send has been sent, if it gave a hint.
+    cr_transfer_end: Option<usize>,
+
+    /// The largest Application packet sent before the handshake completed,
+    /// when Careful Resume may jump after 0-RTT data.
+    cr_early_data_last_pkt: Option<u64>,
+
+    /// The connection-level flow control credit left by the peer, if known.
+    cr_flow_control_credit: Option<usize>,
+
+    /// The queuing delay the application tolerates, if it set one.
+    latency_budget: Option<Duration>,
+
+    /// The last congestion control and Careful Resume decisions.
+    decision_log: RecoveryDecisionLog,
+
+    /// Detects a Careful Resume jump by the peer from the data it sends.
+    peer_resume: PeerResumeDetector,
+
+    /// The largest burst of packets sent while the Careful Resume jump is
+    /// unvalidated, 0 if unlimited.
+    cr_max_burst: usize,
+
+    /// When the current burst started, the bytes sent in it, and when the
+    /// next one may start once it reached the limit.
+    burst_start: Option<Instant>,
+    burst_bytes: usize,
+    burst_release: Option<Instant>,
 }
 
 pub struct RecoveryConfig {
     max_send_udp_payload_size: usize,
     pub max_ack_delay: Duration,
     cc_algorithm: CongestionControlAlgorithm,
+    pub resume: bool,
     hystart: bool,
     pacing: bool,
     max_pacing_rate: Option<u64>,
     initial_congestion_window_packets: usize,
+    resume_retreat_export: bool,
+    cr_min_rtts_before_jump: usize,
+    cr_validating_policy: CrValidatingPolicy,
+    cr_jump_pacing: CrJumpPacing,
+    cr_hystart_policy: CrHystartPolicy,
+    cr_retreat_policy: CrRetreatPolicy,
+    cr_reconnaissance_timeout: Duration,
+    cr_idle_threshold: Duration,
+    cr_unvalidated_timeout: Duration,
+    cr_unvalidated_timeout_rtts: u32,
+    cr_early_data: bool,
+    cr_validating_timeout: Duration,
+    cr_max_unvalidated_burst: usize,
+    pub cr_thresholds: CrThresholds,
+    pub cr_jump_limiter: Option<CrJumpLimiter>,
+    pub cr_phase_observer: Option<Arc<dyn CrPhaseObserver>>,
+    cr_observe_thresholds: CrObserveThresholds,
+    ack_aggregation_compensation: bool,
 }
 
 impl RecoveryConfig {
@@ -377,13 +506,70 @@ impl RecoveryConfig {
             max_send_udp_payload_size: config.max_send_udp_payload_size,
             max_ack_delay: Duration::ZERO,
             cc_algorithm: config.cc_algorithm,
-            hystart: config.hystart,
-            pacing: config.pacing,
-            max_pacing_rate: config.max_pacing_rate,
+            resume: config.cr.resume,
+            hystart: config.cr.hystart,
+            pacing: config.cr.pacing,
+            max_pacing_rate: config.cr.max_pacing_rate,
             initial_congestion_window_packets: config
                 .initial_congestion_window_packets,
+            resume_retreat_export: config.cr.retreat_export,
+            cr_min_rtts_before_jump: config.cr.min_rtts_before_jump,
+            cr_validating_policy: config.cr.validating_policy,
+            cr_jump_pacing: config.cr.jump_pacing,
+            cr_hystart_policy: config.cr.hystart_policy,
+            cr_retreat_policy: config.cr.retreat_policy,
+            cr_reconnaissance_timeout: config.cr.reconnaissance_timeout,
+            cr_idle_threshold: config.cr.idle_threshold,
+            cr_unvalidated_timeout: config.cr.unvalidated_timeout,
+            cr_unvalidated_timeout_rtts: config.cr.unvalidated_timeout_rtts,
+            cr_early_data: config.cr.early_data,
+            cr_validating_timeout: config.cr.validating_timeout,
+            cr_max_unvalidated_burst: config.cr.max_unvalidated_burst,
+            cr_thresholds: config.cr.thresholds,
+            cr_jump_limiter: config.cr.jump_limiter.clone(),
+            cr_phase_observer: config.cr_phase_observer.clone(),
+            cr_observe_thresholds: config.cr.observe_thresholds,
+            ack_aggregation_compensation: config.ack_aggregation_compensation,
+        }
+    }
+
+    /// Applies the settings overridden by `delta`.
+    pub fn apply_delta(&mut self, delta: &ConfigDelta) {
+        if let Some(algo) = delta.cc_algorithm {
+            self.cc_algorithm = algo;
+        }
+
+        if let Some(packets) = delta.initial_congestion_window_packets {
+            self.initial_congestion_window_packets = packets;
+        }
+
+        if let Some(cr) = &delta.cr {
+            self.resume = cr.resume;
+            self.hystart = cr.hystart;
+            self.pacing = cr.pacing;
+            self.max_pacing_rate = cr.max_pacing_rate;
+            self.resume_retreat_export = cr.retreat_export;
+            self.cr_min_rtts_before_jump = cr.min_rtts_before_jump;
+            self.cr_validating_policy = cr.validating_policy;
+            self.cr_jump_pacing = cr.jump_pacing;
+            self.cr_hystart_policy = cr.hystart_policy;
+            self.cr_retreat_policy = cr.retreat_policy;
+            self.cr_reconnaissance_timeout = cr.reconnaissance_timeout;
+            self.cr_idle_threshold = cr.idle_threshold;
+            self.cr_unvalidated_timeout = cr.unvalidated_timeout;
+            self.cr_unvalidated_timeout_rtts = cr.unvalidated_timeout_rtts;
+            self.cr_early_data = cr.early_data;
+            self.cr_validating_timeout = cr.validating_timeout;
+            self.cr_max_unvalidated_burst = cr.max_unvalidated_burst;
+            self.cr_thresholds = cr.thresholds;
+            self.cr_jump_limiter = cr.jump_limiter.clone();
+            self.cr_observe_thresholds = cr.observe_thresholds;
         }
     }
+
+    pub fn cc_algorithm(&self) -> CongestionControlAlgorithm {
+        self.cc_algorithm
+    }
 }
 
 impl Recovery {
@@ -403,6 +589,8 @@ impl Recovery {
 
             time_thresh: INITIAL_TIME_THRESHOLD,
 
+            cr_reordering_window_mult: 1,
+
             bytes_in_flight: 0,
 
             bytes_sent: 0,
@@ -414,6 +602,8 @@ impl Recovery {
             #[cfg(feature = "qlog")]
             qlog_metrics: QlogMetrics::default(),
             #[cfg(feature = "qlog")]
+            qlog_metrics_seen: QlogMetrics::default(),
+            #[cfg(feature = "qlog")]
             qlog_packet_loss: VecDeque::new(),
 
             outstanding_non_ack_eliciting: 0,
@@ -421,6 +611,42 @@ impl Recovery {
             congestion: Congestion::from_config(recovery_config, trace_id),
 
             newly_acked: Vec::new(),
+
+            iw_bytes_sent: 0,
+
+            iw_last_pkt: None,
+
+            iw_acked: false,
+
+            external_congestion_count: 0,
+
+            limited_clock: None,
+
+            app_limited_time: Duration::ZERO,
+
+            cwnd_limited_time: Duration::ZERO,
+
+            largest_acked_time_sent: None,
+
+            cr_transfer_end: None,
+
+            cr_early_data_last_pkt: None,
+
+            cr_flow_control_credit: None,
+
+            latency_budget: None,
+
+            decision_log: RecoveryDecisionLog::default(),
+
+            peer_resume: PeerResumeDetector::default(),
+
+            cr_max_burst: recovery_config.cr_max_unvalidated_burst,
+
+            burst_start: None,
+
+            burst_bytes: 0,
+
+            burst_release: None,
         }
     }
 
@@ -487,6 +713,8 @@ impl Recovery {
             self.outstanding_non_ack_eliciting += 1;
         }
 
+        self.decision_log.start(now);
+
         if in_flight && ack_eliciting {
             self.epochs[epoch].time_of_last_ack_eliciting_packet = Some(now);
         }
@@ -501,12 +729,78 @@ impl Recovery {
             in_flight,
         );
 
+        if in_flight {
+            self.update_limited_clock(!self.congestion.app_limited, now);
+        }
+
+        // The initial window is closed once it has been filled or the first
+        // Application ACK has been received.
+        if epoch == packet::Epoch::Application &&
+            in_flight &&
+            self.epochs[epoch].largest_acked_packet.is_none() &&
+            self.iw_bytes_sent < self.congestion.initial_window
+        {
+            self.iw_bytes_sent += sent_bytes;
+            self.iw_last_pkt = Some(pkt.pkt_num);
+        }
+
         if self.congestion.resume.enabled() && epoch == packet::Epoch::Application {
-            let largest_sent_pkt = self.epochs[epoch].sent_packets.iter().map(|p| p.pkt_num).max().unwrap_or_default();
-            // Increase the congestion window by a jump determined by careful resume
-            self.congestion.congestion_window += self.congestion.resume.send_packet(
-                self.rtt_stats.smoothed_rtt, self.congestion.congestion_window, largest_sent_pkt, self.congestion.app_limited
+            let largest_sent_pkt = self.cr_largest_sent_pkt();
+
+            if let Some(end) = self.cr_transfer_end {
+                self.congestion.resume.check_transfer_size(
+                    end.saturating_sub(self.bytes_sent), self.congestion.congestion_window
+                );
+            }
+
+            self.congestion.resume.check_idle(now);
+
+            // 0-RTT data is sent before any RTT sample is taken, so the RTT
+            // stored with the session stands in for it until the handshake
+            // completes, and the handshake confirms the path in place of the
+            // acknowledgement of the initial window
+            let early_data = self.congestion.resume.early_data();
+            if early_data && !handshake_status.completed {
+                self.cr_early_data_last_pkt = Some(pkt.pkt_num);
+
+                if let (None, Some(rtt)) = (self.rtt_stats.latest_adjusted_rtt(), self.rtt_stats.initial_rtt) {
+                    self.congestion.resume.check_provisional_rtt(rtt);
+                }
+            }
+            let path_confirmed = self.iw_acked || (early_data && handshake_status.completed);
+
+            let new_cwnd = self.congestion.resume.check_phase_timeouts(now, largest_sent_pkt);
+            if new_cwnd != 0 {
+                self.congestion.congestion_window = cmp::max(new_cwnd, self.congestion.initial_window);
+                (self.congestion.cc_ops.on_cr_retreat)(&mut self.congestion, now);
+            }
+
+            // The jump is only useful up to what the peer lets the sender
+            // put in flight
+            let flow_control_limit = self.cr_flow_control_credit.map(|v| self.bytes_in_flight + sent_bytes + v);
+            self.congestion.resume.set_flow_control_limit(flow_control_limit);
+            self.congestion.resume.set_delivery_rate(self.congestion.delivery_rate());
+
+            // Increase the congestion window by a jump determined by careful resume.
+            // The latest sample without the peer's ack delay is compared with the
+            // previous RTT, as the smoothed RTT includes the whole ack delay of
+            // the first sample
+            let jump = self.congestion.resume.send_packet(
+                self.rtt_stats.latest_adjusted_rtt(), self.congestion.congestion_window, largest_sent_pkt,
+                path_confirmed, self.congestion.app_limited
             );
+
+            if jump > 0 {
+                self.congestion.resume.set_pre_jump_ssthresh(self.congestion.ssthresh);
+                self.congestion.congestion_window += jump;
+                (self.congestion.cc_ops.on_cr_jump)(&mut self.congestion, now);
+                self.record_decision(RecoveryDecisionKind::CrJump, now);
+            }
+
+            self.congestion.resume.on_bytes_sent(sent_bytes);
+            self.count_burst(sent_bytes, now, trace_id);
+
+            self.record_cr_phase(now);
         }
 
         if in_flight {
@@ -518,6 +812,7 @@ impl Recovery {
 
         self.bytes_sent += sent_bytes;
 
+        self.epochs[epoch].largest_sent_packet = Some(pkt.pkt_num);
         self.epochs[epoch].sent_packets.push_back(pkt);
 
         trace!("{} {:?}", trace_id, self);
@@ -530,7 +825,7 @@ impl Recovery {
     #[allow(clippy::too_many_arguments)]
     pub fn on_ack_received(
         &mut self, ranges: &ranges::RangeSet, ack_delay: u64,
-        epoch: packet::Epoch, handshake_status: HandshakeStatus, now: Instant,
+        ecn_ce_count: Option<u64>, epoch: packet::Epoch, handshake_status: HandshakeStatus, now: Instant,
         trace_id: &str,
     ) -> Result<(usize, usize, usize)> {
         let largest_acked = ranges.last().unwrap();
@@ -561,6 +856,11 @@ impl Recovery {
         if let Some(thresh) = spurious_pkt_thresh {
             self.pkt_thresh =
                 self.pkt_thresh.max(thresh.min(MAX_PACKET_THRESHOLD));
+
+            self.cr_reordering_window_mult = cmp::min(
+                self.cr_reordering_window_mult + 1,
+                MAX_CR_REORDERING_WINDOW_MULT,
+            );
         }
 
         // Undo congestion window update.
@@ -568,13 +868,53 @@ impl Recovery {
             (self.congestion.cc_ops.rollback)(&mut self.congestion);
         }
 
+        // An increase in the ECN-CE count means some of the newly acked
+        // packets were marked, but not which, so attribute the marks to the
+        // largest one. The count is recorded even when nothing is newly
+        // acked, so that an increase isn't attributed to a later ACK.
+        if let Some(ecn_ce_count) = ecn_ce_count {
+            if ecn_ce_count > self.epochs[epoch].ecn_ce_count {
+                self.epochs[epoch].ecn_ce_count = ecn_ce_count;
+
+                if let Some(largest) = self.newly_acked.last_mut() {
+                    largest.ecn_ce = true;
+                }
+            }
+        }
+
         if self.newly_acked.is_empty() {
             return Ok((0, 0, 0));
         }
 
+        // Bytes acknowledged faster than they were sent are not evidence of
+        // path capacity.
+        let aggregated = if epoch == packet::Epoch::Application {
+            let aggregated = self.congestion.ack_aggregation.on_ack(
+                acked_bytes,
+                self.newly_acked.last().unwrap().time_sent,
+                self.max_datagram_size,
+                now,
+            );
+
+            if self.congestion.ack_aggregation.compensate() {
+                aggregated
+            } else {
+                0
+            }
+        } else {
+            0
+        };
+
         // Check if largest packet is newly acked.
         let largest_newly_acked = self.newly_acked.last().unwrap();
 
+        if epoch == packet::Epoch::Application {
+            self.largest_acked_time_sent = cmp::max(
+                self.largest_acked_time_sent,
+                Some(largest_newly_acked.time_sent),
+            );
+        }
+
         if largest_newly_acked.pkt_num == largest_acked && has_ack_eliciting {
             let latest_rtt = now - largest_newly_acked.time_sent;
             self.rtt_stats.update_rtt(
@@ -583,27 +923,64 @@ impl Recovery {
                 now,
                 handshake_status.completed,
             );
+
+            if self.congestion.resume.enabled() {
+                self.congestion.resume.on_rtt_sample();
+            }
+        }
+
+        if epoch == packet::Epoch::Application && !self.iw_acked {
+            if let Some(iw_last_pkt) = self.iw_last_pkt {
+                self.iw_acked = !self.epochs[epoch]
+                    .sent_packets
+                    .iter()
+                    .take_while(|p| p.pkt_num <= iw_last_pkt)
+                    .any(|p| p.in_flight && p.time_acked.is_none());
+            }
         }
 
         // Detect and mark lost packets without removing them from the sent
         // packets list.
         let loss = self.detect_lost_packets(epoch, now, trace_id);
 
-        if self.congestion.resume.enabled() {
+        // The Careful Resume marks are Application packet numbers, so ACKs
+        // for Initial and Handshake packets, e.g. coalesced with the first
+        // 1-RTT packets, must not be compared with them.
+        if self.congestion.resume.enabled() && epoch == packet::Epoch::Application {
+            self.congestion.resume.on_ack_aggregation(aggregated);
+
+            let in_safe_retreat = self.congestion.resume.in_safe_retreat();
+
             for packet in self.newly_acked.iter() {
-                let largest_sent_pkt = self.epochs[epoch].sent_packets.iter().map(|p| p.pkt_num).max().unwrap_or_default();
+                let largest_sent_pkt = self.cr_largest_sent_pkt();
                 let (new_cwnd, new_ssthresh) = self.congestion.resume.process_ack(
                     largest_sent_pkt, packet, self.bytes_in_flight
                 );
                 if let Some(new_cwnd) = new_cwnd {
-                    self.congestion.congestion_window = new_cwnd;
+                    self.congestion.congestion_window =
+                        cmp::max(new_cwnd, self.congestion.initial_window);
                 }
                 if let Some(new_ssthresh) = new_ssthresh {
                     self.congestion.ssthresh = new_ssthresh;
+
+                    // The capacity of the path is the pipesize, whatever the
+                    // slow start threshold is set to
+                    let pipesize = self.congestion.resume.pipesize();
+                    self.congestion.cr_metrics.on_safe_retreat_exit(pipesize);
                 }
             }
+
+            // A CE mark in the jumped window makes careful resume retreat
+            if !in_safe_retreat && self.congestion.resume.in_safe_retreat() {
+                (self.congestion.cc_ops.on_cr_retreat)(&mut self.congestion, now);
+            }
         }
 
+        self.congestion.resume.update_phase_clock(now);
+        self.record_cr_phase(now);
+
+        let cwnd_before = self.congestion.congestion_window;
+
         self.congestion.on_packets_acked(
             self.bytes_in_flight,
             &mut self.newly_acked,
@@ -611,8 +988,35 @@ impl Recovery {
             now,
         );
 
+        if self.congestion.resume.enabled() {
+            self.congestion.congestion_window = self
+                .congestion
+                .resume
+                .limit_validating_growth(
+                    cwnd_before,
+                    self.congestion.congestion_window,
+                );
+        }
+
+        if aggregated > 0 {
+            self.congestion
+                .limit_aggregated_growth(cwnd_before, acked_bytes, aggregated);
+        }
+
         self.bytes_in_flight -= acked_bytes;
 
+        // Nothing left in flight means the window can't be full.
+        if let Some((cwnd_limited, _)) = self.limited_clock {
+            self.update_limited_clock(
+                cwnd_limited && self.bytes_in_flight > 0,
+                now,
+            );
+        }
+
+        if self.bytes_in_flight == 0 && self.congestion.resume.enabled() {
+            self.congestion.resume.on_idle(now);
+        }
+
         self.pto_count = 0;
 
         self.set_loss_detection_timer(handshake_status, now);
@@ -658,6 +1062,21 @@ impl Recovery {
 
         self.pto_count += 1;
 
+        // The PTO is a congestion signal while a Careful Resume jump isn't
+        // validated, so the jumped window doesn't persist.
+        if self.congestion.resume.enabled() {
+            let largest_sent_pkt = self.cr_largest_sent_pkt();
+            let new_cwnd = self.congestion.resume.pto_event(largest_sent_pkt);
+            if new_cwnd != 0 {
+                self.congestion.congestion_window = cmp::max(new_cwnd, self.congestion.initial_window);
+                (self.congestion.cc_ops.on_cr_retreat)(&mut self.congestion, now);
+            }
+
+            self.congestion.resume.update_phase_clock(now);
+        }
+
+        self.record_decision(RecoveryDecisionKind::ProbeTimeout, now);
+
         let epoch = &mut self.epochs[epoch];
 
         epoch.loss_probes =
@@ -733,15 +1152,67 @@ impl Recovery {
         self.congestion.congestion_window()
     }
 
+    pub fn pacing_rate(&self) -> Option<u64> {
+        let pacer = &self.congestion.pacer;
+
+        if !pacer.enabled() || pacer.rate() == 0 {
+            return None;
+        }
+
+        Some(pacer.rate())
+    }
+
+    pub fn ssthresh(&self) -> Option<usize> {
+        self.congestion.ssthresh()
+    }
+
+    pub fn congestion_phase(&self) -> Option<congestion::CongestionPhase> {
+        self.congestion.phase()
+    }
+
     pub fn cwnd_available(&self) -> usize {
         // Ignore cwnd when sending probe packets.
         if self.epochs.iter().any(|e| e.loss_probes > 0) {
             return usize::MAX;
         }
 
+        if self.burst_release.is_some() && self.cr_burst_limit().is_some() {
+            return 0;
+        }
+
+        let cwnd = cmp::min(self.cwnd(), self.latency_budget_cwnd());
+
         // Open more space (snd_cnt) for PRR when allowed.
-        self.cwnd().saturating_sub(self.bytes_in_flight) +
-            self.congestion.prr.snd_cnt
+        cwnd.saturating_sub(self.bytes_in_flight) + self.congestion.prr.snd_cnt
+    }
+
+    // The largest congestion window that doesn't queue data for longer than
+    // the latency budget: the bandwidth-delay product, plus what the path
+    // drains during the budget. During the Careful Resume jump the path is
+    // assumed to have the capacity of the previous connection, until the
+    // delivery rate shows otherwise. Unbounded until a rate is known.
+    fn latency_budget_cwnd(&self) -> usize {
+        let (budget, min_rtt) = match (self.latency_budget, self.min_rtt()) {
+            (Some(budget), Some(min_rtt)) => (budget, min_rtt),
+
+            _ => return usize::MAX,
+        };
+
+        let resume = &self.congestion.resume;
+
+        let rate = if resume.in_jump() {
+            cmp::max(self.delivery_rate(), resume.previous_rate())
+        } else {
+            self.delivery_rate()
+        };
+
+        if rate == 0 {
+            return usize::MAX;
+        }
+
+        let cwnd = (rate as f64 * (min_rtt + budget).as_secs_f64()) as usize;
+
+        cmp::max(cwnd, self.max_datagram_size * MINIMUM_WINDOW_PACKETS)
     }
 
     pub fn rtt(&self) -> Duration {
@@ -764,6 +1235,21 @@ impl Recovery {
         self.congestion.delivery_rate()
     }
 
+    pub fn rate_sample(&self) -> RateSample {
+        let rate = &self.congestion.delivery_rate;
+
+        RateSample {
+            delivered: rate.sample_delivered(),
+            interval: rate.sample_interval(),
+            delivery_rate: rate.sample_delivery_rate(),
+            rtt: rate.sample_rtt(),
+            is_app_limited: rate.sample_is_app_limited(),
+            cwnd: self.cwnd(),
+            bytes_in_flight: self.bytes_in_flight,
+            cr_phase: self.congestion.resume.phase_name(),
+        }
+    }
+
     pub fn max_datagram_size(&self) -> usize {
         self.max_datagram_size
     }
@@ -892,12 +1378,24 @@ impl Recovery {
     fn detect_lost_packets(
         &mut self, epoch: packet::Epoch, now: Instant, trace_id: &str,
     ) -> (usize, usize) {
-        let loss_delay = cmp::max(self.rtt_stats.latest_rtt, self.rtt())
-            .mul_f64(self.time_thresh);
+        let rtt = cmp::max(self.rtt_stats.latest_rtt, self.rtt());
+
+        // The burst sent when Careful Resume jumps makes reordering more
+        // likely, and a spurious loss would end the jump. Until the jump is
+        // validated, packets are only deemed lost once they are late by more
+        // than the reordering window, as in RACK, rather than by the packet
+        // threshold.
+        let loss_delay = rtt.mul_f64(self.time_thresh);
+
+        let (loss_delay, pkt_thresh) = if self.congestion.resume.in_jump() {
+            (cmp::max(loss_delay, rtt + self.cr_reordering_window()), None)
+        } else {
+            (loss_delay, Some(self.pkt_thresh))
+        };
 
         let loss = self.epochs[epoch].detect_lost_packets(
             loss_delay,
-            self.pkt_thresh,
+            pkt_thresh,
             now,
             trace_id,
             epoch,
@@ -918,13 +1416,25 @@ impl Recovery {
 
             self.bytes_in_flight -= loss.lost_bytes;
 
-            if self.congestion.resume.enabled() {
-                let largest_sent_pkt = self.epochs[epoch].sent_packets.iter().map(|p| p.pkt_num).max().unwrap_or_default();
+            // Packets sent as 0-RTT data are lost when the server rejects
+            // it, which says nothing about the path.
+            let early_data_lost = epoch == packet::Epoch::Application &&
+                self.cr_early_data_last_pkt.map_or(false, |v| pkt.pkt_num <= v);
+
+            // A loss in any epoch is a congestion signal, but the safe
+            // retreat ends with the Application packets sent so far.
+            if self.congestion.resume.enabled() && !early_data_lost {
+                let largest_sent_pkt = self.cr_largest_sent_pkt();
                 let new_cwnd = self.congestion.resume.congestion_event(largest_sent_pkt);
                 if new_cwnd != 0 {
                     self.congestion.congestion_window = cmp::max(new_cwnd, self.congestion.initial_window);
+                    (self.congestion.cc_ops.on_cr_retreat)(&mut self.congestion, now);
                 }
+
+                self.congestion.resume.update_phase_clock(now);
             }
+
+            self.record_decision(RecoveryDecisionKind::CongestionEvent, now);
         };
 
         self.bytes_in_flight -= loss.pmtud_lost_bytes;
@@ -933,25 +1443,291 @@ impl Recovery {
             .drain_acked_and_lost_packets(now - self.rtt_stats.rtt());
 
         self.congestion.lost_count += loss.lost_packets;
+        self.bytes_lost += loss.lost_bytes as u64;
 
         (loss.lost_packets, loss.lost_bytes)
     }
 
+    // The largest packet number sent in the Application epoch, which the
+    // Careful Resume marks refer to.
+    fn cr_largest_sent_pkt(&self) -> u64 {
+        self.epochs[packet::Epoch::Application]
+            .largest_sent_packet
+            .unwrap_or_default()
+    }
+
+    // The reordering window tolerated during a Careful Resume jump.
+    fn cr_reordering_window(&self) -> Duration {
+        let min_rtt = self.rtt_stats.min_rtt().unwrap_or(self.rtt());
+
+        cmp::min(min_rtt / 4 * self.cr_reordering_window_mult, self.rtt())
+    }
+
     pub fn update_app_limited(&mut self, v: bool) {
         self.congestion.app_limited = v;
     }
 
+    // Adds the time since the last update to the time spent in the previous
+    // state, and starts timing `cwnd_limited`.
+    fn update_limited_clock(&mut self, cwnd_limited: bool, now: Instant) {
+        if let Some((was_cwnd_limited, since)) = self.limited_clock {
+            let elapsed = now.saturating_duration_since(since);
+
+            if was_cwnd_limited {
+                self.cwnd_limited_time += elapsed;
+            } else {
+                self.app_limited_time += elapsed;
+            }
+        }
+
+        self.limited_clock = Some((cwnd_limited, now));
+    }
+
+    pub fn app_limited_time(&self) -> Duration {
+        self.app_limited_time
+    }
+
+    pub fn cwnd_limited_time(&self) -> Duration {
+        self.cwnd_limited_time
+    }
+
     #[cfg(test)]
     pub fn app_limited(&self) -> bool {
         self.congestion.app_limited
     }
 
+    #[cfg(any(test, feature = "ffi"))]
+    pub fn resume_enabled(&self) -> bool {
+        self.congestion.resume.enabled()
+    }
+
+    #[cfg(feature = "ffi")]
+    pub fn resume_in_jump(&self) -> bool {
+        self.congestion.resume.in_jump()
+    }
+
+    /// Returns whether all packets sent in the Application epoch within the
+    /// initial window have been acknowledged.
+    pub fn initial_window_acked(&self) -> bool {
+        self.iw_acked
+    }
+
+    pub fn ack_aggregation_count(&self) -> u64 {
+        self.congestion.ack_aggregation.count()
+    }
+
+    pub fn ack_aggregation_bytes(&self) -> u64 {
+        self.congestion.ack_aggregation.bytes()
+    }
+
+    // Handles a congestion signal from outside the transport, e.g. from an
+    // AQM or the link layer. Careful resume handles it as a CE mark, the
+    // congestion controller doesn't react to it.
+    pub fn on_external_congestion(&mut self, source: CongestionSignalSource, now: Instant) {
+        self.external_congestion_count += 1;
+
+        if !self.congestion.resume.enabled() {
+            return;
+        }
+
+        let largest_sent_pkt = self.cr_largest_sent_pkt();
+        let new_cwnd = self.congestion.resume.external_congestion_event(largest_sent_pkt, source);
+        if new_cwnd != 0 {
+            self.congestion.congestion_window = cmp::max(new_cwnd, self.congestion.initial_window);
+            (self.congestion.cc_ops.on_cr_retreat)(&mut self.congestion, now);
+        }
+
+        self.congestion.resume.update_phase_clock(now);
+        self.record_cr_phase(now);
+    }
+
+    /// Abandons careful resume when the connection stops using the path.
+    pub fn on_cr_path_change(&mut self, now: Instant) {
+        let new_cwnd = self.congestion.resume.on_path_change();
+        self.on_cr_abandoned(new_cwnd, now);
+    }
+
+    /// Abandons careful resume at the request of the application.
+    pub fn abandon_careful_resume(&mut self, now: Instant) {
+        let new_cwnd = self.congestion.resume.on_abandon();
+        self.on_cr_abandoned(new_cwnd, now);
+    }
+
+    /// Resets careful resume along with the recovery state, e.g. after a
+    /// Retry, so that no marks from before the reset survive it.
+    ///
+    /// With `preserve_params`, careful resume still in progress starts again
+    /// from the reconnaissance phase with the same previous parameters.
+    pub fn reset_careful_resume(&mut self, preserve_params: bool, now: Instant) {
+        let new_cwnd = self.congestion.resume.reset(preserve_params);
+
+        self.iw_bytes_sent = 0;
+        self.iw_last_pkt = None;
+        self.iw_acked = false;
+        self.cr_early_data_last_pkt = None;
+        self.burst_start = None;
+        self.burst_bytes = 0;
+        self.burst_release = None;
+
+        self.on_cr_abandoned(new_cwnd, now);
+    }
+
+    // Reduces the congestion window to the pipesize returned when careful
+    // resume was abandoned, if the jumped window wasn't validated yet.
+    fn on_cr_abandoned(&mut self, new_cwnd: usize, now: Instant) {
+        if new_cwnd != 0 {
+            self.congestion.congestion_window = cmp::max(new_cwnd, self.congestion.initial_window);
+            (self.congestion.cc_ops.on_cr_retreat)(&mut self.congestion, now);
+        }
+
+        self.congestion.resume.update_phase_clock(now);
+        self.record_cr_phase(now);
+    }
+
+    pub fn external_congestion_count(&self) -> u64 {
+        self.external_congestion_count
+    }
+
+    /// Returns the last congestion control and Careful Resume decisions.
+    pub fn decision_log(&self) -> &RecoveryDecisionLog {
+        &self.decision_log
+    }
+
+    fn record_decision(&mut self, kind: RecoveryDecisionKind, now: Instant) {
+        self.decision_log.record(
+            now,
+            kind,
+            self.congestion.resume.phase_name(),
+            self.congestion.congestion_window,
+            self.bytes_in_flight,
+        );
+    }
+
+    // Records a change of Careful Resume phase that no other decision
+    // recorded.
+    fn record_cr_phase(&mut self, now: Instant) {
+        if self.decision_log.cr_phase_changed(self.congestion.resume.phase_name()) {
+            self.record_decision(RecoveryDecisionKind::CrPhaseChange, now);
+        }
+    }
+
+    /// Accounts for an application packet received from the peer, and returns
+    /// a Careful Resume jump by the peer if one is suspected.
+    ///
+    /// Nothing is detected until an RTT sample was taken, as the data
+    /// received is compared over round trips.
+    pub fn on_app_packet_received(&mut self, size: usize, now: Instant) -> Option<SuspectedPeerJump> {
+        let rtt = self.rtt_stats.min_rtt()?;
+
+        self.peer_resume.on_packet_received(size, rtt, now)
+    }
+
+    pub fn cr_phase_durations(&self) -> CrPhaseDurations {
+        self.congestion.resume.phase_durations()
+    }
+
+    pub fn cr_counters(&self) -> CrCounters {
+        self.congestion.resume.counters()
+    }
+
+    pub fn cr_jump_fraction(&self) -> Option<f64> {
+        self.congestion.resume.jump_fraction()
+    }
+
+    pub fn cr_last_jump(&self) -> Option<usize> {
+        self.congestion.resume.last_jump()
+    }
+
+    pub fn cr_state(&self) -> Option<CarefulResumeState> {
+        self.congestion.resume.state()
+    }
+
     pub fn delivery_rate_update_app_limited(&mut self, v: bool) {
         self.congestion.delivery_rate.update_app_limited(v);
     }
 
-    pub fn maybe_cr_event(&mut self) -> Option<resume::CREvent> {
-        self.congestion.cr_metrics.maybe_update(*self.rtt_stats.min_rtt, self.congestion.congestion_window)
+    pub fn maybe_cr_event(&mut self, now: Instant) -> Option<resume::CREvent> {
+        let state = self.cwnd_sample_state();
+
+        self.congestion.cr_metrics.maybe_update(
+            *self.rtt_stats.min_rtt, self.congestion.congestion_window, self.congestion.pacer.rate(),
+            state, now,
+        ).map(|ev| self.with_cr_context(ev))
+    }
+
+    // Like maybe_cr_event(), but for the last observation of the connection.
+    pub fn final_cr_event(&mut self, now: Instant) -> Option<resume::CREvent> {
+        let state = self.cwnd_sample_state();
+
+        self.congestion.cr_metrics.final_update(
+            *self.rtt_stats.min_rtt, self.congestion.congestion_window, self.congestion.pacer.rate(),
+            state, now,
+        ).map(|ev| self.with_cr_context(ev))
+    }
+
+    // Adds the datagram size and the loss rate the parameters were observed
+    // with, so that they can be interpreted on a different path
+    fn with_cr_context(&self, ev: resume::CREvent) -> resume::CREvent {
+        resume::CREvent {
+            max_datagram_size: self.max_datagram_size,
+            loss_rate_ppm: self.loss_rate_ppm(),
+            ..ev
+        }
+    }
+
+    // Returns the fraction of the bytes sent that were lost, in parts per
+    // million
+    pub fn loss_rate_ppm(&self) -> u32 {
+        if self.bytes_sent == 0 {
+            return 0;
+        }
+
+        let ppm = self.bytes_lost as u128 * 1_000_000 / self.bytes_sent as u128;
+
+        cmp::min(ppm, 1_000_000) as u32
+    }
+
+    // Replaces the congestion controller with one built from
+    // `recovery_config`, before any packet is acknowledged. The datagram size
+    // and the careful resume parameters and workload hint set up on the path
+    // are kept.
+    pub fn reconfigure(&mut self, recovery_config: &RecoveryConfig, trace_id: &str) {
+        let cr_params = self.congestion.resume.setup_params();
+        let cr_workload = self.congestion.resume.workload_hint();
+        let max_datagram_size = self.max_datagram_size;
+
+        self.congestion = Congestion::from_config(recovery_config, trace_id);
+        self.congestion.resume.set_workload_hint(cr_workload);
+
+        // Resize the new initial window and pacer.
+        self.max_datagram_size = recovery_config.max_send_udp_payload_size;
+        self.pmtud_update_max_datagram_size(max_datagram_size);
+
+        if let Some(p) = cr_params.filter(|_| recovery_config.resume) {
+            self.setup_careful_resume(p.min_rtt, p.cwnd, p.pacing_rate);
+        }
+    }
+
+    // Returns the careful resume parameters the path retreated from, once;
+    // later calls return `None`
+    pub fn take_invalid_cr_params(&mut self) -> Option<resume::CREvent> {
+        self.congestion.resume.take_invalid_params()
+    }
+
+    // The congestion window is reduced on congestion, and recovery lasts
+    // until a packet sent after the reduction is acknowledged.
+    fn cwnd_sample_state(&self) -> resume::CwndSampleState {
+        let in_recovery = match self.largest_acked_time_sent {
+            Some(time_sent) => self.congestion.in_congestion_recovery(time_sent),
+
+            None => self.congestion.congestion_recovery_start_time.is_some(),
+        };
+
+        if in_recovery || self.congestion.resume.in_safe_retreat() {
+            resume::CwndSampleState::Recovery
+        } else {
+            resume::CwndSampleState::Steady
+        }
     }
 
     pub fn update_max_ack_delay(&mut self, max_ack_delay: Duration) {
@@ -959,7 +1735,9 @@ impl Recovery {
     }
 
     #[cfg(feature = "qlog")]
-    pub fn maybe_qlog(&mut self) -> Option<EventData> {
+    pub fn maybe_qlog(
+        &mut self, sample: impl FnOnce() -> bool,
+    ) -> Option<EventData> {
         let qlog_metrics = QlogMetrics {
             min_rtt: *self.rtt_stats.min_rtt,
             smoothed_rtt: self.rtt(),
@@ -971,12 +1749,25 @@ impl Recovery {
             pacing_rate: self.congestion.pacer.rate(),
         };
 
+        // Only changes since the last call make an event.
+        self.qlog_metrics_seen.maybe_update(qlog_metrics)?;
+
+        // Changes are only recorded once written, so that the changes of an
+        // event that is not sampled are part of the next one.
+        if !sample() {
+            return None;
+        }
+
         self.qlog_metrics.maybe_update(qlog_metrics)
     }
 
     #[cfg(feature = "qlog")]
     pub fn maybe_cr_qlog(&mut self) -> Option<EventData> {
-        self.congestion.resume.maybe_qlog(self.cwnd(), self.congestion.ssthresh)
+        self.congestion.resume.maybe_qlog(
+            self.cwnd(),
+            self.congestion.ssthresh,
+            self.iw_acked,
+        )
     }
 
     #[cfg(feature = "qlog")]
@@ -985,11 +1776,111 @@ impl Recovery {
     }
 
     pub fn send_quantum(&self) -> usize {
-        self.congestion.send_quantum()
+        let send_quantum = self.congestion.send_quantum();
+
+        match self.cr_burst_limit() {
+            Some(limit) => cmp::min(send_quantum, limit),
+
+            None => send_quantum,
+        }
     }
 
-    pub fn setup_careful_resume(&mut self, previous_rtt: Duration, previous_cwnd: usize) {
-        self.congestion.resume.setup(previous_rtt, previous_cwnd);
+    // The largest burst that may be sent, in bytes, while the Careful Resume
+    // jump is unvalidated and a limit is set
+    fn cr_burst_limit(&self) -> Option<usize> {
+        if self.cr_max_burst == 0 || !self.congestion.resume.in_unvalidated() {
+            return None;
+        }
+
+        Some(self.cr_max_burst * self.max_datagram_size)
+    }
+
+    // Counts `sent_bytes` in the current burst, and holds further packets
+    // back once the burst limit is reached, until the time it takes to send
+    // the limit at the rate of the congestion window over the RTT has passed.
+    // This spreads the jump over the RTT even when the application neither
+    // paces nor limits its segmentation offload batches
+    fn count_burst(&mut self, sent_bytes: usize, now: Instant, trace_id: &str) {
+        let limit = match self.cr_burst_limit() {
+            Some(v) => v,
+
+            None => {
+                self.burst_start = None;
+                self.burst_release = None;
+                return;
+            },
+        };
+
+        let interval = self
+            .rtt()
+            .mul_f64(limit as f64 / cmp::max(self.cwnd(), limit) as f64);
+
+        let start = match self.burst_start {
+            Some(start) if now < start + interval => start,
+
+            _ => {
+                self.burst_bytes = 0;
+                now
+            },
+        };
+
+        self.burst_start = Some(start);
+        self.burst_bytes += sent_bytes;
+
+        if self.burst_bytes >= limit {
+            trace!(
+                "{} careful resume burst limit reached; burst_bytes={} limit={}",
+                trace_id,
+                self.burst_bytes,
+                limit
+            );
+
+            self.burst_release = Some(start + interval);
+        }
+    }
+
+    /// Lets the packets held back by the Careful Resume burst limit be sent
+    /// once the burst interval is over.
+    pub fn release_burst(&mut self, now: Instant) {
+        if self.burst_release.map_or(false, |t| t <= now) {
+            self.burst_release = None;
+        }
+    }
+
+    /// Returns when the packets held back by the Careful Resume burst limit
+    /// may be sent.
+    pub fn burst_timer(&self) -> Option<Instant> {
+        self.burst_release
+    }
+
+    pub fn setup_careful_resume(&mut self, previous_rtt: Duration, previous_cwnd: usize, previous_pacing_rate: u64) {
+        self.congestion.resume.setup(previous_rtt, previous_cwnd, previous_pacing_rate);
+    }
+
+    // Subjects the jump to `limiter`, for connections through the local
+    // interface `local_ip`.
+    pub fn set_cr_jump_limiter(&mut self, limiter: &CrJumpLimiter, local_ip: IpAddr) {
+        self.congestion.resume.set_jump_limiter(limiter, local_ip);
+    }
+
+    pub fn set_cr_phase_observer(&mut self, observer: Arc<dyn CrPhaseObserver>) {
+        self.congestion.resume.set_phase_observer(observer);
+    }
+
+    pub fn set_cr_workload_hint(&mut self, workload: CrWorkloadHint) {
+        self.congestion.resume.set_workload_hint(workload);
+    }
+
+    pub fn set_cr_flow_control_credit(&mut self, credit: usize) {
+        self.cr_flow_control_credit = Some(credit);
+    }
+
+    pub fn set_latency_budget(&mut self, budget: Option<Duration>) {
+        self.latency_budget = budget;
+    }
+
+    pub fn set_cr_transfer_size_hint(&mut self, bytes: Option<usize>) {
+        self.cr_transfer_end = bytes.map(|v| self.bytes_sent.saturating_add(v));
     }
 
     pub fn set_initial_rtt(&mut self, initial_rtt: Duration) {
@@ -1083,6 +1974,8 @@ pub struct Sent {
     pub has_data: bool,
 
     pub pmtud: bool,
+
+    pub retransmission: bool,
 }
 
 impl std::fmt::Debug for Sent {
@@ -1097,7 +1990,8 @@ impl std::fmt::Debug for Sent {
         write!(f, "tx_in_flight={} ", self.tx_in_flight)?;
         write!(f, "lost={} ", self.lost)?;
         write!(f, "has_data={} ", self.has_data)?;
-        write!(f, "pmtud={}", self.pmtud)?;
+        write!(f, "pmtud={} ", self.pmtud)?;
+        write!(f, "retransmission={}", self.retransmission)?;
 
         Ok(())
     }
@@ -1115,11 +2009,42 @@ pub struct Acked {
 
     pub delivered: usize,
 
-    pub delivered_time: Instant,
+    pub delivered_time: Instant,
+
+    pub first_sent_time: Instant,
+
+    pub is_app_limited: bool,
+
+    /// Whether the ACK frame acknowledging this packet reported new ECN-CE
+    /// marks.
+    pub ecn_ce: bool,
+
+    /// Whether the packet carried stream data that was already sent in an
+    /// earlier packet.
+    pub retransmission: bool,
+}
+
+/// The delivery rate sample generated by the latest ACK, along with the state
+/// of the congestion controller that consumed it.
+#[derive(Clone, Copy, Debug)]
+pub struct RateSample {
+    pub delivered: usize,
+
+    /// The interval the sample was measured over, zero if it was shorter than
+    /// the minimum RTT and so not reliable.
+    pub interval: Duration,
+
+    pub delivery_rate: u64,
+
+    pub rtt: Duration,
+
+    pub is_app_limited: bool,
+
+    pub cwnd: usize,
 
-    pub first_sent_time: Instant,
+    pub bytes_in_flight: usize,
 
-    pub is_app_limited: bool,
+    pub cr_phase: &'static str,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -1144,11 +2069,20 @@ impl Default for HandshakeStatus {
     }
 }
 
+// Converts `d` to the floating point milliseconds used by qlog.
+//
+// Going through whole microseconds keeps sub-millisecond values exact, which
+// scaling `as_secs_f32()` doesn't, e.g. 50µs would become 0.049999997ms.
+#[cfg(feature = "qlog")]
+pub(crate) fn qlog_millis(d: Duration) -> f32 {
+    d.as_micros() as f32 / 1000.0
+}
+
 // We don't need to log all qlog metrics every time there is a recovery event.
 // Instead, we can log only the MetricsUpdated event data fields that we care
 // about, only when they change. To support this, the QLogMetrics structure
 // keeps a running picture of the fields.
-#[derive(Default)]
+#[derive(Clone, Copy, Default)]
 #[cfg(feature = "qlog")]
 struct QlogMetrics {
     min_rtt: Duration,
@@ -1174,7 +2108,7 @@ impl QlogMetrics {
         let new_min_rtt = if self.min_rtt != latest.min_rtt {
             self.min_rtt = latest.min_rtt;
             emit_event = true;
-            Some(latest.min_rtt.as_secs_f32() * 1000.0)
+            Some(qlog_millis(latest.min_rtt))
         } else {
             None
         };
@@ -1182,7 +2116,7 @@ impl QlogMetrics {
         let new_smoothed_rtt = if self.smoothed_rtt != latest.smoothed_rtt {
             self.smoothed_rtt = latest.smoothed_rtt;
             emit_event = true;
-            Some(latest.smoothed_rtt.as_secs_f32() * 1000.0)
+            Some(qlog_millis(latest.smoothed_rtt))
         } else {
             None
         };
@@ -1190,7 +2124,7 @@ impl QlogMetrics {
         let new_latest_rtt = if self.latest_rtt != latest.latest_rtt {
             self.latest_rtt = latest.latest_rtt;
             emit_event = true;
-            Some(latest.latest_rtt.as_secs_f32() * 1000.0)
+            Some(qlog_millis(latest.latest_rtt))
         } else {
             None
         };
@@ -1198,7 +2132,7 @@ impl QlogMetrics {
         let new_rttvar = if self.rttvar != latest.rttvar {
             self.rttvar = latest.rttvar;
             emit_event = true;
-            Some(latest.rttvar.as_secs_f32() * 1000.0)
+            Some(qlog_millis(latest.rttvar))
         } else {
             None
         };
@@ -1307,6 +2241,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1337,6 +2272,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1367,6 +2303,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1396,6 +2333,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1419,6 +2357,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1457,6 +2396,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1486,6 +2426,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1510,6 +2451,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1560,6 +2502,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1589,6 +2532,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1618,6 +2562,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1647,6 +2592,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1671,6 +2617,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1732,6 +2679,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1761,6 +2709,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1790,6 +2739,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1819,6 +2769,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1842,6 +2793,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1861,6 +2813,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1887,6 +2840,426 @@ mod tests {
         assert_eq!(r.epochs[packet::Epoch::Application].sent_packets.len(), 0);
     }
 
+    #[test]
+    fn ssthresh_and_phase() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg, "");
+
+        let mut now = Instant::now();
+
+        assert_eq!(r.ssthresh(), None);
+        assert_eq!(
+            r.congestion_phase(),
+            Some(congestion::CongestionPhase::SlowStart)
+        );
+
+        for pkt_num in 0..5 {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        }
+
+        now += Duration::from_millis(10);
+
+        // Packet 0 is lost.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(1..5);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((1, 1000, 1000 * 4))
+        );
+
+        assert_eq!(r.ssthresh(), Some(r.cwnd()));
+        assert_eq!(
+            r.congestion_phase(),
+            Some(congestion::CongestionPhase::CongestionAvoidance)
+        );
+
+        // BBR has neither.
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::BBR2);
+
+        let r = Recovery::new(&cfg, "");
+
+        assert_eq!(r.ssthresh(), None);
+        assert_eq!(r.congestion_phase(), None);
+    }
+
+    #[test]
+    fn latency_budget() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg, "");
+
+        let mut now = Instant::now();
+
+        // Without a rate estimate the budget doesn't limit the window.
+        r.set_latency_budget(Some(Duration::from_millis(10)));
+        assert_eq!(r.cwnd_available(), r.cwnd());
+
+        for pkt_num in 0..10 {
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        }
+
+        now += Duration::from_millis(10);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..10);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                0,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 10_000))
+        );
+
+        r.congestion.congestion_window = 200_000;
+
+        let rate = r.delivery_rate();
+        assert!(rate > 0);
+
+        // The window holds the data in flight over the min RTT, plus 10ms of
+        // queuing.
+        let min_rtt = r.min_rtt().unwrap();
+        let cwnd = (rate as f64 *
+            (min_rtt + Duration::from_millis(10)).as_secs_f64())
+            as usize;
+
+        assert!(cwnd < 200_000);
+        assert_eq!(r.cwnd_available(), cwnd);
+
+        r.set_latency_budget(None);
+        assert_eq!(r.cwnd_available(), 200_000);
+    }
+
+    #[test]
+    fn limited_time() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg, "");
+
+        let mut now = Instant::now();
+
+        assert_eq!(r.cwnd(), 12_000);
+
+        // 2 packets leave room in the window, 10 more fill it.
+        for pkt_num in 0..12 {
+            if pkt_num == 2 {
+                now += Duration::from_millis(10);
+            }
+
+            let p = Sent {
+                pkt_num,
+                frames: smallvec![],
+                time_sent: now,
+                time_acked: None,
+                time_lost: None,
+                size: 1000,
+                ack_eliciting: true,
+                in_flight: true,
+                delivered: 0,
+                delivered_time: now,
+                first_sent_time: now,
+                is_app_limited: false,
+                tx_in_flight: 0,
+                lost: 0,
+                has_data: false,
+                pmtud: false,
+                retransmission: false,
+            };
+
+            r.on_packet_sent(
+                p,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        }
+
+        assert_eq!(r.app_limited_time(), Duration::from_millis(10));
+        assert_eq!(r.cwnd_limited_time(), Duration::ZERO);
+
+        now += Duration::from_millis(20);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(0..12);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                0,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 12_000))
+        );
+
+        assert_eq!(r.app_limited_time(), Duration::from_millis(10));
+        assert_eq!(r.cwnd_limited_time(), Duration::from_millis(20));
+
+        // Nothing is in flight, so the sender is app-limited until it sends
+        // again.
+        now += Duration::from_millis(5);
+
+        let p = Sent {
+            pkt_num: 12,
+            frames: smallvec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            tx_in_flight: 0,
+            lost: 0,
+            has_data: false,
+            pmtud: false,
+            retransmission: false,
+        };
+
+        r.on_packet_sent(
+            p,
+            packet::Epoch::Application,
+            HandshakeStatus::default(),
+            now,
+            "",
+        );
+
+        assert_eq!(r.app_limited_time(), Duration::from_millis(15));
+        assert_eq!(r.cwnd_limited_time(), Duration::from_millis(20));
+    }
+
+    #[test]
+    fn cr_event_skips_recovery() {
+        let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+        cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+
+        let mut r = Recovery::new(&cfg, "");
+
+        let mut now = Instant::now();
+
+        let sent = |pkt_num, now| Sent {
+            pkt_num,
+            frames: smallvec![],
+            time_sent: now,
+            time_acked: None,
+            time_lost: None,
+            size: 1000,
+            ack_eliciting: true,
+            in_flight: true,
+            delivered: 0,
+            delivered_time: now,
+            first_sent_time: now,
+            is_app_limited: false,
+            tx_in_flight: 0,
+            lost: 0,
+            has_data: false,
+            pmtud: false,
+            retransmission: false,
+        };
+
+        r.congestion.congestion_window = 200_000;
+
+        for pkt_num in 0..5 {
+            r.on_packet_sent(
+                sent(pkt_num, now),
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            );
+        }
+
+        now += Duration::from_millis(10);
+
+        // Packet 0 is lost, and the congestion window is reduced.
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(1..5);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((1, 1000, 1000 * 4))
+        );
+
+        assert!(r.cwnd() < 200_000);
+        assert_eq!(r.cwnd_sample_state(), resume::CwndSampleState::Recovery);
+        assert_eq!(r.maybe_cr_event(now), None);
+
+        // Recovery is over once a packet sent after the reduction is acked.
+        now += Duration::from_millis(1);
+
+        r.on_packet_sent(
+            sent(5, now),
+            packet::Epoch::Application,
+            HandshakeStatus::default(),
+            now,
+            "",
+        );
+
+        now += Duration::from_millis(10);
+
+        let mut acked = ranges::RangeSet::default();
+        acked.insert(5..6);
+
+        assert_eq!(
+            r.on_ack_received(
+                &acked,
+                25,
+                None,
+                packet::Epoch::Application,
+                HandshakeStatus::default(),
+                now,
+                "",
+            ),
+            Ok((0, 0, 1000))
+        );
+
+        assert_eq!(r.cwnd_sample_state(), resume::CwndSampleState::Steady);
+
+        // The observed window is the one after recovery.
+        let event = r.maybe_cr_event(now).unwrap();
+        assert_eq!(event.cwnd, r.cwnd());
+        assert!(!event.post_congestion);
+
+        let reduced_cwnd = r.cwnd();
+
+        // Several round trips of acks in congestion avoidance grow the
+        // window, and every sample taken meanwhile is a steady one.
+        let mut pkt_num = 6;
+
+        for _ in 0..3 {
+            let start = pkt_num;
+
+            // Fill the window, so that the sender isn't app-limited.
+            for _ in 0..r.cwnd() / 1000 + 1 {
+                r.on_packet_sent(
+                    sent(pkt_num, now),
+                    packet::Epoch::Application,
+                    HandshakeStatus::default(),
+                    now,
+                    "",
+                );
+
+                pkt_num += 1;
+            }
+
+            now += Duration::from_millis(10);
+
+            for pn in start..pkt_num {
+                let mut acked = ranges::RangeSet::default();
+                acked.insert(start..pn + 1);
+
+                r.on_ack_received(
+                    &acked,
+                    25,
+                    None,
+                    packet::Epoch::Application,
+                    HandshakeStatus::default(),
+                    now,
+                    "",
+                )
+                .unwrap();
+
+                assert_eq!(
+                    r.cwnd_sample_state(),
+                    resume::CwndSampleState::Steady
+                );
+            }
+        }
+
+        // One datagram per round trip.
+        assert_eq!(r.cwnd(), reduced_cwnd + 3 * r.max_datagram_size);
+
+        // The last sample reports the grown window, not the reduced one.
+        let event = r.final_cr_event(now).unwrap();
+        assert_eq!(event.cwnd, r.cwnd());
+        assert!(!event.post_congestion);
+    }
+
     #[test]
     fn pacing() {
         let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
@@ -1916,6 +3289,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -1943,6 +3317,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 10,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -1976,6 +3351,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -2010,6 +3386,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -2041,6 +3418,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -2095,6 +3473,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -2126,6 +3505,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: true,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -2155,6 +3535,7 @@ mod tests {
             lost: 0,
             has_data: false,
             pmtud: false,
+            retransmission: false,
         };
 
         r.on_packet_sent(
@@ -2179,6 +3560,7 @@ mod tests {
             r.on_ack_received(
                 &acked,
                 25,
+                None,
                 packet::Epoch::Application,
                 HandshakeStatus::default(),
                 now,
@@ -2215,7 +3597,100 @@ mod tests {
         assert_eq!(r.bytes_in_flight, 0);
         assert_eq!(r.congestion.lost_count, 0);
     }
+
+    #[test]
+    fn ack_aggregation() {
+        let run = |compensate: bool| {
+            let mut cfg = crate::Config::new(crate::PROTOCOL_VERSION).unwrap();
+            cfg.set_cc_algorithm(CongestionControlAlgorithm::Reno);
+            cfg.enable_hystart(false);
+            cfg.enable_ack_aggregation_compensation(compensate);
+
+            let mut r = Recovery::new(&cfg, "");
+
+            let start = Instant::now();
+
+            // Send 13 packets, 1ms apart, to fill the congestion window.
+            for i in 0..13 {
+                let now = start + Duration::from_millis(i);
+
+                let p = Sent {
+                    pkt_num: i,
+                    frames: smallvec![],
+                    time_sent: now,
+                    time_acked: None,
+                    time_lost: None,
+                    size: 1000,
+                    ack_eliciting: true,
+                    in_flight: true,
+                    delivered: 0,
+                    delivered_time: now,
+                    first_sent_time: now,
+                    is_app_limited: false,
+                    tx_in_flight: 0,
+                    lost: 0,
+                    has_data: false,
+                    pmtud: false,
+                    retransmission: false,
+                };
+
+                r.on_packet_sent(
+                    p,
+                    packet::Epoch::Application,
+                    HandshakeStatus::default(),
+                    now,
+                    "",
+                );
+            }
+
+            let mut ack = |range: std::ops::Range<u64>, now: Instant| {
+                let mut acked = ranges::RangeSet::default();
+                acked.insert(range);
+
+                r.on_ack_received(
+                    &acked,
+                    0,
+                    None,
+                    packet::Epoch::Application,
+                    HandshakeStatus::default(),
+                    now,
+                    "",
+                )
+                .unwrap();
+            };
+
+            ack(0..1, start + Duration::from_millis(50));
+
+            // The remaining ACKs are held, then arrive back to back.
+            let now = start + Duration::from_millis(62);
+
+            ack(1..5, now);
+            ack(5..9, now);
+            ack(9..13, now);
+
+            r
+        };
+
+        // The first ACK of the bunch arrives after a gap long enough to
+        // cover the packets it acknowledges. The next two arrive faster than
+        // the packets were sent, less a tolerance of 2 packets.
+        let r = run(false);
+        assert_eq!(r.ack_aggregation_count(), 2);
+        assert_eq!(r.ack_aggregation_bytes(), 1600 + 4000);
+        assert_eq!(r.cwnd(), 12000 + 13 * 1200);
+
+        // Slow start growth is scaled down by the aggregated share of each
+        // ACK.
+        let r = run(true);
+        assert_eq!(r.ack_aggregation_count(), 2);
+        assert_eq!(r.ack_aggregation_bytes(), 1600 + 4000);
+        assert_eq!(r.cwnd(), 12000 + 1200 + 4 * 1200 + 4 * 1200 * 2400 / 4000);
+    }
 }
 
 pub mod congestion;
+mod decision_log;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod peer_resume;
 mod rtt;
@@ -0,0 +1,194 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Detection of a Careful Resume jump by the peer, from the receiving side.
+//!
+//! A sender in slow start at most doubles the data it sends every round trip,
+//! while the Careful Resume jump takes it to a fraction of the congestion
+//! window of a previous connection one round trip after the first application
+//! data. A round trip in which much more data is received than in the
+//! previous one is therefore likely to be a jump. This is only a heuristic,
+//! e.g. an application-limited peer sending a burst after being idle looks
+//! the same, but it gives a hint when testing against implementations that
+//! don't share their traces.
+
+use std::time::Duration;
+use std::time::Instant;
+
+// How much more data than in the previous round trip must be received for a
+// jump to be suspected. Slow start grows by at most 2 per round trip.
+const JUMP_GROWTH: usize = 3;
+
+// The least data received in a round trip suspected to be a jump. Smaller
+// jumps can't be told apart from slow start.
+const MIN_JUMP_BYTES: usize = 10 * 1200;
+
+// The jump happens early in the connection, so later round trips aren't
+// checked.
+const MAX_ROUNDS: usize = 4;
+
+/// A Careful Resume jump suspected by [`PeerResumeDetector`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SuspectedPeerJump {
+    /// The data received in the round trip after the jump, in bytes.
+    pub estimated_jump: usize,
+
+    /// The data received in the round trip before the jump, in bytes.
+    pub previous_round_bytes: usize,
+
+    /// The round trip the jump was suspected in, counting from 0 for the
+    /// first one application data was received in.
+    pub round: usize,
+
+    /// The round trip time used to delimit round trips.
+    pub rtt: Duration,
+}
+
+/// Compares the application data received in consecutive round trips to
+/// detect a Careful Resume jump by the peer.
+#[derive(Default)]
+pub(crate) struct PeerResumeDetector {
+    round_start: Option<Instant>,
+
+    round_bytes: usize,
+
+    prev_round_bytes: usize,
+
+    rounds: usize,
+
+    done: bool,
+}
+
+impl PeerResumeDetector {
+    /// Accounts for an application packet of `size` bytes received at `now`,
+    /// with round trips lasting `rtt`.
+    ///
+    /// A jump is checked for once a round trip is over, so it is returned
+    /// along with the first packet received after the round trip it was
+    /// suspected in. At most one jump is returned.
+    pub(crate) fn on_packet_received(
+        &mut self, size: usize, rtt: Duration, now: Instant,
+    ) -> Option<SuspectedPeerJump> {
+        if self.done {
+            return None;
+        }
+
+        let start = *self.round_start.get_or_insert(now);
+
+        if now.saturating_duration_since(start) < rtt {
+            self.round_bytes += size;
+            return None;
+        }
+
+        let suspected = self.rounds > 0 &&
+            self.prev_round_bytes > 0 &&
+            self.round_bytes >= MIN_JUMP_BYTES &&
+            self.round_bytes >= self.prev_round_bytes * JUMP_GROWTH;
+
+        let jump = suspected.then_some(SuspectedPeerJump {
+            estimated_jump: self.round_bytes,
+            previous_round_bytes: self.prev_round_bytes,
+            round: self.rounds,
+            rtt,
+        });
+
+        self.rounds += 1;
+        self.done = suspected || self.rounds >= MAX_ROUNDS;
+
+        self.prev_round_bytes = self.round_bytes;
+        self.round_bytes = size;
+        self.round_start = Some(now);
+
+        jump
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receive(
+        d: &mut PeerResumeDetector, packets: usize, start: Instant,
+    ) -> Option<SuspectedPeerJump> {
+        let rtt = Duration::from_millis(50);
+        let mut jump = None;
+
+        for i in 0..packets {
+            let now = start + rtt * i as u32 / packets as u32;
+
+            if let Some(j) = d.on_packet_received(1200, rtt, now) {
+                jump = Some(j);
+            }
+        }
+
+        jump
+    }
+
+    #[test]
+    fn jump() {
+        let mut d = PeerResumeDetector::default();
+        let rtt = Duration::from_millis(50);
+        let start = Instant::now();
+
+        assert_eq!(receive(&mut d, 10, start), None);
+        assert_eq!(receive(&mut d, 60, start + rtt), None);
+        assert_eq!(
+            receive(&mut d, 10, start + rtt * 2),
+            Some(SuspectedPeerJump {
+                estimated_jump: 60 * 1200,
+                previous_round_bytes: 10 * 1200,
+                round: 1,
+                rtt,
+            })
+        );
+
+        // Only the first jump is reported.
+        assert_eq!(receive(&mut d, 100, start + rtt * 3), None);
+        assert_eq!(receive(&mut d, 10, start + rtt * 4), None);
+    }
+
+    #[test]
+    fn slow_start() {
+        let mut d = PeerResumeDetector::default();
+        let rtt = Duration::from_millis(50);
+        let start = Instant::now();
+
+        for (i, packets) in [10, 20, 40, 80, 160, 320].iter().enumerate() {
+            assert_eq!(receive(&mut d, *packets, start + rtt * i as u32), None);
+        }
+    }
+
+    #[test]
+    fn small_jump() {
+        let mut d = PeerResumeDetector::default();
+        let rtt = Duration::from_millis(50);
+        let start = Instant::now();
+
+        assert_eq!(receive(&mut d, 1, start), None);
+        assert_eq!(receive(&mut d, 8, start + rtt), None);
+        assert_eq!(receive(&mut d, 8, start + rtt * 2), None);
+    }
+}
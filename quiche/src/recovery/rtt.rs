@@ -47,6 +47,8 @@ pub struct RttStats {
     pub(super) max_ack_delay: Duration,
 
     pub(super) first_rtt_sample: Option<Instant>,
+
+    pub(super) latest_adjusted_rtt: Option<Duration>,
 }
 
 impl std::fmt::Debug for RttStats {
@@ -69,6 +71,7 @@ impl RttStats {
             initial_rtt: None,
             rttvar: None,
             first_rtt_sample: None,
+            latest_adjusted_rtt: None,
             max_ack_delay,
         }
     }
@@ -79,7 +82,18 @@ impl RttStats {
     ) {
         self.latest_rtt = latest_rtt;
 
+        // Careful Resume compares individual samples with the RTT of a
+        // previous connection, so the peer's ack delay (bounded by its
+        // max_ack_delay) is always removed, including from the first sample.
+        let cr_ack_delay = ack_delay.min(self.max_ack_delay);
+
         if self.first_rtt_sample.is_none() {
+            self.latest_adjusted_rtt = Some(if latest_rtt > cr_ack_delay {
+                latest_rtt - cr_ack_delay
+            } else {
+                latest_rtt
+            });
+
             self.min_rtt.reset(now, latest_rtt);
             self.smoothed_rtt = Some(latest_rtt);
             self.rttvar = Some(latest_rtt / 2);
@@ -90,6 +104,13 @@ impl RttStats {
         // min_rtt ignores acknowledgment delay.
         self.min_rtt.running_min(RTT_WINDOW, now, latest_rtt);
 
+        self.latest_adjusted_rtt =
+            Some(if latest_rtt >= *self.min_rtt + cr_ack_delay {
+                latest_rtt - cr_ack_delay
+            } else {
+                latest_rtt
+            });
+
         // Limit ack_delay by max_ack_delay after handshake confirmation.
         if handshake_confirmed {
             ack_delay = ack_delay.min(self.max_ack_delay);
@@ -119,6 +140,11 @@ impl RttStats {
             .unwrap_or(INITIAL_RTT)
     }
 
+    /// The latest RTT sample with the peer's ack delay removed, if plausible.
+    pub(crate) fn latest_adjusted_rtt(&self) -> Option<Duration> {
+        self.latest_adjusted_rtt
+    }
+
     pub(crate) fn min_rtt(&self) -> Option<Duration> {
         self.min_rtt.ne(&Duration::ZERO).then_some(*self.min_rtt)
     }
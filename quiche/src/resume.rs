@@ -0,0 +1,91 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Careful Resume types.
+//!
+//! All the public types used to configure, observe and store Careful Resume
+//! parameters are re-exported in this module, so that applications and
+//! experiment tooling can import them from a single path:
+//!
+//! ```
+//! use quiche::resume::CREvent;
+//! use quiche::resume::CarefulResumeTrigger;
+//! use quiche::resume::CrState;
+//! use quiche::resume::CrStore;
+//!
+//! fn log_phase(state: Option<CrState>, trigger: CarefulResumeTrigger) {
+//!     println!("careful resume phase {state:?} after {trigger:?}");
+//! }
+//! # let _: Option<CREvent> = None;
+//! # let _: Option<&dyn CrStore> = None;
+//! ```
+//!
+//! The paths of this module are part of the stable API of the crate: a type
+//! is only removed from the module or renamed in a new major version. The
+//! modules the types are defined in are private, and may be reorganized at
+//! any time. The types are also available at the root of the crate.
+//!
+//! [`CrState`] is the phase of careful resume on a path, as returned by
+//! [`Connection::careful_resume_state()`] and passed to a
+//! [`CrPhaseObserver`]. [`CarefulResumeTrigger`] and [`CarefulResumePhase`]
+//! are the types of the qlog crate used in the `careful_resume_phase_updated`
+//! event, re-exported so that observers don't have to depend on the same
+//! version of the qlog crate as quiche.
+//!
+//! [`Connection::careful_resume_state()`]: crate::Connection::careful_resume_state
+
+pub use qlog::events::resume::CarefulResumePhase;
+pub use qlog::events::resume::CarefulResumeTrigger;
+
+pub use crate::cr_config::ConfigDelta;
+pub use crate::cr_config::CrConfig;
+pub use crate::cr_config::CrConfigError;
+pub use crate::cr_config::CrProfile;
+pub use crate::cr_hints::CrHints;
+pub use crate::cr_hints::CrHintsHandler;
+pub use crate::cr_path_cache::CrPathCache;
+pub use crate::cr_policy::CrParamsPolicy;
+pub use crate::cr_policy::CrParamsSource;
+pub use crate::cr_store::CrStore;
+pub use crate::cr_store::FileCrStore;
+pub use crate::cr_store::MemoryCrStore;
+pub use crate::recovery::BdpFrame;
+pub use crate::recovery::CREvent;
+pub use crate::recovery::CarefulResumeState;
+pub use crate::recovery::CarefulResumeState as CrState;
+pub use crate::recovery::CongestionSignalSource;
+pub use crate::recovery::CrCounters;
+pub use crate::recovery::CrHystartPolicy;
+pub use crate::recovery::CrJumpLimiter;
+pub use crate::recovery::CrJumpPacing;
+pub use crate::recovery::CrObservePolicy;
+pub use crate::recovery::CrPhaseDurations;
+pub use crate::recovery::CrPhaseObserver;
+pub use crate::recovery::CrRejectReason;
+pub use crate::recovery::CrRetreatPolicy;
+pub use crate::recovery::CrValidatingPolicy;
+pub use crate::recovery::CrWorkloadHint;
+pub use crate::recovery::StoredCREvent;
@@ -0,0 +1,415 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Stateless Careful Resume parameter blobs.
+//!
+//! A server that does not want to keep any per-client state can seal the
+//! parameters observed during the observe phase into an opaque blob, hand it
+//! to the client, and have the client echo it back in the
+//! `careful_resume_blob` transport parameter when it reconnects.
+//!
+//! ```text
+//! Blob {
+//!   Version (8),
+//!   Nonce (96),
+//!   Sealed Parameters (..),
+//! }
+//!
+//! Sealed Parameters {
+//!   CR Event (..),
+//!   Authentication Tag (128),
+//! }
+//! ```
+//!
+//! The parameters are serialized with [`CREvent::to_bytes()`], for the IP
+//! address of the client and with the time the blob was issued. They are
+//! sealed with AES-GCM using the blob version and the IP address of the
+//! client as associated data, so a blob is only accepted when it is echoed
+//! from the address it was issued to. Blobs older than the configured maximum
+//! age, or issued in the future, are rejected.
+//!
+//! [`CREvent::to_bytes()`]: crate::CREvent::to_bytes
+//!
+//! A server can also carry the blob in the tokens of its NEW_TOKEN frames, so
+//! that it comes back in the Initial packets of the client's next connection.
+//! Such tokens are the blob prefixed with a marker, which distinguishes them
+//! from address validation tokens minted by the application.
+//!
+//! ```text
+//! Resume Token {
+//!   Marker (32) = 0x71637231,
+//!   Blob (..),
+//! }
+//! ```
+//!
+//! The token of the BDP frames sent by a server is also a blob, so that the
+//! parameters echoed by the client, and their age, can be trusted.
+
+use std::net::IpAddr;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use ring::aead;
+
+use crate::rand;
+use crate::CREvent;
+use crate::Error;
+use crate::Result;
+
+const BLOB_VERSION: u8 = 1;
+
+const NONCE_LEN: usize = aead::NONCE_LEN;
+
+const TAG_LEN: usize = 16;
+
+/// How far in the future the issue time of a blob can be before it is
+/// rejected, to accommodate clock differences between server instances.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5);
+
+const TOKEN_MARKER: [u8; 4] = *b"qcr1";
+
+/// The default maximum age of an accepted blob.
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+pub struct BlobKey {
+    key: aead::LessSafeKey,
+}
+
+impl BlobKey {
+    /// Creates a key for sealing and opening blobs.
+    ///
+    /// 16 byte keys select AES-128-GCM and 32 byte keys AES-256-GCM, any other
+    /// length is rejected.
+    pub fn new(key: &[u8]) -> Result<BlobKey> {
+        let alg = match key.len() {
+            16 => &aead::AES_128_GCM,
+            32 => &aead::AES_256_GCM,
+            _ => return Err(Error::CryptoFail),
+        };
+
+        let key =
+            aead::UnboundKey::new(alg, key).map_err(|_| Error::CryptoFail)?;
+
+        Ok(BlobKey {
+            key: aead::LessSafeKey::new(key),
+        })
+    }
+
+    pub fn seal(
+        &self, params: &CREvent, peer: IpAddr, now: SystemTime,
+    ) -> Result<Vec<u8>> {
+        now.duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|_| Error::CryptoFail)?;
+
+        let mut nonce = [0; NONCE_LEN];
+        rand::rand_bytes(&mut nonce);
+
+        let mut sealed = params.to_bytes(&peer.to_string(), now);
+
+        self.key
+            .seal_in_place_append_tag(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(associated_data(BLOB_VERSION, peer)),
+                &mut sealed,
+            )
+            .map_err(|_| Error::CryptoFail)?;
+
+        let mut out = Vec::with_capacity(1 + NONCE_LEN + sealed.len());
+        out.push(BLOB_VERSION);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+
+        Ok(out)
+    }
+
+    pub fn open(
+        &self, blob: &[u8], peer: IpAddr, now: SystemTime, max_age: Duration,
+    ) -> Result<CREvent> {
+        self.open_with_age(blob, peer, now, max_age)
+            .map(|(params, _)| params)
+    }
+
+    /// Opens `blob` like [`open()`], also returning how long ago it was
+    /// issued.
+    ///
+    /// [`open()`]: BlobKey::open
+    pub fn open_with_age(
+        &self, blob: &[u8], peer: IpAddr, now: SystemTime, max_age: Duration,
+    ) -> Result<(CREvent, Duration)> {
+        if blob.len() < 1 + NONCE_LEN + TAG_LEN {
+            return Err(Error::CryptoFail);
+        }
+
+        let version = blob[0];
+
+        if version != BLOB_VERSION {
+            return Err(Error::CryptoFail);
+        }
+
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(&blob[1..1 + NONCE_LEN]);
+
+        let mut sealed = blob[1 + NONCE_LEN..].to_vec();
+
+        let params = self
+            .key
+            .open_in_place(
+                aead::Nonce::assume_unique_for_key(nonce),
+                aead::Aad::from(associated_data(version, peer)),
+                &mut sealed,
+            )
+            .map_err(|_| Error::CryptoFail)?;
+
+        let record =
+            CREvent::from_bytes(params).map_err(|_| Error::CryptoFail)?;
+
+        if record.endpoint != peer.to_string() {
+            return Err(Error::CryptoFail);
+        }
+
+        let issued_at = record.stored_at;
+
+        // Limit the window in which a blob can be replayed.
+        if issued_at > now + MAX_CLOCK_SKEW {
+            return Err(Error::CryptoFail);
+        }
+
+        let age = now.duration_since(issued_at).unwrap_or_default();
+
+        if age > max_age {
+            return Err(Error::CryptoFail);
+        }
+
+        Ok((record.params, age))
+    }
+}
+
+/// Wraps `blob` into a NEW_TOKEN token.
+pub fn to_token(blob: &[u8]) -> Vec<u8> {
+    let mut token = Vec::with_capacity(TOKEN_MARKER.len() + blob.len());
+    token.extend_from_slice(&TOKEN_MARKER);
+    token.extend_from_slice(blob);
+
+    token
+}
+
+/// Returns the blob carried by `token`, or `None` if it's not a resume token.
+pub fn from_token(token: &[u8]) -> Option<&[u8]> {
+    token.strip_prefix(&TOKEN_MARKER[..])
+}
+
+fn associated_data(version: u8, peer: IpAddr) -> Vec<u8> {
+    let mut ad = vec![version];
+
+    match peer {
+        IpAddr::V4(ip) => ad.extend_from_slice(&ip.octets()),
+        IpAddr::V6(ip) => ad.extend_from_slice(&ip.octets()),
+    }
+
+    ad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> CREvent {
+        CREvent {
+            min_rtt: Duration::from_millis(600),
+            cwnd: 1_250_000,
+            pacing_rate: 6_250_000,
+            post_congestion: false,
+            max_datagram_size: 0,
+            loss_rate_ppm: 0,
+        }
+    }
+
+    #[test]
+    fn seal_open() {
+        let key = BlobKey::new(&[0xba; 16]).unwrap();
+        let peer = "127.0.0.1".parse().unwrap();
+        let now = SystemTime::now();
+
+        let blob = key.seal(&params(), peer, now).unwrap();
+        let opened = key.open(&blob, peer, now, DEFAULT_MAX_AGE).unwrap();
+
+        assert_eq!(opened, params());
+    }
+
+    #[test]
+    fn seal_open_sub_millisecond_rtt() {
+        let key = BlobKey::new(&[0xba; 16]).unwrap();
+        let peer = "127.0.0.1".parse().unwrap();
+        let now = SystemTime::now();
+
+        let params = CREvent {
+            min_rtt: Duration::from_micros(50),
+            ..params()
+        };
+
+        let blob = key.seal(&params, peer, now).unwrap();
+        let opened = key.open(&blob, peer, now, DEFAULT_MAX_AGE).unwrap();
+
+        assert_eq!(opened, params);
+    }
+
+    #[test]
+    fn seal_max_len() {
+        let key = BlobKey::new(&[0xba; 32]).unwrap();
+        let peer = "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ffff".parse().unwrap();
+        let now = SystemTime::now();
+
+        // Every varint takes 8 bytes.
+        let max = (1 << 62) - 1;
+
+        let params = CREvent {
+            min_rtt: Duration::from_micros(max),
+            cwnd: max as usize,
+            pacing_rate: max,
+            post_congestion: true,
+            max_datagram_size: max as usize,
+            loss_rate_ppm: u32::MAX,
+        };
+
+        let blob = key.seal(&params, peer, now).unwrap();
+        assert_eq!(blob.len(), 119);
+        assert!(blob.len() <= crate::MAX_RESUME_BLOB_LEN);
+    }
+
+    #[test]
+    fn invalid_key_len() {
+        assert_eq!(BlobKey::new(&[0xba; 24]).err(), Some(Error::CryptoFail));
+    }
+
+    #[test]
+    fn open_wrong_peer() {
+        let key = BlobKey::new(&[0xba; 32]).unwrap();
+        let now = SystemTime::now();
+
+        let blob = key
+            .seal(&params(), "127.0.0.1".parse().unwrap(), now)
+            .unwrap();
+
+        assert_eq!(
+            key.open(&blob, "127.0.0.2".parse().unwrap(), now, DEFAULT_MAX_AGE)
+                .err(),
+            Some(Error::CryptoFail)
+        );
+    }
+
+    #[test]
+    fn open_tampered() {
+        let key = BlobKey::new(&[0xba; 16]).unwrap();
+        let peer = "::1".parse().unwrap();
+        let now = SystemTime::now();
+
+        let mut blob = key.seal(&params(), peer, now).unwrap();
+        let last = blob.len() - 1;
+        blob[last] ^= 0x01;
+
+        assert_eq!(
+            key.open(&blob, peer, now, DEFAULT_MAX_AGE).err(),
+            Some(Error::CryptoFail)
+        );
+
+        assert_eq!(
+            key.open(&blob[..10], peer, now, DEFAULT_MAX_AGE).err(),
+            Some(Error::CryptoFail)
+        );
+    }
+
+    #[test]
+    fn open_outside_replay_window() {
+        let key = BlobKey::new(&[0xba; 16]).unwrap();
+        let peer = "127.0.0.1".parse().unwrap();
+        let now = SystemTime::now();
+
+        let blob = key.seal(&params(), peer, now).unwrap();
+
+        // Expired.
+        let later = now + Duration::from_secs(120);
+        assert_eq!(
+            key.open(&blob, peer, later, Duration::from_secs(60)).err(),
+            Some(Error::CryptoFail)
+        );
+
+        // Issued in the future.
+        let earlier = now - Duration::from_secs(60);
+        assert_eq!(
+            key.open(&blob, peer, earlier, DEFAULT_MAX_AGE).err(),
+            Some(Error::CryptoFail)
+        );
+    }
+
+    #[test]
+    fn open_with_age() {
+        let key = BlobKey::new(&[0xba; 16]).unwrap();
+        let peer = "127.0.0.1".parse().unwrap();
+        let now = SystemTime::now();
+
+        let blob = key.seal(&params(), peer, now).unwrap();
+
+        // The issue time is stored with a resolution of one second.
+        let later = now + Duration::from_secs(90);
+        let (opened, age) = key
+            .open_with_age(&blob, peer, later, DEFAULT_MAX_AGE)
+            .unwrap();
+
+        assert_eq!(opened, params());
+        assert!(age >= Duration::from_secs(90) && age < Duration::from_secs(91));
+    }
+
+    #[test]
+    fn key_mismatch() {
+        let key = BlobKey::new(&[0xba; 16]).unwrap();
+        let other = BlobKey::new(&[0xab; 16]).unwrap();
+        let peer = "127.0.0.1".parse().unwrap();
+        let now = SystemTime::now();
+
+        let blob = key.seal(&params(), peer, now).unwrap();
+
+        assert_eq!(
+            other.open(&blob, peer, now, DEFAULT_MAX_AGE).err(),
+            Some(Error::CryptoFail)
+        );
+    }
+
+    #[test]
+    fn token() {
+        let key = BlobKey::new(&[0xba; 16]).unwrap();
+        let peer = "127.0.0.1".parse().unwrap();
+        let now = SystemTime::now();
+
+        let blob = key.seal(&params(), peer, now).unwrap();
+        let token = to_token(&blob);
+
+        assert_eq!(from_token(&token), Some(&blob[..]));
+
+        // Address validation tokens minted by the application are left alone.
+        assert_eq!(from_token(b"quiche\x7f\x00\x00\x01"), None);
+        assert_eq!(from_token(&blob), None);
+    }
+}
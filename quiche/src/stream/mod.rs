@@ -155,6 +155,12 @@ pub struct StreamMap {
     /// to include in the RESET_STREAM frame.
     reset: StreamIdHashMap<(u64, u64)>,
 
+    /// Set of stream IDs corresponding to streams that are reset with a
+    /// reliable size. The value of the map elements is a tuple of the error
+    /// code, final size and reliable size values to include in the
+    /// RESET_STREAM_AT frame.
+    reset_at: StreamIdHashMap<(u64, u64, u64)>,
+
     /// Set of stream IDs corresponding to streams that are shutdown on the
     /// receive side, and need to send a STOP_SENDING frame. The value of the
     /// map elements is the error code to include in the STOP_SENDING frame.
@@ -473,6 +479,23 @@ impl StreamMap {
         self.reset.remove(&stream_id);
     }
 
+    /// Adds the stream ID to the reliably reset streams set with the
+    /// given error code, final size and reliable size values.
+    ///
+    /// If the stream was already in the list, this does nothing.
+    pub fn insert_reset_at(
+        &mut self, stream_id: u64, error_code: u64, final_size: u64,
+        reliable_size: u64,
+    ) {
+        self.reset_at
+            .insert(stream_id, (error_code, final_size, reliable_size));
+    }
+
+    /// Removes the stream ID from the reliably reset streams set.
+    pub fn remove_reset_at(&mut self, stream_id: u64) {
+        self.reset_at.remove(&stream_id);
+    }
+
     /// Adds the stream ID to the stopped streams set with the
     /// given error code.
     ///
@@ -595,6 +618,11 @@ impl StreamMap {
         self.reset.iter()
     }
 
+    /// Creates an iterator over streams that need to send RESET_STREAM_AT.
+    pub fn reset_at(&self) -> hash_map::Iter<u64, (u64, u64, u64)> {
+        self.reset_at.iter()
+    }
+
     /// Creates an iterator over streams that need to send STOP_SENDING.
     pub fn stopped(&self) -> hash_map::Iter<u64, u64> {
         self.stopped.iter()
@@ -628,7 +656,7 @@ impl StreamMap {
 
     /// Returns true if there are any streams that are reset.
     pub fn has_reset(&self) -> bool {
-        !self.reset.is_empty()
+        !self.reset.is_empty() || !self.reset_at.is_empty()
     }
 
     /// Returns true if there are any streams that need to send STOP_SENDING.
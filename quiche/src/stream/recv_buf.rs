@@ -61,9 +61,13 @@ pub struct RecvBuf {
     /// The final stream offset received from the peer, if any.
     fin_off: Option<u64>,
 
-    /// The error code received via RESET_STREAM.
+    /// The error code received via RESET_STREAM or RESET_STREAM_AT.
     error: Option<u64>,
 
+    /// The offset up to which data is still delivered to the application
+    /// after the stream was reset with RESET_STREAM_AT, if any.
+    reliable_off: Option<u64>,
+
     /// Whether incoming data is validated but not buffered.
     drain: bool,
 }
@@ -87,7 +91,7 @@ impl RecvBuf {
     /// This also takes care of enforcing stream flow control limits, as well
     /// as handling incoming data that overlaps data that is already in the
     /// buffer.
-    pub fn write(&mut self, buf: RangeBuf) -> Result<()> {
+    pub fn write(&mut self, mut buf: RangeBuf) -> Result<()> {
         if buf.max_off() > self.max_data() {
             return Err(Error::FlowControl);
         }
@@ -109,6 +113,17 @@ impl RecvBuf {
             return Err(Error::FinalSize);
         }
 
+        // The stream was reset, so only keep data below the reliable size.
+        if let Some(reliable_off) = self.reliable_off {
+            if buf.off() >= reliable_off {
+                return Ok(());
+            }
+
+            if buf.max_off() > reliable_off {
+                buf.split_off((reliable_off - buf.off()) as usize);
+            }
+        }
+
         // We already saved the final offset, so there's nothing else we
         // need to keep from the RangeBuf if it's empty.
         if self.fin_off.is_some() && buf.is_empty() {
@@ -211,9 +226,16 @@ impl RecvBuf {
         }
 
         // The stream was reset, so clear its data and return the error code
-        // instead.
-        if let Some(e) = self.error {
+        // instead, once data up to the reliable size was read.
+        if let Some(e) = self.error.filter(|_| self.is_reliable_read()) {
             self.data.clear();
+
+            if let Some(fin_off) = self.fin_off {
+                self.off = fin_off;
+            }
+
+            self.reliable_off = None;
+
             return Err(Error::StreamReset(e));
         }
 
@@ -252,6 +274,21 @@ impl RecvBuf {
 
     /// Resets the stream at the given offset.
     pub fn reset(&mut self, error_code: u64, final_size: u64) -> Result<usize> {
+        self.reset_at(error_code, final_size, 0)
+    }
+
+    /// Resets the stream at the given offset, still delivering data up to
+    /// the given reliable size to the application.
+    ///
+    /// A later reset can only lower the reliable size, resets with a larger
+    /// one are ignored.
+    pub fn reset_at(
+        &mut self, error_code: u64, final_size: u64, reliable_size: u64,
+    ) -> Result<usize> {
+        if reliable_size > final_size {
+            return Err(Error::InvalidFrame);
+        }
+
         // Stream's size is already known, forbid changing it.
         if let Some(fin_off) = self.fin_off {
             if fin_off != final_size {
@@ -268,12 +305,47 @@ impl RecvBuf {
         // control.
         let max_data_delta = final_size - self.len;
 
+        let reliable_size = if self.drain { 0 } else { reliable_size };
+
+        if let Some(reliable_off) = self.reliable_off {
+            // The reliable size was lowered, truncate the data accordingly.
+            if reliable_size < reliable_off {
+                self.error = None;
+            }
+        }
+
         if self.error.is_some() {
             return Ok(max_data_delta as usize);
         }
 
         self.error = Some(error_code);
 
+        // Keep the data still to be read up to the reliable size, and drop
+        // the rest.
+        if reliable_size > self.off {
+            self.fin_off = Some(final_size);
+            self.len = final_size;
+
+            self.reliable_off = Some(reliable_size);
+
+            self.data.retain(|_, b| b.off() < reliable_size);
+
+            if let Some(entry) = self.data.last_entry() {
+                if entry.get().max_off() > reliable_size {
+                    // The buffer might have been partially read already, so
+                    // split it relative to its initial offset.
+                    let mut buf = entry.remove();
+                    buf.split_off((reliable_size - buf.off) as usize);
+
+                    self.data.insert(buf.max_off(), buf);
+                }
+            }
+
+            return Ok(max_data_delta as usize);
+        }
+
+        self.reliable_off = None;
+
         // Clear all data already buffered.
         self.off = final_size;
 
@@ -327,6 +399,8 @@ impl RecvBuf {
 
         self.data.clear();
 
+        self.reliable_off = None;
+
         self.off = self.max_off();
 
         Ok(())
@@ -352,7 +426,7 @@ impl RecvBuf {
     /// This happens when the stream's receive final size is known, and the
     /// application has read all data from the stream.
     pub fn is_fin(&self) -> bool {
-        if self.fin_off == Some(self.off) {
+        if self.reliable_off.is_none() && self.fin_off == Some(self.off) {
             return true;
         }
 
@@ -364,8 +438,19 @@ impl RecvBuf {
         self.drain
     }
 
+    /// Returns true if all data up to the reliable size of a stream reset
+    /// with RESET_STREAM_AT was read.
+    fn is_reliable_read(&self) -> bool {
+        self.reliable_off.map_or(true, |r| self.off >= r)
+    }
+
     /// Returns true if the stream has data to be read.
     pub fn ready(&self) -> bool {
+        // The reset is reported once data up to the reliable size was read.
+        if self.reliable_off.is_some() && self.is_reliable_read() {
+            return true;
+        }
+
         let (_, buf) = match self.data.first_key_value() {
             Some(v) => v,
             None => return false,
@@ -982,4 +1067,85 @@ mod tests {
 
         assert_eq!(recv.emit(&mut buf), Err(Error::Done));
     }
+
+    #[test]
+    fn reset_at_buffered() {
+        let mut recv = RecvBuf::new(u64::MAX, DEFAULT_STREAM_WINDOW, None);
+        let mut buf = [0; 32];
+
+        assert!(recv.write(RangeBuf::from(b"hello", 0, false)).is_ok());
+        assert!(recv.write(RangeBuf::from(b"world", 5, false)).is_ok());
+
+        assert_eq!(recv.emit(&mut buf[..3]), Ok((3, false)));
+
+        assert_eq!(recv.reset_at(7, 15, 20), Err(Error::InvalidFrame));
+        assert_eq!(recv.reset_at(7, 15, 8), Ok(5));
+
+        // Retransmitted data past the reliable size is discarded.
+        assert!(recv.write(RangeBuf::from(b"ld", 8, false)).is_ok());
+
+        let (len, fin) = recv.emit(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"lowor");
+        assert!(!fin);
+
+        assert!(recv.ready());
+        assert!(!recv.is_fin());
+
+        assert_eq!(recv.emit(&mut buf), Err(Error::StreamReset(7)));
+        assert!(!recv.ready());
+        assert!(recv.is_fin());
+
+        assert_eq!(recv.emit(&mut buf), Err(Error::Done));
+    }
+
+    #[test]
+    fn reset_at_missing_data() {
+        let mut recv = RecvBuf::new(u64::MAX, DEFAULT_STREAM_WINDOW, None);
+        let mut buf = [0; 32];
+
+        assert!(recv.write(RangeBuf::from(b"hello", 0, false)).is_ok());
+
+        assert_eq!(recv.reset_at(7, 15, 8), Ok(10));
+        assert_eq!(recv.reset_at(7, 15, 8), Ok(0));
+
+        assert_eq!(recv.emit(&mut buf), Ok((5, false)));
+
+        // Wait for the missing data up to the reliable size.
+        assert!(!recv.ready());
+        assert_eq!(recv.emit(&mut buf), Err(Error::Done));
+
+        assert!(recv.write(RangeBuf::from(b"worldwide", 5, false)).is_ok());
+
+        let (len, fin) = recv.emit(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"wor");
+        assert!(!fin);
+
+        assert_eq!(recv.emit(&mut buf), Err(Error::StreamReset(7)));
+        assert!(recv.is_fin());
+    }
+
+    #[test]
+    fn reset_at_lowered() {
+        let mut recv = RecvBuf::new(u64::MAX, DEFAULT_STREAM_WINDOW, None);
+        let mut buf = [0; 32];
+
+        assert!(recv.write(RangeBuf::from(b"hello", 0, false)).is_ok());
+
+        assert_eq!(recv.reset_at(7, 10, 4), Ok(5));
+
+        // A larger reliable size is ignored.
+        assert_eq!(recv.reset_at(7, 10, 8), Ok(0));
+
+        // A smaller one truncates the data further.
+        assert_eq!(recv.reset_at(7, 10, 2), Ok(0));
+
+        let (len, _) = recv.emit(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"he");
+
+        assert_eq!(recv.emit(&mut buf), Err(Error::StreamReset(7)));
+
+        // A plain reset after the data was read doesn't report it again.
+        assert_eq!(recv.reset(7, 10), Ok(0));
+        assert_eq!(recv.emit(&mut buf), Err(Error::Done));
+    }
 }
@@ -232,6 +232,12 @@ impl SendBuf {
         Ok((out.len() - out_len, fin))
     }
 
+    /// Returns whether data at offset `off` was already emitted, i.e. whether
+    /// emitting it again would be a retransmission.
+    pub fn is_emitted(&self, off: u64) -> bool {
+        off < self.emit_off
+    }
+
     /// Updates the max_data limit to the given value.
     pub fn update_max_data(&mut self, max_data: u64) {
         self.max_data = cmp::max(self.max_data, max_data);
@@ -366,6 +372,56 @@ impl SendBuf {
         (self.emit_off, unsent_len)
     }
 
+    /// Resets the stream, keeping the data below `reliable_size` so that it
+    /// is still delivered to the peer.
+    ///
+    /// Data buffered past the reliable size is dropped, and will not be sent
+    /// or retransmitted. The final size of the stream and the amount of data
+    /// that was buffered but will not be sent are returned.
+    ///
+    /// The reliable size must not be larger than the amount of data written
+    /// to the stream.
+    pub fn reset_at(&mut self, reliable_size: u64) -> Result<(u64, u64)> {
+        if self.shutdown {
+            return Err(Error::Done);
+        }
+
+        self.shutdown = true;
+
+        let final_size = cmp::max(self.emit_off, reliable_size);
+        let unsent_len = self.off.saturating_sub(final_size);
+
+        // Drop buffered data past the reliable size.
+        while let Some(buf) = self.data.back_mut() {
+            if buf.off >= reliable_size {
+                self.data.pop_back();
+                continue;
+            }
+
+            if buf.off + (buf.len as u64) > reliable_size {
+                buf.split_off((reliable_size - buf.off) as usize);
+            }
+
+            break;
+        }
+
+        self.pos = cmp::min(self.pos, self.data.len());
+        self.len = self.data.iter().map(|b| b.len() as u64).sum();
+
+        // The peer learns the final size from the RESET_STREAM_AT frame, so
+        // it counts as sent.
+        self.fin_off = Some(final_size);
+        self.emit_off = final_size;
+        self.off = final_size;
+
+        // Data past the reliable size is never going to be acked.
+        if final_size > reliable_size {
+            self.ack(reliable_size, (final_size - reliable_size) as usize);
+        }
+
+        Ok((final_size, unsent_len))
+    }
+
     /// Resets the streams and records the received error code.
     ///
     /// Calling this again after the first time has no effect.
@@ -748,10 +804,12 @@ mod tests {
         assert_eq!(&buf[..written], b"some");
         assert_eq!(send.len, 5);
         assert_eq!(send.off_front(), 4);
+        assert!(!send.is_emitted(send.off_front()));
 
         send.retransmit(3, 5);
         assert_eq!(send.len, 6);
         assert_eq!(send.off_front(), 3);
+        assert!(send.is_emitted(send.off_front()));
     }
 
     #[test]
@@ -778,4 +836,66 @@ mod tests {
         assert_eq!(fin_off, 50);
         assert_eq!(unsent, 0);
     }
+
+    #[test]
+    fn reset_at_unsent() {
+        let mut buf = [0; 50];
+        let mut send = SendBuf::new(u64::MAX);
+
+        send.write(b"somethinghelloworld", false).unwrap();
+
+        let (written, fin) = send.emit(&mut buf[..7]).unwrap();
+        assert_eq!(written, 7);
+        assert!(!fin);
+
+        // Data up to the reliable size is still sent, the rest is dropped.
+        assert_eq!(send.reset_at(12), Ok((12, 7)));
+        assert_eq!(send.reset_at(12), Err(Error::Done));
+        assert!(send.is_fin());
+
+        let (written, fin) = send.emit(&mut buf).unwrap();
+        assert_eq!(written, 5);
+        assert!(fin);
+        assert_eq!(&buf[..written], b"nghel");
+        assert_eq!(send.len, 0);
+
+        assert!(!send.is_complete());
+
+        send.ack_and_drop(0, 12);
+        assert!(send.is_complete());
+    }
+
+    #[test]
+    fn reset_at_sent() {
+        let mut buf = [0; 50];
+        let mut send = SendBuf::new(u64::MAX);
+
+        send.write(b"somethinghelloworld", false).unwrap();
+
+        let (written, fin) = send.emit(&mut buf[..15]).unwrap();
+        assert_eq!(written, 15);
+        assert!(!fin);
+
+        // The final size covers all the data that was already sent.
+        assert_eq!(send.reset_at(6), Ok((15, 4)));
+
+        let (written, _) = send.emit(&mut buf).unwrap();
+        assert_eq!(written, 0);
+
+        // Only data below the reliable size is retransmitted.
+        send.retransmit(0, 15);
+        assert_eq!(send.len, 6);
+
+        let (written, fin) = send.emit(&mut buf).unwrap();
+        assert_eq!(written, 6);
+        assert!(!fin);
+        assert_eq!(&buf[..written], b"someth");
+
+        // Data past the reliable size doesn't need to be acked.
+        send.ack_and_drop(0, 6);
+        assert!(send.is_complete());
+
+        // The final size is unchanged when the peer then stops the stream.
+        assert_eq!(send.stop(0), Ok((15, 0)));
+    }
 }
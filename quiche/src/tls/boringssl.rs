@@ -101,6 +101,13 @@ impl Context {
             );
         }
     }
+
+    pub fn set_chacha20_only(&mut self, _v: bool) -> Result<()> {
+        // BoringSSL doesn't allow configuring the TLS 1.3 cipher suites, it
+        // already prefers ChaCha20 without AES hardware support. The
+        // negotiated cipher is checked when installing the 1-RTT keys instead.
+        Ok(())
+    }
 }
 
 impl Handshake {
@@ -693,6 +693,10 @@ pub struct ExData<'a> {
     pub trace_id: &'a str,
 
     pub is_server: bool,
+
+    pub version: u32,
+
+    pub chacha20_only: bool,
 }
 
 fn get_ex_data_from_ptr<'a, T>(ptr: *const SSL, idx: c_int) -> Option<&'a mut T> {
@@ -715,6 +719,28 @@ fn get_cipher_from_ptr(cipher: *const SSL_CIPHER) -> Result<crypto::Algorithm> {
     Ok(alg)
 }
 
+// Whether the keys for `level` may use `aead`. Only the 1-RTT keys are
+// restricted by `Config::set_chacha20_only()`.
+fn is_cipher_allowed(
+    chacha20_only: bool, level: crypto::Level, aead: crypto::Algorithm,
+    trace_id: &str,
+) -> bool {
+    if level != crypto::Level::OneRTT ||
+        !chacha20_only ||
+        aead == crypto::Algorithm::ChaCha20_Poly1305
+    {
+        return true;
+    }
+
+    trace!(
+        "{} rejected {:?} for the 1-RTT keys, only ChaCha20 is allowed",
+        trace_id,
+        aead
+    );
+
+    false
+}
+
 extern fn set_read_secret(
     ssl: *mut SSL, level: crypto::Level, cipher: *const SSL_CIPHER,
     secret: *const u8, secret_len: usize,
@@ -745,11 +771,19 @@ extern fn set_read_secret(
         Err(_) => return 0,
     };
 
+    if !is_cipher_allowed(ex_data.chacha20_only, level, aead, ex_data.trace_id) {
+        return 0;
+    }
+
     // 0-RTT read secrets are present only on the server.
     if level != crypto::Level::ZeroRTT || ex_data.is_server {
         let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
 
-        let open = match crypto::Open::from_secret(aead, secret.to_vec()) {
+        let open = match crypto::Open::from_secret(
+            aead,
+            ex_data.version,
+            secret.to_vec(),
+        ) {
             Ok(v) => v,
 
             Err(_) => return 0,
@@ -796,11 +830,19 @@ extern fn set_write_secret(
         Err(_) => return 0,
     };
 
+    if !is_cipher_allowed(ex_data.chacha20_only, level, aead, ex_data.trace_id) {
+        return 0;
+    }
+
     // 0-RTT write secrets are present only on the client.
     if level != crypto::Level::ZeroRTT || !ex_data.is_server {
         let secret = unsafe { slice::from_raw_parts(secret, secret_len) };
 
-        let seal = match crypto::Seal::from_secret(aead, secret.to_vec()) {
+        let seal = match crypto::Seal::from_secret(
+            aead,
+            ex_data.version,
+            secret.to_vec(),
+        ) {
             Ok(v) => v,
 
             Err(_) => return 0,
@@ -55,6 +55,22 @@ impl Context {
     pub fn set_early_data_enabled(&mut self, _enabled: bool) {
         // not yet supported
     }
+
+    pub fn set_chacha20_only(&mut self, v: bool) -> Result<()> {
+        let suites: &[u8] = if v {
+            b"TLS_CHACHA20_POLY1305_SHA256\0"
+        } else {
+            b"TLS_AES_128_GCM_SHA256:TLS_AES_256_GCM_SHA384:\
+              TLS_CHACHA20_POLY1305_SHA256\0"
+        };
+
+        map_result(unsafe {
+            SSL_CTX_set_ciphersuites(
+                self.as_mut_ptr(),
+                suites.as_ptr() as *const c_char,
+            )
+        })
+    }
 }
 
 impl Handshake {
@@ -325,6 +341,8 @@ extern {
         ctx: *mut SSL_CTX, cmd: c_int, larg: c_long, parg: *mut c_void,
     ) -> c_long;
 
+    fn SSL_CTX_set_ciphersuites(ctx: *mut SSL_CTX, str: *const c_char) -> c_int;
+
     fn SSL_get_peer_cert_chain(ssl: *const SSL) -> *mut STACK_OF;
 
     fn SSL_get0_peer_certificate(ssl: *const SSL) -> *mut X509;
@@ -0,0 +1,332 @@
+// Copyright (C) 2024, Cloudflare, Inc.
+// All rights reserved.
+//
+// Redistribution and use in source and binary forms, with or without
+// modification, are permitted provided that the following conditions are
+// met:
+//
+//     * Redistributions of source code must retain the above copyright notice,
+//       this list of conditions and the following disclaimer.
+//
+//     * Redistributions in binary form must reproduce the above copyright
+//       notice, this list of conditions and the following disclaimer in the
+//       documentation and/or other materials provided with the distribution.
+//
+// THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS
+// IS" AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO,
+// THE IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR
+// PURPOSE ARE DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR
+// CONTRIBUTORS BE LIABLE FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL,
+// EXEMPLARY, OR CONSEQUENTIAL DAMAGES (INCLUDING, BUT NOT LIMITED TO,
+// PROCUREMENT OF SUBSTITUTE GOODS OR SERVICES; LOSS OF USE, DATA, OR
+// PROFITS; OR BUSINESS INTERRUPTION) HOWEVER CAUSED AND ON ANY THEORY OF
+// LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY, OR TORT (INCLUDING
+// NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE OF THIS
+// SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+
+//! Throughput regression tests for Careful Resume.
+//!
+//! A client and a server are connected through an emulated link with a fixed
+//! bandwidth, propagation delay and drop-tail buffer, and a transfer is timed
+//! with and without careful resume. The tests run in real time and take
+//! several seconds, so they are only built with the `throughput-tests`
+//! feature:
+//!
+//! ```text
+//! cargo test --release --features throughput-tests --test throughput
+//! ```
+
+#![cfg(feature = "throughput-tests")]
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::time::Instant;
+
+use quiche::testing::Pipe;
+
+/// The round-trip propagation delay of the emulated path.
+const RTT: Duration = Duration::from_millis(600);
+
+/// The bottleneck bandwidth of the emulated path, in bits per second.
+const BANDWIDTH: u64 = 50_000_000;
+
+/// The size of the transfer, in bytes.
+const TRANSFER_SIZE: usize = 10_000_000;
+
+/// How much faster, in percent, the resumed transfer must complete.
+const MIN_SPEEDUP_PERCENT: u128 = 20;
+
+/// The largest share, in percent, of packets sent by the server that may be
+/// lost during the resumed transfer.
+const LOSS_BUDGET_PERCENT: usize = 1;
+
+/// A transfer that takes longer than this is considered stuck.
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(120);
+
+const MAX_DATAGRAM_SIZE: usize = 1350;
+
+/// The seed of the randomness used by quiche during the transfers.
+const RAND_SEED: u64 = 0x5eed;
+
+/// Bandwidth-delay product of the path, in bytes.
+fn bdp() -> usize {
+    (BANDWIDTH as u128 * RTT.as_micros() / 8_000_000) as usize
+}
+
+/// One direction of the emulated link.
+///
+/// Packets are serialized at the bottleneck bandwidth, after waiting in a
+/// drop-tail buffer holding up to one bandwidth-delay product, and are
+/// delivered after the one-way propagation delay.
+struct Link {
+    /// When the bottleneck finishes transmitting the last queued packet.
+    free_at: Instant,
+
+    /// Packets in flight, with the time they arrive at the other end.
+    in_flight: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl Link {
+    fn new(now: Instant) -> Link {
+        Link {
+            free_at: now,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    /// Queues a packet handed to the link at `at`.
+    fn push(&mut self, at: Instant, pkt: &[u8]) {
+        let start = self.free_at.max(at);
+
+        let queued = start.saturating_duration_since(at);
+        let queued_bytes = queued.as_micros() * BANDWIDTH as u128 / 8_000_000;
+
+        if queued_bytes as usize + pkt.len() > bdp() {
+            return;
+        }
+
+        let tx_time = Duration::from_micros(
+            (pkt.len() as u128 * 8_000_000 / BANDWIDTH as u128) as u64,
+        );
+
+        self.free_at = start + tx_time;
+
+        self.in_flight
+            .push_back((self.free_at + RTT / 2, pkt.to_vec()));
+    }
+
+    /// Returns the next packet that arrived by `now`.
+    fn pop(&mut self, now: Instant) -> Option<Vec<u8>> {
+        match self.in_flight.front() {
+            Some((arrival, _)) if *arrival <= now =>
+                self.in_flight.pop_front().map(|(_, pkt)| pkt),
+
+            _ => None,
+        }
+    }
+
+    fn next_arrival(&self) -> Option<Instant> {
+        self.in_flight.front().map(|(arrival, _)| *arrival)
+    }
+}
+
+struct TransferResult {
+    duration: Duration,
+    stats: quiche::Stats,
+}
+
+fn flush(
+    conn: &mut quiche::Connection, link: &mut Link, buf: &mut [u8],
+    now: Instant,
+) {
+    loop {
+        let (len, info) = match conn.send(buf) {
+            Ok(v) => v,
+
+            Err(quiche::Error::Done) => break,
+
+            Err(e) => panic!("send failed: {:?}", e),
+        };
+
+        link.push(info.at.max(now), &buf[..len]);
+    }
+}
+
+fn deliver(
+    conn: &mut quiche::Connection, link: &mut Link, from: SocketAddr,
+    to: SocketAddr, now: Instant,
+) {
+    while let Some(mut pkt) = link.pop(now) {
+        let info = quiche::RecvInfo { from, to };
+
+        match conn.recv(&mut pkt, info) {
+            Ok(_) | Err(quiche::Error::Done) => (),
+
+            Err(e) => panic!("recv failed: {:?}", e),
+        }
+    }
+}
+
+/// Serves a `TRANSFER_SIZE` response over the emulated link, with careful
+/// resume set up on the server from a previous connection over the same
+/// path when `resume` is true, and returns how long it took from the start
+/// of the handshake.
+fn transfer(resume: bool) -> TransferResult {
+    let mut config = quiche::Config::new(quiche::PROTOCOL_VERSION).unwrap();
+    config
+        .load_cert_chain_from_pem_file("examples/cert.crt")
+        .unwrap();
+    config
+        .load_priv_key_from_pem_file("examples/cert.key")
+        .unwrap();
+    config
+        .set_application_protos(&[b"proto1", b"proto2"])
+        .unwrap();
+    config.set_initial_max_data(2 * TRANSFER_SIZE as u64);
+    config.set_initial_max_stream_data_bidi_local(2 * TRANSFER_SIZE as u64);
+    config.set_initial_max_stream_data_bidi_remote(2 * TRANSFER_SIZE as u64);
+    config.set_initial_max_streams_bidi(1);
+    config.set_max_idle_timeout(180_000);
+    config.set_max_recv_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.set_max_send_udp_payload_size(MAX_DATAGRAM_SIZE);
+    config.enable_resume(resume);
+    config.verify_peer(false);
+
+    // Use the same connection IDs for both transfers.
+    quiche::testing::set_rand_seed(Some(RAND_SEED));
+
+    let mut pipe = Pipe::with_config(&mut config).unwrap();
+
+    if resume {
+        pipe.server
+            .setup_careful_resume(RTT, bdp(), BANDWIDTH / 8)
+            .unwrap();
+    }
+
+    let client_addr = Pipe::client_addr();
+    let server_addr = Pipe::server_addr();
+
+    let start = Instant::now();
+
+    let mut uplink = Link::new(start);
+    let mut downlink = Link::new(start);
+
+    let mut buf = [0; 65535];
+    let body = vec![0; TRANSFER_SIZE];
+
+    let mut request_sent = false;
+    let mut response_written = None;
+    let mut received = 0;
+
+    loop {
+        let now = Instant::now();
+
+        assert!(
+            now - start < TRANSFER_TIMEOUT,
+            "transfer did not complete in {:?}",
+            TRANSFER_TIMEOUT
+        );
+
+        deliver(&mut pipe.server, &mut uplink, client_addr, server_addr, now);
+        deliver(&mut pipe.client, &mut downlink, server_addr, client_addr, now);
+
+        if pipe.client.is_established() && !request_sent {
+            pipe.client.stream_send(0, b"GET /", true).unwrap();
+            request_sent = true;
+        }
+
+        if response_written.is_none() && pipe.server.readable().any(|s| s == 0) {
+            while pipe.server.stream_recv(0, &mut buf).is_ok() {}
+            response_written = Some(0);
+        }
+
+        if let Some(written) = response_written.as_mut() {
+            if *written < TRANSFER_SIZE {
+                match pipe.server.stream_send(0, &body[*written..], true) {
+                    Ok(v) => *written += v,
+
+                    Err(quiche::Error::Done) => (),
+
+                    Err(e) => panic!("stream_send failed: {:?}", e),
+                }
+            }
+        }
+
+        while let Ok((len, fin)) = pipe.client.stream_recv(0, &mut buf) {
+            received += len;
+
+            if fin {
+                assert_eq!(received, TRANSFER_SIZE);
+
+                return TransferResult {
+                    duration: now - start,
+                    stats: pipe.server.stats(),
+                };
+            }
+        }
+
+        flush(&mut pipe.client, &mut uplink, &mut buf, now);
+        flush(&mut pipe.server, &mut downlink, &mut buf, now);
+
+        // Sleep until a packet arrives or a connection timer fires.
+        let next = [
+            uplink.next_arrival(),
+            downlink.next_arrival(),
+            pipe.client.timeout().map(|t| now + t),
+            pipe.server.timeout().map(|t| now + t),
+        ]
+        .iter()
+        .flatten()
+        .min()
+        .copied()
+        .unwrap_or(now + Duration::from_millis(1));
+
+        std::thread::sleep(next.saturating_duration_since(Instant::now()));
+
+        let now = Instant::now();
+
+        if pipe.client.timeout().map_or(false, |t| t.is_zero()) {
+            pipe.client.on_timeout();
+        }
+
+        if pipe.server.timeout().map_or(false, |t| t.is_zero()) {
+            pipe.server.on_timeout();
+        }
+
+        assert!(!pipe.client.is_closed(), "client closed at {:?}", now - start);
+        assert!(!pipe.server.is_closed(), "server closed at {:?}", now - start);
+    }
+}
+
+#[test]
+fn resume_faster_than_slow_start() {
+    let normal = transfer(false);
+    let resumed = transfer(true);
+
+    println!(
+        "normal: {:?} lost={}/{}, resumed: {:?} lost={}/{}",
+        normal.duration,
+        normal.stats.lost,
+        normal.stats.sent,
+        resumed.duration,
+        resumed.stats.lost,
+        resumed.stats.sent,
+    );
+
+    assert!(
+        resumed.duration.as_micros() * 100 <=
+            normal.duration.as_micros() * (100 - MIN_SPEEDUP_PERCENT),
+        "resumed transfer took {:?}, not {}% faster than {:?}",
+        resumed.duration,
+        MIN_SPEEDUP_PERCENT,
+        normal.duration
+    );
+
+    assert!(
+        resumed.stats.lost * 100 <= resumed.stats.sent * LOSS_BUDGET_PERCENT,
+        "resumed transfer lost {} of {} packets",
+        resumed.stats.lost,
+        resumed.stats.sent
+    );
+}